@@ -708,6 +708,18 @@ impl FirmwareUpdater {
         Ok(FirmwareWriter(self.dfu))
     }
 
+    /// Read firmware from the DFU area, starting at `offset`.
+    ///
+    /// This is useful to read back an update, for instance to verify it or to upload it to a host.
+    pub async fn read_dfu<F: AsyncNorFlash>(
+        &mut self,
+        offset: usize,
+        buf: &mut [u8],
+        flash: &mut F,
+    ) -> Result<(), F::Error> {
+        flash.read((self.dfu.from + offset) as u32, buf).await
+    }
+
     //
     // Blocking API
     //
@@ -811,6 +823,18 @@ impl FirmwareUpdater {
 
         Ok(FirmwareWriter(self.dfu))
     }
+
+    /// Read firmware from the DFU area, starting at `offset`.
+    ///
+    /// This is useful to read back an update, for instance to verify it or to upload it to a host.
+    pub fn read_dfu_blocking<F: NorFlash>(
+        &mut self,
+        offset: usize,
+        buf: &mut [u8],
+        flash: &mut F,
+    ) -> Result<(), F::Error> {
+        flash.read((self.dfu.from + offset) as u32, buf)
+    }
 }
 
 /// FirmwareWriter allows writing blocks to an already erased flash.
@@ -1124,6 +1148,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_dfu() {
+        const STATE: Partition = Partition::new(0, 4096);
+        const DFU: Partition = Partition::new(61440, 122880);
+        let mut flash = MemFlash::<131072, 4096, 4>([0xff; 131072]);
+
+        let update: [u8; DFU.len()] = [rand::random::<u8>(); DFU.len()];
+
+        let mut updater = FirmwareUpdater::new(DFU, STATE);
+        let mut offset = 0;
+        for chunk in update.chunks(4096) {
+            block_on(updater.write_firmware(offset, chunk, &mut flash, 4096)).unwrap();
+            offset += chunk.len();
+        }
+
+        let mut buf = [0; 100];
+        block_on(updater.read_dfu(4000, &mut buf, &mut flash)).unwrap();
+        assert_eq!(buf, update[4000..4100]);
+
+        updater
+            .read_dfu_blocking(DFU.len() - 100, &mut buf, &mut flash)
+            .unwrap();
+        assert_eq!(buf, update[DFU.len() - 100..]);
+    }
+
     #[test]
     #[should_panic]
     fn test_range_asserts() {
@@ -0,0 +1,37 @@
+//! Asynchronous block device abstraction.
+//!
+//! Storage media such as SD cards are addressed in fixed size blocks rather than bytes. This module
+//! provides a trait for such devices, so filesystems and USB mass storage can be written once and used
+//! with any of them.
+use core::future::Future;
+
+/// Size in bytes of a single block.
+pub const BLOCK_SIZE: usize = 512;
+
+/// A single block of data.
+pub type Block = [u8; BLOCK_SIZE];
+
+/// An asynchronous block device.
+pub trait BlockDevice {
+    /// Error type returned by the device.
+    type Error: core::fmt::Debug;
+
+    /// Future returned by `read`.
+    type ReadFuture<'a>: Future<Output = Result<(), Self::Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Read consecutive blocks, starting at `block_address`, into `blocks`.
+    fn read<'a>(&'a mut self, block_address: u32, blocks: &'a mut [Block]) -> Self::ReadFuture<'a>;
+
+    /// Future returned by `write`.
+    type WriteFuture<'a>: Future<Output = Result<(), Self::Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Write `blocks` to consecutive blocks, starting at `block_address`.
+    fn write<'a>(&'a mut self, block_address: u32, blocks: &'a [Block]) -> Self::WriteFuture<'a>;
+
+    /// Number of blocks on the device.
+    fn block_count(&self) -> u32;
+}
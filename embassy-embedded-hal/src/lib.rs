@@ -7,6 +7,12 @@
 #[cfg(feature = "nightly")]
 pub mod adapter;
 
+#[cfg(feature = "nightly")]
+pub mod block_device;
+
+#[cfg(feature = "nightly")]
+pub mod sdcard;
+
 pub mod shared_bus;
 
 /// Set the configuration of a peripheral driver.
@@ -0,0 +1,605 @@
+//! SD card driver using the SPI mode of the card.
+//!
+//! This is useful on boards without an SDMMC peripheral. The card is operated with single block
+//! reads (CMD17) and writes (CMD24), optionally with CRC checking enabled on both commands and data.
+//!
+//! The SPI bus must be configured for SPI mode 0 and should run at 100-400 kHz until
+//! [`SdCard::init`] returns, after which it can be raised to up to 25 MHz.
+//!
+//! # Example (nrf52)
+//!
+//! ```rust
+//! use embassy_embedded_hal::block_device::BlockDevice;
+//! use embassy_embedded_hal::sdcard::{self, NoCardDetect, SdCard};
+//! use embassy_time::Delay;
+//!
+//! let mut config = spim::Config::default();
+//! config.frequency = spim::Frequency::K250;
+//! let irq = interrupt::take!(SPIM3);
+//! let spi = spim::Spim::new(p.SPI3, irq, p.P0_13, p.P0_14, p.P0_15, config);
+//! let cs = Output::new(p.P0_16, Level::High, OutputDrive::Standard);
+//!
+//! let mut card = SdCard::new(spi, cs, NoCardDetect, Delay, sdcard::Config::default());
+//! card.init().await.unwrap();
+//!
+//! let mut blocks = [[0; 512]; 1];
+//! card.read(0, &mut blocks).await.unwrap();
+//! ```
+use core::future::Future;
+
+use embedded_hal_1::digital::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayUs;
+use embedded_hal_async::spi::{SpiBus, SpiBusFlush};
+
+use crate::block_device::{Block, BlockDevice, BLOCK_SIZE};
+
+const CMD0_GO_IDLE_STATE: u8 = 0;
+const CMD8_SEND_IF_COND: u8 = 8;
+const CMD9_SEND_CSD: u8 = 9;
+const CMD13_SEND_STATUS: u8 = 13;
+const CMD16_SET_BLOCKLEN: u8 = 16;
+const CMD17_READ_SINGLE_BLOCK: u8 = 17;
+const CMD24_WRITE_BLOCK: u8 = 24;
+const CMD55_APP_CMD: u8 = 55;
+const CMD58_READ_OCR: u8 = 58;
+const CMD59_CRC_ON_OFF: u8 = 59;
+const ACMD41_SD_SEND_OP_COND: u8 = 41;
+
+const R1_IDLE_STATE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+const R1_CRC_ERROR: u8 = 0x08;
+
+const DATA_START_BLOCK: u8 = 0xFE;
+const DATA_RES_MASK: u8 = 0x1F;
+const DATA_RES_ACCEPTED: u8 = 0x05;
+const DATA_RES_CRC_ERROR: u8 = 0x0B;
+
+/// Number of polls while waiting for the card, each separated by `POLL_INTERVAL_US`.
+const POLL_ATTEMPTS: u32 = 5000;
+const POLL_INTERVAL_US: u32 = 100;
+
+/// SD card configuration.
+#[non_exhaustive]
+pub struct Config {
+    /// Enable CRC checking of commands and data blocks.
+    ///
+    /// The CRC of commands is always sent, since CMD0 and CMD8 require it. With this option
+    /// disabled the card ignores it afterwards and data blocks are not checked.
+    pub crc: bool,
+    /// Level of the card detect pin when a card is inserted.
+    pub card_detect_active_high: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            crc: true,
+            card_detect_active_high: false,
+        }
+    }
+}
+
+/// Type of card, as detected during initialization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CardType {
+    /// Standard capacity card, version 1.x.
+    SD1,
+    /// Standard capacity card, version 2.0 or later.
+    SD2,
+    /// High or extended capacity card (SDHC/SDXC), using block addressing.
+    SDHC,
+}
+
+/// Error returned by the SD card driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<SPI> {
+    /// An error occurred on the SPI bus.
+    Spi(SPI),
+    /// Setting the chip select pin or reading the card detect pin failed.
+    Pin,
+    /// No card is inserted.
+    NoCard,
+    /// The card was not initialized with [`SdCard::init`].
+    NotInitialized,
+    /// The card is not a supported SD card.
+    UnsupportedCard,
+    /// The card did not respond in time.
+    Timeout,
+    /// The card rejected a command. Contains the command index and the R1 response.
+    Command(u8, u8),
+    /// A CRC mismatch was detected, either by the card or on received data.
+    Crc,
+    /// The card returned an error token instead of a data block.
+    Read(u8),
+    /// The card rejected written data. Contains the data response token.
+    Write(u8),
+    /// The card failed to program written data. Contains the R2 status response, R1 first.
+    Program(u8, u8),
+    /// The block address is beyond the end of the card.
+    OutOfRange,
+}
+
+/// Card detect pin placeholder for sockets without a card detect switch.
+///
+/// The card is always reported as present.
+pub struct NoCardDetect;
+
+impl embedded_hal_1::digital::ErrorType for NoCardDetect {
+    type Error = core::convert::Infallible;
+}
+
+impl InputPin for NoCardDetect {
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+/// SD card on a SPI bus.
+pub struct SdCard<SPI, CS, CD, D> {
+    spi: SPI,
+    cs: CS,
+    cd: CD,
+    delay: D,
+    config: Config,
+    card_type: Option<CardType>,
+    block_count: u32,
+}
+
+impl<SPI, CS, CD, D> SdCard<SPI, CS, CD, D>
+where
+    SPI: SpiBus + SpiBusFlush,
+    CS: OutputPin,
+    CD: InputPin,
+    D: DelayUs,
+{
+    /// Create a new `SdCard`.
+    ///
+    /// Use [`NoCardDetect`] for `cd` if the socket has no card detect switch.
+    pub fn new(spi: SPI, cs: CS, cd: CD, delay: D, config: Config) -> Self {
+        Self {
+            spi,
+            cs,
+            cd,
+            delay,
+            config,
+            card_type: None,
+            block_count: 0,
+        }
+    }
+
+    /// Release the bus and pins.
+    pub fn release(self) -> (SPI, CS, CD, D) {
+        (self.spi, self.cs, self.cd, self.delay)
+    }
+
+    /// Returns whether a card is inserted, according to the card detect pin.
+    pub fn is_card_present(&self) -> Result<bool, Error<SPI::Error>> {
+        let high = self.cd.is_high().map_err(|_| Error::Pin)?;
+        Ok(high == self.config.card_detect_active_high)
+    }
+
+    /// Type of the card, if it is initialized.
+    pub fn card_type(&self) -> Option<CardType> {
+        self.card_type
+    }
+
+    /// Initialize the card.
+    ///
+    /// This must be called again after the card is reinserted.
+    pub async fn init(&mut self) -> Result<CardType, Error<SPI::Error>> {
+        self.card_type = None;
+        if !self.is_card_present()? {
+            return Err(Error::NoCard);
+        }
+
+        // Supply at least 74 clocks with CS high, so the card enters its native mode.
+        self.cs.set_high().map_err(|_| Error::Pin)?;
+        self.spi.write(&[0xFF; 10]).await.map_err(Error::Spi)?;
+
+        let res = self.init_inner().await;
+        self.deselect().await?;
+
+        let card_type = res?;
+        self.card_type = Some(card_type);
+        Ok(card_type)
+    }
+
+    async fn init_inner(&mut self) -> Result<CardType, Error<SPI::Error>> {
+        self.select()?;
+
+        // Enter SPI mode.
+        let mut attempts = 0;
+        while self.cmd(CMD0_GO_IDLE_STATE, 0).await? != R1_IDLE_STATE {
+            attempts += 1;
+            if attempts == 32 {
+                return Err(Error::Timeout);
+            }
+            self.delay_us(POLL_INTERVAL_US).await;
+        }
+
+        if self.config.crc {
+            let r1 = self.cmd(CMD59_CRC_ON_OFF, 1).await?;
+            if r1 != R1_IDLE_STATE {
+                return Err(Error::Command(CMD59_CRC_ON_OFF, r1));
+            }
+        }
+
+        // Check voltage range, only version 2 cards know this command.
+        let r1 = self.cmd(CMD8_SEND_IF_COND, 0x1AA).await?;
+        let mut card_type = if r1 & R1_ILLEGAL_COMMAND != 0 {
+            CardType::SD1
+        } else {
+            let mut r7 = [0xFF; 4];
+            self.spi.transfer_in_place(&mut r7).await.map_err(Error::Spi)?;
+            if r7[3] != 0xAA {
+                return Err(Error::UnsupportedCard);
+            }
+            CardType::SD2
+        };
+
+        // Start initialization, announcing high capacity support to version 2 cards.
+        let arg = match card_type {
+            CardType::SD1 => 0,
+            _ => 0x4000_0000,
+        };
+        let mut attempts = 0;
+        loop {
+            let r1 = self.acmd(ACMD41_SD_SEND_OP_COND, arg).await?;
+            if r1 == 0 {
+                break;
+            }
+            if r1 != R1_IDLE_STATE {
+                return Err(Error::Command(ACMD41_SD_SEND_OP_COND, r1));
+            }
+            attempts += 1;
+            if attempts == POLL_ATTEMPTS {
+                return Err(Error::Timeout);
+            }
+            self.delay_us(POLL_INTERVAL_US).await;
+        }
+
+        if card_type == CardType::SD2 {
+            let r1 = self.cmd(CMD58_READ_OCR, 0).await?;
+            if r1 != 0 {
+                return Err(Error::Command(CMD58_READ_OCR, r1));
+            }
+            let mut ocr = [0xFF; 4];
+            self.spi.transfer_in_place(&mut ocr).await.map_err(Error::Spi)?;
+            if ocr[0] & 0x40 != 0 {
+                card_type = CardType::SDHC;
+            }
+        }
+
+        // Standard capacity cards may default to a different block length.
+        if card_type != CardType::SDHC {
+            let r1 = self.cmd(CMD16_SET_BLOCKLEN, BLOCK_SIZE as u32).await?;
+            if r1 != 0 {
+                return Err(Error::Command(CMD16_SET_BLOCKLEN, r1));
+            }
+        }
+
+        let r1 = self.cmd(CMD9_SEND_CSD, 0).await?;
+        if r1 != 0 {
+            return Err(Error::Command(CMD9_SEND_CSD, r1));
+        }
+        let mut csd = [0; 16];
+        self.read_data(&mut csd).await?;
+        self.block_count = csd_block_count(&csd).ok_or(Error::UnsupportedCard)?;
+
+        Ok(card_type)
+    }
+
+    /// Read a single block.
+    pub async fn read_block(&mut self, block_address: u32, block: &mut Block) -> Result<(), Error<SPI::Error>> {
+        let address = self.card_address(block_address)?;
+        self.select()?;
+        let res = self.read_block_inner(address, block).await;
+        self.deselect().await?;
+        res
+    }
+
+    async fn read_block_inner(&mut self, address: u32, block: &mut Block) -> Result<(), Error<SPI::Error>> {
+        let r1 = self.cmd(CMD17_READ_SINGLE_BLOCK, address).await?;
+        if r1 != 0 {
+            return Err(Error::Command(CMD17_READ_SINGLE_BLOCK, r1));
+        }
+        self.read_data(block).await
+    }
+
+    /// Write a single block.
+    pub async fn write_block(&mut self, block_address: u32, block: &Block) -> Result<(), Error<SPI::Error>> {
+        let address = self.card_address(block_address)?;
+        self.select()?;
+        let res = self.write_block_inner(address, block).await;
+        self.deselect().await?;
+        res
+    }
+
+    async fn write_block_inner(&mut self, address: u32, block: &Block) -> Result<(), Error<SPI::Error>> {
+        let r1 = self.cmd(CMD24_WRITE_BLOCK, address).await?;
+        if r1 != 0 {
+            return Err(Error::Command(CMD24_WRITE_BLOCK, r1));
+        }
+        self.write_data(block).await?;
+
+        // The data response only covers the transfer, check the programming result too.
+        let r1 = self.cmd(CMD13_SEND_STATUS, 0).await?;
+        let mut r2 = [0xFF; 1];
+        self.spi.transfer_in_place(&mut r2).await.map_err(Error::Spi)?;
+        if r1 != 0 || r2[0] != 0 {
+            return Err(Error::Program(r1, r2[0]));
+        }
+        Ok(())
+    }
+
+    fn card_address(&mut self, block_address: u32) -> Result<u32, Error<SPI::Error>> {
+        if !self.is_card_present()? {
+            self.card_type = None;
+            return Err(Error::NoCard);
+        }
+        match self.card_type {
+            None => Err(Error::NotInitialized),
+            Some(_) if block_address >= self.block_count => Err(Error::OutOfRange),
+            Some(CardType::SDHC) => Ok(block_address),
+            Some(_) => block_address.checked_mul(BLOCK_SIZE as u32).ok_or(Error::OutOfRange),
+        }
+    }
+
+    fn select(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.cs.set_low().map_err(|_| Error::Pin)
+    }
+
+    async fn deselect(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.cs.set_high().map_err(|_| Error::Pin)?;
+        // The card only releases MISO on the next clock edge after CS goes high.
+        self.spi.write(&[0xFF]).await.map_err(Error::Spi)?;
+        self.spi.flush().await.map_err(Error::Spi)
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        let _ = self.delay.delay_us(us).await;
+    }
+
+    async fn read_byte(&mut self) -> Result<u8, Error<SPI::Error>> {
+        let mut buf = [0xFF];
+        self.spi.transfer_in_place(&mut buf).await.map_err(Error::Spi)?;
+        Ok(buf[0])
+    }
+
+    /// Wait until the card stops signalling busy.
+    async fn wait_ready(&mut self) -> Result<(), Error<SPI::Error>> {
+        for _ in 0..POLL_ATTEMPTS {
+            if self.read_byte().await? == 0xFF {
+                return Ok(());
+            }
+            self.delay_us(POLL_INTERVAL_US).await;
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Send a command and return its R1 response.
+    async fn cmd(&mut self, cmd: u8, arg: u32) -> Result<u8, Error<SPI::Error>> {
+        if cmd != CMD0_GO_IDLE_STATE {
+            self.wait_ready().await?;
+        }
+
+        let mut frame = [0x40 | cmd, 0, 0, 0, 0, 0];
+        frame[1..5].copy_from_slice(&arg.to_be_bytes());
+        frame[5] = (crc7(&frame[..5]) << 1) | 1;
+        self.spi.write(&frame).await.map_err(Error::Spi)?;
+
+        // The response arrives within 8 bytes and always has the top bit cleared.
+        for _ in 0..8 {
+            let r1 = self.read_byte().await?;
+            if r1 & 0x80 == 0 {
+                if r1 & R1_CRC_ERROR != 0 {
+                    return Err(Error::Crc);
+                }
+                return Ok(r1);
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Send an application specific command and return its R1 response.
+    async fn acmd(&mut self, cmd: u8, arg: u32) -> Result<u8, Error<SPI::Error>> {
+        self.cmd(CMD55_APP_CMD, 0).await?;
+        self.cmd(cmd, arg).await
+    }
+
+    async fn read_data(&mut self, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let mut token = 0xFF;
+        for _ in 0..POLL_ATTEMPTS {
+            token = self.read_byte().await?;
+            if token != 0xFF {
+                break;
+            }
+            self.delay_us(POLL_INTERVAL_US).await;
+        }
+        match token {
+            DATA_START_BLOCK => {}
+            0xFF => return Err(Error::Timeout),
+            token => return Err(Error::Read(token)),
+        }
+
+        // MOSI must be held high while reading, don't leave it to the bus implementation.
+        buf.fill(0xFF);
+        self.spi.transfer_in_place(buf).await.map_err(Error::Spi)?;
+
+        let mut crc = [0xFF; 2];
+        self.spi.transfer_in_place(&mut crc).await.map_err(Error::Spi)?;
+        if self.config.crc && u16::from_be_bytes(crc) != crc16(buf) {
+            return Err(Error::Crc);
+        }
+        Ok(())
+    }
+
+    async fn write_data(&mut self, buf: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.spi.write(&[0xFF, DATA_START_BLOCK]).await.map_err(Error::Spi)?;
+        self.spi.write(buf).await.map_err(Error::Spi)?;
+        let crc = if self.config.crc { crc16(buf) } else { 0xFFFF };
+        self.spi.write(&crc.to_be_bytes()).await.map_err(Error::Spi)?;
+
+        let response = self.read_byte().await? & DATA_RES_MASK;
+        match response {
+            DATA_RES_ACCEPTED => {}
+            DATA_RES_CRC_ERROR => return Err(Error::Crc),
+            response => return Err(Error::Write(response)),
+        }
+
+        self.wait_ready().await
+    }
+}
+
+impl<SPI, CS, CD, D> BlockDevice for SdCard<SPI, CS, CD, D>
+where
+    SPI: SpiBus + SpiBusFlush,
+    CS: OutputPin,
+    CD: InputPin,
+    D: DelayUs,
+{
+    type Error = Error<SPI::Error>;
+
+    type ReadFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+    fn read<'a>(&'a mut self, block_address: u32, blocks: &'a mut [Block]) -> Self::ReadFuture<'a> {
+        async move {
+            for (i, block) in blocks.iter_mut().enumerate() {
+                let block_address = block_address.checked_add(i as u32).ok_or(Error::OutOfRange)?;
+                self.read_block(block_address, block).await?;
+            }
+            Ok(())
+        }
+    }
+
+    type WriteFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+    fn write<'a>(&'a mut self, block_address: u32, blocks: &'a [Block]) -> Self::WriteFuture<'a> {
+        async move {
+            for (i, block) in blocks.iter().enumerate() {
+                let block_address = block_address.checked_add(i as u32).ok_or(Error::OutOfRange)?;
+                self.write_block(block_address, block).await?;
+            }
+            Ok(())
+        }
+    }
+
+    fn block_count(&self) -> u32 {
+        self.block_count
+    }
+}
+
+/// Number of 512 byte blocks on the card, from the CSD register.
+fn csd_block_count(csd: &[u8; 16]) -> Option<u32> {
+    match csd[0] >> 6 {
+        // CSD version 1.0
+        0 => {
+            let c_size = ((csd[6] as u32 & 0x03) << 10) | ((csd[7] as u32) << 2) | (csd[8] as u32 >> 6);
+            let c_size_mult = ((csd[9] as u32 & 0x03) << 1) | (csd[10] as u32 >> 7);
+            // Blocks of 512, 1024 or 2048 bytes, anything else is a malformed CSD.
+            let read_bl_len = csd[5] as u32 & 0x0F;
+            if !(9..=11).contains(&read_bl_len) {
+                return None;
+            }
+            Some((c_size + 1) << (c_size_mult + 2 + read_bl_len - 9))
+        }
+        // CSD version 2.0
+        1 => {
+            let c_size = ((csd[7] as u32 & 0x3F) << 16) | ((csd[8] as u32) << 8) | csd[9] as u32;
+            Some((c_size + 1) * 1024)
+        }
+        _ => None,
+    }
+}
+
+/// CRC7 used for commands.
+fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            crc <<= 1;
+            if (byte ^ crc) & 0x80 != 0 {
+                crc ^= 0x09;
+            }
+            byte <<= 1;
+        }
+    }
+    crc & 0x7F
+}
+
+/// CRC16-CCITT used for data blocks.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = (crc << 1) ^ if crc & 0x8000 != 0 { 0x1021 } else { 0 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc7_of_commands() {
+        // CMD0 and CMD8, the only commands whose CRC the card checks before CRC is turned on.
+        assert_eq!((crc7(&[0x40, 0x00, 0x00, 0x00, 0x00]) << 1) | 1, 0x95);
+        assert_eq!((crc7(&[0x48, 0x00, 0x00, 0x01, 0xAA]) << 1) | 1, 0x87);
+    }
+
+    #[test]
+    fn crc16_of_block() {
+        assert_eq!(crc16(&[0xFF; 512]), 0x7FA1);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn csd_v1_block_count() {
+        let mut csd = [
+            0x00, 0x26, 0x00, 0x32, 0x5F, 0x59, 0x83, 0xC8, 0xBE, 0xFB, 0xCF, 0xFF, 0x92, 0x40, 0x40, 0xDF,
+        ];
+        // C_SIZE 3874, C_SIZE_MULT 7, READ_BL_LEN 9.
+        assert_eq!(csd_block_count(&csd), Some(3875 << 9));
+
+        // 1024 byte blocks, counted as 512 byte blocks.
+        csd[5] = 0x5A;
+        assert_eq!(csd_block_count(&csd), Some(3875 << 10));
+    }
+
+    #[test]
+    fn csd_v1_rejects_bad_block_length() {
+        let mut csd = [
+            0x00, 0x26, 0x00, 0x32, 0x5F, 0x59, 0x83, 0xC8, 0xBE, 0xFB, 0xCF, 0xFF, 0x92, 0x40, 0x40, 0xDF,
+        ];
+        for read_bl_len in [0x0, 0x8, 0xC, 0xF] {
+            csd[5] = 0x50 | read_bl_len;
+            assert_eq!(csd_block_count(&csd), None);
+        }
+    }
+
+    #[test]
+    fn csd_v2_block_count() {
+        let csd = [
+            0x40, 0x0E, 0x00, 0x32, 0x5B, 0x59, 0x00, 0x00, 0x3B, 0x37, 0x7F, 0x80, 0x0A, 0x40, 0x00, 0x8B,
+        ];
+        // C_SIZE 15159.
+        assert_eq!(csd_block_count(&csd), Some(15160 * 1024));
+    }
+
+    #[test]
+    fn csd_unknown_version() {
+        let mut csd = [0; 16];
+        csd[0] = 0x80;
+        assert_eq!(csd_block_count(&csd), None);
+    }
+}
@@ -51,6 +51,7 @@ pub struct TaskHeader {
     pub(crate) run_queue_item: RunQueueItem,
     pub(crate) executor: Cell<*const Executor>, // Valid if state != 0
     pub(crate) poll_fn: UninitCell<unsafe fn(NonNull<TaskHeader>)>, // Valid if STATE_SPAWNED
+    pub(crate) priority: u8,
 
     #[cfg(feature = "integrated-timers")]
     pub(crate) expires_at: Cell<Instant>,
@@ -59,12 +60,13 @@ pub struct TaskHeader {
 }
 
 impl TaskHeader {
-    pub(crate) const fn new() -> Self {
+    pub(crate) const fn new(priority: u8) -> Self {
         Self {
             state: AtomicU32::new(0),
             run_queue_item: RunQueueItem::new(),
             executor: Cell::new(ptr::null()),
             poll_fn: UninitCell::uninit(),
+            priority,
 
             #[cfg(feature = "integrated-timers")]
             expires_at: Cell::new(Instant::from_ticks(0)),
@@ -86,6 +88,10 @@ impl TaskHeader {
 /// Internally, the [embassy_executor::task](embassy_macros::task) macro allocates an array of `TaskStorage`s
 /// in a `static`. The most common reason to use the raw `Task` is to have control of where
 /// the memory for the task is allocated: on the stack, or on the heap with e.g. `Box::leak`, etc.
+///
+/// Each task has a priority, 0 by default. When several tasks of an executor are ready to run,
+/// the ones with the highest priority are polled first. Tasks with the same priority are polled
+/// in the order they were woken, or spawned.
 
 // repr(C) is needed to guarantee that the Task is located at offset 0
 // This makes it safe to cast between TaskHeader and TaskStorage pointers.
@@ -100,8 +106,15 @@ impl<F: Future + 'static> TaskStorage<F> {
 
     /// Create a new TaskStorage, in not-spawned state.
     pub const fn new() -> Self {
+        Self::with_priority(0)
+    }
+
+    /// Create a new TaskStorage, in not-spawned state, for a task with the given priority.
+    ///
+    /// See [`TaskStorage`] for how priorities are used.
+    pub const fn with_priority(priority: u8) -> Self {
         Self {
-            raw: TaskHeader::new(),
+            raw: TaskHeader::new(priority),
             future: UninitCell::uninit(),
         }
     }
@@ -179,6 +192,19 @@ impl<F: Future + 'static, const N: usize> TaskPool<F, N> {
         }
     }
 
+    /// Create a new TaskPool, with all tasks in non-spawned state, for tasks with the given priority.
+    ///
+    /// See [`TaskStorage`] for how priorities are used.
+    pub const fn with_priority(priority: u8) -> Self {
+        let mut pool = [TaskStorage::NEW; N];
+        let mut i = 0;
+        while i < N {
+            pool[i].raw.priority = priority;
+            i += 1;
+        }
+        Self { pool }
+    }
+
     /// Try to spawn a task in the pool.
     ///
     /// See [`TaskStorage::spawn()`] for details.
@@ -339,7 +365,8 @@ impl Executor {
     /// Poll all queued tasks in this executor.
     ///
     /// This loops over all tasks that are queued to be polled (i.e. they're
-    /// freshly spawned or they've been woken). Other tasks are not polled.
+    /// freshly spawned or they've been woken), highest priority first. Other tasks
+    /// are not polled.
     ///
     /// You must call `poll` after receiving a call to `signal_fn`. It is OK
     /// to call `poll` even when not requested by `signal_fn`, but it wastes
@@ -461,3 +488,79 @@ impl rtos_trace::RtosTraceOSCallbacks for Executor {
 
 #[cfg(feature = "rtos-trace")]
 rtos_trace::global_os_callbacks! {Executor}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::future::poll_fn;
+    use core::task::Waker;
+    use std::boxed::Box;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    use super::*;
+
+    type Log = &'static Mutex<Vec<usize>>;
+
+    fn executor() -> &'static Executor {
+        Box::leak(Box::new(Executor::new(|_| {}, ptr::null_mut())))
+    }
+
+    fn log() -> Log {
+        Box::leak(Box::new(Mutex::new(Vec::new())))
+    }
+
+    fn spawn(executor: &'static Executor, priority: u8, future: impl Future + 'static) {
+        let task = Box::leak(Box::new(TaskStorage::with_priority(priority)));
+        executor.spawner().spawn(task.spawn(|| future)).unwrap();
+    }
+
+    /// Logs `id` on the second poll, after having been woken once.
+    fn wait_for_wake(id: usize, log: Log, wakers: &'static Mutex<Vec<Option<Waker>>>) -> impl Future {
+        let mut woken = false;
+        poll_fn(move |cx| {
+            if woken {
+                log.lock().unwrap().push(id);
+                return Poll::Ready(());
+            }
+            woken = true;
+            wakers.lock().unwrap()[id] = Some(cx.waker().clone());
+            Poll::Pending
+        })
+    }
+
+    #[test]
+    fn spawned_tasks_are_polled_by_priority() {
+        let executor = executor();
+        let log = log();
+
+        for (id, priority) in [0, 2, 1, 2, 0].into_iter().enumerate() {
+            spawn(executor, priority, async move { log.lock().unwrap().push(id) });
+        }
+        unsafe { executor.poll() };
+
+        assert_eq!(*log.lock().unwrap(), [1, 3, 2, 0, 4]);
+    }
+
+    #[test]
+    fn woken_tasks_are_polled_by_priority_then_wake_order() {
+        let executor = executor();
+        let log = log();
+        let wakers: &Mutex<Vec<Option<Waker>>> = Box::leak(Box::new(Mutex::new(Vec::new())));
+
+        for (id, priority) in [0, 0, 0, 1].into_iter().enumerate() {
+            wakers.lock().unwrap().push(None);
+            spawn(executor, priority, wait_for_wake(id, log, wakers));
+        }
+        unsafe { executor.poll() };
+        assert!(log.lock().unwrap().is_empty());
+
+        for id in [2, 0, 3, 1] {
+            wakers.lock().unwrap()[id].take().unwrap().wake();
+        }
+        unsafe { executor.poll() };
+
+        assert_eq!(*log.lock().unwrap(), [3, 2, 0, 1]);
+    }
+}
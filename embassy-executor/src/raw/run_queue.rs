@@ -25,10 +25,12 @@ impl RunQueueItem {
 /// Dequeuing is done in batches: the queue is emptied by atomically replacing head with
 /// null. Then the batch is iterated following the next pointers until null is reached.
 ///
-/// Note that batches will be iterated in the reverse order as they were enqueued. This is OK
-/// for our purposes: it can't create fairness problems since the next batch won't run until the
-/// current batch is completely processed, so even if a task enqueues itself instantly (for example
-/// by waking its own waker) can't prevent other tasks from running.
+/// Before being iterated, a batch is sorted by task priority, highest first, and tasks with the same
+/// priority in the order they were enqueued. The next batch won't run until the current batch is
+/// completely processed, so this can't create fairness problems: even a task that enqueues itself
+/// instantly (for example by waking its own waker) can't prevent other tasks from running. A task
+/// enqueued while a batch is being processed waits for the next batch, even if its priority is
+/// higher, so a high-priority task can't starve the other tasks either.
 pub(crate) struct RunQueue {
     head: AtomicPtr<TaskHeader>,
 }
@@ -58,7 +60,10 @@ impl RunQueue {
     /// and will be processed by the *next* call to `dequeue_all`, *not* the current one.
     pub(crate) fn dequeue_all(&self, on_task: impl Fn(NonNull<TaskHeader>)) {
         // Atomically empty the queue.
-        let mut ptr = self.head.swap(ptr::null_mut(), Ordering::AcqRel);
+        let ptr = self.head.swap(ptr::null_mut(), Ordering::AcqRel);
+
+        // Tasks with a higher priority run first.
+        let mut ptr = unsafe { sort_by_priority(ptr) };
 
         // Iterate the linked list of tasks that were previously in the queue.
         while let Some(task) = NonNull::new(ptr) {
@@ -72,3 +77,38 @@ impl RunQueue {
         }
     }
 }
+
+/// Sorts a batch of tasks by decreasing priority, tasks with the same priority in the order they
+/// were enqueued.
+///
+/// # Safety
+///
+/// The tasks must have been dequeued, so that nothing else accesses their `next` pointers.
+unsafe fn sort_by_priority(mut ptr: *mut TaskHeader) -> *mut TaskHeader {
+    let mut head: *mut TaskHeader = ptr::null_mut();
+
+    // The batch starts with the task enqueued last, so each task goes before the tasks with the
+    // same priority already sorted.
+    while let Some(task) = NonNull::new(ptr) {
+        let task = task.as_ptr();
+        ptr = (*task).run_queue_item.next.load(Ordering::Relaxed);
+
+        if head.is_null() || (*head).priority <= (*task).priority {
+            // Most tasks have the same priority, make prepending them cheap.
+            (*task).run_queue_item.next.store(head, Ordering::Relaxed);
+            head = task;
+        } else {
+            // Insert the task after the last one with a higher priority.
+            let mut prev = head;
+            let mut next = (*prev).run_queue_item.next.load(Ordering::Relaxed);
+            while !next.is_null() && (*next).priority > (*task).priority {
+                prev = next;
+                next = (*next).run_queue_item.next.load(Ordering::Relaxed);
+            }
+            (*task).run_queue_item.next.store(next, Ordering::Relaxed);
+            (*prev).run_queue_item.next.store(task, Ordering::Relaxed);
+        }
+    }
+
+    head
+}
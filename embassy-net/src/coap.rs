@@ -0,0 +1,1186 @@
+//! CoAP client and server.
+//!
+//! [`CoapClient`] sends requests, confirmable or not, retransmitting the confirmable ones until
+//! they are acknowledged. Large payloads are split in blocks, and large responses are fetched
+//! block by block into the buffer of the application (RFC 7959). Resources can be observed with
+//! [`CoapClient::observe`] (RFC 7641).
+//!
+//! [`CoapServer`] answers requests with a handler, which writes the whole representation of the
+//! resource: the block asked for by the client is sent. The server doesn't keep state between
+//! requests, so duplicated requests are handled again, and large request payloads, which would
+//! have to be reassembled, are rejected.
+
+use core::cell::Cell;
+use core::str;
+
+use embassy_time::{with_timeout, Duration, Instant};
+use smoltcp::wire::IpEndpoint;
+
+use crate::udp::{self, BindError, UdpSocket};
+use crate::{Device, PacketMetadata, Stack};
+
+/// Default CoAP port.
+pub const PORT: u16 = 5683;
+
+/// Maximum size of the messages, which fits in the minimum IPv6 MTU with the headers.
+pub const MAX_MESSAGE_SIZE: usize = 1152;
+
+/// Size of the socket buffers needed, for one message.
+pub const BUFFER_SIZE: usize = MAX_MESSAGE_SIZE;
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+const MAX_TOKEN_LEN: usize = 8;
+
+/// Time after which a notification is fresher than the previous one, whatever their sequence
+/// numbers.
+const OBSERVE_FRESHNESS: Duration = Duration::from_secs(128);
+
+/// Option numbers.
+pub mod option {
+    pub const IF_MATCH: u16 = 1;
+    pub const URI_HOST: u16 = 3;
+    pub const ETAG: u16 = 4;
+    pub const IF_NONE_MATCH: u16 = 5;
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PORT: u16 = 7;
+    pub const LOCATION_PATH: u16 = 8;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const MAX_AGE: u16 = 14;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+    pub const LOCATION_QUERY: u16 = 20;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const PROXY_URI: u16 = 35;
+    pub const PROXY_SCHEME: u16 = 39;
+    pub const SIZE1: u16 = 60;
+}
+
+/// Content formats.
+pub mod content_format {
+    pub const TEXT_PLAIN: u16 = 0;
+    pub const LINK_FORMAT: u16 = 40;
+    pub const XML: u16 = 41;
+    pub const OCTET_STREAM: u16 = 42;
+    pub const EXI: u16 = 47;
+    pub const JSON: u16 = 50;
+    pub const CBOR: u16 = 60;
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The message couldn't be sent.
+    Send(udp::Error),
+    /// The peer didn't answer in time.
+    Timeout,
+    /// The peer rejected the message with a reset.
+    Reset,
+    /// The request doesn't fit in a message.
+    MessageTooLarge,
+    /// The payload of the response doesn't fit in the buffer.
+    BufferTooSmall,
+    /// The message isn't valid CoAP.
+    InvalidMessage,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+/// Method or response code, made of a class and a detail, written `class.detail`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Code(pub u8);
+
+impl Code {
+    pub const EMPTY: Code = Code::new(0, 0);
+    pub const GET: Code = Code::new(0, 1);
+    pub const POST: Code = Code::new(0, 2);
+    pub const PUT: Code = Code::new(0, 3);
+    pub const DELETE: Code = Code::new(0, 4);
+    pub const CREATED: Code = Code::new(2, 1);
+    pub const DELETED: Code = Code::new(2, 2);
+    pub const VALID: Code = Code::new(2, 3);
+    pub const CHANGED: Code = Code::new(2, 4);
+    pub const CONTENT: Code = Code::new(2, 5);
+    pub const CONTINUE: Code = Code::new(2, 31);
+    pub const BAD_REQUEST: Code = Code::new(4, 0);
+    pub const BAD_OPTION: Code = Code::new(4, 2);
+    pub const NOT_FOUND: Code = Code::new(4, 4);
+    pub const METHOD_NOT_ALLOWED: Code = Code::new(4, 5);
+    pub const REQUEST_ENTITY_INCOMPLETE: Code = Code::new(4, 8);
+    pub const REQUEST_ENTITY_TOO_LARGE: Code = Code::new(4, 13);
+    pub const INTERNAL_SERVER_ERROR: Code = Code::new(5, 0);
+    pub const NOT_IMPLEMENTED: Code = Code::new(5, 1);
+    pub const SERVICE_UNAVAILABLE: Code = Code::new(5, 3);
+
+    pub const fn new(class: u8, detail: u8) -> Self {
+        Self(class << 5 | detail)
+    }
+
+    pub fn class(&self) -> u8 {
+        self.0 >> 5
+    }
+
+    pub fn detail(&self) -> u8 {
+        self.0 & 0x1f
+    }
+
+    /// Whether this is a method, as opposed to a response code or the empty code.
+    pub fn is_request(&self) -> bool {
+        self.class() == 0 && *self != Code::EMPTY
+    }
+
+    /// Whether this is a 2.xx response code.
+    pub fn is_success(&self) -> bool {
+        self.class() == 2
+    }
+}
+
+/// Value of the Block1 and Block2 options: a block of a payload split in blocks of the same
+/// power-of-two size.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Block {
+    /// Number of the block.
+    pub num: u32,
+    /// Whether more blocks follow.
+    pub more: bool,
+    /// Exponent of the size of the blocks, which is `2^(szx + 4)`, from 0 to 6.
+    pub szx: u8,
+}
+
+impl Block {
+    pub fn size(&self) -> usize {
+        1 << (self.szx + 4)
+    }
+
+    /// Offset of the block in the payload.
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    fn from_value(value: u32) -> Option<Self> {
+        let szx = (value & 0x07) as u8;
+        if szx == 7 {
+            return None;
+        }
+        Some(Self {
+            num: value >> 4,
+            more: value & 0x08 != 0,
+            szx,
+        })
+    }
+
+    fn value(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+}
+
+/// A parsed CoAP message.
+#[derive(Clone, Copy, Debug)]
+pub struct Message<'m> {
+    pub msg_type: MessageType,
+    pub code: Code,
+    pub message_id: u16,
+    pub token: &'m [u8],
+    options: &'m [u8],
+    pub payload: &'m [u8],
+}
+
+impl<'m> Message<'m> {
+    pub fn parse(buf: &'m [u8]) -> Result<Self, Error> {
+        if buf.len() < 4 || buf[0] >> 6 != VERSION {
+            return Err(Error::InvalidMessage);
+        }
+        let msg_type = match (buf[0] >> 4) & 0x03 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        };
+        let token_len = (buf[0] & 0x0f) as usize;
+        if token_len > MAX_TOKEN_LEN {
+            return Err(Error::InvalidMessage);
+        }
+        let token = buf.get(4..4 + token_len).ok_or(Error::InvalidMessage)?;
+
+        let options = &buf[4 + token_len..];
+        let mut rest = options;
+        let mut number = 0;
+        while !rest.is_empty() && rest[0] != PAYLOAD_MARKER {
+            let (n, _, r) = parse_option(rest, number).ok_or(Error::InvalidMessage)?;
+            number = n;
+            rest = r;
+        }
+        let payload = match rest {
+            [] => &[][..],
+            // The marker must not be followed by an empty payload.
+            [_] => return Err(Error::InvalidMessage),
+            [_, payload @ ..] => payload,
+        };
+
+        Ok(Self {
+            msg_type,
+            code: Code(buf[1]),
+            message_id: u16::from_be_bytes([buf[2], buf[3]]),
+            token,
+            options: &options[..options.len() - rest.len()],
+            payload,
+        })
+    }
+
+    /// The options, as number-value pairs, in increasing number order.
+    pub fn options(&self) -> Options<'m> {
+        Options {
+            data: self.options,
+            number: 0,
+        }
+    }
+
+    /// Value of the first option numbered `number`.
+    pub fn option(&self, number: u16) -> Option<&'m [u8]> {
+        self.options().find(|(n, _)| *n == number).map(|(_, v)| v)
+    }
+
+    /// Value of the first option numbered `number`, as an unsigned integer.
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        let value = self.option(number)?;
+        if value.len() > 4 {
+            return None;
+        }
+        Some(value.iter().fold(0, |acc, b| acc << 8 | *b as u32))
+    }
+
+    /// The segments of the path of the request.
+    pub fn uri_path(&self) -> impl Iterator<Item = &'m str> {
+        self.options()
+            .filter(|(n, _)| *n == option::URI_PATH)
+            .map(|(_, v)| str::from_utf8(v).unwrap_or(""))
+    }
+
+    /// Whether the path of the request is `path`, whose segments are separated by slashes.
+    pub fn path_eq(&self, path: &str) -> bool {
+        let mut segments = self.uri_path();
+        path_segments(path).all(|s| segments.next() == Some(s)) && segments.next().is_none()
+    }
+
+    pub fn content_format(&self) -> Option<u16> {
+        self.uint_option(option::CONTENT_FORMAT).map(|v| v as u16)
+    }
+
+    pub fn block1(&self) -> Option<Block> {
+        self.uint_option(option::BLOCK1).and_then(Block::from_value)
+    }
+
+    pub fn block2(&self) -> Option<Block> {
+        self.uint_option(option::BLOCK2).and_then(Block::from_value)
+    }
+}
+
+/// Iterator over the options of a [`Message`].
+pub struct Options<'m> {
+    data: &'m [u8],
+    number: u16,
+}
+
+impl<'m> Iterator for Options<'m> {
+    type Item = (u16, &'m [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        // The options were checked when parsing the message.
+        let (number, value, rest) = parse_option(self.data, self.number)?;
+        self.number = number;
+        self.data = rest;
+        Some((number, value))
+    }
+}
+
+/// Parse the option at the start of `data`, following the option numbered `prev`, returning
+/// its number, its value and the data after it.
+fn parse_option(data: &[u8], prev: u16) -> Option<(u16, &[u8], &[u8])> {
+    fn extended(nibble: u8, data: &[u8], pos: &mut usize) -> Option<usize> {
+        match nibble {
+            0..=12 => Some(nibble as usize),
+            13 => {
+                let v = *data.get(*pos)? as usize + 13;
+                *pos += 1;
+                Some(v)
+            }
+            14 => {
+                let b = data.get(*pos..*pos + 2)?;
+                *pos += 2;
+                Some(u16::from_be_bytes([b[0], b[1]]) as usize + 269)
+            }
+            _ => None,
+        }
+    }
+
+    let mut pos = 1;
+    let delta = extended(data[0] >> 4, data, &mut pos)?;
+    let len = extended(data[0] & 0x0f, data, &mut pos)?;
+    let number = u16::try_from(prev as usize + delta).ok()?;
+    let value = data.get(pos..pos + len)?;
+    Some((number, value, &data[pos + len..]))
+}
+
+fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+/// Writer of a message, whose options must be added in increasing number order.
+struct MessageWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    last_option: u16,
+    overflow: bool,
+}
+
+impl<'b> MessageWriter<'b> {
+    fn new(buf: &'b mut [u8], msg_type: MessageType, code: Code, message_id: u16, token: &[u8]) -> Self {
+        let mut w = Self {
+            buf,
+            len: 0,
+            last_option: 0,
+            overflow: false,
+        };
+        let id = message_id.to_be_bytes();
+        w.raw(&[
+            VERSION << 6 | (msg_type as u8) << 4 | token.len() as u8,
+            code.0,
+            id[0],
+            id[1],
+        ]);
+        w.raw(token);
+        w
+    }
+
+    fn raw(&mut self, data: &[u8]) {
+        match self.buf.get_mut(self.len..self.len + data.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(data);
+                self.len += data.len();
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    fn option(&mut self, number: u16, value: &[u8]) {
+        fn nibble(v: usize) -> (u8, [u8; 2], usize) {
+            match v {
+                0..=12 => (v as u8, [0; 2], 0),
+                13..=268 => (13, [(v - 13) as u8, 0], 1),
+                _ => (14, ((v - 269) as u16).to_be_bytes(), 2),
+            }
+        }
+
+        debug_assert!(number >= self.last_option);
+        let (delta, delta_ext, delta_ext_len) = nibble((number - self.last_option) as usize);
+        let (len, len_ext, len_ext_len) = nibble(value.len());
+        self.raw(&[delta << 4 | len]);
+        self.raw(&delta_ext[..delta_ext_len]);
+        self.raw(&len_ext[..len_ext_len]);
+        self.raw(value);
+        self.last_option = number;
+    }
+
+    fn uint_option(&mut self, number: u16, value: u32) {
+        let bytes = value.to_be_bytes();
+        let skip = (value.leading_zeros() / 8) as usize;
+        self.option(number, &bytes[skip..]);
+    }
+
+    fn path(&mut self, number: u16, path: &str) {
+        for segment in path_segments(path) {
+            self.option(number, segment.as_bytes());
+        }
+    }
+
+    fn query(&mut self, query: Option<&str>) {
+        for param in query.iter().flat_map(|q| q.split('&')).filter(|p| !p.is_empty()) {
+            self.option(option::URI_QUERY, param.as_bytes());
+        }
+    }
+
+    /// Finish the message with `payload`, returning its length.
+    fn finish(mut self, payload: &[u8]) -> Result<usize, Error> {
+        if !payload.is_empty() {
+            self.raw(&[PAYLOAD_MARKER]);
+            self.raw(payload);
+        }
+        if self.overflow {
+            return Err(Error::MessageTooLarge);
+        }
+        Ok(self.len)
+    }
+}
+
+/// Exponent of the largest block size not larger than `size`.
+fn szx(size: u16) -> u8 {
+    (15 - size.clamp(16, 1024).leading_zeros() - 4) as u8
+}
+
+/// CoAP configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// Time to wait for the acknowledgement of a confirmable message, before retransmitting it.
+    /// It's doubled after each retransmission.
+    pub ack_timeout: Duration,
+    /// Number of retransmissions of a confirmable message.
+    pub max_retransmit: u8,
+    /// Time to wait for a response after the acknowledgement of a request, or for the response
+    /// to a non-confirmable request.
+    pub response_timeout: Duration,
+    /// Size of the blocks of large payloads, a power of two from 16 to 1024.
+    pub block_size: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_secs(2),
+            max_retransmit: 4,
+            response_timeout: Duration::from_secs(30),
+            block_size: 512,
+        }
+    }
+}
+
+/// Request sent by a [`CoapClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request<'r> {
+    pub method: Code,
+    /// Path of the resource, whose segments are separated by slashes.
+    pub path: &'r str,
+    /// Query of the request, whose parameters are separated by ampersands.
+    pub query: Option<&'r str>,
+    pub content_format: Option<u16>,
+    pub payload: &'r [u8],
+    /// Whether the request is confirmable, that is retransmitted until acknowledged.
+    pub confirmable: bool,
+}
+
+impl<'r> Request<'r> {
+    /// Confirmable request without payload.
+    pub fn new(method: Code, path: &'r str) -> Self {
+        Self {
+            method,
+            path,
+            query: None,
+            content_format: None,
+            payload: &[],
+            confirmable: true,
+        }
+    }
+
+    pub fn get(path: &'r str) -> Self {
+        Self::new(Code::GET, path)
+    }
+
+    pub fn post(path: &'r str, payload: &'r [u8]) -> Self {
+        Self {
+            payload,
+            ..Self::new(Code::POST, path)
+        }
+    }
+
+    pub fn put(path: &'r str, payload: &'r [u8]) -> Self {
+        Self {
+            payload,
+            ..Self::new(Code::PUT, path)
+        }
+    }
+
+    pub fn delete(path: &'r str) -> Self {
+        Self::new(Code::DELETE, path)
+    }
+}
+
+/// Response to a request, whose payload is in the buffer given with the request.
+///
+/// It's also returned by the handler of a [`CoapServer`], which writes the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    pub code: Code,
+    pub content_format: Option<u16>,
+    /// Length of the payload.
+    pub len: usize,
+    /// Sequence number of a notification, for observed resources.
+    pub observe: Option<u32>,
+}
+
+impl Response {
+    /// Response without payload.
+    pub fn new(code: Code) -> Self {
+        Self {
+            code,
+            content_format: None,
+            len: 0,
+            observe: None,
+        }
+    }
+}
+
+/// CoAP client.
+pub struct CoapClient<'a> {
+    socket: UdpSocket<'a>,
+    config: Config,
+    next_message_id: Cell<u16>,
+    next_token: Cell<u32>,
+}
+
+impl<'a> CoapClient<'a> {
+    /// Create a CoAP client, using a dynamic port of `stack`.
+    ///
+    /// The buffers should hold at least [`BUFFER_SIZE`] bytes.
+    pub fn new<D: Device>(
+        stack: &'a Stack<D>,
+        config: Config,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, BindError> {
+        let mut socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+        socket.bind(0)?;
+
+        // Start from an arbitrary point, so that the identifiers of a previous run aren't
+        // reused right away.
+        let seed = Instant::now().as_ticks();
+        Ok(Self {
+            socket,
+            config,
+            next_message_id: Cell::new(seed as u16),
+            next_token: Cell::new((seed >> 16) as u32),
+        })
+    }
+
+    /// Send `request` to `remote`, and wait for the response, whose payload is written to `buf`.
+    pub async fn request(&self, remote: IpEndpoint, request: &Request<'_>, buf: &mut [u8]) -> Result<Response, Error> {
+        let token = self.token();
+        let mut packet = [0; MAX_MESSAGE_SIZE];
+        let n = self.send_payload(remote, request, &token, &mut packet).await?;
+        self.read_payload(remote, request, &token, &mut packet, n, buf).await
+    }
+
+    /// Observe the resource at `path` of `remote`.
+    ///
+    /// `on_notification` is called with the current state of the resource, then with each
+    /// notification of the server, until it returns `false`. The observation is then
+    /// cancelled. It's also called once, then this returns, if the server can't be observed.
+    ///
+    /// This waits for notifications forever, and should be wrapped in a timeout if the server
+    /// may vanish.
+    pub async fn observe(
+        &self,
+        remote: IpEndpoint,
+        path: &str,
+        buf: &mut [u8],
+        mut on_notification: impl FnMut(&Response, &[u8]) -> bool,
+    ) -> Result<(), Error> {
+        let request = Request::get(path);
+        let token = self.token();
+        let mut packet = [0; MAX_MESSAGE_SIZE];
+        let mut n = self
+            .exchange(remote, &request, &token, Some(0), None, None, &[], &mut packet)
+            .await?;
+        let mut last: Option<(u32, Instant)> = None;
+        loop {
+            let observe = Message::parse(&packet[..n])?.uint_option(option::OBSERVE);
+            let fresh = match (last, observe) {
+                (Some((prev, at)), Some(seq)) => is_fresher(prev, seq) || at.elapsed() > OBSERVE_FRESHNESS,
+                _ => true,
+            };
+            if fresh {
+                let response = self.read_payload(remote, &request, &token, &mut packet, n, buf).await?;
+                let observing = observe.is_some() && response.code.is_success();
+                if !on_notification(&response, &buf[..response.len]) || !observing {
+                    if observing {
+                        self.exchange(remote, &request, &token, Some(1), None, None, &[], &mut packet)
+                            .await?;
+                    }
+                    return Ok(());
+                }
+                last = observe.map(|seq| (seq, Instant::now()));
+            }
+
+            n = loop {
+                if let Some(n) = self.receive_response(remote, None, &token, &mut packet).await? {
+                    break n;
+                }
+            };
+        }
+    }
+
+    /// Send the payload of `request`, in blocks if it doesn't fit in one message, returning the
+    /// length of the response in `packet`.
+    async fn send_payload(
+        &self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        token: &[u8],
+        packet: &mut [u8; MAX_MESSAGE_SIZE],
+    ) -> Result<usize, Error> {
+        let szx = szx(self.config.block_size);
+        let size = 1 << (szx + 4);
+        if request.payload.len() <= size {
+            return self
+                .exchange(remote, request, token, None, None, None, request.payload, packet)
+                .await;
+        }
+
+        let mut block = Block {
+            num: 0,
+            more: true,
+            szx,
+        };
+        loop {
+            let start = block.offset();
+            let end = request.payload.len().min(start + block.size());
+            block.more = end < request.payload.len();
+            let n = self
+                .exchange(
+                    remote,
+                    request,
+                    token,
+                    None,
+                    Some(block),
+                    None,
+                    &request.payload[start..end],
+                    packet,
+                )
+                .await?;
+            let response = Message::parse(&packet[..n])?;
+            if !block.more || response.code != Code::CONTINUE {
+                return Ok(n);
+            }
+            // The server may ask for smaller blocks.
+            match response.block1() {
+                Some(b) if b.szx < block.szx => {
+                    block.num = (block.num + 1) << (block.szx - b.szx);
+                    block.szx = b.szx;
+                }
+                _ => block.num += 1,
+            }
+        }
+    }
+
+    /// Copy the payload of the response in `packet` to `buf`, fetching the next blocks if it's
+    /// split in blocks.
+    async fn read_payload(
+        &self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        token: &[u8],
+        packet: &mut [u8; MAX_MESSAGE_SIZE],
+        n: usize,
+        buf: &mut [u8],
+    ) -> Result<Response, Error> {
+        let message = Message::parse(&packet[..n])?;
+        let mut response = Response {
+            code: message.code,
+            content_format: message.content_format(),
+            len: 0,
+            observe: message.uint_option(option::OBSERVE),
+        };
+        let mut block = message.block2();
+        let mut n = n;
+        loop {
+            let message = Message::parse(&packet[..n])?;
+            let offset = block.map(|b| b.offset()).unwrap_or(0);
+            let dst = buf
+                .get_mut(offset..offset + message.payload.len())
+                .ok_or(Error::BufferTooSmall)?;
+            dst.copy_from_slice(message.payload);
+            response.len = offset + message.payload.len();
+
+            let next = match block {
+                Some(b) if b.more => Block {
+                    num: b.num + 1,
+                    more: false,
+                    szx: b.szx,
+                },
+                _ => return Ok(response),
+            };
+            let get = Request {
+                query: request.query,
+                ..Request::get(request.path)
+            };
+            n = self
+                .exchange(remote, &get, token, None, None, Some(next), &[], packet)
+                .await?;
+            block = Message::parse(&packet[..n])?.block2();
+            if block.map(|b| b.num) != Some(next.num) {
+                return Err(Error::InvalidMessage);
+            }
+        }
+    }
+
+    /// Send a request, retransmitting it if it's confirmable, and wait for the response,
+    /// returning its length in `packet`.
+    #[allow(clippy::too_many_arguments)]
+    async fn exchange(
+        &self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        token: &[u8],
+        observe: Option<u32>,
+        block1: Option<Block>,
+        block2: Option<Block>,
+        payload: &[u8],
+        packet: &mut [u8; MAX_MESSAGE_SIZE],
+    ) -> Result<usize, Error> {
+        let message_id = self.message_id();
+        let msg_type = if request.confirmable {
+            MessageType::Confirmable
+        } else {
+            MessageType::NonConfirmable
+        };
+
+        let mut timeout = self.config.ack_timeout;
+        let mut retransmissions = 0;
+        loop {
+            let mut w = MessageWriter::new(packet, msg_type, request.method, message_id, token);
+            if let Some(observe) = observe {
+                w.uint_option(option::OBSERVE, observe);
+            }
+            w.path(option::URI_PATH, request.path);
+            if let Some(content_format) = request.content_format {
+                w.uint_option(option::CONTENT_FORMAT, content_format as u32);
+            }
+            w.query(request.query);
+            if let Some(block2) = block2 {
+                w.uint_option(option::BLOCK2, block2.value());
+            }
+            if let Some(block1) = block1 {
+                w.uint_option(option::BLOCK1, block1.value());
+            }
+            let len = w.finish(payload)?;
+            self.socket.send_to(&packet[..len], remote).await.map_err(Error::Send)?;
+
+            if !request.confirmable {
+                break;
+            }
+            match with_timeout(timeout, self.receive_response(remote, Some(message_id), token, packet)).await {
+                Ok(Ok(Some(n))) => return Ok(n),
+                // Acknowledged, the response will be sent separately.
+                Ok(Ok(None)) => break,
+                Ok(Err(e)) => return Err(e),
+                Err(_) if retransmissions < self.config.max_retransmit => {
+                    retransmissions += 1;
+                    timeout *= 2;
+                }
+                Err(_) => return Err(Error::Timeout),
+            }
+        }
+
+        with_timeout(self.config.response_timeout, async {
+            loop {
+                if let Some(n) = self.receive_response(remote, None, token, packet).await? {
+                    return Ok(n);
+                }
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)?
+    }
+
+    /// Wait for the response with `token`, or the acknowledgement of the request with
+    /// `message_id`, returning the length of the response in `packet`, or `None` for an empty
+    /// acknowledgement. Separate responses are acknowledged.
+    async fn receive_response(
+        &self,
+        remote: IpEndpoint,
+        message_id: Option<u16>,
+        token: &[u8],
+        packet: &mut [u8; MAX_MESSAGE_SIZE],
+    ) -> Result<Option<usize>, Error> {
+        loop {
+            let (n, from) = match self.socket.recv_from(packet).await {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if from != remote {
+                continue;
+            }
+            let message = match Message::parse(&packet[..n]) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+
+            match message.msg_type {
+                MessageType::Acknowledgement | MessageType::Reset if Some(message.message_id) != message_id => {}
+                MessageType::Reset => return Err(Error::Reset),
+                MessageType::Acknowledgement if message.code == Code::EMPTY => return Ok(None),
+                MessageType::Acknowledgement if message.token == token => return Ok(Some(n)),
+                MessageType::Acknowledgement => {}
+                msg_type => {
+                    // Separate response, or unknown message which is rejected.
+                    let matches = message.token == token && !message.code.is_request();
+                    if msg_type == MessageType::Confirmable {
+                        let reply = if matches {
+                            MessageType::Acknowledgement
+                        } else {
+                            MessageType::Reset
+                        };
+                        self.send_empty(remote, reply, message.message_id).await?;
+                    }
+                    if matches {
+                        return Ok(Some(n));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send_empty(&self, remote: IpEndpoint, msg_type: MessageType, message_id: u16) -> Result<(), Error> {
+        send_empty(&self.socket, remote, msg_type, message_id).await
+    }
+
+    fn message_id(&self) -> u16 {
+        let id = self.next_message_id.get();
+        self.next_message_id.set(id.wrapping_add(1));
+        id
+    }
+
+    fn token(&self) -> [u8; 4] {
+        let token = self.next_token.get();
+        self.next_token.set(token.wrapping_add(1));
+        token.to_be_bytes()
+    }
+}
+
+/// Whether the notification with sequence number `seq` is fresher than the one with `prev`.
+fn is_fresher(prev: u32, seq: u32) -> bool {
+    const HALF: u32 = 1 << 23;
+    (prev < seq && seq - prev < HALF) || (prev > seq && prev - seq > HALF)
+}
+
+async fn send_empty(
+    socket: &UdpSocket<'_>,
+    remote: IpEndpoint,
+    msg_type: MessageType,
+    message_id: u16,
+) -> Result<(), Error> {
+    let mut buf = [0; 4];
+    let len = MessageWriter::new(&mut buf, msg_type, Code::EMPTY, message_id, &[]).finish(&[])?;
+    socket.send_to(&buf[..len], remote).await.map_err(Error::Send)
+}
+
+/// CoAP server.
+pub struct CoapServer<'a> {
+    socket: UdpSocket<'a>,
+    config: Config,
+    next_message_id: Cell<u16>,
+}
+
+impl<'a> CoapServer<'a> {
+    /// Create a CoAP server, listening on `port` of `stack`, usually [`PORT`].
+    ///
+    /// The buffers should hold at least [`BUFFER_SIZE`] bytes.
+    pub fn new<D: Device>(
+        stack: &'a Stack<D>,
+        port: u16,
+        config: Config,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, BindError> {
+        let mut socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+        socket.bind(port)?;
+
+        Ok(Self {
+            socket,
+            config,
+            next_message_id: Cell::new(Instant::now().as_ticks() as u16),
+        })
+    }
+
+    /// Answer requests with `handler`.
+    ///
+    /// The handler is called with each request, and returns the response, whose payload it
+    /// writes to `buf`. Payloads larger than the block size of the configuration are sent in
+    /// blocks, so `buf` must hold the largest representation of the resources.
+    pub async fn run(&self, buf: &mut [u8], mut handler: impl FnMut(&Message<'_>, &mut [u8]) -> Response) -> ! {
+        let mut packet = [0; MAX_MESSAGE_SIZE];
+        loop {
+            let (n, remote) = match self.socket.recv_from(&mut packet).await {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let request = match Message::parse(&packet[..n]) {
+                Ok(request) => request,
+                Err(_) => {
+                    warn!("coap: invalid message");
+                    continue;
+                }
+            };
+
+            let reply_type = match request.msg_type {
+                MessageType::Confirmable => MessageType::Acknowledgement,
+                MessageType::NonConfirmable => MessageType::NonConfirmable,
+                _ => continue,
+            };
+            if !request.code.is_request() {
+                // Pings, and messages we don't understand, are rejected.
+                if request.msg_type == MessageType::Confirmable {
+                    let _ = send_empty(&self.socket, remote, MessageType::Reset, request.message_id).await;
+                }
+                continue;
+            }
+
+            let response = match request.block1() {
+                // The blocks of a payload would have to be reassembled.
+                Some(block1) if block1.more || block1.num > 0 => Response::new(Code::REQUEST_ENTITY_TOO_LARGE),
+                _ => handler(&request, buf),
+            };
+
+            // Send the block asked for, or the first one if the payload is too large.
+            let len = response.len.min(buf.len());
+            let szx = szx(self.config.block_size);
+            let block = match request.block2() {
+                Some(b) => Some(b),
+                None if len > 1 << (szx + 4) => Some(Block {
+                    num: 0,
+                    more: false,
+                    szx,
+                }),
+                None => None,
+            };
+            let mut code = response.code;
+            let payload = match block {
+                Some(b) if b.offset() < len || b.num == 0 => &buf[b.offset()..len.min(b.offset() + b.size())],
+                Some(_) => {
+                    code = Code::BAD_OPTION;
+                    &[][..]
+                }
+                None => &buf[..len],
+            };
+
+            let message_id = match reply_type {
+                MessageType::Acknowledgement => request.message_id,
+                _ => {
+                    let id = self.next_message_id.get();
+                    self.next_message_id.set(id.wrapping_add(1));
+                    id
+                }
+            };
+            let mut reply = [0; MAX_MESSAGE_SIZE];
+            let mut w = MessageWriter::new(&mut reply, reply_type, code, message_id, request.token);
+            if code == response.code {
+                if let Some(observe) = response.observe {
+                    w.uint_option(option::OBSERVE, observe);
+                }
+                if let Some(content_format) = response.content_format {
+                    w.uint_option(option::CONTENT_FORMAT, content_format as u32);
+                }
+                if let Some(b) = block {
+                    let more = b.offset() + b.size() < len;
+                    w.uint_option(option::BLOCK2, Block { more, ..b }.value());
+                    if b.num == 0 {
+                        w.uint_option(option::SIZE2, len as u32);
+                    }
+                }
+            }
+            let len = match w.finish(payload) {
+                Ok(len) => len,
+                Err(_) => {
+                    warn!("coap: response too large");
+                    continue;
+                }
+            };
+            if let Err(e) = self.socket.send_to(&reply[..len], remote).await {
+                warn!("coap: failed to send response: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a message with `options` and `payload` to `buf`, returning its length.
+    fn write(buf: &mut [u8], options: &[(u16, &[u8])], payload: &[u8]) -> Result<usize, Error> {
+        let mut w = MessageWriter::new(buf, MessageType::Confirmable, Code::GET, 0x1234, &[0xab, 0xcd]);
+        for (number, value) in options {
+            w.option(*number, value);
+        }
+        w.finish(payload)
+    }
+
+    #[test]
+    fn option_delta_and_length_extensions() {
+        let long = [0x55; 300];
+        // Deltas and lengths of 12, 13, 268 and 269 are at the limits of the extensions.
+        let options: [(u16, &[u8]); 6] = [
+            (12, &long[..12]),
+            (25, &long[..13]),
+            (293, &long[..268]),
+            (562, &long[..269]),
+            (562, &[]),
+            (65535, &[1]),
+        ];
+        let mut buf = [0; 1024];
+        let len = write(&mut buf, &options, b"hi").unwrap();
+
+        // Delta 12 and length 12 fit in the nibbles.
+        assert_eq!(buf[6], 0xcc);
+        // Delta 13 and length 13 take one extension byte each.
+        assert_eq!(&buf[19..22], &[0xdd, 0x00, 0x00]);
+        // Delta 268 and length 268 take one, delta 269 and length 269 two.
+        assert_eq!(&buf[35..38], &[0xdd, 0xff, 0xff]);
+        assert_eq!(&buf[306..311], &[0xee, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(buf[580], 0x00);
+        assert_eq!(&buf[581..585], &[0xe1, 0xfc, 0xc0, 0x01]);
+
+        let msg = Message::parse(&buf[..len]).unwrap();
+        assert_eq!(msg.msg_type, MessageType::Confirmable);
+        assert_eq!(msg.code, Code::GET);
+        assert_eq!(msg.message_id, 0x1234);
+        assert_eq!(msg.token, &[0xab, 0xcd]);
+        assert!(msg.options().eq(options.iter().copied()));
+        assert_eq!(msg.payload, b"hi");
+    }
+
+    #[test]
+    fn uint_options() {
+        let mut buf = [0; 64];
+        let mut w = MessageWriter::new(&mut buf, MessageType::NonConfirmable, Code::CONTENT, 1, &[]);
+        w.uint_option(option::OBSERVE, 0);
+        w.uint_option(option::CONTENT_FORMAT, content_format::JSON as u32);
+        let block = Block {
+            num: 0x12345,
+            more: true,
+            szx: 6,
+        };
+        w.uint_option(option::BLOCK2, block.value());
+        let len = w.finish(&[]).unwrap();
+
+        let msg = Message::parse(&buf[..len]).unwrap();
+        // Zero is sent as an empty value.
+        assert_eq!(msg.option(option::OBSERVE), Some(&[][..]));
+        assert_eq!(msg.uint_option(option::OBSERVE), Some(0));
+        assert_eq!(msg.content_format(), Some(content_format::JSON));
+        assert_eq!(msg.block2(), Some(block));
+        assert_eq!(msg.block2().unwrap().offset(), 0x12345 * 1024);
+        assert_eq!(msg.block1(), None);
+    }
+
+    #[test]
+    fn uri_path() {
+        let mut buf = [0; 64];
+        let mut w = MessageWriter::new(&mut buf, MessageType::Confirmable, Code::GET, 1, &[]);
+        w.path(option::URI_PATH, "/sensors//temp/");
+        w.query(Some("unit=c&&precise"));
+        let len = w.finish(&[]).unwrap();
+
+        let msg = Message::parse(&buf[..len]).unwrap();
+        assert!(msg.uri_path().eq(["sensors", "temp"]));
+        assert!(msg.path_eq("sensors/temp"));
+        assert!(!msg.path_eq("sensors"));
+        assert!(!msg.path_eq("sensors/temp/now"));
+        let query = msg.options().filter(|(n, _)| *n == option::URI_QUERY).map(|(_, v)| v);
+        assert!(query.eq([&b"unit=c"[..], &b"precise"[..]]));
+    }
+
+    #[test]
+    fn message_too_large() {
+        let mut buf = [0; 16];
+        assert_eq!(write(&mut buf, &[(option::URI_PATH, b"0123")], b"0123"), Ok(16));
+        assert_eq!(
+            write(&mut buf, &[(option::URI_PATH, b"0123")], b"01234"),
+            Err(Error::MessageTooLarge)
+        );
+        assert_eq!(
+            write(&mut buf, &[(option::URI_PATH, &[0; 13])], &[]),
+            Err(Error::MessageTooLarge)
+        );
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let long = [0x55; 300];
+        let options: [(u16, &[u8]); 3] = [(13, &long[..1]), (300, &long[..269]), (301, &long[..2])];
+        let mut buf = [0; 1024];
+        let len = write(&mut buf, &options, &[]).unwrap();
+        Message::parse(&buf[..len]).unwrap();
+
+        // Cut in the header, the token, the extensions or the values of the options. Cuts
+        // between options leave a valid message with fewer options.
+        let boundaries = [6, 9, 283];
+        for cut in 0..len {
+            let res = Message::parse(&buf[..cut]);
+            if boundaries.contains(&cut) {
+                assert!(res.is_ok(), "cut at {}", cut);
+            } else {
+                assert_eq!(res.err(), Some(Error::InvalidMessage), "cut at {}", cut);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        let parse = |data: &[u8]| Message::parse(data).err();
+        // Version 2.
+        assert_eq!(parse(&[0x80, 0x01, 0, 1]), Some(Error::InvalidMessage));
+        // Token longer than 8 bytes.
+        assert_eq!(
+            parse(&[0x49, 0x01, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Some(Error::InvalidMessage)
+        );
+        // Reserved delta and length nibbles.
+        assert_eq!(parse(&[0x40, 0x01, 0, 1, 0xf0]), Some(Error::InvalidMessage));
+        assert_eq!(parse(&[0x40, 0x01, 0, 1, 0x1f]), Some(Error::InvalidMessage));
+        // Option number over 65535.
+        assert_eq!(
+            parse(&[0x40, 0x01, 0, 1, 0xe0, 0xfe, 0xf2, 0xe0, 0xfe, 0xf2]),
+            Some(Error::InvalidMessage)
+        );
+        // Payload marker without a payload.
+        assert_eq!(parse(&[0x40, 0x01, 0, 1, 0xff]), Some(Error::InvalidMessage));
+        assert_eq!(parse(&[0x40, 0x01, 0, 1, 0xff, 0x2a]), None);
+    }
+
+    #[test]
+    fn block_sizes() {
+        assert_eq!(szx(0), 0);
+        assert_eq!(szx(16), 0);
+        assert_eq!(szx(1000), 5);
+        assert_eq!(szx(1024), 6);
+        assert_eq!(szx(u16::MAX), 6);
+        // The reserved size exponent.
+        assert_eq!(Block::from_value(0x17), None);
+        assert_eq!(
+            Block::from_value(0x1e),
+            Some(Block {
+                num: 1,
+                more: true,
+                szx: 6
+            })
+        );
+    }
+
+    #[test]
+    fn observe_freshness() {
+        assert!(is_fresher(1, 2));
+        assert!(!is_fresher(2, 1));
+        assert!(!is_fresher(2, 2));
+        // Sequence numbers are 24 bits, and wrap around.
+        assert!(is_fresher(0xff_ffff, 0));
+        assert!(!is_fresher(0, 0xff_ffff));
+    }
+}
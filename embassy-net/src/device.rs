@@ -1,9 +1,16 @@
+use core::cell::Cell;
 use core::task::Waker;
 
+#[cfg(feature = "tcp")]
+use smoltcp::phy::Medium;
 use smoltcp::phy::{Device as SmolDevice, DeviceCapabilities};
 use smoltcp::time::Instant as SmolInstant;
 
 use crate::packet_pool::PacketBoxExt;
+#[cfg(feature = "pcap")]
+use crate::pcap::{Direction, Tap};
+#[cfg(feature = "tcp")]
+use crate::tcp_stats::{self, Connection};
 use crate::{Packet, PacketBox, PacketBuf};
 
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -12,6 +19,31 @@ pub enum LinkState {
     Up,
 }
 
+/// Counters of the frames going through the device, see [`Stack::stats`](crate::Stack::stats).
+///
+/// The counters wrap around on overflow.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    pub rx_frames: u32,
+    pub rx_bytes: u64,
+    pub tx_frames: u32,
+    pub tx_bytes: u64,
+    /// Frames received and dropped by the stack, being malformed or of an unsupported protocol.
+    pub rx_dropped: u32,
+    /// Frames received and dropped because of a wrong checksum.
+    pub rx_checksum_errors: u32,
+    /// Times a frame couldn't be sent yet, the device being busy or the packet pool exhausted.
+    /// The frame is sent later.
+    pub tx_busy: u32,
+}
+
+fn update_stats(stats: &Cell<Stats>, f: impl FnOnce(&mut Stats)) {
+    let mut s = stats.get();
+    f(&mut s);
+    stats.set(s);
+}
+
 // 'static required due to the "fake GAT" in smoltcp::phy::Device.
 // https://github.com/smoltcp-rs/smoltcp/pull/572
 pub trait Device {
@@ -23,6 +55,21 @@ pub trait Device {
     fn capabilities(&self) -> DeviceCapabilities;
     fn link_state(&mut self) -> LinkState;
     fn ethernet_address(&self) -> [u8; 6];
+
+    /// Extended IEEE 802.15.4 address, used when the medium is [`Medium::Ieee802154`](smoltcp::phy::Medium::Ieee802154).
+    #[cfg(feature = "medium-ieee802154")]
+    fn ieee802154_address(&self) -> [u8; 8] {
+        [0; 8]
+    }
+
+    /// Start receiving the frames sent to the multicast MAC `address`.
+    ///
+    /// Devices filtering the received frames by destination should let them through. Each
+    /// call is matched by a call to [`leave_multicast`](Device::leave_multicast).
+    fn join_multicast(&mut self, _address: [u8; 6]) {}
+
+    /// Stop receiving the frames sent to the multicast MAC `address`.
+    fn leave_multicast(&mut self, _address: [u8; 6]) {}
 }
 
 impl<T: ?Sized + Device> Device for &'static mut T {
@@ -47,11 +94,28 @@ impl<T: ?Sized + Device> Device for &'static mut T {
     fn ethernet_address(&self) -> [u8; 6] {
         T::ethernet_address(self)
     }
+    #[cfg(feature = "medium-ieee802154")]
+    fn ieee802154_address(&self) -> [u8; 8] {
+        T::ieee802154_address(self)
+    }
+    fn join_multicast(&mut self, address: [u8; 6]) {
+        T::join_multicast(self, address)
+    }
+    fn leave_multicast(&mut self, address: [u8; 6]) {
+        T::leave_multicast(self, address)
+    }
 }
 
 pub struct DeviceAdapter<D: Device> {
     pub device: D,
     caps: DeviceCapabilities,
+    pub stats: Cell<Stats>,
+    /// Handed the frames received and sent.
+    #[cfg(feature = "pcap")]
+    pub tap: Option<Tap>,
+    /// Updated from the TCP segments received and sent.
+    #[cfg(feature = "tcp")]
+    pub tcp_connections: &'static [Cell<Connection>],
 }
 
 impl<D: Device> DeviceAdapter<D> {
@@ -59,21 +123,42 @@ impl<D: Device> DeviceAdapter<D> {
         Self {
             caps: device.capabilities(),
             device,
+            stats: Cell::new(Stats::default()),
+            #[cfg(feature = "pcap")]
+            tap: None,
+            #[cfg(feature = "tcp")]
+            tcp_connections: &[],
         }
     }
 }
 
 impl<'a, D: Device + 'static> SmolDevice<'a> for DeviceAdapter<D> {
-    type RxToken = RxToken;
+    type RxToken = RxToken<'a>;
     type TxToken = TxToken<'a, D>;
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
         let tx_pkt = PacketBox::new(Packet::new())?;
         let rx_pkt = self.device.receive()?;
-        let rx_token = RxToken { pkt: rx_pkt };
+        let rx_token = RxToken {
+            pkt: rx_pkt,
+            stats: &self.stats,
+            #[cfg(feature = "pcap")]
+            tap: self.tap,
+            #[cfg(feature = "tcp")]
+            tcp_connections: self.tcp_connections,
+            #[cfg(feature = "tcp")]
+            medium: self.caps.medium,
+        };
         let tx_token = TxToken {
             device: &mut self.device,
             pkt: tx_pkt,
+            stats: &self.stats,
+            #[cfg(feature = "pcap")]
+            tap: self.tap,
+            #[cfg(feature = "tcp")]
+            tcp_connections: self.tcp_connections,
+            #[cfg(feature = "tcp")]
+            medium: self.caps.medium,
         };
 
         Some((rx_token, tx_token))
@@ -81,14 +166,27 @@ impl<'a, D: Device + 'static> SmolDevice<'a> for DeviceAdapter<D> {
 
     /// Construct a transmit token.
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        if !self.device.is_transmit_ready() {
-            return None;
-        }
-
-        let tx_pkt = PacketBox::new(Packet::new())?;
+        let tx_pkt = match self.device.is_transmit_ready() {
+            true => PacketBox::new(Packet::new()),
+            false => None,
+        };
+        let tx_pkt = match tx_pkt {
+            Some(tx_pkt) => tx_pkt,
+            None => {
+                update_stats(&self.stats, |s| s.tx_busy = s.tx_busy.wrapping_add(1));
+                return None;
+            }
+        };
         Some(TxToken {
             device: &mut self.device,
             pkt: tx_pkt,
+            stats: &self.stats,
+            #[cfg(feature = "pcap")]
+            tap: self.tap,
+            #[cfg(feature = "tcp")]
+            tcp_connections: self.tcp_connections,
+            #[cfg(feature = "tcp")]
+            medium: self.caps.medium,
         })
     }
 
@@ -98,22 +196,60 @@ impl<'a, D: Device + 'static> SmolDevice<'a> for DeviceAdapter<D> {
     }
 }
 
-pub struct RxToken {
+pub struct RxToken<'a> {
     pkt: PacketBuf,
+    stats: &'a Cell<Stats>,
+    #[cfg(feature = "pcap")]
+    tap: Option<Tap>,
+    #[cfg(feature = "tcp")]
+    tcp_connections: &'static [Cell<Connection>],
+    #[cfg(feature = "tcp")]
+    medium: Medium,
 }
 
-impl smoltcp::phy::RxToken for RxToken {
+impl<'a> smoltcp::phy::RxToken for RxToken<'a> {
     fn consume<R, F>(mut self, _timestamp: SmolInstant, f: F) -> smoltcp::Result<R>
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        f(&mut self.pkt)
+        #[cfg(feature = "pcap")]
+        if let Some(tap) = self.tap {
+            tap(Direction::Rx, embassy_time::Instant::now(), &self.pkt);
+        }
+        let len = self.pkt.len();
+        let res = f(&mut self.pkt);
+        #[cfg(feature = "tcp")]
+        if res.is_ok() {
+            tcp_stats::on_received(
+                self.tcp_connections,
+                self.medium,
+                &self.pkt,
+                embassy_time::Instant::now(),
+            );
+        }
+        update_stats(self.stats, |s| {
+            s.rx_frames = s.rx_frames.wrapping_add(1);
+            s.rx_bytes = s.rx_bytes.wrapping_add(len as u64);
+            match &res {
+                Ok(_) => {}
+                Err(smoltcp::Error::Checksum) => s.rx_checksum_errors = s.rx_checksum_errors.wrapping_add(1),
+                Err(_) => s.rx_dropped = s.rx_dropped.wrapping_add(1),
+            }
+        });
+        res
     }
 }
 
 pub struct TxToken<'a, D: Device> {
     device: &'a mut D,
     pkt: PacketBox,
+    stats: &'a Cell<Stats>,
+    #[cfg(feature = "pcap")]
+    tap: Option<Tap>,
+    #[cfg(feature = "tcp")]
+    tcp_connections: &'static [Cell<Connection>],
+    #[cfg(feature = "tcp")]
+    medium: Medium,
 }
 
 impl<'a, D: Device> smoltcp::phy::TxToken for TxToken<'a, D> {
@@ -123,6 +259,16 @@ impl<'a, D: Device> smoltcp::phy::TxToken for TxToken<'a, D> {
     {
         let mut buf = self.pkt.slice(0..len);
         let r = f(&mut buf)?;
+        #[cfg(feature = "pcap")]
+        if let Some(tap) = self.tap {
+            tap(Direction::Tx, embassy_time::Instant::now(), &buf);
+        }
+        #[cfg(feature = "tcp")]
+        tcp_stats::on_sent(self.tcp_connections, self.medium, &buf, embassy_time::Instant::now());
+        update_stats(self.stats, |s| {
+            s.tx_frames = s.tx_frames.wrapping_add(1);
+            s.tx_bytes = s.tx_bytes.wrapping_add(len as u64);
+        });
         self.device.transmit(buf);
         Ok(r)
     }
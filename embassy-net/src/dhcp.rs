@@ -0,0 +1,867 @@
+//! DHCPv4 client with extra options.
+//!
+//! [`ConfigStrategy::Dhcp`](crate::ConfigStrategy::Dhcp) uses the DHCP socket of smoltcp, which
+//! doesn't send the options some networks require. With
+//! [`ConfigStrategy::DhcpWithConfig`](crate::ConfigStrategy::DhcpWithConfig), this client is used
+//! instead, and [`DhcpConfig`] sets the host name registered in the DNS of the network, a client
+//! identifier, the address to ask for, and the timing of the retransmissions. Its `on_event`
+//! callback is told when a lease is obtained, renewed or lost.
+//!
+//! This client runs in the stack over a raw socket, which sees every IPv4 UDP packet. The UDP
+//! sockets still receive theirs, but each packet is also copied to the raw socket, and the
+//! packets to closed ports are no longer answered with an ICMP port unreachable.
+
+use embassy_time::{Duration, Instant};
+use heapless::{String, Vec};
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+use crate::Config;
+
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const IPPROTO_UDP: u8 = 17;
+const TTL: u8 = 64;
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u8 = 0x80;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Offset of the options, after the fixed fields and the magic cookie.
+const OPTIONS_OFFSET: usize = 240;
+/// Messages are padded to the minimum length of a BOOTP message, which some servers require.
+const MIN_MESSAGE_LEN: usize = 300;
+/// Options of the longest message: a 32-byte client identifier and host name, and all the others.
+const MAX_OPTIONS_LEN: usize = 112;
+/// Largest reply accepted, the minimum every client must accept.
+const MAX_REPLY_LEN: u16 = 576;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVERS: u8 = 6;
+const OPTION_HOSTNAME: u8 = 12;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPTION_MAX_MESSAGE_SIZE: u8 = 57;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_CLIENT_ID: u8 = 61;
+const OPTION_END: u8 = 255;
+
+/// Retransmissions of the renewals are at least this far apart (RFC 2131).
+const MIN_RENEW_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Length of the longest message sent, including the IPv4 and UDP headers.
+pub(crate) const MESSAGE_LEN: usize = IPV4_HEADER_LEN + UDP_HEADER_LEN + OPTIONS_OFFSET + MAX_OPTIONS_LEN;
+
+/// Options of the DHCP client, used with
+/// [`ConfigStrategy::DhcpWithConfig`](crate::ConfigStrategy::DhcpWithConfig).
+///
+/// The default sends no extra option, and backs off the retransmissions as RFC 2131 suggests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpConfig {
+    /// Host name sent in option 12, which many servers register in the DNS of the network.
+    pub hostname: Option<String<32>>,
+    /// Client identifier sent in option 61. Defaults to the MAC address.
+    pub client_id: Option<Vec<u8, 32>>,
+    /// Address asked for in the discoveries, such as the one of a lease saved across reboots.
+    /// The server may offer another one. Defaults to the address of the previous lease, if the
+    /// link went down.
+    pub requested_address: Option<Ipv4Address>,
+    /// Longest lease accepted, to renew it sooner than the server requires.
+    pub max_lease_duration: Option<Duration>,
+    /// Delay before the first retransmission of a discovery or request. It doubles at each
+    /// retransmission, up to `max_retry_delay`.
+    pub initial_retry_delay: Duration,
+    /// Longest delay between retransmissions of a discovery or request.
+    pub max_retry_delay: Duration,
+    /// Requests sent to the server of an offer before starting over with a discovery.
+    pub max_requests: u8,
+    /// Called when a lease is obtained, renewed or lost, once the configuration is updated. It
+    /// runs in the stack, so it should return quickly.
+    pub on_event: Option<fn(DhcpEvent)>,
+}
+
+impl Default for DhcpConfig {
+    fn default() -> Self {
+        Self {
+            hostname: None,
+            client_id: None,
+            requested_address: None,
+            max_lease_duration: None,
+            initial_retry_delay: Duration::from_secs(4),
+            max_retry_delay: Duration::from_secs(64),
+            max_requests: 4,
+            on_event: None,
+        }
+    }
+}
+
+/// Change of the lease, reported to [`DhcpConfig::on_event`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(clippy::enum_variant_names)]
+pub enum DhcpEvent {
+    /// A lease of `address` was obtained from `server`, and the stack configured with it. Also
+    /// reported when a renewal changes the address, the gateway or the DNS servers.
+    LeaseObtained {
+        address: Ipv4Cidr,
+        server: Ipv4Address,
+        lease_duration: Duration,
+    },
+    /// The lease was renewed, without changes.
+    LeaseRenewed { lease_duration: Duration },
+    /// The lease expired, the server refused to renew it, or the link went down, and the
+    /// configuration was dropped. The client starts over with a discovery.
+    LeaseLost,
+}
+
+/// Fields of a reply from a server.
+struct Reply {
+    message_type: u8,
+    address: Ipv4Address,
+    server: Option<Ipv4Address>,
+    subnet_mask: Option<Ipv4Address>,
+    router: Option<Ipv4Address>,
+    dns_servers: Vec<Ipv4Address, 3>,
+    lease_duration: Option<Duration>,
+    renewal_duration: Option<Duration>,
+    rebinding_duration: Option<Duration>,
+}
+
+/// Parse the reply to the transaction `xid` of the client with MAC address `mac`, from an IPv4
+/// packet.
+fn parse_reply(packet: &[u8], xid: u32, mac: [u8; 6]) -> Option<Reply> {
+    if packet.len() < IPV4_HEADER_LEN || packet[0] >> 4 != 4 || packet[9] != IPPROTO_UDP {
+        return None;
+    }
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < IPV4_HEADER_LEN || total_len < header_len + UDP_HEADER_LEN || total_len > packet.len() {
+        return None;
+    }
+    let udp = &packet[header_len..total_len];
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    if udp_len < UDP_HEADER_LEN || udp_len > udp.len() {
+        return None;
+    }
+    let udp = &udp[..udp_len];
+    if u16::from_be_bytes([udp[0], udp[1]]) != SERVER_PORT || u16::from_be_bytes([udp[2], udp[3]]) != CLIENT_PORT {
+        return None;
+    }
+    // The raw socket doesn't verify the UDP checksum. A zero checksum means there is none.
+    if udp[6..8] != [0, 0] && udp_checksum(&packet[12..16], &packet[16..20], udp) != 0 {
+        return None;
+    }
+
+    let msg = &udp[UDP_HEADER_LEN..];
+    if msg.len() < OPTIONS_OFFSET
+        || msg[0] != BOOTREPLY
+        || msg[4..8] != xid.to_be_bytes()
+        || msg[28..34] != mac
+        || msg[236..240] != MAGIC_COOKIE
+    {
+        return None;
+    }
+
+    let mut reply = Reply {
+        message_type: 0,
+        address: Ipv4Address::from_bytes(&msg[16..20]),
+        server: None,
+        subnet_mask: None,
+        router: None,
+        dns_servers: Vec::new(),
+        lease_duration: None,
+        renewal_duration: None,
+        rebinding_duration: None,
+    };
+    let secs = |data: &[u8]| Duration::from_secs(u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64);
+
+    let mut options = &msg[OPTIONS_OFFSET..];
+    while let Some(&code) = options.first() {
+        match code {
+            OPTION_PAD => {
+                options = &options[1..];
+                continue;
+            }
+            OPTION_END => break,
+            _ => {}
+        }
+        if options.len() < 2 || options.len() < 2 + options[1] as usize {
+            return None;
+        }
+        let (data, rest) = options[2..].split_at(options[1] as usize);
+        match (code, data.len()) {
+            (OPTION_MESSAGE_TYPE, 1) => reply.message_type = data[0],
+            (OPTION_SERVER_ID, 4) => reply.server = Some(Ipv4Address::from_bytes(data)),
+            (OPTION_SUBNET_MASK, 4) => reply.subnet_mask = Some(Ipv4Address::from_bytes(data)),
+            (OPTION_ROUTER, n) if n >= 4 => reply.router = Some(Ipv4Address::from_bytes(&data[..4])),
+            (OPTION_DNS_SERVERS, _) => {
+                for addr in data.chunks_exact(4) {
+                    let _ = reply.dns_servers.push(Ipv4Address::from_bytes(addr));
+                }
+            }
+            (OPTION_LEASE_TIME, 4) => reply.lease_duration = Some(secs(data)),
+            (OPTION_RENEWAL_TIME, 4) => reply.renewal_duration = Some(secs(data)),
+            (OPTION_REBINDING_TIME, 4) => reply.rebinding_duration = Some(secs(data)),
+            _ => {}
+        }
+        options = rest;
+    }
+
+    (reply.message_type != 0).then_some(reply)
+}
+
+/// Checksum of a UDP datagram from `src` to `dst`, which is zero if the datagram holds a valid
+/// one.
+fn udp_checksum(src: &[u8], dst: &[u8], udp: &[u8]) -> u16 {
+    let mut sum = IPPROTO_UDP as u32 + udp.len() as u32;
+    for chunk in src.chunks(2).chain(dst.chunks(2)).chain(udp.chunks(2)) {
+        sum += u16::from_be_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)]) as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn put_option(msg: &mut [u8], len: &mut usize, code: u8, data: &[u8]) {
+    msg[*len] = code;
+    msg[*len + 1] = data.len() as u8;
+    msg[*len + 2..][..data.len()].copy_from_slice(data);
+    *len += 2 + data.len();
+}
+
+/// State of the client.
+#[derive(Clone, Copy)]
+enum State {
+    /// Looking for servers.
+    Discovering,
+    /// Requesting `address` from `server`, which offered it.
+    Requesting {
+        server: Ipv4Address,
+        address: Ipv4Address,
+        requests_left: u8,
+    },
+    /// Configured with the lease, until it needs renewing.
+    Bound,
+    /// Renewing the lease with the server that granted it.
+    Renewing,
+    /// Renewing the lease with any server, the one that granted it not answering.
+    Rebinding,
+}
+
+struct Lease {
+    config: Config,
+    server: Ipv4Address,
+    renew_at: Instant,
+    rebind_at: Instant,
+    expires_at: Instant,
+}
+
+pub(crate) struct DhcpClient {
+    config: DhcpConfig,
+    state: State,
+    lease: Option<Lease>,
+    /// Address of the lease lost when the link went down, asked for again.
+    last_address: Option<Ipv4Address>,
+    xid: u32,
+    /// State of the xorshift generator of the transaction IDs.
+    rng: u32,
+    /// When the next message is sent.
+    send_at: Instant,
+    retry_delay: Duration,
+}
+
+impl DhcpClient {
+    pub fn new(config: DhcpConfig, random_seed: u64) -> Self {
+        let mut client = Self {
+            state: State::Discovering,
+            lease: None,
+            last_address: None,
+            xid: 0,
+            rng: (random_seed ^ random_seed >> 32) as u32 | 1,
+            send_at: Instant::from_ticks(0),
+            retry_delay: config.initial_retry_delay,
+            config,
+        };
+        client.restart(Instant::from_ticks(0));
+        client
+    }
+
+    /// The configuration of the current lease.
+    pub fn config(&self) -> Option<&Config> {
+        self.lease.as_ref().map(|lease| &lease.config)
+    }
+
+    pub fn on_event(&self) -> Option<fn(DhcpEvent)> {
+        self.config.on_event
+    }
+
+    /// Start over, when the link goes down, dropping the lease.
+    pub fn reset(&mut self) -> Option<DhcpEvent> {
+        self.restart(Instant::from_ticks(0));
+        self.lose()
+    }
+
+    /// Handle an IPv4 packet received by the raw socket, which may be a reply to the client.
+    pub fn process(&mut self, packet: &[u8], mac: [u8; 6], now: Instant) -> Option<DhcpEvent> {
+        let reply = parse_reply(packet, self.xid, mac)?;
+        match (self.state, reply.message_type) {
+            (State::Discovering, DHCPOFFER) if !reply.address.is_unspecified() => {
+                self.state = State::Requesting {
+                    server: reply.server?,
+                    address: reply.address,
+                    requests_left: self.config.max_requests,
+                };
+                self.send_at = now;
+                self.retry_delay = self.config.initial_retry_delay;
+                None
+            }
+            (State::Requesting { .. } | State::Renewing | State::Rebinding, DHCPACK) => self.bind(reply, now),
+            (State::Requesting { .. }, DHCPNAK) => {
+                self.restart(now);
+                None
+            }
+            (State::Renewing | State::Rebinding, DHCPNAK) => {
+                self.restart(now);
+                self.lose()
+            }
+            _ => None,
+        }
+    }
+
+    /// Update the state for the time `now`, returning [`DhcpEvent::LeaseLost`] if the lease
+    /// expired.
+    pub fn poll_timeout(&mut self, now: Instant) -> Option<DhcpEvent> {
+        let (renew_at, rebind_at, expires_at) = match &self.lease {
+            Some(lease) => (lease.renew_at, lease.rebind_at, lease.expires_at),
+            None => return None,
+        };
+        if now >= expires_at {
+            self.restart(now);
+            return self.lose();
+        }
+        match self.state {
+            State::Bound if now >= renew_at => {
+                self.state = State::Renewing;
+                self.send_at = now;
+            }
+            State::Renewing if now >= rebind_at => {
+                self.state = State::Rebinding;
+                self.send_at = now;
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Write the message to send at `now`, if any, including the IPv4 and UDP headers, to
+    /// `buf`. Returns its length.
+    pub fn message(&mut self, now: Instant, mac: [u8; 6], buf: &mut [u8; MESSAGE_LEN]) -> Option<usize> {
+        if now < self.send_at {
+            return None;
+        }
+        if let State::Requesting { requests_left: 0, .. } = self.state {
+            self.restart(now);
+        }
+
+        let (message_type, client_addr, dst, requested, server) = match (self.state, &self.lease) {
+            (State::Discovering, _) => {
+                self.retry(now);
+                let requested = self.config.requested_address.or(self.last_address);
+                (
+                    DHCPDISCOVER,
+                    Ipv4Address::UNSPECIFIED,
+                    Ipv4Address::BROADCAST,
+                    requested,
+                    None,
+                )
+            }
+            (
+                State::Requesting {
+                    server,
+                    address,
+                    requests_left,
+                },
+                _,
+            ) => {
+                self.state = State::Requesting {
+                    server,
+                    address,
+                    requests_left: requests_left - 1,
+                };
+                self.retry(now);
+                (
+                    DHCPREQUEST,
+                    Ipv4Address::UNSPECIFIED,
+                    Ipv4Address::BROADCAST,
+                    Some(address),
+                    Some(server),
+                )
+            }
+            (State::Renewing, Some(lease)) => {
+                self.send_at = renew_retry_at(now, lease.rebind_at);
+                (DHCPREQUEST, lease.config.address.address(), lease.server, None, None)
+            }
+            (State::Rebinding, Some(lease)) => {
+                self.send_at = renew_retry_at(now, lease.expires_at);
+                (
+                    DHCPREQUEST,
+                    lease.config.address.address(),
+                    Ipv4Address::BROADCAST,
+                    None,
+                    None,
+                )
+            }
+            _ => return None,
+        };
+
+        buf.fill(0);
+        let msg = &mut buf[IPV4_HEADER_LEN + UDP_HEADER_LEN..];
+        msg[0] = BOOTREQUEST;
+        msg[1] = HTYPE_ETHERNET;
+        msg[2] = mac.len() as u8;
+        msg[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        if client_addr.is_unspecified() {
+            // The address isn't configured yet, ask for the replies to be broadcast.
+            msg[10] = FLAG_BROADCAST;
+        }
+        msg[12..16].copy_from_slice(client_addr.as_bytes());
+        msg[28..34].copy_from_slice(&mac);
+        msg[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut len = OPTIONS_OFFSET;
+        put_option(msg, &mut len, OPTION_MESSAGE_TYPE, &[message_type]);
+        match &self.config.client_id {
+            Some(id) => put_option(msg, &mut len, OPTION_CLIENT_ID, id),
+            None => {
+                let mut id = [HTYPE_ETHERNET, 0, 0, 0, 0, 0, 0];
+                id[1..].copy_from_slice(&mac);
+                put_option(msg, &mut len, OPTION_CLIENT_ID, &id);
+            }
+        }
+        if let Some(hostname) = &self.config.hostname {
+            put_option(msg, &mut len, OPTION_HOSTNAME, hostname.as_bytes());
+        }
+        if let Some(addr) = requested {
+            put_option(msg, &mut len, OPTION_REQUESTED_ADDRESS, addr.as_bytes());
+        }
+        if let Some(addr) = server {
+            put_option(msg, &mut len, OPTION_SERVER_ID, addr.as_bytes());
+        }
+        if let Some(max) = self.config.max_lease_duration {
+            let secs = max.as_secs().min(u32::MAX as u64) as u32;
+            put_option(msg, &mut len, OPTION_LEASE_TIME, &secs.to_be_bytes());
+        }
+        put_option(
+            msg,
+            &mut len,
+            OPTION_PARAMETER_REQUEST_LIST,
+            &[
+                OPTION_SUBNET_MASK,
+                OPTION_ROUTER,
+                OPTION_DNS_SERVERS,
+                OPTION_LEASE_TIME,
+                OPTION_RENEWAL_TIME,
+                OPTION_REBINDING_TIME,
+            ],
+        );
+        put_option(msg, &mut len, OPTION_MAX_MESSAGE_SIZE, &MAX_REPLY_LEN.to_be_bytes());
+        msg[len] = OPTION_END;
+        let msg_len = (len + 1).max(MIN_MESSAGE_LEN);
+
+        // The raw socket fills the checksum of the IPv4 header, and the one of UDP is optional.
+        let udp_len = UDP_HEADER_LEN + msg_len;
+        let total_len = IPV4_HEADER_LEN + udp_len;
+        buf[0] = 0x45;
+        buf[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+        buf[8] = TTL;
+        buf[9] = IPPROTO_UDP;
+        buf[12..16].copy_from_slice(client_addr.as_bytes());
+        buf[16..20].copy_from_slice(dst.as_bytes());
+        let udp = &mut buf[IPV4_HEADER_LEN..];
+        udp[0..2].copy_from_slice(&CLIENT_PORT.to_be_bytes());
+        udp[2..4].copy_from_slice(&SERVER_PORT.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        Some(total_len)
+    }
+
+    /// When the client needs to be polled next.
+    pub fn poll_at(&self) -> Instant {
+        match &self.lease {
+            Some(lease) => self.send_at.min(lease.expires_at),
+            None => self.send_at,
+        }
+    }
+
+    fn bind(&mut self, reply: Reply, now: Instant) -> Option<DhcpEvent> {
+        let server = match self.state {
+            State::Requesting { server, .. } => server,
+            _ => reply.server.or_else(|| self.lease.as_ref().map(|lease| lease.server))?,
+        };
+        let offered = reply.lease_duration?;
+        let duration = match self.config.max_lease_duration {
+            Some(max) if max < offered => max,
+            _ => offered,
+        };
+        // The times given by the server only hold for the full lease.
+        let (renewal, rebinding) = match (reply.renewal_duration, reply.rebinding_duration) {
+            (Some(t1), Some(t2)) if duration == offered && t1 <= t2 && t2 <= duration => (t1, t2),
+            _ => (duration / 2, duration * 7 / 8),
+        };
+
+        // Servers nearly always send the subnet mask, as it is requested.
+        let prefix_len = reply
+            .subnet_mask
+            .map_or(24, |mask| u32::from_be_bytes(mask.0).leading_ones() as u8);
+        let config = Config {
+            address: Ipv4Cidr::new(reply.address, prefix_len),
+            gateway: reply.router,
+            dns_servers: reply.dns_servers,
+        };
+
+        let event = match &self.lease {
+            Some(lease) if lease.config == config => DhcpEvent::LeaseRenewed {
+                lease_duration: duration,
+            },
+            _ => DhcpEvent::LeaseObtained {
+                address: config.address,
+                server,
+                lease_duration: duration,
+            },
+        };
+        self.lease = Some(Lease {
+            config,
+            server,
+            renew_at: now + renewal,
+            rebind_at: now + rebinding,
+            expires_at: now + duration,
+        });
+        self.state = State::Bound;
+        self.send_at = now + renewal;
+        self.retry_delay = self.config.initial_retry_delay;
+        Some(event)
+    }
+
+    /// Drop the lease, returning [`DhcpEvent::LeaseLost`] if there was one.
+    fn lose(&mut self) -> Option<DhcpEvent> {
+        let lease = self.lease.take()?;
+        self.last_address = Some(lease.config.address.address());
+        Some(DhcpEvent::LeaseLost)
+    }
+
+    /// Start over with a discovery, in a new transaction.
+    fn restart(&mut self, now: Instant) {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.xid = self.rng;
+        self.state = State::Discovering;
+        self.send_at = now;
+        self.retry_delay = self.config.initial_retry_delay;
+    }
+
+    /// Schedule the retransmission of a discovery or request.
+    fn retry(&mut self, now: Instant) {
+        self.send_at = now + self.retry_delay;
+        self.retry_delay = (self.retry_delay * 2).min(self.config.max_retry_delay);
+    }
+}
+
+/// When to retransmit a renewal sent at `now`: halfway to `deadline`, but not sooner than a
+/// minute, nor after the deadline.
+fn renew_retry_at(now: Instant, deadline: Instant) -> Instant {
+    (now + ((deadline - now) / 2).max(MIN_RENEW_RETRY_DELAY)).min(deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    const SERVER: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+    const ADDRESS: Ipv4Address = Ipv4Address([192, 168, 1, 50]);
+
+    /// Writes a reply of `SERVER` to the transaction `xid` to `buf`, returning its length.
+    fn reply(buf: &mut [u8; 576], xid: u32, message_type: u8, options: &[(u8, &[u8])]) -> usize {
+        buf.fill(0);
+        let msg = &mut buf[IPV4_HEADER_LEN + UDP_HEADER_LEN..];
+        msg[0] = BOOTREPLY;
+        msg[1] = HTYPE_ETHERNET;
+        msg[2] = 6;
+        msg[4..8].copy_from_slice(&xid.to_be_bytes());
+        msg[16..20].copy_from_slice(ADDRESS.as_bytes());
+        msg[28..34].copy_from_slice(&MAC);
+        msg[236..240].copy_from_slice(&MAGIC_COOKIE);
+        let mut len = OPTIONS_OFFSET;
+        put_option(msg, &mut len, OPTION_MESSAGE_TYPE, &[message_type]);
+        put_option(msg, &mut len, OPTION_SERVER_ID, SERVER.as_bytes());
+        for (code, data) in options {
+            put_option(msg, &mut len, *code, data);
+        }
+        msg[len] = OPTION_END;
+
+        let udp_len = UDP_HEADER_LEN + len + 1;
+        let total_len = IPV4_HEADER_LEN + udp_len;
+        buf[0] = 0x45;
+        buf[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+        buf[8] = TTL;
+        buf[9] = IPPROTO_UDP;
+        buf[12..16].copy_from_slice(SERVER.as_bytes());
+        buf[16..20].copy_from_slice(Ipv4Address::BROADCAST.as_bytes());
+        let udp = &mut buf[IPV4_HEADER_LEN..total_len];
+        udp[0..2].copy_from_slice(&SERVER_PORT.to_be_bytes());
+        udp[2..4].copy_from_slice(&CLIENT_PORT.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        let checksum = udp_checksum(SERVER.as_bytes(), Ipv4Address::BROADCAST.as_bytes(), udp);
+        udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+        total_len
+    }
+
+    fn ack(buf: &mut [u8; 576], xid: u32) -> usize {
+        reply(
+            buf,
+            xid,
+            DHCPACK,
+            &[
+                (OPTION_SUBNET_MASK, &[255, 255, 255, 0]),
+                (OPTION_ROUTER, SERVER.as_bytes()),
+                (OPTION_DNS_SERVERS, &[8, 8, 8, 8, 1, 1, 1, 1]),
+                (OPTION_LEASE_TIME, &3600u32.to_be_bytes()),
+            ],
+        )
+    }
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    /// Gets the client from discovering to bound at `t = 0`.
+    fn bound_client() -> DhcpClient {
+        let mut client = DhcpClient::new(DhcpConfig::default(), 1234);
+        let mut msg = [0; MESSAGE_LEN];
+        let mut buf = [0; 576];
+        assert!(client.message(at(0), MAC, &mut msg).is_some());
+        let len = reply(&mut buf, client.xid, DHCPOFFER, &[]);
+        assert_eq!(client.process(&buf[..len], MAC, at(0)), None);
+        assert!(client.message(at(0), MAC, &mut msg).is_some());
+        let len = ack(&mut buf, client.xid);
+        assert!(matches!(
+            client.process(&buf[..len], MAC, at(0)),
+            Some(DhcpEvent::LeaseObtained { .. })
+        ));
+        client
+    }
+
+    #[test]
+    fn udp_checksum_of_datagram() {
+        let mut udp = [0, 67, 0, 68, 0, 12, 0, 0, b'a', b'b', b'c', b'd'];
+        assert_eq!(
+            udp_checksum(SERVER.as_bytes(), Ipv4Address::BROADCAST.as_bytes(), &udp),
+            0x78df
+        );
+        udp[6..8].copy_from_slice(&[0x78, 0xdf]);
+        assert_eq!(
+            udp_checksum(SERVER.as_bytes(), Ipv4Address::BROADCAST.as_bytes(), &udp),
+            0
+        );
+    }
+
+    #[test]
+    fn parse_ack() {
+        let mut buf = [0; 576];
+        let len = ack(&mut buf, 42);
+        let reply = parse_reply(&buf[..len], 42, MAC).unwrap();
+        assert_eq!(reply.message_type, DHCPACK);
+        assert_eq!(reply.address, ADDRESS);
+        assert_eq!(reply.server, Some(SERVER));
+        assert_eq!(reply.subnet_mask, Some(Ipv4Address([255, 255, 255, 0])));
+        assert_eq!(reply.router, Some(SERVER));
+        assert_eq!(
+            &reply.dns_servers[..],
+            &[Ipv4Address([8, 8, 8, 8]), Ipv4Address([1, 1, 1, 1])]
+        );
+        assert_eq!(reply.lease_duration, Some(Duration::from_secs(3600)));
+        assert_eq!(reply.renewal_duration, None);
+    }
+
+    #[test]
+    fn parse_rejects_other_transactions() {
+        let mut buf = [0; 576];
+        let len = ack(&mut buf, 42);
+        assert!(parse_reply(&buf[..len], 43, MAC).is_none());
+        assert!(parse_reply(&buf[..len], 42, [0x02, 0, 0, 0, 0, 2]).is_none());
+    }
+
+    #[test]
+    fn parse_checks_udp_checksum() {
+        let mut buf = [0; 576];
+        let len = ack(&mut buf, 42);
+        buf[IPV4_HEADER_LEN + UDP_HEADER_LEN + 20] ^= 1;
+        assert!(parse_reply(&buf[..len], 42, MAC).is_none());
+
+        // Without a checksum, the datagram is accepted.
+        buf[IPV4_HEADER_LEN + 6..][..2].fill(0);
+        assert!(parse_reply(&buf[..len], 42, MAC).is_some());
+    }
+
+    #[test]
+    fn parse_rejects_truncated_replies() {
+        let mut buf = [0; 576];
+        let len = ack(&mut buf, 42);
+        // The IPv4 header claims more bytes than received.
+        assert!(parse_reply(&buf[..len - 1], 42, MAC).is_none());
+        assert!(parse_reply(&buf[..IPV4_HEADER_LEN + 4], 42, MAC).is_none());
+
+        // An option longer than the message.
+        let len = reply(&mut buf, 42, DHCPACK, &[]);
+        buf[len - 1] = OPTION_LEASE_TIME;
+        buf[IPV4_HEADER_LEN + 6..][..2].fill(0);
+        assert!(parse_reply(&buf[..len], 42, MAC).is_none());
+    }
+
+    #[test]
+    fn lease_is_obtained() {
+        let client = bound_client();
+        let config = client.config().unwrap();
+        assert_eq!(config.address, Ipv4Cidr::new(ADDRESS, 24));
+        assert_eq!(config.gateway, Some(SERVER));
+        assert_eq!(client.poll_at(), at(1800));
+    }
+
+    #[test]
+    fn offer_without_server_id_is_ignored() {
+        let mut client = DhcpClient::new(DhcpConfig::default(), 1234);
+        let mut msg = [0; MESSAGE_LEN];
+        let mut buf = [0; 576];
+        assert!(client.message(at(0), MAC, &mut msg).is_some());
+        let len = reply(&mut buf, client.xid, DHCPOFFER, &[]);
+        // Remove the server identifier by turning it into padding.
+        buf[IPV4_HEADER_LEN + UDP_HEADER_LEN + OPTIONS_OFFSET + 3..][..6].fill(OPTION_PAD);
+        buf[IPV4_HEADER_LEN + 6..][..2].fill(0);
+        assert_eq!(client.process(&buf[..len], MAC, at(0)), None);
+        assert!(matches!(client.state, State::Discovering));
+    }
+
+    #[test]
+    fn requests_are_retransmitted_then_start_over() {
+        let mut client = DhcpClient::new(DhcpConfig::default(), 1234);
+        let mut msg = [0; MESSAGE_LEN];
+        let mut buf = [0; 576];
+        assert!(client.message(at(0), MAC, &mut msg).is_some());
+        let len = reply(&mut buf, client.xid, DHCPOFFER, &[]);
+        client.process(&buf[..len], MAC, at(0));
+        let xid = client.xid;
+
+        // 4 requests, 4, 8 and 16 seconds apart.
+        let mut t = 0;
+        for delay in [0, 4, 8, 16] {
+            t += delay;
+            if delay != 0 {
+                assert!(client.message(at(t - 1), MAC, &mut msg).is_none());
+            }
+            let len = client.message(at(t), MAC, &mut msg).unwrap();
+            assert_eq!(msg[IPV4_HEADER_LEN + UDP_HEADER_LEN + OPTIONS_OFFSET + 2], DHCPREQUEST);
+            assert!(len >= IPV4_HEADER_LEN + UDP_HEADER_LEN + MIN_MESSAGE_LEN);
+        }
+
+        // Then a new discovery, in a new transaction.
+        client.message(at(t + 32), MAC, &mut msg).unwrap();
+        assert_eq!(msg[IPV4_HEADER_LEN + UDP_HEADER_LEN + OPTIONS_OFFSET + 2], DHCPDISCOVER);
+        assert_ne!(client.xid, xid);
+    }
+
+    #[test]
+    fn lease_is_renewed() {
+        let mut client = bound_client();
+        let mut msg = [0; MESSAGE_LEN];
+        let mut buf = [0; 576];
+
+        assert!(client.message(at(1799), MAC, &mut msg).is_none());
+        assert_eq!(client.poll_timeout(at(1800)), None);
+        client.message(at(1800), MAC, &mut msg).unwrap();
+        assert_eq!(msg[IPV4_HEADER_LEN + UDP_HEADER_LEN + OPTIONS_OFFSET + 2], DHCPREQUEST);
+        // Renewals are sent to the server, from the leased address.
+        assert_eq!(&msg[12..16], ADDRESS.as_bytes());
+        assert_eq!(&msg[16..20], SERVER.as_bytes());
+
+        let len = ack(&mut buf, client.xid);
+        assert_eq!(
+            client.process(&buf[..len], MAC, at(1801)),
+            Some(DhcpEvent::LeaseRenewed {
+                lease_duration: Duration::from_secs(3600)
+            })
+        );
+        assert_eq!(client.poll_at(), at(1801 + 1800));
+    }
+
+    #[test]
+    fn lease_is_rebound_then_lost() {
+        let mut client = bound_client();
+        let mut msg = [0; MESSAGE_LEN];
+
+        client.poll_timeout(at(1800));
+        client.message(at(1800), MAC, &mut msg).unwrap();
+        client.poll_timeout(at(3150));
+        client.message(at(3150), MAC, &mut msg).unwrap();
+        // Rebinding requests are broadcast.
+        assert_eq!(&msg[16..20], Ipv4Address::BROADCAST.as_bytes());
+
+        assert_eq!(client.poll_timeout(at(3599)), None);
+        assert_eq!(client.poll_timeout(at(3600)), Some(DhcpEvent::LeaseLost));
+        assert!(client.config().is_none());
+
+        // The next discovery asks for the lost address.
+        let len = client.message(at(3600), MAC, &mut msg).unwrap();
+        let options = &msg[IPV4_HEADER_LEN + UDP_HEADER_LEN + OPTIONS_OFFSET..len];
+        assert_eq!(options[2], DHCPDISCOVER);
+        assert!(options
+            .windows(6)
+            .any(|w| w == [OPTION_REQUESTED_ADDRESS, 4, 192, 168, 1, 50]));
+    }
+
+    #[test]
+    fn nak_while_renewing_loses_lease() {
+        let mut client = bound_client();
+        let mut msg = [0; MESSAGE_LEN];
+        let mut buf = [0; 576];
+
+        client.poll_timeout(at(1800));
+        client.message(at(1800), MAC, &mut msg).unwrap();
+        let len = reply(&mut buf, client.xid, DHCPNAK, &[]);
+        assert_eq!(client.process(&buf[..len], MAC, at(1801)), Some(DhcpEvent::LeaseLost));
+        assert!(matches!(client.state, State::Discovering));
+    }
+
+    #[test]
+    fn options_are_sent() {
+        let config = DhcpConfig {
+            hostname: Some(String::from("sensor")),
+            max_lease_duration: Some(Duration::from_secs(600)),
+            ..Default::default()
+        };
+        let mut client = DhcpClient::new(config, 1234);
+        let mut msg = [0; MESSAGE_LEN];
+        let len = client.message(at(0), MAC, &mut msg).unwrap();
+        let options = &msg[IPV4_HEADER_LEN + UDP_HEADER_LEN + OPTIONS_OFFSET..len];
+        assert!(options.windows(8).any(|w| w == b"\x0c\x06sensor"));
+        assert!(options
+            .windows(9)
+            .any(|w| w == [OPTION_CLIENT_ID, 7, HTYPE_ETHERNET, 2, 0, 0, 0, 0, 1]));
+        assert!(options.windows(6).any(|w| w == [OPTION_LEASE_TIME, 4, 0, 0, 2, 0x58]));
+    }
+}
@@ -0,0 +1,294 @@
+//! DHCPv4 server, handing out addresses to the hosts of the local network.
+//!
+//! This is meant for devices that are the network themselves, like a WiFi access point or a
+//! USB Ethernet gadget, so the host connecting to them gets an address without configuration.
+//! The stack must use a static configuration, with an address outside of the pool.
+//!
+//! The server keeps a lease table with one entry per address of the pool, and answers with the
+//! subnet mask, the router and the DNS servers of its [`Config`]. Leases aren't persisted, so
+//! they are lost when the server is dropped.
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::wire::{DhcpMessageType, DhcpPacket, DhcpRepr, EthernetAddress, IpEndpoint};
+
+use crate::udp::{BindError, UdpSocket};
+use crate::{Device, Ipv4Address, Ipv4Cidr, PacketMetadata, Stack};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+/// How long an offered address is reserved for the client it was offered to.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of the socket buffers needed by the server, for one DHCP message.
+pub const BUFFER_SIZE: usize = 576;
+
+/// DHCP server configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Address and subnet of the server. This must be the static address of the stack.
+    pub address: Ipv4Cidr,
+    /// First address of the pool. The pool has as many addresses as the lease table has entries.
+    pub pool_start: Ipv4Address,
+    /// Router sent to the clients, usually the server itself.
+    pub router: Option<Ipv4Address>,
+    /// DNS servers sent to the clients.
+    pub dns_servers: Vec<Ipv4Address, 3>,
+    /// Duration of the leases.
+    pub lease_duration: Duration,
+}
+
+impl Config {
+    /// Configuration for a server at `address`, acting as the router, with the pool starting
+    /// at the next address.
+    pub fn new(address: Ipv4Cidr) -> Self {
+        Self {
+            address,
+            pool_start: add(address.address(), 1),
+            router: Some(address.address()),
+            dns_servers: Vec::new(),
+            lease_duration: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// An entry of the lease table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    /// Hardware address of the client.
+    pub hardware_address: EthernetAddress,
+    /// Address leased to the client.
+    pub address: Ipv4Address,
+    /// When the lease expires.
+    pub expires_at: Instant,
+    /// Whether the address was only offered, not acknowledged yet.
+    pub offered: bool,
+}
+
+/// DHCP server, with a pool of `N` addresses.
+pub struct DhcpServer<'a, const N: usize> {
+    socket: UdpSocket<'a>,
+    config: Config,
+    leases: [Option<Lease>; N],
+}
+
+impl<'a, const N: usize> DhcpServer<'a, N> {
+    /// Create a DHCP server, listening on the DHCP server port of `stack`.
+    ///
+    /// The buffers should hold at least [`BUFFER_SIZE`] bytes.
+    pub fn new<D: Device>(
+        stack: &'a Stack<D>,
+        config: Config,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, BindError> {
+        let mut socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+        socket.bind(SERVER_PORT)?;
+
+        Ok(Self {
+            socket,
+            config,
+            leases: [None; N],
+        })
+    }
+
+    /// Current leases, including offers waiting for an answer from the client.
+    pub fn leases(&self) -> impl Iterator<Item = &Lease> {
+        let now = Instant::now();
+        self.leases.iter().flatten().filter(move |l| l.expires_at > now)
+    }
+
+    /// Answer the requests of the clients.
+    pub async fn run(&mut self) -> ! {
+        let mut buf = [0; BUFFER_SIZE];
+        loop {
+            let n = match self.socket.recv_from(&mut buf).await {
+                Ok((n, _)) => n,
+                Err(_) => continue,
+            };
+
+            let packet = match DhcpPacket::new_checked(&buf[..n]) {
+                Ok(packet) => packet,
+                Err(_) => {
+                    warn!("dhcp server: invalid packet");
+                    continue;
+                }
+            };
+            let request = match DhcpRepr::parse(&packet) {
+                Ok(repr) => repr,
+                Err(_) => {
+                    warn!("dhcp server: invalid packet");
+                    continue;
+                }
+            };
+
+            if let Some((message_type, address)) = self.handle(&request) {
+                self.reply(&request, message_type, address).await;
+            }
+        }
+    }
+
+    /// Update the lease table for `request`, returning the type of the reply and the address
+    /// to put in it, if any.
+    fn handle(&mut self, request: &DhcpRepr) -> Option<(DhcpMessageType, Ipv4Address)> {
+        let mac = request.client_hardware_address;
+        let now = Instant::now();
+
+        match request.message_type {
+            DhcpMessageType::Discover => {
+                let slot = self.find_slot(mac, request.requested_ip, now)?;
+                let address = self.slot_address(slot);
+                debug!("dhcp server: offering {} to {}", address, mac);
+                self.leases[slot] = Some(Lease {
+                    hardware_address: mac,
+                    address,
+                    expires_at: now + OFFER_TIMEOUT,
+                    offered: true,
+                });
+                Some((DhcpMessageType::Offer, address))
+            }
+            DhcpMessageType::Request => {
+                if let Some(server) = request.server_identifier {
+                    if server != self.config.address.address() {
+                        // The client accepted the offer of another server.
+                        self.release(mac);
+                        return None;
+                    }
+                }
+
+                // A renewing client puts its address in `client_ip`.
+                let requested = request.requested_ip.unwrap_or(request.client_ip);
+                let slot = match self.slot_of(requested) {
+                    Some(slot) if self.is_free_for(slot, mac, now) => slot,
+                    _ => {
+                        debug!("dhcp server: refusing {} to {}", requested, mac);
+                        return Some((DhcpMessageType::Nak, Ipv4Address::UNSPECIFIED));
+                    }
+                };
+
+                // Release any other address held by the client.
+                self.release(mac);
+                debug!("dhcp server: leasing {} to {}", requested, mac);
+                self.leases[slot] = Some(Lease {
+                    hardware_address: mac,
+                    address: requested,
+                    expires_at: now + self.config.lease_duration,
+                    offered: false,
+                });
+                Some((DhcpMessageType::Ack, requested))
+            }
+            DhcpMessageType::Release => {
+                debug!("dhcp server: {} released its address", mac);
+                self.release(mac);
+                None
+            }
+            DhcpMessageType::Decline => {
+                // Another host uses the address, keep it out of the pool for a lease duration.
+                if let Some(slot) = self.slot_of_client(mac) {
+                    warn!("dhcp server: {} declined {}", mac, self.slot_address(slot));
+                    self.leases[slot] = Some(Lease {
+                        hardware_address: EthernetAddress::BROADCAST,
+                        address: self.slot_address(slot),
+                        expires_at: now + self.config.lease_duration,
+                        offered: false,
+                    });
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Find a slot for `mac`: its current lease, the address it asks for, or the first free
+    /// address.
+    fn find_slot(&self, mac: EthernetAddress, requested: Option<Ipv4Address>, now: Instant) -> Option<usize> {
+        if let Some(slot) = self.slot_of_client(mac) {
+            return Some(slot);
+        }
+        if let Some(slot) = requested.and_then(|a| self.slot_of(a)) {
+            if self.is_free_for(slot, mac, now) {
+                return Some(slot);
+            }
+        }
+        (0..N).find(|&slot| self.is_free_for(slot, mac, now))
+    }
+
+    fn is_free_for(&self, slot: usize, mac: EthernetAddress, now: Instant) -> bool {
+        match &self.leases[slot] {
+            None => true,
+            Some(l) => l.hardware_address == mac || l.expires_at <= now,
+        }
+    }
+
+    fn slot_of_client(&self, mac: EthernetAddress) -> Option<usize> {
+        self.leases
+            .iter()
+            .position(|l| matches!(l, Some(l) if l.hardware_address == mac))
+    }
+
+    fn release(&mut self, mac: EthernetAddress) {
+        for lease in &mut self.leases {
+            if matches!(lease, Some(l) if l.hardware_address == mac) {
+                *lease = None;
+            }
+        }
+    }
+
+    fn slot_address(&self, slot: usize) -> Ipv4Address {
+        add(self.config.pool_start, slot as u32)
+    }
+
+    fn slot_of(&self, address: Ipv4Address) -> Option<usize> {
+        let offset = u32::from_be_bytes(address.0).wrapping_sub(u32::from_be_bytes(self.config.pool_start.0));
+        (offset < N as u32).then(|| offset as usize)
+    }
+
+    async fn reply(&mut self, request: &DhcpRepr<'_>, message_type: DhcpMessageType, address: Ipv4Address) {
+        let mut dns_servers = [None; 3];
+        for (dst, src) in dns_servers.iter_mut().zip(&self.config.dns_servers) {
+            *dst = Some(*src);
+        }
+        let ack = message_type != DhcpMessageType::Nak;
+
+        let reply = DhcpRepr {
+            message_type,
+            transaction_id: request.transaction_id,
+            client_hardware_address: request.client_hardware_address,
+            client_ip: Ipv4Address::UNSPECIFIED,
+            your_ip: address,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: self.config.router.filter(|_| ack),
+            subnet_mask: ack.then(|| self.config.address.netmask()),
+            relay_agent_ip: request.relay_agent_ip,
+            broadcast: request.broadcast,
+            requested_ip: None,
+            client_identifier: None,
+            server_identifier: Some(self.config.address.address()),
+            parameter_request_list: None,
+            dns_servers: (ack && !self.config.dns_servers.is_empty()).then(|| dns_servers),
+            max_size: None,
+            lease_duration: ack.then(|| self.config.lease_duration.as_secs() as u32),
+        };
+
+        let mut buf = [0; BUFFER_SIZE];
+        let len = reply.buffer_len();
+        let mut packet = DhcpPacket::new_unchecked(&mut buf[..len]);
+        if reply.emit(&mut packet).is_err() {
+            warn!("dhcp server: failed to build reply");
+            return;
+        }
+
+        // The client has no address yet, so the reply is broadcast.
+        let remote = IpEndpoint::new(Ipv4Address::BROADCAST.into(), CLIENT_PORT);
+        if self.socket.send_to(&buf[..len], remote).await.is_err() {
+            warn!("dhcp server: failed to send reply");
+        }
+    }
+}
+
+fn add(address: Ipv4Address, n: u32) -> Ipv4Address {
+    Ipv4Address::from_bytes(&(u32::from_be_bytes(address.0) + n).to_be_bytes())
+}
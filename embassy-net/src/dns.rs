@@ -0,0 +1,106 @@
+//! DNS resolution, see [`Stack::dns_query`](crate::Stack::dns_query).
+//!
+//! Queries go to the DNS servers of the current [`Config`](crate::Config), whether they come
+//! from DHCP or a static configuration. Successful answers are cached for [`CACHE_TTL`], so
+//! repeated lookups of the same names don't hit the network.
+//!
+//! The queries are sent by a socket of the stack, which takes one of the sockets of
+//! [`StackResources`](crate::StackResources).
+
+use embassy_time::{Duration, Instant};
+use heapless::{String, Vec};
+use smoltcp::socket::dns;
+pub use smoltcp::wire::DnsQueryType;
+
+use crate::IpAddress;
+
+/// Number of answers kept in the cache.
+pub const CACHE_SIZE: usize = 4;
+
+/// Longest name kept in the cache. Longer names can still be resolved, but aren't cached.
+pub const CACHE_MAX_NAME_LEN: usize = 64;
+
+/// How long answers are cached. The TTL of the answers isn't available, so this is kept short.
+pub const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Errors returned by DNS queries.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The name is not a valid hostname.
+    InvalidName,
+    /// The name is too long.
+    NameTooLong,
+    /// The query failed: no server answered, or the name doesn't exist.
+    Failed,
+}
+
+impl From<dns::StartQueryError> for Error {
+    fn from(e: dns::StartQueryError) -> Self {
+        match e {
+            dns::StartQueryError::InvalidName => Error::InvalidName,
+            dns::StartQueryError::NameTooLong => Error::NameTooLong,
+            // No free slot is handled by waiting for one.
+            dns::StartQueryError::NoFreeSlot => Error::Failed,
+        }
+    }
+}
+
+impl From<dns::GetQueryResultError> for Error {
+    fn from(_: dns::GetQueryResultError) -> Self {
+        Error::Failed
+    }
+}
+
+struct CacheEntry {
+    name: String<CACHE_MAX_NAME_LEN>,
+    qtype: DnsQueryType,
+    addrs: Vec<IpAddress, 1>,
+    expires_at: Instant,
+}
+
+/// Cache of the latest answers, evicting the one expiring first when full.
+pub(crate) struct Cache {
+    entries: Vec<CacheEntry, CACHE_SIZE>,
+}
+
+impl Cache {
+    pub(crate) const fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    pub(crate) fn get(&mut self, name: &str, qtype: DnsQueryType) -> Option<Vec<IpAddress, 1>> {
+        let now = Instant::now();
+        self.entries.retain(|e| e.expires_at > now);
+        self.entries
+            .iter()
+            .find(|e| e.name.eq_ignore_ascii_case(name) && e.qtype == qtype)
+            .map(|e| e.addrs.clone())
+    }
+
+    pub(crate) fn insert(&mut self, name: &str, qtype: DnsQueryType, addrs: &Vec<IpAddress, 1>) {
+        let mut entry = CacheEntry {
+            name: String::new(),
+            qtype,
+            addrs: addrs.clone(),
+            expires_at: Instant::now() + CACHE_TTL,
+        };
+        if entry.name.push_str(name).is_err() {
+            return;
+        }
+
+        self.entries
+            .retain(|e| !(e.name.eq_ignore_ascii_case(name) && e.qtype == qtype));
+        if self.entries.is_full() {
+            // The oldest entry expires first.
+            self.entries.remove(0);
+        }
+        // Can't fail, there's room now.
+        let _ = self.entries.push(entry);
+    }
+
+    /// Forget every answer, for example when the DNS servers change.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,478 @@
+//! Minimal HTTP/1.1 client.
+//!
+//! [`HttpClient`] sends requests over a connection implementing the `embedded-io` traits, such
+//! as a connected [`TcpSocket`](crate::tcp::TcpSocket), or a [`TlsSocket`](crate::tls::TlsSocket)
+//! for HTTPS. The head of the responses is parsed in a buffer given by the application, whose
+//! size bounds the size of the headers, and the body is read as a stream, decoding the chunked
+//! transfer encoding.
+//!
+//! The connection can be reused for several requests, once the body of each response has been
+//! read to the end.
+
+use core::str;
+
+use embedded_io::asynch::{Read, Write};
+use heapless::Vec;
+
+/// Maximum number of headers of a request, in addition to `Host` and `Content-Length`.
+pub const MAX_REQUEST_HEADERS: usize = 8;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        }
+    }
+
+    /// Method named `name` in a request line, if known.
+    #[cfg(feature = "http-server")]
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "DELETE" => Some(Method::Delete),
+            "PATCH" => Some(Method::Patch),
+            "OPTIONS" => Some(Method::Options),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The connection failed.
+    Io(E),
+    /// The connection was closed before the end of the response.
+    ConnectionClosed,
+    /// More than [`MAX_REQUEST_HEADERS`] headers were added to the request.
+    TooManyHeaders,
+    /// The head of the response doesn't fit in the buffer of the client.
+    HeadersTooLarge,
+    /// The body of the response doesn't fit in the buffer given to [`Response::read_to_end`].
+    BodyTooLarge,
+    /// The response isn't valid HTTP/1.x.
+    InvalidResponse,
+}
+
+/// HTTP/1.1 client, over the connection `C`.
+pub struct HttpClient<'b, C> {
+    conn: C,
+    buf: &'b mut [u8],
+}
+
+impl<'b, C: Read + Write> HttpClient<'b, C> {
+    /// Create a client sending requests over `conn`.
+    ///
+    /// `buf` holds the head of the responses, that is the status line and the headers, and
+    /// buffers the start of their body. Responses whose head doesn't fit are rejected.
+    pub fn new(conn: C, buf: &'b mut [u8]) -> Self {
+        Self { conn, buf }
+    }
+
+    /// Start building a request for `path`, which is sent with [`Request::send`].
+    pub fn request<'r>(&'r mut self, method: Method, path: &'r str) -> Request<'r, 'b, C> {
+        Request {
+            client: self,
+            method,
+            path,
+            host: "",
+            headers: Vec::new(),
+            too_many_headers: false,
+            body: &[],
+        }
+    }
+
+    /// The connection, for example to close it.
+    pub fn into_inner(self) -> C {
+        self.conn
+    }
+}
+
+/// A request being built.
+pub struct Request<'r, 'b, C> {
+    client: &'r mut HttpClient<'b, C>,
+    method: Method,
+    path: &'r str,
+    host: &'r str,
+    headers: Vec<(&'r str, &'r str), MAX_REQUEST_HEADERS>,
+    too_many_headers: bool,
+    body: &'r [u8],
+}
+
+impl<'r, 'b, C: Read + Write> Request<'r, 'b, C> {
+    /// Set the `Host` header, which HTTP/1.1 servers require. It's empty if not set.
+    pub fn host(mut self, host: &'r str) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Add a header.
+    pub fn header(mut self, name: &'r str, value: &'r str) -> Self {
+        if self.headers.push((name, value)).is_err() {
+            self.too_many_headers = true;
+        }
+        self
+    }
+
+    /// Set the `Content-Type` header.
+    pub fn content_type(self, content_type: &'r str) -> Self {
+        self.header("Content-Type", content_type)
+    }
+
+    /// Set the body of the request. Its `Content-Length` header is added when sending.
+    pub fn body(mut self, body: &'r [u8]) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Send the request, and wait for the head of the response.
+    pub async fn send(self) -> Result<Response<'r, C>, Error<C::Error>> {
+        if self.too_many_headers {
+            return Err(Error::TooManyHeaders);
+        }
+        let HttpClient { conn, buf } = self.client;
+        let buf: &'r mut [u8] = buf;
+
+        write_all(conn, self.method.as_str().as_bytes()).await?;
+        write_all(conn, b" ").await?;
+        write_all(conn, self.path.as_bytes()).await?;
+        write_all(conn, b" HTTP/1.1\r\nHost: ").await?;
+        write_all(conn, self.host.as_bytes()).await?;
+        write_all(conn, b"\r\n").await?;
+        for (name, value) in &self.headers {
+            write_all(conn, name.as_bytes()).await?;
+            write_all(conn, b": ").await?;
+            write_all(conn, value.as_bytes()).await?;
+            write_all(conn, b"\r\n").await?;
+        }
+        if !self.body.is_empty() || matches!(self.method, Method::Post | Method::Put | Method::Patch) {
+            let mut len = [0; 20];
+            write_all(conn, b"Content-Length: ").await?;
+            write_all(conn, format_decimal(self.body.len(), &mut len)).await?;
+            write_all(conn, b"\r\n").await?;
+        }
+        write_all(conn, b"\r\n").await?;
+        write_all(conn, self.body).await?;
+        conn.flush().await.map_err(Error::Io)?;
+
+        // Read the head of the response, skipping the informational ones.
+        let mut len = 0;
+        let head_len = loop {
+            if let Some(head_len) = find_head_end(&buf[..len]) {
+                let status = parse_status_line(&buf[..head_len])?.0;
+                if (100..200).contains(&status) && status != 101 {
+                    buf.copy_within(head_len..len, 0);
+                    len -= head_len;
+                    continue;
+                }
+                break head_len;
+            }
+            // The body is buffered after the head, so at least one byte is left for it.
+            if len + 1 >= buf.len() {
+                return Err(Error::HeadersTooLarge);
+            }
+            let end = buf.len() - 1;
+            let n = conn.read(&mut buf[len..end]).await.map_err(Error::Io)?;
+            if n == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            len += n;
+        };
+
+        let (head, rest) = buf.split_at_mut(head_len);
+        let (status, reason, headers) = parse_status_line(head)?;
+        let mut response = Response {
+            status,
+            reason,
+            headers,
+            body: Body {
+                conn,
+                buf: rest,
+                pos: 0,
+                len: len - head_len,
+                framing: Framing::UntilClose,
+            },
+        };
+
+        response.body.framing = if self.method == Method::Head || matches!(status, 101 | 204 | 304) {
+            Framing::Done
+        } else if let Some(encoding) = response.header("Transfer-Encoding") {
+            // Chunked must be the last of the encodings, and the others aren't supported.
+            if !encoding.eq_ignore_ascii_case("chunked") {
+                return Err(Error::InvalidResponse);
+            }
+            Framing::ChunkSize
+        } else if let Some(length) = response.header("Content-Length") {
+            match length.parse() {
+                Ok(0) => Framing::Done,
+                Ok(length) => Framing::Length(length),
+                Err(_) => return Err(Error::InvalidResponse),
+            }
+        } else {
+            Framing::UntilClose
+        };
+
+        Ok(response)
+    }
+}
+
+/// Response to a request, whose body is read with [`Response::read`].
+pub struct Response<'r, C> {
+    status: u16,
+    reason: &'r str,
+    /// The header lines.
+    headers: &'r str,
+    body: Body<'r, C>,
+}
+
+impl<'r, C: Read> Response<'r, C> {
+    /// Status code, for example 200.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Reason phrase, for example "OK".
+    pub fn reason(&self) -> &'r str {
+        self.reason
+    }
+
+    /// Value of the first header named `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&'r str> {
+        find_header(self.headers, name)
+    }
+
+    /// The headers, as name-value pairs.
+    pub fn headers(&self) -> impl Iterator<Item = (&'r str, &'r str)> {
+        parse_headers(self.headers)
+    }
+
+    /// Read part of the body, returning 0 at its end.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<C::Error>> {
+        self.body.read(buf).await
+    }
+
+    /// Read the whole body into `buf`, returning its length.
+    pub async fn read_to_end(&mut self, buf: &mut [u8]) -> Result<usize, Error<C::Error>> {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.body.read(&mut buf[len..]).await? {
+                0 => return Ok(len),
+                n => len += n,
+            }
+        }
+        match self.body.read(&mut [0]).await? {
+            0 => Ok(len),
+            _ => Err(Error::BodyTooLarge),
+        }
+    }
+}
+
+/// How the end of the body is found.
+#[derive(Clone, Copy)]
+enum Framing {
+    /// The given number of bytes are left.
+    Length(usize),
+    /// The body ends when the connection is closed.
+    UntilClose,
+    /// The size line of the next chunk is expected.
+    ChunkSize,
+    /// The given number of bytes are left in the current chunk.
+    Chunk(usize),
+    /// The line ending of the current chunk is expected.
+    ChunkEnd,
+    /// The trailer lines are expected, until an empty line.
+    Trailers,
+    Done,
+}
+
+struct Body<'r, C> {
+    conn: &'r mut C,
+    /// Data received after the head, in `buf[pos..len]`.
+    buf: &'r mut [u8],
+    pos: usize,
+    len: usize,
+    framing: Framing,
+}
+
+impl<'r, C: Read> Body<'r, C> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<C::Error>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.framing {
+                Framing::Done => return Ok(0),
+                Framing::UntilClose => {
+                    let n = self.read_raw(buf).await?;
+                    if n == 0 {
+                        self.framing = Framing::Done;
+                    }
+                    return Ok(n);
+                }
+                Framing::Length(left) | Framing::Chunk(left) => {
+                    let max = buf.len().min(left);
+                    let n = self.read_raw(&mut buf[..max]).await?;
+                    if n == 0 {
+                        return Err(Error::ConnectionClosed);
+                    }
+                    self.framing = match self.framing {
+                        Framing::Length(_) if n == left => Framing::Done,
+                        Framing::Length(_) => Framing::Length(left - n),
+                        _ if n == left => Framing::ChunkEnd,
+                        _ => Framing::Chunk(left - n),
+                    };
+                    return Ok(n);
+                }
+                Framing::ChunkSize => {
+                    let mut size: usize = 0;
+                    let mut digits = 0;
+                    let mut b = self.next_byte().await?;
+                    while let Some(digit) = (b as char).to_digit(16) {
+                        size = size
+                            .checked_mul(16)
+                            .and_then(|s| s.checked_add(digit as usize))
+                            .ok_or(Error::InvalidResponse)?;
+                        digits += 1;
+                        b = self.next_byte().await?;
+                    }
+                    if digits == 0 {
+                        return Err(Error::InvalidResponse);
+                    }
+                    // Skip the chunk extensions.
+                    while b != b'\n' {
+                        b = self.next_byte().await?;
+                    }
+                    self.framing = match size {
+                        0 => Framing::Trailers,
+                        size => Framing::Chunk(size),
+                    };
+                }
+                Framing::ChunkEnd => {
+                    if !self.skip_line().await? {
+                        return Err(Error::InvalidResponse);
+                    }
+                    self.framing = Framing::ChunkSize;
+                }
+                Framing::Trailers => {
+                    if self.skip_line().await? {
+                        self.framing = Framing::Done;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read from the buffered data, or from the connection if there's none.
+    async fn read_raw(&mut self, buf: &mut [u8]) -> Result<usize, Error<C::Error>> {
+        if self.pos < self.len {
+            let n = buf.len().min(self.len - self.pos);
+            buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        } else {
+            self.conn.read(buf).await.map_err(Error::Io)
+        }
+    }
+
+    async fn next_byte(&mut self) -> Result<u8, Error<C::Error>> {
+        if self.pos == self.len {
+            let n = self.conn.read(self.buf).await.map_err(Error::Io)?;
+            if n == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            self.pos = 0;
+            self.len = n;
+        }
+        let b = self.buf[self.pos];
+        self.pos += 1;
+        Ok(b)
+    }
+
+    /// Skip the rest of a line, returning whether it was empty.
+    async fn skip_line(&mut self) -> Result<bool, Error<C::Error>> {
+        let mut empty = true;
+        loop {
+            match self.next_byte().await? {
+                b'\n' => return Ok(empty),
+                b'\r' => {}
+                _ => empty = false,
+            }
+        }
+    }
+}
+
+async fn write_all<C: Write>(conn: &mut C, mut buf: &[u8]) -> Result<(), Error<C::Error>> {
+    while !buf.is_empty() {
+        match conn.write(buf).await.map_err(Error::Io)? {
+            0 => return Err(Error::ConnectionClosed),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Length of the head of the message in `buf`, including the empty line ending it.
+pub(crate) fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+/// Parse the head of a response into its status code, reason phrase and header lines.
+pub(crate) fn parse_status_line<E>(head: &[u8]) -> Result<(u16, &str, &str), Error<E>> {
+    let head = str::from_utf8(head).map_err(|_| Error::InvalidResponse)?;
+    let (status_line, headers) = head.split_once("\r\n").ok_or(Error::InvalidResponse)?;
+    let (version, rest) = status_line.split_once(' ').ok_or(Error::InvalidResponse)?;
+    let (status, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+    if !version.starts_with("HTTP/1.") || status.len() != 3 {
+        return Err(Error::InvalidResponse);
+    }
+    let status = status.parse().map_err(|_| Error::InvalidResponse)?;
+    Ok((status, reason, headers.trim_end_matches("\r\n")))
+}
+
+/// Parse header lines into name-value pairs.
+pub(crate) fn parse_headers(headers: &str) -> impl Iterator<Item = (&str, &str)> {
+    headers
+        .split("\r\n")
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+/// Value of the first header named `name` in the header lines, compared case-insensitively.
+pub(crate) fn find_header<'h>(headers: &'h str, name: &str) -> Option<&'h str> {
+    parse_headers(headers)
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v)
+}
+
+pub(crate) fn format_decimal(mut n: usize, buf: &mut [u8; 20]) -> &[u8] {
+    let mut i = buf.len();
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            return &buf[i..];
+        }
+    }
+}
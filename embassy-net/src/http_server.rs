@@ -0,0 +1,676 @@
+//! Small HTTP/1.1 server.
+//!
+//! An [`HttpServer`] owns a [`TcpSocket`], accepts connections on a port, and returns their
+//! requests one by one from [`HttpServer::next_request`], keeping the connections alive between
+//! requests. A server handles one connection at a time: to serve several clients at once, run
+//! several servers listening on the same port, each in its own task.
+//!
+//! The head of the requests is parsed in a buffer given by the application, whose size bounds
+//! the size of the headers. Requests are dispatched with a [`Router`], and answered either at
+//! once with [`Request::send`], or by streaming the body of the response with a
+//! [`ResponseWriter`].
+//!
+//! Request bodies must have a `Content-Length`: chunked request bodies aren't supported.
+
+use core::str;
+
+use embassy_time::{with_timeout, Duration};
+
+use crate::http::{find_head_end, find_header, format_decimal, parse_headers, Method};
+use crate::tcp::{self, TcpSocket};
+use crate::{Device, Stack};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The connection was reset or closed by the client.
+    ConnectionReset,
+    /// The client didn't send the body of the request in time.
+    Timeout,
+    /// The body of the request doesn't fit in the buffer given to [`Request::read_to_end`].
+    BodyTooLarge,
+}
+
+impl From<tcp::Error> for Error {
+    fn from(_: tcp::Error) -> Self {
+        Error::ConnectionReset
+    }
+}
+
+/// Common media types, for the `Content-Type` of the responses.
+pub mod content_type {
+    pub const HTML: &str = "text/html; charset=utf-8";
+    pub const TEXT: &str = "text/plain; charset=utf-8";
+    pub const CSS: &str = "text/css";
+    pub const JAVASCRIPT: &str = "text/javascript";
+    pub const JSON: &str = "application/json";
+    pub const SVG: &str = "image/svg+xml";
+    pub const PNG: &str = "image/png";
+    pub const ICON: &str = "image/x-icon";
+    pub const OCTET_STREAM: &str = "application/octet-stream";
+    /// The body of HTML forms, which [`form_value`](super::form_value) parses.
+    pub const FORM: &str = "application/x-www-form-urlencoded";
+
+    /// Media type of a file, guessed from the extension of its path.
+    pub fn from_path(path: &str) -> &'static str {
+        let extension = path.rsplit_once('.').map_or("", |(_, extension)| extension);
+        match extension {
+            "html" | "htm" => HTML,
+            "txt" => TEXT,
+            "css" => CSS,
+            "js" => JAVASCRIPT,
+            "json" => JSON,
+            "svg" => SVG,
+            "png" => PNG,
+            "ico" => ICON,
+            _ => OCTET_STREAM,
+        }
+    }
+}
+
+/// Value of the first parameter named `name` in a query string or a form body, still
+/// percent-encoded. See [`url_decode`].
+pub fn form_value<'f>(form: &'f str, name: &str) -> Option<&'f str> {
+    form.split('&')
+        .map(|param| param.split_once('=').unwrap_or((param, "")))
+        .find(|(n, _)| *n == name)
+        .map(|(_, v)| v)
+}
+
+/// Decode a percent-encoded `value` into `buf`, `+` standing for a space.
+///
+/// Returns `None` if `value` is malformed, doesn't fit in `buf`, or isn't UTF-8 once decoded.
+pub fn url_decode<'b>(value: &str, buf: &'b mut [u8]) -> Option<&'b str> {
+    let mut bytes = value.bytes();
+    let mut len = 0;
+    while let Some(b) = bytes.next() {
+        let b = match b {
+            b'+' => b' ',
+            b'%' => {
+                let hi = (bytes.next()? as char).to_digit(16)?;
+                let lo = (bytes.next()? as char).to_digit(16)?;
+                (hi << 4 | lo) as u8
+            }
+            b => b,
+        };
+        *buf.get_mut(len)? = b;
+        len += 1;
+    }
+    str::from_utf8(&buf[..len]).ok()
+}
+
+/// Reason phrase of the common status codes.
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RouteError {
+    /// No route matches the path.
+    NotFound,
+    /// Routes match the path, but not the method.
+    MethodNotAllowed,
+}
+
+impl RouteError {
+    /// Status code to answer the request with.
+    pub fn status(&self) -> u16 {
+        match self {
+            RouteError::NotFound => 404,
+            RouteError::MethodNotAllowed => 405,
+        }
+    }
+}
+
+/// Routing table, mapping the method and path of the requests to values of `T`, such as an
+/// enum of the pages of the application.
+///
+/// A path ending with `*` matches all the paths starting with the rest of it. The routes are
+/// tried in order, and the routes of `GET` also match `HEAD` requests.
+pub struct Router<'r, T> {
+    routes: &'r [(Method, &'r str, T)],
+}
+
+impl<'r, T: Copy> Router<'r, T> {
+    pub const fn new(routes: &'r [(Method, &'r str, T)]) -> Self {
+        Self { routes }
+    }
+
+    /// Value of the first route matching `method` and `path`.
+    pub fn route(&self, method: Method, path: &str) -> Result<T, RouteError> {
+        let mut path_found = false;
+        for &(route_method, pattern, value) in self.routes {
+            let matches = match pattern.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == pattern,
+            };
+            if matches {
+                if route_method == method || (route_method == Method::Get && method == Method::Head) {
+                    return Ok(value);
+                }
+                path_found = true;
+            }
+        }
+        if path_found {
+            Err(RouteError::MethodNotAllowed)
+        } else {
+            Err(RouteError::NotFound)
+        }
+    }
+}
+
+/// State of the current connection.
+#[derive(Default)]
+struct Connection {
+    open: bool,
+    /// Whether the client allows the connection to be reused after the current request.
+    keep_alive: bool,
+    /// Whether the response to the current request was completed.
+    responded: bool,
+    /// Length of the head of the current request, at the start of the buffer.
+    head_len: usize,
+    /// Data received after that head, in `buf[head_len + pos..head_len + len]`.
+    pos: usize,
+    len: usize,
+    /// Length of the body of the current request not read yet.
+    body_left: usize,
+}
+
+/// HTTP server accepting connections on one port.
+pub struct HttpServer<'a> {
+    socket: TcpSocket<'a>,
+    port: u16,
+    buf: &'a mut [u8],
+    timeout: Duration,
+    conn: Connection,
+}
+
+impl<'a> HttpServer<'a> {
+    /// Create a server listening on `port`, with the buffers of its socket.
+    ///
+    /// `buf` holds the head of the requests, typically 1 KiB.
+    pub fn new<D: Device>(
+        stack: &'a Stack<D>,
+        port: u16,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+        buf: &'a mut [u8],
+    ) -> Self {
+        Self {
+            socket: TcpSocket::new(stack, rx_buffer, tx_buffer),
+            port,
+            buf,
+            timeout: Duration::from_secs(10),
+            conn: Connection::default(),
+        }
+    }
+
+    /// Set how long a client may take to send a request, or any part of its body, or stay idle
+    /// between requests, before its connection is closed. It defaults to 10 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Wait for the next request, accepting a connection if none is open.
+    ///
+    /// The connection is reused if the previous request was answered completely, its unread
+    /// body being skipped, and closed otherwise. Invalid requests are answered with an error
+    /// status here, without being returned.
+    pub async fn next_request(&mut self) -> Request<'_, 'a> {
+        let head_len = self.wait_request().await;
+        let (head, rest) = self.buf.split_at_mut(head_len);
+        // `wait_request` checked the head.
+        let head = unwrap!(parse_head(head));
+        Request {
+            socket: &mut self.socket,
+            conn: &mut self.conn,
+            rest,
+            method: head.method,
+            path: head.path,
+            query: head.query,
+            headers: head.headers,
+            content_length: head.content_length,
+            timeout: self.timeout,
+        }
+    }
+
+    /// Wait for a valid request, returning the length of its head, at the start of the buffer.
+    async fn wait_request(&mut self) -> usize {
+        loop {
+            let mut len = 0;
+            if self.conn.open {
+                if self.conn.responded && self.conn.keep_alive && self.skip_body().await {
+                    // Move the start of the next request to the start of the buffer.
+                    let start = self.conn.head_len + self.conn.pos;
+                    len = self.conn.len - self.conn.pos;
+                    self.buf.copy_within(start..start + len, 0);
+                } else {
+                    self.close().await;
+                }
+            }
+            if !self.conn.open {
+                if self.socket.accept(self.port).await.is_err() {
+                    self.socket.abort();
+                    continue;
+                }
+                self.conn.open = true;
+            }
+
+            let head_len = match with_timeout(self.timeout, self.read_head(&mut len)).await {
+                Ok(Ok(Some(head_len))) => head_len,
+                Ok(Ok(None)) => {
+                    self.reject(431).await;
+                    continue;
+                }
+                Ok(Err(_)) | Err(_) => {
+                    self.close().await;
+                    continue;
+                }
+            };
+            match parse_head(&self.buf[..head_len]) {
+                Ok(head) => {
+                    self.conn.keep_alive = head.keep_alive;
+                    self.conn.body_left = head.content_length;
+                }
+                Err(status) => {
+                    self.reject(status).await;
+                    continue;
+                }
+            }
+            self.conn.responded = false;
+            self.conn.head_len = head_len;
+            self.conn.pos = 0;
+            self.conn.len = len - head_len;
+            return head_len;
+        }
+    }
+
+    /// Read until the end of the head of a request, `len` bytes being already in the buffer.
+    ///
+    /// Returns `None` if the head doesn't fit in the buffer.
+    async fn read_head(&mut self, len: &mut usize) -> Result<Option<usize>, Error> {
+        loop {
+            if let Some(head_len) = find_head_end(&self.buf[..*len]) {
+                return Ok(Some(head_len));
+            }
+            if *len == self.buf.len() {
+                return Ok(None);
+            }
+            match self.socket.read(&mut self.buf[*len..]).await? {
+                0 => return Err(Error::ConnectionReset),
+                n => *len += n,
+            }
+        }
+    }
+
+    /// Skip the unread body of the current request, returning whether the connection can be
+    /// reused.
+    async fn skip_body(&mut self) -> bool {
+        let buffered = self.conn.body_left.min(self.conn.len - self.conn.pos);
+        self.conn.pos += buffered;
+        self.conn.body_left -= buffered;
+        if self.conn.body_left == 0 {
+            return true;
+        }
+
+        // The buffered data was all part of the body, so the whole buffer is free.
+        self.conn.pos = 0;
+        self.conn.len = 0;
+        let timeout = self.timeout;
+        let skip = async {
+            while self.conn.body_left > 0 {
+                let n = self.conn.body_left.min(self.buf.len());
+                match self.socket.read(&mut self.buf[..n]).await {
+                    Ok(0) | Err(_) => return false,
+                    Ok(n) => self.conn.body_left -= n,
+                }
+            }
+            true
+        };
+        with_timeout(timeout, skip).await.unwrap_or(false)
+    }
+
+    /// Answer an invalid request with `status`, and close the connection.
+    async fn reject(&mut self, status: u16) {
+        let mut status_buf = [0; 20];
+        let head: [&[u8]; 5] = [
+            b"HTTP/1.1 ",
+            format_decimal(status as usize, &mut status_buf),
+            b" ",
+            reason(status).as_bytes(),
+            b"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ];
+        for part in head {
+            if write_all(&mut self.socket, part).await.is_err() {
+                break;
+            }
+        }
+        self.close().await;
+    }
+
+    /// Close the connection, waiting for the end of the response to be sent.
+    async fn close(&mut self) {
+        self.socket.close();
+        if with_timeout(self.timeout, self.socket.wait_closed()).await.is_err() {
+            self.socket.abort();
+        }
+        self.conn.open = false;
+    }
+}
+
+/// Request line and headers of a request.
+struct Head<'h> {
+    method: Method,
+    path: &'h str,
+    query: Option<&'h str>,
+    headers: &'h str,
+    content_length: usize,
+    keep_alive: bool,
+}
+
+/// Parse the head of a request, or return the status code to reject it with.
+fn parse_head(head: &[u8]) -> Result<Head<'_>, u16> {
+    let head = str::from_utf8(head).map_err(|_| 400u16)?;
+    let (request_line, headers) = head.split_once("\r\n").ok_or(400u16)?;
+    let headers = headers.trim_end_matches("\r\n");
+
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(400),
+    };
+    let method = Method::from_name(method).ok_or(501u16)?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+    if !path.starts_with('/') {
+        return Err(400);
+    }
+
+    // HTTP/1.0 responses can't be chunked, so their end is marked by closing the connection.
+    let keep_alive = match version {
+        "HTTP/1.1" => !find_header(headers, "Connection")
+            .map_or(false, |v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case("close"))),
+        "HTTP/1.0" => false,
+        _ => return Err(505),
+    };
+    if find_header(headers, "Transfer-Encoding").is_some() {
+        return Err(411);
+    }
+    let content_length = match find_header(headers, "Content-Length") {
+        Some(v) => v.parse().map_err(|_| 400u16)?,
+        None => 0,
+    };
+
+    Ok(Head {
+        method,
+        path,
+        query,
+        headers,
+        content_length,
+        keep_alive,
+    })
+}
+
+/// Request returned by [`HttpServer::next_request`].
+///
+/// It must be answered with [`Request::send`], [`Request::redirect`] or [`Request::respond`]
+/// for the connection to be reused: otherwise the connection is closed before the next request.
+pub struct Request<'s, 'a> {
+    socket: &'s mut TcpSocket<'a>,
+    conn: &'s mut Connection,
+    /// The data received after the head.
+    rest: &'s mut [u8],
+    method: Method,
+    path: &'s str,
+    query: Option<&'s str>,
+    headers: &'s str,
+    content_length: usize,
+    timeout: Duration,
+}
+
+impl<'s, 'a> Request<'s, 'a> {
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Path of the target, without the query.
+    pub fn path(&self) -> &'s str {
+        self.path
+    }
+
+    /// Query of the target, after the `?`. See [`form_value`].
+    pub fn query(&self) -> Option<&'s str> {
+        self.query
+    }
+
+    /// Value of the first header named `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&'s str> {
+        find_header(self.headers, name)
+    }
+
+    /// Names and values of all the headers.
+    pub fn headers(&self) -> impl Iterator<Item = (&'s str, &'s str)> {
+        parse_headers(self.headers)
+    }
+
+    /// Length of the body, from the `Content-Length` header.
+    pub fn content_length(&self) -> usize {
+        self.content_length
+    }
+
+    /// Value of the route of `router` matching the request.
+    pub fn route<T: Copy>(&self, router: &Router<'_, T>) -> Result<T, RouteError> {
+        router.route(self.method, self.path)
+    }
+
+    /// Read the next part of the body into `buf`, returning its length, 0 at the end of the body.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let max = buf.len().min(self.conn.body_left);
+        if max == 0 {
+            return Ok(0);
+        }
+        let n = if self.conn.pos < self.conn.len {
+            let n = max.min(self.conn.len - self.conn.pos);
+            buf[..n].copy_from_slice(&self.rest[self.conn.pos..self.conn.pos + n]);
+            self.conn.pos += n;
+            n
+        } else {
+            let read = with_timeout(self.timeout, self.socket.read(&mut buf[..max]));
+            match read.await.map_err(|_| Error::Timeout)?? {
+                0 => return Err(Error::ConnectionReset),
+                n => n,
+            }
+        };
+        self.conn.body_left -= n;
+        Ok(n)
+    }
+
+    /// Read the whole body into `buf`, returning its length.
+    pub async fn read_to_end(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.conn.body_left > buf.len() {
+            return Err(Error::BodyTooLarge);
+        }
+        let mut len = 0;
+        loop {
+            match self.read(&mut buf[len..]).await? {
+                0 => return Ok(len),
+                n => len += n,
+            }
+        }
+    }
+
+    /// Answer with `status` and the whole `body`.
+    pub async fn send(mut self, status: u16, content_type: &str, body: &[u8]) -> Result<(), Error> {
+        let body_allowed = self.write_head(status, content_type, Some(body.len()), &[]).await?;
+        if body_allowed {
+            write_all(self.socket, body).await?;
+        }
+        self.conn.responded = true;
+        Ok(())
+    }
+
+    /// Answer with a redirection to `location`, to be fetched with `GET`, for example after
+    /// a form was posted.
+    pub async fn redirect(mut self, location: &str) -> Result<(), Error> {
+        self.write_head(303, content_type::TEXT, Some(0), &[("Location", location)])
+            .await?;
+        self.conn.responded = true;
+        Ok(())
+    }
+
+    /// Start answering with `status` and the additional `headers`, the body being then written
+    /// with the returned [`ResponseWriter`].
+    ///
+    /// The body is sent with the chunked transfer encoding, or until the connection is closed
+    /// for HTTP/1.0 clients.
+    pub async fn respond(
+        mut self,
+        status: u16,
+        content_type: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<ResponseWriter<'s, 'a>, Error> {
+        let body_allowed = self.write_head(status, content_type, None, headers).await?;
+        let encoding = match (body_allowed, self.conn.keep_alive) {
+            (false, _) => Encoding::None,
+            (true, true) => Encoding::Chunked,
+            (true, false) => Encoding::UntilClose,
+        };
+        Ok(ResponseWriter {
+            socket: self.socket,
+            conn: self.conn,
+            encoding,
+        })
+    }
+
+    /// Write the head of the response, returning whether it has a body.
+    ///
+    /// `content_length` is `None` if the body is streamed.
+    async fn write_head(
+        &mut self,
+        status: u16,
+        content_type: &str,
+        content_length: Option<usize>,
+        headers: &[(&str, &str)],
+    ) -> Result<bool, Error> {
+        let body_allowed = self.method != Method::Head && !matches!(status, 100..=199 | 204 | 304);
+
+        let mut num_buf = [0; 20];
+        write_all(self.socket, b"HTTP/1.1 ").await?;
+        write_all(self.socket, format_decimal(status as usize, &mut num_buf)).await?;
+        write_all(self.socket, b" ").await?;
+        write_all(self.socket, reason(status).as_bytes()).await?;
+        write_all(self.socket, b"\r\nContent-Type: ").await?;
+        write_all(self.socket, content_type.as_bytes()).await?;
+        write_all(self.socket, b"\r\n").await?;
+        for (name, value) in headers {
+            write_all(self.socket, name.as_bytes()).await?;
+            write_all(self.socket, b": ").await?;
+            write_all(self.socket, value.as_bytes()).await?;
+            write_all(self.socket, b"\r\n").await?;
+        }
+        match content_length {
+            Some(len) => {
+                write_all(self.socket, b"Content-Length: ").await?;
+                write_all(self.socket, format_decimal(len, &mut num_buf)).await?;
+                write_all(self.socket, b"\r\n").await?;
+            }
+            None if body_allowed && self.conn.keep_alive => {
+                write_all(self.socket, b"Transfer-Encoding: chunked\r\n").await?;
+            }
+            None => {}
+        }
+        if !self.conn.keep_alive {
+            write_all(self.socket, b"Connection: close\r\n").await?;
+        }
+        write_all(self.socket, b"\r\n").await?;
+        Ok(body_allowed)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Encoding {
+    Chunked,
+    UntilClose,
+    /// The response has no body, such as the ones of `HEAD` requests.
+    None,
+}
+
+/// Writer of the body of a response, returned by [`Request::respond`].
+///
+/// [`ResponseWriter::finish`] must be called at the end of the body.
+pub struct ResponseWriter<'s, 'a> {
+    socket: &'s mut TcpSocket<'a>,
+    conn: &'s mut Connection,
+    encoding: Encoding,
+}
+
+impl<'s, 'a> ResponseWriter<'s, 'a> {
+    /// Write the next part of the body. It is discarded if the response has no body.
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        match self.encoding {
+            // An empty chunk would end the body.
+            Encoding::Chunked if !data.is_empty() => {
+                let mut size_buf = [0; 16];
+                write_all(self.socket, format_hex(data.len(), &mut size_buf)).await?;
+                write_all(self.socket, b"\r\n").await?;
+                write_all(self.socket, data).await?;
+                write_all(self.socket, b"\r\n").await
+            }
+            Encoding::UntilClose => write_all(self.socket, data).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// End the body.
+    pub async fn finish(self) -> Result<(), Error> {
+        if self.encoding == Encoding::Chunked {
+            write_all(self.socket, b"0\r\n\r\n").await?;
+        }
+        self.conn.responded = true;
+        Ok(())
+    }
+}
+
+async fn write_all(socket: &mut TcpSocket<'_>, mut buf: &[u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        let n = socket.write(buf).await?;
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+fn format_hex(mut n: usize, buf: &mut [u8; 16]) -> &[u8] {
+    let mut i = buf.len();
+    loop {
+        i -= 1;
+        buf[i] = b"0123456789abcdef"[n % 16];
+        n /= 16;
+        if n == 0 {
+            return &buf[i..];
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! ICMP sockets, for ping and other ICMP messages.
+
+use core::cell::UnsafeCell;
+use core::future::poll_fn;
+use core::mem;
+use core::task::Poll;
+
+use embassy_time::{Duration, Instant};
+use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::icmp;
+pub use smoltcp::socket::icmp::{Endpoint, PacketMetadata};
+use smoltcp::wire::{Icmpv4Message, Icmpv4Packet, Icmpv4Repr, IpAddress, Ipv4Address};
+
+use super::stack::SocketStack;
+use crate::{Device, Stack};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BindError {
+    /// The socket was already open.
+    InvalidState,
+    /// The endpoint isn't valid, for example an unspecified identifier.
+    InvalidEndpoint,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// No route to host.
+    NoRoute,
+    /// The socket isn't bound to an identifier, which [`IcmpSocket::ping`] needs.
+    NotBound,
+}
+
+pub struct IcmpSocket<'a> {
+    stack: &'a UnsafeCell<SocketStack>,
+    handle: SocketHandle,
+    ident: Option<u16>,
+}
+
+impl<'a> IcmpSocket<'a> {
+    pub fn new<D: Device>(
+        stack: &'a Stack<D>,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        // safety: not accessed reentrantly.
+        let s = unsafe { &mut *stack.socket.get() };
+
+        let rx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(rx_meta) };
+        let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
+        let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
+        let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
+        let handle = s.sockets.add(icmp::Socket::new(
+            icmp::PacketBuffer::new(rx_meta, rx_buffer),
+            icmp::PacketBuffer::new(tx_meta, tx_buffer),
+        ));
+
+        Self {
+            stack: &stack.socket,
+            handle,
+            ident: None,
+        }
+    }
+
+    /// Bind the socket, selecting the messages it receives.
+    ///
+    /// For ping, bind to [`Endpoint::Ident`] with the identifier used in the echo requests.
+    pub fn bind(&mut self, endpoint: Endpoint) -> Result<(), BindError> {
+        // safety: not accessed reentrantly.
+        match unsafe { self.with_mut(|s, _| s.bind(endpoint)) } {
+            Ok(()) => {
+                if let Endpoint::Ident(ident) = endpoint {
+                    self.ident = Some(ident);
+                }
+                Ok(())
+            }
+            Err(icmp::BindError::InvalidState) => Err(BindError::InvalidState),
+            Err(icmp::BindError::Unaddressable) => Err(BindError::InvalidEndpoint),
+        }
+    }
+
+    /// SAFETY: must not call reentrantly.
+    unsafe fn with<R>(&self, f: impl FnOnce(&icmp::Socket, &Interface) -> R) -> R {
+        let s = &*self.stack.get();
+        let socket = s.sockets.get::<icmp::Socket>(self.handle);
+        f(socket, &s.iface)
+    }
+
+    /// SAFETY: must not call reentrantly.
+    unsafe fn with_mut<R>(&self, f: impl FnOnce(&mut icmp::Socket, &mut Interface) -> R) -> R {
+        let s = &mut *self.stack.get();
+        let socket = s.sockets.get_mut::<icmp::Socket>(self.handle);
+        let res = f(socket, &mut s.iface);
+        s.waker.wake();
+        res
+    }
+
+    /// Receive an ICMP message, returning its length and the address of the sender.
+    ///
+    /// The message is truncated if it doesn't fit in `buf`.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, IpAddress), Error> {
+        poll_fn(move |cx| unsafe {
+            self.with_mut(|s, _| match s.recv_slice(buf) {
+                Ok(x) => Poll::Ready(Ok(x)),
+                // No data ready
+                Err(icmp::RecvError::Exhausted) => {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Send an ICMP message, which must include the ICMP header with a valid checksum.
+    ///
+    /// The message must fit in the transmit buffer.
+    pub async fn send_to(&self, buf: &[u8], remote: IpAddress) -> Result<(), Error> {
+        self.send_with(buf.len(), remote, |dst| dst.copy_from_slice(buf)).await
+    }
+
+    /// Send an ICMP message of `len` bytes, written to the transmit buffer by `f`.
+    async fn send_with(&self, len: usize, remote: IpAddress, f: impl FnOnce(&mut [u8])) -> Result<(), Error> {
+        let mut f = Some(f);
+        poll_fn(move |cx| unsafe {
+            self.with_mut(|s, _| match s.send(len, remote) {
+                Ok(dst) => {
+                    (f.take().unwrap())(dst);
+                    Poll::Ready(Ok(()))
+                }
+                Err(icmp::SendError::BufferFull) => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                Err(icmp::SendError::Unaddressable) => Poll::Ready(Err(Error::NoRoute)),
+            })
+        })
+        .await
+    }
+
+    /// Send an echo request to `remote`, and wait for the reply, returning the round trip time.
+    ///
+    /// The socket must be bound to [`Endpoint::Ident`], whose identifier is used in the request.
+    /// Replies to other requests are discarded, so this should be wrapped in a timeout.
+    pub async fn ping(&self, remote: Ipv4Address, seq_no: u16, data: &[u8]) -> Result<Duration, Error> {
+        let ident = self.ident.ok_or(Error::NotBound)?;
+
+        let request = Icmpv4Repr::EchoRequest { ident, seq_no, data };
+        let start = Instant::now();
+        self.send_with(request.buffer_len(), remote.into(), |buf| {
+            let mut packet = Icmpv4Packet::new_unchecked(buf);
+            request.emit(&mut packet, &ChecksumCapabilities::default());
+        })
+        .await?;
+
+        let mut buf = [0; 64];
+        loop {
+            let (n, from) = self.recv_from(&mut buf).await?;
+            if from != IpAddress::Ipv4(remote) {
+                continue;
+            }
+            let packet = match Icmpv4Packet::new_checked(&buf[..n]) {
+                Ok(packet) => packet,
+                Err(_) => continue,
+            };
+            // The payload is truncated to the buffer, so the checksum isn't checked.
+            if packet.msg_type() == Icmpv4Message::EchoReply
+                && packet.echo_ident() == ident
+                && packet.echo_seq_no() == seq_no
+            {
+                return Ok(Instant::now() - start);
+            }
+        }
+    }
+
+    /// Time-to-live of the sent packets, `None` for the default of the stack.
+    pub fn hop_limit(&self) -> Option<u8> {
+        unsafe { self.with(|s, _| s.hop_limit()) }
+    }
+
+    /// Set the time-to-live of the sent packets, `None` for the default of the stack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hop_limit` is `Some(0)`.
+    pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
+        unsafe { self.with_mut(|s, _| s.set_hop_limit(hop_limit)) }
+    }
+
+    pub fn is_open(&self) -> bool {
+        unsafe { self.with(|s, _| s.is_open()) }
+    }
+
+    pub fn may_send(&self) -> bool {
+        unsafe { self.with(|s, _| s.can_send()) }
+    }
+
+    pub fn may_recv(&self) -> bool {
+        unsafe { self.with(|s, _| s.can_recv()) }
+    }
+}
+
+impl Drop for IcmpSocket<'_> {
+    fn drop(&mut self) {
+        // safety: not accessed reentrantly.
+        let s = unsafe { &mut *self.stack.get() };
+        s.sockets.remove(self.handle);
+    }
+}
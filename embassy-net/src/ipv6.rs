@@ -0,0 +1,232 @@
+//! IPv6 configuration: link-local address, SLAAC and router discovery.
+//!
+//! On Ethernet, the stack always has a link-local address derived from the MAC address
+//! (EUI-64). With [`ConfigStrategyV6::Slaac`], it also solicits the routers of the network, and
+//! configures a global address from the prefix they advertise, the router as the default
+//! gateway and the DNS servers of the RDNSS option. DHCPv6 isn't supported: the networks
+//! requiring it, which set the "managed" flag in their advertisements, are reported in the log.
+//!
+//! Sockets bound without an address accept both IPv4 and IPv6 traffic.
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::wire::{Ipv6Address, Ipv6Cidr};
+
+/// Hop limit of the neighbor discovery messages, which must not have been routed.
+const NDISC_HOP_LIMIT: u8 = 255;
+const ICMPV6_ROUTER_SOLICIT: u8 = 133;
+const ICMPV6_ROUTER_ADVERT: u8 = 134;
+const NDISC_OPTION_SOURCE_LLADDR: u8 = 1;
+const NDISC_OPTION_PREFIX_INFO: u8 = 3;
+const NDISC_OPTION_RDNSS: u8 = 25;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// All-routers link-local multicast address.
+const ALL_ROUTERS: Ipv6Address = Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+/// Router solicitations sent before waiting for unsolicited advertisements (RFC 4861).
+const MAX_RTR_SOLICITATIONS: u8 = 3;
+const RTR_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Length of a router solicitation with the source link-layer address option.
+pub(crate) const ROUTER_SOLICIT_LEN: usize = 40 + 8 + 8;
+
+/// Static IPv6 configuration, or the one obtained with SLAAC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigV6 {
+    pub address: Ipv6Cidr,
+    pub gateway: Option<Ipv6Address>,
+    pub dns_servers: Vec<Ipv6Address, 3>,
+}
+
+/// How the IPv6 configuration is obtained, in addition to the link-local address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigStrategyV6 {
+    /// Only the link-local address.
+    LinkLocal,
+    /// A static configuration.
+    Static(ConfigV6),
+    /// Stateless address autoconfiguration from the router advertisements.
+    Slaac,
+}
+
+/// Link-local address of the interface with MAC address `mac`.
+pub(crate) fn link_local_address(mac: [u8; 6]) -> Ipv6Address {
+    slaac_address(Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), mac)
+}
+
+/// Address made of the /64 `prefix` and the EUI-64 interface identifier of `mac`.
+pub(crate) fn slaac_address(prefix: Ipv6Address, mac: [u8; 6]) -> Ipv6Address {
+    let mut a = prefix.0;
+    a[8..16].copy_from_slice(&[mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]]);
+    Ipv6Address(a)
+}
+
+/// MAC address of the solicited-node multicast group of `addr`, which the neighbor
+/// solicitations for `addr` are sent to.
+pub(crate) fn solicited_node_mac(addr: Ipv6Address) -> [u8; 6] {
+    [0x33, 0x33, 0xff, addr.0[13], addr.0[14], addr.0[15]]
+}
+
+/// MAC address of the all-nodes multicast group, which router advertisements are sent to.
+pub(crate) const ALL_NODES_MAC: [u8; 6] = [0x33, 0x33, 0, 0, 0, 1];
+
+/// Write a router solicitation from `src`, including the IPv6 header, to `buf`.
+pub(crate) fn router_solicitation(src: Ipv6Address, mac: [u8; 6], buf: &mut [u8; ROUTER_SOLICIT_LEN]) {
+    let payload_len = (ROUTER_SOLICIT_LEN - 40) as u16;
+    buf.fill(0);
+    buf[0] = 0x60;
+    buf[4..6].copy_from_slice(&payload_len.to_be_bytes());
+    buf[6] = IPPROTO_ICMPV6;
+    buf[7] = NDISC_HOP_LIMIT;
+    buf[8..24].copy_from_slice(&src.0);
+    buf[24..40].copy_from_slice(&ALL_ROUTERS.0);
+
+    let icmp = &mut buf[40..];
+    icmp[0] = ICMPV6_ROUTER_SOLICIT;
+    icmp[8] = NDISC_OPTION_SOURCE_LLADDR;
+    icmp[9] = 1;
+    icmp[10..16].copy_from_slice(&mac);
+    let checksum = icmpv6_checksum(&src, &ALL_ROUTERS, &buf[40..]);
+    buf[42..44].copy_from_slice(&checksum.to_be_bytes());
+}
+
+fn icmpv6_checksum(src: &Ipv6Address, dst: &Ipv6Address, msg: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut add = |data: &[u8]| {
+        for chunk in data.chunks(2) {
+            let hi = chunk[0] as u32;
+            let lo = chunk.get(1).copied().unwrap_or(0) as u32;
+            sum += hi << 8 | lo;
+        }
+    };
+    add(&src.0);
+    add(&dst.0);
+    add(&(msg.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, IPPROTO_ICMPV6]);
+    add(msg);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The parts of a router advertisement used for SLAAC.
+pub(crate) struct RouterAdvertisement {
+    pub router: Ipv6Address,
+    /// How long the router can be the default gateway, zero if it can't.
+    pub router_lifetime: Duration,
+    /// Whether addresses should be obtained with DHCPv6.
+    pub managed: bool,
+    /// Autonomous /64 prefix, and how long it's valid.
+    pub prefix: Option<(Ipv6Address, Duration)>,
+    pub dns_servers: Vec<Ipv6Address, 3>,
+}
+
+/// Parse a router advertisement, with its IPv6 header, as received by a raw socket.
+pub(crate) fn parse_router_advertisement(packet: &[u8]) -> Option<RouterAdvertisement> {
+    // Extension headers aren't expected in neighbor discovery messages.
+    if packet.len() < 40 + 16 || packet[0] >> 4 != 6 || packet[6] != IPPROTO_ICMPV6 || packet[7] != NDISC_HOP_LIMIT {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+    let msg = packet.get(40..40 + payload_len)?;
+    if msg.len() < 16 || msg[0] != ICMPV6_ROUTER_ADVERT || msg[1] != 0 {
+        return None;
+    }
+
+    let mut src = [0; 16];
+    src.copy_from_slice(&packet[8..24]);
+    let router = Ipv6Address(src);
+    if !router.is_link_local() {
+        return None;
+    }
+    let mut dst = [0; 16];
+    dst.copy_from_slice(&packet[24..40]);
+    if icmpv6_checksum(&router, &Ipv6Address(dst), msg) != 0 {
+        return None;
+    }
+
+    let mut ra = RouterAdvertisement {
+        router,
+        router_lifetime: Duration::from_secs(u16::from_be_bytes([msg[6], msg[7]]) as u64),
+        managed: msg[5] & 0x80 != 0,
+        prefix: None,
+        dns_servers: Vec::new(),
+    };
+
+    let mut options = &msg[16..];
+    while options.len() >= 8 {
+        let len = options[1] as usize * 8;
+        if len == 0 || len > options.len() {
+            return None;
+        }
+        let option = &options[..len];
+        match option[0] {
+            NDISC_OPTION_PREFIX_INFO if len == 32 => {
+                let prefix_len = option[2];
+                let autonomous = option[3] & 0x40 != 0;
+                let valid = u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+                if prefix_len == 64 && autonomous && ra.prefix.is_none() {
+                    let mut prefix = [0; 16];
+                    prefix.copy_from_slice(&option[16..32]);
+                    ra.prefix = Some((Ipv6Address(prefix), Duration::from_secs(valid as u64)));
+                }
+            }
+            NDISC_OPTION_RDNSS => {
+                for addr in option[8..].chunks_exact(16) {
+                    let mut a = [0; 16];
+                    a.copy_from_slice(addr);
+                    let _ = ra.dns_servers.push(Ipv6Address(a));
+                }
+            }
+            _ => {}
+        }
+        options = &options[len..];
+    }
+
+    Some(ra)
+}
+
+/// State of the router discovery.
+pub(crate) struct Slaac {
+    solicitations_left: u8,
+    next_solicitation: Instant,
+    /// When the prefix or the router of the current configuration expire.
+    pub expires_at: Option<Instant>,
+}
+
+impl Slaac {
+    pub fn new() -> Self {
+        Self {
+            solicitations_left: MAX_RTR_SOLICITATIONS,
+            next_solicitation: Instant::from_ticks(0),
+            expires_at: None,
+        }
+    }
+
+    /// Start over, when the link comes up.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Whether a router solicitation should be sent now. The solicitations stop once
+    /// configured.
+    pub fn should_solicit(&mut self, configured: bool, now: Instant) -> bool {
+        if configured || self.solicitations_left == 0 || now < self.next_solicitation {
+            return false;
+        }
+        self.solicitations_left -= 1;
+        self.next_solicitation = now + RTR_SOLICITATION_INTERVAL;
+        true
+    }
+
+    /// When the state machine needs to be polled next.
+    pub fn poll_at(&self, configured: bool) -> Option<Instant> {
+        let solicit = (!configured && self.solicitations_left > 0).then(|| self.next_solicitation);
+        match (solicit, self.expires_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
@@ -4,17 +4,66 @@
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
+#[cfg(feature = "coap")]
+pub mod coap;
 mod device;
+#[cfg(feature = "dhcpv4")]
+mod dhcp;
+#[cfg(feature = "dhcpv4-server")]
+pub mod dhcp_server;
+#[cfg(feature = "dns")]
+pub mod dns;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "http-server")]
+pub mod http_server;
+#[cfg(feature = "proto-ipv6")]
+mod ipv6;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 mod packet_pool;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+#[cfg(feature = "ppp")]
+pub mod ppp;
+#[cfg(feature = "router")]
+pub mod router;
+#[cfg(feature = "slip")]
+pub mod slip;
+#[cfg(feature = "sntp")]
+pub mod sntp;
 mod stack;
+#[cfg(feature = "tcp")]
+mod tcp_stats;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
-pub use device::{Device, LinkState};
+pub use device::{Device, LinkState, Stats};
+#[cfg(feature = "dhcpv4")]
+pub use dhcp::{DhcpConfig, DhcpEvent};
+#[cfg(feature = "proto-ipv6")]
+pub use ipv6::{ConfigStrategyV6, ConfigV6};
 pub use packet_pool::{Packet, PacketBox, PacketBoxExt, PacketBuf, MTU};
+#[cfg(feature = "igmp")]
+pub use stack::MulticastError;
+#[cfg(feature = "medium-ethernet")]
+pub use stack::RouteError;
 pub use stack::{Config, ConfigStrategy, Stack, StackResources};
 
+#[cfg(feature = "icmp")]
+pub mod icmp;
+
+#[cfg(feature = "raw")]
+pub mod raw;
+
 #[cfg(feature = "tcp")]
 pub mod tcp;
 
+#[cfg(feature = "tls")]
+pub mod tls;
+
 #[cfg(feature = "udp")]
 pub mod udp;
 
@@ -22,7 +71,11 @@ pub mod udp;
 pub use smoltcp::phy::{DeviceCapabilities, Medium};
 pub use smoltcp::time::{Duration as SmolDuration, Instant as SmolInstant};
 #[cfg(feature = "medium-ethernet")]
-pub use smoltcp::wire::{EthernetAddress, HardwareAddress};
+pub use smoltcp::wire::EthernetAddress;
+#[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
+pub use smoltcp::wire::HardwareAddress;
+#[cfg(feature = "medium-ieee802154")]
+pub use smoltcp::wire::{Ieee802154Address, Ieee802154Pan};
 pub use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
 #[cfg(feature = "proto-ipv6")]
 pub use smoltcp::wire::{Ipv6Address, Ipv6Cidr};
@@ -0,0 +1,458 @@
+//! mDNS responder, with DNS-SD service advertisement.
+//!
+//! The [`Responder`] answers the queries for `<hostname>.local` with the IPv4 address of the
+//! stack, so the device can be reached by name on the local network without a DNS server. It
+//! also advertises [`Service`]s with DNS-SD, so browsers for a service type like `_http._tcp`
+//! find the device.
+//!
+//! Names are written without compression, and only IPv4 is supported. The responder doesn't
+//! probe for conflicts: the hostname and the instance names must be unique on the network.
+
+use heapless::String;
+use smoltcp::wire::IpEndpoint;
+
+use crate::udp::{BindError, UdpSocket};
+use crate::{Device, Ipv4Address, PacketMetadata, Stack};
+
+const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+
+/// Size of the socket buffers needed by the responder, for one mDNS message.
+pub const BUFFER_SIZE: usize = 1500;
+
+/// TTL of the records, in seconds.
+const TTL: u32 = 120;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+/// In questions, asks for a unicast response. In answers, flushes the caches of the record.
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// Response flags: response, authoritative answer.
+const FLAGS_RESPONSE: u16 = 0x8400;
+
+/// Name of the pseudo-service listing the service types, for DNS-SD browsers.
+const SERVICES_NAME: &str = "_services._dns-sd._udp";
+
+/// A service advertised with DNS-SD.
+#[derive(Debug, Clone, Copy)]
+pub struct Service<'a> {
+    /// Name of this instance of the service, shown to the users. It must not contain dots.
+    pub instance: &'a str,
+    /// Service type and protocol, like `_http._tcp`.
+    pub service: &'a str,
+    /// Port of the service.
+    pub port: u16,
+    /// Entries of the TXT record, usually `key=value`.
+    pub txt: &'a [&'a str],
+}
+
+/// Errors of the responder.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The socket couldn't be bound to the mDNS port.
+    Bind(BindError),
+    /// The mDNS multicast group couldn't be joined.
+    Multicast,
+}
+
+/// mDNS responder, answering for `hostname` and advertising `services`.
+pub struct Responder<'a, D: Device> {
+    stack: &'a Stack<D>,
+    socket: UdpSocket<'a>,
+    hostname: &'a str,
+    services: &'a [Service<'a>],
+}
+
+impl<'a, D: Device + 'static> Responder<'a, D> {
+    /// Create a responder, listening on the mDNS port of `stack`.
+    ///
+    /// `hostname` is given without the `.local` suffix. The buffers should hold at least
+    /// [`BUFFER_SIZE`] bytes.
+    pub fn new(
+        stack: &'a Stack<D>,
+        hostname: &'a str,
+        services: &'a [Service<'a>],
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, Error> {
+        let mut socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+        socket.bind(MDNS_PORT).map_err(Error::Bind)?;
+        stack.join_multicast_group(MDNS_GROUP).map_err(|_| Error::Multicast)?;
+
+        Ok(Self {
+            stack,
+            socket,
+            hostname,
+            services,
+        })
+    }
+
+    /// Announce the hostname and the services, then answer the queries.
+    pub async fn run(&mut self) -> ! {
+        let mut rx = [0; BUFFER_SIZE];
+        let mut tx = [0; BUFFER_SIZE];
+
+        if let Some(len) = self.announcement(&mut tx) {
+            self.send(&tx[..len], IpEndpoint::new(MDNS_GROUP.into(), MDNS_PORT))
+                .await;
+        }
+
+        loop {
+            let (n, from) = match self.socket.recv_from(&mut rx).await {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+
+            let (len, unicast) = match self.answer(&rx[..n], &mut tx) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            // Queries from other ports are from plain DNS resolvers, which expect a unicast
+            // answer.
+            let to = if unicast || from.port != MDNS_PORT {
+                from
+            } else {
+                IpEndpoint::new(MDNS_GROUP.into(), MDNS_PORT)
+            };
+            self.send(&tx[..len], to).await;
+        }
+    }
+
+    async fn send(&mut self, buf: &[u8], to: IpEndpoint) {
+        if self.socket.send_to(buf, to).await.is_err() {
+            warn!("mdns: failed to send answer");
+        }
+    }
+
+    fn address(&self) -> Option<Ipv4Address> {
+        self.stack.config().map(|c| c.address.address())
+    }
+
+    /// Build an unsolicited response with every record.
+    fn announcement(&self, tx: &mut [u8]) -> Option<usize> {
+        let address = self.address()?;
+        let mut w = Writer::new(tx);
+        let mut answers = 0;
+        let res: Result<(), Truncated> = (|| {
+            w.header(0, 0, 0)?;
+            w.a(self.hostname, address)?;
+            answers += 1;
+            for s in self.services {
+                w.ptr(&[SERVICES_NAME], &[s.service])?;
+                w.ptr(&[s.service], &[s.instance, s.service])?;
+                w.srv(s, self.hostname)?;
+                w.txt(s)?;
+                answers += 4;
+            }
+            Ok(())
+        })();
+        res.ok()?;
+        w.set_counts(answers, 0);
+        Some(w.pos)
+    }
+
+    /// Build the answer to `query`, returning its length and whether it should be sent by
+    /// unicast, or `None` if there's nothing to answer.
+    ///
+    /// If the query is malformed or the answer doesn't fit, the answers built so far are sent.
+    fn answer(&self, query: &[u8], tx: &mut [u8]) -> Option<(usize, bool)> {
+        let address = self.address()?;
+
+        let mut r = Reader::new(query);
+        let id = r.u16().ok()?;
+        let flags = r.u16().ok()?;
+        if flags & 0x8000 != 0 {
+            // A response from another responder.
+            return None;
+        }
+        let questions = r.u16().ok()?;
+        r.skip(6).ok()?;
+
+        let mut w = Writer::new(tx);
+        let mut answers = 0;
+        let mut additionals = 0;
+        let mut unicast = true;
+
+        // Records sent as additional records, after the answers.
+        let mut add_host = false;
+        let mut add_services = false;
+        let _: Result<(), Truncated> = (|| {
+            w.header(id, 0, 0)?;
+            for _ in 0..questions {
+                let mut name: String<255> = String::new();
+                r.name(&mut name)?;
+                let qtype = r.u16()?;
+                let qclass = r.u16()?;
+                unicast &= qclass & CLASS_TOP_BIT != 0;
+                let any = qtype == TYPE_ANY;
+
+                if (any || qtype == TYPE_A) && name_eq(&name, &[self.hostname]) {
+                    w.a(self.hostname, address)?;
+                    answers += 1;
+                }
+                if (any || qtype == TYPE_PTR) && name_eq(&name, &[SERVICES_NAME]) {
+                    for s in self.services {
+                        w.ptr(&[SERVICES_NAME], &[s.service])?;
+                        answers += 1;
+                    }
+                }
+                for s in self.services {
+                    if (any || qtype == TYPE_PTR) && name_eq(&name, &[s.service]) {
+                        w.ptr(&[s.service], &[s.instance, s.service])?;
+                        answers += 1;
+                        add_host = true;
+                        add_services = true;
+                    }
+                    if name_eq(&name, &[s.instance, s.service]) {
+                        if any || qtype == TYPE_SRV {
+                            w.srv(s, self.hostname)?;
+                            answers += 1;
+                            add_host = true;
+                        }
+                        if any || qtype == TYPE_TXT {
+                            w.txt(s)?;
+                            answers += 1;
+                        }
+                    }
+                }
+            }
+
+            // Save a round trip to the browsers, with the records they ask for next.
+            if add_services {
+                for s in self.services {
+                    w.srv(s, self.hostname)?;
+                    additionals += 1;
+                    w.txt(s)?;
+                    additionals += 1;
+                }
+            }
+            if add_host {
+                w.a(self.hostname, address)?;
+                additionals += 1;
+            }
+            Ok(())
+        })();
+
+        if answers == 0 {
+            return None;
+        }
+        w.set_counts(answers, additionals);
+        Some((w.pos, unicast))
+    }
+}
+
+/// Whether `name` is the concatenation of `parts` and `local`, ignoring the case.
+fn name_eq(name: &str, parts: &[&str]) -> bool {
+    let mut rest = name;
+    for part in parts {
+        match rest.get(..part.len()) {
+            Some(p) if p.eq_ignore_ascii_case(part) => {}
+            _ => return false,
+        }
+        match rest[part.len()..].strip_prefix('.') {
+            Some(r) => rest = r,
+            None => return false,
+        }
+    }
+    rest.eq_ignore_ascii_case("local")
+}
+
+/// A query ended early, or an answer doesn't fit in the buffer.
+struct Truncated;
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn u8(&mut self) -> Result<u8, Truncated> {
+        let b = *self.buf.get(self.pos).ok_or(Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> Result<u16, Truncated> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), Truncated> {
+        if self.pos + n > self.buf.len() {
+            return Err(Truncated);
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    /// Read a name, following the compression pointers, as dot-separated labels.
+    fn name(&mut self, name: &mut String<255>) -> Result<(), Truncated> {
+        let mut pos = self.pos;
+        let mut end = None;
+        // Bound the pointers followed, against loops.
+        for _ in 0..128 {
+            let len = *self.buf.get(pos).ok_or(Truncated)? as usize;
+            match len {
+                0 => {
+                    self.pos = end.unwrap_or(pos + 1);
+                    return Ok(());
+                }
+                0xc0..=0xff => {
+                    let lo = *self.buf.get(pos + 1).ok_or(Truncated)? as usize;
+                    end.get_or_insert(pos + 2);
+                    pos = (len & 0x3f) << 8 | lo;
+                }
+                1..=0x3f => {
+                    let label = self.buf.get(pos + 1..pos + 1 + len).ok_or(Truncated)?;
+                    let label = core::str::from_utf8(label).map_err(|_| Truncated)?;
+                    if !name.is_empty() {
+                        name.push('.').map_err(|_| Truncated)?;
+                    }
+                    name.push_str(label).map_err(|_| Truncated)?;
+                    pos += 1 + len;
+                }
+                _ => return Err(Truncated),
+            }
+        }
+        Err(Truncated)
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> Result<(), Truncated> {
+        let dst = self.buf.get_mut(self.pos..self.pos + data.len()).ok_or(Truncated)?;
+        dst.copy_from_slice(data);
+        self.pos += data.len();
+        Ok(())
+    }
+
+    fn u16(&mut self, v: u16) -> Result<(), Truncated> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> Result<(), Truncated> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    fn header(&mut self, id: u16, answers: u16, additionals: u16) -> Result<(), Truncated> {
+        self.u16(id)?;
+        self.u16(FLAGS_RESPONSE)?;
+        self.u16(0)?;
+        self.u16(answers)?;
+        self.u16(0)?;
+        self.u16(additionals)
+    }
+
+    fn set_counts(&mut self, answers: u16, additionals: u16) {
+        self.buf[6..8].copy_from_slice(&answers.to_be_bytes());
+        self.buf[10..12].copy_from_slice(&additionals.to_be_bytes());
+    }
+
+    /// Write the name made of `parts` followed by `local`.
+    fn name(&mut self, parts: &[&str]) -> Result<(), Truncated> {
+        for part in parts.iter().chain(&["local"]) {
+            for label in part.split('.') {
+                let len = label.len().min(63);
+                self.bytes(&[len as u8])?;
+                self.bytes(&label.as_bytes()[..len])?;
+            }
+        }
+        self.bytes(&[0])
+    }
+
+    /// Write the start of a record, returning the position of its data length.
+    fn record(&mut self, name: &[&str], rtype: u16, unique: bool) -> Result<usize, Truncated> {
+        self.name(name)?;
+        self.u16(rtype)?;
+        self.u16(if unique { CLASS_IN | CLASS_TOP_BIT } else { CLASS_IN })?;
+        self.u32(TTL)?;
+        let len_pos = self.pos;
+        self.u16(0)?;
+        Ok(len_pos)
+    }
+
+    /// Fill in the data length of the record started with [`record`](Self::record).
+    fn end_record(&mut self, len_pos: usize) {
+        let len = (self.pos - len_pos - 2) as u16;
+        self.buf[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+    }
+
+    /// Run `f`, rolling back what it wrote if it doesn't fit, so only whole records are sent.
+    fn whole(&mut self, f: impl FnOnce(&mut Self) -> Result<(), Truncated>) -> Result<(), Truncated> {
+        let start = self.pos;
+        let res = f(self);
+        if res.is_err() {
+            self.pos = start;
+        }
+        res
+    }
+
+    fn a(&mut self, hostname: &str, address: Ipv4Address) -> Result<(), Truncated> {
+        self.whole(|w| {
+            let len_pos = w.record(&[hostname], TYPE_A, true)?;
+            w.bytes(address.as_bytes())?;
+            w.end_record(len_pos);
+            Ok(())
+        })
+    }
+
+    fn ptr(&mut self, name: &[&str], target: &[&str]) -> Result<(), Truncated> {
+        self.whole(|w| {
+            let len_pos = w.record(name, TYPE_PTR, false)?;
+            w.name(target)?;
+            w.end_record(len_pos);
+            Ok(())
+        })
+    }
+
+    fn srv(&mut self, service: &Service, hostname: &str) -> Result<(), Truncated> {
+        self.whole(|w| {
+            let len_pos = w.record(&[service.instance, service.service], TYPE_SRV, true)?;
+            w.u16(0)?; // priority
+            w.u16(0)?; // weight
+            w.u16(service.port)?;
+            w.name(&[hostname])?;
+            w.end_record(len_pos);
+            Ok(())
+        })
+    }
+
+    fn txt(&mut self, service: &Service) -> Result<(), Truncated> {
+        self.whole(|w| {
+            let len_pos = w.record(&[service.instance, service.service], TYPE_TXT, true)?;
+            if service.txt.is_empty() {
+                // A TXT record can't be empty.
+                w.bytes(&[0])?;
+            }
+            for entry in service.txt {
+                let len = entry.len().min(255);
+                w.bytes(&[len as u8])?;
+                w.bytes(&entry.as_bytes()[..len])?;
+            }
+            w.end_record(len_pos);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,702 @@
+//! MQTT 3.1.1 client.
+//!
+//! [`MqttClient::connect`] opens an MQTT session over a connected [`TcpSocket`]. Messages are
+//! published with QoS 0 or 1, and the messages of the subscriptions are returned by
+//! [`MqttClient::receive`]. MQTT 5 and QoS 2 aren't supported.
+//!
+//! The client is shared by reference between tasks. One of them must call
+//! [`MqttClient::receive`] in a loop: it also processes the acknowledgements the other
+//! methods wait for, and keeps the session alive with pings when nothing else is sent.
+
+use core::cell::Cell;
+use core::ops::Range;
+use core::str;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant};
+
+use crate::tcp::{TcpReader, TcpSocket, TcpWriter};
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// Protocol level of MQTT 3.1.1.
+const PROTOCOL_LEVEL: u8 = 4;
+
+/// Maximum length of the fixed header: the packet type and the remaining length.
+const MAX_FIXED_HEADER_LEN: usize = 5;
+const MAX_REMAINING_LEN: usize = 268_435_455;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QoS {
+    /// The message is delivered at most once, without acknowledgement.
+    AtMostOnce = 0,
+    /// The message is delivered at least once, and acknowledged.
+    AtLeastOnce = 1,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The connection was reset or closed.
+    ConnectionReset,
+    /// The broker refused the connection, with the given return code of its CONNACK.
+    ConnectionRefused(u8),
+    /// The broker didn't answer in time.
+    Timeout,
+    /// The packet doesn't fit in the transmit buffer.
+    PacketTooLarge,
+    /// The broker rejected the subscription.
+    SubscriptionRejected,
+    /// The broker sent an invalid packet.
+    Protocol,
+}
+
+/// Message published by the broker when the client disconnects without a DISCONNECT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+/// MQTT session configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config<'a> {
+    pub client_id: &'a str,
+    /// Maximum time between two packets sent to the broker, after which it considers the
+    /// client dead. Pings are sent when there's nothing else to send. Zero disables it.
+    pub keep_alive: Duration,
+    /// Whether to start a new session, instead of resuming the previous one.
+    pub clean_session: bool,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    pub will: Option<Will<'a>>,
+    /// How long to wait for the acknowledgements of the broker.
+    pub timeout: Duration,
+}
+
+impl<'a> Config<'a> {
+    /// Configuration of a clean session, with a keep-alive of one minute.
+    pub fn new(client_id: &'a str) -> Self {
+        Self {
+            client_id,
+            keep_alive: Duration::from_secs(60),
+            clean_session: true,
+            username: None,
+            password: None,
+            will: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// MQTT client.
+pub struct MqttClient<'a, M: RawMutex> {
+    reader: Mutex<M, Reader<'a>>,
+    writer: Mutex<M, Writer<'a>>,
+    /// Held while waiting for an acknowledgement, so that only one is expected at a time.
+    request: Mutex<M, ()>,
+    ack: Signal<M, Ack>,
+    state: BlockingMutex<M, Cell<State>>,
+    keep_alive: Duration,
+    timeout: Duration,
+    session_present: bool,
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    last_sent: Instant,
+    ping_pending: bool,
+    next_packet_id: u16,
+}
+
+/// Acknowledgement of a packet sent by the client.
+#[derive(Clone, Copy)]
+struct Ack {
+    packet_type: u8,
+    packet_id: u16,
+    /// Return code of a SUBACK.
+    code: u8,
+}
+
+impl<'a, M: RawMutex> MqttClient<'a, M> {
+    /// Open a session over `socket`, which must be connected to the broker.
+    ///
+    /// `rx_buffer` must hold the largest packet received, that is the largest message of the
+    /// subscriptions with its topic: larger ones are discarded. `tx_buffer` must hold the
+    /// largest packet sent.
+    pub async fn connect(
+        socket: &'a mut TcpSocket<'_>,
+        config: &Config<'_>,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, Error> {
+        let (reader, writer) = socket.split();
+        let mut reader = Reader {
+            socket: reader,
+            buf: rx_buffer,
+            len: 0,
+            consumed: 0,
+            discard: 0,
+        };
+        let mut writer = Writer {
+            socket: writer,
+            buf: tx_buffer,
+        };
+
+        let keep_alive = config.keep_alive.as_secs().min(u16::MAX as u64) as u16;
+        writer
+            .send(CONNECT << 4, |e| {
+                let mut flags = 0;
+                if config.username.is_some() {
+                    flags |= 0x80;
+                }
+                if config.password.is_some() {
+                    flags |= 0x40;
+                }
+                if let Some(will) = &config.will {
+                    flags |= 0x04 | (will.qos as u8) << 3;
+                    if will.retain {
+                        flags |= 0x20;
+                    }
+                }
+                if config.clean_session {
+                    flags |= 0x02;
+                }
+
+                e.str("MQTT");
+                e.u8(PROTOCOL_LEVEL);
+                e.u8(flags);
+                e.u16(keep_alive);
+                e.str(config.client_id);
+                if let Some(will) = &config.will {
+                    e.str(will.topic);
+                    e.bytes(will.payload);
+                }
+                if let Some(username) = config.username {
+                    e.str(username);
+                }
+                if let Some(password) = config.password {
+                    e.bytes(password);
+                }
+            })
+            .await?;
+
+        let (header, body) = with_timeout(config.timeout, reader.next())
+            .await
+            .map_err(|_| Error::Timeout)??;
+        let body = &reader.buf[body];
+        if header >> 4 != CONNACK || body.len() != 2 {
+            return Err(Error::Protocol);
+        }
+        if body[1] != 0 {
+            return Err(Error::ConnectionRefused(body[1]));
+        }
+        let session_present = body[0] & 0x01 != 0;
+        debug!("mqtt: connected, session present: {}", session_present);
+
+        Ok(Self {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+            request: Mutex::new(()),
+            ack: Signal::new(),
+            state: BlockingMutex::new(Cell::new(State {
+                last_sent: Instant::now(),
+                ping_pending: false,
+                next_packet_id: 1,
+            })),
+            keep_alive: Duration::from_secs(keep_alive as u64),
+            timeout: config.timeout,
+            session_present,
+        })
+    }
+
+    /// Whether the broker resumed a previous session, with its subscriptions.
+    pub fn session_present(&self) -> bool {
+        self.session_present
+    }
+
+    /// Publish `payload` to `topic`.
+    ///
+    /// With [`QoS::AtLeastOnce`], this waits for the acknowledgement of the broker.
+    pub async fn publish(&self, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Result<(), Error> {
+        let header = PUBLISH << 4 | (qos as u8) << 1 | retain as u8;
+        match qos {
+            QoS::AtMostOnce => {
+                self.send(header, |e| {
+                    e.str(topic);
+                    e.raw(payload);
+                })
+                .await
+            }
+            QoS::AtLeastOnce => {
+                self.request(header, PUBACK, |e, packet_id| {
+                    e.str(topic);
+                    e.u16(packet_id);
+                    e.raw(payload);
+                })
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Subscribe to `topic`, which may contain wildcards, returning the QoS granted by the
+    /// broker.
+    pub async fn subscribe(&self, topic: &str, qos: QoS) -> Result<QoS, Error> {
+        let ack = self
+            .request(SUBSCRIBE << 4 | 0x02, SUBACK, |e, packet_id| {
+                e.u16(packet_id);
+                e.str(topic);
+                e.u8(qos as u8);
+            })
+            .await?;
+        match ack.code {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            0x80 => Err(Error::SubscriptionRejected),
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// Unsubscribe from `topic`.
+    pub async fn unsubscribe(&self, topic: &str) -> Result<(), Error> {
+        self.request(UNSUBSCRIBE << 4 | 0x02, UNSUBACK, |e, packet_id| {
+            e.u16(packet_id);
+            e.str(topic);
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// End the session, so that the will isn't published. The socket should be closed then.
+    pub async fn disconnect(&self) -> Result<(), Error> {
+        self.send(DISCONNECT << 4, |_| {}).await
+    }
+
+    /// Wait for the next message of the subscriptions.
+    ///
+    /// Meanwhile, the acknowledgements of the broker are processed, and pings are sent to
+    /// keep the session alive. [`Error::Timeout`] is returned if the broker doesn't answer them.
+    ///
+    /// The connection can't be used after an error.
+    pub async fn receive(&self) -> Result<Message<'_, 'a, M>, Error> {
+        let mut reader = self.reader.lock().await;
+        loop {
+            let (header, body) = if self.keep_alive.as_ticks() == 0 {
+                reader.next().await?
+            } else {
+                let state = self.state.lock(|s| s.get());
+                let timeout = (state.last_sent + self.keep_alive).saturating_duration_since(Instant::now());
+                match with_timeout(timeout, reader.next()).await {
+                    Ok(r) => r?,
+                    Err(_) if state.ping_pending => return Err(Error::Timeout),
+                    Err(_) => {
+                        self.send(PINGREQ << 4, |_| {}).await?;
+                        self.update_state(|s| s.ping_pending = true);
+                        continue;
+                    }
+                }
+            };
+
+            let data = &reader.buf[body.clone()];
+            match header >> 4 {
+                PUBLISH => {
+                    let qos = match (header >> 1) & 0x03 {
+                        0 => QoS::AtMostOnce,
+                        1 => QoS::AtLeastOnce,
+                        _ => return Err(Error::Protocol),
+                    };
+                    let topic_len = read_u16(data, 0)? as usize;
+                    let topic = body.start + 2..body.start + 2 + topic_len;
+                    let packet_id = match qos {
+                        QoS::AtMostOnce => None,
+                        QoS::AtLeastOnce => Some(read_u16(data, 2 + topic_len)?),
+                    };
+                    let payload_start = topic.end + if packet_id.is_some() { 2 } else { 0 };
+                    if payload_start > body.end || str::from_utf8(&reader.buf[topic.clone()]).is_err() {
+                        return Err(Error::Protocol);
+                    }
+                    if let Some(packet_id) = packet_id {
+                        self.send(PUBACK << 4, |e| e.u16(packet_id)).await?;
+                    }
+                    return Ok(Message {
+                        reader,
+                        topic,
+                        payload: payload_start..body.end,
+                        qos,
+                        retain: header & 0x01 != 0,
+                    });
+                }
+                packet_type @ (PUBACK | UNSUBACK) => self.ack.signal(Ack {
+                    packet_type,
+                    packet_id: read_u16(data, 0)?,
+                    code: 0,
+                }),
+                SUBACK => self.ack.signal(Ack {
+                    packet_type: SUBACK,
+                    packet_id: read_u16(data, 0)?,
+                    code: *data.get(2).ok_or(Error::Protocol)?,
+                }),
+                PINGRESP => self.update_state(|s| s.ping_pending = false),
+                packet_type => {
+                    warn!("mqtt: unexpected packet type {}", packet_type);
+                }
+            }
+        }
+    }
+
+    /// Send a packet, and wait for its acknowledgement, of type `ack_type`.
+    async fn request(&self, header: u8, ack_type: u8, encode: impl FnOnce(&mut Encoder, u16)) -> Result<Ack, Error> {
+        let _request = self.request.lock().await;
+        let mut packet_id = 0;
+        self.update_state(|s| {
+            packet_id = s.next_packet_id;
+            s.next_packet_id = s.next_packet_id.checked_add(1).unwrap_or(1);
+        });
+
+        self.ack.reset();
+        self.send(header, |e| encode(e, packet_id)).await?;
+        with_timeout(self.timeout, async {
+            loop {
+                let ack = self.ack.wait().await;
+                if ack.packet_type == ack_type && ack.packet_id == packet_id {
+                    return ack;
+                }
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)
+    }
+
+    async fn send(&self, header: u8, encode: impl FnOnce(&mut Encoder)) -> Result<(), Error> {
+        self.writer.lock().await.send(header, encode).await?;
+        self.update_state(|s| s.last_sent = Instant::now());
+        Ok(())
+    }
+
+    fn update_state(&self, f: impl FnOnce(&mut State)) {
+        self.state.lock(|s| {
+            let mut state = s.get();
+            f(&mut state);
+            s.set(state);
+        })
+    }
+}
+
+/// Message received from a subscription.
+///
+/// [`MqttClient::receive`] can't be called again until the message is dropped.
+pub struct Message<'c, 'a, M: RawMutex> {
+    reader: MutexGuard<'c, M, Reader<'a>>,
+    topic: Range<usize>,
+    payload: Range<usize>,
+    qos: QoS,
+    retain: bool,
+}
+
+impl<'c, 'a, M: RawMutex> Message<'c, 'a, M> {
+    pub fn topic(&self) -> &str {
+        // The topic was checked to be UTF-8 when received.
+        str::from_utf8(&self.reader.buf[self.topic.clone()]).unwrap()
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.reader.buf[self.payload.clone()]
+    }
+
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+
+    /// Whether the message was retained by the broker, and sent because of a new subscription.
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+}
+
+struct Reader<'a> {
+    socket: TcpReader<'a>,
+    /// Data received in `buf[..len]`, whose `consumed` first bytes were already returned.
+    buf: &'a mut [u8],
+    len: usize,
+    consumed: usize,
+    /// Bytes left of a packet too large for the buffer, which is discarded.
+    discard: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Wait for the next packet, returning its first byte, and the range of its body in the
+    /// buffer. This is cancel-safe.
+    async fn next(&mut self) -> Result<(u8, Range<usize>), Error> {
+        loop {
+            self.buf.copy_within(self.consumed..self.len, 0);
+            self.len -= self.consumed;
+            self.consumed = 0;
+
+            if self.discard > 0 {
+                self.consumed = self.discard.min(self.len);
+                self.discard -= self.consumed;
+            } else if let Some((header_len, body_len)) = parse_fixed_header(&self.buf[..self.len])? {
+                let len = header_len + body_len;
+                if len > self.buf.len() {
+                    warn!("mqtt: discarding packet of {} bytes, larger than the buffer", len);
+                    self.discard = len;
+                    continue;
+                }
+                if self.len >= len {
+                    self.consumed = len;
+                    return Ok((self.buf[0], header_len..len));
+                }
+            }
+            if self.consumed > 0 {
+                continue;
+            }
+
+            let n = self
+                .socket
+                .read(&mut self.buf[self.len..])
+                .await
+                .map_err(|_| Error::ConnectionReset)?;
+            if n == 0 {
+                return Err(Error::ConnectionReset);
+            }
+            self.len += n;
+        }
+    }
+}
+
+struct Writer<'a> {
+    socket: TcpWriter<'a>,
+    buf: &'a mut [u8],
+}
+
+impl<'a> Writer<'a> {
+    /// Send a packet, whose body is written by `encode`.
+    async fn send(&mut self, header: u8, encode: impl FnOnce(&mut Encoder)) -> Result<(), Error> {
+        let body = self.buf.get_mut(MAX_FIXED_HEADER_LEN..).ok_or(Error::PacketTooLarge)?;
+        let mut e = Encoder {
+            buf: body,
+            len: 0,
+            overflow: false,
+        };
+        encode(&mut e);
+        let body_len = e.len;
+        if e.overflow || body_len > MAX_REMAINING_LEN {
+            return Err(Error::PacketTooLarge);
+        }
+
+        // The fixed header is written just before the body.
+        let mut remaining_len = [0; 4];
+        let n = encode_remaining_len(body_len, &mut remaining_len);
+        let start = MAX_FIXED_HEADER_LEN - 1 - n;
+        self.buf[start] = header;
+        self.buf[start + 1..MAX_FIXED_HEADER_LEN].copy_from_slice(&remaining_len[..n]);
+
+        let mut buf = &self.buf[start..MAX_FIXED_HEADER_LEN + body_len];
+        while !buf.is_empty() {
+            let n = self.socket.write(buf).await.map_err(|_| Error::ConnectionReset)?;
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+}
+
+/// Writer of the body of a packet, which records whether it overflowed the buffer.
+struct Encoder<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    overflow: bool,
+}
+
+impl<'b> Encoder<'b> {
+    fn raw(&mut self, data: &[u8]) {
+        match self.buf.get_mut(self.len..self.len + data.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(data);
+                self.len += data.len();
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.raw(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.raw(&value.to_be_bytes());
+    }
+
+    /// Binary data, prefixed with its length.
+    fn bytes(&mut self, data: &[u8]) {
+        match u16::try_from(data.len()) {
+            Ok(len) => {
+                self.u16(len);
+                self.raw(data);
+            }
+            Err(_) => self.overflow = true,
+        }
+    }
+
+    fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+}
+
+/// Parse the fixed header at the start of `buf`, returning its length and the length of the
+/// body, or `None` if it's incomplete.
+fn parse_fixed_header(buf: &[u8]) -> Result<Option<(usize, usize)>, Error> {
+    let mut len = 0;
+    for i in 0..4 {
+        let b = match buf.get(1 + i) {
+            Some(b) => *b,
+            None => return Ok(None),
+        };
+        len |= ((b & 0x7f) as usize) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok(Some((2 + i, len)));
+        }
+    }
+    Err(Error::Protocol)
+}
+
+fn encode_remaining_len(mut len: usize, buf: &mut [u8; 4]) -> usize {
+    let mut n = 0;
+    loop {
+        buf[n] = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            return n + 1;
+        }
+        buf[n] |= 0x80;
+        n += 1;
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, Error> {
+    match data.get(pos..pos + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => Err(Error::Protocol),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_length_limits() {
+        // The largest lengths encoded in 1 to 4 bytes, and the smallest in 2 to 4 bytes.
+        let cases: [(usize, &[u8]); 8] = [
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xff, 0x7f]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (2_097_151, &[0xff, 0xff, 0x7f]),
+            (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+            (MAX_REMAINING_LEN, &[0xff, 0xff, 0xff, 0x7f]),
+        ];
+        for (len, encoded) in cases {
+            let mut buf = [0; 4];
+            let n = encode_remaining_len(len, &mut buf);
+            assert_eq!(&buf[..n], encoded, "length {}", len);
+
+            let mut packet = [PUBLISH << 4; MAX_FIXED_HEADER_LEN];
+            packet[1..1 + n].copy_from_slice(encoded);
+            assert_eq!(parse_fixed_header(&packet[..1 + n]), Ok(Some((1 + n, len))));
+            // The body doesn't need to be received to parse the header.
+            assert_eq!(parse_fixed_header(&packet), Ok(Some((1 + n, len))));
+        }
+    }
+
+    #[test]
+    fn incomplete_fixed_header() {
+        assert_eq!(parse_fixed_header(&[]), Ok(None));
+        assert_eq!(parse_fixed_header(&[PINGRESP << 4]), Ok(None));
+        assert_eq!(parse_fixed_header(&[PUBLISH << 4, 0x80]), Ok(None));
+        assert_eq!(parse_fixed_header(&[PUBLISH << 4, 0xff, 0xff, 0xff]), Ok(None));
+    }
+
+    #[test]
+    fn remaining_length_over_four_bytes() {
+        assert_eq!(
+            parse_fixed_header(&[PUBLISH << 4, 0xff, 0xff, 0xff, 0xff]),
+            Err(Error::Protocol)
+        );
+        assert_eq!(
+            parse_fixed_header(&[PUBLISH << 4, 0x80, 0x80, 0x80, 0x80, 0x00]),
+            Err(Error::Protocol)
+        );
+    }
+
+    #[test]
+    fn encoder() {
+        let mut buf = [0; 16];
+        let mut e = Encoder {
+            buf: &mut buf,
+            len: 0,
+            overflow: false,
+        };
+        e.u8(PROTOCOL_LEVEL);
+        e.u16(60);
+        e.str("MQTT");
+        e.bytes(&[]);
+        assert!(!e.overflow);
+        assert_eq!(e.len, 11);
+        assert_eq!(&buf[..11], &[4, 0, 60, 0, 4, b'M', b'Q', b'T', b'T', 0, 0]);
+    }
+
+    #[test]
+    fn encoder_overflow() {
+        let mut buf = [0; 8];
+        let mut e = Encoder {
+            buf: &mut buf,
+            len: 0,
+            overflow: false,
+        };
+        e.str("topic");
+        assert!(!e.overflow);
+        e.str("a");
+        assert!(e.overflow);
+
+        // Binary data is limited to 65535 bytes by its length prefix.
+        let data = [0; 65_536];
+        let mut buf = [0; 70_000];
+        let mut e = Encoder {
+            buf: &mut buf,
+            len: 0,
+            overflow: false,
+        };
+        e.bytes(&data[..65_535]);
+        assert!(!e.overflow);
+        e.len = 0;
+        e.bytes(&data);
+        assert!(e.overflow);
+        assert_eq!(e.len, 0);
+    }
+
+    #[test]
+    fn truncated_u16() {
+        assert_eq!(read_u16(&[0x12, 0x34], 0), Ok(0x1234));
+        assert_eq!(read_u16(&[0x12, 0x34], 1), Err(Error::Protocol));
+        assert_eq!(read_u16(&[], 0), Err(Error::Protocol));
+        assert_eq!(read_u16(&[0x12], 4), Err(Error::Protocol));
+    }
+}
@@ -0,0 +1,81 @@
+//! Packet capture, in the pcap format read by Wireshark.
+//!
+//! A [`Tap`] set with [`Stack::set_tap`](crate::Stack::set_tap) is handed every frame received
+//! or sent by the device, with its timestamp. It can encode them with [`encode_record`] and
+//! stream the records, after the [`global_header`], over RTT or a UART, to be saved to a file
+//! or piped into Wireshark on the host.
+//!
+//! The tap runs in the stack, so it should only copy the records to a buffer, such as a
+//! `Pipe`, and leave the writing to another task.
+
+use embassy_time::Instant;
+
+use crate::Medium;
+
+/// Length of the pcap global header.
+pub const GLOBAL_HEADER_LEN: usize = 24;
+/// Length of the header of each pcap record.
+pub const RECORD_HEADER_LEN: usize = 16;
+
+const MAGIC: u32 = 0xa1b2c3d4;
+
+#[cfg(feature = "medium-ethernet")]
+const LINKTYPE_ETHERNET: u32 = 1;
+#[cfg(feature = "medium-ip")]
+const LINKTYPE_RAW: u32 = 101;
+#[cfg(feature = "medium-ieee802154")]
+const LINKTYPE_IEEE802_15_4_NOFCS: u32 = 230;
+
+/// Whether a frame was received or sent.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// Callback handed every frame received or sent by the device, with the time it went through
+/// the stack.
+pub type Tap = fn(Direction, Instant, &[u8]);
+
+/// Header of a capture of the frames of `medium`, truncated to `snaplen` bytes.
+pub fn global_header(medium: Medium, snaplen: u32) -> [u8; GLOBAL_HEADER_LEN] {
+    let linktype = match medium {
+        #[cfg(feature = "medium-ethernet")]
+        Medium::Ethernet => LINKTYPE_ETHERNET,
+        #[cfg(feature = "medium-ip")]
+        Medium::Ip => LINKTYPE_RAW,
+        #[cfg(feature = "medium-ieee802154")]
+        Medium::Ieee802154 => LINKTYPE_IEEE802_15_4_NOFCS,
+    };
+
+    let mut header = [0; GLOBAL_HEADER_LEN];
+    header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    // Version 2.4.
+    header[4..6].copy_from_slice(&2u16.to_le_bytes());
+    header[6..8].copy_from_slice(&4u16.to_le_bytes());
+    // The timezone and the accuracy of the timestamps are 0.
+    header[16..20].copy_from_slice(&snaplen.to_le_bytes());
+    header[20..24].copy_from_slice(&linktype.to_le_bytes());
+    header
+}
+
+/// Encode the record of `frame`, captured at `timestamp`, to `buf`, truncating the frame to
+/// the length of `buf`. Returns the length of the record.
+///
+/// The timestamps are relative to the boot of the device, as they are given by
+/// [`Instant`].
+///
+/// # Panics
+///
+/// Panics if `buf` is shorter than [`RECORD_HEADER_LEN`].
+pub fn encode_record(timestamp: Instant, frame: &[u8], buf: &mut [u8]) -> usize {
+    let captured = frame.len().min(buf.len() - RECORD_HEADER_LEN);
+    let micros = timestamp.as_micros();
+    buf[0..4].copy_from_slice(&((micros / 1_000_000) as u32).to_le_bytes());
+    buf[4..8].copy_from_slice(&((micros % 1_000_000) as u32).to_le_bytes());
+    buf[8..12].copy_from_slice(&(captured as u32).to_le_bytes());
+    buf[12..16].copy_from_slice(&(frame.len() as u32).to_le_bytes());
+    buf[RECORD_HEADER_LEN..][..captured].copy_from_slice(&frame[..captured]);
+    RECORD_HEADER_LEN + captured
+}
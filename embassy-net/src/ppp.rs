@@ -0,0 +1,1209 @@
+//! PPP over serial links, for cellular modems in data mode.
+//!
+//! [`new`] splits a [`State`] into a [`PppDevice`], which is given to the [`Stack`], and a
+//! [`Runner`], which runs PPP over a serial port implementing the `embedded-io` traits. The
+//! link is established with LCP, authenticated with PAP or CHAP-MD5 if the peer requires it,
+//! and configured with IPCP. The negotiated address and DNS servers are then applied to the
+//! stack, which should be created with [`ConfigStrategy::None`](crate::ConfigStrategy::None).
+//!
+//! The modem must be switched to data mode beforehand, typically with `ATD*99#`.
+//!
+//! Only IPv4 is supported, without header compression.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io::asynch::{Read, Write};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use heapless::{Deque, Vec};
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+use crate::{
+    ConfigStrategy, Device, DeviceCapabilities, LinkState, Medium, Packet, PacketBox, PacketBoxExt, PacketBuf, Stack,
+};
+
+const FLAG: u8 = 0x7e;
+const ESCAPE: u8 = 0x7d;
+
+const FCS_INIT: u16 = 0xffff;
+/// FCS of a frame followed by its own FCS.
+const FCS_GOOD: u16 = 0xf0b8;
+
+const PROTO_IP: u16 = 0x0021;
+const PROTO_IPCP: u16 = 0x8021;
+const PROTO_LCP: u16 = 0xc021;
+const PROTO_PAP: u16 = 0xc023;
+const PROTO_CHAP: u16 = 0xc223;
+
+// Codes of the LCP and IPCP packets.
+const CONFIGURE_REQUEST: u8 = 1;
+const CONFIGURE_ACK: u8 = 2;
+const CONFIGURE_NAK: u8 = 3;
+const CONFIGURE_REJECT: u8 = 4;
+const TERMINATE_REQUEST: u8 = 5;
+const TERMINATE_ACK: u8 = 6;
+const CODE_REJECT: u8 = 7;
+const PROTOCOL_REJECT: u8 = 8;
+const ECHO_REQUEST: u8 = 9;
+const ECHO_REPLY: u8 = 10;
+const DISCARD_REQUEST: u8 = 11;
+
+// Codes of the PAP and CHAP packets.
+const PAP_REQUEST: u8 = 1;
+const PAP_ACK: u8 = 2;
+const PAP_NAK: u8 = 3;
+const CHAP_CHALLENGE: u8 = 1;
+const CHAP_RESPONSE: u8 = 2;
+const CHAP_SUCCESS: u8 = 3;
+const CHAP_FAILURE: u8 = 4;
+const CHAP_MD5: u8 = 5;
+
+const LCP_MRU: u8 = 1;
+const LCP_ACCM: u8 = 2;
+const LCP_AUTH: u8 = 3;
+const LCP_MAGIC: u8 = 5;
+const LCP_PFC: u8 = 7;
+const LCP_ACFC: u8 = 8;
+
+const IPCP_ADDRESS: u8 = 3;
+const IPCP_DNS: [u8; 2] = [129, 131];
+
+/// Maximum length of the IP packets.
+const MTU: usize = 1500;
+/// Maximum length of a frame: address, control, protocol, IP packet and FCS.
+const MAX_FRAME_LEN: usize = 4 + MTU + 2;
+/// Maximum length of the options of a configuration response.
+const MAX_OPTIONS_LEN: usize = 128;
+
+/// Length of the queues of packets between the stack and the runner.
+const QUEUE_LEN: usize = 4;
+
+const RESTART_INTERVAL: Duration = Duration::from_secs(3);
+/// Number of requests sent without answer before giving up.
+const MAX_REQUESTS: u8 = 10;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The serial port failed.
+    Io(E),
+    /// The serial port reached its end.
+    ConnectionClosed,
+    /// The peer didn't answer the negotiation.
+    Timeout,
+    /// The peer rejected the credentials.
+    AuthenticationFailed,
+    /// The peer terminated the link.
+    Terminated,
+}
+
+/// Credentials sent if the peer requires authentication, of at most 255 bytes each.
+#[derive(Default)]
+pub struct Config<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+}
+
+/// State shared by the [`PppDevice`] and the [`Runner`].
+pub struct State {
+    shared: Mutex<NoopRawMutex, RefCell<Shared>>,
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            shared: Mutex::new(RefCell::new(Shared {
+                link_up: false,
+                rx: Deque::new(),
+                tx: Deque::new(),
+                stack_waker: WakerRegistration::new(),
+                runner_waker: WakerRegistration::new(),
+            })),
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Shared {
+    link_up: bool,
+    rx: Deque<PacketBuf, QUEUE_LEN>,
+    tx: Deque<PacketBuf, QUEUE_LEN>,
+    /// Woken when a packet is received, a packet was sent, or the link changes.
+    stack_waker: WakerRegistration,
+    /// Woken when a packet is queued for sending.
+    runner_waker: WakerRegistration,
+}
+
+/// Create the device and the runner of a PPP link.
+pub fn new(state: &'static State) -> (PppDevice, Runner) {
+    (PppDevice { state }, Runner { state })
+}
+
+/// Device of the stack, up while IPCP is open.
+pub struct PppDevice {
+    state: &'static State,
+}
+
+impl Device for PppDevice {
+    fn is_transmit_ready(&mut self) -> bool {
+        self.state.shared.lock(|s| !s.borrow().tx.is_full())
+    }
+
+    fn transmit(&mut self, pkt: PacketBuf) {
+        self.state.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            if s.tx.push_back(pkt).is_err() {
+                warn!("PPP: TX queue full");
+            }
+            s.runner_waker.wake();
+        })
+    }
+
+    fn receive(&mut self) -> Option<PacketBuf> {
+        self.state.shared.lock(|s| s.borrow_mut().rx.pop_front())
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.state.shared.lock(|s| s.borrow_mut().stack_waker.register(waker))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = MTU;
+        caps.medium = Medium::Ip;
+        caps
+    }
+
+    fn link_state(&mut self) -> LinkState {
+        match self.state.shared.lock(|s| s.borrow().link_up) {
+            true => LinkState::Up,
+            false => LinkState::Down,
+        }
+    }
+
+    fn ethernet_address(&self) -> [u8; 6] {
+        [0; 6]
+    }
+}
+
+/// Runner of a PPP link, exchanging the packets of the [`PppDevice`] over a serial port.
+pub struct Runner {
+    state: &'static State,
+}
+
+impl Runner {
+    /// Run PPP over `port` until the link ends, configuring `stack` while it is up, and return
+    /// why it ended. It can then be called again, once the modem is back in data mode.
+    ///
+    /// The reads of `port` are cancelled when there are packets to send, so they must not lose
+    /// data when cancelled, as is the case of buffered UART drivers.
+    pub async fn run<P: Read + Write, D: Device + 'static>(
+        &mut self,
+        port: P,
+        stack: &Stack<D>,
+        config: &Config<'_>,
+    ) -> Error<P::Error> {
+        let mut link = Link {
+            port,
+            state: self.state,
+            stack,
+            config,
+            phase: Phase::Establish,
+            lcp: Fsm::new(PROTO_LCP),
+            ipcp: Fsm::new(PROTO_IPCP),
+            reject_id: 0,
+            request_accm: true,
+            auth: Auth::None,
+            auth_id: 0,
+            auth_retries: 0,
+            auth_deadline: None,
+            address: [0; 4],
+            dns: [Some([0; 4]); 2],
+        };
+        let mut deframer = Deframer::new();
+        let error = match link.serve(&mut deframer).await {
+            Ok(never) => match never {},
+            Err(error) => error,
+        };
+        if link.ipcp.state == FsmState::Opened {
+            link.layer_down(Layer::Ipcp);
+        }
+        error
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Phase {
+    Establish,
+    Authenticate,
+    Network,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Layer {
+    Lcp,
+    Ipcp,
+}
+
+/// Authentication required by the peer.
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Auth {
+    None,
+    Pap,
+    Chap,
+}
+
+impl Auth {
+    /// Parse the value of the authentication option of LCP.
+    fn parse(value: &[u8]) -> Option<Self> {
+        match value {
+            [0xc0, 0x23] => Some(Auth::Pap),
+            [0xc2, 0x23, CHAP_MD5] => Some(Auth::Chap),
+            _ => None,
+        }
+    }
+}
+
+/// State of the option negotiation of LCP or IPCP (RFC 1661), without the states of
+/// termination, as the link ends when either layer is terminated.
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum FsmState {
+    Closed,
+    RequestSent,
+    AckReceived,
+    AckSent,
+    Opened,
+}
+
+struct Fsm {
+    protocol: u16,
+    state: FsmState,
+    /// Identifier of the last Configure-Request.
+    id: u8,
+    /// Configure-Requests left to send.
+    retries: u8,
+    /// When to send the Configure-Request again.
+    deadline: Option<Instant>,
+}
+
+impl Fsm {
+    fn new(protocol: u16) -> Self {
+        Self {
+            protocol,
+            state: FsmState::Closed,
+            id: 0,
+            retries: 0,
+            deadline: None,
+        }
+    }
+}
+
+/// Verdict on an option of a Configure-Request of the peer, from the best to the worst.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum Verdict {
+    Ack,
+    Nak,
+    Reject,
+}
+
+enum Event {
+    Read(usize),
+    Transmit,
+    Timeout,
+}
+
+struct Link<'l, P, D: Device + 'static> {
+    port: P,
+    state: &'static State,
+    stack: &'l Stack<D>,
+    config: &'l Config<'l>,
+    phase: Phase,
+    lcp: Fsm,
+    ipcp: Fsm,
+    /// Identifier of the last Code-Reject or Protocol-Reject.
+    reject_id: u8,
+    /// Whether to ask the peer not to escape the control characters.
+    request_accm: bool,
+    auth: Auth,
+    /// Identifier of the last PAP request.
+    auth_id: u8,
+    auth_retries: u8,
+    /// When to send the PAP request again, or to give up waiting for the CHAP challenge.
+    auth_deadline: Option<Instant>,
+    /// Address requested with IPCP, assigned by the peer.
+    address: [u8; 4],
+    /// DNS servers requested with IPCP, `None` if the peer rejected them.
+    dns: [Option<[u8; 4]>; 2],
+}
+
+impl<'l, P: Read + Write, D: Device + 'static> Link<'l, P, D> {
+    async fn serve(&mut self, deframer: &mut Deframer) -> Result<Infallible, Error<P::Error>> {
+        self.open(Layer::Lcp).await?;
+
+        let mut chunk = [0; 64];
+        loop {
+            let deadline = [self.lcp.deadline, self.ipcp.deadline, self.auth_deadline]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or(Instant::MAX);
+            let event = {
+                let read = self.port.read(&mut chunk);
+                let shared = &self.state.shared;
+                let transmit = poll_fn(|cx| {
+                    shared.lock(|s| {
+                        let mut s = s.borrow_mut();
+                        if s.tx.is_empty() {
+                            s.runner_waker.register(cx.waker());
+                            Poll::Pending
+                        } else {
+                            Poll::Ready(())
+                        }
+                    })
+                });
+                let timer = Timer::at(deadline);
+                pin_mut!(read, transmit, timer);
+                match select(read, select(transmit, timer)).await {
+                    Either::Left((result, _)) => Event::Read(result.map_err(Error::Io)?),
+                    Either::Right((Either::Left(_), _)) => Event::Transmit,
+                    Either::Right((Either::Right(_), _)) => Event::Timeout,
+                }
+            };
+
+            match event {
+                Event::Read(0) => return Err(Error::ConnectionClosed),
+                Event::Read(n) => {
+                    for &b in &chunk[..n] {
+                        if let Some(frame) = deframer.push(b) {
+                            self.handle_frame(frame).await?;
+                        }
+                    }
+                }
+                Event::Transmit => self.transmit().await?,
+                Event::Timeout => self.handle_timeout().await?,
+            }
+        }
+    }
+
+    fn fsm(&mut self, layer: Layer) -> &mut Fsm {
+        match layer {
+            Layer::Lcp => &mut self.lcp,
+            Layer::Ipcp => &mut self.ipcp,
+        }
+    }
+
+    /// Send the packets queued by the stack.
+    async fn transmit(&mut self) -> Result<(), Error<P::Error>> {
+        loop {
+            let pkt = self.state.shared.lock(|s| {
+                let mut s = s.borrow_mut();
+                // Room is made for the stack.
+                s.stack_waker.wake();
+                s.tx.pop_front()
+            });
+            match pkt {
+                Some(pkt) if self.ipcp.state == FsmState::Opened => self.send_frame(PROTO_IP, &[&pkt[..]]).await?,
+                Some(_) => {}
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Send again the requests left unanswered.
+    async fn handle_timeout(&mut self) -> Result<(), Error<P::Error>> {
+        let now = Instant::now();
+        for layer in [Layer::Lcp, Layer::Ipcp] {
+            let fsm = self.fsm(layer);
+            if fsm.deadline.map_or(false, |d| d <= now) {
+                if fsm.retries == 0 {
+                    return Err(Error::Timeout);
+                }
+                if fsm.state == FsmState::AckReceived {
+                    fsm.state = FsmState::RequestSent;
+                }
+                self.send_configure_request(layer).await?;
+            }
+        }
+
+        if self.auth_deadline.map_or(false, |d| d <= now) {
+            if self.auth_retries == 0 {
+                return Err(Error::Timeout);
+            }
+            if self.auth == Auth::Pap {
+                self.send_pap_request().await?;
+            } else {
+                self.auth_retries -= 1;
+                self.auth_deadline = Some(now + RESTART_INTERVAL);
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_frame(&mut self, frame: &[u8]) -> Result<(), Error<P::Error>> {
+        // The address and control fields, and the first byte of the protocol, may be
+        // compressed.
+        let frame = frame.strip_prefix(&[0xff, 0x03][..]).unwrap_or(frame);
+        let (protocol, payload) = match frame {
+            [protocol, payload @ ..] if protocol & 1 == 1 => (*protocol as u16, payload),
+            [hi, lo, payload @ ..] => (u16::from_be_bytes([*hi, *lo]), payload),
+            _ => return Ok(()),
+        };
+
+        match protocol {
+            PROTO_LCP => self.handle_control(Layer::Lcp, payload).await,
+            PROTO_IPCP if self.phase == Phase::Network => self.handle_control(Layer::Ipcp, payload).await,
+            PROTO_PAP | PROTO_CHAP if self.phase != Phase::Establish => self.handle_auth(protocol, payload).await,
+            PROTO_IP if self.ipcp.state == FsmState::Opened => {
+                self.receive_ip(payload);
+                Ok(())
+            }
+            // Too early, or too late.
+            PROTO_IP | PROTO_IPCP | PROTO_PAP | PROTO_CHAP => Ok(()),
+            _ if self.lcp.state == FsmState::Opened => {
+                self.reject_id = self.reject_id.wrapping_add(1);
+                let len = payload.len().min(MTU - 6);
+                let protocol = protocol.to_be_bytes();
+                let parts = [&protocol[..], &payload[..len]];
+                self.send_control(PROTO_LCP, PROTOCOL_REJECT, self.reject_id, &parts)
+                    .await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn receive_ip(&mut self, packet: &[u8]) {
+        if packet.len() > MTU {
+            return;
+        }
+        let mut pkt = match PacketBox::new(Packet::new()) {
+            Some(pkt) => pkt,
+            None => {
+                warn!("PPP: packet pool exhausted");
+                return;
+            }
+        };
+        pkt[..packet.len()].copy_from_slice(packet);
+        let pkt = pkt.slice(0..packet.len());
+        self.state.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            if s.rx.push_back(pkt).is_err() {
+                warn!("PPP: RX queue full");
+            }
+            s.stack_waker.wake();
+        })
+    }
+
+    /// Handle a packet of LCP or IPCP.
+    async fn handle_control(&mut self, layer: Layer, payload: &[u8]) -> Result<(), Error<P::Error>> {
+        let (code, id, data) = match parse_control(payload) {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+        let fsm = self.fsm(layer);
+        let (protocol, state, request_id) = (fsm.protocol, fsm.state, fsm.id);
+
+        match code {
+            CONFIGURE_REQUEST => self.receive_configure_request(layer, id, data).await?,
+            CONFIGURE_ACK if id == request_id => match state {
+                FsmState::RequestSent => {
+                    let fsm = self.fsm(layer);
+                    fsm.state = FsmState::AckReceived;
+                    fsm.retries = MAX_REQUESTS;
+                }
+                FsmState::AckSent => {
+                    let fsm = self.fsm(layer);
+                    fsm.state = FsmState::Opened;
+                    fsm.deadline = None;
+                    self.layer_up(layer).await?;
+                }
+                FsmState::Opened => {
+                    self.layer_down(layer);
+                    self.fsm(layer).state = FsmState::RequestSent;
+                    self.send_configure_request(layer).await?;
+                }
+                _ => {}
+            },
+            CONFIGURE_NAK | CONFIGURE_REJECT if id == request_id && state != FsmState::Closed => {
+                self.apply_nak(layer, code == CONFIGURE_REJECT, data);
+                match state {
+                    FsmState::Opened => {
+                        self.layer_down(layer);
+                        self.fsm(layer).state = FsmState::RequestSent;
+                    }
+                    FsmState::AckReceived => self.fsm(layer).state = FsmState::RequestSent,
+                    _ => {}
+                }
+                self.send_configure_request(layer).await?;
+            }
+            TERMINATE_REQUEST => {
+                self.send_control(protocol, TERMINATE_ACK, id, &[data]).await?;
+                return Err(Error::Terminated);
+            }
+            ECHO_REQUEST if layer == Layer::Lcp => {
+                if self.lcp.state == FsmState::Opened {
+                    // The magic number is 0, as none was negotiated.
+                    let data = data.get(4..).unwrap_or(&[]);
+                    self.send_control(PROTO_LCP, ECHO_REPLY, id, &[&[0; 4], data]).await?;
+                }
+            }
+            // Identifiers not matching the last request, and codes needing no answer.
+            CONFIGURE_ACK..=CODE_REJECT => {}
+            PROTOCOL_REJECT..=DISCARD_REQUEST if layer == Layer::Lcp => {}
+            _ => {
+                self.reject_id = self.reject_id.wrapping_add(1);
+                let len = payload.len().min(MTU - 4);
+                self.send_control(protocol, CODE_REJECT, self.reject_id, &[&payload[..len]])
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn receive_configure_request(&mut self, layer: Layer, id: u8, data: &[u8]) -> Result<(), Error<P::Error>> {
+        let verdict = options(data)
+            .map(|(kind, value)| check_option(layer, kind, value))
+            .max()
+            .unwrap_or(Verdict::Ack);
+
+        // The response lists the options of the worst verdict, with the values suggested for
+        // the ones that are nak'ed.
+        let mut response: Vec<u8, MAX_OPTIONS_LEN> = Vec::new();
+        for (kind, value) in options(data) {
+            if check_option(layer, kind, value) != verdict {
+                continue;
+            }
+            let res = match verdict {
+                Verdict::Ack => Ok(()),
+                // Only the authentication protocol is nak'ed, PAP being suggested.
+                Verdict::Nak => response.extend_from_slice(&[LCP_AUTH, 4, 0xc0, 0x23]),
+                Verdict::Reject => response
+                    .extend_from_slice(&[kind, value.len() as u8 + 2])
+                    .and_then(|_| response.extend_from_slice(value)),
+            };
+            if res.is_err() {
+                // The peer sends more options than supported: let it fail.
+                return Ok(());
+            }
+        }
+
+        let protocol = self.fsm(layer).protocol;
+        if verdict == Verdict::Ack {
+            if layer == Layer::Lcp {
+                self.auth = options(data)
+                    .find(|(kind, _)| *kind == LCP_AUTH)
+                    .and_then(|(_, value)| Auth::parse(value))
+                    .unwrap_or(Auth::None);
+            }
+            self.send_control(protocol, CONFIGURE_ACK, id, &[data]).await?;
+            match self.fsm(layer).state {
+                FsmState::RequestSent => self.fsm(layer).state = FsmState::AckSent,
+                FsmState::AckReceived => {
+                    let fsm = self.fsm(layer);
+                    fsm.state = FsmState::Opened;
+                    fsm.deadline = None;
+                    self.layer_up(layer).await?;
+                }
+                FsmState::Opened => {
+                    self.layer_down(layer);
+                    self.fsm(layer).state = FsmState::AckSent;
+                    self.send_configure_request(layer).await?;
+                }
+                _ => {}
+            }
+        } else {
+            let code = match verdict {
+                Verdict::Nak => CONFIGURE_NAK,
+                _ => CONFIGURE_REJECT,
+            };
+            self.send_control(protocol, code, id, &[&response]).await?;
+            match self.fsm(layer).state {
+                FsmState::AckSent => self.fsm(layer).state = FsmState::RequestSent,
+                FsmState::Opened => {
+                    self.layer_down(layer);
+                    self.fsm(layer).state = FsmState::RequestSent;
+                    self.send_configure_request(layer).await?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Adapt the next Configure-Request to the options nak'ed or rejected by the peer.
+    fn apply_nak(&mut self, layer: Layer, reject: bool, data: &[u8]) {
+        for (kind, value) in options(data) {
+            match (layer, kind) {
+                (Layer::Lcp, LCP_ACCM) => self.request_accm = false,
+                (Layer::Ipcp, IPCP_ADDRESS) if !reject && value.len() == 4 => self.address.copy_from_slice(value),
+                (Layer::Ipcp, _) => {
+                    if let Some(i) = IPCP_DNS.iter().position(|&k| k == kind) {
+                        self.dns[i] = match value.try_into() {
+                            Ok(addr) if !reject => Some(addr),
+                            _ => None,
+                        };
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn open(&mut self, layer: Layer) -> Result<(), Error<P::Error>> {
+        let fsm = self.fsm(layer);
+        fsm.state = FsmState::RequestSent;
+        fsm.retries = MAX_REQUESTS;
+        self.send_configure_request(layer).await
+    }
+
+    async fn send_configure_request(&mut self, layer: Layer) -> Result<(), Error<P::Error>> {
+        let mut options: Vec<u8, 32> = Vec::new();
+        match layer {
+            Layer::Lcp => {
+                if self.request_accm {
+                    unwrap!(options.extend_from_slice(&[LCP_ACCM, 6, 0, 0, 0, 0]));
+                }
+            }
+            Layer::Ipcp => {
+                unwrap!(options.extend_from_slice(&[IPCP_ADDRESS, 6]));
+                unwrap!(options.extend_from_slice(&self.address));
+                for (kind, addr) in IPCP_DNS.into_iter().zip(self.dns) {
+                    if let Some(addr) = addr {
+                        unwrap!(options.extend_from_slice(&[kind, 6]));
+                        unwrap!(options.extend_from_slice(&addr));
+                    }
+                }
+            }
+        }
+
+        let fsm = self.fsm(layer);
+        fsm.id = fsm.id.wrapping_add(1);
+        fsm.retries = fsm.retries.saturating_sub(1);
+        fsm.deadline = Some(Instant::now() + RESTART_INTERVAL);
+        let (protocol, id) = (fsm.protocol, fsm.id);
+        self.send_control(protocol, CONFIGURE_REQUEST, id, &[&options]).await
+    }
+
+    async fn layer_up(&mut self, layer: Layer) -> Result<(), Error<P::Error>> {
+        match layer {
+            Layer::Lcp => {
+                debug!("PPP: LCP opened");
+                self.auth_retries = MAX_REQUESTS;
+                match self.auth {
+                    Auth::None => return self.start_network().await,
+                    Auth::Pap => {
+                        self.phase = Phase::Authenticate;
+                        self.send_pap_request().await?;
+                    }
+                    Auth::Chap => {
+                        self.phase = Phase::Authenticate;
+                        self.auth_deadline = Some(Instant::now() + RESTART_INTERVAL);
+                    }
+                }
+            }
+            Layer::Ipcp => {
+                debug!("PPP: IPCP opened");
+                let mut dns_servers = Vec::new();
+                for addr in self.dns.into_iter().flatten() {
+                    if addr != [0; 4] {
+                        unwrap!(dns_servers.push(Ipv4Address(addr)));
+                    }
+                }
+                self.stack.set_config_v4(ConfigStrategy::Static(crate::Config {
+                    address: Ipv4Cidr::new(Ipv4Address(self.address), 32),
+                    gateway: None,
+                    dns_servers,
+                }));
+                self.set_link_up(true);
+            }
+        }
+        Ok(())
+    }
+
+    fn layer_down(&mut self, layer: Layer) {
+        match layer {
+            Layer::Lcp => {
+                debug!("PPP: LCP renegotiated");
+                self.phase = Phase::Establish;
+                self.auth_deadline = None;
+                if self.ipcp.state == FsmState::Opened {
+                    self.layer_down(Layer::Ipcp);
+                }
+                self.ipcp = Fsm::new(PROTO_IPCP);
+            }
+            Layer::Ipcp => {
+                self.set_link_up(false);
+                self.stack.set_config_v4(ConfigStrategy::None);
+            }
+        }
+    }
+
+    async fn start_network(&mut self) -> Result<(), Error<P::Error>> {
+        self.phase = Phase::Network;
+        self.auth_deadline = None;
+        self.open(Layer::Ipcp).await
+    }
+
+    fn set_link_up(&mut self, up: bool) {
+        self.state.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.link_up = up;
+            if !up {
+                s.rx.clear();
+                s.tx.clear();
+            }
+            s.stack_waker.wake();
+        })
+    }
+
+    async fn handle_auth(&mut self, protocol: u16, payload: &[u8]) -> Result<(), Error<P::Error>> {
+        let (code, id, data) = match parse_control(payload) {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+        let authenticating = self.phase == Phase::Authenticate;
+        match (protocol, code) {
+            (PROTO_PAP, PAP_ACK) if authenticating && id == self.auth_id => self.start_network().await,
+            (PROTO_PAP, PAP_NAK) if authenticating && id == self.auth_id => Err(Error::AuthenticationFailed),
+            // Challenges may also be sent again during the network phase.
+            (PROTO_CHAP, CHAP_CHALLENGE) => {
+                let challenge = match data.split_first() {
+                    Some((&len, rest)) if rest.len() >= len as usize => &rest[..len as usize],
+                    _ => return Ok(()),
+                };
+                let response = md5(&[&[id], self.config.password.as_bytes(), challenge]);
+                let name = self.config.username.as_bytes();
+                self.send_control(PROTO_CHAP, CHAP_RESPONSE, id, &[&[16], &response, name])
+                    .await
+            }
+            (PROTO_CHAP, CHAP_SUCCESS) if authenticating => self.start_network().await,
+            (PROTO_CHAP, CHAP_FAILURE) => Err(Error::AuthenticationFailed),
+            _ => Ok(()),
+        }
+    }
+
+    async fn send_pap_request(&mut self) -> Result<(), Error<P::Error>> {
+        self.auth_id = self.auth_id.wrapping_add(1);
+        self.auth_retries -= 1;
+        self.auth_deadline = Some(Instant::now() + RESTART_INTERVAL);
+        let username = truncate(self.config.username.as_bytes());
+        let password = truncate(self.config.password.as_bytes());
+        let (username_len, password_len) = ([username.len() as u8], [password.len() as u8]);
+        let parts = [&username_len[..], username, &password_len, password];
+        self.send_control(PROTO_PAP, PAP_REQUEST, self.auth_id, &parts).await
+    }
+
+    /// Send a packet of LCP, IPCP, PAP or CHAP, whose data is the concatenation of `parts`.
+    async fn send_control(&mut self, protocol: u16, code: u8, id: u8, parts: &[&[u8]]) -> Result<(), Error<P::Error>> {
+        let len = 4 + parts.iter().map(|p| p.len()).sum::<usize>();
+        let [hi, lo] = (len as u16).to_be_bytes();
+        let header = [code, id, hi, lo];
+        let mut frame_parts: Vec<&[u8], 5> = Vec::new();
+        unwrap!(frame_parts.push(&header));
+        for part in parts {
+            unwrap!(frame_parts.push(*part));
+        }
+        self.send_frame(protocol, &frame_parts).await
+    }
+
+    /// Send a frame, whose information field is the concatenation of `parts`.
+    async fn send_frame(&mut self, protocol: u16, parts: &[&[u8]]) -> Result<(), Error<P::Error>> {
+        let header = [0xff, 0x03, (protocol >> 8) as u8, protocol as u8];
+        let mut fcs = fcs16(FCS_INIT, &header);
+        for part in parts {
+            fcs = fcs16(fcs, part);
+        }
+        let fcs = (!fcs).to_le_bytes();
+
+        let mut buf = [0; 64];
+        buf[0] = FLAG;
+        let mut len = 1;
+        let bytes = header.iter().chain(parts.iter().flat_map(|p| p.iter())).chain(&fcs);
+        for &b in bytes {
+            len += escape(b, &mut buf[len..]);
+            if len >= buf.len() - 2 {
+                write_all(&mut self.port, &buf[..len]).await?;
+                len = 0;
+            }
+        }
+        buf[len] = FLAG;
+        write_all(&mut self.port, &buf[..len + 1]).await?;
+        self.port.flush().await.map_err(Error::Io)
+    }
+}
+
+/// Verdict on an option of a Configure-Request of the peer.
+fn check_option(layer: Layer, kind: u8, value: &[u8]) -> Verdict {
+    match (layer, kind, value.len()) {
+        // The maximum receive unit of the peer is ignored, modems accepting 1500 bytes.
+        (Layer::Lcp, LCP_MRU, 2) => Verdict::Ack,
+        // The control characters are always escaped.
+        (Layer::Lcp, LCP_ACCM, 4) => Verdict::Ack,
+        (Layer::Lcp, LCP_MAGIC, 4) => Verdict::Ack,
+        // The frames sent aren't compressed, which is allowed.
+        (Layer::Lcp, LCP_PFC | LCP_ACFC, 0) => Verdict::Ack,
+        (Layer::Lcp, LCP_AUTH, _) => match Auth::parse(value) {
+            Some(_) => Verdict::Ack,
+            None => Verdict::Nak,
+        },
+        (Layer::Ipcp, IPCP_ADDRESS, 4) if value != [0; 4] => Verdict::Ack,
+        _ => Verdict::Reject,
+    }
+}
+
+/// Parse a packet of LCP, IPCP, PAP or CHAP into its code, identifier and data.
+fn parse_control(payload: &[u8]) -> Option<(u8, u8, &[u8])> {
+    let header = payload.get(..4)?;
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if len < 4 || len > payload.len() {
+        return None;
+    }
+    Some((header[0], header[1], &payload[4..len]))
+}
+
+/// Iterate over the kinds and values of configuration options, stopping at a malformed one.
+fn options(mut data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    core::iter::from_fn(move || {
+        let (kind, len) = (*data.first()?, *data.get(1)? as usize);
+        if len < 2 || len > data.len() {
+            return None;
+        }
+        let (option, rest) = data.split_at(len);
+        data = rest;
+        Some((kind, &option[2..]))
+    })
+}
+
+fn truncate(s: &[u8]) -> &[u8] {
+    &s[..s.len().min(255)]
+}
+
+async fn write_all<P: Write>(port: &mut P, mut buf: &[u8]) -> Result<(), Error<P::Error>> {
+    while !buf.is_empty() {
+        match port.write(buf).await.map_err(Error::Io)? {
+            0 => return Err(Error::ConnectionClosed),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Write `b` to the start of `buf`, escaped if needed, returning the number of bytes written.
+fn escape(b: u8, buf: &mut [u8]) -> usize {
+    // All the control characters are escaped, whatever the map negotiated by the peer.
+    if b < 0x20 || b == FLAG || b == ESCAPE {
+        buf[0] = ESCAPE;
+        buf[1] = b ^ 0x20;
+        2
+    } else {
+        buf[0] = b;
+        1
+    }
+}
+
+/// Decoder of the frames received, delimited by flags, with escaped bytes.
+struct Deframer {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+    escaped: bool,
+    /// Whether the frame being received is too long, and is dropped.
+    overflow: bool,
+}
+
+impl Deframer {
+    fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+            escaped: false,
+            overflow: false,
+        }
+    }
+
+    /// Decode the byte `b`, returning the frame it ends if it is valid, without its FCS.
+    fn push(&mut self, b: u8) -> Option<&[u8]> {
+        match b {
+            FLAG => {
+                let len = core::mem::replace(&mut self.len, 0);
+                let overflow = core::mem::replace(&mut self.overflow, false);
+                let escaped = core::mem::replace(&mut self.escaped, false);
+                let valid = !overflow && !escaped && len >= 4 && fcs16(FCS_INIT, &self.buf[..len]) == FCS_GOOD;
+                valid.then(|| &self.buf[..len - 2])
+            }
+            ESCAPE => {
+                self.escaped = true;
+                None
+            }
+            b => {
+                let b = if core::mem::replace(&mut self.escaped, false) {
+                    b ^ 0x20
+                } else {
+                    b
+                };
+                match self.buf.get_mut(self.len) {
+                    Some(dst) => {
+                        *dst = b;
+                        self.len += 1;
+                    }
+                    None => self.overflow = true,
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Update the 16-bit FCS of a frame (RFC 1662) with `data`.
+fn fcs16(mut fcs: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        fcs ^= b as u16;
+        for _ in 0..8 {
+            fcs = if fcs & 1 != 0 { (fcs >> 1) ^ 0x8408 } else { fcs >> 1 };
+        }
+    }
+    fcs
+}
+
+/// MD5 digest of the concatenation of `parts`, which CHAP needs.
+fn md5(parts: &[&[u8]]) -> [u8; 16] {
+    let mut h: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    let mut block = [0; 64];
+    let mut n = 0;
+    let mut len = 0u64;
+    for &b in parts.iter().flat_map(|p| p.iter()) {
+        block[n] = b;
+        n += 1;
+        len += 1;
+        if n == 64 {
+            md5_block(&mut h, &block);
+            n = 0;
+        }
+    }
+
+    // Padding: a one bit, zeros, and the length in bits.
+    block[n] = 0x80;
+    block[n + 1..].fill(0);
+    if n >= 56 {
+        md5_block(&mut h, &block);
+        block = [0; 64];
+    }
+    block[56..].copy_from_slice(&(len * 8).to_le_bytes());
+    md5_block(&mut h, &block);
+
+    let mut digest = [0; 16];
+    for (dst, word) in digest.chunks_exact_mut(4).zip(h) {
+        dst.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+/// Per-round shift amounts of MD5.
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// Integer parts of the sines of 1 to 64, scaled by 2^32.
+const MD5_SINES: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501, 0x698098d8,
+    0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821, 0xf61e2562, 0xc040b340,
+    0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8, 0x21e1cde6, 0xc33707d6, 0xf4d50d87,
+    0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a, 0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c,
+    0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70, 0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039,
+    0xe6db99e5, 0x1fa27cf8, 0xc4ac5665, 0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92,
+    0xffeff47d, 0x85845dd1, 0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb,
+    0xeb86d391,
+];
+
+fn md5_block(h: &mut [u32; 4], block: &[u8; 64]) {
+    let mut m = [0; 16];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        m[i] = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+    }
+
+    let [mut a, mut b, mut c, mut d] = *h;
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+        let f = f.wrapping_add(a).wrapping_add(MD5_SINES[i]).wrapping_add(m[g]);
+        a = d;
+        d = c;
+        c = b;
+        b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[i / 16 * 4 + i % 4]));
+    }
+
+    for (h, v) in h.iter_mut().zip([a, b, c, d]) {
+        *h = h.wrapping_add(v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode a frame of `protocol` with `payload`, as `Link::send_frame` does.
+    fn encode(protocol: u16, payload: &[u8]) -> Vec<u8, 256> {
+        let header = [0xff, 0x03, (protocol >> 8) as u8, protocol as u8];
+        let fcs = !fcs16(fcs16(FCS_INIT, &header), payload);
+        let mut frame = Vec::new();
+        frame.push(FLAG).unwrap();
+        for &b in header.iter().chain(payload).chain(&fcs.to_le_bytes()) {
+            let mut buf = [0; 2];
+            let n = escape(b, &mut buf);
+            frame.extend_from_slice(&buf[..n]).unwrap();
+        }
+        frame.push(FLAG).unwrap();
+        frame
+    }
+
+    /// Push `data` to `deframer`, returning the last frame decoded.
+    fn deframe(deframer: &mut Deframer, data: &[u8]) -> Option<Vec<u8, MAX_FRAME_LEN>> {
+        let mut frame = None;
+        for &b in data {
+            if let Some(f) = deframer.push(b) {
+                frame = Some(Vec::from_slice(f).unwrap());
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn fcs() {
+        // Check value of the CRC-16/X-25 used by the FCS.
+        assert_eq!(!fcs16(FCS_INIT, b"123456789"), 0x906e);
+        // The FCS is the same whatever the data is split in.
+        assert_eq!(fcs16(fcs16(FCS_INIT, b"1234"), b"56789"), fcs16(FCS_INIT, b"123456789"));
+    }
+
+    #[test]
+    fn escaping() {
+        let escaped = |b| {
+            let mut buf = [0; 2];
+            let n = escape(b, &mut buf);
+            (n, buf)
+        };
+        assert_eq!(escaped(0x00), (2, [ESCAPE, 0x20]));
+        assert_eq!(escaped(0x1f), (2, [ESCAPE, 0x3f]));
+        assert_eq!(escaped(FLAG), (2, [ESCAPE, 0x5e]));
+        assert_eq!(escaped(ESCAPE), (2, [ESCAPE, 0x5d]));
+        assert_eq!(escaped(0x20), (1, [0x20, 0]));
+        assert_eq!(escaped(0xff), (1, [0xff, 0]));
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let payload = [0x01, 0x02, 0x00, 0x0a, FLAG, ESCAPE, 0x11, 0x13, 0x20, 0xff];
+        let frame = encode(PROTO_LCP, &payload);
+        assert!(!frame[1..frame.len() - 1].contains(&FLAG));
+        assert!(frame[1..frame.len() - 1].iter().all(|&b| b >= 0x20));
+
+        let mut deframer = Deframer::new();
+        let decoded = deframe(&mut deframer, &frame).unwrap();
+        assert_eq!(&decoded[..4], &[0xff, 0x03, 0xc0, 0x21]);
+        assert_eq!(&decoded[4..], &payload);
+
+        // Frames can share their flags.
+        let decoded = deframe(&mut deframer, &frame[1..]).unwrap();
+        assert_eq!(&decoded[4..], &payload);
+    }
+
+    #[test]
+    fn invalid_frames_are_dropped() {
+        let frame = encode(PROTO_IP, &[0x45, 0x00, 0x7e, 0x7d]);
+        let mut deframer = Deframer::new();
+
+        // Truncated frames, ended by the flag of the next one.
+        for cut in 1..frame.len() - 1 {
+            assert_eq!(deframe(&mut deframer, &frame[..cut]), None);
+            assert_eq!(deframe(&mut deframer, &[FLAG]), None, "cut at {}", cut);
+        }
+
+        // A corrupted byte.
+        let mut corrupted = frame.clone();
+        corrupted[6] ^= 0x01;
+        assert_eq!(deframe(&mut deframer, &corrupted), None);
+
+        // A frame aborted by an escape before the flag.
+        let mut aborted = frame.clone();
+        let len = aborted.len();
+        aborted[len - 1] = ESCAPE;
+        assert_eq!(deframe(&mut deframer, &aborted), None);
+        assert_eq!(deframe(&mut deframer, &[FLAG]), None);
+
+        // Empty frames between flags, and frames shorter than the FCS.
+        assert_eq!(deframe(&mut deframer, &[FLAG, FLAG, 0xff, FLAG]), None);
+
+        // The next frame is still decoded.
+        assert!(deframe(&mut deframer, &frame).is_some());
+    }
+
+    #[test]
+    fn long_frames_are_dropped() {
+        let mut deframer = Deframer::new();
+        assert_eq!(deframe(&mut deframer, &[0x55; MAX_FRAME_LEN + 1]), None);
+        assert_eq!(deframe(&mut deframer, &[FLAG]), None);
+
+        let frame = encode(PROTO_IP, &[0x45]);
+        assert!(deframe(&mut deframer, &frame).is_some());
+    }
+
+    #[test]
+    fn truncated_control_packets() {
+        assert_eq!(
+            parse_control(&[CONFIGURE_REQUEST, 7, 0, 4]),
+            Some((CONFIGURE_REQUEST, 7, &[][..]))
+        );
+        // The padding after the length is ignored.
+        assert_eq!(
+            parse_control(&[ECHO_REQUEST, 1, 0, 5, 0xaa, 0xbb]),
+            Some((ECHO_REQUEST, 1, &[0xaa][..]))
+        );
+        assert_eq!(parse_control(&[ECHO_REQUEST, 1, 0]), None);
+        assert_eq!(parse_control(&[ECHO_REQUEST, 1, 0, 3]), None);
+        assert_eq!(parse_control(&[ECHO_REQUEST, 1, 0, 6, 0xaa]), None);
+    }
+
+    #[test]
+    fn truncated_options() {
+        let data = [LCP_MRU, 4, 0x05, 0xdc, LCP_PFC, 2, LCP_MAGIC, 6, 1, 2];
+        let mut it = options(&data);
+        assert_eq!(it.next(), Some((LCP_MRU, &[0x05, 0xdc][..])));
+        assert_eq!(it.next(), Some((LCP_PFC, &[][..])));
+        // The magic number is cut.
+        assert_eq!(it.next(), None);
+
+        assert_eq!(options(&[LCP_MRU]).next(), None);
+        assert_eq!(options(&[LCP_MRU, 1, 0]).next(), None);
+    }
+
+    #[test]
+    fn md5_digests() {
+        // Test suite of RFC 1321, with messages of 0, 3 and 80 bytes.
+        assert_eq!(
+            md5(&[]),
+            [0xd4, 0x1d, 0x8c, 0xd9, 0x8f, 0x00, 0xb2, 0x04, 0xe9, 0x80, 0x09, 0x98, 0xec, 0xf8, 0x42, 0x7e]
+        );
+        assert_eq!(
+            md5(&[b"a", b"bc"]),
+            [0x90, 0x01, 0x50, 0x98, 0x3c, 0xd2, 0x4f, 0xb0, 0xd6, 0x96, 0x3f, 0x7d, 0x28, 0xe1, 0x7f, 0x72]
+        );
+        let digits: &[u8] = b"1234567890";
+        assert_eq!(
+            md5(&[digits; 8]),
+            [0x57, 0xed, 0xf4, 0xa2, 0x2b, 0xe3, 0xc9, 0x55, 0xac, 0x49, 0xda, 0x2e, 0x21, 0x07, 0xb6, 0x7a]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Raw IP sockets, for protocols not implemented by the stack.
+//!
+//! A [`RawSocket`] receives a copy of the IP packets of one protocol, headers included, and
+//! sends packets written with their IP header. Ethernet frames aren't accessible.
+
+use core::cell::UnsafeCell;
+use core::future::poll_fn;
+use core::mem;
+use core::task::Poll;
+
+use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::socket::raw;
+pub use smoltcp::socket::raw::PacketMetadata;
+pub use smoltcp::wire::{IpProtocol, IpVersion};
+
+use super::stack::SocketStack;
+use crate::{Device, Stack};
+
+pub struct RawSocket<'a> {
+    stack: &'a UnsafeCell<SocketStack>,
+    handle: SocketHandle,
+}
+
+impl<'a> RawSocket<'a> {
+    /// Create a socket for the packets of `ip_protocol` over `ip_version`.
+    pub fn new<D: Device>(
+        stack: &'a Stack<D>,
+        ip_version: IpVersion,
+        ip_protocol: IpProtocol,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        // safety: not accessed reentrantly.
+        let s = unsafe { &mut *stack.socket.get() };
+
+        let rx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(rx_meta) };
+        let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
+        let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
+        let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
+        let handle = s.sockets.add(raw::Socket::new(
+            ip_version,
+            ip_protocol,
+            raw::PacketBuffer::new(rx_meta, rx_buffer),
+            raw::PacketBuffer::new(tx_meta, tx_buffer),
+        ));
+
+        Self {
+            stack: &stack.socket,
+            handle,
+        }
+    }
+
+    /// SAFETY: must not call reentrantly.
+    unsafe fn with<R>(&self, f: impl FnOnce(&raw::Socket, &Interface) -> R) -> R {
+        let s = &*self.stack.get();
+        let socket = s.sockets.get::<raw::Socket>(self.handle);
+        f(socket, &s.iface)
+    }
+
+    /// SAFETY: must not call reentrantly.
+    unsafe fn with_mut<R>(&self, f: impl FnOnce(&mut raw::Socket, &mut Interface) -> R) -> R {
+        let s = &mut *self.stack.get();
+        let socket = s.sockets.get_mut::<raw::Socket>(self.handle);
+        let res = f(socket, &mut s.iface);
+        s.waker.wake();
+        res
+    }
+
+    /// Receive a packet, including its IP header, returning its length.
+    ///
+    /// The packet is truncated if it doesn't fit in `buf`.
+    pub async fn recv(&self, buf: &mut [u8]) -> usize {
+        poll_fn(move |cx| unsafe {
+            self.with_mut(|s, _| match s.recv_slice(buf) {
+                Ok(n) => Poll::Ready(n),
+                // No data ready
+                Err(raw::RecvError::Exhausted) => {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Send a packet, including its IP header.
+    ///
+    /// The checksum of the IPv4 header is filled in. Invalid packets, or packets of another
+    /// version or protocol than the socket's, are dropped. The packet must fit in the transmit
+    /// buffer.
+    pub async fn send(&self, buf: &[u8]) {
+        poll_fn(move |cx| unsafe {
+            self.with_mut(|s, _| match s.send_slice(buf) {
+                Ok(()) => Poll::Ready(()),
+                Err(raw::SendError::BufferFull) => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    pub fn ip_version(&self) -> IpVersion {
+        unsafe { self.with(|s, _| s.ip_version()) }
+    }
+
+    pub fn ip_protocol(&self) -> IpProtocol {
+        unsafe { self.with(|s, _| s.ip_protocol()) }
+    }
+
+    pub fn may_send(&self) -> bool {
+        unsafe { self.with(|s, _| s.can_send()) }
+    }
+
+    pub fn may_recv(&self) -> bool {
+        unsafe { self.with(|s, _| s.can_recv()) }
+    }
+}
+
+impl Drop for RawSocket<'_> {
+    fn drop(&mut self) {
+        // safety: not accessed reentrantly.
+        let s = unsafe { &mut *self.stack.get() };
+        s.sockets.remove(self.handle);
+    }
+}
@@ -0,0 +1,215 @@
+//! Route selection between several stacks, for devices with multiple network interfaces.
+//!
+//! Each interface, such as Ethernet and WiFi or USB-ECM and cellular, runs its own [`Stack`],
+//! with its own addressing. A [`Router`] chooses the stack to open the sockets of a destination
+//! on, from, in order:
+//!
+//! 1. the static routes added with [`Router::add_route`], the longest prefix first,
+//! 2. the interfaces whose subnet contains the destination,
+//! 3. the first interface, in the order given to [`Router::new`], with a default route: a
+//!    gateway, or a point-to-point link such as [PPP](crate::ppp).
+//!
+//! Only the interfaces whose link and configuration are up are chosen, so an interface listed
+//! later is a fallback of the ones before it.
+//!
+//! Gateway-style devices can then bridge connections between interfaces, accepting them on one
+//! stack and connecting to the destination on the stack chosen by the router, and copying the
+//! data both ways with [`splice`].
+
+use heapless::Vec;
+use smoltcp::wire::{IpAddress, IpCidr};
+
+use crate::{Device, Stack};
+
+/// Errors when adding a route.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The table of static routes is full.
+    TableFull,
+    /// The interface isn't one of the router.
+    InvalidInterface,
+}
+
+/// Network interface a [`Router`] chooses from, implemented by [`Stack`].
+pub trait Interface {
+    /// Whether the interface can be chosen, its link and configuration being up.
+    fn is_up(&self) -> bool;
+
+    /// Whether `addr` is in the subnet of the interface.
+    fn is_on_link(&self, addr: IpAddress) -> bool;
+
+    /// Whether the interface has a default route for the protocol of `addr`.
+    fn has_default_route(&self, addr: IpAddress) -> bool;
+
+    /// Address of the interface for the protocol of `addr`, the source of the packets sent to it.
+    fn source_address(&self, addr: IpAddress) -> Option<IpAddress>;
+}
+
+impl<D: Device + 'static> Interface for Stack<D> {
+    fn is_up(&self) -> bool {
+        #[cfg(feature = "proto-ipv6")]
+        let config_up = self.is_config_up() || self.config_v6().is_some();
+        #[cfg(not(feature = "proto-ipv6"))]
+        let config_up = self.is_config_up();
+        self.is_link_up() && config_up
+    }
+
+    fn is_on_link(&self, addr: IpAddress) -> bool {
+        match addr {
+            IpAddress::Ipv4(addr) => self.config().map_or(false, |c| c.address.contains_addr(&addr)),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(addr) => self.config_v6().map_or(false, |c| c.address.contains_addr(&addr)),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    fn has_default_route(&self, addr: IpAddress) -> bool {
+        // Point-to-point links reach every address through the peer.
+        #[cfg(feature = "medium-ip")]
+        let point_to_point = self.medium() == crate::Medium::Ip;
+        #[cfg(not(feature = "medium-ip"))]
+        let point_to_point = false;
+
+        match addr {
+            IpAddress::Ipv4(_) => self.config().map_or(false, |c| point_to_point || c.gateway.is_some()),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(_) => self
+                .config_v6()
+                .map_or(false, |c| point_to_point || c.gateway.is_some()),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    fn source_address(&self, addr: IpAddress) -> Option<IpAddress> {
+        match addr {
+            IpAddress::Ipv4(_) => self.config().map(|c| c.address.address().into()),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(_) => self.config_v6().map(|c| c.address.address().into()),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+/// Static route, sending the packets to `cidr` through an interface of a [`Router`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Route {
+    pub cidr: IpCidr,
+    /// Index of the interface, in the order given to [`Router::new`].
+    pub interface: usize,
+}
+
+/// Chooses the interface to reach a destination through, from `N` interfaces and at most
+/// `ROUTES` static routes.
+pub struct Router<'a, const N: usize, const ROUTES: usize = 4> {
+    interfaces: [&'a dyn Interface; N],
+    routes: Vec<Route, ROUTES>,
+}
+
+impl<'a, const N: usize, const ROUTES: usize> Router<'a, N, ROUTES> {
+    /// Create a router over `interfaces`, from the most preferred to the least preferred.
+    pub fn new(interfaces: [&'a dyn Interface; N]) -> Self {
+        Self {
+            interfaces,
+            routes: Vec::new(),
+        }
+    }
+
+    /// Route the packets to `cidr` through the interface of index `interface`, replacing the
+    /// route to the same subnet if any.
+    pub fn add_route(&mut self, cidr: IpCidr, interface: usize) -> Result<(), Error> {
+        if interface >= N {
+            return Err(Error::InvalidInterface);
+        }
+        let route = Route { cidr, interface };
+        match self.routes.iter_mut().find(|r| r.cidr == cidr) {
+            Some(r) => *r = route,
+            None => self.routes.push(route).map_err(|_| Error::TableFull)?,
+        }
+        Ok(())
+    }
+
+    /// Remove the route to `cidr`, returning it if there was one.
+    pub fn remove_route(&mut self, cidr: IpCidr) -> Option<Route> {
+        let i = self.routes.iter().position(|r| r.cidr == cidr)?;
+        Some(self.routes.swap_remove(i))
+    }
+
+    /// The static routes.
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Index of the interface to reach `addr` through, `None` if no interface that is up
+    /// reaches it.
+    pub fn route(&self, addr: IpAddress) -> Option<usize> {
+        let is_up = |i: usize| self.interfaces[i].is_up();
+
+        let route = self
+            .routes
+            .iter()
+            .filter(|r| r.cidr.contains_addr(&addr) && is_up(r.interface))
+            .max_by_key(|r| r.cidr.prefix_len());
+        if let Some(route) = route {
+            return Some(route.interface);
+        }
+
+        (0..N)
+            .find(|&i| is_up(i) && self.interfaces[i].is_on_link(addr))
+            .or_else(|| (0..N).find(|&i| is_up(i) && self.interfaces[i].has_default_route(addr)))
+    }
+
+    /// Index of the interface to reach `addr` through, with the address of the interface the
+    /// packets are sent from.
+    pub fn route_with_source(&self, addr: IpAddress) -> Option<(usize, IpAddress)> {
+        let i = self.route(addr)?;
+        Some((i, self.interfaces[i].source_address(addr)?))
+    }
+}
+
+/// Copy the data received by each socket to the other, until either reaches the end of its
+/// data or fails. The sockets should then be closed.
+///
+/// `buf_a` holds the data from `a` to `b`, and `buf_b` the data from `b` to `a`.
+#[cfg(feature = "tcp")]
+pub async fn splice(
+    a: &mut crate::tcp::TcpSocket<'_>,
+    b: &mut crate::tcp::TcpSocket<'_>,
+    buf_a: &mut [u8],
+    buf_b: &mut [u8],
+) -> Result<(), crate::tcp::Error> {
+    use futures::future::{select, Either};
+    use futures::pin_mut;
+
+    let (mut a_reader, mut a_writer) = a.split();
+    let (mut b_reader, mut b_writer) = b.split();
+    let a_to_b = copy(&mut a_reader, &mut b_writer, buf_a);
+    let b_to_a = copy(&mut b_reader, &mut a_writer, buf_b);
+    pin_mut!(a_to_b, b_to_a);
+    match select(a_to_b, b_to_a).await {
+        Either::Left((result, _)) | Either::Right((result, _)) => result,
+    }
+}
+
+#[cfg(feature = "tcp")]
+async fn copy(
+    reader: &mut crate::tcp::TcpReader<'_>,
+    writer: &mut crate::tcp::TcpWriter<'_>,
+    buf: &mut [u8],
+) -> Result<(), crate::tcp::Error> {
+    loop {
+        let n = reader.read(buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        let mut data = &buf[..n];
+        while !data.is_empty() {
+            let n = writer.write(data).await?;
+            data = &data[n..];
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! SLIP (RFC 1055) over serial links, for point-to-point links such as tethering to a host PC.
+//!
+//! [`new`] splits a [`State`] into a [`SlipDevice`], which is given to the [`Stack`](crate::Stack),
+//! and a [`Runner`], which exchanges the packets over a serial port implementing the
+//! `embedded-io` traits. SLIP doesn't negotiate addresses, so the stack is configured statically.
+//!
+//! On a Linux host, the other end of the link can be set up with:
+//!
+//! ```text
+//! sudo slattach -L -s 115200 -p slip /dev/ttyUSB0 &
+//! sudo ip link set sl0 mtu 1500 up
+//! sudo ip addr add 192.168.7.1 peer 192.168.7.2 dev sl0
+//! ```
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+use embedded_io::asynch::{Read, Write};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use heapless::Deque;
+
+use crate::{Device, DeviceCapabilities, LinkState, Medium, Packet, PacketBox, PacketBoxExt, PacketBuf};
+
+const END: u8 = 0xc0;
+const ESC: u8 = 0xdb;
+const ESC_END: u8 = 0xdc;
+const ESC_ESC: u8 = 0xdd;
+
+/// Maximum length of the IP packets, which must match the MTU of the peer.
+const MTU: usize = 1500;
+
+/// Length of the queues of packets between the stack and the runner.
+const QUEUE_LEN: usize = 4;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The serial port failed.
+    Io(E),
+    /// The serial port reached its end.
+    ConnectionClosed,
+}
+
+/// State shared by the [`SlipDevice`] and the [`Runner`].
+pub struct State {
+    shared: Mutex<NoopRawMutex, RefCell<Shared>>,
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            shared: Mutex::new(RefCell::new(Shared {
+                link_up: false,
+                rx: Deque::new(),
+                tx: Deque::new(),
+                stack_waker: WakerRegistration::new(),
+                runner_waker: WakerRegistration::new(),
+            })),
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Shared {
+    link_up: bool,
+    rx: Deque<PacketBuf, QUEUE_LEN>,
+    tx: Deque<PacketBuf, QUEUE_LEN>,
+    /// Woken when a packet is received, a packet was sent, or the link changes.
+    stack_waker: WakerRegistration,
+    /// Woken when a packet is queued for sending.
+    runner_waker: WakerRegistration,
+}
+
+/// Create the device and the runner of a SLIP link.
+pub fn new(state: &'static State) -> (SlipDevice, Runner) {
+    (SlipDevice { state }, Runner { state })
+}
+
+/// Device of the stack, up while the [`Runner`] runs.
+pub struct SlipDevice {
+    state: &'static State,
+}
+
+impl Device for SlipDevice {
+    fn is_transmit_ready(&mut self) -> bool {
+        self.state.shared.lock(|s| !s.borrow().tx.is_full())
+    }
+
+    fn transmit(&mut self, pkt: PacketBuf) {
+        self.state.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            if s.tx.push_back(pkt).is_err() {
+                warn!("SLIP: TX queue full");
+            }
+            s.runner_waker.wake();
+        })
+    }
+
+    fn receive(&mut self) -> Option<PacketBuf> {
+        self.state.shared.lock(|s| s.borrow_mut().rx.pop_front())
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.state.shared.lock(|s| s.borrow_mut().stack_waker.register(waker))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = MTU;
+        caps.medium = Medium::Ip;
+        caps
+    }
+
+    fn link_state(&mut self) -> LinkState {
+        match self.state.shared.lock(|s| s.borrow().link_up) {
+            true => LinkState::Up,
+            false => LinkState::Down,
+        }
+    }
+
+    fn ethernet_address(&self) -> [u8; 6] {
+        [0; 6]
+    }
+}
+
+/// Runner of a SLIP link, exchanging the packets of the [`SlipDevice`] over a serial port.
+pub struct Runner {
+    state: &'static State,
+}
+
+impl Runner {
+    /// Run SLIP over `port` until it fails, and return why. It can then be called again.
+    ///
+    /// The reads of `port` are cancelled when there are packets to send, so they must not lose
+    /// data when cancelled, as is the case of buffered UART drivers.
+    pub async fn run<P: Read + Write>(&mut self, mut port: P) -> Error<P::Error> {
+        self.set_link_up(true);
+        let mut decoder = Decoder::new();
+        let error = match self.serve(&mut port, &mut decoder).await {
+            Ok(never) => match never {},
+            Err(error) => error,
+        };
+        self.set_link_up(false);
+        error
+    }
+
+    fn set_link_up(&mut self, link_up: bool) {
+        self.state.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.link_up = link_up;
+            s.stack_waker.wake();
+        })
+    }
+
+    async fn serve<P: Read + Write>(
+        &mut self,
+        port: &mut P,
+        decoder: &mut Decoder,
+    ) -> Result<Infallible, Error<P::Error>> {
+        // A leading END flushes the noise the peer may have received before.
+        write_all(port, &[END]).await?;
+
+        let mut chunk = [0; 64];
+        loop {
+            let read = {
+                let read = port.read(&mut chunk);
+                let shared = &self.state.shared;
+                let transmit = poll_fn(|cx| {
+                    shared.lock(|s| {
+                        let mut s = s.borrow_mut();
+                        if s.tx.is_empty() {
+                            s.runner_waker.register(cx.waker());
+                            Poll::Pending
+                        } else {
+                            Poll::Ready(())
+                        }
+                    })
+                });
+                pin_mut!(read, transmit);
+                match select(read, transmit).await {
+                    Either::Left((result, _)) => Some(result.map_err(Error::Io)?),
+                    Either::Right(_) => None,
+                }
+            };
+
+            match read {
+                Some(0) => return Err(Error::ConnectionClosed),
+                Some(n) => {
+                    for &b in &chunk[..n] {
+                        if let Some(packet) = decoder.push(b) {
+                            self.receive(packet);
+                        }
+                    }
+                }
+                None => self.transmit(port).await?,
+            }
+        }
+    }
+
+    fn receive(&mut self, packet: &[u8]) {
+        let mut pkt = match PacketBox::new(Packet::new()) {
+            Some(pkt) => pkt,
+            None => {
+                warn!("SLIP: packet pool exhausted");
+                return;
+            }
+        };
+        pkt[..packet.len()].copy_from_slice(packet);
+        let pkt = pkt.slice(0..packet.len());
+        self.state.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            if s.rx.push_back(pkt).is_err() {
+                warn!("SLIP: RX queue full");
+            }
+            s.stack_waker.wake();
+        })
+    }
+
+    /// Send the packets queued by the stack.
+    async fn transmit<P: Write>(&mut self, port: &mut P) -> Result<(), Error<P::Error>> {
+        loop {
+            let pkt = self.state.shared.lock(|s| {
+                let mut s = s.borrow_mut();
+                // Room is made for the stack.
+                s.stack_waker.wake();
+                s.tx.pop_front()
+            });
+            match pkt {
+                Some(pkt) => send_packet(port, &pkt[..]).await?,
+                None => return port.flush().await.map_err(Error::Io),
+            }
+        }
+    }
+}
+
+/// Send a packet, with its END and ESC bytes escaped, terminated by an END.
+async fn send_packet<P: Write>(port: &mut P, packet: &[u8]) -> Result<(), Error<P::Error>> {
+    let mut buf = [0; 64];
+    let mut len = 0;
+    for &b in packet {
+        match b {
+            END => {
+                buf[len..len + 2].copy_from_slice(&[ESC, ESC_END]);
+                len += 2;
+            }
+            ESC => {
+                buf[len..len + 2].copy_from_slice(&[ESC, ESC_ESC]);
+                len += 2;
+            }
+            b => {
+                buf[len] = b;
+                len += 1;
+            }
+        }
+        if len >= buf.len() - 2 {
+            write_all(port, &buf[..len]).await?;
+            len = 0;
+        }
+    }
+    buf[len] = END;
+    write_all(port, &buf[..len + 1]).await
+}
+
+async fn write_all<P: Write>(port: &mut P, mut buf: &[u8]) -> Result<(), Error<P::Error>> {
+    while !buf.is_empty() {
+        match port.write(buf).await.map_err(Error::Io)? {
+            0 => return Err(Error::ConnectionClosed),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Decoder of the packets received, terminated by END, with escaped bytes.
+struct Decoder {
+    buf: [u8; MTU],
+    len: usize,
+    escaped: bool,
+    /// Whether the packet being received is too long or malformed, and is dropped.
+    invalid: bool,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Self {
+            buf: [0; MTU],
+            len: 0,
+            escaped: false,
+            invalid: false,
+        }
+    }
+
+    /// Decode the byte `b`, returning the packet it ends if it is valid and not empty.
+    fn push(&mut self, b: u8) -> Option<&[u8]> {
+        if b == END {
+            let len = core::mem::replace(&mut self.len, 0);
+            let invalid = core::mem::replace(&mut self.invalid, false);
+            let escaped = core::mem::replace(&mut self.escaped, false);
+            return (!invalid && !escaped && len > 0).then(|| &self.buf[..len]);
+        }
+
+        let b = match (core::mem::replace(&mut self.escaped, false), b) {
+            (false, ESC) => {
+                self.escaped = true;
+                return None;
+            }
+            (false, b) => b,
+            (true, ESC_END) => END,
+            (true, ESC_ESC) => ESC,
+            (true, _) => {
+                self.invalid = true;
+                return None;
+            }
+        };
+        match self.buf.get_mut(self.len) {
+            Some(dst) => {
+                *dst = b;
+                self.len += 1;
+            }
+            None => self.invalid = true,
+        }
+        None
+    }
+}
@@ -0,0 +1,194 @@
+//! SNTP client, for wall-clock time.
+//!
+//! The [`SntpClient`] queries an NTP server periodically, and keeps the offset between the
+//! `embassy-time` clock and UTC. The current UTC time is then computed from the local clock,
+//! without waiting for the network. The error of the offset is bounded by half the round trip
+//! to the server, which [`Synchronization::max_error`] gives.
+//!
+//! The `on_sync` callback of [`SntpClient::run`] is called after each synchronization, for
+//! example to set an RTC.
+
+use core::cell::Cell;
+
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use smoltcp::wire::IpEndpoint;
+
+use crate::udp::{self, BindError, UdpSocket};
+use crate::{Device, IpAddress, PacketMetadata, Stack};
+
+const NTP_PORT: u16 = 123;
+const PACKET_LEN: usize = 48;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970).
+const NTP_TO_UNIX_SECS: u64 = 2_208_988_800;
+
+/// Size of the socket buffers needed by the client, for one NTP packet.
+pub const BUFFER_SIZE: usize = PACKET_LEN;
+
+/// SNTP client configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// Address of the NTP server.
+    pub server: IpAddress,
+    /// Time between synchronizations.
+    pub interval: Duration,
+    /// How long to wait for the answer of the server.
+    pub timeout: Duration,
+    /// Time between retries, after a failure.
+    pub retry_interval: Duration,
+}
+
+impl Config {
+    /// Configuration to synchronize with `server` every hour.
+    pub fn new(server: IpAddress) -> Self {
+        Self {
+            server,
+            interval: Duration::from_secs(3600),
+            timeout: Duration::from_secs(5),
+            retry_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Errors of a synchronization.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The query couldn't be sent.
+    Send(udp::Error),
+    /// The server didn't answer in time.
+    Timeout,
+    /// The server isn't synchronized, or asks to stop querying it.
+    Unsynchronized,
+}
+
+/// Result of a synchronization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Synchronization {
+    /// UTC time at instant zero of `embassy-time`, in microseconds since the Unix epoch.
+    pub epoch_micros: u64,
+    /// Half the round trip to the server, which bounds the error of the offset.
+    pub max_error: Duration,
+    /// When the synchronization happened.
+    pub at: Instant,
+}
+
+impl Synchronization {
+    /// UTC time at `instant`, in microseconds since the Unix epoch.
+    pub fn utc_micros(&self, instant: Instant) -> u64 {
+        self.epoch_micros + instant.as_micros()
+    }
+}
+
+/// SNTP client.
+pub struct SntpClient<'a> {
+    socket: UdpSocket<'a>,
+    config: Config,
+    sync: Cell<Option<Synchronization>>,
+}
+
+impl<'a> SntpClient<'a> {
+    /// Create an SNTP client, using a dynamic port of `stack`.
+    ///
+    /// The buffers should hold at least [`BUFFER_SIZE`] bytes.
+    pub fn new<D: Device>(
+        stack: &'a Stack<D>,
+        config: Config,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, BindError> {
+        let mut socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+        socket.bind(0)?;
+
+        Ok(Self {
+            socket,
+            config,
+            sync: Cell::new(None),
+        })
+    }
+
+    /// The latest synchronization, if any succeeded.
+    pub fn synchronization(&self) -> Option<Synchronization> {
+        self.sync.get()
+    }
+
+    /// Current UTC time, in microseconds since the Unix epoch, if a synchronization succeeded.
+    pub fn now_utc_micros(&self) -> Option<u64> {
+        self.sync.get().map(|s| s.utc_micros(Instant::now()))
+    }
+
+    /// Synchronize with the server periodically, calling `on_sync` after each synchronization.
+    pub async fn run(&self, mut on_sync: impl FnMut(&Synchronization)) -> ! {
+        loop {
+            let delay = match self.sync().await {
+                Ok(sync) => {
+                    on_sync(&sync);
+                    self.config.interval
+                }
+                Err(e) => {
+                    warn!("sntp: synchronization failed: {:?}", e);
+                    self.config.retry_interval
+                }
+            };
+            Timer::after(delay).await;
+        }
+    }
+
+    /// Synchronize with the server once.
+    pub async fn sync(&self) -> Result<Synchronization, Error> {
+        let server = IpEndpoint::new(self.config.server, NTP_PORT);
+
+        // The transmit timestamp is echoed by the server, to match the answer with the query.
+        // The local clock is sent, as the time isn't known.
+        let t1 = Instant::now();
+        let mut packet = [0; PACKET_LEN];
+        packet[0] = 0x23; // version 4, client mode
+        packet[40..48].copy_from_slice(&t1.as_micros().to_be_bytes());
+        self.socket.send_to(&packet, server).await.map_err(Error::Send)?;
+
+        let (t2, t3, t4) = with_timeout(self.config.timeout, async {
+            loop {
+                let (n, from) = match self.socket.recv_from(&mut packet).await {
+                    Ok(x) => x,
+                    Err(_) => continue,
+                };
+                let t4 = Instant::now();
+                if from != server || n < PACKET_LEN || packet[24..32] != t1.as_micros().to_be_bytes() {
+                    // Not an answer to this query.
+                    continue;
+                }
+                let mode = packet[0] & 0x07;
+                let leap = packet[0] >> 6;
+                let stratum = packet[1];
+                if mode != 4 || leap == 3 || stratum == 0 {
+                    return Err(Error::Unsynchronized);
+                }
+                return Ok((timestamp_micros(&packet[32..40]), timestamp_micros(&packet[40..48]), t4));
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)??;
+
+        // The round trip, minus the time spent in the server.
+        let round_trip = (t4 - t1).as_micros().saturating_sub(t3.saturating_sub(t2));
+        let utc_at_t4 = t3 + round_trip / 2;
+        let sync = Synchronization {
+            epoch_micros: utc_at_t4.saturating_sub(t4.as_micros()),
+            max_error: Duration::from_micros(round_trip / 2),
+            at: t4,
+        };
+        debug!("sntp: synchronized, max error {} us", round_trip / 2);
+
+        self.sync.set(Some(sync));
+        Ok(sync)
+    }
+}
+
+/// Convert an NTP timestamp to microseconds since the Unix epoch.
+fn timestamp_micros(ts: &[u8]) -> u64 {
+    let secs = u32::from_be_bytes([ts[0], ts[1], ts[2], ts[3]]) as u64;
+    let frac = u32::from_be_bytes([ts[4], ts[5], ts[6], ts[7]]) as u64;
+    secs.saturating_sub(NTP_TO_UNIX_SECS) * 1_000_000 + ((frac * 1_000_000) >> 32)
+}
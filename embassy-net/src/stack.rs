@@ -1,38 +1,118 @@
+#[cfg(feature = "tcp")]
+use core::cell::Cell;
 use core::cell::UnsafeCell;
 use core::future::{poll_fn, Future};
-use core::task::{Context, Poll};
+use core::task::{Context, Poll, Waker};
 
-use embassy_sync::waitqueue::WakerRegistration;
+use embassy_sync::waitqueue::{MultiWakerRegistration, WakerRegistration};
 use embassy_time::{Instant, Timer};
 use futures::pin_mut;
 use heapless::Vec;
-#[cfg(feature = "dhcpv4")]
+#[cfg(any(feature = "dhcpv4", feature = "dns", feature = "proto-ipv6"))]
 use smoltcp::iface::SocketHandle;
 use smoltcp::iface::{Interface, InterfaceBuilder, SocketSet, SocketStorage};
+#[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
+use smoltcp::iface::{Neighbor, NeighborCache};
 #[cfg(feature = "medium-ethernet")]
-use smoltcp::iface::{Neighbor, NeighborCache, Route, Routes};
-#[cfg(feature = "medium-ethernet")]
+use smoltcp::iface::{Route, Routes};
+#[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
 use smoltcp::phy::{Device as _, Medium};
 #[cfg(feature = "dhcpv4")]
 use smoltcp::socket::dhcpv4;
+#[cfg(feature = "dns")]
+use smoltcp::socket::dns::{self, GetQueryResultError, StartQueryError};
+#[cfg(any(feature = "dhcpv4", feature = "proto-ipv6"))]
+use smoltcp::socket::raw;
+#[cfg(feature = "dhcpv4")]
+use smoltcp::socket::Socket;
 use smoltcp::time::Instant as SmolInstant;
 #[cfg(feature = "medium-ethernet")]
-use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress};
+use smoltcp::wire::EthernetAddress;
+#[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
+use smoltcp::wire::HardwareAddress;
+#[cfg(feature = "medium-ieee802154")]
+use smoltcp::wire::Ieee802154Address;
+#[cfg(any(
+    feature = "medium-ethernet",
+    feature = "medium-ieee802154",
+    feature = "dns",
+    feature = "igmp"
+))]
+use smoltcp::wire::IpAddress;
+#[cfg(feature = "proto-ipv6")]
+use smoltcp::wire::Ipv6Cidr;
 use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr};
+#[cfg(any(feature = "dhcpv4", feature = "proto-ipv6"))]
+use smoltcp::wire::{IpProtocol, IpVersion};
 
-use crate::device::{Device, DeviceAdapter, LinkState};
+use crate::device::{Device, DeviceAdapter, LinkState, Stats};
+#[cfg(feature = "dhcpv4")]
+use crate::dhcp::{self, DhcpClient, DhcpConfig, DhcpEvent};
+#[cfg(feature = "proto-ipv6")]
+use crate::ipv6::{self, ConfigStrategyV6, ConfigV6, Slaac};
+#[cfg(feature = "tcp")]
+use crate::tcp_stats;
 
 const LOCAL_PORT_MIN: u16 = 1025;
 const LOCAL_PORT_MAX: u16 = 65535;
+#[cfg(feature = "igmp")]
+const MAX_MULTICAST_GROUPS: usize = 4;
+/// Routes added with `Stack::add_route`.
+#[cfg(feature = "medium-ethernet")]
+const MAX_STATIC_ROUTES: usize = 4;
+/// Default routes, for IPv4 and IPv6, and static routes.
+#[cfg(all(feature = "medium-ethernet", not(feature = "proto-ipv6")))]
+const ROUTES: usize = 1 + MAX_STATIC_ROUTES;
+#[cfg(all(feature = "medium-ethernet", feature = "proto-ipv6"))]
+const ROUTES: usize = 2 + MAX_STATIC_ROUTES;
+/// Slots of the addresses in `StackResources::addresses`.
+#[cfg(feature = "proto-ipv6")]
+const ADDR_SLOT_LINK_LOCAL: usize = 1;
+#[cfg(feature = "proto-ipv6")]
+const ADDR_SLOT_V6: usize = 2;
+/// Receive buffer for the router advertisements.
+#[cfg(feature = "proto-ipv6")]
+const NDISC_BUFFER_LEN: usize = 1024;
+/// Receive buffer for the replies to the client of [`ConfigStrategy::DhcpWithConfig`], and the
+/// other UDP packets its raw socket sees.
+#[cfg(feature = "dhcpv4")]
+const DHCP_BUFFER_LEN: usize = 1536;
 
+/// Memory of the stack: `ADDR` addresses, `SOCK` sockets and `NEIGHBOR` neighbor cache entries.
+///
+/// The stack takes some of the `SOCK` sockets for itself, so count them in: one for DNS with the
+/// `dns` feature, one for the DHCP client while it runs, and one for router discovery with IPv6
+/// on Ethernet.
 pub struct StackResources<const ADDR: usize, const SOCK: usize, const NEIGHBOR: usize> {
     addresses: [IpCidr; ADDR],
     sockets: [SocketStorage<'static>; SOCK],
+    #[cfg(feature = "tcp")]
+    tcp_connections: [tcp_stats::Connection; SOCK],
 
     #[cfg(feature = "medium-ethernet")]
-    routes: [Option<(IpCidr, Route)>; 1],
-    #[cfg(feature = "medium-ethernet")]
+    routes: [Option<(IpCidr, Route)>; ROUTES],
+    #[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
     neighbor_cache: [Option<(IpAddress, Neighbor)>; NEIGHBOR],
+    #[cfg(feature = "dns")]
+    queries: [Option<dns::DnsQuery>; 1],
+    #[cfg(feature = "igmp")]
+    multicast_groups: [Option<(Ipv4Address, ())>; MAX_MULTICAST_GROUPS],
+    #[cfg(feature = "proto-ipv6")]
+    ndisc_rx_meta: [raw::PacketMetadata; 4],
+    #[cfg(feature = "proto-ipv6")]
+    ndisc_rx_buffer: [u8; NDISC_BUFFER_LEN],
+    #[cfg(feature = "proto-ipv6")]
+    ndisc_tx_meta: [raw::PacketMetadata; 1],
+    #[cfg(feature = "proto-ipv6")]
+    ndisc_tx_buffer: [u8; ipv6::ROUTER_SOLICIT_LEN],
+    #[cfg(feature = "dhcpv4")]
+    dhcp_rx_meta: [raw::PacketMetadata; 4],
+    #[cfg(feature = "dhcpv4")]
+    dhcp_rx_buffer: [u8; DHCP_BUFFER_LEN],
+    #[cfg(feature = "dhcpv4")]
+    dhcp_tx_meta: [raw::PacketMetadata; 1],
+    #[cfg(feature = "dhcpv4")]
+    dhcp_tx_buffer: [u8; dhcp::MESSAGE_LEN],
 }
 
 impl<const ADDR: usize, const SOCK: usize, const NEIGHBOR: usize> StackResources<ADDR, SOCK, NEIGHBOR> {
@@ -40,10 +120,32 @@ impl<const ADDR: usize, const SOCK: usize, const NEIGHBOR: usize> StackResources
         Self {
             addresses: [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 32); ADDR],
             sockets: [SocketStorage::EMPTY; SOCK],
+            #[cfg(feature = "tcp")]
+            tcp_connections: [tcp_stats::Connection::EMPTY; SOCK],
             #[cfg(feature = "medium-ethernet")]
-            routes: [None; 1],
-            #[cfg(feature = "medium-ethernet")]
+            routes: [None; ROUTES],
+            #[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
             neighbor_cache: [None; NEIGHBOR],
+            #[cfg(feature = "dns")]
+            queries: [None; 1],
+            #[cfg(feature = "igmp")]
+            multicast_groups: [None; MAX_MULTICAST_GROUPS],
+            #[cfg(feature = "proto-ipv6")]
+            ndisc_rx_meta: [raw::PacketMetadata::EMPTY; 4],
+            #[cfg(feature = "proto-ipv6")]
+            ndisc_rx_buffer: [0; NDISC_BUFFER_LEN],
+            #[cfg(feature = "proto-ipv6")]
+            ndisc_tx_meta: [raw::PacketMetadata::EMPTY; 1],
+            #[cfg(feature = "proto-ipv6")]
+            ndisc_tx_buffer: [0; ipv6::ROUTER_SOLICIT_LEN],
+            #[cfg(feature = "dhcpv4")]
+            dhcp_rx_meta: [raw::PacketMetadata::EMPTY; 4],
+            #[cfg(feature = "dhcpv4")]
+            dhcp_rx_buffer: [0; DHCP_BUFFER_LEN],
+            #[cfg(feature = "dhcpv4")]
+            dhcp_tx_meta: [raw::PacketMetadata::EMPTY; 1],
+            #[cfg(feature = "dhcpv4")]
+            dhcp_tx_buffer: [0; dhcp::MESSAGE_LEN],
         }
     }
 }
@@ -55,10 +157,40 @@ pub struct Config {
     pub dns_servers: Vec<Ipv4Address, 3>,
 }
 
+/// Errors when joining or leaving a multicast group.
+#[cfg(feature = "igmp")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MulticastError {
+    /// The table of multicast groups is full.
+    GroupTableFull,
+    /// The address isn't a multicast address, or its protocol isn't supported.
+    Unaddressable,
+}
+
+/// Errors when adding a static route.
+#[cfg(feature = "medium-ethernet")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RouteError {
+    /// The table of static routes is full, or the device isn't an Ethernet one.
+    TableFull,
+    /// The route is a default route, which is set by the configuration, or its router isn't of
+    /// the protocol of its subnet.
+    Invalid,
+}
+
 pub enum ConfigStrategy {
+    /// No IPv4 configuration, until one is set with [`Stack::set_config_v4`], as with
+    /// [PPP](crate::ppp).
+    None,
     Static(Config),
     #[cfg(feature = "dhcpv4")]
     Dhcp,
+    /// Obtained from a DHCP server, with the options of [`DhcpConfig`] such as the host name. Use
+    /// [`ConfigStrategy::Dhcp`] unless they are needed, see the [`DhcpConfig`] docs.
+    #[cfg(feature = "dhcpv4")]
+    DhcpWithConfig(DhcpConfig),
 }
 
 pub struct Stack<D: Device> {
@@ -70,8 +202,48 @@ struct Inner<D: Device> {
     device: DeviceAdapter<D>,
     link_up: bool,
     config: Option<Config>,
+    /// Incremented when the IPv4 or IPv6 configuration changes.
+    config_generation: u32,
+    /// Woken when the link or the configuration changes.
+    state_waker: MultiWakerRegistration<MAX_WAITERS>,
     #[cfg(feature = "dhcpv4")]
     dhcp_socket: Option<SocketHandle>,
+    /// Client of [`ConfigStrategy::DhcpWithConfig`], with the handle of its raw socket.
+    #[cfg(feature = "dhcpv4")]
+    dhcp_client: Option<(SocketHandle, DhcpClient)>,
+    /// Raw socket of the client, while it isn't in the socket set.
+    #[cfg(feature = "dhcpv4")]
+    dhcp_raw_socket: Option<raw::Socket<'static>>,
+    #[cfg(feature = "dhcpv4")]
+    random_seed: u64,
+    #[cfg(feature = "dns")]
+    dns_socket: SocketHandle,
+    /// Woken when the query slot of the DNS socket becomes free.
+    #[cfg(feature = "dns")]
+    dns_waker: MultiWakerRegistration<MAX_WAITERS>,
+    #[cfg(feature = "dns")]
+    dns_cache: crate::dns::Cache,
+    #[cfg(feature = "proto-ipv6")]
+    config_v6: Option<ConfigV6>,
+    #[cfg(feature = "proto-ipv6")]
+    strategy_v6: ConfigStrategyV6,
+    /// Raw socket for router discovery, on Ethernet.
+    #[cfg(feature = "proto-ipv6")]
+    ndisc_socket: Option<SocketHandle>,
+    #[cfg(feature = "proto-ipv6")]
+    slaac: Slaac,
+}
+
+/// Number of tasks which can wait for the same state change at once without waking each other.
+const MAX_WAITERS: usize = 4;
+
+fn register_waiter(wakers: &mut MultiWakerRegistration<MAX_WAITERS>, waker: &Waker) {
+    if wakers.register(waker).is_err() {
+        // All slots are taken, possibly by tasks which stopped waiting. Wake them all, the ones
+        // still waiting register again.
+        wakers.wake();
+        let _ = wakers.register(waker);
+    }
 }
 
 pub(crate) struct SocketStack {
@@ -79,6 +251,9 @@ pub(crate) struct SocketStack {
     pub(crate) iface: Interface<'static>,
     pub(crate) waker: WakerRegistration,
     next_local_port: u16,
+    /// Round-trip time and retransmissions of the TCP connections, shared with the device.
+    #[cfg(feature = "tcp")]
+    pub(crate) tcp_connections: &'static [Cell<tcp_stats::Connection>],
 }
 
 unsafe impl<D: Device> Send for Stack<D> {}
@@ -90,7 +265,7 @@ impl<D: Device + 'static> Stack<D> {
         resources: &'static mut StackResources<ADDR, SOCK, NEIGH>,
         random_seed: u64,
     ) -> Self {
-        #[cfg(feature = "medium-ethernet")]
+        #[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
         let medium = device.capabilities().medium;
 
         #[cfg(feature = "medium-ethernet")]
@@ -100,11 +275,28 @@ impl<D: Device + 'static> Stack<D> {
             [0, 0, 0, 0, 0, 0]
         };
 
+        #[cfg(feature = "medium-ieee802154")]
+        let ieee802154_addr = if medium == Medium::Ieee802154 {
+            device.ieee802154_address()
+        } else {
+            [0; 8]
+        };
+
         let mut device = DeviceAdapter::new(device);
+        #[cfg(feature = "tcp")]
+        let tcp_connections = Cell::from_mut(&mut resources.tcp_connections[..]).as_slice_of_cells();
+        #[cfg(feature = "tcp")]
+        {
+            device.tcp_connections = tcp_connections;
+        }
 
         let mut b = InterfaceBuilder::new();
         b = b.ip_addrs(&mut resources.addresses[..]);
         b = b.random_seed(random_seed);
+        #[cfg(feature = "igmp")]
+        {
+            b = b.ipv4_multicast_groups(&mut resources.multicast_groups[..]);
+        }
 
         #[cfg(feature = "medium-ethernet")]
         if medium == Medium::Ethernet {
@@ -113,9 +305,21 @@ impl<D: Device + 'static> Stack<D> {
             b = b.routes(Routes::new(&mut resources.routes[..]));
         }
 
+        #[cfg(feature = "medium-ieee802154")]
+        if medium == Medium::Ieee802154 {
+            b = b.hardware_addr(HardwareAddress::Ieee802154(Ieee802154Address::Extended(
+                ieee802154_addr,
+            )));
+            b = b.neighbor_cache(NeighborCache::new(&mut resources.neighbor_cache[..]));
+        }
+
         let iface = b.finalize(&mut device);
 
-        let sockets = SocketSet::new(&mut resources.sockets[..]);
+        #[allow(unused_mut)]
+        let mut sockets = SocketSet::new(&mut resources.sockets[..]);
+
+        #[cfg(feature = "dns")]
+        let dns_socket = sockets.add(dns::Socket::new(&[], &mut resources.queries[..]));
 
         let next_local_port = (random_seed % (LOCAL_PORT_MAX - LOCAL_PORT_MIN) as u64) as u16 + LOCAL_PORT_MIN;
 
@@ -123,25 +327,64 @@ impl<D: Device + 'static> Stack<D> {
             device,
             link_up: false,
             config: None,
+            config_generation: 0,
+            state_waker: MultiWakerRegistration::new(),
             #[cfg(feature = "dhcpv4")]
             dhcp_socket: None,
+            #[cfg(feature = "dhcpv4")]
+            dhcp_client: None,
+            #[cfg(feature = "dhcpv4")]
+            dhcp_raw_socket: Some(raw::Socket::new(
+                IpVersion::Ipv4,
+                IpProtocol::Udp,
+                raw::PacketBuffer::new(&mut resources.dhcp_rx_meta[..], &mut resources.dhcp_rx_buffer[..]),
+                raw::PacketBuffer::new(&mut resources.dhcp_tx_meta[..], &mut resources.dhcp_tx_buffer[..]),
+            )),
+            #[cfg(feature = "dhcpv4")]
+            random_seed,
+            #[cfg(feature = "dns")]
+            dns_socket,
+            #[cfg(feature = "dns")]
+            dns_waker: MultiWakerRegistration::new(),
+            #[cfg(feature = "dns")]
+            dns_cache: crate::dns::Cache::new(),
+            #[cfg(feature = "proto-ipv6")]
+            config_v6: None,
+            #[cfg(feature = "proto-ipv6")]
+            strategy_v6: ConfigStrategyV6::LinkLocal,
+            #[cfg(feature = "proto-ipv6")]
+            ndisc_socket: None,
+            #[cfg(feature = "proto-ipv6")]
+            slaac: Slaac::new(),
         };
         let mut socket = SocketStack {
             sockets,
             iface,
             waker: WakerRegistration::new(),
             next_local_port,
+            #[cfg(feature = "tcp")]
+            tcp_connections,
         };
 
-        match config {
-            ConfigStrategy::Static(config) => inner.apply_config(&mut socket, config),
-            #[cfg(feature = "dhcpv4")]
-            ConfigStrategy::Dhcp => {
-                let handle = socket.sockets.add(smoltcp::socket::dhcpv4::Socket::new());
-                inner.dhcp_socket = Some(handle);
-            }
+        #[cfg(all(feature = "proto-ipv6", feature = "medium-ethernet"))]
+        if medium == Medium::Ethernet {
+            let link_local = ipv6::link_local_address(ethernet_addr);
+            inner.set_addr(&mut socket, ADDR_SLOT_LINK_LOCAL, IpCidr::new(link_local.into(), 64));
+            // Receive the router advertisements and the neighbor solicitations.
+            inner.device.device.join_multicast(ipv6::ALL_NODES_MAC);
+            inner.device.device.join_multicast(ipv6::solicited_node_mac(link_local));
+
+            let handle = socket.sockets.add(raw::Socket::new(
+                IpVersion::Ipv6,
+                IpProtocol::Icmpv6,
+                raw::PacketBuffer::new(&mut resources.ndisc_rx_meta[..], &mut resources.ndisc_rx_buffer[..]),
+                raw::PacketBuffer::new(&mut resources.ndisc_tx_meta[..], &mut resources.ndisc_tx_buffer[..]),
+            ));
+            inner.ndisc_socket = Some(handle);
         }
 
+        inner.apply_strategy(&mut socket, config);
+
         Self {
             socket: UnsafeCell::new(socket),
             inner: UnsafeCell::new(inner),
@@ -174,6 +417,295 @@ impl<D: Device + 'static> Stack<D> {
         unsafe { self.with(|_s, i| i.config.clone()) }
     }
 
+    /// Wait until the link is up, returning at once if it already is.
+    pub async fn wait_link_up(&self) {
+        self.wait(|i| i.link_up).await
+    }
+
+    /// Wait until the link is down, such as when a cable is unplugged, returning at once if it
+    /// already is.
+    pub async fn wait_link_down(&self) {
+        self.wait(|i| !i.link_up).await
+    }
+
+    /// Wait until the IPv4 or IPv6 configuration changes: acquired, lost, or replaced, as when
+    /// a DHCP lease is renewed with another address.
+    pub async fn wait_config_changed(&self) {
+        let generation = unsafe { self.with(|_s, i| i.config_generation) };
+        self.wait(|i| i.config_generation != generation).await
+    }
+
+    async fn wait(&self, mut done: impl FnMut(&Inner<D>) -> bool) {
+        poll_fn(|cx| unsafe {
+            self.with_mut(|_s, i| {
+                if done(i) {
+                    Poll::Ready(())
+                } else {
+                    register_waiter(&mut i.state_waker, cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    #[cfg(all(feature = "router", feature = "medium-ip"))]
+    pub(crate) fn medium(&self) -> crate::Medium {
+        unsafe { self.with(|_s, i| i.device.device.capabilities().medium) }
+    }
+
+    /// Counters of the frames received and sent, to report the health of the link.
+    pub fn stats(&self) -> Stats {
+        unsafe { self.with(|_s, i| i.device.stats.get()) }
+    }
+
+    /// Reset the counters of [`stats`](Self::stats) to zero.
+    pub fn reset_stats(&self) {
+        unsafe { self.with(|_s, i| i.device.stats.set(Stats::default())) }
+    }
+
+    /// Set how the IPv4 configuration is obtained, dropping the current one.
+    ///
+    /// This switches between DHCP and a static configuration at runtime, or changes the static
+    /// address, gateway or DNS servers. With DHCP, `StackResources` needs a free socket for the
+    /// DHCP client.
+    pub fn set_config_v4(&self, strategy: ConfigStrategy) {
+        unsafe {
+            self.with_mut(|s, i| {
+                i.unapply_config(s);
+                #[cfg(feature = "dhcpv4")]
+                if let Some(handle) = i.dhcp_socket.take() {
+                    s.sockets.remove(handle);
+                }
+                #[cfg(feature = "dhcpv4")]
+                if let Some((handle, _)) = i.dhcp_client.take() {
+                    if let Socket::Raw(socket) = s.sockets.remove(handle) {
+                        i.dhcp_raw_socket = Some(socket);
+                    }
+                }
+                i.apply_strategy(s, strategy);
+                s.waker.wake();
+            })
+        }
+    }
+
+    /// Hand every frame received or sent by the device to `tap`, or stop if `None`. See the
+    /// [`pcap`](crate::pcap) module.
+    #[cfg(feature = "pcap")]
+    pub fn set_tap(&self, tap: Option<crate::pcap::Tap>) {
+        unsafe { self.with_mut(|_s, i| i.device.tap = tap) }
+    }
+
+    /// Route the packets to `cidr` through the router `via`, replacing the route to the same
+    /// subnet if any. The default routes are the gateways of the configurations.
+    ///
+    /// Up to 4 static routes can be added. Only Ethernet devices have routes, the other ones
+    /// sending all the packets to their peer.
+    #[cfg(feature = "medium-ethernet")]
+    pub fn add_route(&self, cidr: IpCidr, via: IpAddress) -> Result<(), RouteError> {
+        let same_protocol = matches!((cidr, via), (IpCidr::Ipv4(_), IpAddress::Ipv4(_)));
+        #[cfg(feature = "proto-ipv6")]
+        let same_protocol = same_protocol || matches!((cidr, via), (IpCidr::Ipv6(_), IpAddress::Ipv6(_)));
+        if cidr.prefix_len() == 0 || !same_protocol {
+            return Err(RouteError::Invalid);
+        }
+
+        let route = Route {
+            via_router: via,
+            preferred_until: None,
+            expires_at: None,
+        };
+        let mut res = Err(RouteError::TableFull);
+        unsafe {
+            self.with_mut(|s, _i| {
+                s.iface.routes_mut().update(|routes| {
+                    // The default routes have slots of their own.
+                    let static_routes = routes.iter().filter(|(c, _)| c.prefix_len() != 0).count();
+                    if routes.get(&cidr).is_some() || static_routes < MAX_STATIC_ROUTES {
+                        res = routes
+                            .insert(cidr, route)
+                            .map(|_| ())
+                            .map_err(|_| RouteError::TableFull);
+                    }
+                });
+                s.waker.wake();
+            })
+        }
+        res
+    }
+
+    /// Remove the static route to `cidr`. Returns `false` if there was none.
+    #[cfg(feature = "medium-ethernet")]
+    pub fn remove_route(&self, cidr: IpCidr) -> bool {
+        if cidr.prefix_len() == 0 {
+            return false;
+        }
+        let mut removed = false;
+        unsafe {
+            self.with_mut(|s, _i| {
+                s.iface
+                    .routes_mut()
+                    .update(|routes| removed = routes.remove(&cidr).is_some());
+            })
+        }
+        removed
+    }
+
+    /// Set how the IPv6 configuration is obtained, dropping the current one. The link-local
+    /// address is kept.
+    ///
+    /// IPv6 needs `StackResources` with at least 3 addresses, for IPv4, the link-local address
+    /// and the IPv6 configuration.
+    #[cfg(feature = "proto-ipv6")]
+    pub fn set_config_v6(&self, strategy: ConfigStrategyV6) {
+        unsafe {
+            self.with_mut(|s, i| {
+                i.unapply_config_v6(s);
+                i.slaac.reset();
+                if let ConfigStrategyV6::Static(config) = &strategy {
+                    i.apply_config_v6(s, config.clone());
+                }
+                i.strategy_v6 = strategy;
+                s.waker.wake();
+            })
+        }
+    }
+
+    /// The current IPv6 configuration, static or obtained with SLAAC.
+    #[cfg(feature = "proto-ipv6")]
+    pub fn config_v6(&self) -> Option<ConfigV6> {
+        unsafe { self.with(|_s, i| i.config_v6.clone()) }
+    }
+
+    /// Resolve `name` to an address of type `qtype`, using the DNS servers of the current
+    /// configuration.
+    ///
+    /// Answers are cached, see the [`dns`](crate::dns) module. Only one query is sent at a time,
+    /// other calls wait for it to complete. The DNS socket takes one of the sockets of
+    /// [`StackResources`].
+    #[cfg(feature = "dns")]
+    pub async fn dns_query(
+        &self,
+        name: &str,
+        qtype: dns::DnsQueryType,
+    ) -> Result<Vec<IpAddress, 1>, crate::dns::Error> {
+        if let Some(addrs) = unsafe { self.with_mut(|_s, i| i.dns_cache.get(name, qtype)) } {
+            return Ok(addrs);
+        }
+
+        let query = poll_fn(|cx| unsafe {
+            self.with_mut(|s, i| {
+                let socket = s.sockets.get_mut::<dns::Socket>(i.dns_socket);
+                match socket.start_query(s.iface.context(), name, qtype) {
+                    Ok(handle) => Poll::Ready(Ok(handle)),
+                    Err(StartQueryError::NoFreeSlot) => {
+                        register_waiter(&mut i.dns_waker, cx.waker());
+                        Poll::Pending
+                    }
+                    Err(e) => Poll::Ready(Err(e)),
+                }
+            })
+        })
+        .await?;
+
+        // Drop guard to cancel the query if the future is dropped.
+        struct OnDrop<F: FnOnce()>(Option<F>);
+        impl<F: FnOnce()> Drop for OnDrop<F> {
+            fn drop(&mut self) {
+                if let Some(f) = self.0.take() {
+                    f()
+                }
+            }
+        }
+        let drop = OnDrop(Some(|| unsafe {
+            self.with_mut(|s, i| {
+                let socket = s.sockets.get_mut::<dns::Socket>(i.dns_socket);
+                socket.cancel_query(query);
+                s.waker.wake();
+                i.dns_waker.wake();
+            })
+        }));
+
+        let res = poll_fn(|cx| unsafe {
+            self.with_mut(|s, i| {
+                let socket = s.sockets.get_mut::<dns::Socket>(i.dns_socket);
+                match socket.get_query_result(query) {
+                    Ok(addrs) => {
+                        i.dns_waker.wake();
+                        Poll::Ready(Ok(addrs))
+                    }
+                    Err(GetQueryResultError::Pending) => {
+                        socket.register_query_waker(query, cx.waker());
+                        Poll::Pending
+                    }
+                    Err(e) => {
+                        i.dns_waker.wake();
+                        Poll::Ready(Err(e.into()))
+                    }
+                }
+            })
+        })
+        .await;
+
+        // The query is finished, nothing to cancel.
+        core::mem::forget(drop);
+
+        let addrs = res?;
+        unsafe { self.with_mut(|_s, i| i.dns_cache.insert(name, qtype, &addrs)) };
+        Ok(addrs)
+    }
+
+    /// Join the multicast group `addr`, to receive the packets sent to it.
+    ///
+    /// The membership is announced with IGMP, and the device is asked to let the frames of the
+    /// group through. Returns `false` if the group was already joined.
+    ///
+    /// Only IPv4 groups are supported, smoltcp doesn't implement MLD for IPv6 yet.
+    #[cfg(feature = "igmp")]
+    pub fn join_multicast_group<T: Into<IpAddress>>(&self, addr: T) -> Result<bool, MulticastError> {
+        let addr = addr.into();
+        unsafe {
+            self.with_mut(|s, i| {
+                let timestamp = instant_to_smoltcp(Instant::now());
+                let res = s.iface.join_multicast_group(&mut i.device, addr, timestamp);
+                s.waker.wake();
+                let joined = res.map_err(multicast_error)?;
+                if joined {
+                    if let Some(mac) = multicast_mac(addr) {
+                        i.device.device.join_multicast(mac);
+                    }
+                }
+                Ok(joined)
+            })
+        }
+    }
+
+    /// Leave the multicast group `addr`. Returns `false` if the group wasn't joined.
+    #[cfg(feature = "igmp")]
+    pub fn leave_multicast_group<T: Into<IpAddress>>(&self, addr: T) -> Result<bool, MulticastError> {
+        let addr = addr.into();
+        unsafe {
+            self.with_mut(|s, i| {
+                let timestamp = instant_to_smoltcp(Instant::now());
+                let res = s.iface.leave_multicast_group(&mut i.device, addr, timestamp);
+                s.waker.wake();
+                let left = res.map_err(multicast_error)?;
+                if left {
+                    if let Some(mac) = multicast_mac(addr) {
+                        i.device.device.leave_multicast(mac);
+                    }
+                }
+                Ok(left)
+            })
+        }
+    }
+
+    /// Whether the multicast group `addr` was joined.
+    #[cfg(feature = "igmp")]
+    pub fn has_multicast_group<T: Into<IpAddress>>(&self, addr: T) -> bool {
+        unsafe { self.with(|s, _i| s.iface.has_multicast_group(addr)) }
+    }
+
     pub async fn run(&self) -> ! {
         poll_fn(|cx| {
             unsafe { self.with_mut(|s, i| i.poll(cx, s)) }
@@ -194,6 +726,26 @@ impl SocketStack {
 }
 
 impl<D: Device + 'static> Inner<D> {
+    fn apply_strategy(&mut self, s: &mut SocketStack, strategy: ConfigStrategy) {
+        match strategy {
+            ConfigStrategy::None => {}
+            ConfigStrategy::Static(config) => self.apply_config(s, config),
+            #[cfg(feature = "dhcpv4")]
+            ConfigStrategy::Dhcp => {
+                let handle = s.sockets.add(dhcpv4::Socket::new());
+                self.dhcp_socket = Some(handle);
+            }
+            #[cfg(feature = "dhcpv4")]
+            ConfigStrategy::DhcpWithConfig(config) => {
+                if let Some(socket) = self.dhcp_raw_socket.take() {
+                    let handle = s.sockets.add(socket);
+                    let seed = self.random_seed ^ Instant::now().as_ticks();
+                    self.dhcp_client = Some((handle, DhcpClient::new(config, seed)));
+                }
+            }
+        }
+    }
+
     fn apply_config(&mut self, s: &mut SocketStack, config: Config) {
         #[cfg(feature = "medium-ethernet")]
         let medium = self.device.capabilities().medium;
@@ -217,11 +769,17 @@ impl<D: Device + 'static> Inner<D> {
             debug!("   DNS server {}:    {}", i, s);
         }
 
-        self.config = Some(config)
+        self.config = Some(config);
+        self.config_changed();
+        #[cfg(feature = "dns")]
+        self.update_dns_servers(s);
     }
 
-    #[allow(unused)] // used only with dhcp
     fn unapply_config(&mut self, s: &mut SocketStack) {
+        if self.config.is_none() {
+            return;
+        }
+
         #[cfg(feature = "medium-ethernet")]
         let medium = self.device.capabilities().medium;
 
@@ -231,7 +789,233 @@ impl<D: Device + 'static> Inner<D> {
         if medium == Medium::Ethernet {
             s.iface.routes_mut().remove_default_ipv4_route();
         }
-        self.config = None
+        self.config = None;
+        self.config_changed();
+        #[cfg(feature = "dns")]
+        self.update_dns_servers(s);
+    }
+
+    fn config_changed(&mut self) {
+        self.config_generation = self.config_generation.wrapping_add(1);
+        self.state_waker.wake();
+    }
+
+    /// Use the DNS servers of the IPv4 and IPv6 configurations.
+    #[cfg(feature = "dns")]
+    fn update_dns_servers(&mut self, s: &mut SocketStack) {
+        let mut servers: Vec<IpAddress, 3> = Vec::new();
+        if let Some(config) = &self.config {
+            for &addr in &config.dns_servers {
+                let _ = servers.push(addr.into());
+            }
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if let Some(config) = &self.config_v6 {
+            for &addr in &config.dns_servers {
+                let _ = servers.push(addr.into());
+            }
+        }
+        s.sockets
+            .get_mut::<dns::Socket>(self.dns_socket)
+            .update_servers(&servers);
+        self.dns_cache.clear();
+    }
+
+    #[cfg(feature = "proto-ipv6")]
+    fn apply_config_v6(&mut self, s: &mut SocketStack, config: ConfigV6) {
+        #[cfg(feature = "medium-ethernet")]
+        let medium = self.device.capabilities().medium;
+
+        debug!("Acquired IPv6 configuration:");
+
+        debug!("   IP address:      {}", config.address);
+        self.set_addr(s, ADDR_SLOT_V6, IpCidr::Ipv6(config.address));
+        self.device
+            .device
+            .join_multicast(ipv6::solicited_node_mac(config.address.address()));
+
+        #[cfg(feature = "medium-ethernet")]
+        if medium == Medium::Ethernet {
+            if let Some(gateway) = config.gateway {
+                debug!("   Default gateway: {}", gateway);
+                s.iface.routes_mut().add_default_ipv6_route(gateway).unwrap();
+            } else {
+                debug!("   Default gateway: None");
+                s.iface.routes_mut().remove_default_ipv6_route();
+            }
+        }
+        for (i, s) in config.dns_servers.iter().enumerate() {
+            debug!("   DNS server {}:    {}", i, s);
+        }
+
+        self.config_v6 = Some(config);
+        self.config_changed();
+        #[cfg(feature = "dns")]
+        self.update_dns_servers(s);
+    }
+
+    #[cfg(feature = "proto-ipv6")]
+    fn unapply_config_v6(&mut self, s: &mut SocketStack) {
+        let config = match self.config_v6.take() {
+            Some(config) => config,
+            None => return,
+        };
+
+        debug!("Lost IPv6 configuration");
+        self.set_addr(s, ADDR_SLOT_V6, IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 32));
+        self.device
+            .device
+            .leave_multicast(ipv6::solicited_node_mac(config.address.address()));
+        #[cfg(feature = "medium-ethernet")]
+        if self.device.capabilities().medium == Medium::Ethernet {
+            s.iface.routes_mut().remove_default_ipv6_route();
+        }
+        self.config_changed();
+        #[cfg(feature = "dns")]
+        self.update_dns_servers(s);
+    }
+
+    #[cfg(feature = "proto-ipv6")]
+    fn set_addr(&mut self, s: &mut SocketStack, slot: usize, cidr: IpCidr) {
+        s.iface.update_ip_addrs(|addrs| match addrs.get_mut(slot) {
+            Some(dest) => *dest = cidr,
+            None => warn!("StackResources needs 3 addresses for IPv6"),
+        });
+    }
+
+    /// Run the router discovery, and configure IPv6 from the router advertisements.
+    #[cfg(feature = "proto-ipv6")]
+    fn poll_slaac(&mut self, cx: &mut Context<'_>, s: &mut SocketStack, old_link_up: bool) {
+        let handle = match self.ndisc_socket {
+            Some(handle) if self.strategy_v6 == ConfigStrategyV6::Slaac => handle,
+            _ => return,
+        };
+
+        if !self.link_up {
+            if old_link_up {
+                self.unapply_config_v6(s);
+            }
+            return;
+        } else if !old_link_up {
+            // Solicit the routers of the new network.
+            self.slaac.reset();
+        }
+
+        let now = Instant::now();
+        let mac = self.device.device.ethernet_address();
+
+        // Only the latest advertisement matters.
+        let mut advertisement = None;
+        let socket = s.sockets.get_mut::<raw::Socket>(handle);
+        while let Ok(packet) = socket.recv() {
+            if let Some(ra) = ipv6::parse_router_advertisement(packet) {
+                advertisement = Some(ra);
+            }
+        }
+
+        if let Some(ra) = advertisement {
+            if ra.managed {
+                warn!("The network uses DHCPv6, which isn't supported");
+            }
+            match ra.prefix {
+                Some((_, valid)) if valid.as_ticks() == 0 => {
+                    self.slaac.expires_at = None;
+                    self.unapply_config_v6(s);
+                }
+                Some((prefix, valid)) => {
+                    let config = ConfigV6 {
+                        address: Ipv6Cidr::new(ipv6::slaac_address(prefix, mac), 64),
+                        gateway: (ra.router_lifetime.as_ticks() != 0).then(|| ra.router),
+                        dns_servers: ra.dns_servers,
+                    };
+                    self.slaac.expires_at = Some(now + valid);
+                    if self.config_v6.as_ref() != Some(&config) {
+                        self.unapply_config_v6(s);
+                        self.apply_config_v6(s, config);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        if matches!(self.slaac.expires_at, Some(t) if t <= now) {
+            self.slaac.expires_at = None;
+            self.unapply_config_v6(s);
+        }
+
+        let configured = self.config_v6.is_some();
+        if self.slaac.should_solicit(configured, now) {
+            let mut packet = [0; ipv6::ROUTER_SOLICIT_LEN];
+            ipv6::router_solicitation(ipv6::link_local_address(mac), mac, &mut packet);
+            let socket = s.sockets.get_mut::<raw::Socket>(handle);
+            if socket.send_slice(&packet).is_err() {
+                warn!("Failed to send a router solicitation");
+            }
+        }
+
+        if let Some(poll_at) = self.slaac.poll_at(configured) {
+            let t = Timer::at(poll_at);
+            pin_mut!(t);
+            if t.poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+    }
+
+    /// Run the client of [`ConfigStrategy::DhcpWithConfig`], and configure IPv4 from its leases.
+    #[cfg(feature = "dhcpv4")]
+    fn poll_dhcp_client(&mut self, cx: &mut Context<'_>, s: &mut SocketStack, old_link_up: bool) {
+        let mac = self.device.device.ethernet_address();
+        let (handle, client) = match &mut self.dhcp_client {
+            Some((handle, client)) => (*handle, client),
+            None => return,
+        };
+
+        let now = Instant::now();
+        let mut events: Vec<DhcpEvent, 4> = Vec::new();
+        if self.link_up {
+            let socket = s.sockets.get_mut::<raw::Socket>(handle);
+            while let Ok(packet) = socket.recv() {
+                if let Some(event) = client.process(packet, mac, now) {
+                    let _ = events.push(event);
+                }
+            }
+            if let Some(event) = client.poll_timeout(now) {
+                let _ = events.push(event);
+            }
+
+            let mut message = [0; dhcp::MESSAGE_LEN];
+            if let Some(len) = client.message(now, mac, &mut message) {
+                if socket.send_slice(&message[..len]).is_err() {
+                    warn!("Failed to send a DHCP message");
+                }
+            }
+
+            let t = Timer::at(client.poll_at());
+            pin_mut!(t);
+            if t.poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        } else if old_link_up {
+            if let Some(event) = client.reset() {
+                let _ = events.push(event);
+            }
+        }
+
+        if events.is_empty() {
+            return;
+        }
+        let on_event = client.on_event();
+        match client.config().cloned() {
+            Some(config) if self.config.as_ref() != Some(&config) => self.apply_config(s, config),
+            Some(_) => {}
+            None => self.unapply_config(s),
+        }
+        if let Some(on_event) = on_event {
+            for event in events {
+                on_event(event);
+            }
+        }
     }
 
     fn set_ipv4_addr(&mut self, s: &mut SocketStack, cidr: Ipv4Cidr) {
@@ -259,6 +1043,7 @@ impl<D: Device + 'static> Inner<D> {
         // Print when changed
         if old_link_up != self.link_up {
             info!("link_up = {:?}", self.link_up);
+            self.state_waker.wake();
         }
 
         #[cfg(feature = "dhcpv4")]
@@ -292,10 +1077,16 @@ impl<D: Device + 'static> Inner<D> {
                 self.unapply_config(s);
             }
         }
+
+        #[cfg(feature = "dhcpv4")]
+        self.poll_dhcp_client(cx, s, old_link_up);
         //if old_link_up || self.link_up {
         //    self.poll_configurator(timestamp)
         //}
 
+        #[cfg(feature = "proto-ipv6")]
+        self.poll_slaac(cx, s, old_link_up);
+
         if let Some(poll_at) = s.iface.poll_at(timestamp, &mut s.sockets) {
             let t = Timer::at(instant_from_smoltcp(poll_at));
             pin_mut!(t);
@@ -306,6 +1097,26 @@ impl<D: Device + 'static> Inner<D> {
     }
 }
 
+#[cfg(feature = "igmp")]
+fn multicast_error(e: smoltcp::Error) -> MulticastError {
+    match e {
+        smoltcp::Error::Exhausted => MulticastError::GroupTableFull,
+        _ => MulticastError::Unaddressable,
+    }
+}
+
+/// The MAC address the frames of the multicast group `addr` are sent to.
+#[cfg(feature = "igmp")]
+fn multicast_mac(addr: IpAddress) -> Option<[u8; 6]> {
+    match addr {
+        IpAddress::Ipv4(a) => Some([0x01, 0x00, 0x5e, a.0[1] & 0x7f, a.0[2], a.0[3]]),
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(a) => Some([0x33, 0x33, a.0[12], a.0[13], a.0[14], a.0[15]]),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
 fn instant_to_smoltcp(instant: Instant) -> SmolInstant {
     SmolInstant::from_millis(instant.as_millis() as i64)
 }
@@ -1,7 +1,8 @@
-use core::cell::UnsafeCell;
+use core::cell::{Cell, UnsafeCell};
 use core::future::poll_fn;
-use core::mem;
-use core::task::Poll;
+use core::mem::{self, ManuallyDrop};
+use core::ops::{Deref, DerefMut};
+use core::task::{Poll, Waker};
 
 use smoltcp::iface::{Interface, SocketHandle};
 use smoltcp::socket::tcp;
@@ -10,7 +11,7 @@ use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
 
 use super::stack::Stack;
 use crate::stack::SocketStack;
-use crate::Device;
+use crate::{tcp_stats, Device};
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -42,6 +43,30 @@ pub enum AcceptError {
     ConnectionReset,
 }
 
+/// Diagnostics of a TCP socket, see [`TcpSocket::info`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct TcpInfo {
+    pub state: tcp::State,
+    pub local_endpoint: Option<IpEndpoint>,
+    pub remote_endpoint: Option<IpEndpoint>,
+    /// Bytes written and not yet acknowledged by the peer.
+    pub send_queue: usize,
+    /// Bytes received and not yet read.
+    pub recv_queue: usize,
+    pub send_capacity: usize,
+    pub recv_capacity: usize,
+    /// Smoothed round-trip time of the connection, `None` until data sent was acknowledged.
+    ///
+    /// The stack measures it from the segments it sends and receives, as smoltcp keeps its own
+    /// estimate private, so it can differ slightly from the one smoltcp retransmits with.
+    pub rtt: Option<Duration>,
+    /// Segments sent again since the connection was opened, the peer not acknowledging them in
+    /// time.
+    pub retransmits: u32,
+}
+
 pub struct TcpSocket<'a> {
     io: TcpIo<'a>,
 }
@@ -148,18 +173,61 @@ impl<'a> TcpSocket<'a> {
         self.io.write(buf).await
     }
 
+    /// How long the peer may stay unresponsive before the connection is aborted, `None` to wait
+    /// forever.
+    pub fn timeout(&self) -> Option<Duration> {
+        unsafe { self.io.with(|s, _| s.timeout()) }
+    }
+
+    /// Set how long the peer may stay unresponsive before the connection is aborted, `None` to
+    /// wait forever.
+    ///
+    /// Combined with [`set_keep_alive`](Self::set_keep_alive), this detects dead peers on idle
+    /// connections. It can be changed at any time, including after connecting.
     pub fn set_timeout(&mut self, duration: Option<Duration>) {
         unsafe { self.io.with_mut(|s, _| s.set_timeout(duration)) }
     }
 
+    /// Interval between keep-alive packets on an idle connection, `None` if disabled.
+    pub fn keep_alive(&self) -> Option<Duration> {
+        unsafe { self.io.with(|s, _| s.keep_alive()) }
+    }
+
+    /// Set the interval between keep-alive packets on an idle connection, `None` to disable them.
+    ///
+    /// It can be changed at any time, including after connecting.
     pub fn set_keep_alive(&mut self, interval: Option<Duration>) {
         unsafe { self.io.with_mut(|s, _| s.set_keep_alive(interval)) }
     }
 
+    /// Time-to-live of the sent packets, `None` for the default of the stack.
+    pub fn hop_limit(&self) -> Option<u8> {
+        unsafe { self.io.with(|s, _| s.hop_limit()) }
+    }
+
+    /// Set the time-to-live of the sent packets, `None` for the default of the stack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hop_limit` is `Some(0)`.
     pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
         unsafe { self.io.with_mut(|s, _| s.set_hop_limit(hop_limit)) }
     }
 
+    /// Whether Nagle's algorithm is enabled, which is the default.
+    pub fn nagle_enabled(&self) -> bool {
+        unsafe { self.io.with(|s, _| s.nagle_enabled()) }
+    }
+
+    /// Enable or disable Nagle's algorithm.
+    ///
+    /// When enabled, small writes are delayed while data is unacknowledged, to be sent in
+    /// fewer segments. Disabling it lowers the latency of small messages, such as the ones of
+    /// request-response protocols. It can be changed at any time, including after connecting.
+    pub fn set_nagle_enabled(&mut self, enabled: bool) {
+        unsafe { self.io.with_mut(|s, _| s.set_nagle_enabled(enabled)) }
+    }
+
     pub fn local_endpoint(&self) -> Option<IpEndpoint> {
         unsafe { self.io.with(|s, _| s.local_endpoint()) }
     }
@@ -180,6 +248,49 @@ impl<'a> TcpSocket<'a> {
         unsafe { self.io.with_mut(|s, _| s.abort()) }
     }
 
+    /// Wait until the connection is fully closed, after [`close`](Self::close) or a reset.
+    ///
+    /// The socket can then accept or make another connection.
+    pub async fn wait_closed(&mut self) {
+        poll_fn(|cx| unsafe {
+            self.io.with_mut(|s, _| match s.state() {
+                tcp::State::Closed | tcp::State::TimeWait => Poll::Ready(()),
+                _ => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Diagnostics of the socket, such as the data waiting to be acknowledged, which grows when
+    /// the link or the peer is slow.
+    pub fn info(&self) -> TcpInfo {
+        unsafe {
+            let connections = (*self.io.stack.get()).tcp_connections;
+            self.io.with(|s, _| {
+                let connection = match (s.local_endpoint(), s.remote_endpoint()) {
+                    (Some(local), Some(remote)) => tcp_stats::get(connections, local, remote),
+                    _ => None,
+                };
+                TcpInfo {
+                    state: s.state(),
+                    local_endpoint: s.local_endpoint(),
+                    remote_endpoint: s.remote_endpoint(),
+                    send_queue: s.send_queue(),
+                    recv_queue: s.recv_queue(),
+                    send_capacity: s.send_capacity(),
+                    recv_capacity: s.recv_capacity(),
+                    rtt: connection
+                        .and_then(|c| c.rtt)
+                        .map(|rtt| Duration::from_micros(rtt.as_micros())),
+                    retransmits: connection.map_or(0, |c| c.retransmits),
+                }
+            })
+        }
+    }
+
     pub fn may_send(&self) -> bool {
         unsafe { self.io.with(|s, _| s.may_send()) }
     }
@@ -197,6 +308,235 @@ impl<'a> Drop for TcpSocket<'a> {
     }
 }
 
+/// State of a [`TcpListener`], holding the buffers of its `N` sockets.
+pub struct TcpListenerState<const N: usize, const TX_SZ: usize = 1024, const RX_SZ: usize = 1024> {
+    slots: [Slot; N],
+    rx_buffers: [[u8; RX_SZ]; N],
+    tx_buffers: [[u8; TX_SZ]; N],
+}
+
+impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpListenerState<N, TX_SZ, RX_SZ> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [Slot::NEW; N],
+            rx_buffers: [[0; RX_SZ]; N],
+            tx_buffers: [[0; TX_SZ]; N],
+        }
+    }
+}
+
+struct Slot {
+    handle: Cell<Option<SocketHandle>>,
+    state: Cell<SlotState>,
+    /// Waker of a task waiting in `accept`. The slots hold one each, so that up to `N` tasks
+    /// can wait at once.
+    accept_waker: Cell<Option<Waker>>,
+}
+
+impl Slot {
+    const NEW: Slot = Slot {
+        handle: Cell::new(None),
+        state: Cell::new(SlotState::Listening),
+        accept_waker: Cell::new(None),
+    };
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum SlotState {
+    /// Listening, or connected and waiting to be accepted.
+    Listening,
+    /// Handed out by `accept`.
+    Accepted,
+    /// Closed after being accepted, listening again once the connection is fully closed.
+    Closing,
+}
+
+/// TCP server socket accepting up to `N` simultaneous connections on a port.
+///
+/// Each of the `N` sockets listens, so connections are established while others are being
+/// handled, up to `N` at a time, and wait in the backlog until accepted. The sockets listen
+/// again when the connections are dropped.
+pub struct TcpListener<'d> {
+    stack: &'d UnsafeCell<SocketStack>,
+    local_endpoint: IpListenEndpoint,
+    slots: &'d [Slot],
+}
+
+impl<'d> TcpListener<'d> {
+    /// Listen on `local_endpoint` with the sockets of `state`.
+    ///
+    /// `StackResources` needs a socket for each of them.
+    pub fn new<D: Device, T, const N: usize, const TX_SZ: usize, const RX_SZ: usize>(
+        stack: &'d Stack<D>,
+        state: &'d mut TcpListenerState<N, TX_SZ, RX_SZ>,
+        local_endpoint: T,
+    ) -> Result<Self, AcceptError>
+    where
+        T: Into<IpListenEndpoint>,
+    {
+        let local_endpoint = local_endpoint.into();
+        if local_endpoint.port == 0 {
+            return Err(AcceptError::InvalidPort);
+        }
+        assert!(N > 0);
+
+        let TcpListenerState {
+            slots,
+            rx_buffers,
+            tx_buffers,
+        } = state;
+        let slots: &'d [Slot; N] = slots;
+
+        // safety: not accessed reentrantly.
+        let s = unsafe { &mut *stack.socket.get() };
+        for (slot, (rx_buffer, tx_buffer)) in slots.iter().zip(rx_buffers.iter_mut().zip(tx_buffers)) {
+            let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(&mut rx_buffer[..]) };
+            let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(&mut tx_buffer[..]) };
+            let mut socket = tcp::Socket::new(tcp::SocketBuffer::new(rx_buffer), tcp::SocketBuffer::new(tx_buffer));
+            unwrap!(socket.listen(local_endpoint));
+            slot.handle.set(Some(s.sockets.add(socket)));
+            slot.state.set(SlotState::Listening);
+        }
+        s.waker.wake();
+
+        Ok(Self {
+            stack: &stack.socket,
+            local_endpoint,
+            slots,
+        })
+    }
+
+    /// Wait for a connection to be established, or take one from the backlog.
+    ///
+    /// It can be called from several tasks at once, each handling its own connections. Up to `N`
+    /// tasks can wait at once without waking each other needlessly.
+    pub async fn accept(&self) -> AcceptedSocket<'_> {
+        // The sockets wake the last task which polled them. When it stops waiting, because it
+        // accepted a connection or was cancelled, the other waiting tasks are woken so that one
+        // of them takes over.
+        let _wake_others = WakeAcceptTasks(self);
+
+        poll_fn(|cx| {
+            // safety: not accessed reentrantly.
+            let s = unsafe { &mut *self.stack.get() };
+            for slot in self.slots {
+                let handle = unwrap!(slot.handle.get());
+                let socket = s.sockets.get_mut::<tcp::Socket>(handle);
+                match (slot.state.get(), socket.state()) {
+                    (SlotState::Accepted, _) => continue,
+                    (_, tcp::State::Closed | tcp::State::TimeWait) => {
+                        // The connection ended, before or after being accepted.
+                        unwrap!(socket.listen(self.local_endpoint));
+                        slot.state.set(SlotState::Listening);
+                        s.waker.wake();
+                    }
+                    (SlotState::Listening, tcp::State::Listen | tcp::State::SynReceived) => {}
+                    (SlotState::Listening, _) => {
+                        slot.state.set(SlotState::Accepted);
+                        return Poll::Ready(AcceptedSocket {
+                            socket: ManuallyDrop::new(TcpSocket {
+                                io: TcpIo {
+                                    stack: self.stack,
+                                    handle,
+                                },
+                            }),
+                            slot,
+                        });
+                    }
+                    (SlotState::Closing, _) => {}
+                }
+                socket.register_send_waker(cx.waker());
+            }
+            self.register_accept_waker(cx.waker());
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn register_accept_waker(&self, waker: &Waker) {
+        let mut free = None;
+        for slot in self.slots {
+            match slot.accept_waker.take() {
+                // The task is already registered.
+                Some(w) if w.will_wake(waker) => {
+                    slot.accept_waker.set(Some(w));
+                    return;
+                }
+                Some(w) => slot.accept_waker.set(Some(w)),
+                None => free = free.or(Some(slot)),
+            }
+        }
+
+        match free {
+            Some(slot) => slot.accept_waker.set(Some(waker.clone())),
+            None => {
+                // More than `N` tasks are waiting. Wake them all, the ones still waiting
+                // register again.
+                self.wake_accept_tasks();
+                self.slots[0].accept_waker.set(Some(waker.clone()));
+            }
+        }
+    }
+
+    fn wake_accept_tasks(&self) {
+        for slot in self.slots {
+            if let Some(waker) = slot.accept_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+struct WakeAcceptTasks<'a, 'd>(&'a TcpListener<'d>);
+
+impl<'a, 'd> Drop for WakeAcceptTasks<'a, 'd> {
+    fn drop(&mut self) {
+        self.0.wake_accept_tasks();
+    }
+}
+
+impl<'d> Drop for TcpListener<'d> {
+    fn drop(&mut self) {
+        // safety: not accessed reentrantly.
+        let s = unsafe { &mut *self.stack.get() };
+        for slot in self.slots {
+            if let Some(handle) = slot.handle.take() {
+                s.sockets.remove(handle);
+            }
+        }
+    }
+}
+
+/// Connection accepted by a [`TcpListener`], used as a [`TcpSocket`].
+///
+/// It is closed when dropped, and its socket then listens again.
+pub struct AcceptedSocket<'l> {
+    socket: ManuallyDrop<TcpSocket<'l>>,
+    slot: &'l Slot,
+}
+
+impl<'l> Deref for AcceptedSocket<'l> {
+    type Target = TcpSocket<'l>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.socket
+    }
+}
+
+impl<'l> DerefMut for AcceptedSocket<'l> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.socket
+    }
+}
+
+impl<'l> Drop for AcceptedSocket<'l> {
+    fn drop(&mut self) {
+        // The socket is kept by the listener.
+        self.socket.close();
+        self.slot.state.set(SlotState::Closing);
+    }
+}
+
 // =======================
 
 #[derive(Copy, Clone)]
@@ -0,0 +1,366 @@
+//! Round-trip time and retransmissions of the TCP connections, measured on the frames going
+//! through the device.
+//!
+//! smoltcp keeps its own estimates private, so the stack follows each connection from the
+//! segments it sends and receives: a segment sent again below the highest sequence number sent
+//! is a retransmission, and the time from sending new data to its acknowledgment is a round-trip
+//! time sample, skipping the retransmitted data as Karn's algorithm does.
+
+use core::cell::Cell;
+
+use embassy_time::{Duration, Instant};
+use smoltcp::phy::Medium;
+#[cfg(feature = "proto-ipv6")]
+use smoltcp::wire::Ipv6Address;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+const IPPROTO_TCP: u8 = 6;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+/// Measurements of one connection, see [`TcpSocket::info`](crate::tcp::TcpSocket::info).
+#[derive(Clone, Copy)]
+pub(crate) struct Connection {
+    in_use: bool,
+    local: IpEndpoint,
+    remote: IpEndpoint,
+    /// Sequence number of the SYN, to tell its retransmissions from a new connection.
+    initial_seq: u32,
+    /// End of the data sent, in sequence space.
+    max_seq: u32,
+    /// End of the data being timed, and when it was sent.
+    timed: Option<(u32, Instant)>,
+    last_used: Instant,
+    pub rtt: Option<Duration>,
+    pub retransmits: u32,
+}
+
+impl Connection {
+    pub const EMPTY: Self = Self {
+        in_use: false,
+        local: IpEndpoint {
+            addr: IpAddress::Ipv4(Ipv4Address::UNSPECIFIED),
+            port: 0,
+        },
+        remote: IpEndpoint {
+            addr: IpAddress::Ipv4(Ipv4Address::UNSPECIFIED),
+            port: 0,
+        },
+        initial_seq: 0,
+        max_seq: 0,
+        timed: None,
+        last_used: Instant::from_ticks(0),
+        rtt: None,
+        retransmits: 0,
+    };
+
+    fn new(local: IpEndpoint, remote: IpEndpoint, seq: u32) -> Self {
+        Self {
+            in_use: true,
+            local,
+            remote,
+            initial_seq: seq,
+            max_seq: seq,
+            ..Self::EMPTY
+        }
+    }
+
+    fn is(&self, local: IpEndpoint, remote: IpEndpoint) -> bool {
+        self.in_use && self.local == local && self.remote == remote
+    }
+
+    fn sent(&mut self, seq: u32, len: u32, now: Instant) {
+        let end = seq.wrapping_add(len);
+        if seq_lt(seq, self.max_seq) {
+            self.retransmits = self.retransmits.wrapping_add(1);
+            // The acknowledgment could be for either copy.
+            if matches!(self.timed, Some((timed_end, _)) if seq_lt(seq, timed_end)) {
+                self.timed = None;
+            }
+            if seq_lt(self.max_seq, end) {
+                self.max_seq = end;
+            }
+        } else {
+            self.max_seq = end;
+            if self.timed.is_none() {
+                self.timed = Some((end, now));
+            }
+        }
+    }
+
+    fn acked(&mut self, ack: u32, now: Instant) {
+        if let Some((end, sent_at)) = self.timed {
+            if !seq_lt(ack, end) {
+                let sample = now - sent_at;
+                // Smoothed as in RFC 6298.
+                self.rtt = Some(match self.rtt {
+                    Some(rtt) => Duration::from_ticks((rtt.as_ticks() * 7 + sample.as_ticks()) / 8),
+                    None => sample,
+                });
+                self.timed = None;
+            }
+        }
+    }
+}
+
+/// Whether `a` is before `b` in sequence space, which wraps around.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Fields of a TCP segment used for the measurements.
+struct Segment {
+    src: IpEndpoint,
+    dst: IpEndpoint,
+    seq: u32,
+    ack: Option<u32>,
+    syn: bool,
+    /// Length in sequence space, counting the SYN and FIN flags.
+    len: u32,
+}
+
+fn parse(medium: Medium, frame: &[u8]) -> Option<Segment> {
+    let packet = match medium {
+        #[cfg(feature = "medium-ethernet")]
+        Medium::Ethernet => match frame.get(12..14)? {
+            // IPv4
+            [0x08, 0x00] => frame.get(14..)?,
+            // IPv6
+            #[cfg(feature = "proto-ipv6")]
+            [0x86, 0xDD] => frame.get(14..)?,
+            _ => return None,
+        },
+        #[cfg(feature = "medium-ip")]
+        Medium::Ip => frame,
+        // The headers are compressed with 6LoWPAN.
+        #[cfg(feature = "medium-ieee802154")]
+        Medium::Ieee802154 => return None,
+    };
+
+    let (src, dst, segment) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = (packet[0] & 0x0F) as usize * 4;
+            let total_len = u16::from_be_bytes(packet.get(2..4)?.try_into().unwrap()) as usize;
+            // Fragments other than the first don't start with a TCP header.
+            let fragment_offset = u16::from_be_bytes(packet.get(6..8)?.try_into().unwrap()) & 0x1FFF;
+            if packet.get(9)? != &IPPROTO_TCP || fragment_offset != 0 || header_len < 20 {
+                return None;
+            }
+            let src = IpAddress::Ipv4(Ipv4Address::from_bytes(packet.get(12..16)?));
+            let dst = IpAddress::Ipv4(Ipv4Address::from_bytes(packet.get(16..20)?));
+            (src, dst, packet.get(header_len..total_len)?)
+        }
+        #[cfg(feature = "proto-ipv6")]
+        6 => {
+            // Extension headers aren't followed, the stack doesn't send any with TCP.
+            let payload_len = u16::from_be_bytes(packet.get(4..6)?.try_into().unwrap()) as usize;
+            if packet.get(6)? != &IPPROTO_TCP {
+                return None;
+            }
+            let src = IpAddress::Ipv6(Ipv6Address::from_bytes(packet.get(8..24)?));
+            let dst = IpAddress::Ipv6(Ipv6Address::from_bytes(packet.get(24..40)?));
+            (src, dst, packet.get(40..40 + payload_len)?)
+        }
+        _ => return None,
+    };
+
+    let header_len = (segment.get(12)? >> 4) as usize * 4;
+    let flags = *segment.get(13)?;
+    if header_len < 20 || segment.len() < header_len {
+        return None;
+    }
+    let syn = flags & TCP_SYN != 0;
+    let fin = flags & TCP_FIN != 0;
+    Some(Segment {
+        src: IpEndpoint::new(src, u16::from_be_bytes([segment[0], segment[1]])),
+        dst: IpEndpoint::new(dst, u16::from_be_bytes([segment[2], segment[3]])),
+        seq: u32::from_be_bytes(segment[4..8].try_into().unwrap()),
+        ack: (flags & TCP_ACK != 0).then(|| u32::from_be_bytes(segment[8..12].try_into().unwrap())),
+        syn,
+        len: (segment.len() - header_len) as u32 + syn as u32 + fin as u32,
+    })
+}
+
+/// Update the measurements with a frame sent to the device.
+pub(crate) fn on_sent(connections: &[Cell<Connection>], medium: Medium, frame: &[u8], now: Instant) {
+    let segment = match parse(medium, frame) {
+        Some(segment) => segment,
+        None => return,
+    };
+    let (local, remote) = (segment.src, segment.dst);
+
+    let found = connections.iter().find(|c| c.get().is(local, remote));
+    let cell = match found {
+        // A SYN sent again is a retransmission, another one starts a new connection.
+        Some(cell) if !segment.syn || cell.get().initial_seq == segment.seq => cell,
+        Some(cell) => {
+            cell.set(Connection::new(local, remote, segment.seq));
+            cell
+        }
+        None if segment.syn => {
+            // Reuse the connection unused for the longest time, which is closed by now.
+            let cell = match connections.iter().min_by_key(|c| (c.get().in_use, c.get().last_used)) {
+                Some(cell) => cell,
+                None => return,
+            };
+            cell.set(Connection::new(local, remote, segment.seq));
+            cell
+        }
+        None => return,
+    };
+
+    let mut connection = cell.get();
+    connection.last_used = now;
+    if segment.len != 0 {
+        connection.sent(segment.seq, segment.len, now);
+    }
+    cell.set(connection);
+}
+
+/// Update the measurements with a frame received from the device.
+pub(crate) fn on_received(connections: &[Cell<Connection>], medium: Medium, frame: &[u8], now: Instant) {
+    let segment = match parse(medium, frame) {
+        Some(segment) => segment,
+        None => return,
+    };
+    if let Some(cell) = connections.iter().find(|c| c.get().is(segment.dst, segment.src)) {
+        let mut connection = cell.get();
+        connection.last_used = now;
+        if let Some(ack) = segment.ack {
+            connection.acked(ack, now);
+        }
+        cell.set(connection);
+    }
+}
+
+/// Measurements of the connection from `local` to `remote`, if it was seen.
+pub(crate) fn get(connections: &[Cell<Connection>], local: IpEndpoint, remote: IpEndpoint) -> Option<Connection> {
+    connections.iter().map(Cell::get).find(|c| c.is(local, remote))
+}
+
+#[cfg(all(test, feature = "medium-ethernet"))]
+mod tests {
+    use super::*;
+
+    const LOCAL: [u8; 4] = [192, 168, 1, 50];
+    const REMOTE: [u8; 4] = [192, 168, 1, 1];
+    const SYN: u8 = TCP_SYN;
+    const ACK: u8 = TCP_ACK;
+
+    /// An Ethernet frame with a TCP segment from `src` to `dst`, carrying `len` bytes of data.
+    fn frame(src: ([u8; 4], u16), dst: ([u8; 4], u16), seq: u32, ack: u32, flags: u8, len: usize) -> [u8; 64] {
+        let mut frame = [0; 64];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        let ip = &mut frame[14..];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&(40 + len as u16).to_be_bytes());
+        ip[9] = IPPROTO_TCP;
+        ip[12..16].copy_from_slice(&src.0);
+        ip[16..20].copy_from_slice(&dst.0);
+        let tcp = &mut ip[20..];
+        tcp[0..2].copy_from_slice(&src.1.to_be_bytes());
+        tcp[2..4].copy_from_slice(&dst.1.to_be_bytes());
+        tcp[4..8].copy_from_slice(&seq.to_be_bytes());
+        tcp[8..12].copy_from_slice(&ack.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = flags;
+        frame
+    }
+
+    fn sent(connections: &[Cell<Connection>], seq: u32, ack: u32, flags: u8, len: usize, ms: u64) {
+        let frame = frame((LOCAL, 1025), (REMOTE, 80), seq, ack, flags, len);
+        on_sent(connections, Medium::Ethernet, &frame, Instant::from_millis(ms));
+    }
+
+    fn received(connections: &[Cell<Connection>], seq: u32, ack: u32, flags: u8, len: usize, ms: u64) {
+        let frame = frame((REMOTE, 80), (LOCAL, 1025), seq, ack, flags, len);
+        on_received(connections, Medium::Ethernet, &frame, Instant::from_millis(ms));
+    }
+
+    fn connection(connections: &[Cell<Connection>]) -> Option<Connection> {
+        let local = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(LOCAL)), 1025);
+        let remote = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(REMOTE)), 80);
+        get(connections, local, remote)
+    }
+
+    #[test]
+    fn handshake_gives_first_rtt() {
+        let mut storage = [Connection::EMPTY; 2];
+        let connections = Cell::from_mut(&mut storage[..]).as_slice_of_cells();
+
+        sent(connections, 1000, 0, SYN, 0, 0);
+        received(connections, 5000, 1001, SYN | ACK, 0, 40);
+
+        let c = connection(connections).unwrap();
+        assert_eq!(c.rtt, Some(Duration::from_millis(40)));
+        assert_eq!(c.retransmits, 0);
+    }
+
+    #[test]
+    fn rtt_is_smoothed() {
+        let mut storage = [Connection::EMPTY; 2];
+        let connections = Cell::from_mut(&mut storage[..]).as_slice_of_cells();
+
+        sent(connections, 1000, 0, SYN, 0, 0);
+        received(connections, 5000, 1001, SYN | ACK, 0, 80);
+        sent(connections, 1001, 5001, ACK, 10, 100);
+        received(connections, 5001, 1011, ACK, 0, 260);
+
+        // 7/8 of 80 ms, and 1/8 of 160 ms.
+        assert_eq!(connection(connections).unwrap().rtt, Some(Duration::from_millis(90)));
+    }
+
+    #[test]
+    fn retransmitted_data_is_counted_and_not_timed() {
+        let mut storage = [Connection::EMPTY; 2];
+        let connections = Cell::from_mut(&mut storage[..]).as_slice_of_cells();
+
+        sent(connections, 1000, 0, SYN, 0, 0);
+        received(connections, 5000, 1001, SYN | ACK, 0, 40);
+        sent(connections, 1001, 5001, ACK, 10, 100);
+        sent(connections, 1001, 5001, ACK, 10, 1100);
+        received(connections, 5001, 1011, ACK, 0, 1140);
+
+        let c = connection(connections).unwrap();
+        assert_eq!(c.rtt, Some(Duration::from_millis(40)));
+        assert_eq!(c.retransmits, 1);
+    }
+
+    #[test]
+    fn syn_sent_again_is_a_retransmission() {
+        let mut storage = [Connection::EMPTY; 2];
+        let connections = Cell::from_mut(&mut storage[..]).as_slice_of_cells();
+
+        sent(connections, 1000, 0, SYN, 0, 0);
+        sent(connections, 1000, 0, SYN, 0, 1000);
+        assert_eq!(connection(connections).unwrap().retransmits, 1);
+
+        // Another initial sequence number is a new connection between the same endpoints.
+        sent(connections, 9000, 0, SYN, 0, 60_000);
+        assert_eq!(connection(connections).unwrap().retransmits, 0);
+    }
+
+    #[test]
+    fn oldest_connection_is_replaced() {
+        let mut storage = [Connection::EMPTY; 1];
+        let connections = Cell::from_mut(&mut storage[..]).as_slice_of_cells();
+
+        sent(connections, 1000, 0, SYN, 0, 0);
+        let other = frame((LOCAL, 1026), (REMOTE, 80), 2000, 0, SYN, 0);
+        on_sent(connections, Medium::Ethernet, &other, Instant::from_millis(10));
+
+        assert!(connection(connections).is_none());
+    }
+
+    #[test]
+    fn segments_without_connection_are_ignored() {
+        let mut storage = [Connection::EMPTY; 2];
+        let connections = Cell::from_mut(&mut storage[..]).as_slice_of_cells();
+
+        sent(connections, 1001, 5001, ACK, 10, 0);
+        received(connections, 5001, 1011, ACK, 0, 10);
+        assert!(connection(connections).is_none());
+    }
+}
@@ -0,0 +1,108 @@
+//! TLS client sockets, using [`embedded-tls`](https://docs.rs/embedded-tls).
+//!
+//! A [`TlsSocket`] wraps a connected [`TcpSocket`]. The handshake is done by
+//! [`TlsSocket::open`], after which data is encrypted transparently. The server certificate is
+//! checked by the `Verifier` type given to `open`: [`NoVerify`] accepts any certificate, which
+//! is only suitable for testing, while other implementations of [`TlsVerifier`] can check it
+//! against a CA or pin it. Pre-shared keys are set up with [`TlsConfig::with_psk`].
+//!
+//! Only TLS 1.3 is supported.
+
+use core::future::Future;
+
+pub use embedded_tls::{
+    Aes128GcmSha256, Aes256GcmSha384, Certificate, NoVerify, TlsCipherSuite, TlsConfig, TlsError, TlsVerifier,
+};
+use embedded_tls::{TlsConnection, TlsContext};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::tcp::TcpSocket;
+
+/// Size of the buffers needed to hold the largest TLS records.
+pub const MAX_RECORD_SIZE: usize = 16640;
+
+/// TLS client socket, wrapping a [`TcpSocket`].
+///
+/// The read buffer must hold a whole TLS record, so it should be [`MAX_RECORD_SIZE`] bytes,
+/// unless the server is known to send smaller records. The write buffer limits the size of the
+/// records sent.
+pub struct TlsSocket<'a, CipherSuite: TlsCipherSuite + 'static = Aes128GcmSha256> {
+    conn: TlsConnection<'a, TcpSocket<'a>, CipherSuite>,
+}
+
+impl<'a, CipherSuite: TlsCipherSuite + 'static> TlsSocket<'a, CipherSuite> {
+    /// Create a TLS socket over `socket`, which must be connected already.
+    pub fn new(socket: TcpSocket<'a>, read_buffer: &'a mut [u8], write_buffer: &'a mut [u8]) -> Self {
+        Self {
+            conn: TlsConnection::new(socket, read_buffer, write_buffer),
+        }
+    }
+
+    /// Do the TLS handshake, with the server certificate checked by `Verifier`.
+    ///
+    /// The server name in `config` is used for SNI, and should be set when connecting by name.
+    pub async fn open<RNG, Verifier>(
+        &mut self,
+        config: &TlsConfig<'a, CipherSuite>,
+        rng: &mut RNG,
+    ) -> Result<(), TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+        Verifier: TlsVerifier<'a, CipherSuite>,
+    {
+        self.conn.open::<RNG, Verifier>(TlsContext::new(config, rng)).await
+    }
+
+    /// Read decrypted data into `buf`, returning how many bytes were read.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, TlsError> {
+        self.conn.read(buf).await
+    }
+
+    /// Write data to be encrypted, returning how many bytes were written. The data is buffered
+    /// until the write buffer is full or [`flush`](Self::flush) is called.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, TlsError> {
+        self.conn.write(buf).await
+    }
+
+    /// Send the buffered data as a TLS record.
+    pub async fn flush(&mut self) -> Result<(), TlsError> {
+        self.conn.flush().await
+    }
+
+    /// Send a close notification to the server, and return the TCP socket.
+    pub async fn close(self) -> Result<TcpSocket<'a>, (TcpSocket<'a>, TlsError)> {
+        self.conn.close().await
+    }
+}
+
+impl<'a, CipherSuite: TlsCipherSuite + 'static> embedded_io::Io for TlsSocket<'a, CipherSuite> {
+    type Error = TlsError;
+}
+
+impl<'a, CipherSuite: TlsCipherSuite + 'static> embedded_io::asynch::Read for TlsSocket<'a, CipherSuite> {
+    type ReadFuture<'m> = impl Future<Output = Result<usize, Self::Error>>
+    where
+        Self: 'm;
+
+    fn read<'m>(&'m mut self, buf: &'m mut [u8]) -> Self::ReadFuture<'m> {
+        self.conn.read(buf)
+    }
+}
+
+impl<'a, CipherSuite: TlsCipherSuite + 'static> embedded_io::asynch::Write for TlsSocket<'a, CipherSuite> {
+    type WriteFuture<'m> = impl Future<Output = Result<usize, Self::Error>>
+    where
+        Self: 'm;
+
+    fn write<'m>(&'m mut self, buf: &'m [u8]) -> Self::WriteFuture<'m> {
+        self.conn.write(buf)
+    }
+
+    type FlushFuture<'m> = impl Future<Output = Result<(), Self::Error>>
+    where
+        Self: 'm;
+
+    fn flush<'m>(&'m mut self) -> Self::FlushFuture<'m> {
+        self.conn.flush()
+    }
+}
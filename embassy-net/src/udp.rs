@@ -127,6 +127,54 @@ impl<'a> UdpSocket<'a> {
         .await
     }
 
+    /// Receive a datagram, handing it to `f` in place in the receive buffer, with the endpoint
+    /// it came from, without copying it.
+    ///
+    /// `f` runs while the stack is borrowed, so it must not use any socket.
+    pub async fn recv_from_with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&[u8], IpEndpoint) -> R,
+    {
+        let mut f = Some(f);
+        poll_fn(move |cx| unsafe {
+            self.with_mut(|s, _| match s.recv() {
+                Ok((data, remote_endpoint)) => Poll::Ready(unwrap!(f.take())(data, remote_endpoint)),
+                // No data ready
+                Err(udp::RecvError::Exhausted) => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Send a datagram of `size` bytes to `remote_endpoint`, written by `f` in place in the
+    /// transmit buffer, without copying it.
+    ///
+    /// The buffer handed to `f` holds stale data, it must write all of it. `f` runs while the
+    /// stack is borrowed, so it must not use any socket. Datagrams larger than the transmit
+    /// buffer are never sent.
+    pub async fn send_to_with<T, F, R>(&self, size: usize, remote_endpoint: T, f: F) -> Result<R, Error>
+    where
+        T: Into<IpEndpoint>,
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let remote_endpoint = remote_endpoint.into();
+        let mut f = Some(f);
+        poll_fn(move |cx| unsafe {
+            self.with_mut(|s, _| match s.send(size, remote_endpoint) {
+                Ok(buf) => Poll::Ready(Ok(unwrap!(f.take())(buf))),
+                Err(udp::SendError::BufferFull) => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                Err(udp::SendError::Unaddressable) => Poll::Ready(Err(Error::NoRoute)),
+            })
+        })
+        .await
+    }
+
     pub fn endpoint(&self) -> IpListenEndpoint {
         unsafe { self.with(|s, _| s.endpoint()) }
     }
@@ -0,0 +1,449 @@
+//! WebSocket client.
+//!
+//! [`WebSocket::connect`] performs the opening handshake over a connection implementing the
+//! `embedded-io` traits, such as a connected [`TcpSocket`](crate::tcp::TcpSocket), or a
+//! [`TlsSocket`](crate::tls::TlsSocket) for `wss://` URLs. Messages are then exchanged with
+//! [`WebSocket::send`] and [`WebSocket::recv`]. Large messages can be sent in fragments, and
+//! fragmented messages are reassembled when received.
+//!
+//! The pings of the server are answered automatically, while receiving.
+
+use core::str;
+
+use embedded_io::asynch::{Read, Write};
+use rand_core::RngCore;
+
+use crate::http::{find_head_end, find_header, parse_status_line};
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Maximum payload length of the control frames.
+const MAX_CONTROL_LEN: usize = 125;
+
+/// Appended to the key of the handshake, to compute the accept value (RFC 6455).
+const GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Status code of a normal closure.
+pub const CLOSE_NORMAL: u16 = 1000;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The connection failed.
+    Io(E),
+    /// The connection was closed.
+    ConnectionClosed,
+    /// The server refused the handshake, or answered it wrongly.
+    HandshakeFailed,
+    /// The head of the handshake response doesn't fit in the buffer.
+    HeadersTooLarge,
+    /// The message doesn't fit in the buffer, or a control frame is too long. The connection
+    /// can't be used anymore.
+    MessageTooLarge,
+    /// The server broke the protocol. The connection can't be used anymore.
+    InvalidFrame,
+}
+
+/// Kind of a data message.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageKind {
+    /// UTF-8 text.
+    Text,
+    Binary,
+}
+
+/// Message received by [`WebSocket::recv`], whose payload is in the buffer given to it.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Received {
+    /// A text message of the given length, which was checked to be UTF-8.
+    Text(usize),
+    /// A binary message of the given length.
+    Binary(usize),
+    /// A pong of the given length, answering a ping.
+    Pong(usize),
+    /// The server closed the connection, with the given status code.
+    Close(Option<u16>),
+}
+
+/// WebSocket client, over the connection `C`, masking the frames with random numbers of `R`.
+pub struct WebSocket<'b, C, R> {
+    conn: C,
+    rng: R,
+    /// Data received after the handshake response, in `buf[pos..len]`.
+    buf: &'b mut [u8],
+    pos: usize,
+    len: usize,
+    /// Whether a fragmented message is being sent.
+    fragmenting: bool,
+    close_sent: bool,
+}
+
+impl<'b, C: Read + Write, R: RngCore> WebSocket<'b, C, R> {
+    /// Perform the opening handshake for the resource `path` of `host` over `conn`.
+    ///
+    /// `protocol` is sent as the subprotocol of the connection, if any. `buf` holds the head
+    /// of the handshake response.
+    pub async fn connect(
+        mut conn: C,
+        mut rng: R,
+        host: &str,
+        path: &str,
+        protocol: Option<&str>,
+        buf: &'b mut [u8],
+    ) -> Result<Self, Error<C::Error>> {
+        let mut nonce = [0; 16];
+        rng.fill_bytes(&mut nonce);
+        let mut key = [0; 24];
+        let key = base64(&nonce, &mut key);
+
+        write_all(&mut conn, b"GET ").await?;
+        write_all(&mut conn, path.as_bytes()).await?;
+        write_all(&mut conn, b" HTTP/1.1\r\nHost: ").await?;
+        write_all(&mut conn, host.as_bytes()).await?;
+        write_all(
+            &mut conn,
+            b"\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: ",
+        )
+        .await?;
+        write_all(&mut conn, key).await?;
+        write_all(&mut conn, b"\r\nSec-WebSocket-Version: 13\r\n").await?;
+        if let Some(protocol) = protocol {
+            write_all(&mut conn, b"Sec-WebSocket-Protocol: ").await?;
+            write_all(&mut conn, protocol.as_bytes()).await?;
+            write_all(&mut conn, b"\r\n").await?;
+        }
+        write_all(&mut conn, b"\r\n").await?;
+        conn.flush().await.map_err(Error::Io)?;
+
+        let mut len = 0;
+        let head_len = loop {
+            if let Some(head_len) = find_head_end(&buf[..len]) {
+                break head_len;
+            }
+            if len == buf.len() {
+                return Err(Error::HeadersTooLarge);
+            }
+            let n = conn.read(&mut buf[len..]).await.map_err(Error::Io)?;
+            if n == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            len += n;
+        };
+
+        let (status, _, headers) = parse_status_line::<()>(&buf[..head_len]).map_err(|_| Error::HandshakeFailed)?;
+        let mut accept = [0; 60];
+        accept[..24].copy_from_slice(key);
+        accept[24..].copy_from_slice(GUID);
+        let mut expected = [0; 28];
+        let expected = base64(&sha1(&accept), &mut expected);
+        let upgraded = find_header(headers, "Upgrade").map_or(false, |v| v.eq_ignore_ascii_case("websocket"));
+        if status != 101 || !upgraded || find_header(headers, "Sec-WebSocket-Accept") != str::from_utf8(expected).ok() {
+            return Err(Error::HandshakeFailed);
+        }
+
+        Ok(Self {
+            conn,
+            rng,
+            buf,
+            pos: head_len,
+            len,
+            fragmenting: false,
+            close_sent: false,
+        })
+    }
+
+    /// Send a whole message.
+    pub async fn send(&mut self, kind: MessageKind, data: &[u8]) -> Result<(), Error<C::Error>> {
+        self.send_fragment(kind, data, true).await
+    }
+
+    /// Send a fragment of a message, `last` being whether it's the last one.
+    ///
+    /// The kind of the fragments following the first one is ignored.
+    pub async fn send_fragment(&mut self, kind: MessageKind, data: &[u8], last: bool) -> Result<(), Error<C::Error>> {
+        let opcode = match (self.fragmenting, kind) {
+            (true, _) => OP_CONTINUATION,
+            (false, MessageKind::Text) => OP_TEXT,
+            (false, MessageKind::Binary) => OP_BINARY,
+        };
+        self.send_frame(opcode, last, data).await?;
+        self.fragmenting = !last;
+        Ok(())
+    }
+
+    /// Send a ping, of at most 125 bytes. The pong is returned by [`WebSocket::recv`].
+    pub async fn ping(&mut self, data: &[u8]) -> Result<(), Error<C::Error>> {
+        if data.len() > MAX_CONTROL_LEN {
+            return Err(Error::MessageTooLarge);
+        }
+        self.send_frame(OP_PING, true, data).await
+    }
+
+    /// Start the closing handshake, with the status `code` and `reason`.
+    ///
+    /// [`WebSocket::recv`] should then be called until it returns the close message of the
+    /// server, before closing the connection.
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), Error<C::Error>> {
+        let mut payload = [0; MAX_CONTROL_LEN];
+        let len = 2 + reason.len();
+        if len > MAX_CONTROL_LEN {
+            return Err(Error::MessageTooLarge);
+        }
+        payload[..2].copy_from_slice(&code.to_be_bytes());
+        payload[2..len].copy_from_slice(reason.as_bytes());
+        self.send_frame(OP_CLOSE, true, &payload[..len]).await?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    /// Receive the next message into `buf`, reassembling the fragmented ones.
+    ///
+    /// Pings are answered meanwhile. When the server closes the connection, the close message
+    /// is answered if [`WebSocket::close`] wasn't called, and the connection should be closed.
+    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<Received, Error<C::Error>> {
+        let mut kind = None;
+        let mut len = 0;
+        loop {
+            let mut header = [0; 2];
+            self.read_exact(&mut header).await?;
+            let fin = header[0] & 0x80 != 0;
+            let opcode = header[0] & 0x0f;
+            // Extensions aren't negotiated, so the reserved bits must be unset, and the frames
+            // of the server must not be masked.
+            if header[0] & 0x70 != 0 || header[1] & 0x80 != 0 {
+                return Err(Error::InvalidFrame);
+            }
+            let payload_len = match header[1] & 0x7f {
+                126 => {
+                    let mut ext = [0; 2];
+                    self.read_exact(&mut ext).await?;
+                    u16::from_be_bytes(ext) as u64
+                }
+                127 => {
+                    let mut ext = [0; 8];
+                    self.read_exact(&mut ext).await?;
+                    u64::from_be_bytes(ext)
+                }
+                n => n as u64,
+            };
+
+            match opcode {
+                OP_CONTINUATION | OP_TEXT | OP_BINARY => {
+                    match (opcode, kind) {
+                        (OP_CONTINUATION, None) => return Err(Error::InvalidFrame),
+                        (OP_CONTINUATION, Some(_)) => {}
+                        (_, Some(_)) => return Err(Error::InvalidFrame),
+                        (OP_TEXT, None) => kind = Some(MessageKind::Text),
+                        (_, None) => kind = Some(MessageKind::Binary),
+                    }
+                    let end = usize::try_from(payload_len)
+                        .ok()
+                        .and_then(|n| len.checked_add(n))
+                        .ok_or(Error::MessageTooLarge)?;
+                    let dst = buf.get_mut(len..end).ok_or(Error::MessageTooLarge)?;
+                    self.read_exact(dst).await?;
+                    len = end;
+
+                    if fin {
+                        return match kind {
+                            Some(MessageKind::Text) if str::from_utf8(&buf[..len]).is_err() => Err(Error::InvalidFrame),
+                            Some(MessageKind::Text) => Ok(Received::Text(len)),
+                            _ => Ok(Received::Binary(len)),
+                        };
+                    }
+                }
+                OP_CLOSE | OP_PING | OP_PONG => {
+                    // Control frames may be interleaved with the fragments of a message.
+                    if !fin || payload_len > MAX_CONTROL_LEN as u64 {
+                        return Err(Error::InvalidFrame);
+                    }
+                    let mut payload = [0; MAX_CONTROL_LEN];
+                    let payload = &mut payload[..payload_len as usize];
+                    self.read_exact(payload).await?;
+
+                    match opcode {
+                        OP_PING => self.send_frame(OP_PONG, true, payload).await?,
+                        // A pong received in the middle of a message is discarded, as the
+                        // buffer is in use.
+                        OP_PONG if kind.is_none() => {
+                            let dst = buf.get_mut(..payload.len()).ok_or(Error::MessageTooLarge)?;
+                            dst.copy_from_slice(payload);
+                            return Ok(Received::Pong(payload.len()));
+                        }
+                        OP_PONG => {}
+                        _ => {
+                            let code = payload.get(..2).map(|c| u16::from_be_bytes([c[0], c[1]]));
+                            if !self.close_sent {
+                                // Echo the status code.
+                                let len = payload.len().min(2);
+                                self.send_frame(OP_CLOSE, true, &payload[..len]).await?;
+                                self.close_sent = true;
+                            }
+                            return Ok(Received::Close(code));
+                        }
+                    }
+                }
+                _ => return Err(Error::InvalidFrame),
+            }
+        }
+    }
+
+    /// The connection, for example to close it.
+    pub fn into_inner(self) -> C {
+        self.conn
+    }
+
+    async fn send_frame(&mut self, opcode: u8, fin: bool, data: &[u8]) -> Result<(), Error<C::Error>> {
+        let mut header = [0; 14];
+        header[0] = (fin as u8) << 7 | opcode;
+        let mut n = 2;
+        match data.len() {
+            0..=125 => header[1] = 0x80 | data.len() as u8,
+            126..=0xffff => {
+                header[1] = 0x80 | 126;
+                header[2..4].copy_from_slice(&(data.len() as u16).to_be_bytes());
+                n += 2;
+            }
+            _ => {
+                header[1] = 0x80 | 127;
+                header[2..10].copy_from_slice(&(data.len() as u64).to_be_bytes());
+                n += 8;
+            }
+        }
+        let mut mask = [0; 4];
+        self.rng.fill_bytes(&mut mask);
+        header[n..n + 4].copy_from_slice(&mask);
+        n += 4;
+        write_all(&mut self.conn, &header[..n]).await?;
+
+        // The chunks are a multiple of the mask length, so each starts with the first byte
+        // of the mask.
+        let mut chunk = [0; 64];
+        for part in data.chunks(chunk.len()) {
+            for (i, (dst, b)) in chunk.iter_mut().zip(part).enumerate() {
+                *dst = b ^ mask[i % 4];
+            }
+            write_all(&mut self.conn, &chunk[..part.len()]).await?;
+        }
+        self.conn.flush().await.map_err(Error::Io)
+    }
+
+    /// Fill `buf` from the data received with the handshake response, then from the
+    /// connection.
+    async fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), Error<C::Error>> {
+        if self.pos < self.len {
+            let n = buf.len().min(self.len - self.pos);
+            buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+            self.pos += n;
+            buf = &mut buf[n..];
+        }
+        while !buf.is_empty() {
+            match self.conn.read(buf).await.map_err(Error::Io)? {
+                0 => return Err(Error::ConnectionClosed),
+                n => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn write_all<C: Write>(conn: &mut C, mut buf: &[u8]) -> Result<(), Error<C::Error>> {
+    while !buf.is_empty() {
+        match conn.write(buf).await.map_err(Error::Io)? {
+            0 => return Err(Error::ConnectionClosed),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Encode `data` in base64, with padding, to `out`, which must be large enough.
+fn base64<'o>(data: &[u8], out: &'o mut [u8]) -> &'o [u8] {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut n = 0;
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let v = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            out[n + i] = if i <= chunk.len() {
+                ALPHABET[(v >> (18 - 6 * i) & 0x3f) as usize]
+            } else {
+                b'='
+            };
+        }
+        n += 4;
+    }
+    &out[..n]
+}
+
+/// SHA-1 digest of `data`, which the handshake needs.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut chunks = data.chunks_exact(64);
+    let mut block = [0; 64];
+    for chunk in &mut chunks {
+        block.copy_from_slice(chunk);
+        sha1_block(&mut h, &block);
+    }
+
+    // Padding: a one bit, zeros, and the length in bits.
+    let rest = chunks.remainder();
+    block = [0; 64];
+    block[..rest.len()].copy_from_slice(rest);
+    block[rest.len()] = 0x80;
+    if rest.len() >= 56 {
+        sha1_block(&mut h, &block);
+        block = [0; 64];
+    }
+    block[56..].copy_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    sha1_block(&mut h, &block);
+
+    let mut digest = [0; 20];
+    for (dst, word) in digest.chunks_exact_mut(4).zip(h) {
+        dst.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn sha1_block(h: &mut [u32; 5], block: &[u8; 64]) {
+    let mut w = [0; 80];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *h;
+    for (i, w) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5a827999),
+            20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+            _ => (b ^ c ^ d, 0xca62c1d6),
+        };
+        let t = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(*w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = t;
+    }
+
+    for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+        *h = h.wrapping_add(v);
+    }
+}
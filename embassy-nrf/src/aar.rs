@@ -0,0 +1,151 @@
+//! Accelerated Address Resolver (AAR) interface.
+//!
+//! The AAR checks Bluetooth Low Energy resolvable private addresses against a list of identity
+//! resolving keys (IRKs), to find out which known device an address belongs to.
+//!
+//! The AAR and [`CCM`](crate::ccm) peripherals share their hardware and interrupt, so only one of
+//! them can be in use at a time.
+
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::InterruptExt;
+use crate::peripherals::AAR;
+use crate::util::slice_in_ram_or;
+use crate::{interrupt, pac, Peripheral};
+
+/// Maximum number of IRKs the AAR can check an address against.
+pub const MAX_IRKS: usize = 16;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// AAR error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The IRK list is not in RAM, so EasyDMA can't access it.
+    BufferNotInRAM,
+    /// More than [`MAX_IRKS`] IRKs were given.
+    TooManyIrks,
+}
+
+/// AAR driver.
+pub struct Aar<'d> {
+    _p: PeripheralRef<'d, AAR>,
+    irq: PeripheralRef<'d, interrupt::CCM_AAR>,
+    // S0, LENGTH and S1 fields, followed by the address, like a received packet.
+    addr: [u8; 9],
+    scratch: [u8; 3],
+}
+
+impl<'d> Aar<'d> {
+    /// Create a new AAR driver.
+    pub fn new(aar: impl Peripheral<P = AAR> + 'd, irq: impl Peripheral<P = interrupt::CCM_AAR> + 'd) -> Self {
+        into_ref!(aar, irq);
+
+        let r = Self::regs();
+        r.enable.write(|w| w.enable().disabled());
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self {
+            _p: aar,
+            irq,
+            addr: [0; 9],
+            scratch: [0; 3],
+        }
+    }
+
+    fn regs() -> &'static pac::aar::RegisterBlock {
+        unsafe { &*pac::AAR::ptr() }
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = Self::regs();
+        if r.events_end.read().bits() != 0 {
+            r.intenclr.write(|w| w.end().clear());
+            WAKER.wake();
+        }
+    }
+
+    /// Try to resolve `address` with each of the IRKs in `irks`.
+    ///
+    /// `address` is in the byte order it is sent over the air, least significant byte first.
+    /// Returns the index of the IRK that resolved the address, or `None` if none did.
+    pub async fn resolve(&mut self, address: &[u8; 6], irks: &[[u8; 16]]) -> Result<Option<usize>, Error> {
+        if irks.len() > MAX_IRKS {
+            return Err(Error::TooManyIrks);
+        }
+        slice_in_ram_or(irks, Error::BufferNotInRAM)?;
+
+        if irks.is_empty() {
+            return Ok(None);
+        }
+
+        self.addr[3..].copy_from_slice(address);
+
+        let r = Self::regs();
+
+        r.enable.write(|w| w.enable().enabled());
+        r.nirk.write(|w| unsafe { w.nirk().bits(irks.len() as u8) });
+        r.irkptr.write(|w| unsafe { w.bits(irks.as_ptr() as u32) });
+        r.addrptr.write(|w| unsafe { w.bits(self.addr.as_ptr() as u32) });
+        r.scratchptr
+            .write(|w| unsafe { w.bits(self.scratch.as_mut_ptr() as u32) });
+
+        r.events_end.reset();
+        r.events_resolved.reset();
+        r.events_notresolved.reset();
+        r.intenset.write(|w| w.end().set());
+
+        let on_drop = OnDrop::new(|| {
+            let r = Self::regs();
+            r.intenclr.write(|w| w.end().clear());
+            r.tasks_stop.write(|w| unsafe { w.bits(1) });
+            r.enable.write(|w| w.enable().disabled());
+        });
+
+        // Make sure the address is written before the peripheral reads it.
+        compiler_fence(Ordering::SeqCst);
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_end.read().bits() != 0 {
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        let res = if r.events_resolved.read().bits() != 0 {
+            Some(r.status.read().status().bits() as usize)
+        } else {
+            None
+        };
+
+        drop(on_drop);
+
+        Ok(res)
+    }
+}
+
+impl<'d> Drop for Aar<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+        r.enable.write(|w| w.enable().disabled());
+        self.irq.disable();
+    }
+}
@@ -83,6 +83,7 @@ impl<'d, U: UarteInstance, T: TimerInstance> BufferedUarte<'d, U, T> {
     ///
     /// The BufferedUarte uses the provided state to store the buffers and peripheral state. The timer and ppi channels are used to 'emulate' idle line detection so that read operations
     /// can return early if there is no data to receive.
+    #[track_caller]
     pub fn new(
         state: &'d mut State<'d, U, T>,
         peri: impl Peripheral<P = U> + 'd,
@@ -105,17 +106,21 @@ impl<'d, U: UarteInstance, T: TimerInstance> BufferedUarte<'d, U, T> {
         let mut timer = Timer::new(timer);
 
         rxd.conf().write(|w| w.input().connect().drive().h0h1());
+        gpio::claim_pin(rxd.psel_bits());
         r.psel.rxd.write(|w| unsafe { w.bits(rxd.psel_bits()) });
 
         txd.set_high();
         txd.conf().write(|w| w.dir().output().drive().h0h1());
+        gpio::claim_pin(txd.psel_bits());
         r.psel.txd.write(|w| unsafe { w.bits(txd.psel_bits()) });
 
         cts.conf().write(|w| w.input().connect().drive().h0h1());
+        gpio::claim_pin(cts.psel_bits());
         r.psel.cts.write(|w| unsafe { w.bits(cts.psel_bits()) });
 
         rts.set_high();
         rts.conf().write(|w| w.dir().output().drive().h0h1());
+        gpio::claim_pin(rts.psel_bits());
         r.psel.rts.write(|w| unsafe { w.bits(rts.psel_bits()) });
 
         r.baudrate.write(|w| w.baudrate().variant(config.baudrate));
@@ -0,0 +1,311 @@
+//! AES CCM mode encryption (CCM) interface.
+//!
+//! The CCM peripheral encrypts and authenticates packets using AES in CCM mode, as used by
+//! Bluetooth Low Energy. Packets are laid out the way the RADIO peripheral expects them, so
+//! they can be handed to the radio after encryption, or decrypted right after being received.
+//!
+//! The CCM and [`AAR`](crate::aar) peripherals share their hardware and interrupt, so only one of
+//! them can be in use at a time.
+
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::InterruptExt;
+use crate::peripherals::CCM;
+use crate::util::slice_in_ram_or;
+use crate::{interrupt, pac, Peripheral};
+
+/// Maximum payload length of a packet, not counting the MIC.
+pub const MAX_PAYLOAD_LEN: usize = 251;
+
+/// Length of the message integrity check (MIC) appended to encrypted packets.
+pub const MIC_LEN: usize = 4;
+
+const SCRATCH_LEN: usize = 16 + MAX_PAYLOAD_LEN + MIC_LEN + 1;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// CCM error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The packet is not in RAM, so EasyDMA can't access it.
+    BufferNotInRAM,
+    /// The payload is too long to fit in the output packet.
+    PayloadTooLong,
+    /// The payload of a packet to decrypt is too short to hold a MIC.
+    PayloadTooShort,
+    /// The MIC of a decrypted packet didn't match, so it was corrupted or tampered with.
+    MicMismatch,
+}
+
+/// Direction of a packet, used as part of the CCM nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Packet sent from the slave to the master. Direction bit cleared.
+    SlaveToMaster,
+    /// Packet sent from the master to the slave. Direction bit set.
+    MasterToSlave,
+}
+
+/// Packet in the layout used by the CCM and RADIO peripherals.
+///
+/// It has room for the largest payload plus its MIC, so the same type can be used for both
+/// the input and the output of encryption and decryption.
+#[repr(C)]
+#[derive(Clone)]
+pub struct Packet {
+    header: u8,
+    length: u8,
+    rfu: u8,
+    payload: [u8; MAX_PAYLOAD_LEN + MIC_LEN],
+}
+
+impl Packet {
+    /// Create a packet with the given header byte and payload.
+    ///
+    /// Panics if `payload` is longer than [`MAX_PAYLOAD_LEN`] plus [`MIC_LEN`].
+    pub fn new(header: u8, payload: &[u8]) -> Self {
+        let mut this = Self::empty();
+        this.header = header;
+        this.set_payload(payload);
+        this
+    }
+
+    /// Create a packet with an empty payload.
+    pub const fn empty() -> Self {
+        Self {
+            header: 0,
+            length: 0,
+            rfu: 0,
+            payload: [0; MAX_PAYLOAD_LEN + MIC_LEN],
+        }
+    }
+
+    /// Header byte of the packet.
+    pub fn header(&self) -> u8 {
+        self.header
+    }
+
+    /// Set the header byte of the packet.
+    pub fn set_header(&mut self, header: u8) {
+        self.header = header;
+    }
+
+    /// Payload of the packet. For encrypted packets this includes the MIC.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.length as usize]
+    }
+
+    /// Set the payload of the packet.
+    ///
+    /// Panics if `payload` is longer than [`MAX_PAYLOAD_LEN`] plus [`MIC_LEN`].
+    pub fn set_payload(&mut self, payload: &[u8]) {
+        self.payload[..payload.len()].copy_from_slice(payload);
+        self.length = payload.len() as u8;
+    }
+
+    /// Raw bytes of the packet, in the layout expected by the RADIO peripheral when
+    /// S0 is 1 byte, LENGTH is 8 bits and S1 is 8 bits.
+    pub fn as_bytes(&self) -> &[u8] {
+        let ptr = self as *const Self as *const u8;
+        unsafe { core::slice::from_raw_parts(ptr, 3 + self.length as usize) }
+    }
+}
+
+/// CCM configuration structure, read by the peripheral through EasyDMA.
+#[repr(C)]
+struct CcmData {
+    key: [u8; 16],
+    counter: [u8; 8],
+    direction: u8,
+    iv: [u8; 8],
+}
+
+/// CCM driver.
+pub struct Ccm<'d> {
+    _p: PeripheralRef<'d, CCM>,
+    irq: PeripheralRef<'d, interrupt::CCM_AAR>,
+    data: CcmData,
+    scratch: [u8; SCRATCH_LEN],
+}
+
+impl<'d> Ccm<'d> {
+    /// Create a new CCM driver with the given key and initialization vector.
+    ///
+    /// The key and IV are used as-is by the hardware, which for Bluetooth Low Energy means
+    /// the session key and IV exactly as derived by the link layer.
+    pub fn new(
+        ccm: impl Peripheral<P = CCM> + 'd,
+        irq: impl Peripheral<P = interrupt::CCM_AAR> + 'd,
+        key: [u8; 16],
+        iv: [u8; 8],
+    ) -> Self {
+        into_ref!(ccm, irq);
+
+        let r = Self::regs();
+        r.enable.write(|w| w.enable().disabled());
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self {
+            _p: ccm,
+            irq,
+            data: CcmData {
+                key,
+                counter: [0; 8],
+                direction: 0,
+                iv,
+            },
+            scratch: [0; SCRATCH_LEN],
+        }
+    }
+
+    fn regs() -> &'static pac::ccm::RegisterBlock {
+        unsafe { &*pac::CCM::ptr() }
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = Self::regs();
+        if r.events_endcrypt.read().bits() != 0 || r.events_error.read().bits() != 0 {
+            r.intenclr.write(|w| w.endcrypt().clear().error().clear());
+            WAKER.wake();
+        }
+    }
+
+    /// Change the key and initialization vector.
+    pub fn set_key(&mut self, key: [u8; 16], iv: [u8; 8]) {
+        self.data.key = key;
+        self.data.iv = iv;
+    }
+
+    /// Encrypt `input`, writing the encrypted packet with its MIC appended to `output`.
+    ///
+    /// `counter` is the 39-bit packet counter, which must never be reused with the same key.
+    /// Packets with an empty payload are copied unencrypted and without a MIC.
+    pub async fn encrypt(
+        &mut self,
+        counter: u64,
+        direction: Direction,
+        input: &Packet,
+        output: &mut Packet,
+    ) -> Result<(), Error> {
+        if input.length as usize > MAX_PAYLOAD_LEN {
+            return Err(Error::PayloadTooLong);
+        }
+
+        self.crypt(counter, direction, false, input, output).await
+    }
+
+    /// Decrypt `input`, writing the decrypted packet without its MIC to `output`.
+    ///
+    /// Returns [`Error::MicMismatch`] if the packet failed authentication. In that case the
+    /// contents of `output` must not be trusted.
+    pub async fn decrypt(
+        &mut self,
+        counter: u64,
+        direction: Direction,
+        input: &Packet,
+        output: &mut Packet,
+    ) -> Result<(), Error> {
+        if input.length != 0 && (input.length as usize) < MIC_LEN {
+            return Err(Error::PayloadTooShort);
+        }
+
+        self.crypt(counter, direction, true, input, output).await?;
+
+        if input.length != 0 && Self::regs().micstatus.read().micstatus().is_check_failed() {
+            return Err(Error::MicMismatch);
+        }
+
+        Ok(())
+    }
+
+    async fn crypt(
+        &mut self,
+        counter: u64,
+        direction: Direction,
+        decrypt: bool,
+        input: &Packet,
+        output: &mut Packet,
+    ) -> Result<(), Error> {
+        slice_in_ram_or(input.as_bytes(), Error::BufferNotInRAM)?;
+
+        self.data.counter = (counter & ((1 << 39) - 1)).to_le_bytes();
+        self.data.direction = match direction {
+            Direction::SlaveToMaster => 0,
+            Direction::MasterToSlave => 1,
+        };
+
+        let r = Self::regs();
+
+        r.enable.write(|w| w.enable().enabled());
+        r.mode.write(|w| {
+            if decrypt {
+                w.mode().decryption();
+            } else {
+                w.mode().encryption();
+            }
+            w.length().extended()
+        });
+
+        r.cnfptr.write(|w| unsafe { w.bits(&self.data as *const _ as u32) });
+        r.inptr.write(|w| unsafe { w.bits(input as *const _ as u32) });
+        r.outptr.write(|w| unsafe { w.bits(output as *mut _ as u32) });
+        r.scratchptr
+            .write(|w| unsafe { w.bits(self.scratch.as_mut_ptr() as u32) });
+
+        r.events_endksgen.reset();
+        r.events_endcrypt.reset();
+        r.events_error.reset();
+        r.shorts.write(|w| w.endksgen_crypt().enabled());
+        r.intenset.write(|w| w.endcrypt().set().error().set());
+
+        let on_drop = OnDrop::new(|| {
+            let r = Self::regs();
+            r.intenclr.write(|w| w.endcrypt().clear().error().clear());
+            r.tasks_stop.write(|w| unsafe { w.bits(1) });
+            r.enable.write(|w| w.enable().disabled());
+        });
+
+        // Make sure the configuration and input are written before the peripheral reads them.
+        compiler_fence(Ordering::SeqCst);
+        r.tasks_ksgen.write(|w| unsafe { w.bits(1) });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_endcrypt.read().bits() != 0 || r.events_error.read().bits() != 0 {
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        compiler_fence(Ordering::SeqCst);
+
+        drop(on_drop);
+
+        Ok(())
+    }
+}
+
+impl<'d> Drop for Ccm<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+        r.enable.write(|w| w.enable().disabled());
+        self.irq.disable();
+    }
+}
@@ -0,0 +1,344 @@
+//! Comparator (COMP) interface.
+//!
+//! The comparator compares an analog input against a reference voltage, or against a second
+//! analog input in differential mode, and generates events when the input crosses it. Waiting for
+//! an event doesn't need the CPU, so an executor can sleep in System ON idle until the threshold
+//! is crossed.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::InterruptExt;
+use crate::ppi::{Event, Task};
+use crate::saadc::sealed::Input as _;
+use crate::saadc::{AnyInput, Input};
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+#[cfg(any(feature = "nrf52810", feature = "nrf52811", feature = "nrf52820"))]
+type Irq = interrupt::COMP;
+#[cfg(any(feature = "nrf52832", feature = "nrf52833", feature = "nrf52840"))]
+type Irq = interrupt::COMP_LPCOMP;
+
+/// Comparator driver.
+pub struct Comp<'d> {
+    _p: PeripheralRef<'d, peripherals::COMP>,
+}
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Used to configure the COMP peripheral.
+///
+/// See the `Default` impl for suitable default values.
+#[non_exhaustive]
+pub struct Config {
+    /// Reference voltage, used in single ended mode.
+    pub reference: Reference,
+    /// Threshold the input has to fall below to be considered low, in 1/64th of the reference.
+    /// Only used in single ended mode.
+    pub threshold_down: u8,
+    /// Threshold the input has to rise above to be considered high, in 1/64th of the reference.
+    /// Only used in single ended mode.
+    pub threshold_up: u8,
+    /// Enable 50 mV hysteresis. Only used in differential mode.
+    pub hysteresis: bool,
+    /// Trade-off between power consumption and response time.
+    pub speed: SpeedMode,
+}
+
+impl Default for Config {
+    /// Default configuration with a threshold around half the supply voltage.
+    fn default() -> Self {
+        Self {
+            reference: Reference::Vdd,
+            threshold_down: 31,
+            threshold_up: 33,
+            hysteresis: false,
+            speed: SpeedMode::Normal,
+        }
+    }
+}
+
+/// Reference voltage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reference {
+    /// Internal 1.2 V reference.
+    Internal1V2,
+    /// Internal 1.8 V reference.
+    Internal1V8,
+    /// Internal 2.4 V reference.
+    Internal2V4,
+    /// Supply voltage.
+    Vdd,
+}
+
+/// Speed and power mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpeedMode {
+    /// Low power, slow response.
+    Low,
+    /// Medium power and response time.
+    Normal,
+    /// High power, fast response.
+    High,
+}
+
+/// Result of a comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Level {
+    /// The input is below the threshold.
+    Below,
+    /// The input is above the threshold.
+    Above,
+}
+
+impl<'d> Comp<'d> {
+    /// Compare `input` against the configured reference.
+    pub fn new_single_ended(
+        comp: impl Peripheral<P = peripherals::COMP> + 'd,
+        irq: impl Peripheral<P = Irq> + 'd,
+        input: impl Peripheral<P = impl Input> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(input);
+        Self::new_inner(comp, irq, input.map_into(), None, config)
+    }
+
+    /// Compare `p_input` against `n_input`.
+    pub fn new_differential(
+        comp: impl Peripheral<P = peripherals::COMP> + 'd,
+        irq: impl Peripheral<P = Irq> + 'd,
+        p_input: impl Peripheral<P = impl Input> + 'd,
+        n_input: impl Peripheral<P = impl Input> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(p_input, n_input);
+        Self::new_inner(comp, irq, p_input.map_into(), Some(n_input.map_into()), config)
+    }
+
+    fn new_inner(
+        comp: impl Peripheral<P = peripherals::COMP> + 'd,
+        irq: impl Peripheral<P = Irq> + 'd,
+        p_input: PeripheralRef<'d, AnyInput>,
+        n_input: Option<PeripheralRef<'d, AnyInput>>,
+        config: Config,
+    ) -> Self {
+        into_ref!(comp, irq);
+
+        let r = Self::regs();
+
+        r.psel.write(|w| unsafe { w.psel().bits(analog_input_index(&p_input)) });
+        match &n_input {
+            Some(n_input) => {
+                r.extrefsel
+                    .write(|w| unsafe { w.extrefsel().bits(analog_input_index(n_input)) });
+                r.hyst.write(|w| w.hyst().bit(config.hysteresis));
+            }
+            None => {
+                r.refsel.write(|w| match config.reference {
+                    Reference::Internal1V2 => w.refsel().int1v2(),
+                    Reference::Internal1V8 => w.refsel().int1v8(),
+                    Reference::Internal2V4 => w.refsel().int2v4(),
+                    Reference::Vdd => w.refsel().vdd(),
+                });
+                r.th.write(|w| unsafe {
+                    w.thdown().bits(config.threshold_down.min(63));
+                    w.thup().bits(config.threshold_up.min(63))
+                });
+            }
+        }
+        r.mode.write(|w| {
+            match config.speed {
+                SpeedMode::Low => w.sp().low(),
+                SpeedMode::Normal => w.sp().normal(),
+                SpeedMode::High => w.sp().high(),
+            };
+            match n_input {
+                Some(_) => w.main().diff(),
+                None => w.main().se(),
+            }
+        });
+
+        // Disable all events interrupts
+        r.intenclr.write(|w| unsafe { w.bits(0x0000_000F) });
+        r.shorts.reset();
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        r.enable.write(|w| w.enable().enabled());
+
+        // Start the comparator, it only takes a few microseconds to be ready.
+        r.events_ready.reset();
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+        while r.events_ready.read().bits() == 0 {}
+        r.events_ready.reset();
+
+        Self { _p: comp }
+    }
+
+    fn on_interrupt(_ctx: *mut ()) {
+        let r = Self::regs();
+
+        if r.events_up.read().bits() != 0 {
+            r.intenclr.write(|w| w.up().clear());
+            WAKER.wake();
+        }
+
+        if r.events_down.read().bits() != 0 {
+            r.intenclr.write(|w| w.down().clear());
+            WAKER.wake();
+        }
+
+        if r.events_cross.read().bits() != 0 {
+            r.intenclr.write(|w| w.cross().clear());
+            WAKER.wake();
+        }
+    }
+
+    fn regs() -> &'static pac::comp::RegisterBlock {
+        unsafe { &*pac::COMP::ptr() }
+    }
+
+    /// Sample the comparator output.
+    pub fn sample(&mut self) -> Level {
+        let r = Self::regs();
+
+        r.tasks_sample.write(|w| unsafe { w.bits(1) });
+        if r.result.read().result().is_above() {
+            Level::Above
+        } else {
+            Level::Below
+        }
+    }
+
+    /// Wait for the input to rise above the threshold.
+    pub async fn wait_for_up(&mut self) {
+        let r = Self::regs();
+
+        r.events_up.reset();
+        r.intenset.write(|w| w.up().set());
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| w.up().clear());
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_up.read().bits() != 0 {
+                r.events_up.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+    }
+
+    /// Wait for the input to fall below the threshold.
+    pub async fn wait_for_down(&mut self) {
+        let r = Self::regs();
+
+        r.events_down.reset();
+        r.intenset.write(|w| w.down().set());
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| w.down().clear());
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_down.read().bits() != 0 {
+                r.events_down.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+    }
+
+    /// Wait for the input to cross the threshold in either direction, returning the new level.
+    pub async fn wait_for_cross(&mut self) -> Level {
+        let r = Self::regs();
+
+        r.events_cross.reset();
+        r.intenset.write(|w| w.cross().set());
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| w.cross().clear());
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_cross.read().bits() != 0 {
+                r.events_cross.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+        self.sample()
+    }
+
+    /// Returns reference to `Up` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event_up(&self) -> Event {
+        Event::from_reg(&Self::regs().events_up)
+    }
+
+    /// Returns reference to `Down` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event_down(&self) -> Event {
+        Event::from_reg(&Self::regs().events_down)
+    }
+
+    /// Returns reference to `Cross` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event_cross(&self) -> Event {
+        Event::from_reg(&Self::regs().events_cross)
+    }
+
+    /// Returns reference to `Sample` task endpoint for PPI.
+    #[inline(always)]
+    pub fn task_sample(&self) -> Task {
+        Task::from_reg(&Self::regs().tasks_sample)
+    }
+}
+
+impl<'d> Drop for Comp<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+        r.intenclr.write(|w| unsafe { w.bits(0x0000_000F) });
+        r.enable.write(|w| w.enable().disabled());
+    }
+}
+
+/// Index of an analog input, as used by the PSEL and EXTREFSEL registers.
+///
+/// Panics if `input` isn't an analog input pin, such as [`VddInput`](crate::saadc::VddInput).
+pub(crate) fn analog_input_index(input: &AnyInput) -> u8 {
+    let channel = input.channel() as u8;
+    // The SAADC numbers the analog inputs from 1, 0 being "not connected".
+    assert!((1..=8).contains(&channel), "not an analog input pin");
+    channel - 1
+}
@@ -0,0 +1,192 @@
+//! Event Generator Unit (EGU) interface.
+//!
+//! The EGU provides 16 software-controlled events. Each one can be fired from software or through
+//! a PPI channel, and awaited from a task or used to start other tasks through PPI. This allows
+//! chains such as "GPIOTE edge → EGU → wake task" without writing custom interrupt handlers.
+
+#![macro_use]
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::ppi::{Event, Task};
+use crate::{pac, Peripheral};
+
+/// Number of events each EGU instance provides.
+pub const EVENT_COUNT: usize = 16;
+
+pub(crate) mod sealed {
+    use embassy_sync::waitqueue::AtomicWaker;
+
+    use super::*;
+
+    pub struct State {
+        pub wakers: [AtomicWaker; EVENT_COUNT],
+    }
+
+    impl State {
+        pub const fn new() -> Self {
+            const NEW_AW: AtomicWaker = AtomicWaker::new();
+            Self {
+                wakers: [NEW_AW; EVENT_COUNT],
+            }
+        }
+    }
+
+    pub trait Instance {
+        fn regs() -> &'static pac::egu0::RegisterBlock;
+        fn state() -> &'static State;
+    }
+}
+
+/// EGU peripheral instance.
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static + Send {
+    /// Interrupt for this peripheral.
+    type Interrupt: Interrupt;
+}
+
+macro_rules! impl_egu {
+    ($type:ident, $pac_type:ident, $irq:ident) => {
+        impl crate::egu::sealed::Instance for peripherals::$type {
+            fn regs() -> &'static pac::egu0::RegisterBlock {
+                unsafe { &*(pac::$pac_type::ptr() as *const pac::egu0::RegisterBlock) }
+            }
+            fn state() -> &'static crate::egu::sealed::State {
+                static STATE: crate::egu::sealed::State = crate::egu::sealed::State::new();
+                &STATE
+            }
+        }
+        impl crate::egu::Instance for peripherals::$type {
+            type Interrupt = crate::interrupt::$irq;
+        }
+    };
+}
+
+/// EGU driver.
+pub struct Egu<'d, T: Instance> {
+    _p: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: Instance> Egu<'d, T> {
+    /// Create a new EGU driver.
+    ///
+    /// The interrupt is only used to wake tasks waiting on an event. It must not be used by
+    /// anything else, such as an interrupt executor.
+    pub fn new(egu: impl Peripheral<P = T> + 'd, irq: impl Peripheral<P = T::Interrupt> + 'd) -> Self {
+        into_ref!(egu, irq);
+
+        let r = T::regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF) });
+        for event in r.events_triggered.iter() {
+            event.reset();
+        }
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self { _p: egu }
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = T::regs();
+        let s = T::state();
+
+        let enabled = r.inten.read().bits();
+        for (n, event) in r.events_triggered.iter().enumerate() {
+            if enabled & (1 << n) != 0 && event.read().bits() != 0 {
+                r.intenclr.write(|w| unsafe { w.bits(1 << n) });
+                s.wakers[n].wake();
+            }
+        }
+    }
+
+    /// Get a handle to event `n`.
+    ///
+    /// Panics if `n` is not lower than [`EVENT_COUNT`].
+    pub fn channel(&mut self, n: usize) -> Channel<'_, T> {
+        assert!(n < EVENT_COUNT);
+        Channel { n, _p: PhantomData }
+    }
+
+    /// Split the driver into handles to each of its events.
+    ///
+    /// The handles can be moved to different tasks, and shared with interrupt handlers to fire
+    /// events from them.
+    pub fn split(self) -> [Channel<'d, T>; EVENT_COUNT] {
+        core::array::from_fn(|n| Channel { n, _p: PhantomData })
+    }
+}
+
+impl<'d, T: Instance> Drop for Egu<'d, T> {
+    fn drop(&mut self) {
+        T::regs().intenclr.write(|w| unsafe { w.bits(0xFFFF) });
+    }
+}
+
+/// Handle to a single EGU event.
+pub struct Channel<'d, T: Instance> {
+    n: usize,
+    _p: PhantomData<&'d T>,
+}
+
+impl<'d, T: Instance> Channel<'d, T> {
+    /// Index of this event within its EGU instance.
+    pub fn number(&self) -> usize {
+        self.n
+    }
+
+    /// Fire the event.
+    ///
+    /// This can be called from any context, including interrupt handlers.
+    pub fn trigger(&self) {
+        T::regs().tasks_trigger[self.n].write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Wait for the event to fire.
+    ///
+    /// Only events fired after this is called are seen.
+    pub async fn wait(&mut self) {
+        let r = T::regs();
+        let s = T::state();
+        let n = self.n;
+
+        r.events_triggered[n].reset();
+        r.intenset.write(|w| unsafe { w.bits(1 << n) });
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| unsafe { w.bits(1 << n) });
+        });
+
+        poll_fn(|cx| {
+            s.wakers[n].register(cx.waker());
+
+            if r.events_triggered[n].read().bits() != 0 {
+                r.events_triggered[n].reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+    }
+
+    /// Returns reference to the `Trigger` task endpoint for PPI.
+    #[inline(always)]
+    pub fn task(&self) -> Task {
+        Task::from_reg(&T::regs().tasks_trigger[self.n])
+    }
+
+    /// Returns reference to the `Triggered` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event(&self) -> Event {
+        Event::from_reg(&T::regs().events_triggered[self.n])
+    }
+}
@@ -43,6 +43,7 @@ pub struct Input<'d, T: Pin> {
 impl<'d, T: Pin> Input<'d, T> {
     /// Create GPIO input driver for a [Pin] with the provided [Pull] configuration.
     #[inline]
+    #[track_caller]
     pub fn new(pin: impl Peripheral<P = T> + 'd, pull: Pull) -> Self {
         let mut pin = Flex::new(pin);
         pin.set_as_input(pull);
@@ -129,6 +130,7 @@ pub struct Output<'d, T: Pin> {
 impl<'d, T: Pin> Output<'d, T> {
     /// Create GPIO output driver for a [Pin] with the provided [Level] and [OutputDriver] configuration.
     #[inline]
+    #[track_caller]
     pub fn new(pin: impl Peripheral<P = T> + 'd, initial_output: Level, drive: OutputDrive) -> Self {
         let mut pin = Flex::new(pin);
         match initial_output {
@@ -190,7 +192,7 @@ fn convert_drive(drive: OutputDrive) -> DRIVE_A {
     }
 }
 
-fn convert_pull(pull: Pull) -> PULL_A {
+pub(crate) fn convert_pull(pull: Pull) -> PULL_A {
     match pull {
         Pull::None => PULL_A::DISABLED,
         Pull::Up => PULL_A::PULLUP,
@@ -213,8 +215,10 @@ impl<'d, T: Pin> Flex<'d, T> {
     /// The pin remains disconnected. The initial output level is unspecified, but can be changed
     /// before the pin is put into output mode.
     #[inline]
+    #[track_caller]
     pub fn new(pin: impl Peripheral<P = T> + 'd) -> Self {
         into_ref!(pin);
+        claim_pin(pin.psel_bits());
         // Pin will be in disconnected state.
         Self { pin }
     }
@@ -336,6 +340,7 @@ impl<'d, T: Pin> Flex<'d, T> {
 impl<'d, T: Pin> Drop for Flex<'d, T> {
     fn drop(&mut self) {
         self.pin.conf().reset();
+        release_pin(self.pin.pin_port() as u32);
     }
 }
 
@@ -467,6 +472,78 @@ pub(crate) fn deconfigure_pin(psel_bits: u32) {
         return;
     }
     unsafe { AnyPin::steal(psel_bits as _).conf().reset() }
+    release_pin(psel_bits);
+}
+
+// ====================
+
+#[cfg(feature = "pin-conflict-check")]
+mod claims {
+    use core::cell::Cell;
+    use core::panic::Location;
+
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::blocking_mutex::CriticalSectionMutex as Mutex;
+
+    #[cfg(feature = "_gpio-p1")]
+    const PIN_COUNT: usize = 64;
+    #[cfg(not(feature = "_gpio-p1"))]
+    const PIN_COUNT: usize = 32;
+
+    type Claim = Cell<Option<&'static Location<'static>>>;
+
+    const CLAIM_NEW: Claim = Cell::new(None);
+    static CLAIMS: Mutex<[Claim; PIN_COUNT]> = Mutex::const_new(CriticalSectionRawMutex::new(), [CLAIM_NEW; PIN_COUNT]);
+
+    pub(super) fn claim(pin_port: u8, by: &'static Location<'static>) {
+        let prev = CLAIMS.lock(|claims| claims[pin_port as usize].replace(Some(by)));
+        if let Some(prev) = prev {
+            panic!(
+                "pin P{}_{} claimed at {}:{} is already in use by {}:{}",
+                pin_port / 32,
+                pin_port % 32,
+                by.file(),
+                by.line(),
+                prev.file(),
+                prev.line()
+            );
+        }
+    }
+
+    pub(super) fn release(pin_port: u8) {
+        CLAIMS.lock(|claims| claims[pin_port as usize].set(None));
+    }
+}
+
+/// Record that the caller is now using the pin.
+///
+/// When the `pin-conflict-check` feature is enabled, every driver that takes a pin claims it in its
+/// constructor and releases it again when dropped. The constructors are `#[track_caller]` down to
+/// this function, so the source location recorded is where the user created the driver. If a pin
+/// is claimed while another driver still holds it, for example because one of them was obtained
+/// through `steal()`, this panics at init time with the locations of both users.
+///
+/// Disconnected pins are ignored. Without the feature this is a no-op.
+#[inline]
+#[track_caller]
+pub(crate) fn claim_pin(psel_bits: u32) {
+    if psel_bits & 0x8000_0000 != 0 {
+        return;
+    }
+    #[cfg(feature = "pin-conflict-check")]
+    claims::claim(psel_bits as u8, core::panic::Location::caller());
+}
+
+/// Release the claim on a pin without touching its configuration.
+///
+/// Drivers that don't call [deconfigure_pin] on drop must call this instead.
+#[inline]
+pub(crate) fn release_pin(psel_bits: u32) {
+    if psel_bits & 0x8000_0000 != 0 {
+        return;
+    }
+    #[cfg(feature = "pin-conflict-check")]
+    claims::release(psel_bits as u8);
 }
 
 // ====================
@@ -645,3 +722,38 @@ mod eh1 {
         }
     }
 }
+
+#[cfg(all(test, feature = "pin-conflict-check"))]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+    use crate::peripherals::P0_13;
+
+    #[test]
+    fn conflicting_claim_reports_both_call_sites() {
+        let first_line = line!() + 1;
+        let first = Flex::new(unsafe { P0_13::steal() });
+
+        let second_line = line!() + 2;
+        let res = std::panic::catch_unwind(|| {
+            core::mem::forget(Flex::new(unsafe { P0_13::steal() }));
+        });
+
+        let msg = *res.unwrap_err().downcast::<String>().unwrap();
+        let expected = std::format!(
+            "pin P0_13 claimed at {}:{} is already in use by {}:{}",
+            file!(),
+            second_line,
+            file!(),
+            first_line
+        );
+        assert_eq!(msg, expected);
+
+        // Dropping the pin would reset its configuration registers, which don't exist here.
+        core::mem::forget(first);
+        claims::release(13);
+    }
+}
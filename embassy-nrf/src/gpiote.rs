@@ -1,7 +1,21 @@
+//! GPIO task/event (GPIOTE) interface.
+//!
+//! Pins can be awaited in two ways, chosen per pin:
+//!
+//! - **Sense mode**, with the `wait_for_*` methods of [`Input`] and [`Flex`]. This uses the
+//!   PIN_CNF.SENSE mechanism and the shared PORT event, so any number of pins can be awaited
+//!   concurrently without using GPIOTE channels, and it works from low-power sleep with only the
+//!   low frequency clock running. The downside is latency: the PORT event is latched, and edges
+//!   are detected as two level changes, so very short pulses can be missed.
+//! - **Channel mode**, with an [`InputChannel`]. This dedicates one of the 8 GPIOTE channels to
+//!   the pin, which detects edges with low latency and can trigger PPI tasks directly. The
+//!   channel keeps the high frequency clock running while enabled, which costs more power.
+
 use core::convert::Infallible;
 use core::future::{poll_fn, Future};
 use core::task::{Context, Poll};
 
+use embassy_hal_common::drop::OnDrop;
 use embassy_hal_common::{impl_peripheral, Peripheral, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
 
@@ -23,6 +37,9 @@ const NEW_AW: AtomicWaker = AtomicWaker::new();
 static CHANNEL_WAKERS: [AtomicWaker; CHANNEL_COUNT] = [NEW_AW; CHANNEL_COUNT];
 static PORT_WAKERS: [AtomicWaker; PIN_COUNT] = [NEW_AW; PIN_COUNT];
 
+/// Polarity of the IN event of an [`InputChannel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum InputChannelPolarity {
     None,
     HiToLo,
@@ -147,9 +164,13 @@ impl Iterator for BitIter {
 }
 
 /// GPIOTE channel driver in input mode
+///
+/// This is the low-latency channel mode, see the [module docs](self) for how it compares
+/// to awaiting the [`Input`] directly.
 pub struct InputChannel<'d, C: Channel, T: GpioPin> {
     ch: C,
     pin: Input<'d, T>,
+    polarity: InputChannelPolarity,
 }
 
 impl<'d, C: Channel, T: GpioPin> Drop for InputChannel<'d, C, T> {
@@ -167,12 +188,7 @@ impl<'d, C: Channel, T: GpioPin> InputChannel<'d, C, T> {
         let num = ch.number();
 
         g.config[num].write(|w| {
-            match polarity {
-                InputChannelPolarity::HiToLo => w.mode().event().polarity().hi_to_lo(),
-                InputChannelPolarity::LoToHi => w.mode().event().polarity().lo_to_hi(),
-                InputChannelPolarity::None => w.mode().event().polarity().none(),
-                InputChannelPolarity::Toggle => w.mode().event().polarity().toggle(),
-            };
+            w.mode().event();
             #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
             w.port().bit(match pin.pin.pin.port() {
                 crate::gpio::Port::Port0 => false,
@@ -180,12 +196,14 @@ impl<'d, C: Channel, T: GpioPin> InputChannel<'d, C, T> {
             });
             unsafe { w.psel().bits(pin.pin.pin.pin()) }
         });
+        set_input_polarity(num, polarity);
 
         g.events_in[num].reset();
 
-        InputChannel { ch, pin }
+        InputChannel { ch, pin, polarity }
     }
 
+    /// Wait for the IN event, with the polarity the channel was created with.
     pub async fn wait(&self) {
         let g = regs();
         let num = self.ch.number();
@@ -211,6 +229,88 @@ impl<'d, C: Channel, T: GpioPin> InputChannel<'d, C, T> {
         let g = regs();
         Event::from_reg(&g.events_in[self.ch.number()])
     }
+
+    /// Get whether the pin input level is high.
+    pub fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    /// Get whether the pin input level is low.
+    pub fn is_low(&self) -> bool {
+        self.pin.is_low()
+    }
+
+    /// Wait until the pin is high. If it is already high, return immediately.
+    ///
+    /// Like the other `wait_for_*` methods, this temporarily changes the channel polarity,
+    /// so the IN event doesn't follow the configured polarity while it runs.
+    pub async fn wait_for_high(&mut self) {
+        self.wait_for_polarity(InputChannelPolarity::LoToHi, Some(true)).await
+    }
+
+    /// Wait until the pin is low. If it is already low, return immediately.
+    pub async fn wait_for_low(&mut self) {
+        self.wait_for_polarity(InputChannelPolarity::HiToLo, Some(false)).await
+    }
+
+    /// Wait for the pin to undergo a transition from low to high.
+    pub async fn wait_for_rising_edge(&mut self) {
+        self.wait_for_polarity(InputChannelPolarity::LoToHi, None).await
+    }
+
+    /// Wait for the pin to undergo a transition from high to low.
+    pub async fn wait_for_falling_edge(&mut self) {
+        self.wait_for_polarity(InputChannelPolarity::HiToLo, None).await
+    }
+
+    /// Wait for the pin to undergo any transition, i.e low to high OR high to low.
+    pub async fn wait_for_any_edge(&mut self) {
+        self.wait_for_polarity(InputChannelPolarity::Toggle, None).await
+    }
+
+    /// Wait for an IN event with `polarity`, or until the pin is at level `done_if_high`.
+    async fn wait_for_polarity(&mut self, polarity: InputChannelPolarity, done_if_high: Option<bool>) {
+        let g = regs();
+        let num = self.ch.number();
+        let configured = self.polarity;
+
+        set_input_polarity(num, polarity);
+        g.events_in[num].reset();
+        g.intenset.write(|w| unsafe { w.bits(1 << num) });
+
+        let _on_drop = OnDrop::new(|| {
+            g.intenclr.write(|w| unsafe { w.bits(1 << num) });
+            g.events_in[num].reset();
+            set_input_polarity(num, configured);
+        });
+
+        // Check the level only after arming the channel, so a change in between isn't missed.
+        if let Some(high) = done_if_high {
+            if self.pin.is_high() == high {
+                return;
+            }
+        }
+
+        poll_fn(|cx| {
+            CHANNEL_WAKERS[num].register(cx.waker());
+
+            if g.events_in[num].read().bits() != 0 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+}
+
+fn set_input_polarity(num: usize, polarity: InputChannelPolarity) {
+    regs().config[num].modify(|_, w| match polarity {
+        InputChannelPolarity::HiToLo => w.polarity().hi_to_lo(),
+        InputChannelPolarity::LoToHi => w.polarity().lo_to_hi(),
+        InputChannelPolarity::None => w.polarity().none(),
+        InputChannelPolarity::Toggle => w.polarity().toggle(),
+    });
 }
 
 /// GPIOTE channel driver in output mode
@@ -537,4 +637,36 @@ mod eha {
             self.wait_for_any_edge().map(Ok)
         }
     }
+
+    impl<'d, C: Channel, T: GpioPin> embedded_hal_async::digital::Wait for InputChannel<'d, C, T> {
+        type WaitForHighFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+        fn wait_for_high<'a>(&'a mut self) -> Self::WaitForHighFuture<'a> {
+            self.wait_for_high().map(Ok)
+        }
+
+        type WaitForLowFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+        fn wait_for_low<'a>(&'a mut self) -> Self::WaitForLowFuture<'a> {
+            self.wait_for_low().map(Ok)
+        }
+
+        type WaitForRisingEdgeFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+        fn wait_for_rising_edge<'a>(&'a mut self) -> Self::WaitForRisingEdgeFuture<'a> {
+            self.wait_for_rising_edge().map(Ok)
+        }
+
+        type WaitForFallingEdgeFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+        fn wait_for_falling_edge<'a>(&'a mut self) -> Self::WaitForFallingEdgeFuture<'a> {
+            self.wait_for_falling_edge().map(Ok)
+        }
+
+        type WaitForAnyEdgeFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+        fn wait_for_any_edge<'a>(&'a mut self) -> Self::WaitForAnyEdgeFuture<'a> {
+            self.wait_for_any_edge().map(Ok)
+        }
+    }
 }
@@ -0,0 +1,825 @@
+//! Inter-IC Sound (I2S) interface.
+//!
+//! The I2S peripheral streams audio samples between RAM and an external codec, amplifier or
+//! microphone using EasyDMA. It can act as master, generating SCK and LRCK (and optionally the
+//! codec master clock MCK), or as slave, following the clocks of an external master.
+//!
+//! Transmit and receive share the same buffer size. The peripheral latches a new buffer pointer
+//! at the start of every buffer, so streaming uses two buffers: one is transferred while the
+//! other is filled or processed by the CPU.
+
+use core::future::poll_fn;
+use core::mem::size_of;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::{self, AnyPin, Pin as GpioPin};
+use crate::interrupt::InterruptExt;
+use crate::util::slice_in_ram_or;
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    BufferTooLong,
+    BufferZeroLength,
+    /// EasyDMA can only read from data memory, read only buffers in flash will fail.
+    DMABufferNotInDataMemory,
+    /// Buffers must be aligned to a 32-bit word.
+    BufferMisaligned,
+    /// Buffer length must be a whole number of 32-bit words.
+    BufferLengthMisaligned,
+}
+
+/// The maximum buffer size in 32-bit words, limited by the RXTXD.MAXCNT register.
+pub const MAX_BUFFER_WORDS: usize = (1 << 14) - 1;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Used to configure the I2S peripheral.
+///
+/// See the `Default` impl for suitable default values.
+#[non_exhaustive]
+#[derive(Clone)]
+pub struct Config {
+    /// Width of a single sample.
+    pub sample_width: SampleWidth,
+    /// Alignment of samples narrower than the frame half.
+    pub align: Align,
+    /// Frame format.
+    pub format: Format,
+    /// Channels transferred.
+    pub channels: Channels,
+}
+
+impl Default for Config {
+    /// Default configuration for 16-bit stereo samples in standard I2S format.
+    fn default() -> Self {
+        Self {
+            sample_width: SampleWidth::_16bit,
+            align: Align::Left,
+            format: Format::I2S,
+            channels: Channels::Stereo,
+        }
+    }
+}
+
+/// Master clock generator configuration, only used in master mode.
+///
+/// The sample rate (LRCK frequency) is the MCK frequency divided by the ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MasterClock {
+    /// MCK frequency.
+    pub freq: MckFreq,
+    /// MCK / LRCK ratio.
+    pub ratio: Ratio,
+}
+
+impl MasterClock {
+    /// Create a master clock configuration.
+    pub const fn new(freq: MckFreq, ratio: Ratio) -> Self {
+        Self { freq, ratio }
+    }
+
+    /// Resulting sample rate in Hz, rounded down.
+    pub fn sample_rate(&self) -> u32 {
+        self.freq.to_frequency() / self.ratio.to_divisor()
+    }
+}
+
+impl Default for MasterClock {
+    /// 32 MHz / 23 MCK with a ratio of 64, giving a sample rate of about 21.7 kHz.
+    fn default() -> Self {
+        Self::new(MckFreq::_32MDiv23, Ratio::_64x)
+    }
+}
+
+/// MCK frequency, derived from the 32 MHz peripheral clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MckFreq {
+    _32MDiv8,
+    _32MDiv10,
+    _32MDiv11,
+    _32MDiv15,
+    _32MDiv16,
+    _32MDiv21,
+    _32MDiv23,
+    _32MDiv30,
+    _32MDiv31,
+    _32MDiv32,
+    _32MDiv42,
+    _32MDiv63,
+    _32MDiv125,
+}
+
+impl MckFreq {
+    const CORE_FREQUENCY: u32 = 32_000_000;
+
+    fn to_register_value(self) -> u32 {
+        match self {
+            MckFreq::_32MDiv8 => 0x2000_0000,
+            MckFreq::_32MDiv10 => 0x1800_0000,
+            MckFreq::_32MDiv11 => 0x1600_0000,
+            MckFreq::_32MDiv15 => 0x1100_0000,
+            MckFreq::_32MDiv16 => 0x1000_0000,
+            MckFreq::_32MDiv21 => 0x0C00_0000,
+            MckFreq::_32MDiv23 => 0x0B00_0000,
+            MckFreq::_32MDiv30 => 0x0880_0000,
+            MckFreq::_32MDiv31 => 0x0840_0000,
+            MckFreq::_32MDiv32 => 0x0800_0000,
+            MckFreq::_32MDiv42 => 0x0600_0000,
+            MckFreq::_32MDiv63 => 0x0410_0000,
+            MckFreq::_32MDiv125 => 0x020C_0000,
+        }
+    }
+
+    /// Resulting MCK frequency in Hz, rounded down.
+    pub fn to_frequency(self) -> u32 {
+        let divisor = match self {
+            MckFreq::_32MDiv8 => 8,
+            MckFreq::_32MDiv10 => 10,
+            MckFreq::_32MDiv11 => 11,
+            MckFreq::_32MDiv15 => 15,
+            MckFreq::_32MDiv16 => 16,
+            MckFreq::_32MDiv21 => 21,
+            MckFreq::_32MDiv23 => 23,
+            MckFreq::_32MDiv30 => 30,
+            MckFreq::_32MDiv31 => 31,
+            MckFreq::_32MDiv32 => 32,
+            MckFreq::_32MDiv42 => 42,
+            MckFreq::_32MDiv63 => 63,
+            MckFreq::_32MDiv125 => 125,
+        };
+        Self::CORE_FREQUENCY / divisor
+    }
+}
+
+/// MCK / LRCK ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Ratio {
+    _32x,
+    _48x,
+    _64x,
+    _96x,
+    _128x,
+    _192x,
+    _256x,
+    _384x,
+    _512x,
+}
+
+impl Ratio {
+    fn to_register_value(self) -> u8 {
+        self as u8
+    }
+
+    /// The ratio as a divisor of the MCK frequency.
+    pub fn to_divisor(self) -> u32 {
+        match self {
+            Ratio::_32x => 32,
+            Ratio::_48x => 48,
+            Ratio::_64x => 64,
+            Ratio::_96x => 96,
+            Ratio::_128x => 128,
+            Ratio::_192x => 192,
+            Ratio::_256x => 256,
+            Ratio::_384x => 384,
+            Ratio::_512x => 512,
+        }
+    }
+}
+
+/// Width of a single sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SampleWidth {
+    _8bit,
+    _16bit,
+    _24bit,
+}
+
+/// Alignment of samples within a frame half.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Frame format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Format {
+    /// Original I2S format, data is delayed one SCK cycle after the LRCK edge.
+    I2S,
+    /// Left or right aligned format, data starts on the LRCK edge.
+    Aligned,
+}
+
+/// Channels transferred.
+///
+/// In stereo mode, samples are interleaved in memory, left channel first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Channels {
+    Stereo,
+    /// Only the left channel. On transmit, the same sample is sent on both channels.
+    MonoLeft,
+    /// Only the right channel. On transmit, the same sample is sent on both channels.
+    MonoRight,
+}
+
+/// Sample types that can be used with the I2S peripheral.
+///
+/// Use `i8` for 8-bit, `i16` for 16-bit and `i32` for 24-bit samples.
+pub trait Sample: sealed::Sample + Copy + Default + 'static {}
+
+impl Sample for i8 {}
+impl Sample for i16 {}
+impl Sample for i32 {}
+
+mod sealed {
+    pub trait Sample {}
+
+    impl Sample for i8 {}
+    impl Sample for i16 {}
+    impl Sample for i32 {}
+}
+
+/// The state of a running stream. While it reflects the progress of a stream, it also signals
+/// what should be done next. For example, if the stream has stopped then the I2S implementation
+/// can then tear down its infrastructure.
+#[derive(PartialEq)]
+pub enum StreamState {
+    Running,
+    Stopped,
+}
+
+/// I2S driver, before a direction has been chosen.
+///
+/// Call [`I2s::output`], [`I2s::input`] or [`I2s::full_duplex`] to start streaming.
+pub struct I2s<'d> {
+    _p: PeripheralRef<'d, peripherals::I2S>,
+}
+
+impl<'d> I2s<'d> {
+    /// Create an I2S driver in master mode, generating SCK and LRCK.
+    #[track_caller]
+    pub fn new_master(
+        i2s: impl Peripheral<P = peripherals::I2S> + 'd,
+        irq: impl Peripheral<P = interrupt::I2S> + 'd,
+        sck: impl Peripheral<P = impl GpioPin> + 'd,
+        lrck: impl Peripheral<P = impl GpioPin> + 'd,
+        master_clock: MasterClock,
+        config: Config,
+    ) -> Self {
+        into_ref!(sck, lrck);
+        Self::new_inner(
+            i2s,
+            irq,
+            None,
+            sck.map_into(),
+            lrck.map_into(),
+            Some(master_clock),
+            config,
+        )
+    }
+
+    /// Create an I2S driver in master mode, also outputting the master clock for the codec on `mck`.
+    #[track_caller]
+    pub fn new_master_with_mck(
+        i2s: impl Peripheral<P = peripherals::I2S> + 'd,
+        irq: impl Peripheral<P = interrupt::I2S> + 'd,
+        mck: impl Peripheral<P = impl GpioPin> + 'd,
+        sck: impl Peripheral<P = impl GpioPin> + 'd,
+        lrck: impl Peripheral<P = impl GpioPin> + 'd,
+        master_clock: MasterClock,
+        config: Config,
+    ) -> Self {
+        into_ref!(mck, sck, lrck);
+        Self::new_inner(
+            i2s,
+            irq,
+            Some(mck.map_into()),
+            sck.map_into(),
+            lrck.map_into(),
+            Some(master_clock),
+            config,
+        )
+    }
+
+    /// Create an I2S driver in slave mode, following the SCK and LRCK of an external master.
+    #[track_caller]
+    pub fn new_slave(
+        i2s: impl Peripheral<P = peripherals::I2S> + 'd,
+        irq: impl Peripheral<P = interrupt::I2S> + 'd,
+        sck: impl Peripheral<P = impl GpioPin> + 'd,
+        lrck: impl Peripheral<P = impl GpioPin> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(sck, lrck);
+        Self::new_inner(i2s, irq, None, sck.map_into(), lrck.map_into(), None, config)
+    }
+
+    #[track_caller]
+    fn new_inner(
+        i2s: impl Peripheral<P = peripherals::I2S> + 'd,
+        irq: impl Peripheral<P = interrupt::I2S> + 'd,
+        mck: Option<PeripheralRef<'d, AnyPin>>,
+        sck: PeripheralRef<'d, AnyPin>,
+        lrck: PeripheralRef<'d, AnyPin>,
+        master_clock: Option<MasterClock>,
+        config: Config,
+    ) -> Self {
+        into_ref!(i2s, irq);
+
+        let r = Self::regs();
+
+        // Configure pins
+        match master_clock {
+            Some(_) => {
+                sck.conf().write(|w| w.dir().output());
+                lrck.conf().write(|w| w.dir().output());
+            }
+            None => {
+                sck.conf().write(|w| w.input().connect());
+                lrck.conf().write(|w| w.input().connect());
+            }
+        }
+        gpio::claim_pin(sck.psel_bits());
+        r.psel.sck.write(|w| unsafe { w.bits(sck.psel_bits()) });
+        gpio::claim_pin(lrck.psel_bits());
+        r.psel.lrck.write(|w| unsafe { w.bits(lrck.psel_bits()) });
+        if let Some(mck) = &mck {
+            mck.conf().write(|w| w.dir().output());
+            gpio::claim_pin(mck.psel_bits());
+            r.psel.mck.write(|w| unsafe { w.bits(mck.psel_bits()) });
+        }
+
+        // Configure
+        match master_clock {
+            Some(master_clock) => {
+                r.config.mode.write(|w| w.mode().master());
+                r.config.mcken.write(|w| w.mcken().enabled());
+                r.config
+                    .mckfreq
+                    .write(|w| unsafe { w.mckfreq().bits(master_clock.freq.to_register_value()) });
+                r.config
+                    .ratio
+                    .write(|w| unsafe { w.ratio().bits(master_clock.ratio.to_register_value()) });
+            }
+            None => {
+                r.config.mode.write(|w| w.mode().slave());
+                r.config.mcken.write(|w| w.mcken().disabled());
+            }
+        }
+        r.config.swidth.write(|w| match config.sample_width {
+            SampleWidth::_8bit => w.swidth()._8bit(),
+            SampleWidth::_16bit => w.swidth()._16bit(),
+            SampleWidth::_24bit => w.swidth()._24bit(),
+        });
+        r.config.align.write(|w| match config.align {
+            Align::Left => w.align().left(),
+            Align::Right => w.align().right(),
+        });
+        r.config.format.write(|w| match config.format {
+            Format::I2S => w.format().i2s(),
+            Format::Aligned => w.format().aligned(),
+        });
+        r.config.channels.write(|w| match config.channels {
+            Channels::Stereo => w.channels().stereo(),
+            Channels::MonoLeft => w.channels().left(),
+            Channels::MonoRight => w.channels().right(),
+        });
+
+        // Disable all events interrupts
+        r.intenclr.write(|w| unsafe { w.bits(0x0000_0026) });
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self { _p: i2s }
+    }
+
+    /// Transmit only, on `sdout`.
+    #[track_caller]
+    pub fn output(mut self, sdout: impl Peripheral<P = impl GpioPin> + 'd) -> OutputStream<'d> {
+        into_ref!(sdout);
+        self.set_sdout(sdout.map_into());
+        OutputStream { _i2s: self }
+    }
+
+    /// Receive only, on `sdin`.
+    #[track_caller]
+    pub fn input(mut self, sdin: impl Peripheral<P = impl GpioPin> + 'd) -> InputStream<'d> {
+        into_ref!(sdin);
+        self.set_sdin(sdin.map_into());
+        InputStream { _i2s: self }
+    }
+
+    /// Transmit on `sdout` and receive on `sdin` at the same time.
+    #[track_caller]
+    pub fn full_duplex(
+        mut self,
+        sdin: impl Peripheral<P = impl GpioPin> + 'd,
+        sdout: impl Peripheral<P = impl GpioPin> + 'd,
+    ) -> FullDuplexStream<'d> {
+        into_ref!(sdin, sdout);
+        self.set_sdin(sdin.map_into());
+        self.set_sdout(sdout.map_into());
+        FullDuplexStream { _i2s: self }
+    }
+
+    #[track_caller]
+    fn set_sdin(&mut self, sdin: PeripheralRef<'d, AnyPin>) {
+        let r = Self::regs();
+        sdin.conf().write(|w| w.input().connect());
+        gpio::claim_pin(sdin.psel_bits());
+        r.psel.sdin.write(|w| unsafe { w.bits(sdin.psel_bits()) });
+        r.config.rxen.write(|w| w.rxen().enabled());
+    }
+
+    #[track_caller]
+    fn set_sdout(&mut self, sdout: PeripheralRef<'d, AnyPin>) {
+        let r = Self::regs();
+        sdout.conf().write(|w| w.dir().output());
+        gpio::claim_pin(sdout.psel_bits());
+        r.psel.sdout.write(|w| unsafe { w.bits(sdout.psel_bits()) });
+        r.config.txen.write(|w| w.txen().enabled());
+    }
+
+    fn on_interrupt(_ctx: *mut ()) {
+        let r = Self::regs();
+
+        if r.events_rxptrupd.read().bits() != 0 {
+            r.intenclr.write(|w| w.rxptrupd().clear());
+            WAKER.wake();
+        }
+
+        if r.events_txptrupd.read().bits() != 0 {
+            r.intenclr.write(|w| w.txptrupd().clear());
+            WAKER.wake();
+        }
+
+        if r.events_stopped.read().bits() != 0 {
+            r.intenclr.write(|w| w.stopped().clear());
+            WAKER.wake();
+        }
+    }
+
+    fn regs() -> &'static pac::i2s::RegisterBlock {
+        unsafe { &*pac::I2S::ptr() }
+    }
+
+    fn check_buffer<S: Sample>(buffer: &[S]) -> Result<(), Error> {
+        if buffer.is_empty() {
+            return Err(Error::BufferZeroLength);
+        }
+        slice_in_ram_or(buffer, Error::DMABufferNotInDataMemory)?;
+        if buffer.as_ptr() as u32 % 4 != 0 {
+            return Err(Error::BufferMisaligned);
+        }
+        let bytes = buffer.len() * size_of::<S>();
+        if bytes % 4 != 0 {
+            return Err(Error::BufferLengthMisaligned);
+        }
+        if bytes / 4 > MAX_BUFFER_WORDS {
+            return Err(Error::BufferTooLong);
+        }
+        Ok(())
+    }
+
+    fn set_maxcnt<S: Sample>(len: usize) {
+        let r = Self::regs();
+        let words = len * size_of::<S>() / 4;
+        r.rxtxd.maxcnt.write(|w| unsafe { w.maxcnt().bits(words as _) });
+    }
+
+    fn set_tx_ptr<S: Sample>(buffer: &[S]) {
+        let r = Self::regs();
+        r.txd.ptr.write(|w| unsafe { w.ptr().bits(buffer.as_ptr() as u32) });
+    }
+
+    fn set_rx_ptr<S: Sample>(buffer: &mut [S]) {
+        let r = Self::regs();
+        r.rxd.ptr.write(|w| unsafe { w.ptr().bits(buffer.as_mut_ptr() as u32) });
+    }
+
+    fn start(rx: bool, tx: bool) {
+        let r = Self::regs();
+
+        // Reset and enable the events
+        r.events_rxptrupd.reset();
+        r.events_txptrupd.reset();
+        r.events_stopped.reset();
+        r.intenset.write(|w| {
+            if rx {
+                w.rxptrupd().set();
+            }
+            if tx {
+                w.txptrupd().set();
+            }
+            w
+        });
+
+        r.enable.write(|w| w.enable().enabled());
+
+        // Don't reorder the start event before the previous writes.
+        compiler_fence(Ordering::SeqCst);
+
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Stop streaming and wait for the peripheral to release the buffers.
+    async fn stop() {
+        let r = Self::regs();
+
+        compiler_fence(Ordering::SeqCst);
+
+        r.events_stopped.reset();
+        r.intenset.write(|w| w.stopped().set());
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_stopped.read().bits() != 0 {
+                r.events_stopped.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        r.enable.write(|w| w.enable().disabled());
+    }
+
+    // Stop streaming and wait for it to stop in a blocking fashion
+    fn stop_immediately() {
+        let r = Self::regs();
+
+        compiler_fence(Ordering::SeqCst);
+
+        r.events_stopped.reset();
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+
+        while r.events_stopped.read().bits() == 0 {}
+        r.events_stopped.reset();
+
+        r.enable.write(|w| w.enable().disabled());
+    }
+}
+
+impl<'d> Drop for I2s<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+
+        r.enable.write(|w| w.enable().disabled());
+        r.config.rxen.write(|w| w.rxen().disabled());
+        r.config.txen.write(|w| w.txen().disabled());
+
+        gpio::deconfigure_pin(r.psel.mck.read().bits());
+        gpio::deconfigure_pin(r.psel.sck.read().bits());
+        gpio::deconfigure_pin(r.psel.lrck.read().bits());
+        gpio::deconfigure_pin(r.psel.sdin.read().bits());
+        gpio::deconfigure_pin(r.psel.sdout.read().bits());
+        r.psel.mck.reset();
+        r.psel.sdin.reset();
+        r.psel.sdout.reset();
+    }
+}
+
+/// I2S transmitter.
+pub struct OutputStream<'d> {
+    _i2s: I2s<'d>,
+}
+
+impl<'d> OutputStream<'d> {
+    /// Continuous transmission with double buffers.
+    ///
+    /// A producer closure is provided that is called with each buffer that should be filled with
+    /// the next samples, while the peripheral keeps transmitting the other one. The first buffer
+    /// is filled before transmission starts. A command is returned from the closure that
+    /// indicates whether the stream should continue or stop.
+    ///
+    /// NOTE: The time spent within the closure should not exceed the time taken to transmit a
+    /// single buffer, otherwise the previous buffer is transmitted again.
+    ///
+    /// The stream is stopped prior to returning in order to reduce power consumption, and to free
+    /// the buffers from being used by the peripheral. Cancellation will also cause the stream to
+    /// be stopped.
+    pub async fn stream<S: Sample, const N: usize>(
+        &mut self,
+        bufs: &mut [[S; N]; 2],
+        mut producer: impl FnMut(&mut [S; N]) -> StreamState,
+    ) -> Result<(), Error> {
+        I2s::check_buffer(&bufs[0])?;
+        I2s::check_buffer(&bufs[1])?;
+
+        if producer(&mut bufs[0]) == StreamState::Stopped {
+            return Ok(());
+        }
+
+        // In case the future is dropped, stop the task and wait for it to end.
+        let on_drop = OnDrop::new(I2s::stop_immediately);
+
+        let r = I2s::regs();
+
+        // Set up the initial DMA
+        I2s::set_maxcnt::<S>(N);
+        I2s::set_tx_ptr(&bufs[0]);
+        I2s::start(false, true);
+
+        let mut next_buffer = 1;
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_txptrupd.read().bits() != 0 {
+                r.events_txptrupd.reset();
+                r.intenset.write(|w| w.txptrupd().set());
+
+                compiler_fence(Ordering::SeqCst);
+
+                // The pointer has been latched, so the other buffer is no longer in use.
+                if producer(&mut bufs[next_buffer]) == StreamState::Stopped {
+                    return Poll::Ready(());
+                }
+                I2s::set_tx_ptr(&bufs[next_buffer]);
+                next_buffer = 1 - next_buffer;
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+        I2s::stop().await;
+
+        Ok(())
+    }
+}
+
+/// I2S receiver.
+pub struct InputStream<'d> {
+    _i2s: I2s<'d>,
+}
+
+impl<'d> InputStream<'d> {
+    /// Continuous reception with double buffers.
+    ///
+    /// A consumer closure is provided that receives each buffer as soon as it has been filled,
+    /// while the peripheral keeps filling the other one. A command is returned from the closure
+    /// that indicates whether the stream should continue or stop.
+    ///
+    /// NOTE: The time spent within the closure should not exceed the time taken to receive a
+    /// single buffer, otherwise samples are lost.
+    ///
+    /// The stream is stopped prior to returning in order to reduce power consumption, and to free
+    /// the buffers from being used by the peripheral. Cancellation will also cause the stream to
+    /// be stopped.
+    pub async fn stream<S: Sample, const N: usize>(
+        &mut self,
+        bufs: &mut [[S; N]; 2],
+        mut consumer: impl FnMut(&[S; N]) -> StreamState,
+    ) -> Result<(), Error> {
+        I2s::check_buffer(&bufs[0])?;
+        I2s::check_buffer(&bufs[1])?;
+
+        // In case the future is dropped, stop the task and wait for it to end.
+        let on_drop = OnDrop::new(I2s::stop_immediately);
+
+        let r = I2s::regs();
+
+        // Set up the initial DMA
+        I2s::set_maxcnt::<S>(N);
+        I2s::set_rx_ptr(&mut bufs[0]);
+        I2s::start(true, false);
+
+        let mut current_buffer = 0;
+        let mut first = true;
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_rxptrupd.read().bits() != 0 {
+                r.events_rxptrupd.reset();
+                r.intenset.write(|w| w.rxptrupd().set());
+
+                compiler_fence(Ordering::SeqCst);
+
+                // The first update only means the first buffer is now being filled.
+                if !first {
+                    if consumer(&bufs[current_buffer]) == StreamState::Stopped {
+                        return Poll::Ready(());
+                    }
+                    current_buffer = 1 - current_buffer;
+                }
+                first = false;
+
+                // Queue the buffer to be used next.
+                I2s::set_rx_ptr(&mut bufs[1 - current_buffer]);
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+        I2s::stop().await;
+
+        Ok(())
+    }
+}
+
+/// I2S transmitter and receiver.
+pub struct FullDuplexStream<'d> {
+    _i2s: I2s<'d>,
+}
+
+impl<'d> FullDuplexStream<'d> {
+    /// Continuous transmission and reception with double buffers.
+    ///
+    /// The closure receives each buffer that has just been received, together with the transmit
+    /// buffer that should be filled next. Both directions share the same buffer size. See
+    /// [`OutputStream::stream`] and [`InputStream::stream`] for timing requirements.
+    pub async fn stream<S: Sample, const N: usize>(
+        &mut self,
+        tx_bufs: &mut [[S; N]; 2],
+        rx_bufs: &mut [[S; N]; 2],
+        mut process: impl FnMut(&[S; N], &mut [S; N]) -> StreamState,
+    ) -> Result<(), Error> {
+        I2s::check_buffer(&tx_bufs[0])?;
+        I2s::check_buffer(&tx_bufs[1])?;
+        I2s::check_buffer(&rx_bufs[0])?;
+        I2s::check_buffer(&rx_bufs[1])?;
+
+        // Nothing has been received yet, so transmit silence first.
+        tx_bufs[0] = [S::default(); N];
+
+        // In case the future is dropped, stop the task and wait for it to end.
+        let on_drop = OnDrop::new(I2s::stop_immediately);
+
+        let r = I2s::regs();
+
+        // Set up the initial DMA
+        I2s::set_maxcnt::<S>(N);
+        I2s::set_tx_ptr(&tx_bufs[0]);
+        I2s::set_rx_ptr(&mut rx_bufs[0]);
+        I2s::start(true, true);
+
+        let mut done_buffer = 0;
+        let mut first = true;
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            // Both pointers are latched at the same time, wait until both have been.
+            if r.events_rxptrupd.read().bits() != 0 && r.events_txptrupd.read().bits() != 0 {
+                r.events_rxptrupd.reset();
+                r.events_txptrupd.reset();
+                r.intenset.write(|w| {
+                    w.rxptrupd().set();
+                    w.txptrupd().set();
+                    w
+                });
+
+                compiler_fence(Ordering::SeqCst);
+
+                // The first update only means the first buffers are now in use.
+                if first {
+                    tx_bufs[1] = [S::default(); N];
+                    I2s::set_tx_ptr(&tx_bufs[1]);
+                    I2s::set_rx_ptr(&mut rx_bufs[1]);
+                    first = false;
+                } else {
+                    if process(&rx_bufs[done_buffer], &mut tx_bufs[done_buffer]) == StreamState::Stopped {
+                        return Poll::Ready(());
+                    }
+                    I2s::set_tx_ptr(&tx_bufs[done_buffer]);
+                    I2s::set_rx_ptr(&mut rx_bufs[done_buffer]);
+                    done_buffer = 1 - done_buffer;
+                }
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+        I2s::stop().await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+//! Interprocessor communication (IPC) interface, for the nRF5340.
+//!
+//! The IPC peripheral lets the application and network cores signal each other. Each core has
+//! its own IPC peripheral, with 16 channels shared between them. This driver uses channel `n`
+//! for both the SEND task `n` and the RECEIVE event `n`, on both cores, so signalling channel
+//! `n` on one core fires event `n` on the other one. The firmware of both cores must use this
+//! driver, or the same convention.
+//!
+//! Signals carry no data. To exchange data, a [`SharedRing`] placed in RAM accessible to both
+//! cores can be used through a [`Sender`] and a [`Receiver`], which use IPC channels to signal
+//! new data and freed space.
+//!
+//! On the application core, the network core is held in reset until it is released with
+//! [`start_network_core`]. It boots from its own flash, which must be programmed separately,
+//! for example with a debugger or from a bootloader.
+
+use core::cell::UnsafeCell;
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::InterruptExt;
+use crate::peripherals::IPC;
+use crate::ppi::{Event, Task};
+use crate::{interrupt, pac, Peripheral};
+
+/// Number of IPC channels.
+pub const CHANNEL_COUNT: usize = 16;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NEW_AW: AtomicWaker = AtomicWaker::new();
+static WAKERS: [AtomicWaker; CHANNEL_COUNT] = [NEW_AW; CHANNEL_COUNT];
+
+fn regs() -> &'static pac::ipc::RegisterBlock {
+    unsafe { &*pac::IPC::ptr() }
+}
+
+/// IPC driver.
+pub struct Ipc<'d> {
+    _p: PeripheralRef<'d, IPC>,
+}
+
+impl<'d> Ipc<'d> {
+    /// Create a new IPC driver.
+    ///
+    /// This routes SEND task `n` and RECEIVE event `n` to channel `n`, for every channel.
+    pub fn new(ipc: impl Peripheral<P = IPC> + 'd, irq: impl Peripheral<P = interrupt::IPC> + 'd) -> Self {
+        into_ref!(ipc, irq);
+
+        let r = regs();
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF) });
+        for n in 0..CHANNEL_COUNT {
+            r.send_cnf[n].write(|w| unsafe { w.bits(1 << n) });
+            r.receive_cnf[n].write(|w| unsafe { w.bits(1 << n) });
+            r.events_receive[n].reset();
+        }
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self { _p: ipc }
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = regs();
+
+        let enabled = r.inten.read().bits();
+        for (n, event) in r.events_receive.iter().enumerate() {
+            if enabled & (1 << n) != 0 && event.read().bits() != 0 {
+                r.intenclr.write(|w| unsafe { w.bits(1 << n) });
+                WAKERS[n].wake();
+            }
+        }
+    }
+
+    /// Get a handle to channel `n`.
+    ///
+    /// Panics if `n` is not lower than [`CHANNEL_COUNT`].
+    pub fn channel(&mut self, n: usize) -> Channel<'_> {
+        assert!(n < CHANNEL_COUNT);
+        Channel { n, _p: PhantomData }
+    }
+
+    /// Split the driver into handles to each of its channels.
+    pub fn split(self) -> [Channel<'d>; CHANNEL_COUNT] {
+        core::array::from_fn(|n| Channel { n, _p: PhantomData })
+    }
+
+    /// Read general purpose memory register `n`, shared by the IPC peripherals of both cores.
+    ///
+    /// This is commonly used to pass the address of a [`SharedRing`] to the other core.
+    ///
+    /// Panics if `n` is larger than 1.
+    pub fn gpmem(&self, n: usize) -> u32 {
+        regs().gpmem[n].read().bits()
+    }
+
+    /// Write general purpose memory register `n`, shared by the IPC peripherals of both cores.
+    ///
+    /// Panics if `n` is larger than 1.
+    pub fn set_gpmem(&mut self, n: usize, value: u32) {
+        regs().gpmem[n].write(|w| unsafe { w.bits(value) });
+    }
+}
+
+impl<'d> Drop for Ipc<'d> {
+    fn drop(&mut self) {
+        regs().intenclr.write(|w| unsafe { w.bits(0xFFFF) });
+    }
+}
+
+/// Handle to a single IPC channel.
+pub struct Channel<'d> {
+    n: usize,
+    _p: PhantomData<&'d IPC>,
+}
+
+impl<'d> Channel<'d> {
+    /// Index of this channel.
+    pub fn number(&self) -> usize {
+        self.n
+    }
+
+    /// Signal the channel, firing its RECEIVE event on the other core.
+    pub fn signal(&self) {
+        regs().tasks_send[self.n].write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Wait for the other core to signal the channel.
+    ///
+    /// Only signals sent after this is called are seen.
+    pub async fn wait(&mut self) {
+        let r = regs();
+        let n = self.n;
+
+        r.events_receive[n].reset();
+        r.intenset.write(|w| unsafe { w.bits(1 << n) });
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| unsafe { w.bits(1 << n) });
+        });
+
+        poll_fn(|cx| {
+            WAKERS[n].register(cx.waker());
+
+            if r.events_receive[n].read().bits() != 0 {
+                r.events_receive[n].reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+    }
+
+    /// Wait until `f` returns `Some`, checking it again every time the channel is signalled.
+    async fn wait_until<R>(&mut self, mut f: impl FnMut() -> Option<R>) -> R {
+        let r = regs();
+        let n = self.n;
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| unsafe { w.bits(1 << n) });
+        });
+
+        let res = poll_fn(|cx| {
+            WAKERS[n].register(cx.waker());
+
+            // Arm the interrupt before checking, so a signal sent in between isn't missed.
+            r.events_receive[n].reset();
+            r.intenset.write(|w| unsafe { w.bits(1 << n) });
+
+            match f() {
+                Some(res) => Poll::Ready(res),
+                None => Poll::Pending,
+            }
+        })
+        .await;
+
+        drop(on_drop);
+        res
+    }
+
+    /// Returns the SEND task, for use with PPI.
+    pub fn task_send(&self) -> Task {
+        Task::from_reg(&regs().tasks_send[self.n])
+    }
+
+    /// Returns the RECEIVE event, for use with PPI.
+    pub fn event_receive(&self) -> Event {
+        Event::from_reg(&regs().events_receive[self.n])
+    }
+}
+
+/// Single-producer single-consumer byte ring buffer, shared between the two cores.
+///
+/// It must be placed in RAM that both cores can access, and both firmwares must agree on its
+/// address and size `N`. One core owns the memory, typically the application core with a
+/// `static`, and the other one gets a reference to it with [`SharedRing::from_addr`]. If the
+/// application core runs in secure mode, the RAM region must be made non-secure with the SPU
+/// for the network core to access it.
+///
+/// It holds at most `N - 1` bytes.
+#[repr(C)]
+pub struct SharedRing<const N: usize> {
+    write: AtomicUsize,
+    read: AtomicUsize,
+    buf: UnsafeCell<[u8; N]>,
+}
+
+unsafe impl<const N: usize> Sync for SharedRing<N> {}
+
+impl<const N: usize> Default for SharedRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SharedRing<N> {
+    /// Create an empty ring buffer.
+    pub const fn new() -> Self {
+        Self {
+            write: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            buf: UnsafeCell::new([0; N]),
+        }
+    }
+
+    /// Get a reference to a ring buffer owned by the other core.
+    ///
+    /// # Safety
+    /// `addr` must point to a `SharedRing<N>`, created by the other core with the same `N`,
+    /// that lives forever and is only used as a ring buffer from then on.
+    pub unsafe fn from_addr(addr: u32) -> &'static Self {
+        &*(addr as *const Self)
+    }
+
+    /// Address of the ring buffer, to pass to the other core.
+    pub fn addr(&self) -> u32 {
+        self as *const Self as u32
+    }
+
+    /// Empty the ring buffer.
+    ///
+    /// This must only be called while the other core isn't using it, such as before starting it.
+    pub fn reset(&self) {
+        self.write.store(0, Ordering::Relaxed);
+        self.read.store(0, Ordering::Release);
+    }
+
+    /// Copy as much of `data` as fits into the ring buffer, returning how much was copied.
+    fn push(&self, data: &[u8]) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        let mut write = self.write.load(Ordering::Relaxed);
+        let free = (read + N - write - 1) % N;
+        let n = data.len().min(free);
+
+        let buf = self.buf.get() as *mut u8;
+        for &b in &data[..n] {
+            unsafe { buf.add(write).write_volatile(b) };
+            write = (write + 1) % N;
+        }
+
+        self.write.store(write, Ordering::Release);
+        n
+    }
+
+    /// Copy as many bytes as available into `data`, returning how many were copied.
+    fn pop(&self, data: &mut [u8]) -> usize {
+        let write = self.write.load(Ordering::Acquire);
+        let mut read = self.read.load(Ordering::Relaxed);
+        let available = (write + N - read) % N;
+        let n = data.len().min(available);
+
+        let buf = self.buf.get() as *const u8;
+        for b in &mut data[..n] {
+            *b = unsafe { buf.add(read).read_volatile() };
+            read = (read + 1) % N;
+        }
+
+        self.read.store(read, Ordering::Release);
+        n
+    }
+}
+
+/// Sending half of a byte pipe to the other core, over a [`SharedRing`].
+///
+/// The other core must use a [`Receiver`] on the same ring buffer, with the same `data` and
+/// `space` channels.
+pub struct Sender<'d, const N: usize> {
+    ring: &'d SharedRing<N>,
+    data: Channel<'d>,
+    space: Channel<'d>,
+}
+
+impl<'d, const N: usize> Sender<'d, N> {
+    /// Create a sender, signalling new data on `data` and waiting for freed space on `space`.
+    pub fn new(ring: &'d SharedRing<N>, data: Channel<'d>, space: Channel<'d>) -> Self {
+        Self { ring, data, space }
+    }
+
+    /// Send all of `buf`, waiting for the receiver to make room as needed.
+    pub async fn send(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let ring = self.ring;
+            let n = self
+                .space
+                .wait_until(|| match ring.push(buf) {
+                    0 => None,
+                    n => Some(n),
+                })
+                .await;
+            self.data.signal();
+            buf = &buf[n..];
+        }
+    }
+}
+
+/// Receiving half of a byte pipe from the other core, over a [`SharedRing`].
+///
+/// The other core must use a [`Sender`] on the same ring buffer, with the same `data` and
+/// `space` channels.
+pub struct Receiver<'d, const N: usize> {
+    ring: &'d SharedRing<N>,
+    data: Channel<'d>,
+    space: Channel<'d>,
+}
+
+impl<'d, const N: usize> Receiver<'d, N> {
+    /// Create a receiver, waiting for new data on `data` and signalling freed space on `space`.
+    pub fn new(ring: &'d SharedRing<N>, data: Channel<'d>, space: Channel<'d>) -> Self {
+        Self { ring, data, space }
+    }
+
+    /// Receive at least one byte into `buf`, returning how many were received.
+    ///
+    /// Panics if `buf` is empty.
+    pub async fn receive(&mut self, buf: &mut [u8]) -> usize {
+        assert!(!buf.is_empty());
+
+        let ring = self.ring;
+        let n = self
+            .data
+            .wait_until(|| match ring.pop(buf) {
+                0 => None,
+                n => Some(n),
+            })
+            .await;
+        self.space.signal();
+        n
+    }
+}
+
+/// Release the network core from reset, letting it boot from its flash.
+///
+/// Set up anything the network core relies on at boot, such as [`SharedRing`]s and the
+/// [`Ipc`] general purpose memory, before calling this.
+#[cfg(feature = "_nrf5340-app")]
+pub fn start_network_core() {
+    reset_regs().network.forceoff.write(|w| w.forceoff().release());
+}
+
+/// Hold the network core in reset.
+#[cfg(feature = "_nrf5340-app")]
+pub fn stop_network_core() {
+    reset_regs().network.forceoff.write(|w| w.forceoff().hold());
+}
+
+/// Whether the network core has been released from reset.
+#[cfg(feature = "_nrf5340-app")]
+pub fn is_network_core_running() -> bool {
+    reset_regs().network.forceoff.read().forceoff().is_release()
+}
+
+#[cfg(feature = "_nrf5340-app")]
+fn reset_regs() -> &'static pac::reset::RegisterBlock {
+    unsafe { &*pac::RESET::ptr() }
+}
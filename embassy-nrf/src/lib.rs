@@ -30,7 +30,7 @@
 //! assert!(result.is_ok());
 //! ```
 //!
-//! Each peripheral struct which uses EasyDMA ([`Spim`](spim::Spim), [`Uarte`](uarte::Uarte), [`Twim`](twim::Twim)) has two variants of their mutating functions:
+//! Each peripheral struct which uses EasyDMA ([`Spim`](spim::Spim), [`Uarte`](uarte::Uarte), [`Twim`](twim::Twim), [`Twis`](twis::Twis)) has two variants of their mutating functions:
 //! - Functions with the suffix (e.g. [`write_from_ram`](spim::Spim::write_from_ram), [`transfer_from_ram`](spim::Spim::transfer_from_ram)) will return an error if the passed slice does not reside in RAM.
 //! - Functions without the suffix (e.g. [`write`](spim::Spim::write), [`transfer`](spim::Spim::transfer)) will check whether the data is in RAM and copy it into memory prior to transmission.
 //!
@@ -41,6 +41,13 @@
 //!
 //! Note that the methods that read data like [`read`](spim::Spim::read) and [`transfer_in_place`](spim::Spim::transfer_in_place) do not have the corresponding `_from_ram` variants as
 //! mutable slices always reside in RAM.
+//!
+//! ## nRF9160 modem
+//!
+//! The LTE modem of the nRF9160 is driven by Nordic's closed `nrf_modem` library, which talks to the modem firmware
+//! over the IPC peripheral and shared memory. This HAL doesn't wrap it: the [`nrf-modem`](https://crates.io/crates/nrf-modem)
+//! crate provides async AT command and socket APIs on top of it. It needs the `IPC` and `EGU1` interrupts, and its shared
+//! memory in the first 128 kB of RAM, in regions the SPU marks as non-secure.
 
 #![no_std]
 #![cfg_attr(feature = "nightly", feature(type_alias_impl_trait))]
@@ -69,13 +76,45 @@ pub(crate) mod util;
 #[cfg(feature = "_time-driver")]
 mod time_driver;
 
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+pub mod aar;
 #[cfg(feature = "nightly")]
 pub mod buffered_uarte;
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+pub mod ccm;
+#[cfg(any(
+    feature = "nrf52810",
+    feature = "nrf52811",
+    feature = "nrf52820",
+    feature = "nrf52832",
+    feature = "nrf52833",
+    feature = "nrf52840"
+))]
+pub mod comp;
+pub mod egu;
 pub mod gpio;
 #[cfg(feature = "gpiote")]
 pub mod gpiote;
+#[cfg(any(feature = "nrf52832", feature = "nrf52833", feature = "nrf52840"))]
+pub mod i2s;
+#[cfg(feature = "_nrf5340")]
+pub mod ipc;
+#[cfg(any(feature = "nrf52832", feature = "nrf52833", feature = "nrf52840"))]
+pub mod lpcomp;
+#[cfg(any(feature = "nrf52832", feature = "nrf52833", feature = "nrf52840"))]
+pub mod nfct;
 #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
 pub mod nvmc;
+#[cfg(any(
+    feature = "nrf52810",
+    feature = "nrf52811",
+    feature = "nrf52832",
+    feature = "nrf52833",
+    feature = "nrf52840"
+))]
+pub mod pdm;
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+pub mod power;
 pub mod ppi;
 #[cfg(not(any(feature = "nrf52805", feature = "nrf52820", feature = "_nrf5340-net")))]
 pub mod pwm;
@@ -83,15 +122,20 @@ pub mod pwm;
 pub mod qdec;
 #[cfg(feature = "nrf52840")]
 pub mod qspi;
+#[cfg(all(feature = "time", not(any(feature = "_nrf5340-app", feature = "_nrf9160"))))]
+pub mod radio;
 #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
 pub mod rng;
+pub mod rtc;
 #[cfg(not(any(feature = "nrf52820", feature = "_nrf5340-net")))]
 pub mod saadc;
 pub mod spim;
+pub mod spis;
 #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
 pub mod temp;
 pub mod timer;
 pub mod twim;
+pub mod twis;
 pub mod uarte;
 #[cfg(any(
     feature = "_nrf5340-app",
@@ -166,6 +210,38 @@ pub mod config {
         ExternalFullSwing,
     }
 
+    /// DC/DC regulator configuration.
+    ///
+    /// The DC/DC converters are much more efficient than the LDOs they replace, but they need
+    /// an external inductor. Only enable the ones the board has the inductor fitted for, or the
+    /// chip won't be powered properly.
+    #[cfg(not(any(feature = "_nrf5340-net", feature = "_nrf9160")))]
+    #[non_exhaustive]
+    #[derive(Default)]
+    pub struct DcdcConfig {
+        /// Use the DC/DC converter for REG0, the high voltage stage supplied from VDDH.
+        #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+        pub reg0: bool,
+        /// Output voltage of REG0, which supplies VDD when the chip is powered from VDDH.
+        ///
+        /// This is stored in UICR.REGOUT0, which only takes effect after a reset. If it has to be
+        /// written, the chip is reset right away. `None` leaves the UICR untouched.
+        #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+        pub reg0_voltage: Option<crate::nvmc::Regout0Voltage>,
+        /// Use the DC/DC converter for REG1, the main 1.3 V stage.
+        #[cfg(not(feature = "_nrf5340"))]
+        pub reg1: bool,
+        /// Use the DC/DC converter for the main regulator.
+        #[cfg(feature = "_nrf5340-app")]
+        pub regmain: bool,
+        /// Use the DC/DC converter for the network core and radio regulator.
+        #[cfg(feature = "_nrf5340-app")]
+        pub regradio: bool,
+        /// Use the DC/DC converter for the high voltage stage supplied from VDDH.
+        #[cfg(feature = "_nrf5340-app")]
+        pub regh: bool,
+    }
+
     /// Configuration for peripherals. Default configuration should work on any nRF chip.
     #[non_exhaustive]
     pub struct Config {
@@ -173,6 +249,9 @@ pub mod config {
         pub hfclk_source: HfclkSource,
         /// Low frequency clock source.
         pub lfclk_source: LfclkSource,
+        /// DC/DC regulator configuration. All converters are disabled by default.
+        #[cfg(not(any(feature = "_nrf5340-net", feature = "_nrf9160")))]
+        pub dcdc: DcdcConfig,
         /// GPIOTE interrupt priority. Should be lower priority than softdevice if used.
         #[cfg(feature = "gpiote")]
         pub gpiote_interrupt_priority: crate::interrupt::Priority,
@@ -189,6 +268,8 @@ pub mod config {
                 // xtals if they know they have them.
                 hfclk_source: HfclkSource::Internal,
                 lfclk_source: LfclkSource::InternalRC,
+                #[cfg(not(any(feature = "_nrf5340-net", feature = "_nrf9160")))]
+                dcdc: DcdcConfig::default(),
                 #[cfg(feature = "gpiote")]
                 gpiote_interrupt_priority: crate::interrupt::Priority::P0,
                 #[cfg(feature = "_time-driver")]
@@ -204,6 +285,42 @@ pub fn init(config: config::Config) -> Peripherals {
     // before doing anything important.
     let peripherals = Peripherals::take();
 
+    // Set REGOUT0 first, as it needs a reset to take effect.
+    #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+    if let Some(voltage) = config.dcdc.reg0_voltage {
+        match nvmc::uicr_write(nvmc::UICR_REGOUT0, 0xFFFF_FFF8 | voltage as u32) {
+            Ok(nvmc::UicrWrite::Written) => cortex_m::peripheral::SCB::sys_reset(),
+            Ok(nvmc::UicrWrite::Unchanged) => {}
+            Err(_) => warn!("UICR.REGOUT0 already holds a different voltage, erase the UICR to change it"),
+        }
+    }
+
+    // Enable the DC/DC converters.
+    #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+    {
+        let r = unsafe { &*pac::POWER::ptr() };
+        #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+        if config.dcdc.reg0 {
+            r.dcdcen0.write(|w| w.dcdcen().set_bit());
+        }
+        if config.dcdc.reg1 {
+            r.dcdcen.write(|w| w.dcdcen().set_bit());
+        }
+    }
+    #[cfg(feature = "_nrf5340-app")]
+    {
+        let r = unsafe { &*pac::REGULATORS::ptr() };
+        if config.dcdc.regmain {
+            r.vregmain.dcdcen.write(|w| w.dcdcen().set_bit());
+        }
+        if config.dcdc.regradio {
+            r.vregradio.dcdcen.write(|w| w.dcdcen().set_bit());
+        }
+        if config.dcdc.regh {
+            r.vregh.dcdcen.write(|w| w.dcdcen().set_bit());
+        }
+    }
+
     let r = unsafe { &*pac::CLOCK::ptr() };
 
     // Start HFCLK.
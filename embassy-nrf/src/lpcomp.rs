@@ -0,0 +1,273 @@
+//! Low power comparator (LPCOMP) interface.
+//!
+//! The low power comparator compares an analog input against a fraction of the supply voltage.
+//! It draws much less current than [`Comp`](crate::comp::Comp), and can also wake the chip from
+//! System OFF when the input crosses the reference.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::comp::analog_input_index;
+pub use crate::comp::Level;
+use crate::interrupt::InterruptExt;
+use crate::ppi::{Event, Task};
+use crate::saadc::Input;
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+/// Low power comparator driver.
+pub struct Lpcomp<'d> {
+    _p: PeripheralRef<'d, peripherals::LPCOMP>,
+}
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Used to configure the LPCOMP peripheral.
+///
+/// See the `Default` impl for suitable default values.
+#[non_exhaustive]
+pub struct Config {
+    /// Reference voltage, as a fraction of the supply voltage.
+    pub reference: Reference,
+    /// Enable 50 mV hysteresis.
+    pub hysteresis: bool,
+    /// Which crossing wakes the chip from System OFF.
+    pub detect: Detect,
+}
+
+impl Default for Config {
+    /// Default configuration with a reference at half the supply voltage.
+    fn default() -> Self {
+        Self {
+            reference: Reference::Vdd4_8,
+            hysteresis: false,
+            detect: Detect::Cross,
+        }
+    }
+}
+
+/// Reference voltage, as a fraction of the supply voltage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reference {
+    Vdd1_8 = 0,
+    Vdd2_8 = 1,
+    Vdd3_8 = 2,
+    Vdd4_8 = 3,
+    Vdd5_8 = 4,
+    Vdd6_8 = 5,
+    Vdd7_8 = 6,
+    Vdd1_16 = 8,
+    Vdd3_16 = 9,
+    Vdd5_16 = 10,
+    Vdd7_16 = 11,
+    Vdd9_16 = 12,
+    Vdd11_16 = 13,
+    Vdd13_16 = 14,
+    Vdd15_16 = 15,
+}
+
+/// Crossing that wakes the chip from System OFF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Detect {
+    /// Crossing in either direction.
+    Cross,
+    /// Rising above the reference.
+    Up,
+    /// Falling below the reference.
+    Down,
+}
+
+impl<'d> Lpcomp<'d> {
+    /// Compare `input` against the configured reference.
+    pub fn new(
+        lpcomp: impl Peripheral<P = peripherals::LPCOMP> + 'd,
+        irq: impl Peripheral<P = interrupt::COMP_LPCOMP> + 'd,
+        input: impl Peripheral<P = impl Input> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(lpcomp, irq, input);
+
+        let r = Self::regs();
+
+        r.psel
+            .write(|w| unsafe { w.psel().bits(analog_input_index(&input.map_into())) });
+        r.refsel.write(|w| unsafe { w.refsel().bits(config.reference as u8) });
+        r.hyst.write(|w| w.hyst().bit(config.hysteresis));
+        r.anadetect.write(|w| match config.detect {
+            Detect::Cross => w.anadetect().cross(),
+            Detect::Up => w.anadetect().up(),
+            Detect::Down => w.anadetect().down(),
+        });
+
+        // Disable all events interrupts
+        r.intenclr.write(|w| unsafe { w.bits(0x0000_000F) });
+        r.shorts.reset();
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        r.enable.write(|w| w.enable().enabled());
+
+        // Start the comparator, it only takes a few microseconds to be ready.
+        r.events_ready.reset();
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+        while r.events_ready.read().bits() == 0 {}
+        r.events_ready.reset();
+
+        Self { _p: lpcomp }
+    }
+
+    fn on_interrupt(_ctx: *mut ()) {
+        let r = Self::regs();
+
+        if r.events_up.read().bits() != 0 {
+            r.intenclr.write(|w| w.up().clear());
+            WAKER.wake();
+        }
+
+        if r.events_down.read().bits() != 0 {
+            r.intenclr.write(|w| w.down().clear());
+            WAKER.wake();
+        }
+
+        if r.events_cross.read().bits() != 0 {
+            r.intenclr.write(|w| w.cross().clear());
+            WAKER.wake();
+        }
+    }
+
+    fn regs() -> &'static pac::lpcomp::RegisterBlock {
+        unsafe { &*pac::LPCOMP::ptr() }
+    }
+
+    /// Sample the comparator output.
+    pub fn sample(&mut self) -> Level {
+        let r = Self::regs();
+
+        r.tasks_sample.write(|w| unsafe { w.bits(1) });
+        if r.result.read().result().is_above() {
+            Level::Above
+        } else {
+            Level::Below
+        }
+    }
+
+    /// Wait for the input to rise above the reference.
+    pub async fn wait_for_up(&mut self) {
+        let r = Self::regs();
+
+        r.events_up.reset();
+        r.intenset.write(|w| w.up().set());
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| w.up().clear());
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_up.read().bits() != 0 {
+                r.events_up.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+    }
+
+    /// Wait for the input to fall below the reference.
+    pub async fn wait_for_down(&mut self) {
+        let r = Self::regs();
+
+        r.events_down.reset();
+        r.intenset.write(|w| w.down().set());
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| w.down().clear());
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_down.read().bits() != 0 {
+                r.events_down.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+    }
+
+    /// Wait for the input to cross the reference in either direction, returning the new level.
+    pub async fn wait_for_cross(&mut self) -> Level {
+        let r = Self::regs();
+
+        r.events_cross.reset();
+        r.intenset.write(|w| w.cross().set());
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| w.cross().clear());
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_cross.read().bits() != 0 {
+                r.events_cross.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+        self.sample()
+    }
+
+    /// Returns reference to `Up` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event_up(&self) -> Event {
+        Event::from_reg(&Self::regs().events_up)
+    }
+
+    /// Returns reference to `Down` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event_down(&self) -> Event {
+        Event::from_reg(&Self::regs().events_down)
+    }
+
+    /// Returns reference to `Cross` event endpoint for PPI.
+    #[inline(always)]
+    pub fn event_cross(&self) -> Event {
+        Event::from_reg(&Self::regs().events_cross)
+    }
+
+    /// Returns reference to `Sample` task endpoint for PPI.
+    #[inline(always)]
+    pub fn task_sample(&self) -> Task {
+        Task::from_reg(&Self::regs().tasks_sample)
+    }
+}
+
+impl<'d> Drop for Lpcomp<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+        r.intenclr.write(|w| unsafe { w.bits(0x0000_000F) });
+        r.enable.write(|w| w.enable().disabled());
+    }
+}
@@ -0,0 +1,948 @@
+//! NFC tag (NFCT) interface.
+//!
+//! The NFCT peripheral implements the NFC-A listen mode. Field detection and anti-collision are
+//! handled in hardware, once the tag has been selected by a reader all further frames are
+//! exchanged with [`NfcT::receive`] and [`NfcT::transmit`]. [`Type2Tag`] builds a minimal NFC Forum
+//! Type 2 tag on top of that, and [`Type4Tag`] a Type 4 tag serving an NDEF file over ISO-DEP. The
+//! [`ndef`] module helps with filling their memory.
+//!
+//! The NFC antenna pins are shared with GPIOs, and are only usable for NFC while the `NFCPINS`
+//! UICR register is left at its erased value.
+
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::InterruptExt;
+use crate::util::slice_in_ram_or;
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The reader left the field, the tag has to be selected again.
+    FieldLost,
+    /// Received frame had a CRC error.
+    Crc,
+    /// Received frame had a parity error.
+    Parity,
+    /// Received frame didn't fit in the buffer.
+    Overrun,
+    BufferTooLong,
+    /// EasyDMA can only read from data memory, read only buffers in flash will fail.
+    DMABufferNotInDataMemory,
+}
+
+/// The maximum frame size in bytes, limited by the MAXLEN register.
+pub const MAX_FRAME_LEN: usize = 257;
+
+// Event interrupt bits, shared by the INTEN, INTENSET and INTENCLR registers.
+const INT_FIELDDETECTED: u32 = 1 << 1;
+const INT_FIELDLOST: u32 = 1 << 2;
+const INT_TXFRAMEEND: u32 = 1 << 4;
+const INT_RXFRAMEEND: u32 = 1 << 6;
+const INT_SELECTED: u32 = 1 << 19;
+
+// SHORTS register bits.
+const SHORT_FIELDDETECTED_ACTIVATE: u32 = 1 << 0;
+const SHORT_FIELDLOST_SENSE: u32 = 1 << 1;
+
+// FRAMECONFIG register bits, shared by transmit and receive.
+const FRAMECONFIG_PARITY: u32 = 1 << 0;
+const FRAMECONFIG_SOF: u32 = 1 << 2;
+const FRAMECONFIG_CRC: u32 = 1 << 4;
+
+// FRAMESTATUS.RX register bits.
+const FRAMESTATUS_CRCERROR: u32 = 1 << 0;
+const FRAMESTATUS_PARITYSTATUS: u32 = 1 << 2;
+const FRAMESTATUS_OVERRUN: u32 = 1 << 3;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Used to configure the NFCT peripheral.
+#[non_exhaustive]
+pub struct Config {
+    /// NFCID1 sent during anti-collision.
+    pub nfcid1: NfcId,
+    /// Protocol advertised in the SEL_RES response.
+    pub protocol: Protocol,
+}
+
+impl Config {
+    /// Configuration for a tag with the given ID and protocol.
+    pub fn new(nfcid1: NfcId, protocol: Protocol) -> Self {
+        Self { nfcid1, protocol }
+    }
+}
+
+/// NFCID1, the unique ID of an NFC-A tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NfcId {
+    SingleSize([u8; 4]),
+    DoubleSize([u8; 7]),
+    TripleSize([u8; 10]),
+}
+
+/// Protocol advertised in the SEL_RES response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protocol {
+    /// NFC Forum Type 2 tag.
+    Type2,
+    /// NFC Forum Type 4 tag, using ISO-DEP.
+    Type4,
+}
+
+/// NFC tag driver.
+pub struct NfcT<'d> {
+    _p: PeripheralRef<'d, peripherals::NFCT>,
+}
+
+impl<'d> NfcT<'d> {
+    /// Create a new NFC tag, which immediately starts sensing for a field.
+    pub fn new(
+        nfct: impl Peripheral<P = peripherals::NFCT> + 'd,
+        irq: impl Peripheral<P = interrupt::NFCT> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(nfct, irq);
+
+        let r = Self::regs();
+
+        // The NFCID1 is sent starting with the most significant byte of the first register used.
+        let (size, last, second_last, third_last) = match config.nfcid1 {
+            NfcId::SingleSize(id) => (0, id, [0; 3], [0; 3]),
+            NfcId::DoubleSize(id) => (1, [id[3], id[4], id[5], id[6]], [id[0], id[1], id[2]], [0; 3]),
+            NfcId::TripleSize(id) => (
+                2,
+                [id[6], id[7], id[8], id[9]],
+                [id[3], id[4], id[5]],
+                [id[0], id[1], id[2]],
+            ),
+        };
+        let three_bytes = |b: [u8; 3]| u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        r.nfcid1_last.write(|w| unsafe { w.bits(u32::from_be_bytes(last)) });
+        r.nfcid1_2nd_last.write(|w| unsafe { w.bits(three_bytes(second_last)) });
+        r.nfcid1_3rd_last.write(|w| unsafe { w.bits(three_bytes(third_last)) });
+
+        // SENS_RES: bit frame SDD 0b00100 as recommended, NFCID1 size, platform config 0.
+        r.sensres.write(|w| unsafe { w.bits(0b00100 | (size << 6)) });
+
+        // SEL_RES: the cascade bit is handled by hardware.
+        let protocol = match config.protocol {
+            Protocol::Type2 => 0b00,
+            Protocol::Type4 => 0b01,
+        };
+        r.selres.write(|w| unsafe { w.bits(protocol << 5) });
+
+        // Disable all events interrupts
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+
+        // Let the hardware handle activation and anti-collision, and go back to sensing when
+        // the field is lost.
+        r.shorts
+            .write(|w| unsafe { w.bits(SHORT_FIELDDETECTED_ACTIVATE | SHORT_FIELDLOST_SENSE) });
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        r.events_fielddetected.reset();
+        r.events_fieldlost.reset();
+        r.events_selected.reset();
+        r.tasks_sense.write(|w| unsafe { w.bits(1) });
+
+        Self { _p: nfct }
+    }
+
+    fn on_interrupt(_ctx: *mut ()) {
+        let r = Self::regs();
+
+        let events = [
+            (r.events_fielddetected.read().bits(), INT_FIELDDETECTED),
+            (r.events_fieldlost.read().bits(), INT_FIELDLOST),
+            (r.events_txframeend.read().bits(), INT_TXFRAMEEND),
+            (r.events_rxframeend.read().bits(), INT_RXFRAMEEND),
+            (r.events_selected.read().bits(), INT_SELECTED),
+        ];
+
+        for (event, mask) in events {
+            if event != 0 {
+                r.intenclr.write(|w| unsafe { w.bits(mask) });
+                WAKER.wake();
+            }
+        }
+    }
+
+    fn regs() -> &'static pac::nfct::RegisterBlock {
+        unsafe { &*pac::NFCT::ptr() }
+    }
+
+    /// Wait for a reader field to be present.
+    pub async fn wait_for_field(&mut self) {
+        let r = Self::regs();
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| unsafe { w.bits(INT_FIELDDETECTED) });
+        });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            // Arm the interrupt before checking, so a field arriving in between isn't missed.
+            r.events_fielddetected.reset();
+            r.intenset.write(|w| unsafe { w.bits(INT_FIELDDETECTED) });
+
+            if r.fieldpresent.read().bits() & 0b11 != 0 {
+                r.intenclr.write(|w| unsafe { w.bits(INT_FIELDDETECTED) });
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+    }
+
+    /// Wait for the tag to be selected by a reader.
+    ///
+    /// Once selected, the reader sends its first command, which should be read with [`Self::receive`].
+    pub async fn wait_for_selected(&mut self) {
+        let r = Self::regs();
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| unsafe { w.bits(INT_SELECTED) });
+        });
+
+        r.intenset.write(|w| unsafe { w.bits(INT_SELECTED) });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_selected.read().bits() != 0 {
+                r.events_selected.reset();
+                return Poll::Ready(());
+            }
+
+            r.intenset.write(|w| unsafe { w.bits(INT_SELECTED) });
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+    }
+
+    /// Receive a frame from the reader, returning its length without the CRC.
+    pub async fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.len() > MAX_FRAME_LEN {
+            return Err(Error::BufferTooLong);
+        }
+
+        let r = Self::regs();
+
+        r.packetptr.write(|w| unsafe { w.bits(buf.as_mut_ptr() as u32) });
+        r.maxlen.write(|w| unsafe { w.bits(buf.len() as u32) });
+        r.rxd
+            .frameconfig
+            .write(|w| unsafe { w.bits(FRAMECONFIG_PARITY | FRAMECONFIG_SOF | FRAMECONFIG_CRC) });
+
+        r.events_rxframeend.reset();
+        r.framestatus
+            .rx
+            .write(|w| unsafe { w.bits(FRAMESTATUS_CRCERROR | FRAMESTATUS_PARITYSTATUS | FRAMESTATUS_OVERRUN) });
+
+        // Don't reorder the start task before the previous writes.
+        compiler_fence(Ordering::SeqCst);
+
+        r.tasks_enablerxdata.write(|w| unsafe { w.bits(1) });
+
+        self.wait_for_frame_end(INT_RXFRAMEEND, || r.events_rxframeend.read().bits() != 0)
+            .await?;
+        r.events_rxframeend.reset();
+
+        compiler_fence(Ordering::SeqCst);
+
+        let status = r.framestatus.rx.read().bits();
+        if status & FRAMESTATUS_OVERRUN != 0 {
+            return Err(Error::Overrun);
+        }
+        if status & FRAMESTATUS_PARITYSTATUS != 0 {
+            return Err(Error::Parity);
+        }
+        if status & FRAMESTATUS_CRCERROR != 0 {
+            return Err(Error::Crc);
+        }
+
+        // RXD.AMOUNT holds the number of full bytes in bits 3..12, including the CRC.
+        let amount = (r.rxd.amount.read().bits() >> 3) & 0x1FF;
+        Ok((amount as usize).saturating_sub(2))
+    }
+
+    /// Transmit a frame to the reader. The CRC is appended by hardware.
+    pub async fn transmit(&mut self, buf: &[u8]) -> Result<(), Error> {
+        if buf.len() > MAX_FRAME_LEN {
+            return Err(Error::BufferTooLong);
+        }
+        slice_in_ram_or(buf, Error::DMABufferNotInDataMemory)?;
+
+        self.start_tx(
+            buf,
+            (buf.len() as u32) << 3,
+            FRAMECONFIG_PARITY | FRAMECONFIG_SOF | FRAMECONFIG_CRC,
+        );
+        self.wait_for_tx().await
+    }
+
+    /// Transmit a 4-bit ACK or NAK short frame, as used by Type 2 tags.
+    pub async fn transmit_ack_nak(&mut self, nibble: u8) -> Result<(), Error> {
+        let buf = [nibble & 0x0F];
+
+        self.start_tx(&buf, 4, FRAMECONFIG_SOF);
+        self.wait_for_tx().await
+    }
+
+    fn start_tx(&mut self, buf: &[u8], amount: u32, frameconfig: u32) {
+        let r = Self::regs();
+
+        r.packetptr.write(|w| unsafe { w.bits(buf.as_ptr() as u32) });
+        r.maxlen.write(|w| unsafe { w.bits(buf.len() as u32) });
+        r.txd.amount.write(|w| unsafe { w.bits(amount) });
+        r.txd.frameconfig.write(|w| unsafe { w.bits(frameconfig) });
+
+        r.events_txframeend.reset();
+
+        // Don't reorder the start task before the previous writes.
+        compiler_fence(Ordering::SeqCst);
+
+        r.tasks_starttx.write(|w| unsafe { w.bits(1) });
+    }
+
+    async fn wait_for_tx(&mut self) -> Result<(), Error> {
+        let r = Self::regs();
+
+        self.wait_for_frame_end(INT_TXFRAMEEND, || r.events_txframeend.read().bits() != 0)
+            .await?;
+        r.events_txframeend.reset();
+
+        compiler_fence(Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    async fn wait_for_frame_end(&mut self, mask: u32, done: impl Fn() -> bool) -> Result<(), Error> {
+        let r = Self::regs();
+
+        r.events_fieldlost.reset();
+
+        let on_drop = OnDrop::new(|| {
+            r.intenclr.write(|w| unsafe { w.bits(mask | INT_FIELDLOST) });
+            // Going back to sensing aborts the transfer.
+            r.tasks_sense.write(|w| unsafe { w.bits(1) });
+        });
+
+        let result = poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if done() {
+                return Poll::Ready(Ok(()));
+            }
+            if r.events_fieldlost.read().bits() != 0 {
+                r.events_fieldlost.reset();
+                return Poll::Ready(Err(Error::FieldLost));
+            }
+
+            r.intenset.write(|w| unsafe { w.bits(mask | INT_FIELDLOST) });
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+        r.intenclr.write(|w| unsafe { w.bits(mask | INT_FIELDLOST) });
+
+        result
+    }
+
+    /// Go to the IDLE state, waiting to be woken up by the reader with a REQA or WUPA.
+    pub fn go_idle(&mut self) {
+        let r = Self::regs();
+        r.tasks_goidle.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Go to the SLEEP_A state, in response to a HLTA. The reader can only wake the tag with a WUPA.
+    pub fn go_sleep(&mut self) {
+        let r = Self::regs();
+        r.tasks_gosleep.write(|w| unsafe { w.bits(1) });
+    }
+}
+
+impl<'d> Drop for NfcT<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+
+        r.shorts.reset();
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        r.tasks_disable.write(|w| unsafe { w.bits(1) });
+    }
+}
+
+/// A minimal NFC Forum Type 2 tag, serving its memory to readers.
+///
+/// The memory is organized in 4-byte pages. Pages 0 to 2 hold the UID, lock bytes and
+/// capability container, the data area starts at page 4, where an NDEF message can be placed
+/// with [`ndef::write_type2_tlv`]. Pages 0 and 1 are not writable by the reader.
+pub struct Type2Tag<'a, 'd> {
+    nfct: &'a mut NfcT<'d>,
+    memory: &'a mut [u8],
+}
+
+impl<'a, 'd> Type2Tag<'a, 'd> {
+    const READ: u8 = 0x30;
+    const WRITE: u8 = 0xA2;
+    const HALT: u8 = 0x50;
+
+    const ACK: u8 = 0x0A;
+    const NAK_INVALID_ARGUMENT: u8 = 0x00;
+
+    /// Serve `memory`, which must be a whole number of pages, and at least 16 bytes long.
+    pub fn new(nfct: &'a mut NfcT<'d>, memory: &'a mut [u8]) -> Self {
+        assert!(memory.len() >= 16 && memory.len() % 4 == 0);
+        Self { nfct, memory }
+    }
+
+    /// Handle reader commands until the reader halts the tag or leaves the field.
+    ///
+    /// Returns `Ok` when the tag was halted, and `Err(Error::FieldLost)` when the reader left.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        let mut cmd = [0u8; 8];
+        let mut resp = [0u8; 16];
+
+        self.nfct.wait_for_selected().await;
+
+        loop {
+            let n = match self.nfct.receive(&mut cmd).await {
+                Ok(n) => n,
+                // Ignore corrupted frames, the reader will retry.
+                Err(Error::Crc | Error::Parity | Error::Overrun) => continue,
+                Err(e) => return Err(e),
+            };
+
+            match (cmd[0], n) {
+                (Self::READ, 2) => {
+                    // READ returns 4 pages, wrapping around at the end of the memory.
+                    let start = cmd[1] as usize * 4;
+                    if start >= self.memory.len() {
+                        self.nfct.transmit_ack_nak(Self::NAK_INVALID_ARGUMENT).await?;
+                        continue;
+                    }
+                    for (i, b) in resp.iter_mut().enumerate() {
+                        *b = self.memory[(start + i) % self.memory.len()];
+                    }
+                    self.nfct.transmit(&resp).await?;
+                }
+                (Self::WRITE, 6) => {
+                    let start = cmd[1] as usize * 4;
+                    if start < 8 || start + 4 > self.memory.len() {
+                        self.nfct.transmit_ack_nak(Self::NAK_INVALID_ARGUMENT).await?;
+                        continue;
+                    }
+                    self.memory[start..start + 4].copy_from_slice(&cmd[2..6]);
+                    self.nfct.transmit_ack_nak(Self::ACK).await?;
+                }
+                (Self::HALT, 2) => {
+                    self.nfct.go_sleep();
+                    return Ok(());
+                }
+                _ => {
+                    // Unknown commands make the tag go back to idle without responding.
+                    self.nfct.go_idle();
+                    self.nfct.wait_for_selected().await;
+                }
+            }
+        }
+    }
+}
+
+/// A minimal NFC Forum Type 4 tag, serving an NDEF file to readers.
+///
+/// The reader activates the tag with ISO-DEP (ISO/IEC 14443-4), then talks to the NDEF tag
+/// application with APDUs: it selects the application, reads the capability container, and reads
+/// or updates the NDEF file. `file` holds the NDEF file, made of the 2-byte length of the NDEF
+/// message followed by the message, which [`ndef::write_type4_file`] fills in.
+///
+/// The [`NfcT`] must be created with [`Protocol::Type4`].
+pub struct Type4Tag<'a, 'd> {
+    nfct: &'a mut NfcT<'d>,
+    file: &'a mut [u8],
+    writable: bool,
+}
+
+impl<'a, 'd> Type4Tag<'a, 'd> {
+    /// Serve `file`, which must be 2 to 0x7FFF bytes long. If `writable`, readers can update it.
+    pub fn new(nfct: &'a mut NfcT<'d>, file: &'a mut [u8], writable: bool) -> Self {
+        assert!(file.len() >= 2 && file.len() <= 0x7FFF);
+        Self { nfct, file, writable }
+    }
+
+    /// Handle reader commands until the reader deselects or halts the tag, or leaves the field.
+    ///
+    /// Returns `Ok` when the tag was deselected or halted, and `Err(Error::FieldLost)` when the
+    /// reader left.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        let mut rx = [0u8; type4::BLOCK_LEN];
+        let mut tag = type4::IsoDep::new();
+
+        self.nfct.wait_for_selected().await;
+
+        loop {
+            let n = match self.nfct.receive(&mut rx).await {
+                Ok(n) => n,
+                // Ignore corrupted frames, the reader will retry.
+                Err(Error::Crc | Error::Parity | Error::Overrun) => continue,
+                Err(e) => return Err(e),
+            };
+
+            match tag.handle(&rx[..n], self.file, self.writable) {
+                type4::Action::None => {}
+                type4::Action::Reply => self.nfct.transmit(tag.reply()).await?,
+                type4::Action::ReplyAndSleep => {
+                    self.nfct.transmit(tag.reply()).await?;
+                    self.nfct.go_sleep();
+                    return Ok(());
+                }
+                type4::Action::Sleep => {
+                    self.nfct.go_sleep();
+                    return Ok(());
+                }
+                type4::Action::Idle => {
+                    // Unknown commands make the tag go back to idle without responding.
+                    self.nfct.go_idle();
+                    self.nfct.wait_for_selected().await;
+                    tag = type4::IsoDep::new();
+                }
+            }
+        }
+    }
+}
+
+/// ISO-DEP and the NDEF tag application of [`Type4Tag`], independent of the peripheral.
+mod type4 {
+    /// Frame size accepted by the tag, including the CRC, advertised as FSCI 5.
+    pub const BLOCK_LEN: usize = 64;
+    /// Largest response data of READ BINARY, and command data of UPDATE BINARY.
+    const MAX_DATA_LEN: usize = 128;
+
+    /// ATS: TA, TB and TC present, FSCI 5, 106 kbit/s only, FWI 8 (77 ms), no CID or NAD.
+    const ATS: [u8; 5] = [0x05, 0x75, 0x00, 0x80, 0x00];
+    /// Frame sizes of the reader, indexed by FSDI.
+    const FSD: [usize; 9] = [16, 24, 32, 40, 48, 64, 96, 128, 256];
+
+    const RATS: u8 = 0xE0;
+    const HLTA: [u8; 2] = [0x50, 0x00];
+    const PCB_CHAINING: u8 = 0x10;
+    const PCB_NAK: u8 = 0x10;
+    const PCB_CID: u8 = 0x08;
+    const PCB_NAD: u8 = 0x04;
+    const S_DESELECT: u8 = 0xC2;
+
+    const NDEF_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+    const CC_FILE_ID: [u8; 2] = [0xE1, 0x03];
+    const NDEF_FILE_ID: [u8; 2] = [0xE1, 0x04];
+    const CC_LEN: usize = 15;
+
+    const INS_SELECT: u8 = 0xA4;
+    const INS_READ_BINARY: u8 = 0xB0;
+    const INS_UPDATE_BINARY: u8 = 0xD6;
+
+    const SW_OK: u16 = 0x9000;
+    const SW_WRONG_LENGTH: u16 = 0x6700;
+    const SW_SECURITY_STATUS: u16 = 0x6982;
+    const SW_NO_CURRENT_EF: u16 = 0x6986;
+    const SW_NOT_FOUND: u16 = 0x6A82;
+    const SW_INCORRECT_P1P2: u16 = 0x6A86;
+    const SW_WRONG_OFFSET: u16 = 0x6B00;
+    const SW_INS_NOT_SUPPORTED: u16 = 0x6D00;
+    const SW_CLA_NOT_SUPPORTED: u16 = 0x6E00;
+
+    /// What to do after a frame from the reader was handled.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Action {
+        /// Send nothing, the frame was invalid.
+        None,
+        /// Send [`IsoDep::reply`].
+        Reply,
+        /// Send [`IsoDep::reply`], then go to sleep.
+        ReplyAndSleep,
+        /// Go to sleep without replying.
+        Sleep,
+        /// Go back to idle without replying.
+        Idle,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum File {
+        Cc,
+        Ndef,
+    }
+
+    pub struct IsoDep {
+        /// Whether RATS was received, and the tag talks ISO-DEP.
+        active: bool,
+        fsd: usize,
+        block_num: u8,
+        /// Command APDU, received in one or more chained I-blocks.
+        command: [u8; 5 + MAX_DATA_LEN + 1],
+        command_len: usize,
+        command_overflow: bool,
+        /// Response APDU, sent in one or more chained I-blocks.
+        response: [u8; MAX_DATA_LEN + 2],
+        response_len: usize,
+        response_sent: usize,
+        /// Last block sent, kept to be sent again if the reader didn't receive it.
+        reply: [u8; BLOCK_LEN],
+        reply_len: usize,
+        app_selected: bool,
+        file: Option<File>,
+    }
+
+    impl IsoDep {
+        pub fn new() -> Self {
+            Self {
+                active: false,
+                fsd: FSD[0],
+                // The block number of the tag starts at 1, to be toggled by the first I-block.
+                block_num: 1,
+                command: [0; 5 + MAX_DATA_LEN + 1],
+                command_len: 0,
+                command_overflow: false,
+                response: [0; MAX_DATA_LEN + 2],
+                response_len: 0,
+                response_sent: 0,
+                reply: [0; BLOCK_LEN],
+                reply_len: 0,
+                app_selected: false,
+                file: None,
+            }
+        }
+
+        /// The block to send for [`Action::Reply`].
+        pub fn reply(&self) -> &[u8] {
+            &self.reply[..self.reply_len]
+        }
+
+        fn set_reply(&mut self, data: &[u8]) -> Action {
+            self.reply[..data.len()].copy_from_slice(data);
+            self.reply_len = data.len();
+            Action::Reply
+        }
+
+        /// Handle a frame of the reader, with `file` as the NDEF file.
+        pub fn handle(&mut self, frame: &[u8], file: &mut [u8], writable: bool) -> Action {
+            if frame.is_empty() {
+                return Action::None;
+            }
+
+            if !self.active {
+                return match frame {
+                    [RATS, param] => {
+                        self.active = true;
+                        self.fsd = FSD[(param >> 4).min(8) as usize];
+                        self.set_reply(&ATS)
+                    }
+                    _ if frame == HLTA => Action::Sleep,
+                    _ => Action::Idle,
+                };
+            }
+
+            let pcb = frame[0];
+            // CID and NAD aren't supported, as told by the ATS.
+            if pcb & (PCB_CID | PCB_NAD) != 0 {
+                return Action::None;
+            }
+            match pcb & 0xE6 {
+                // I-block
+                0x02 => {
+                    // Whatever its block number, an I-block toggles the block number of the tag.
+                    self.block_num ^= 1;
+                    if self.response_sent < self.response_len {
+                        // A new command aborts the response being sent.
+                        self.response_len = 0;
+                    }
+                    let data = &frame[1..];
+                    match self.command.get_mut(self.command_len..self.command_len + data.len()) {
+                        Some(dst) => {
+                            dst.copy_from_slice(data);
+                            self.command_len += data.len();
+                        }
+                        None => self.command_overflow = true,
+                    }
+
+                    if pcb & PCB_CHAINING != 0 {
+                        return self.set_reply(&[0xA2 | self.block_num]);
+                    }
+
+                    let sw = if self.command_overflow {
+                        self.response_len = 0;
+                        SW_WRONG_LENGTH
+                    } else {
+                        self.process(file, writable)
+                    };
+                    self.response[self.response_len..self.response_len + 2].copy_from_slice(&sw.to_be_bytes());
+                    self.response_len += 2;
+                    self.response_sent = 0;
+                    self.command_len = 0;
+                    self.command_overflow = false;
+                    self.send_next_block()
+                }
+                // R-block
+                0xA2 => {
+                    let nak = pcb & PCB_NAK != 0;
+                    if pcb & 1 == self.block_num {
+                        // The reader didn't receive the last block.
+                        Action::Reply
+                    } else if nak {
+                        self.set_reply(&[0xA2 | self.block_num])
+                    } else if self.response_sent < self.response_len {
+                        self.block_num ^= 1;
+                        self.send_next_block()
+                    } else {
+                        Action::None
+                    }
+                }
+                // S-block
+                0xC2 if pcb == S_DESELECT => {
+                    self.set_reply(&[S_DESELECT]);
+                    Action::ReplyAndSleep
+                }
+                _ => Action::None,
+            }
+        }
+
+        /// Send the next part of the response, chaining if there are more.
+        fn send_next_block(&mut self) -> Action {
+            // The reader's frame size includes the PCB and the CRC.
+            let max = (self.fsd - 3).min(BLOCK_LEN - 1);
+            let rest = &self.response[self.response_sent..self.response_len];
+            let n = rest.len().min(max);
+            let more = n < rest.len();
+
+            self.reply[0] = 0x02 | self.block_num | if more { PCB_CHAINING } else { 0 };
+            self.reply[1..1 + n].copy_from_slice(&rest[..n]);
+            self.reply_len = 1 + n;
+            self.response_sent += n;
+            Action::Reply
+        }
+
+        /// Process the command APDU, writing the response data, and returning the status word.
+        fn process(&mut self, file: &mut [u8], writable: bool) -> u16 {
+            self.response_len = 0;
+
+            let command = &self.command[..self.command_len];
+            let (header, body) = match command.len() {
+                0..=3 => return SW_WRONG_LENGTH,
+                _ => command.split_at(4),
+            };
+            let (cla, ins, p1, p2) = (header[0], header[1], header[2], header[3]);
+            // Short APDUs only: no body, Le, Lc and data, or Lc, data and Le.
+            let (data, le) = match body {
+                [] => (&[][..], None),
+                [le] => (&[][..], Some(*le)),
+                [lc, rest @ ..] if rest.len() == *lc as usize => (rest, None),
+                [lc, rest @ ..] if rest.len() == *lc as usize + 1 => (&rest[..*lc as usize], rest.last().copied()),
+                _ => return SW_WRONG_LENGTH,
+            };
+            // Le is 256 when zero.
+            let le = le.map_or(256, |le| if le == 0 { 256 } else { le as usize });
+
+            if cla != 0x00 {
+                return SW_CLA_NOT_SUPPORTED;
+            }
+
+            match ins {
+                INS_SELECT => match (p1, p2) {
+                    // By name.
+                    (0x04, 0x00) => {
+                        if data != NDEF_AID {
+                            return SW_NOT_FOUND;
+                        }
+                        self.app_selected = true;
+                        self.file = None;
+                        SW_OK
+                    }
+                    // By file identifier, without response data.
+                    (0x00, 0x0C) => {
+                        self.file = match data {
+                            _ if !self.app_selected => return SW_NOT_FOUND,
+                            _ if data == CC_FILE_ID => Some(File::Cc),
+                            _ if data == NDEF_FILE_ID => Some(File::Ndef),
+                            _ => return SW_NOT_FOUND,
+                        };
+                        SW_OK
+                    }
+                    _ => SW_INCORRECT_P1P2,
+                },
+                INS_READ_BINARY => {
+                    let cc = cc_file(file.len(), writable);
+                    let content: &[u8] = match self.file {
+                        Some(File::Cc) => &cc,
+                        Some(File::Ndef) => file,
+                        None => return SW_NO_CURRENT_EF,
+                    };
+                    let offset = u16::from_be_bytes([p1, p2]) as usize;
+                    if p1 & 0x80 != 0 || offset > content.len() {
+                        return SW_WRONG_OFFSET;
+                    }
+                    let n = le.min(MAX_DATA_LEN).min(content.len() - offset);
+                    self.response[..n].copy_from_slice(&content[offset..offset + n]);
+                    self.response_len = n;
+                    SW_OK
+                }
+                INS_UPDATE_BINARY => {
+                    match self.file {
+                        Some(File::Ndef) if writable => {}
+                        Some(_) => return SW_SECURITY_STATUS,
+                        None => return SW_NO_CURRENT_EF,
+                    }
+                    let offset = u16::from_be_bytes([p1, p2]) as usize;
+                    if p1 & 0x80 != 0 || offset + data.len() > file.len() {
+                        return SW_WRONG_OFFSET;
+                    }
+                    file[offset..offset + data.len()].copy_from_slice(data);
+                    SW_OK
+                }
+                _ => SW_INS_NOT_SUPPORTED,
+            }
+        }
+    }
+
+    /// Capability container of an NDEF file of `len` bytes.
+    fn cc_file(len: usize, writable: bool) -> [u8; CC_LEN] {
+        let [mle_hi, mle_lo] = (MAX_DATA_LEN as u16).to_be_bytes();
+        let [len_hi, len_lo] = (len as u16).to_be_bytes();
+        [
+            0x00,
+            CC_LEN as u8,
+            // Mapping version 2.0.
+            0x20,
+            mle_hi,
+            mle_lo,
+            // MLc is the same as MLe.
+            mle_hi,
+            mle_lo,
+            // NDEF file control TLV: file identifier, maximum size, read and write access.
+            0x04,
+            0x06,
+            NDEF_FILE_ID[0],
+            NDEF_FILE_ID[1],
+            len_hi,
+            len_lo,
+            0x00,
+            if writable { 0x00 } else { 0xFF },
+        ]
+    }
+}
+
+/// Helpers for building NDEF messages.
+pub mod ndef {
+    /// The buffer is too small for the message.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct BufferTooSmall;
+
+    const URI_PREFIXES: [(&str, u8); 4] = [
+        ("http://www.", 0x01),
+        ("https://www.", 0x02),
+        ("http://", 0x03),
+        ("https://", 0x04),
+    ];
+
+    /// Write an NDEF message with a single URI record into `buf`, returning its length.
+    ///
+    /// Common prefixes such as `https://` are abbreviated as defined by the URI record type.
+    pub fn write_uri_record(buf: &mut [u8], uri: &str) -> Result<usize, BufferTooSmall> {
+        let (code, rest) = URI_PREFIXES
+            .iter()
+            .filter(|(prefix, _)| uri.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, code)| (*code, &uri[prefix.len()..]))
+            .unwrap_or((0x00, uri));
+
+        write_record(buf, b'U', &[code], rest.as_bytes())
+    }
+
+    /// Write an NDEF message with a single UTF-8 text record into `buf`, returning its length.
+    pub fn write_text_record(buf: &mut [u8], language: &str, text: &str) -> Result<usize, BufferTooSmall> {
+        if language.len() > 0x3F {
+            return Err(BufferTooSmall);
+        }
+        let mut header = [0u8; 0x40];
+        header[0] = language.len() as u8;
+        header[1..1 + language.len()].copy_from_slice(language.as_bytes());
+
+        write_record(buf, b'T', &header[..1 + language.len()], text.as_bytes())
+    }
+
+    /// Write a well-known short record, which is both the first and the last of its message.
+    fn write_record(buf: &mut [u8], record_type: u8, header: &[u8], payload: &[u8]) -> Result<usize, BufferTooSmall> {
+        let payload_len = header.len() + payload.len();
+        if payload_len > 0xFF {
+            return Err(BufferTooSmall);
+        }
+        let len = 4 + payload_len;
+        if buf.len() < len {
+            return Err(BufferTooSmall);
+        }
+
+        // MB | ME | SR | TNF = well-known
+        buf[0] = 0xD1;
+        buf[1] = 1;
+        buf[2] = payload_len as u8;
+        buf[3] = record_type;
+        buf[4..4 + header.len()].copy_from_slice(header);
+        buf[4 + header.len()..len].copy_from_slice(payload);
+
+        Ok(len)
+    }
+
+    /// Fill the memory of a Type 2 tag with a capability container and the NDEF `message`.
+    ///
+    /// `memory` must start at page 0. The UID and lock bytes in pages 0 to 2 are left untouched.
+    pub fn write_type2_tlv(memory: &mut [u8], message: &[u8]) -> Result<(), BufferTooSmall> {
+        // Data area starts at page 4, the TLV needs up to 4 bytes of overhead.
+        if memory.len() < 16 || message.len() > 0xFE || memory.len() < 16 + 3 + message.len() {
+            return Err(BufferTooSmall);
+        }
+        let data_area = memory.len() - 16;
+
+        // Capability container: magic, version 1.0, data area size in 8-byte units, read/write.
+        memory[12..16].copy_from_slice(&[0xE1, 0x10, (data_area / 8) as u8, 0x00]);
+
+        // NDEF message TLV, then terminator TLV.
+        let data = &mut memory[16..];
+        data[0] = 0x03;
+        data[1] = message.len() as u8;
+        data[2..2 + message.len()].copy_from_slice(message);
+        data[2 + message.len()] = 0xFE;
+
+        Ok(())
+    }
+
+    /// Fill the NDEF file of a Type 4 tag with the NDEF `message`, preceded by its length.
+    pub fn write_type4_file(file: &mut [u8], message: &[u8]) -> Result<(), BufferTooSmall> {
+        if message.len() > 0x7FFD || file.len() < 2 + message.len() {
+            return Err(BufferTooSmall);
+        }
+        file[..2].copy_from_slice(&(message.len() as u16).to_be_bytes());
+        file[2..2 + message.len()].copy_from_slice(message);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
 //! Non-Volatile Memory Controller (NVMC) module.
+//!
+//! Besides the `embedded-storage` traits, the driver can erase flash in small steps so the CPU
+//! isn't stalled for a whole page erase, and on nRF52 series chips it can program the User
+//! Information Configuration Registers (UICR) and protect flash regions until the next reset.
 
 use core::{ptr, slice};
 
@@ -24,6 +28,12 @@ pub enum Error {
     OutOfBounds,
     /// Unaligned operation or using unaligned buffers.
     Unaligned,
+    /// The UICR register already holds a value that can only be changed by erasing the UICR first.
+    UicrNeedsErase,
+    /// The operation touches a flash region protected with [`Nvmc::protect`].
+    Protected,
+    /// All the hardware protection regions are in use.
+    NoProtectionRegion,
 }
 
 impl NorFlashError for Error {
@@ -31,6 +41,9 @@ impl NorFlashError for Error {
         match self {
             Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
             Self::Unaligned => NorFlashErrorKind::NotAligned,
+            Self::UicrNeedsErase => NorFlashErrorKind::Other,
+            Self::Protected => NorFlashErrorKind::Other,
+            Self::NoProtectionRegion => NorFlashErrorKind::Other,
         }
     }
 }
@@ -55,6 +68,363 @@ impl<'d> Nvmc<'d> {
         let p = Self::regs();
         while p.ready.read().ready().is_busy() {}
     }
+
+    /// Erase flash pages, yielding to other tasks while doing so.
+    ///
+    /// A regular page erase halts the CPU for the whole erase time (around 85 ms). This uses partial
+    /// erase instead, which erases in steps of [`PARTIAL_ERASE_STEP_MS`] milliseconds and lets the
+    /// executor run other tasks between them. The CPU is still halted during each step.
+    #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+    pub async fn erase_partial(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if to < from || to as usize > FLASH_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+        if from as usize % PAGE_SIZE != 0 || to as usize % PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+
+        check_writable(from, to)?;
+
+        let p = Self::regs();
+
+        p.erasepagepartialcfg
+            .write(|w| unsafe { w.bits(PARTIAL_ERASE_STEP_MS) });
+
+        for page in (from..to).step_by(PAGE_SIZE) {
+            let mut elapsed = 0;
+            while elapsed < PAGE_ERASE_TIME_MS {
+                p.config.write(|w| w.wen().een());
+                self.wait_ready();
+
+                p.erasepagepartial.write(|w| unsafe { w.bits(page) });
+                self.wait_ready();
+
+                p.config.reset();
+                self.wait_ready();
+
+                elapsed += PARTIAL_ERASE_STEP_MS;
+                embassy_futures::yield_now().await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Protect the flash pages in `from..to` until the next reset.
+    ///
+    /// Protection can't be removed other than by a reset, so calling this early at boot locks a
+    /// region, such as the bootloader, against accidental writes from the rest of the firmware.
+    /// Writes and erases of a protected region through this driver return [`Error::Protected`].
+    /// Other accesses are blocked by the hardware, and fault.
+    #[cfg_attr(
+        any(feature = "nrf52810", feature = "nrf52832"),
+        doc = "",
+        doc = "On this chip, protection is disabled while a debugger is attached."
+    )]
+    #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+    pub fn protect(&mut self, from: u32, to: u32, protection: Protection) -> Result<(), Error> {
+        if to < from || to as usize > FLASH_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+        if from as usize % PAGE_SIZE != 0 || to as usize % PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        if from == to {
+            return Ok(());
+        }
+
+        protect(from, to, protection)
+    }
+
+    /// Write a word in the UICR.
+    ///
+    /// `offset` is the byte offset of the register from the start of the UICR, for example
+    /// `0x080` for `CUSTOMER[0]`. UICR writes only take effect after a reset.
+    ///
+    /// Flash bits can only be changed from 1 to 0, so if the register already holds a value that
+    /// can't be turned into `value` this returns [`Error::UicrNeedsErase`] without writing
+    /// anything. Use [`erase_uicr`](Self::erase_uicr) in that case.
+    #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+    pub fn write_uicr(&mut self, offset: u32, value: u32) -> Result<UicrWrite, Error> {
+        uicr_write(offset, value)
+    }
+
+    /// Erase the whole UICR.
+    ///
+    /// This erases all of the UICR, including the bootloader address and the reset pin
+    /// configuration, so make sure to write back whatever the device needs before resetting.
+    #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+    pub fn erase_uicr(&mut self) {
+        let p = Self::regs();
+
+        p.config.write(|w| w.wen().een());
+        self.wait_ready();
+
+        p.eraseuicr.write(|w| unsafe { w.bits(1) });
+        self.wait_ready();
+
+        p.config.reset();
+        self.wait_ready();
+    }
+
+    /// Set the voltage of the REGOUT0 regulator, which supplies VDD when the chip is powered from VDDH.
+    #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+    pub fn set_regout0(&mut self, voltage: Regout0Voltage) -> Result<UicrWrite, Error> {
+        self.write_uicr(UICR_REGOUT0, 0xFFFF_FFF8 | voltage as u32)
+    }
+
+    /// Configure the NFC antenna pins to be used as regular GPIOs.
+    #[cfg(any(feature = "nrf52832", feature = "nrf52833", feature = "nrf52840"))]
+    pub fn set_nfc_pins_as_gpio(&mut self) -> Result<UicrWrite, Error> {
+        self.write_uicr(UICR_NFCPINS, 0xFFFF_FFFE)
+    }
+
+    /// Enable access port protection, which blocks debugger access to the CPU and memory.
+    ///
+    /// Protection can only be removed again by an ERASEALL through the debug interface, which erases
+    /// the whole flash.
+    #[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+    pub fn enable_approtect(&mut self) -> Result<UicrWrite, Error> {
+        self.write_uicr(UICR_APPROTECT, 0xFFFF_FF00)
+    }
+}
+
+/// Write a word in the UICR, see [`Nvmc::write_uicr`].
+///
+/// This is also used at init time, before the NVMC peripheral is handed out.
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+pub(crate) fn uicr_write(offset: u32, value: u32) -> Result<UicrWrite, Error> {
+    if offset >= UICR_SIZE {
+        return Err(Error::OutOfBounds);
+    }
+    if offset % 4 != 0 {
+        return Err(Error::Unaligned);
+    }
+
+    let reg = (pac::UICR::ptr() as u32 + offset) as *mut u32;
+    let current = unsafe { ptr::read_volatile(reg) };
+    if current == value {
+        return Ok(UicrWrite::Unchanged);
+    }
+    if current & value != value {
+        return Err(Error::UicrNeedsErase);
+    }
+
+    let p = unsafe { &*pac::NVMC::ptr() };
+    let wait_ready = || while p.ready.read().ready().is_busy() {};
+
+    p.config.write(|w| w.wen().wen());
+    wait_ready();
+
+    unsafe { ptr::write_volatile(reg, value) };
+    wait_ready();
+
+    p.config.reset();
+    wait_ready();
+
+    Ok(UicrWrite::Written)
+}
+
+/// Duration of each step of [`Nvmc::erase_partial`], in milliseconds.
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+pub const PARTIAL_ERASE_STEP_MS: u32 = 10;
+
+/// Cumulative partial erase time needed to erase a page.
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+const PAGE_ERASE_TIME_MS: u32 = 85;
+
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+const UICR_SIZE: u32 = 0x400;
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+const UICR_APPROTECT: u32 = 0x208;
+#[cfg(any(feature = "nrf52832", feature = "nrf52833", feature = "nrf52840"))]
+const UICR_NFCPINS: u32 = 0x20C;
+#[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+pub(crate) const UICR_REGOUT0: u32 = 0x304;
+
+/// Outcome of a successful UICR write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UicrWrite {
+    /// The register already held the requested value.
+    Unchanged,
+    /// The register was written. The change takes effect after a reset.
+    Written,
+}
+
+/// REGOUT0 output voltage.
+#[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Regout0Voltage {
+    /// 1.8 V
+    V1_8 = 0,
+    /// 2.1 V
+    V2_1 = 1,
+    /// 2.4 V
+    V2_4 = 2,
+    /// 2.7 V
+    V2_7 = 3,
+    /// 3.0 V
+    V3_0 = 4,
+    /// 3.3 V
+    V3_3 = 5,
+}
+
+/// Access allowed to a flash region protected with [`Nvmc::protect`].
+#[cfg(not(any(feature = "_nrf5340", feature = "_nrf9160")))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protection {
+    /// The region can be read and executed, but not written or erased.
+    ReadOnly,
+    /// The region can't be accessed at all, not even to execute code from it.
+    #[cfg(any(
+        feature = "nrf52805",
+        feature = "nrf52811",
+        feature = "nrf52820",
+        feature = "nrf52833",
+        feature = "nrf52840"
+    ))]
+    NoAccess,
+}
+
+// Chips with BPROT protect 4 kB blocks against writes, the others have ACL regions.
+#[cfg(any(feature = "nrf52810", feature = "nrf52832"))]
+const BPROT_CONFIGS: usize = (FLASH_SIZE / PAGE_SIZE + 31) / 32;
+
+#[cfg(any(feature = "nrf52810", feature = "nrf52832"))]
+fn bprot_regs() -> &'static pac::bprot::RegisterBlock {
+    unsafe { &*pac::BPROT::ptr() }
+}
+
+#[cfg(any(feature = "nrf52810", feature = "nrf52832"))]
+fn bprot_read() -> [u32; BPROT_CONFIGS] {
+    let r = bprot_regs();
+    let mut configs = [0; BPROT_CONFIGS];
+    configs[0] = r.config0.read().bits();
+    configs[1] = r.config1.read().bits();
+    #[cfg(feature = "nrf52832")]
+    {
+        configs[2] = r.config2.read().bits();
+        configs[3] = r.config3.read().bits();
+    }
+    configs
+}
+
+#[cfg(any(feature = "nrf52810", feature = "nrf52832"))]
+fn protect(from: u32, to: u32, protection: Protection) -> Result<(), Error> {
+    let Protection::ReadOnly = protection;
+
+    let mut configs = [0; BPROT_CONFIGS];
+    for block in (from as usize / PAGE_SIZE)..(to as usize / PAGE_SIZE) {
+        configs[block / 32] |= 1 << (block % 32);
+    }
+
+    // Writing 1 enables protection of a block, writing 0 has no effect.
+    let r = bprot_regs();
+    r.config0.write(|w| unsafe { w.bits(configs[0]) });
+    r.config1.write(|w| unsafe { w.bits(configs[1]) });
+    #[cfg(feature = "nrf52832")]
+    {
+        r.config2.write(|w| unsafe { w.bits(configs[2]) });
+        r.config3.write(|w| unsafe { w.bits(configs[3]) });
+    }
+    Ok(())
+}
+
+/// Returns [`Error::Protected`] if any page in `from..to` is protected against writes.
+#[cfg(any(feature = "nrf52810", feature = "nrf52832"))]
+fn check_writable(from: u32, to: u32) -> Result<(), Error> {
+    let configs = bprot_read();
+    let first = from as usize / PAGE_SIZE;
+    let last = (to as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+    for block in first..last {
+        if configs[block / 32] & (1 << (block % 32)) != 0 {
+            return Err(Error::Protected);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(
+    feature = "nrf52805",
+    feature = "nrf52811",
+    feature = "nrf52820",
+    feature = "nrf52833",
+    feature = "nrf52840"
+))]
+const ACL_PERM_WRITE_DISABLE: u32 = 1 << 1;
+#[cfg(any(
+    feature = "nrf52805",
+    feature = "nrf52811",
+    feature = "nrf52820",
+    feature = "nrf52833",
+    feature = "nrf52840"
+))]
+const ACL_PERM_READ_DISABLE: u32 = 1 << 2;
+
+#[cfg(any(
+    feature = "nrf52805",
+    feature = "nrf52811",
+    feature = "nrf52820",
+    feature = "nrf52833",
+    feature = "nrf52840"
+))]
+fn acl_regs() -> &'static pac::acl::RegisterBlock {
+    unsafe { &*pac::ACL::ptr() }
+}
+
+#[cfg(any(
+    feature = "nrf52805",
+    feature = "nrf52811",
+    feature = "nrf52820",
+    feature = "nrf52833",
+    feature = "nrf52840"
+))]
+fn protect(from: u32, to: u32, protection: Protection) -> Result<(), Error> {
+    // The registers of a region can only be written once, a region with a size of 0 is unused.
+    let region = acl_regs()
+        .acl
+        .iter()
+        .find(|region| region.size.read().bits() == 0)
+        .ok_or(Error::NoProtectionRegion)?;
+
+    let perm = match protection {
+        Protection::ReadOnly => ACL_PERM_WRITE_DISABLE,
+        Protection::NoAccess => ACL_PERM_WRITE_DISABLE | ACL_PERM_READ_DISABLE,
+    };
+
+    region.addr.write(|w| unsafe { w.bits(from) });
+    region.perm.write(|w| unsafe { w.bits(perm) });
+    region.size.write(|w| unsafe { w.bits(to - from) });
+    Ok(())
+}
+
+/// Returns [`Error::Protected`] if any page in `from..to` is protected against writes.
+#[cfg(any(
+    feature = "nrf52805",
+    feature = "nrf52811",
+    feature = "nrf52820",
+    feature = "nrf52833",
+    feature = "nrf52840"
+))]
+fn check_writable(from: u32, to: u32) -> Result<(), Error> {
+    for region in acl_regs().acl.iter() {
+        let start = region.addr.read().bits();
+        let end = start + region.size.read().bits();
+        let write_disabled = region.perm.read().bits() & ACL_PERM_WRITE_DISABLE != 0;
+        if write_disabled && from < end && start < to {
+            return Err(Error::Protected);
+        }
+    }
+    Ok(())
+}
+
+// Flash protection is done by the SPU on these chips, which isn't supported by this driver.
+#[cfg(any(feature = "_nrf5340", feature = "_nrf9160"))]
+fn check_writable(_from: u32, _to: u32) -> Result<(), Error> {
+    Ok(())
 }
 
 impl<'d> MultiwriteNorFlash for Nvmc<'d> {}
@@ -92,6 +462,7 @@ impl<'d> NorFlash for Nvmc<'d> {
         if from as usize % PAGE_SIZE != 0 || to as usize % PAGE_SIZE != 0 {
             return Err(Error::Unaligned);
         }
+        check_writable(from, to)?;
 
         let p = Self::regs();
 
@@ -116,6 +487,7 @@ impl<'d> NorFlash for Nvmc<'d> {
         if offset as usize % 4 != 0 || bytes.len() as usize % 4 != 0 {
             return Err(Error::Unaligned);
         }
+        check_writable(offset, offset + bytes.len() as u32)?;
 
         let p = Self::regs();
 
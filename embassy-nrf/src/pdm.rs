@@ -0,0 +1,402 @@
+//! PDM microphone interface.
+//!
+//! The PDM peripheral decimates the 1-bit stream of one or two MEMS microphones into 16-bit PCM
+//! samples, which are written to RAM with EasyDMA. In stereo mode samples are interleaved, left
+//! channel first.
+
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+use fixed::types::I7F1;
+pub use pac::pdm::pdmclkctrl::FREQ_A as Frequency;
+#[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+pub use pac::pdm::ratio::RATIO_A as Ratio;
+
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::{self, AnyPin, Pin as GpioPin};
+use crate::interrupt::InterruptExt;
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    BufferTooLong,
+    BufferZeroLength,
+}
+
+/// The maximum buffer length in samples, limited by the SAMPLE.MAXCNT register.
+pub const MAX_BUFFER_LEN: usize = (1 << 15) - 1;
+
+/// PDM microphone interface.
+pub struct Pdm<'d> {
+    _p: PeripheralRef<'d, peripherals::PDM>,
+    clk: PeripheralRef<'d, AnyPin>,
+    din: PeripheralRef<'d, AnyPin>,
+}
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Used to configure the PDM peripheral.
+///
+/// See the `Default` impl for suitable default values.
+#[non_exhaustive]
+pub struct Config {
+    /// Use one or two microphones.
+    pub operation_mode: OperationMode,
+    /// On which PDM clock edge the left channel is sampled.
+    pub edge: Edge,
+    /// PDM clock frequency.
+    pub frequency: Frequency,
+    /// Ratio between the PDM clock and the PCM sample rate.
+    #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+    pub ratio: Ratio,
+    /// Gain of the left channel in dB, from -20 to +20 in steps of 0.5.
+    pub gain_left: I7F1,
+    /// Gain of the right channel in dB, from -20 to +20 in steps of 0.5.
+    pub gain_right: I7F1,
+}
+
+impl Default for Config {
+    /// Default configuration for a single microphone, giving a 16 kHz sample rate.
+    fn default() -> Self {
+        Self {
+            operation_mode: OperationMode::Mono,
+            edge: Edge::LeftFalling,
+            frequency: Frequency::DEFAULT,
+            #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+            ratio: Ratio::_64,
+            gain_left: I7F1::ZERO,
+            gain_right: I7F1::ZERO,
+        }
+    }
+}
+
+/// Number of microphones sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OperationMode {
+    /// A single microphone, on the left channel.
+    Mono,
+    /// Two microphones sharing the data line, samples are interleaved.
+    Stereo,
+}
+
+/// PDM clock edge on which the left channel is sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Edge {
+    /// Left channel is sampled on the falling edge of the PDM clock.
+    LeftFalling,
+    /// Left channel is sampled on the rising edge of the PDM clock.
+    LeftRising,
+}
+
+/// The state of a continuously running sampler. While it reflects
+/// the progress of a sampler, it also signals what should be done
+/// next. For example, if the sampler has stopped then the Pdm implementation
+/// can then tear down its infrastructure.
+#[derive(PartialEq)]
+pub enum SamplerState {
+    Sampled,
+    Stopped,
+}
+
+impl<'d> Pdm<'d> {
+    #[track_caller]
+    pub fn new(
+        pdm: impl Peripheral<P = peripherals::PDM> + 'd,
+        irq: impl Peripheral<P = interrupt::PDM> + 'd,
+        clk: impl Peripheral<P = impl GpioPin> + 'd,
+        din: impl Peripheral<P = impl GpioPin> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(pdm, irq, clk, din);
+
+        let r = Self::regs();
+
+        // Configure pins
+        clk.set_low();
+        clk.conf().write(|w| w.dir().output());
+        din.conf().write(|w| w.input().connect());
+        gpio::claim_pin(clk.psel_bits());
+        r.psel.clk.write(|w| unsafe { w.bits(clk.psel_bits()) });
+        gpio::claim_pin(din.psel_bits());
+        r.psel.din.write(|w| unsafe { w.bits(din.psel_bits()) });
+
+        // Configure
+        r.mode.write(|w| {
+            match config.operation_mode {
+                OperationMode::Mono => w.operation().mono(),
+                OperationMode::Stereo => w.operation().stereo(),
+            };
+            match config.edge {
+                Edge::LeftFalling => w.edge().left_falling(),
+                Edge::LeftRising => w.edge().left_rising(),
+            };
+            w
+        });
+        r.pdmclkctrl.write(|w| w.freq().variant(config.frequency));
+        #[cfg(any(feature = "nrf52833", feature = "nrf52840"))]
+        r.ratio.write(|w| w.ratio().variant(config.ratio));
+        Self::write_gain(config.gain_left, config.gain_right);
+
+        // Disable all events interrupts
+        r.intenclr.write(|w| unsafe { w.bits(0x003F_FFFF) });
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        r.enable.write(|w| w.enable().enabled());
+
+        Self {
+            _p: pdm,
+            clk: clk.map_into(),
+            din: din.map_into(),
+        }
+    }
+
+    fn on_interrupt(_ctx: *mut ()) {
+        let r = Self::regs();
+
+        if r.events_end.read().bits() != 0 {
+            r.intenclr.write(|w| w.end().clear());
+            WAKER.wake();
+        }
+
+        if r.events_started.read().bits() != 0 {
+            r.intenclr.write(|w| w.started().clear());
+            WAKER.wake();
+        }
+
+        if r.events_stopped.read().bits() != 0 {
+            r.intenclr.write(|w| w.stopped().clear());
+            WAKER.wake();
+        }
+    }
+
+    fn regs() -> &'static pac::pdm::RegisterBlock {
+        unsafe { &*pac::PDM::ptr() }
+    }
+
+    fn write_gain(gain_left: I7F1, gain_right: I7F1) {
+        let r = Self::regs();
+
+        // The register counts in 0.5 dB steps from -20 dB, clamped to the valid range.
+        let gain_to_bits = |gain: I7F1| -> u8 { (gain.to_bits().clamp(-40, 40) + 40) as u8 };
+
+        r.gainl.write(|w| unsafe { w.gainl().bits(gain_to_bits(gain_left)) });
+        r.gainr.write(|w| unsafe { w.gainr().bits(gain_to_bits(gain_right)) });
+    }
+
+    /// Adjust the gain of both channels. Takes effect immediately, also while sampling.
+    pub fn set_gain(&mut self, gain_left: I7F1, gain_right: I7F1) {
+        Self::write_gain(gain_left, gain_right)
+    }
+
+    fn check_buffer(buffer: &[i16]) -> Result<(), Error> {
+        if buffer.is_empty() {
+            return Err(Error::BufferZeroLength);
+        }
+        if buffer.len() > MAX_BUFFER_LEN {
+            return Err(Error::BufferTooLong);
+        }
+        Ok(())
+    }
+
+    /// Fill a buffer with samples, then stop sampling.
+    ///
+    /// Note that the decimation filter needs a few milliseconds to settle after sampling starts,
+    /// so the first samples of the buffer should be discarded.
+    pub async fn sample(&mut self, buffer: &mut [i16]) -> Result<(), Error> {
+        Self::check_buffer(buffer)?;
+
+        // In case the future is dropped, stop the task and wait for it to end.
+        let on_drop = OnDrop::new(Self::stop_sampling_immediately);
+
+        let r = Self::regs();
+
+        // Set up the DMA
+        r.sample
+            .ptr
+            .write(|w| unsafe { w.sampleptr().bits(buffer.as_mut_ptr() as u32) });
+        r.sample
+            .maxcnt
+            .write(|w| unsafe { w.buffsize().bits(buffer.len() as _) });
+
+        // Reset and enable the events
+        r.events_end.reset();
+        r.events_stopped.reset();
+        r.intenset.write(|w| w.end().set());
+
+        // Don't reorder the start event before the previous writes.
+        compiler_fence(Ordering::SeqCst);
+
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        // Wait for 'end' event.
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_end.read().bits() != 0 {
+                r.events_end.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        // The peripheral restarts on the same buffer unless stopped.
+        on_drop.defuse();
+        self.stop().await;
+
+        compiler_fence(Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Continuous sampling with double buffers.
+    ///
+    /// A sampler closure is provided that receives each buffer as soon as it has been
+    /// filled, while the peripheral keeps filling the other one. A command is returned from
+    /// the closure that indicates whether the sampling should continue or stop.
+    ///
+    /// NOTE: The time spent within the callback supplied should not exceed the time
+    /// taken to acquire the samples into a single buffer. You should measure the
+    /// time taken by the callback and set the sample buffer size accordingly.
+    /// Exceeding this time can lead to samples becoming dropped.
+    ///
+    /// The sampling is stopped prior to returning in order to reduce power consumption,
+    /// and to free the buffers from being used by the peripheral. Cancellation will
+    /// also cause the sampling to be stopped.
+    pub async fn run_sampler<S, const N: usize>(
+        &mut self,
+        bufs: &mut [[i16; N]; 2],
+        mut sampler: S,
+    ) -> Result<(), Error>
+    where
+        S: FnMut(&[i16; N]) -> SamplerState,
+    {
+        Self::check_buffer(&bufs[0])?;
+
+        // In case the future is dropped, stop the task and wait for it to end.
+        let on_drop = OnDrop::new(Self::stop_sampling_immediately);
+
+        let r = Self::regs();
+
+        // Set up the initial DMA
+        r.sample
+            .ptr
+            .write(|w| unsafe { w.sampleptr().bits(bufs[0].as_mut_ptr() as u32) });
+        r.sample.maxcnt.write(|w| unsafe { w.buffsize().bits(N as _) });
+
+        // Reset and enable the events
+        r.events_end.reset();
+        r.events_started.reset();
+        r.events_stopped.reset();
+        r.intenset.write(|w| {
+            w.end().set();
+            w.started().set();
+            w
+        });
+
+        // Don't reorder the start event before the previous writes.
+        compiler_fence(Ordering::SeqCst);
+
+        r.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        let mut current_buffer = 0;
+
+        // Wait for events and complete when the sampler indicates it has had enough.
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_end.read().bits() != 0 {
+                compiler_fence(Ordering::SeqCst);
+
+                r.events_end.reset();
+                r.intenset.write(|w| w.end().set());
+
+                if sampler(&bufs[current_buffer]) == SamplerState::Sampled {
+                    current_buffer = 1 - current_buffer;
+                } else {
+                    return Poll::Ready(());
+                };
+            }
+
+            if r.events_started.read().bits() != 0 {
+                r.events_started.reset();
+                r.intenset.write(|w| w.started().set());
+
+                // The pointer is latched on STARTED, queue the buffer to be used next.
+                let next_buffer = 1 - current_buffer;
+                r.sample
+                    .ptr
+                    .write(|w| unsafe { w.sampleptr().bits(bufs[next_buffer].as_mut_ptr() as u32) });
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        on_drop.defuse();
+        self.stop().await;
+
+        compiler_fence(Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Stop sampling and wait for the peripheral to release the buffer.
+    async fn stop(&mut self) {
+        let r = Self::regs();
+
+        r.events_stopped.reset();
+        r.intenset.write(|w| w.stopped().set());
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+
+            if r.events_stopped.read().bits() != 0 {
+                r.events_stopped.reset();
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await;
+    }
+
+    // Stop sampling and wait for it to stop in a blocking fashion
+    fn stop_sampling_immediately() {
+        let r = Self::regs();
+
+        compiler_fence(Ordering::SeqCst);
+
+        r.events_stopped.reset();
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+
+        while r.events_stopped.read().bits() == 0 {}
+        r.events_stopped.reset();
+    }
+}
+
+impl<'d> Drop for Pdm<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+
+        r.enable.write(|w| w.enable().disabled());
+
+        self.clk.conf().reset();
+        self.din.conf().reset();
+        gpio::release_pin(self.clk.pin_port() as u32);
+        gpio::release_pin(self.din.pin_port() as u32);
+    }
+}
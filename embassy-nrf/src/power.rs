@@ -0,0 +1,152 @@
+//! Power management: System OFF, RAM retention and reset reason.
+//!
+//! System OFF is the deepest power saving mode, where the chip draws well under 1 µA
+//! (more with RAM retention enabled). Everything is powered down, and the chip can only be
+//! woken up by a reset or by one of these wake sources:
+//!
+//! - A pin configured with [`wake_on_pin`] reaching the configured level.
+//! - A field detected by the NFCT peripheral, if an `NfcT` driver is sensing for a field
+//!   when System OFF is entered.
+//! - A crossing detected by the LPCOMP, if an [`Lpcomp`](crate::lpcomp::Lpcomp) driver is alive
+//!   when System OFF is entered. Its [`Detect`](crate::lpcomp::Detect) setting selects the crossing.
+//! - VBUS being detected, on chips with USB.
+//!
+//! Waking up from System OFF resets the chip. [`reset_reason`] tells whether the chip is booting
+//! from System OFF, and from which wake source.
+
+use embassy_hal_common::into_ref;
+
+use crate::gpio::{convert_pull, Level, Pin as GpioPin, Pull};
+use crate::{pac, Peripheral};
+
+fn regs() -> &'static pac::power::RegisterBlock {
+    unsafe { &*pac::POWER::ptr() }
+}
+
+/// Configure `pin` as an input that wakes the chip from System OFF when it is at `level`.
+///
+/// The pin stays configured this way until it is reconfigured by a driver. Waking up
+/// happens as soon as the level is detected, so make sure it isn't already at `level`
+/// when entering System OFF.
+pub fn wake_on_pin<T: GpioPin>(pin: impl Peripheral<P = T>, pull: Pull, level: Level) {
+    into_ref!(pin);
+
+    pin.conf().write(|w| {
+        w.dir().input();
+        w.input().connect();
+        w.pull().variant(convert_pull(pull));
+        w.drive().s0s1();
+        match level {
+            Level::High => w.sense().high(),
+            Level::Low => w.sense().low(),
+        };
+        w
+    });
+}
+
+/// Keep the contents of RAM sections while in System OFF.
+///
+/// `sections` is a bitmask of the sections of RAM block `block` to retain, see the RAM
+/// section of the product specification for the layout. Retention costs a bit of current
+/// per section, so only retain what is needed.
+pub fn set_ram_retention(block: usize, sections: u16) {
+    // Keep all sections powered in System ON, set retention for System OFF.
+    regs().ram[block]
+        .power
+        .write(|w| unsafe { w.bits(0xFFFF | (sections as u32) << 16) });
+}
+
+/// Enter System OFF.
+///
+/// This never returns: the chip is reset when a wake source triggers. Only the wake
+/// sources configured before calling this wake the chip, see the [module docs](self).
+///
+/// When a debugger is attached, System OFF is emulated and the CPU keeps running, so this
+/// spins forever instead.
+pub fn system_off() -> ! {
+    regs().systemoff.write(|w| unsafe { w.bits(1) });
+    cortex_m::asm::dsb();
+
+    loop {
+        cortex_m::asm::wfe();
+    }
+}
+
+/// Reasons for the last reset.
+///
+/// The reasons accumulate across resets until they are cleared with [`clear_reset_reason`].
+/// If no reason is set, the chip was reset by power-on or brownout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ResetReason {
+    bits: u32,
+}
+
+impl ResetReason {
+    /// Reset from the reset pin.
+    pub fn pin(&self) -> bool {
+        self.bits & (1 << 0) != 0
+    }
+
+    /// Reset from the watchdog.
+    pub fn watchdog(&self) -> bool {
+        self.bits & (1 << 1) != 0
+    }
+
+    /// Reset requested by software, for example with `SCB::sys_reset`.
+    pub fn soft_reset(&self) -> bool {
+        self.bits & (1 << 2) != 0
+    }
+
+    /// Reset from the CPU locking up.
+    pub fn lockup(&self) -> bool {
+        self.bits & (1 << 3) != 0
+    }
+
+    /// Woken up from System OFF by a pin.
+    pub fn system_off_gpio(&self) -> bool {
+        self.bits & (1 << 16) != 0
+    }
+
+    /// Woken up from System OFF by the LPCOMP.
+    pub fn system_off_lpcomp(&self) -> bool {
+        self.bits & (1 << 17) != 0
+    }
+
+    /// Woken up from System OFF by the debug interface.
+    pub fn system_off_debug(&self) -> bool {
+        self.bits & (1 << 18) != 0
+    }
+
+    /// Woken up from System OFF by an NFC field.
+    pub fn system_off_nfc(&self) -> bool {
+        self.bits & (1 << 19) != 0
+    }
+
+    /// Woken up from System OFF by VBUS being detected.
+    pub fn system_off_vbus(&self) -> bool {
+        self.bits & (1 << 20) != 0
+    }
+
+    /// Whether the chip was reset by power-on or brownout, as no other reason is set.
+    pub fn power_on(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Raw value of the RESETREAS register.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+}
+
+/// Get the reasons for the last reset.
+pub fn reset_reason() -> ResetReason {
+    ResetReason {
+        bits: regs().resetreas.read().bits(),
+    }
+}
+
+/// Clear the reset reasons, so the next reset reports only its own reason.
+pub fn clear_reset_reason() {
+    regs().resetreas.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+}
@@ -6,17 +6,21 @@ use crate::{pac, Peripheral};
 const DPPI_ENABLE_BIT: u32 = 0x8000_0000;
 const DPPI_CHANNEL_MASK: u32 = 0x0000_00FF;
 
-fn regs() -> &'static pac::dppic::RegisterBlock {
+pub(super) fn regs() -> &'static pac::dppic::RegisterBlock {
     unsafe { &*pac::DPPIC::ptr() }
 }
 
 impl<'d, C: ConfigurableChannel> Ppi<'d, C, 1, 1> {
+    /// Configure PPI channel to trigger `task` on `event`.
     pub fn new_one_to_one(ch: impl Peripheral<P = C> + 'd, event: Event, task: Task) -> Self {
         Ppi::new_many_to_many(ch, [event], [task])
     }
 }
 
 impl<'d, C: ConfigurableChannel> Ppi<'d, C, 1, 2> {
+    /// Configure PPI channel to trigger `task1` and `task2` on `event`.
+    ///
+    /// DPPI channels have no dedicated fork endpoint, both tasks simply subscribe to the channel.
     pub fn new_one_to_two(ch: impl Peripheral<P = C> + 'd, event: Event, task1: Task, task2: Task) -> Self {
         Ppi::new_many_to_many(ch, [event], [task1, task2])
     }
@@ -25,11 +29,26 @@ impl<'d, C: ConfigurableChannel> Ppi<'d, C, 1, 2> {
 impl<'d, C: ConfigurableChannel, const EVENT_COUNT: usize, const TASK_COUNT: usize>
     Ppi<'d, C, EVENT_COUNT, TASK_COUNT>
 {
+    // A channel without publishers never triggers, and one without subscribers does nothing.
+    const COUNTS_OK: () = assert!(
+        EVENT_COUNT > 0 && TASK_COUNT > 0,
+        "a DPPI channel needs at least one event and one task"
+    );
+
+    /// Configure PPI channel to trigger all of `tasks` on any of `events`.
+    ///
+    /// This is also how a task is forked on DPPI: any number of tasks can subscribe to the
+    /// channel. Using no event or no task fails to compile.
+    ///
+    /// Panics if a task or event is already connected to another channel.
     pub fn new_many_to_many(
         ch: impl Peripheral<P = C> + 'd,
         events: [Event; EVENT_COUNT],
         tasks: [Task; TASK_COUNT],
     ) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::COUNTS_OK;
+
         into_ref!(ch);
 
         let val = DPPI_ENABLE_BIT | (ch.number() as u32 & DPPI_CHANNEL_MASK);
@@ -13,11 +13,19 @@
 //!
 //! The DPPI for nRF53 and nRF91 devices works in a different way. Every channel can support infinitely
 //! many tasks and events, but any single task or event can only be coupled with one channel.
+//! There is no separate fork endpoint: a task is forked by subscribing more tasks to the channel.
+//!
+//! The number of events and tasks of a channel is part of its type. Only the combinations the
+//! hardware supports can be built, anything else fails to compile.
+//!
+//! Channels can be collected in a [`PpiGroup`], which enables or disables all of them at once,
+//! either from software or through its own tasks, so that one event can switch a whole set of
+//! channels on or off.
 //!
 
 use core::ptr::NonNull;
 
-use embassy_hal_common::{impl_peripheral, PeripheralRef};
+use embassy_hal_common::{impl_peripheral, into_ref, PeripheralRef};
 
 use crate::{peripherals, Peripheral};
 
@@ -26,6 +34,11 @@ mod dppi;
 #[cfg(feature = "_ppi")]
 mod ppi;
 
+#[cfg(feature = "_dppi")]
+use dppi::regs;
+#[cfg(feature = "_ppi")]
+use ppi::regs;
+
 /// An instance of the Programmable peripheral interconnect on nRF devices.
 pub struct Ppi<'d, C: Channel, const EVENT_COUNT: usize, const TASK_COUNT: usize> {
     ch: PeripheralRef<'d, C>,
@@ -130,7 +143,7 @@ pub trait StaticChannel: Channel + Into<AnyStaticChannel> {
 }
 
 /// Interface for a group of PPI channels.
-pub trait Group: sealed::Group + Sized {
+pub trait Group: sealed::Group + Peripheral<P = Self> + Sized {
     /// Returns the number of the group.
     fn number(&self) -> usize;
     /// Convert into a type erased group.
@@ -228,6 +241,80 @@ macro_rules! impl_ppi_channel {
 // ======================
 //       groups
 
+/// A group of PPI channels that can be enabled and disabled together.
+pub struct PpiGroup<'d, G: Group> {
+    g: PeripheralRef<'d, G>,
+}
+
+impl<'d, G: Group> PpiGroup<'d, G> {
+    /// Create a new, empty group.
+    pub fn new(g: impl Peripheral<P = G> + 'd) -> Self {
+        into_ref!(g);
+
+        let n = g.number();
+        regs().chg[n].write(|w| unsafe { w.bits(0) });
+
+        Self { g }
+    }
+
+    /// Add a channel to the group.
+    pub fn add_channel<C: Channel, const EVENT_COUNT: usize, const TASK_COUNT: usize>(
+        &mut self,
+        ch: &Ppi<'_, C, EVENT_COUNT, TASK_COUNT>,
+    ) {
+        let n = self.g.number();
+        let mask = 1 << ch.ch.number();
+        regs().chg[n].modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+    }
+
+    /// Remove a channel from the group.
+    pub fn remove_channel<C: Channel, const EVENT_COUNT: usize, const TASK_COUNT: usize>(
+        &mut self,
+        ch: &Ppi<'_, C, EVENT_COUNT, TASK_COUNT>,
+    ) {
+        let n = self.g.number();
+        let mask = 1 << ch.ch.number();
+        regs().chg[n].modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+    }
+
+    /// Enable all the channels in the group.
+    pub fn enable_all(&mut self) {
+        let n = self.g.number();
+        regs().tasks_chg[n].en.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Disable all the channels in the group.
+    pub fn disable_all(&mut self) {
+        let n = self.g.number();
+        regs().tasks_chg[n].dis.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Returns reference to the `Enable` task endpoint for PPI.
+    ///
+    /// Triggering it enables all the channels in the group.
+    #[inline(always)]
+    pub fn task_enable_all(&self) -> Task {
+        let n = self.g.number();
+        Task::from_reg(&regs().tasks_chg[n].en)
+    }
+
+    /// Returns reference to the `Disable` task endpoint for PPI.
+    ///
+    /// Triggering it disables all the channels in the group.
+    #[inline(always)]
+    pub fn task_disable_all(&self) -> Task {
+        let n = self.g.number();
+        Task::from_reg(&regs().tasks_chg[n].dis)
+    }
+}
+
+impl<'d, G: Group> Drop for PpiGroup<'d, G> {
+    fn drop(&mut self) {
+        let n = self.g.number();
+        regs().chg[n].write(|w| unsafe { w.bits(0) });
+    }
+}
+
 /// A type erased PPI group.
 pub struct AnyGroup {
     number: u8,
@@ -1,12 +1,14 @@
 #![macro_use]
 
+use core::future::poll_fn;
 use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
 
 use embassy_hal_common::{into_ref, PeripheralRef};
 
 use crate::gpio::sealed::Pin as _;
-use crate::gpio::{AnyPin, Pin as GpioPin, PselBits};
-use crate::interrupt::Interrupt;
+use crate::gpio::{self, AnyPin, Pin as GpioPin, PselBits};
+use crate::interrupt::{Interrupt, InterruptExt};
 use crate::ppi::{Event, Task};
 use crate::util::slice_in_ram_or;
 use crate::{pac, Peripheral};
@@ -26,6 +28,7 @@ pub struct SimplePwm<'d, T: Instance> {
 /// to up to four channels, as well as repeat that sequence n times.
 pub struct SequencePwm<'d, T: Instance> {
     _peri: PeripheralRef<'d, T>,
+    irq: Option<PeripheralRef<'d, T::Interrupt>>,
     ch0: Option<PeripheralRef<'d, AnyPin>>,
     ch1: Option<PeripheralRef<'d, AnyPin>>,
     ch2: Option<PeripheralRef<'d, AnyPin>>,
@@ -49,6 +52,7 @@ const MAX_SEQUENCE_LEN: usize = 32767;
 impl<'d, T: Instance> SequencePwm<'d, T> {
     /// Create a new 1-channel PWM
     #[allow(unused_unsafe)]
+    #[track_caller]
     pub fn new_1ch(
         pwm: impl Peripheral<P = T> + 'd,
         ch0: impl Peripheral<P = impl GpioPin> + 'd,
@@ -60,6 +64,7 @@ impl<'d, T: Instance> SequencePwm<'d, T> {
 
     /// Create a new 2-channel PWM
     #[allow(unused_unsafe)]
+    #[track_caller]
     pub fn new_2ch(
         pwm: impl Peripheral<P = T> + 'd,
         ch0: impl Peripheral<P = impl GpioPin> + 'd,
@@ -72,6 +77,7 @@ impl<'d, T: Instance> SequencePwm<'d, T> {
 
     /// Create a new 3-channel PWM
     #[allow(unused_unsafe)]
+    #[track_caller]
     pub fn new_3ch(
         pwm: impl Peripheral<P = T> + 'd,
         ch0: impl Peripheral<P = impl GpioPin> + 'd,
@@ -92,6 +98,7 @@ impl<'d, T: Instance> SequencePwm<'d, T> {
 
     /// Create a new 4-channel PWM
     #[allow(unused_unsafe)]
+    #[track_caller]
     pub fn new_4ch(
         pwm: impl Peripheral<P = T> + 'd,
         ch0: impl Peripheral<P = impl GpioPin> + 'd,
@@ -111,6 +118,7 @@ impl<'d, T: Instance> SequencePwm<'d, T> {
         )
     }
 
+    #[track_caller]
     fn new_inner(
         _pwm: impl Peripheral<P = T> + 'd,
         ch0: Option<PeripheralRef<'d, AnyPin>>,
@@ -140,9 +148,13 @@ impl<'d, T: Instance> SequencePwm<'d, T> {
             pin.conf().write(|w| w.dir().output());
         }
 
+        gpio::claim_pin(ch0.psel_bits());
         r.psel.out[0].write(|w| unsafe { w.bits(ch0.psel_bits()) });
+        gpio::claim_pin(ch1.psel_bits());
         r.psel.out[1].write(|w| unsafe { w.bits(ch1.psel_bits()) });
+        gpio::claim_pin(ch2.psel_bits());
         r.psel.out[2].write(|w| unsafe { w.bits(ch2.psel_bits()) });
+        gpio::claim_pin(ch3.psel_bits());
         r.psel.out[3].write(|w| unsafe { w.bits(ch3.psel_bits()) });
 
         // Disable all interrupts
@@ -170,6 +182,7 @@ impl<'d, T: Instance> SequencePwm<'d, T> {
 
         Ok(Self {
             _peri: _pwm,
+            irq: None,
             ch0,
             ch1,
             ch2,
@@ -177,6 +190,33 @@ impl<'d, T: Instance> SequencePwm<'d, T> {
         })
     }
 
+    /// Enable the PWM interrupt, which [`Sequencer::wait_complete`] needs to be woken up.
+    pub fn with_interrupt(mut self, irq: impl Peripheral<P = T::Interrupt> + 'd) -> Self {
+        into_ref!(irq);
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        self.irq = Some(irq);
+        self
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = T::regs();
+        let s = T::state();
+
+        if r.events_loopsdone.read().bits() != 0 {
+            r.intenclr.write(|w| w.loopsdone().clear());
+            s.end_waker.wake();
+        }
+
+        if r.events_stopped.read().bits() != 0 {
+            r.intenclr.write(|w| w.stopped().clear());
+            s.end_waker.wake();
+        }
+    }
+
     /// Returns reference to `Stopped` event endpoint for PPI.
     #[inline(always)]
     pub fn event_stopped(&self) -> Event {
@@ -282,24 +322,33 @@ impl<'a, T: Instance> Drop for SequencePwm<'a, T> {
     fn drop(&mut self) {
         let r = T::regs();
 
+        if let Some(irq) = &self.irq {
+            irq.disable();
+            r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        }
+
         if let Some(pin) = &self.ch0 {
             pin.set_low();
             pin.conf().reset();
+            gpio::release_pin(pin.pin_port() as u32);
             r.psel.out[0].reset();
         }
         if let Some(pin) = &self.ch1 {
             pin.set_low();
             pin.conf().reset();
+            gpio::release_pin(pin.pin_port() as u32);
             r.psel.out[1].reset();
         }
         if let Some(pin) = &self.ch2 {
             pin.set_low();
             pin.conf().reset();
+            gpio::release_pin(pin.pin_port() as u32);
             r.psel.out[2].reset();
         }
         if let Some(pin) = &self.ch3 {
             pin.set_low();
             pin.conf().reset();
+            gpio::release_pin(pin.pin_port() as u32);
             r.psel.out[3].reset();
         }
     }
@@ -391,12 +440,17 @@ impl<'d, 's, T: Instance> SingleSequencer<'d, 's, T> {
     }
 
     /// Stop playback. Disables the peripheral. Does NOT clear the last duty
-    /// cycle from the pin. Returns any sequences previously provided to
-    /// `start` so that they may be further mutated.
+    /// cycle from the pin. Blocks until the end of the current PWM period, see
+    /// [`Sequencer::stop`].
     #[inline(always)]
     pub fn stop(&self) {
         self.sequencer.stop();
     }
+
+    /// Wait until playback is complete. See [`Sequencer::wait_complete`].
+    pub async fn wait_complete(&self) {
+        self.sequencer.wait_complete().await
+    }
 }
 
 /// A composition of sequences that can be started and stopped.
@@ -406,7 +460,7 @@ impl<'d, 's, T: Instance> SingleSequencer<'d, 's, T> {
 /// is used.
 #[non_exhaustive]
 pub struct Sequencer<'d, 's, T: Instance> {
-    _pwm: &'s mut SequencePwm<'d, T>,
+    pwm: &'s mut SequencePwm<'d, T>,
     sequence0: Sequence<'s>,
     sequence1: Option<Sequence<'s>>,
 }
@@ -416,7 +470,7 @@ impl<'d, 's, T: Instance> Sequencer<'d, 's, T> {
     /// will be used twice in the one loop.
     pub fn new(pwm: &'s mut SequencePwm<'d, T>, sequence0: Sequence<'s>, sequence1: Option<Sequence<'s>>) -> Self {
         Sequencer {
-            _pwm: pwm,
+            pwm,
             sequence0,
             sequence1,
         }
@@ -443,6 +497,9 @@ impl<'d, 's, T: Instance> Sequencer<'d, 's, T> {
 
         let r = T::regs();
 
+        r.events_loopsdone.reset();
+        r.events_stopped.reset();
+
         r.seq0.refresh.write(|w| unsafe { w.bits(sequence0.config.refresh) });
         r.seq0.enddelay.write(|w| unsafe { w.bits(sequence0.config.end_delay) });
         r.seq0.ptr.write(|w| unsafe { w.bits(sequence0.words.as_ptr() as u32) });
@@ -478,6 +535,7 @@ impl<'d, 's, T: Instance> Sequencer<'d, 's, T> {
 
         // tasks_seqstart() doesn't exist in all svds so write its bit instead
         r.tasks_seqstart[seqstart_index].write(|w| unsafe { w.bits(0x01) });
+        T::state().running.store(true, Ordering::Relaxed);
 
         Ok(())
     }
@@ -485,6 +543,10 @@ impl<'d, 's, T: Instance> Sequencer<'d, 's, T> {
     /// Stop playback. Disables the peripheral. Does NOT clear the last duty
     /// cycle from the pin. Returns any sequences previously provided to
     /// `start` so that they may be further mutated.
+    ///
+    /// The PWM only stops at the end of the current PWM period: if a sequence is playing, this
+    /// blocks until the STOPPED event, at most one PWM period. It returns at once if no sequence
+    /// was started, or if it was already stopped, e.g. through PPI.
     #[inline(always)]
     pub fn stop(&self) {
         let r = T::regs();
@@ -493,11 +555,54 @@ impl<'d, 's, T: Instance> Sequencer<'d, 's, T> {
 
         compiler_fence(Ordering::SeqCst);
 
-        // tasks_stop() doesn't exist in all svds so write its bit instead
-        r.tasks_stop.write(|w| unsafe { w.bits(0x01) });
+        // STOPPED is cleared by `start`, so if it is set, this run was already stopped. It isn't
+        // cleared here: stopping a PWM that was just stopped through PPI wouldn't raise it again.
+        let running = T::state().running.swap(false, Ordering::Relaxed);
+        if running && r.events_stopped.read().bits() == 0 {
+            // tasks_stop() doesn't exist in all svds so write its bit instead
+            r.tasks_stop.write(|w| unsafe { w.bits(0x01) });
+
+            // Disabling the peripheral before STOPPED would lose the event `wait_complete` waits
+            // for.
+            while r.events_stopped.read().bits() == 0 {}
+        }
 
         r.enable.write(|w| w.enable().disabled());
     }
+
+    /// Wait until playback is complete.
+    ///
+    /// Playback is complete once all loops of [`SequenceMode::Loop`] have been played, or once the
+    /// PWM has been stopped through its `Stop` task, e.g. via PPI. In [`SequenceMode::Infinite`]
+    /// only the latter completes the future.
+    ///
+    /// Panics if the interrupt wasn't given with [`SequencePwm::with_interrupt`].
+    pub async fn wait_complete(&self) {
+        assert!(self.pwm.irq.is_some(), "wait_complete needs the PWM interrupt");
+
+        let r = T::regs();
+        let s = T::state();
+
+        poll_fn(|cx| {
+            s.end_waker.register(cx.waker());
+
+            let infinite = r.shorts.read().loopsdone_seqstart0().is_enabled();
+
+            if r.events_stopped.read().bits() != 0 || (!infinite && r.events_loopsdone.read().bits() != 0) {
+                return Poll::Ready(());
+            }
+
+            r.intenset.write(|w| {
+                if !infinite {
+                    w.loopsdone().set();
+                }
+                w.stopped().set()
+            });
+
+            Poll::Pending
+        })
+        .await;
+    }
 }
 
 impl<'d, 's, T: Instance> Drop for Sequencer<'d, 's, T> {
@@ -574,6 +679,7 @@ pub enum CounterMode {
 impl<'d, T: Instance> SimplePwm<'d, T> {
     /// Create a new 1-channel PWM
     #[allow(unused_unsafe)]
+    #[track_caller]
     pub fn new_1ch(pwm: impl Peripheral<P = T> + 'd, ch0: impl Peripheral<P = impl GpioPin> + 'd) -> Self {
         unsafe {
             into_ref!(ch0);
@@ -583,6 +689,7 @@ impl<'d, T: Instance> SimplePwm<'d, T> {
 
     /// Create a new 2-channel PWM
     #[allow(unused_unsafe)]
+    #[track_caller]
     pub fn new_2ch(
         pwm: impl Peripheral<P = T> + 'd,
         ch0: impl Peripheral<P = impl GpioPin> + 'd,
@@ -594,6 +701,7 @@ impl<'d, T: Instance> SimplePwm<'d, T> {
 
     /// Create a new 3-channel PWM
     #[allow(unused_unsafe)]
+    #[track_caller]
     pub fn new_3ch(
         pwm: impl Peripheral<P = T> + 'd,
         ch0: impl Peripheral<P = impl GpioPin> + 'd,
@@ -614,6 +722,7 @@ impl<'d, T: Instance> SimplePwm<'d, T> {
 
     /// Create a new 4-channel PWM
     #[allow(unused_unsafe)]
+    #[track_caller]
     pub fn new_4ch(
         pwm: impl Peripheral<P = T> + 'd,
         ch0: impl Peripheral<P = impl GpioPin> + 'd,
@@ -633,6 +742,7 @@ impl<'d, T: Instance> SimplePwm<'d, T> {
         }
     }
 
+    #[track_caller]
     fn new_inner(
         _pwm: impl Peripheral<P = T> + 'd,
         ch0: Option<PeripheralRef<'d, AnyPin>>,
@@ -663,9 +773,13 @@ impl<'d, T: Instance> SimplePwm<'d, T> {
 
         // if NoPin provided writes disconnected (top bit 1) 0x80000000 else
         // writes pin number ex 13 (0x0D) which is connected (top bit 0)
+        gpio::claim_pin(ch0.psel_bits());
         r.psel.out[0].write(|w| unsafe { w.bits(ch0.psel_bits()) });
+        gpio::claim_pin(ch1.psel_bits());
         r.psel.out[1].write(|w| unsafe { w.bits(ch1.psel_bits()) });
+        gpio::claim_pin(ch2.psel_bits());
         r.psel.out[2].write(|w| unsafe { w.bits(ch2.psel_bits()) });
+        gpio::claim_pin(ch3.psel_bits());
         r.psel.out[3].write(|w| unsafe { w.bits(ch3.psel_bits()) });
 
         let pwm = Self {
@@ -800,31 +914,56 @@ impl<'a, T: Instance> Drop for SimplePwm<'a, T> {
         if let Some(pin) = &self.ch0 {
             pin.set_low();
             pin.conf().reset();
+            gpio::release_pin(pin.pin_port() as u32);
             r.psel.out[0].reset();
         }
         if let Some(pin) = &self.ch1 {
             pin.set_low();
             pin.conf().reset();
+            gpio::release_pin(pin.pin_port() as u32);
             r.psel.out[1].reset();
         }
         if let Some(pin) = &self.ch2 {
             pin.set_low();
             pin.conf().reset();
+            gpio::release_pin(pin.pin_port() as u32);
             r.psel.out[2].reset();
         }
         if let Some(pin) = &self.ch3 {
             pin.set_low();
             pin.conf().reset();
+            gpio::release_pin(pin.pin_port() as u32);
             r.psel.out[3].reset();
         }
     }
 }
 
 pub(crate) mod sealed {
+    use core::sync::atomic::AtomicBool;
+
+    use embassy_sync::waitqueue::AtomicWaker;
+
     use super::*;
 
+    pub struct State {
+        pub end_waker: AtomicWaker,
+        /// Whether a sequence was started by [`Sequencer::start`](super::Sequencer::start) and
+        /// not stopped by [`Sequencer::stop`](super::Sequencer::stop) yet.
+        pub running: AtomicBool,
+    }
+
+    impl State {
+        pub const fn new() -> Self {
+            Self {
+                end_waker: AtomicWaker::new(),
+                running: AtomicBool::new(false),
+            }
+        }
+    }
+
     pub trait Instance {
         fn regs() -> &'static pac::pwm0::RegisterBlock;
+        fn state() -> &'static State;
     }
 }
 
@@ -838,6 +977,10 @@ macro_rules! impl_pwm {
             fn regs() -> &'static pac::pwm0::RegisterBlock {
                 unsafe { &*pac::$pac_type::ptr() }
             }
+            fn state() -> &'static crate::pwm::sealed::State {
+                static STATE: crate::pwm::sealed::State = crate::pwm::sealed::State::new();
+                &STATE
+            }
         }
         impl crate::pwm::Instance for peripherals::$type {
             type Interrupt = crate::interrupt::$irq;
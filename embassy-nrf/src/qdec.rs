@@ -7,7 +7,7 @@ use embassy_hal_common::{into_ref, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
 
 use crate::gpio::sealed::Pin as _;
-use crate::gpio::{AnyPin, Pin as GpioPin};
+use crate::gpio::{self, AnyPin, Pin as GpioPin};
 use crate::interrupt::InterruptExt;
 use crate::peripherals::QDEC;
 use crate::{interrupt, pac, Peripheral};
@@ -41,6 +41,7 @@ impl Default for Config {
 static WAKER: AtomicWaker = AtomicWaker::new();
 
 impl<'d> Qdec<'d> {
+    #[track_caller]
     pub fn new(
         qdec: impl Peripheral<P = QDEC> + 'd,
         irq: impl Peripheral<P = interrupt::QDEC> + 'd,
@@ -52,6 +53,7 @@ impl<'d> Qdec<'d> {
         Self::new_inner(qdec, irq, a.map_into(), b.map_into(), None, config)
     }
 
+    #[track_caller]
     pub fn new_with_led(
         qdec: impl Peripheral<P = QDEC> + 'd,
         irq: impl Peripheral<P = interrupt::QDEC> + 'd,
@@ -64,6 +66,7 @@ impl<'d> Qdec<'d> {
         Self::new_inner(qdec, irq, a.map_into(), b.map_into(), Some(led.map_into()), config)
     }
 
+    #[track_caller]
     fn new_inner(
         p: impl Peripheral<P = QDEC> + 'd,
         irq: impl Peripheral<P = interrupt::QDEC> + 'd,
@@ -78,10 +81,13 @@ impl<'d> Qdec<'d> {
         // Select pins.
         a.conf().write(|w| w.input().connect().pull().pullup());
         b.conf().write(|w| w.input().connect().pull().pullup());
+        gpio::claim_pin(a.psel_bits());
         r.psel.a.write(|w| unsafe { w.bits(a.psel_bits()) });
+        gpio::claim_pin(b.psel_bits());
         r.psel.b.write(|w| unsafe { w.bits(b.psel_bits()) });
         if let Some(led_pin) = &led {
             led_pin.conf().write(|w| w.dir().output());
+            gpio::claim_pin(led_pin.psel_bits());
             r.psel.led.write(|w| unsafe { w.bits(led_pin.psel_bits()) });
         }
 
@@ -170,6 +176,18 @@ impl<'d> Qdec<'d> {
     }
 }
 
+impl<'d> Drop for Qdec<'d> {
+    fn drop(&mut self) {
+        let r = Self::regs();
+
+        // Pin configuration is left untouched, only the claims are released.
+        gpio::release_pin(r.psel.a.read().bits());
+        gpio::release_pin(r.psel.b.read().bits());
+        gpio::release_pin(r.psel.led.read().bits());
+        r.psel.led.reset();
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum SamplePeriod {
     _128us,
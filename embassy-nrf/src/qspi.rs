@@ -1,8 +1,8 @@
 #![macro_use]
 
 use core::future::poll_fn;
-use core::ptr;
 use core::task::Poll;
+use core::{ptr, slice};
 
 use embassy_hal_common::drop::DropBomb;
 use embassy_hal_common::{into_ref, PeripheralRef};
@@ -15,6 +15,13 @@ pub use crate::pac::qspi::ifconfig0::{
 pub use crate::pac::qspi::ifconfig1::SPIMODE_A as SpiMode;
 use crate::{pac, Peripheral};
 
+/// Address at which the external flash is mapped for execute-in-place (XIP) access.
+#[cfg(feature = "nrf52840")]
+pub const XIP_BASE: usize = 0x1200_0000;
+/// Address at which the external flash is mapped for execute-in-place (XIP) access.
+#[cfg(feature = "_nrf5340-app")]
+pub const XIP_BASE: usize = 0x1000_0000;
+
 pub struct DeepPowerDownConfig {
     /// Time required for entering DPM, in units of 16us
     pub enter_time: u16,
@@ -43,6 +50,7 @@ pub enum Frequency {
 
 #[non_exhaustive]
 pub struct Config {
+    /// Flash address that is mapped at [`XIP_BASE`].
     pub xip_offset: u32,
     pub read_opcode: ReadOpcode,
     pub write_opcode: WriteOpcode,
@@ -83,9 +91,12 @@ pub enum Error {
 pub struct Qspi<'d, T: Instance, const FLASH_SIZE: usize> {
     irq: PeripheralRef<'d, T::Interrupt>,
     dpm_enabled: bool,
+    dpm_active: bool,
+    xip_offset: u32,
 }
 
 impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
+    #[track_caller]
     pub fn new(
         _qspi: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -114,11 +125,17 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
         io2.conf().write(|w| w.dir().output().drive().h0h1());
         io3.conf().write(|w| w.dir().output().drive().h0h1());
 
+        gpio::claim_pin(sck.psel_bits());
         r.psel.sck.write(|w| unsafe { w.bits(sck.psel_bits()) });
+        gpio::claim_pin(csn.psel_bits());
         r.psel.csn.write(|w| unsafe { w.bits(csn.psel_bits()) });
+        gpio::claim_pin(io0.psel_bits());
         r.psel.io0.write(|w| unsafe { w.bits(io0.psel_bits()) });
+        gpio::claim_pin(io1.psel_bits());
         r.psel.io1.write(|w| unsafe { w.bits(io1.psel_bits()) });
+        gpio::claim_pin(io2.psel_bits());
         r.psel.io2.write(|w| unsafe { w.bits(io2.psel_bits()) });
+        gpio::claim_pin(io3.psel_bits());
         r.psel.io3.write(|w| unsafe { w.bits(io3.psel_bits()) });
 
         r.ifconfig0.write(|w| {
@@ -160,6 +177,8 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
 
         let mut res = Self {
             dpm_enabled: config.deep_power_down.is_some(),
+            dpm_active: false,
+            xip_offset: config.xip_offset,
             irq,
         };
 
@@ -183,11 +202,15 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
         }
     }
 
+    /// Do a custom instruction transfer of up to 8 bytes.
+    ///
+    /// `req` is sent after the opcode, while the bytes clocked in at the same time are written
+    /// to `resp`. Use [`custom_instruction_long`](Self::custom_instruction_long) for longer transfers.
     pub async fn custom_instruction(&mut self, opcode: u8, req: &[u8], resp: &mut [u8]) -> Result<(), Error> {
         let bomb = DropBomb::new();
 
         let len = core::cmp::max(req.len(), resp.len()) as u8;
-        self.custom_instruction_start(opcode, req, len)?;
+        self.custom_instruction_start(opcode, req, len, LongFrame::Disabled)?;
 
         self.wait_ready().await;
 
@@ -198,9 +221,10 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
         Ok(())
     }
 
+    /// Do a custom instruction transfer of up to 8 bytes, blocking version.
     pub fn blocking_custom_instruction(&mut self, opcode: u8, req: &[u8], resp: &mut [u8]) -> Result<(), Error> {
         let len = core::cmp::max(req.len(), resp.len()) as u8;
-        self.custom_instruction_start(opcode, req, len)?;
+        self.custom_instruction_start(opcode, req, len, LongFrame::Disabled)?;
 
         self.blocking_wait_ready();
 
@@ -209,8 +233,74 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
         Ok(())
     }
 
-    fn custom_instruction_start(&mut self, opcode: u8, req: &[u8], len: u8) -> Result<(), Error> {
+    /// Do a custom instruction transfer of any length.
+    ///
+    /// This uses the long frame mode, which keeps CSN asserted while the data is transferred
+    /// in chunks of 8 bytes. It is useful for instructions such as reading the SFDP table or the
+    /// security registers, which don't fit in a regular custom instruction.
+    pub async fn custom_instruction_long(&mut self, opcode: u8, req: &[u8], resp: &mut [u8]) -> Result<(), Error> {
+        let bomb = DropBomb::new();
+
+        let len = core::cmp::max(req.len(), resp.len());
+
+        // Send only the opcode first, the data follows in continuation frames.
+        let frame = if len == 0 {
+            LongFrame::Disabled
+        } else {
+            LongFrame::Start
+        };
+        self.custom_instruction_start(opcode, &[], 0, frame)?;
+        self.wait_ready().await;
+
+        for offset in (0..len).step_by(8) {
+            let n = core::cmp::min(8, len - offset);
+            let frame = if offset + n == len {
+                LongFrame::Stop
+            } else {
+                LongFrame::Continue
+            };
+
+            self.custom_instruction_start(opcode, chunk(req, offset, n), n as u8, frame)?;
+            self.wait_ready().await;
+            self.custom_instruction_finish(chunk_mut(resp, offset, n))?;
+        }
+
+        bomb.defuse();
+
+        Ok(())
+    }
+
+    /// Do a custom instruction transfer of any length, blocking version.
+    pub fn blocking_custom_instruction_long(&mut self, opcode: u8, req: &[u8], resp: &mut [u8]) -> Result<(), Error> {
+        let len = core::cmp::max(req.len(), resp.len());
+
+        let frame = if len == 0 {
+            LongFrame::Disabled
+        } else {
+            LongFrame::Start
+        };
+        self.custom_instruction_start(opcode, &[], 0, frame)?;
+        self.blocking_wait_ready();
+
+        for offset in (0..len).step_by(8) {
+            let n = core::cmp::min(8, len - offset);
+            let frame = if offset + n == len {
+                LongFrame::Stop
+            } else {
+                LongFrame::Continue
+            };
+
+            self.custom_instruction_start(opcode, chunk(req, offset, n), n as u8, frame)?;
+            self.blocking_wait_ready();
+            self.custom_instruction_finish(chunk_mut(resp, offset, n))?;
+        }
+
+        Ok(())
+    }
+
+    fn custom_instruction_start(&mut self, opcode: u8, req: &[u8], len: u8, frame: LongFrame) -> Result<(), Error> {
         assert!(req.len() <= 8);
+        assert!(len <= 8);
 
         let mut dat0: u32 = 0;
         let mut dat1: u32 = 0;
@@ -233,15 +323,19 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
         r.events_ready.reset();
         r.intenset.write(|w| w.ready().set());
 
+        // Continuation frames of a long frame transfer must not be preceded by WREN
+        // or wait for WIP, as that would break up the transfer.
+        let first = matches!(frame, LongFrame::Disabled | LongFrame::Start);
+
         r.cinstrconf.write(|w| {
             let w = unsafe { w.opcode().bits(opcode) };
             let w = unsafe { w.length().bits(len + 1) };
             let w = w.lio2().bit(true);
             let w = w.lio3().bit(true);
-            let w = w.wipwait().bit(true);
-            let w = w.wren().bit(true);
-            let w = w.lfen().bit(false);
-            let w = w.lfstop().bit(false);
+            let w = w.wipwait().bit(first);
+            let w = w.wren().bit(first);
+            let w = w.lfen().bit(frame != LongFrame::Disabled);
+            let w = w.lfstop().bit(frame == LongFrame::Stop);
             w
         });
         Ok(())
@@ -259,7 +353,7 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
         }
         for i in 0..4 {
             if i + 4 < resp.len() {
-                resp[i] = (dat1 >> (i * 8)) as u8;
+                resp[i + 4] = (dat1 >> (i * 8)) as u8;
             }
         }
         Ok(())
@@ -287,6 +381,83 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
         }
     }
 
+    /// Put the flash chip in deep power-down mode (DPM).
+    ///
+    /// The flash must be woken up with [`exit_deep_power_down`](Self::exit_deep_power_down)
+    /// before doing any other operation on it.
+    ///
+    /// Panics if deep power-down was not enabled in [`Config::deep_power_down`].
+    pub fn enter_deep_power_down(&mut self) {
+        assert!(self.dpm_enabled, "deep power-down is not enabled");
+        if self.dpm_active {
+            return;
+        }
+
+        let r = T::regs();
+        r.ifconfig1.modify(|_, w| w.dpmen().enter());
+
+        // Wait for DPM enter.
+        // Unfortunately we must spin. There's no way to do this interrupt-driven.
+        // The READY event does NOT fire on DPM enter (but it does fire on DPM exit :shrug:)
+        while r.status.read().dpm().is_disabled() {}
+
+        // Wait MORE for DPM enter.
+        // I have absolutely no idea why, but the wait above is not enough :'(
+        // Tested with mx25r64 in nrf52840-dk, and with mx25r16 in custom board
+        cortex_m::asm::delay(4096);
+
+        self.dpm_active = true;
+    }
+
+    /// Wake the flash chip up from deep power-down mode.
+    pub async fn exit_deep_power_down(&mut self) {
+        if !self.dpm_active {
+            return;
+        }
+
+        let bomb = DropBomb::new();
+
+        self.start_exit_deep_power_down();
+        self.wait_ready().await;
+        self.dpm_active = false;
+
+        bomb.defuse();
+    }
+
+    /// Wake the flash chip up from deep power-down mode, blocking version.
+    pub fn blocking_exit_deep_power_down(&mut self) {
+        if !self.dpm_active {
+            return;
+        }
+
+        self.start_exit_deep_power_down();
+        self.blocking_wait_ready();
+        self.dpm_active = false;
+    }
+
+    fn start_exit_deep_power_down(&mut self) {
+        let r = T::regs();
+        r.events_ready.reset();
+        r.intenset.write(|w| w.ready().set());
+        r.ifconfig1.modify(|_, w| w.dpmen().exit());
+    }
+
+    /// Get the execute-in-place (XIP) view of the flash.
+    ///
+    /// The returned slice starts at [`Config::xip_offset`] in the flash and ends at the end of
+    /// the flash. Code and data placed there can be used directly by the CPU, which is the
+    /// typical way of storing large assets in external flash.
+    ///
+    /// The slice borrows the driver, so the flash contents can't change under it. To erase or
+    /// program the flash, drop the slice, do the operations, and then get it again.
+    ///
+    /// Panics if the flash is in deep power-down mode.
+    pub fn xip(&self) -> &[u8] {
+        assert!(!self.dpm_active, "flash is in deep power-down");
+        let len = FLASH_SIZE.saturating_sub(self.xip_offset as usize);
+        unsafe { slice::from_raw_parts(XIP_BASE as *const u8, len) }
+    }
+
     fn start_read(&mut self, address: usize, data: &mut [u8]) -> Result<(), Error> {
         assert_eq!(data.as_ptr() as u32 % 4, 0);
         assert_eq!(data.len() as u32 % 4, 0);
@@ -398,24 +569,37 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Qspi<'d, T, FLASH_SIZE> {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LongFrame {
+    /// Regular custom instruction, CSN is released at the end.
+    Disabled,
+    /// First frame of a long frame transfer.
+    Start,
+    /// Middle frame of a long frame transfer.
+    Continue,
+    /// Last frame of a long frame transfer, CSN is released at the end.
+    Stop,
+}
+
+fn chunk(buf: &[u8], offset: usize, len: usize) -> &[u8] {
+    let start = core::cmp::min(offset, buf.len());
+    let end = core::cmp::min(offset + len, buf.len());
+    &buf[start..end]
+}
+
+fn chunk_mut(buf: &mut [u8], offset: usize, len: usize) -> &mut [u8] {
+    let start = core::cmp::min(offset, buf.len());
+    let end = core::cmp::min(offset + len, buf.len());
+    &mut buf[start..end]
+}
+
 impl<'d, T: Instance, const FLASH_SIZE: usize> Drop for Qspi<'d, T, FLASH_SIZE> {
     fn drop(&mut self) {
         let r = T::regs();
 
         if self.dpm_enabled {
             trace!("qspi: doing deep powerdown...");
-
-            r.ifconfig1.modify(|_, w| w.dpmen().enter());
-
-            // Wait for DPM enter.
-            // Unfortunately we must spin. There's no way to do this interrupt-driven.
-            // The READY event does NOT fire on DPM enter (but it does fire on DPM exit :shrug:)
-            while r.status.read().dpm().is_disabled() {}
-
-            // Wait MORE for DPM enter.
-            // I have absolutely no idea why, but the wait above is not enough :'(
-            // Tested with mx25r64 in nrf52840-dk, and with mx25r16 in custom board
-            cortex_m::asm::delay(4096);
+            self.enter_deep_power_down();
         }
 
         // it seems events_ready is not generated in response to deactivate. nrfx doesn't wait for it.
@@ -438,6 +622,7 @@ impl<'d, T: Instance, const FLASH_SIZE: usize> Drop for Qspi<'d, T, FLASH_SIZE>
         gpio::deconfigure_pin(r.psel.io1.read().bits());
         gpio::deconfigure_pin(r.psel.io2.read().bits());
         gpio::deconfigure_pin(r.psel.io3.read().bits());
+        gpio::release_pin(r.psel.csn.read().bits());
 
         trace!("qspi: dropped");
     }
@@ -0,0 +1,391 @@
+//! Bluetooth Low Energy advertising and scanning.
+//!
+//! This is a minimal BLE link layer, without connections: it can send non-connectable
+//! advertisements, and passively scan for the advertisements of other devices. This is enough to
+//! implement beacons, or to collect data from them, without a full Bluetooth stack.
+//!
+//! Only the legacy advertising PDUs on the 1 Mbit/s PHY are supported.
+
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{with_timeout, Duration};
+
+use super::{disable, regs, TxPower, INT_DISABLED, SHORTS_END_DISABLE, SHORTS_READY_START};
+use crate::interrupt::InterruptExt;
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+/// Maximum length of advertising data.
+pub const MAX_ADV_DATA_LEN: usize = 31;
+
+// Advertiser address followed by the advertising data.
+const MAX_PAYLOAD_LEN: usize = 6 + MAX_ADV_DATA_LEN;
+
+const ACCESS_ADDRESS: u32 = 0x8E89_BED6;
+
+const SHORTS_ADDRESS_RSSISTART: u32 = 1 << 4;
+const SHORTS_DISABLED_RSSISTOP: u32 = 1 << 8;
+
+const HEADER_TX_ADD: u8 = 1 << 6;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// BLE error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The advertising data is longer than [`MAX_ADV_DATA_LEN`].
+    DataTooLong,
+}
+
+/// Type of a device address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressKind {
+    /// Public address, assigned by the IEEE.
+    Public,
+    /// Random address.
+    Random,
+}
+
+/// Device address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Address {
+    /// Type of the address.
+    pub kind: AddressKind,
+    /// The address, least significant byte first as sent over the air.
+    pub bytes: [u8; 6],
+}
+
+impl Address {
+    /// The random static address of this device, programmed in the factory information registers.
+    pub fn from_ficr() -> Self {
+        let ficr = unsafe { &*pac::FICR::ptr() };
+        let lo = ficr.deviceaddr[0].read().bits().to_le_bytes();
+        let hi = ficr.deviceaddr[1].read().bits().to_le_bytes();
+        Self {
+            kind: AddressKind::Random,
+            // The two most significant bits of a random static address are set.
+            bytes: [lo[0], lo[1], lo[2], lo[3], hi[0], hi[1] | 0xC0],
+        }
+    }
+}
+
+/// Advertising PDU type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PduType {
+    /// Connectable and scannable undirected advertising.
+    AdvInd,
+    /// Connectable directed advertising.
+    AdvDirectInd,
+    /// Non-connectable and non-scannable undirected advertising.
+    AdvNonconnInd,
+    /// Scan request.
+    ScanReq,
+    /// Scan response.
+    ScanRsp,
+    /// Connection request.
+    ConnectInd,
+    /// Scannable undirected advertising.
+    AdvScanInd,
+    /// Unknown or extended advertising PDU.
+    Other(u8),
+}
+
+impl From<u8> for PduType {
+    fn from(val: u8) -> Self {
+        match val {
+            0x0 => PduType::AdvInd,
+            0x1 => PduType::AdvDirectInd,
+            0x2 => PduType::AdvNonconnInd,
+            0x3 => PduType::ScanReq,
+            0x4 => PduType::ScanRsp,
+            0x5 => PduType::ConnectInd,
+            0x6 => PduType::AdvScanInd,
+            other => PduType::Other(other),
+        }
+    }
+}
+
+/// Advertising channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Channel {
+    /// Channel 37, at 2402 MHz.
+    _37,
+    /// Channel 38, at 2426 MHz.
+    _38,
+    /// Channel 39, at 2480 MHz.
+    _39,
+}
+
+impl Channel {
+    /// All advertising channels, in the order they are used.
+    pub const ALL: [Channel; 3] = [Channel::_37, Channel::_38, Channel::_39];
+
+    fn index(self) -> u8 {
+        match self {
+            Channel::_37 => 37,
+            Channel::_38 => 38,
+            Channel::_39 => 39,
+        }
+    }
+
+    fn frequency(self) -> u8 {
+        match self {
+            Channel::_37 => 2,
+            Channel::_38 => 26,
+            Channel::_39 => 80,
+        }
+    }
+}
+
+/// Used to configure the BLE driver.
+///
+/// See the `Default` impl for suitable default values.
+#[non_exhaustive]
+pub struct Config {
+    /// Transmit power.
+    pub tx_power: TxPower,
+}
+
+impl Default for Config {
+    /// Default configuration, transmitting at 0 dBm.
+    fn default() -> Self {
+        Self {
+            tx_power: TxPower::ZerodBm,
+        }
+    }
+}
+
+/// An advertisement received while scanning.
+pub struct Report<'a> {
+    /// Channel the advertisement was received on.
+    pub channel: Channel,
+    /// Type of the advertising PDU.
+    pub pdu_type: PduType,
+    /// Address of the advertiser.
+    pub address: Address,
+    /// Advertising data.
+    pub data: &'a [u8],
+    /// Received signal strength, in dBm.
+    pub rssi: i8,
+}
+
+/// BLE advertising and scanning driver.
+pub struct Radio<'d> {
+    _p: PeripheralRef<'d, peripherals::RADIO>,
+    // S0 header, LENGTH, payload.
+    buf: [u8; 2 + MAX_PAYLOAD_LEN],
+}
+
+impl<'d> Radio<'d> {
+    /// Create a new BLE driver.
+    pub fn new(
+        radio: impl Peripheral<P = peripherals::RADIO> + 'd,
+        irq: impl Peripheral<P = interrupt::RADIO> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(radio, irq);
+
+        let r = regs();
+
+        // Power cycle the radio to get it into a known state.
+        r.power.write(|w| unsafe { w.bits(0) });
+        r.power.write(|w| unsafe { w.bits(1) });
+
+        // Ble_1Mbit
+        r.mode.write(|w| unsafe { w.bits(3) });
+        r.txpower.write(|w| unsafe { w.bits(config.tx_power as u8 as u32) });
+        // 8-bit LENGTH field, 1-byte S0 field holding the PDU header.
+        r.pcnf0.write(|w| unsafe { w.bits(8 | 1 << 8) });
+        // 3-byte base address, little endian, whitening enabled.
+        r.pcnf1
+            .write(|w| unsafe { w.bits(MAX_PAYLOAD_LEN as u32 | 3 << 16 | 1 << 25) });
+        // 24-bit CRC, not including the access address.
+        r.crccnf.write(|w| unsafe { w.bits(3 | 1 << 8) });
+        r.crcpoly.write(|w| unsafe { w.bits(0x00_065B) });
+        r.crcinit.write(|w| unsafe { w.bits(0x55_5555) });
+
+        r.base0.write(|w| unsafe { w.bits(ACCESS_ADDRESS << 8) });
+        r.prefix0.write(|w| unsafe { w.bits(ACCESS_ADDRESS >> 24) });
+        r.txaddress.write(|w| unsafe { w.bits(0) });
+        r.rxaddresses.write(|w| unsafe { w.bits(1) });
+
+        r.shorts.reset();
+        r.events_disabled.reset();
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        r.intenset.write(|w| unsafe { w.bits(INT_DISABLED) });
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self {
+            _p: radio,
+            buf: [0; 2 + MAX_PAYLOAD_LEN],
+        }
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = regs();
+
+        if r.events_disabled.read().bits() != 0 {
+            r.events_disabled.reset();
+            DONE.store(true, Ordering::Relaxed);
+            WAKER.wake();
+        }
+    }
+
+    fn set_channel(&mut self, channel: Channel) {
+        let r = regs();
+        r.frequency.write(|w| unsafe { w.bits(channel.frequency() as u32) });
+        r.datawhiteiv
+            .write(|w| unsafe { w.bits(channel.index() as u32 | 0x40) });
+    }
+
+    /// Send a non-connectable advertisement with `data` from `address`.
+    ///
+    /// This is one advertising event: the advertisement is sent once on each advertising channel.
+    /// To advertise continuously, call this at the advertising interval, plus a random delay of up
+    /// to 10 ms.
+    pub async fn advertise(&mut self, address: &Address, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_ADV_DATA_LEN {
+            return Err(Error::DataTooLong);
+        }
+
+        let mut header = 0x2; // ADV_NONCONN_IND
+        if address.kind == AddressKind::Random {
+            header |= HEADER_TX_ADD;
+        }
+        self.buf[0] = header;
+        self.buf[1] = (6 + data.len()) as u8;
+        self.buf[2..8].copy_from_slice(&address.bytes);
+        self.buf[8..][..data.len()].copy_from_slice(data);
+
+        let r = regs();
+        r.packetptr.write(|w| unsafe { w.bits(self.buf.as_ptr() as u32) });
+        r.shorts
+            .write(|w| unsafe { w.bits(SHORTS_READY_START | SHORTS_END_DISABLE) });
+
+        let on_drop = OnDrop::new(abort);
+
+        for channel in Channel::ALL {
+            self.set_channel(channel);
+
+            compiler_fence(Ordering::SeqCst);
+            DONE.store(false, Ordering::Relaxed);
+            r.tasks_txen.write(|w| unsafe { w.bits(1) });
+            wait_done().await;
+        }
+
+        abort();
+        on_drop.defuse();
+        compiler_fence(Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Scan for advertisements, staying on each advertising channel for `window`.
+    ///
+    /// If `filter` isn't empty, only advertisements from the addresses it contains are returned.
+    pub async fn scan(&mut self, window: Duration, filter: &[Address]) -> Report<'_> {
+        let mut channels = Channel::ALL.into_iter().cycle();
+        let mut channel = channels.next().unwrap();
+        self.set_channel(channel);
+
+        loop {
+            match with_timeout(window, self.receive()).await {
+                Ok(true) => {}
+                // Invalid CRC or PDU.
+                Ok(false) => continue,
+                Err(_) => {
+                    channel = channels.next().unwrap();
+                    self.set_channel(channel);
+                    continue;
+                }
+            }
+
+            let header = self.buf[0];
+            let address = Address {
+                kind: if header & HEADER_TX_ADD != 0 {
+                    AddressKind::Random
+                } else {
+                    AddressKind::Public
+                },
+                bytes: self.buf[2..8].try_into().unwrap(),
+            };
+
+            if !filter.is_empty() && !filter.contains(&address) {
+                continue;
+            }
+
+            let len = (self.buf[1] as usize).min(MAX_PAYLOAD_LEN);
+            return Report {
+                channel,
+                pdu_type: PduType::from(header & 0xF),
+                address,
+                data: &self.buf[8..2 + len],
+                rssi: -(regs().rssisample.read().bits() as i8),
+            };
+        }
+    }
+
+    /// Receive one PDU on the current channel, returns whether it's valid.
+    async fn receive(&mut self) -> bool {
+        let r = regs();
+
+        r.packetptr.write(|w| unsafe { w.bits(self.buf.as_mut_ptr() as u32) });
+        r.shorts.write(|w| unsafe {
+            w.bits(SHORTS_READY_START | SHORTS_END_DISABLE | SHORTS_ADDRESS_RSSISTART | SHORTS_DISABLED_RSSISTOP)
+        });
+
+        let on_drop = OnDrop::new(abort);
+
+        compiler_fence(Ordering::SeqCst);
+        DONE.store(false, Ordering::Relaxed);
+        r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+
+        wait_done().await;
+
+        on_drop.defuse();
+        compiler_fence(Ordering::SeqCst);
+
+        // Advertising PDUs always start with the advertiser address.
+        r.crcstatus.read().bits() & 1 != 0 && self.buf[1] >= 6
+    }
+}
+
+impl<'d> Drop for Radio<'d> {
+    fn drop(&mut self) {
+        abort();
+        regs().intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        regs().power.write(|w| unsafe { w.bits(0) });
+    }
+}
+
+async fn wait_done() {
+    poll_fn(|cx| {
+        WAKER.register(cx.waker());
+
+        if DONE.load(Ordering::Relaxed) {
+            return Poll::Ready(());
+        }
+
+        Poll::Pending
+    })
+    .await
+}
+
+fn abort() {
+    disable();
+    DONE.store(false, Ordering::Relaxed);
+}
@@ -0,0 +1,508 @@
+//! Enhanced ShockBurst (ESB) protocol.
+//!
+//! ESB is the packet protocol of the nRF24 series. A primary transmitter (PTX) sends packets to
+//! one of eight pipes of a primary receiver (PRX), which acknowledges them. Acknowledgements can
+//! carry a payload back to the PTX, and unacknowledged packets are retransmitted a configurable
+//! number of times.
+//!
+//! The same [`Esb`] driver can act as either role: [`Esb::send`] makes it a PTX for the duration
+//! of the call, and [`Esb::receive`] makes it a PRX.
+//!
+//! Time-critical parts of the protocol, such as sending acknowledgements, are done from the radio
+//! interrupt.
+
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU32, AtomicU8, Ordering};
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{with_timeout, Duration, Timer};
+
+use super::{disable, regs, TxPower, INT_DISABLED, SHORTS_DISABLED_RXEN, SHORTS_END_DISABLE, SHORTS_READY_START};
+use crate::interrupt::InterruptExt;
+use crate::{interrupt, peripherals, Peripheral};
+
+/// Maximum payload length, in bytes.
+pub const MAX_PAYLOAD_LEN: usize = 32;
+
+/// Number of pipes.
+pub const PIPE_COUNT: usize = 8;
+
+// Packets in RAM are a LENGTH byte, an S1 byte holding the PID and the ACK flag, and the payload.
+const PACKET_LEN: usize = MAX_PAYLOAD_LEN + 2;
+
+const PHASE_IDLE: u8 = 0;
+const PHASE_TX: u8 = 1;
+const PHASE_PTX_TX: u8 = 2;
+const PHASE_PTX_RX: u8 = 3;
+const PHASE_PRX_RX: u8 = 4;
+const PHASE_PRX_ACK: u8 = 5;
+const PHASE_DONE: u8 = 6;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+static PHASE: AtomicU8 = AtomicU8::new(PHASE_IDLE);
+static RX_PTR: AtomicU32 = AtomicU32::new(0);
+static ACK_PTR: AtomicU32 = AtomicU32::new(0);
+static DUPLICATE: AtomicBool = AtomicBool::new(false);
+// CRC and PID of the last packet received on each pipe, used to detect retransmissions.
+static LAST_RX: [AtomicU32; PIPE_COUNT] = {
+    const NONE: AtomicU32 = AtomicU32::new(u32::MAX);
+    [NONE; PIPE_COUNT]
+};
+
+/// ESB error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The payload is longer than [`MAX_PAYLOAD_LEN`].
+    PayloadTooLong,
+    /// The pipe number is out of range, or the pipe isn't enabled.
+    InvalidPipe,
+    /// No acknowledgement was received, even after retransmitting.
+    MaxRetransmits,
+}
+
+/// Bit rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Bitrate {
+    /// 1 Mbit/s
+    _1Mbps,
+    /// 2 Mbit/s
+    _2Mbps,
+}
+
+/// CRC length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Crc {
+    /// 8-bit CRC.
+    _8Bit,
+    /// 16-bit CRC.
+    _16Bit,
+}
+
+/// Address width, including the prefix byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressWidth {
+    /// 3 bytes
+    _3Bytes = 3,
+    /// 4 bytes
+    _4Bytes = 4,
+    /// 5 bytes
+    _5Bytes = 5,
+}
+
+/// Used to configure the ESB driver.
+///
+/// See the `Default` impl for suitable default values.
+#[non_exhaustive]
+pub struct Config {
+    /// Bit rate.
+    pub bitrate: Bitrate,
+    /// CRC length.
+    pub crc: Crc,
+    /// Transmit power.
+    pub tx_power: TxPower,
+    /// RF channel, the frequency is 2400 MHz plus this value. Must be at most 100.
+    pub channel: u8,
+    /// Address width.
+    pub address_width: AddressWidth,
+    /// Base address of pipe 0.
+    pub base_address0: [u8; 4],
+    /// Base address of pipes 1 to 7.
+    pub base_address1: [u8; 4],
+    /// Address prefix of each pipe.
+    pub prefixes: [u8; PIPE_COUNT],
+    /// Bitmask of the pipes the receiver listens on.
+    pub rx_pipes: u8,
+    /// How long the transmitter waits for an acknowledgement, from the start of the transmission.
+    pub ack_timeout: Duration,
+    /// Delay between retransmissions.
+    pub retransmit_delay: Duration,
+    /// How many times a packet is retransmitted if it isn't acknowledged.
+    pub retransmit_count: u8,
+}
+
+impl Default for Config {
+    /// Default configuration, matching the defaults of the nRF24L01+ and Nordic's ESB library.
+    fn default() -> Self {
+        Self {
+            bitrate: Bitrate::_2Mbps,
+            crc: Crc::_16Bit,
+            tx_power: TxPower::ZerodBm,
+            channel: 2,
+            address_width: AddressWidth::_5Bytes,
+            base_address0: [0xE7, 0xE7, 0xE7, 0xE7],
+            base_address1: [0xC2, 0xC2, 0xC2, 0xC2],
+            prefixes: [0xE7, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8],
+            rx_pipes: 0xFF,
+            ack_timeout: Duration::from_micros(1000),
+            retransmit_delay: Duration::from_micros(250),
+            retransmit_count: 3,
+        }
+    }
+}
+
+/// Acknowledgement of a sent packet.
+pub struct Ack<'a> {
+    /// Payload carried by the acknowledgement. Can be empty.
+    pub payload: &'a [u8],
+    /// How many retransmissions were needed.
+    pub retransmits: u8,
+}
+
+/// Received packet.
+pub struct Packet<'a> {
+    /// Pipe the packet was received on.
+    pub pipe: u8,
+    /// Packet payload.
+    pub payload: &'a [u8],
+}
+
+/// ESB driver.
+pub struct Esb<'d> {
+    _p: PeripheralRef<'d, peripherals::RADIO>,
+    ack_timeout: Duration,
+    retransmit_delay: Duration,
+    retransmit_count: u8,
+    rx_pipes: u8,
+    pids: [u8; PIPE_COUNT],
+    tx_buf: [u8; PACKET_LEN],
+    rx_buf: [u8; PACKET_LEN],
+    ack_bufs: [[u8; PACKET_LEN]; PIPE_COUNT],
+}
+
+impl<'d> Esb<'d> {
+    /// Create a new ESB driver.
+    pub fn new(
+        radio: impl Peripheral<P = peripherals::RADIO> + 'd,
+        irq: impl Peripheral<P = interrupt::RADIO> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(radio, irq);
+
+        assert!(config.channel <= 100);
+
+        let r = regs();
+
+        // Power cycle the radio to get it into a known state.
+        r.power.write(|w| unsafe { w.bits(0) });
+        r.power.write(|w| unsafe { w.bits(1) });
+
+        r.mode.write(|w| unsafe {
+            w.bits(match config.bitrate {
+                Bitrate::_1Mbps => 0,
+                Bitrate::_2Mbps => 1,
+            })
+        });
+        r.txpower.write(|w| unsafe { w.bits(config.tx_power as u8 as u32) });
+        r.frequency.write(|w| unsafe { w.bits(config.channel as u32) });
+
+        // 6-bit LENGTH field, 3-bit S1 field holding the PID and the ACK flag.
+        r.pcnf0.write(|w| unsafe { w.bits(6 | 3 << 16) });
+        // Big endian, no whitening.
+        let balen = config.address_width as u32 - 1;
+        r.pcnf1
+            .write(|w| unsafe { w.bits(MAX_PAYLOAD_LEN as u32 | balen << 16 | 1 << 24) });
+
+        match config.crc {
+            Crc::_8Bit => {
+                r.crccnf.write(|w| unsafe { w.bits(1) });
+                r.crcpoly.write(|w| unsafe { w.bits(0x107) });
+                r.crcinit.write(|w| unsafe { w.bits(0xFF) });
+            }
+            Crc::_16Bit => {
+                r.crccnf.write(|w| unsafe { w.bits(2) });
+                r.crcpoly.write(|w| unsafe { w.bits(0x11021) });
+                r.crcinit.write(|w| unsafe { w.bits(0xFFFF) });
+            }
+        }
+
+        let shift = (4 - balen) * 8;
+        r.base0
+            .write(|w| unsafe { w.bits(convert_base(config.base_address0) >> shift) });
+        r.base1
+            .write(|w| unsafe { w.bits(convert_base(config.base_address1) >> shift) });
+        let p = config.prefixes;
+        r.prefix0
+            .write(|w| unsafe { w.bits(convert_prefix([p[0], p[1], p[2], p[3]])) });
+        r.prefix1
+            .write(|w| unsafe { w.bits(convert_prefix([p[4], p[5], p[6], p[7]])) });
+
+        for last in LAST_RX.iter() {
+            last.store(u32::MAX, Ordering::Relaxed);
+        }
+        PHASE.store(PHASE_IDLE, Ordering::Relaxed);
+
+        r.shorts.reset();
+        r.events_disabled.reset();
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        r.intenset.write(|w| unsafe { w.bits(INT_DISABLED) });
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self {
+            _p: radio,
+            ack_timeout: config.ack_timeout,
+            retransmit_delay: config.retransmit_delay,
+            retransmit_count: config.retransmit_count,
+            rx_pipes: config.rx_pipes,
+            pids: [0; PIPE_COUNT],
+            tx_buf: [0; PACKET_LEN],
+            rx_buf: [0; PACKET_LEN],
+            ack_bufs: [[0; PACKET_LEN]; PIPE_COUNT],
+        }
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = regs();
+
+        if r.events_disabled.read().bits() == 0 {
+            return;
+        }
+        r.events_disabled.reset();
+
+        let done = || {
+            PHASE.store(PHASE_DONE, Ordering::Relaxed);
+            WAKER.wake();
+        };
+
+        match PHASE.load(Ordering::Relaxed) {
+            PHASE_TX | PHASE_PTX_RX => done(),
+            PHASE_PTX_TX => {
+                // The DISABLED_RXEN short is already ramping up the receiver for the
+                // acknowledgement, switch buffers before it starts receiving.
+                r.packetptr.write(|w| unsafe { w.bits(RX_PTR.load(Ordering::Relaxed)) });
+                r.shorts
+                    .write(|w| unsafe { w.bits(SHORTS_READY_START | SHORTS_END_DISABLE) });
+                PHASE.store(PHASE_PTX_RX, Ordering::Relaxed);
+            }
+            PHASE_PRX_RX => {
+                if r.crcstatus.read().bits() & 1 == 0 {
+                    r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                    return;
+                }
+
+                let pipe = (r.rxmatch.read().bits() & 0x7) as usize;
+                let s1 = unsafe { *(RX_PTR.load(Ordering::Relaxed) as *const u8).add(1) };
+                let pid = s1 >> 1;
+                let key = (r.rxcrc.read().bits() & 0xFFFF) << 2 | pid as u32;
+                let duplicate = LAST_RX[pipe].swap(key, Ordering::Relaxed) == key;
+                DUPLICATE.store(duplicate, Ordering::Relaxed);
+
+                if s1 & 1 != 0 {
+                    let ack = unsafe { (ACK_PTR.load(Ordering::Relaxed) as *mut u8).add(pipe * PACKET_LEN) };
+                    unsafe { *ack.add(1) = pid << 1 };
+                    r.packetptr.write(|w| unsafe { w.bits(ack as u32) });
+                    r.txaddress.write(|w| unsafe { w.bits(pipe as u32) });
+                    r.tasks_txen.write(|w| unsafe { w.bits(1) });
+                    PHASE.store(PHASE_PRX_ACK, Ordering::Relaxed);
+                } else if duplicate {
+                    r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                } else {
+                    done();
+                }
+            }
+            PHASE_PRX_ACK => {
+                if DUPLICATE.load(Ordering::Relaxed) {
+                    // The transmitter missed our acknowledgement, but the packet was already
+                    // delivered. Keep listening.
+                    r.packetptr.write(|w| unsafe { w.bits(RX_PTR.load(Ordering::Relaxed)) });
+                    r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                    PHASE.store(PHASE_PRX_RX, Ordering::Relaxed);
+                } else {
+                    // Acknowledgement payloads are only sent once.
+                    let pipe = r.txaddress.read().bits() as usize;
+                    unsafe { *(ACK_PTR.load(Ordering::Relaxed) as *mut u8).add(pipe * PACKET_LEN) = 0 };
+                    done();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Send `payload` on `pipe`, and wait for it to be acknowledged.
+    ///
+    /// The packet is retransmitted up to [`Config::retransmit_count`] times if no acknowledgement
+    /// is received.
+    pub async fn send(&mut self, pipe: u8, payload: &[u8]) -> Result<Ack<'_>, Error> {
+        self.prepare_tx(pipe, payload, true)?;
+
+        for attempt in 0..=self.retransmit_count {
+            if attempt > 0 {
+                Timer::after(self.retransmit_delay).await;
+            }
+
+            if self.transmit(pipe, true).await {
+                let len = (self.rx_buf[0] as usize).min(MAX_PAYLOAD_LEN);
+                return Ok(Ack {
+                    payload: &self.rx_buf[2..][..len],
+                    retransmits: attempt,
+                });
+            }
+        }
+
+        Err(Error::MaxRetransmits)
+    }
+
+    /// Send `payload` on `pipe` without asking for an acknowledgement.
+    pub async fn send_no_ack(&mut self, pipe: u8, payload: &[u8]) -> Result<(), Error> {
+        self.prepare_tx(pipe, payload, false)?;
+        self.transmit(pipe, false).await;
+        Ok(())
+    }
+
+    fn prepare_tx(&mut self, pipe: u8, payload: &[u8], ack: bool) -> Result<(), Error> {
+        if pipe as usize >= PIPE_COUNT {
+            return Err(Error::InvalidPipe);
+        }
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(Error::PayloadTooLong);
+        }
+
+        let pid = &mut self.pids[pipe as usize];
+        *pid = (*pid + 1) & 0x3;
+
+        self.tx_buf[0] = payload.len() as u8;
+        self.tx_buf[1] = *pid << 1 | ack as u8;
+        self.tx_buf[2..][..payload.len()].copy_from_slice(payload);
+
+        Ok(())
+    }
+
+    /// Transmit the packet in `tx_buf`, returns whether it was acknowledged.
+    async fn transmit(&mut self, pipe: u8, ack: bool) -> bool {
+        let r = regs();
+
+        r.txaddress.write(|w| unsafe { w.bits(pipe as u32) });
+        r.rxaddresses.write(|w| unsafe { w.bits(1 << pipe) });
+        r.packetptr.write(|w| unsafe { w.bits(self.tx_buf.as_ptr() as u32) });
+        RX_PTR.store(self.rx_buf.as_mut_ptr() as u32, Ordering::Relaxed);
+
+        if ack {
+            r.shorts
+                .write(|w| unsafe { w.bits(SHORTS_READY_START | SHORTS_END_DISABLE | SHORTS_DISABLED_RXEN) });
+            PHASE.store(PHASE_PTX_TX, Ordering::Relaxed);
+        } else {
+            r.shorts
+                .write(|w| unsafe { w.bits(SHORTS_READY_START | SHORTS_END_DISABLE) });
+            PHASE.store(PHASE_TX, Ordering::Relaxed);
+        }
+
+        let on_drop = OnDrop::new(abort);
+
+        compiler_fence(Ordering::SeqCst);
+        r.events_disabled.reset();
+        r.tasks_txen.write(|w| unsafe { w.bits(1) });
+
+        let acked = if ack {
+            with_timeout(self.ack_timeout, wait_done()).await.is_ok() && r.crcstatus.read().bits() & 1 != 0
+        } else {
+            wait_done().await;
+            true
+        };
+
+        abort();
+        on_drop.defuse();
+        compiler_fence(Ordering::SeqCst);
+
+        acked
+    }
+
+    /// Wait for a packet on any of the [`Config::rx_pipes`].
+    ///
+    /// Packets asking for it are acknowledged automatically, with the payload set by
+    /// [`set_ack_payload`](Self::set_ack_payload) if any. Retransmissions of a packet that was
+    /// already received are acknowledged but not returned again.
+    pub async fn receive(&mut self) -> Packet<'_> {
+        let r = regs();
+
+        r.rxaddresses.write(|w| unsafe { w.bits(self.rx_pipes as u32) });
+        r.packetptr.write(|w| unsafe { w.bits(self.rx_buf.as_ptr() as u32) });
+        r.shorts
+            .write(|w| unsafe { w.bits(SHORTS_READY_START | SHORTS_END_DISABLE) });
+        RX_PTR.store(self.rx_buf.as_mut_ptr() as u32, Ordering::Relaxed);
+        ACK_PTR.store(self.ack_bufs.as_mut_ptr() as u32, Ordering::Relaxed);
+        PHASE.store(PHASE_PRX_RX, Ordering::Relaxed);
+
+        let on_drop = OnDrop::new(abort);
+
+        compiler_fence(Ordering::SeqCst);
+        r.events_disabled.reset();
+        r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+
+        wait_done().await;
+
+        abort();
+        on_drop.defuse();
+        compiler_fence(Ordering::SeqCst);
+
+        let len = (self.rx_buf[0] as usize).min(MAX_PAYLOAD_LEN);
+        Packet {
+            pipe: (r.rxmatch.read().bits() & 0x7) as u8,
+            payload: &self.rx_buf[2..][..len],
+        }
+    }
+
+    /// Set the payload sent with the next acknowledgement on `pipe`.
+    ///
+    /// The payload is only sent once, the following acknowledgements are empty until a new one is
+    /// set.
+    pub fn set_ack_payload(&mut self, pipe: u8, payload: &[u8]) -> Result<(), Error> {
+        if pipe as usize >= PIPE_COUNT || self.rx_pipes & (1 << pipe) == 0 {
+            return Err(Error::InvalidPipe);
+        }
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(Error::PayloadTooLong);
+        }
+
+        let buf = &mut self.ack_bufs[pipe as usize];
+        buf[0] = payload.len() as u8;
+        buf[2..][..payload.len()].copy_from_slice(payload);
+
+        Ok(())
+    }
+}
+
+impl<'d> Drop for Esb<'d> {
+    fn drop(&mut self) {
+        abort();
+        regs().intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        regs().power.write(|w| unsafe { w.bits(0) });
+    }
+}
+
+async fn wait_done() {
+    poll_fn(|cx| {
+        WAKER.register(cx.waker());
+
+        if PHASE.load(Ordering::Relaxed) == PHASE_DONE {
+            return Poll::Ready(());
+        }
+
+        Poll::Pending
+    })
+    .await
+}
+
+fn abort() {
+    PHASE.store(PHASE_IDLE, Ordering::Relaxed);
+    disable();
+}
+
+/// Convert a base address to the RADIO's bit order, compatible with the nRF24 series.
+fn convert_base(address: [u8; 4]) -> u32 {
+    u32::from_be_bytes(address.map(u8::reverse_bits))
+}
+
+/// Convert four prefixes to the RADIO's bit order, compatible with the nRF24 series.
+fn convert_prefix(prefixes: [u8; 4]) -> u32 {
+    u32::from_le_bytes(prefixes.map(u8::reverse_bits))
+}
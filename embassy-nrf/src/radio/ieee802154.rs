@@ -0,0 +1,513 @@
+//! IEEE 802.15.4 radio.
+//!
+//! This driver sends and receives raw 802.15.4 frames on the 2.4 GHz O-QPSK PHY. It handles the
+//! parts of the MAC layer that are too time-critical for a task: frames addressed to this device
+//! that request an acknowledgement are acknowledged from the radio interrupt, and [`Radio::transmit`]
+//! waits for the acknowledgement of frames that request one.
+//!
+//! Transmissions are preceded by a clear channel assessment (CCA), and fail with
+//! [`Error::ChannelBusy`] if the channel is in use.
+
+use core::cell::Cell;
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU32, AtomicU8, Ordering};
+use core::task::Poll;
+
+use critical_section::Mutex;
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{with_timeout, Duration, Instant};
+
+use super::{disable, regs, TxPower, INT_DISABLED};
+use crate::interrupt::InterruptExt;
+use crate::{interrupt, peripherals, Peripheral};
+
+/// Maximum PSDU length, including the 2-byte FCS.
+pub const MAX_PSDU_LEN: usize = 127;
+
+const FCS_LEN: usize = 2;
+
+// How long to wait for an acknowledgement after a transmission, macAckWaitDuration for the
+// 2.4 GHz O-QPSK PHY.
+const ACK_TIMEOUT: Duration = Duration::from_micros(864);
+
+const SHORTS_RXREADY_CCASTART: u32 = 1 << 11;
+const SHORTS_CCAIDLE_TXEN: u32 = 1 << 12;
+const SHORTS_CCABUSY_DISABLE: u32 = 1 << 13;
+const SHORTS_TXREADY_START: u32 = 1 << 18;
+const SHORTS_RXREADY_START: u32 = 1 << 19;
+const SHORTS_PHYEND_DISABLE: u32 = 1 << 20;
+
+const FCF_FRAME_TYPE_MASK: u16 = 0b111;
+const FCF_FRAME_TYPE_ACK: u16 = 0b010;
+const FCF_ACK_REQUEST: u16 = 1 << 5;
+const ADDR_MODE_SHORT: u16 = 0b10;
+const ADDR_MODE_EXTENDED: u16 = 0b11;
+
+const PHASE_IDLE: u8 = 0;
+const PHASE_RX: u8 = 1;
+const PHASE_RX_ACK: u8 = 2;
+const PHASE_TX: u8 = 3;
+const PHASE_TX_ACK: u8 = 4;
+const PHASE_DONE: u8 = 5;
+
+const RESULT_OK: u8 = 0;
+const RESULT_BUSY: u8 = 1;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+static PHASE: AtomicU8 = AtomicU8::new(PHASE_IDLE);
+static RESULT: AtomicU8 = AtomicU8::new(RESULT_OK);
+static RX_PTR: AtomicU32 = AtomicU32::new(0);
+static ACK_PTR: AtomicU32 = AtomicU32::new(0);
+// Sequence number of the frame we're waiting an acknowledgement for.
+static TX_SEQ: AtomicU8 = AtomicU8::new(0);
+static TIMESTAMP: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+// Addressing information used to filter received frames, see `Config`.
+static PAN_ID: AtomicU32 = AtomicU32::new(0xFFFF);
+static SHORT_ADDRESS: AtomicU32 = AtomicU32::new(0xFFFF);
+static EXTENDED_ADDRESS_LO: AtomicU32 = AtomicU32::new(0);
+static EXTENDED_ADDRESS_HI: AtomicU32 = AtomicU32::new(0);
+static PROMISCUOUS: AtomicBool = AtomicBool::new(false);
+
+/// 802.15.4 error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The frame is longer than [`MAX_PSDU_LEN`], including the FCS.
+    FrameTooLong,
+    /// The clear channel assessment failed, the frame wasn't sent.
+    ChannelBusy,
+    /// The frame requested an acknowledgement, but none was received.
+    NoAck,
+}
+
+/// Clear channel assessment mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Cca {
+    /// The channel is busy if the energy detected is above the threshold.
+    EnergyDetection {
+        /// Energy detection threshold, in the units of the `EDSAMPLE` register.
+        ed_threshold: u8,
+    },
+    /// The channel is busy if an 802.15.4 signal is detected.
+    CarrierSense,
+    /// The channel is busy if either an 802.15.4 signal is detected, or the energy is above the threshold.
+    CarrierOrEnergyDetection {
+        /// Energy detection threshold, in the units of the `EDSAMPLE` register.
+        ed_threshold: u8,
+    },
+}
+
+/// Used to configure the 802.15.4 driver.
+///
+/// See the `Default` impl for suitable default values.
+#[non_exhaustive]
+pub struct Config {
+    /// Channel, from 11 to 26.
+    pub channel: u8,
+    /// Transmit power.
+    pub tx_power: TxPower,
+    /// Clear channel assessment mode.
+    pub cca: Cca,
+    /// PAN ID of this device.
+    pub pan_id: u16,
+    /// Short address of this device.
+    pub short_address: u16,
+    /// Extended address of this device.
+    pub extended_address: u64,
+    /// Receive all frames, not only the ones addressed to this device. Frames are still only
+    /// acknowledged if they're addressed to this device.
+    pub promiscuous: bool,
+}
+
+impl Default for Config {
+    /// Default configuration, on channel 11, not part of a PAN.
+    fn default() -> Self {
+        Self {
+            channel: 11,
+            tx_power: TxPower::ZerodBm,
+            cca: Cca::CarrierSense,
+            pan_id: 0xFFFF,
+            short_address: 0xFFFF,
+            extended_address: 0,
+            promiscuous: false,
+        }
+    }
+}
+
+/// An 802.15.4 frame.
+pub struct Packet {
+    // PHR, holding the PSDU length, followed by the PSDU.
+    buffer: [u8; MAX_PSDU_LEN + 1],
+    timestamp: Instant,
+}
+
+impl Packet {
+    /// Create an empty packet.
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; MAX_PSDU_LEN + 1],
+            timestamp: Instant::from_ticks(0),
+        }
+    }
+
+    /// Set the frame contents, without the FCS which is computed by the radio.
+    pub fn set(&mut self, frame: &[u8]) -> Result<(), Error> {
+        if frame.len() + FCS_LEN > MAX_PSDU_LEN {
+            return Err(Error::FrameTooLong);
+        }
+
+        self.buffer[0] = (frame.len() + FCS_LEN) as u8;
+        self.buffer[1..][..frame.len()].copy_from_slice(frame);
+
+        Ok(())
+    }
+
+    /// Link quality indicator of a received frame.
+    pub fn lqi(&self) -> u8 {
+        // The radio replaces the last FCS byte with the LQI.
+        let len = (self.buffer[0] as usize).min(MAX_PSDU_LEN);
+        self.buffer[len]
+    }
+
+    /// When a received frame ended.
+    pub fn timestamp(&self) -> Instant {
+        self.timestamp
+    }
+}
+
+impl Default for Packet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::ops::Deref for Packet {
+    type Target = [u8];
+
+    /// The frame contents, without the FCS.
+    fn deref(&self) -> &[u8] {
+        let len = (self.buffer[0] as usize).clamp(FCS_LEN, MAX_PSDU_LEN);
+        &self.buffer[1..][..len - FCS_LEN]
+    }
+}
+
+/// 802.15.4 radio driver.
+pub struct Radio<'d> {
+    _p: PeripheralRef<'d, peripherals::RADIO>,
+    ack_buf: [u8; 4],
+}
+
+impl<'d> Radio<'d> {
+    /// Create a new 802.15.4 driver.
+    pub fn new(
+        radio: impl Peripheral<P = peripherals::RADIO> + 'd,
+        irq: impl Peripheral<P = interrupt::RADIO> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(radio, irq);
+
+        let r = regs();
+
+        // Power cycle the radio to get it into a known state.
+        r.power.write(|w| unsafe { w.bits(0) });
+        r.power.write(|w| unsafe { w.bits(1) });
+
+        // Ieee802154_250Kbit
+        r.mode.write(|w| unsafe { w.bits(15) });
+        // 8-bit LENGTH field, 32-bit zero preamble, LENGTH includes the CRC.
+        r.pcnf0.write(|w| unsafe { w.bits(8 | 2 << 24 | 1 << 26) });
+        r.pcnf1.write(|w| unsafe { w.bits(MAX_PSDU_LEN as u32) });
+        // 16-bit CRC over the PSDU.
+        r.crccnf.write(|w| unsafe { w.bits(2 | 2 << 8) });
+        r.crcpoly.write(|w| unsafe { w.bits(0x11021) });
+        r.crcinit.write(|w| unsafe { w.bits(0) });
+        r.sfd.write(|w| unsafe { w.bits(0xA7) });
+
+        PAN_ID.store(config.pan_id as u32, Ordering::Relaxed);
+        SHORT_ADDRESS.store(config.short_address as u32, Ordering::Relaxed);
+        EXTENDED_ADDRESS_LO.store(config.extended_address as u32, Ordering::Relaxed);
+        EXTENDED_ADDRESS_HI.store((config.extended_address >> 32) as u32, Ordering::Relaxed);
+        PROMISCUOUS.store(config.promiscuous, Ordering::Relaxed);
+        PHASE.store(PHASE_IDLE, Ordering::Relaxed);
+
+        r.shorts.reset();
+        r.events_disabled.reset();
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        r.intenset.write(|w| unsafe { w.bits(INT_DISABLED) });
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        let mut this = Self {
+            _p: radio,
+            // Immediate acknowledgement: PHR, frame control, sequence number.
+            ack_buf: [3 + FCS_LEN as u8, FCF_FRAME_TYPE_ACK as u8, 0, 0],
+        };
+        this.set_channel(config.channel);
+        this.set_tx_power(config.tx_power);
+        this.set_cca(config.cca);
+        this
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = regs();
+
+        if r.events_disabled.read().bits() == 0 {
+            return;
+        }
+        r.events_disabled.reset();
+
+        let done = |result| {
+            RESULT.store(result, Ordering::Relaxed);
+            PHASE.store(PHASE_DONE, Ordering::Relaxed);
+            WAKER.wake();
+        };
+
+        match PHASE.load(Ordering::Relaxed) {
+            PHASE_RX => {
+                if r.crcstatus.read().bits() & 1 == 0 {
+                    r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                    return;
+                }
+
+                let rx = unsafe { &*(RX_PTR.load(Ordering::Relaxed) as *const [u8; MAX_PSDU_LEN + 1]) };
+                let for_us = frame_is_for_us(rx);
+                if !for_us && !PROMISCUOUS.load(Ordering::Relaxed) {
+                    r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                    return;
+                }
+
+                critical_section::with(|cs| TIMESTAMP.borrow(cs).set(Instant::now().as_ticks()));
+
+                let fcf = u16::from_le_bytes([rx[1], rx[2]]);
+                if for_us && fcf & FCF_ACK_REQUEST != 0 && !is_broadcast(rx) {
+                    let ack = ACK_PTR.load(Ordering::Relaxed) as *mut u8;
+                    unsafe { *ack.add(3) = rx[3] };
+                    r.packetptr.write(|w| unsafe { w.bits(ack as u32) });
+                    r.shorts
+                        .write(|w| unsafe { w.bits(SHORTS_TXREADY_START | SHORTS_PHYEND_DISABLE) });
+                    r.tasks_txen.write(|w| unsafe { w.bits(1) });
+                    PHASE.store(PHASE_RX_ACK, Ordering::Relaxed);
+                } else {
+                    done(RESULT_OK);
+                }
+            }
+            PHASE_RX_ACK => done(RESULT_OK),
+            PHASE_TX => {
+                if r.events_ccabusy.read().bits() != 0 {
+                    r.events_ccabusy.reset();
+                    return done(RESULT_BUSY);
+                }
+
+                let tx = unsafe { &*(r.packetptr.read().bits() as *const [u8; 4]) };
+                let fcf = u16::from_le_bytes([tx[1], tx[2]]);
+                if fcf & FCF_ACK_REQUEST != 0 {
+                    TX_SEQ.store(tx[3], Ordering::Relaxed);
+                    r.packetptr.write(|w| unsafe { w.bits(RX_PTR.load(Ordering::Relaxed)) });
+                    r.shorts
+                        .write(|w| unsafe { w.bits(SHORTS_RXREADY_START | SHORTS_PHYEND_DISABLE) });
+                    r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                    PHASE.store(PHASE_TX_ACK, Ordering::Relaxed);
+                } else {
+                    done(RESULT_OK);
+                }
+            }
+            PHASE_TX_ACK => {
+                let rx = unsafe { &*(RX_PTR.load(Ordering::Relaxed) as *const [u8; 4]) };
+                let fcf = u16::from_le_bytes([rx[1], rx[2]]);
+                if r.crcstatus.read().bits() & 1 != 0
+                    && fcf & FCF_FRAME_TYPE_MASK == FCF_FRAME_TYPE_ACK
+                    && rx[3] == TX_SEQ.load(Ordering::Relaxed)
+                {
+                    done(RESULT_OK);
+                } else {
+                    // Not our acknowledgement, keep listening until the timeout.
+                    r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Change the channel, from 11 to 26.
+    pub fn set_channel(&mut self, channel: u8) {
+        assert!((11..=26).contains(&channel));
+        // Channel 11 is at 2405 MHz, channels are 5 MHz apart.
+        let frequency = 5 + 5 * (channel as u32 - 11);
+        regs().frequency.write(|w| unsafe { w.bits(frequency) });
+    }
+
+    /// Change the transmit power.
+    pub fn set_tx_power(&mut self, power: TxPower) {
+        regs().txpower.write(|w| unsafe { w.bits(power as u8 as u32) });
+    }
+
+    /// Change the clear channel assessment mode.
+    pub fn set_cca(&mut self, cca: Cca) {
+        let (mode, ed_threshold) = match cca {
+            Cca::EnergyDetection { ed_threshold } => (0, ed_threshold),
+            Cca::CarrierSense => (1, 0),
+            Cca::CarrierOrEnergyDetection { ed_threshold } => (3, ed_threshold),
+        };
+        // The correlator threshold and count are the recommended values from the product specification.
+        regs()
+            .ccactrl
+            .write(|w| unsafe { w.bits(mode | (ed_threshold as u32) << 8 | 0x14 << 16 | 0x02 << 24) });
+    }
+
+    /// Receive a frame addressed to this device, or any frame in promiscuous mode.
+    ///
+    /// Frames requesting it are acknowledged before this returns.
+    pub async fn receive(&mut self, packet: &mut Packet) {
+        let r = regs();
+
+        r.packetptr.write(|w| unsafe { w.bits(packet.buffer.as_ptr() as u32) });
+        r.shorts
+            .write(|w| unsafe { w.bits(SHORTS_RXREADY_START | SHORTS_PHYEND_DISABLE) });
+        RX_PTR.store(packet.buffer.as_mut_ptr() as u32, Ordering::Relaxed);
+        ACK_PTR.store(self.ack_buf.as_mut_ptr() as u32, Ordering::Relaxed);
+        PHASE.store(PHASE_RX, Ordering::Relaxed);
+
+        let on_drop = OnDrop::new(abort);
+
+        compiler_fence(Ordering::SeqCst);
+        r.events_disabled.reset();
+        r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+
+        wait_done().await;
+
+        abort();
+        on_drop.defuse();
+        compiler_fence(Ordering::SeqCst);
+
+        packet.timestamp = Instant::from_ticks(critical_section::with(|cs| TIMESTAMP.borrow(cs).get()));
+    }
+
+    /// Transmit a frame, after a clear channel assessment.
+    ///
+    /// If the frame requests an acknowledgement, this waits for it. The contents of `packet` are
+    /// overwritten by the acknowledgement.
+    pub async fn transmit(&mut self, packet: &mut Packet) -> Result<(), Error> {
+        let r = regs();
+
+        let ack_requested = packet.len() >= 2 && u16::from_le_bytes([packet[0], packet[1]]) & FCF_ACK_REQUEST != 0;
+
+        r.packetptr.write(|w| unsafe { w.bits(packet.buffer.as_ptr() as u32) });
+        r.shorts.write(|w| unsafe {
+            w.bits(
+                SHORTS_RXREADY_CCASTART
+                    | SHORTS_CCAIDLE_TXEN
+                    | SHORTS_CCABUSY_DISABLE
+                    | SHORTS_TXREADY_START
+                    | SHORTS_PHYEND_DISABLE,
+            )
+        });
+        RX_PTR.store(packet.buffer.as_mut_ptr() as u32, Ordering::Relaxed);
+        PHASE.store(PHASE_TX, Ordering::Relaxed);
+
+        let on_drop = OnDrop::new(abort);
+
+        compiler_fence(Ordering::SeqCst);
+        r.events_ccabusy.reset();
+        r.events_disabled.reset();
+        // The CCA is done in receive mode, the radio switches to transmit mode if the channel is clear.
+        r.tasks_rxen.write(|w| unsafe { w.bits(1) });
+
+        let result = if ack_requested {
+            // The timeout starts before the transmission, account for the longest frame.
+            let frame_time = Duration::from_micros(32 * (MAX_PSDU_LEN as u64 + 6));
+            with_timeout(frame_time + ACK_TIMEOUT, wait_done()).await.is_ok()
+        } else {
+            wait_done().await;
+            true
+        };
+
+        abort();
+        on_drop.defuse();
+        compiler_fence(Ordering::SeqCst);
+
+        match (result, RESULT.load(Ordering::Relaxed)) {
+            (true, RESULT_OK) => Ok(()),
+            (true, _) => Err(Error::ChannelBusy),
+            (false, _) => Err(Error::NoAck),
+        }
+    }
+}
+
+impl<'d> Drop for Radio<'d> {
+    fn drop(&mut self) {
+        abort();
+        regs().intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        regs().power.write(|w| unsafe { w.bits(0) });
+    }
+}
+
+async fn wait_done() {
+    poll_fn(|cx| {
+        WAKER.register(cx.waker());
+
+        if PHASE.load(Ordering::Relaxed) == PHASE_DONE {
+            return Poll::Ready(());
+        }
+
+        Poll::Pending
+    })
+    .await
+}
+
+fn abort() {
+    PHASE.store(PHASE_IDLE, Ordering::Relaxed);
+    disable();
+}
+
+/// Destination PAN ID and address of a frame, as `(pan_id, address)`. The address is the raw
+/// little endian value, either 2 or 8 bytes.
+fn destination(rx: &[u8; MAX_PSDU_LEN + 1]) -> Option<(u16, &[u8])> {
+    let len = rx[0] as usize;
+    let fcf = u16::from_le_bytes([rx[1], rx[2]]);
+    let addr_len = match (fcf >> 10) & 0b11 {
+        ADDR_MODE_SHORT => 2,
+        ADDR_MODE_EXTENDED => 8,
+        _ => return None,
+    };
+    // Frame control, sequence number, PAN ID, address, FCS.
+    if len < 3 + 2 + addr_len + FCS_LEN {
+        return None;
+    }
+    let pan_id = u16::from_le_bytes([rx[4], rx[5]]);
+    Some((pan_id, &rx[6..][..addr_len]))
+}
+
+fn is_broadcast(rx: &[u8; MAX_PSDU_LEN + 1]) -> bool {
+    matches!(destination(rx), Some((_, [0xFF, 0xFF])))
+}
+
+fn frame_is_for_us(rx: &[u8; MAX_PSDU_LEN + 1]) -> bool {
+    let fcf = u16::from_le_bytes([rx[1], rx[2]]);
+    let (pan_id, address) = match destination(rx) {
+        Some(dst) => dst,
+        // Frames without a destination, such as beacons, are for everyone.
+        None => return fcf & FCF_FRAME_TYPE_MASK != FCF_FRAME_TYPE_ACK,
+    };
+
+    let our_pan_id = PAN_ID.load(Ordering::Relaxed) as u16;
+    if pan_id != 0xFFFF && pan_id != our_pan_id {
+        return false;
+    }
+
+    match address {
+        [0xFF, 0xFF] => true,
+        [a, b] => u16::from_le_bytes([*a, *b]) as u32 == SHORT_ADDRESS.load(Ordering::Relaxed),
+        _ => {
+            let lo = EXTENDED_ADDRESS_LO.load(Ordering::Relaxed).to_le_bytes();
+            let hi = EXTENDED_ADDRESS_HI.load(Ordering::Relaxed).to_le_bytes();
+            address[..4] == lo && address[4..] == hi
+        }
+    }
+}
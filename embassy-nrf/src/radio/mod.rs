@@ -0,0 +1,64 @@
+//! 2.4 GHz radio (RADIO) interface.
+//!
+//! The radio peripheral is shared by several protocol drivers, each of which takes ownership of
+//! the `RADIO` peripheral and its interrupt while it is in use.
+//!
+//! The radio needs the high frequency crystal oscillator to be running, so make sure to set
+//! [`HfclkSource::ExternalXtal`](crate::config::HfclkSource::ExternalXtal) when initializing the HAL.
+
+pub mod ble;
+pub mod esb;
+#[cfg(any(
+    feature = "nrf52811",
+    feature = "nrf52820",
+    feature = "nrf52833",
+    feature = "nrf52840",
+    feature = "_nrf5340-net"
+))]
+pub mod ieee802154;
+
+use crate::pac;
+
+/// Transmit power.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TxPower {
+    /// +4 dBm
+    Pos4dBm = 0x04,
+    /// +3 dBm
+    Pos3dBm = 0x03,
+    /// 0 dBm
+    ZerodBm = 0x00,
+    /// -4 dBm
+    Neg4dBm = 0xFC,
+    /// -8 dBm
+    Neg8dBm = 0xF8,
+    /// -12 dBm
+    Neg12dBm = 0xF4,
+    /// -16 dBm
+    Neg16dBm = 0xF0,
+    /// -20 dBm
+    Neg20dBm = 0xEC,
+    /// -40 dBm
+    Neg40dBm = 0xD8,
+}
+
+pub(crate) const SHORTS_READY_START: u32 = 1 << 0;
+pub(crate) const SHORTS_END_DISABLE: u32 = 1 << 1;
+pub(crate) const SHORTS_DISABLED_RXEN: u32 = 1 << 3;
+
+pub(crate) const INT_DISABLED: u32 = 1 << 4;
+
+pub(crate) fn regs() -> &'static pac::radio::RegisterBlock {
+    unsafe { &*pac::RADIO::ptr() }
+}
+
+/// Stop whatever the radio is doing, and wait for it to be disabled.
+pub(crate) fn disable() {
+    let r = regs();
+
+    r.shorts.reset();
+    r.tasks_disable.write(|w| unsafe { w.bits(1) });
+    while r.state.read().bits() != 0 {}
+    r.events_disabled.reset();
+}
@@ -0,0 +1,299 @@
+//! Real Time Counter (RTC) interface.
+//!
+//! The RTC is a 24-bit low power counter clocked from the 32.768 kHz LFCLK, with a 12-bit
+//! prescaler. It keeps running in System ON sleep at a fraction of the power of a TIMER, which
+//! makes it a good fit for low-power periodic jobs that don't need the global embassy-time tick.
+//!
+//! The RTC used by the time driver (RTC1 with the `time-driver-rtc1` feature) is not available.
+
+#![macro_use]
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::ppi::{Event, Task};
+use crate::{pac, Peripheral};
+
+/// Frequency of the clock feeding the RTC, before the prescaler.
+pub const LFCLK_HZ: u32 = 32768;
+
+/// Maximum value of the counter, and of the CC registers.
+pub const COUNTER_MAX: u32 = 0xFF_FFFF;
+
+const INT_TICK: u32 = 1 << 0;
+const INT_OVRFLW: u32 = 1 << 1;
+const fn int_compare(n: usize) -> u32 {
+    1 << (16 + n)
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Instance {
+        /// The number of CC registers this instance has.
+        const CCS: usize;
+        fn regs() -> &'static pac::rtc0::RegisterBlock;
+        /// Storage for the wakers. `0..CCS` are for the CC registers, `CCS` for the tick
+        /// event and `CCS + 1` for the overflow event.
+        fn waker(n: usize) -> &'static AtomicWaker;
+    }
+}
+
+/// RTC peripheral instance.
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static + Send {
+    /// Interrupt for this peripheral.
+    type Interrupt: Interrupt;
+}
+
+macro_rules! impl_rtc {
+    ($type:ident, $pac_type:ident, $irq:ident, $ccs:literal) => {
+        impl crate::rtc::sealed::Instance for peripherals::$type {
+            const CCS: usize = $ccs;
+            fn regs() -> &'static pac::rtc0::RegisterBlock {
+                unsafe { &*(pac::$pac_type::ptr() as *const pac::rtc0::RegisterBlock) }
+            }
+            fn waker(n: usize) -> &'static ::embassy_sync::waitqueue::AtomicWaker {
+                use ::embassy_sync::waitqueue::AtomicWaker;
+                const NEW_AW: AtomicWaker = AtomicWaker::new();
+                static WAKERS: [AtomicWaker; $ccs + 2] = [NEW_AW; $ccs + 2];
+                &WAKERS[n]
+            }
+        }
+        impl crate::rtc::Instance for peripherals::$type {
+            type Interrupt = crate::interrupt::$irq;
+        }
+    };
+}
+
+/// RTC driver.
+pub struct Rtc<'d, T: Instance> {
+    _p: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: Instance> Rtc<'d, T> {
+    /// Create a new RTC driver.
+    ///
+    /// The counter increments at `32768 / (prescaler + 1)` Hz, so for example a prescaler of
+    /// 4095 gives an 8 Hz tick. The RTC is created stopped, call [`start`](Self::start) to
+    /// start counting.
+    ///
+    /// Panics if `prescaler` is larger than 4095.
+    pub fn new(rtc: impl Peripheral<P = T> + 'd, irq: impl Peripheral<P = T::Interrupt> + 'd, prescaler: u16) -> Self {
+        into_ref!(rtc, irq);
+
+        let this = Self { _p: rtc };
+
+        let r = T::regs();
+        this.stop();
+        this.clear();
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        r.evtenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        this.set_prescaler(prescaler);
+
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        this
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        let r = T::regs();
+        let enabled = r.intenset.read().bits();
+
+        for n in 0..T::CCS {
+            if enabled & int_compare(n) != 0 && r.events_compare[n].read().bits() != 0 {
+                r.intenclr.write(|w| unsafe { w.bits(int_compare(n)) });
+                T::waker(n).wake();
+            }
+        }
+        if enabled & INT_TICK != 0 && r.events_tick.read().bits() != 0 {
+            r.intenclr.write(|w| unsafe { w.bits(INT_TICK) });
+            T::waker(T::CCS).wake();
+        }
+        if enabled & INT_OVRFLW != 0 && r.events_ovrflw.read().bits() != 0 {
+            r.intenclr.write(|w| unsafe { w.bits(INT_OVRFLW) });
+            T::waker(T::CCS + 1).wake();
+        }
+    }
+
+    /// Start the counter.
+    pub fn start(&self) {
+        T::regs().tasks_start.write(|w| unsafe { w.bits(1) })
+    }
+
+    /// Stop the counter.
+    pub fn stop(&self) {
+        T::regs().tasks_stop.write(|w| unsafe { w.bits(1) })
+    }
+
+    /// Reset the counter to 0.
+    pub fn clear(&self) {
+        T::regs().tasks_clear.write(|w| unsafe { w.bits(1) })
+    }
+
+    /// Change the prescaler.
+    ///
+    /// This stops the counter, as the prescaler can only be changed while it is stopped.
+    ///
+    /// Panics if `prescaler` is larger than 4095.
+    pub fn set_prescaler(&self, prescaler: u16) {
+        assert!(prescaler <= 0xFFF);
+        self.stop();
+        T::regs().prescaler.write(|w| unsafe { w.prescaler().bits(prescaler) })
+    }
+
+    /// Current value of the counter.
+    pub fn counter(&self) -> u32 {
+        T::regs().counter.read().bits()
+    }
+
+    /// Wait for the next tick, which happens every time the counter increments.
+    pub async fn wait_tick(&mut self) {
+        // The event fires whether or not someone is waiting, so drop any stale one.
+        T::regs().events_tick.reset();
+        wait_event::<T>(INT_TICK, T::CCS, || {
+            let event = &T::regs().events_tick;
+            let fired = event.read().bits() != 0;
+            event.reset();
+            fired
+        })
+        .await
+    }
+
+    /// Wait for the counter to overflow from [`COUNTER_MAX`] to 0.
+    pub async fn wait_overflow(&mut self) {
+        // The event fires whether or not someone is waiting, so drop any stale one.
+        T::regs().events_ovrflw.reset();
+        wait_event::<T>(INT_OVRFLW, T::CCS + 1, || {
+            let event = &T::regs().events_ovrflw;
+            let fired = event.read().bits() != 0;
+            event.reset();
+            fired
+        })
+        .await
+    }
+
+    /// Returns the START task, for use with PPI.
+    pub fn task_start(&self) -> Task {
+        Task::from_reg(&T::regs().tasks_start)
+    }
+
+    /// Returns the STOP task, for use with PPI.
+    pub fn task_stop(&self) -> Task {
+        Task::from_reg(&T::regs().tasks_stop)
+    }
+
+    /// Returns the CLEAR task, for use with PPI.
+    pub fn task_clear(&self) -> Task {
+        Task::from_reg(&T::regs().tasks_clear)
+    }
+
+    /// Returns the TICK event, for use with PPI.
+    ///
+    /// This also enables routing the event to PPI, which increases power consumption.
+    pub fn event_tick(&self) -> Event {
+        T::regs().evtenset.write(|w| unsafe { w.bits(INT_TICK) });
+        Event::from_reg(&T::regs().events_tick)
+    }
+
+    /// Returns the OVRFLW event, for use with PPI.
+    ///
+    /// This also enables routing the event to PPI.
+    pub fn event_overflow(&self) -> Event {
+        T::regs().evtenset.write(|w| unsafe { w.bits(INT_OVRFLW) });
+        Event::from_reg(&T::regs().events_ovrflw)
+    }
+
+    /// Returns this RTC's `n`th CC register.
+    ///
+    /// # Panics
+    /// Panics if `n` >= the number of CC registers this RTC has.
+    pub fn cc(&mut self, n: usize) -> Cc<'_, T> {
+        assert!(n < T::CCS);
+        Cc { n, _p: PhantomData }
+    }
+}
+
+impl<'d, T: Instance> Drop for Rtc<'d, T> {
+    fn drop(&mut self) {
+        let r = T::regs();
+        self.stop();
+        r.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        r.evtenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+    }
+}
+
+/// A Capture/Compare (CC) register of the RTC.
+///
+/// The RTC fires the register's COMPARE event when its counter reaches the value stored in it.
+pub struct Cc<'d, T: Instance> {
+    n: usize,
+    _p: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> Cc<'d, T> {
+    /// Get the current value stored in the register.
+    pub fn read(&self) -> u32 {
+        T::regs().cc[self.n].read().bits()
+    }
+
+    /// Set the value stored in the register. Only the lower 24 bits are used.
+    ///
+    /// Setting it to `counter + 1` or less may not fire the COMPARE event, as the counter
+    /// can increment before the value is in place.
+    pub fn write(&self, value: u32) {
+        T::regs().cc[self.n].write(|w| unsafe { w.bits(value & COUNTER_MAX) })
+    }
+
+    /// Wait until the counter reaches the value stored in this register.
+    pub async fn wait(&mut self) {
+        let n = self.n;
+        wait_event::<T>(int_compare(n), n, || {
+            let event = &T::regs().events_compare[n];
+            let fired = event.read().bits() != 0;
+            event.reset();
+            fired
+        })
+        .await
+    }
+
+    /// Returns this CC register's COMPARE event, for use with PPI.
+    ///
+    /// This also enables routing the event to PPI.
+    pub fn event_compare(&self) -> Event {
+        T::regs().evtenset.write(|w| unsafe { w.bits(int_compare(self.n)) });
+        Event::from_reg(&T::regs().events_compare[self.n])
+    }
+}
+
+/// Wait for an event, with `take_event` checking whether it fired and resetting it.
+async fn wait_event<T: Instance>(int: u32, waker: usize, take_event: impl Fn() -> bool) {
+    let r = T::regs();
+
+    r.intenset.write(|w| unsafe { w.bits(int) });
+
+    let on_drop = OnDrop::new(|| {
+        r.intenclr.write(|w| unsafe { w.bits(int) });
+    });
+
+    poll_fn(|cx| {
+        T::waker(waker).register(cx.waker());
+
+        if take_event() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+
+    // The interrupt was already disabled in the interrupt handler, so there's no need to disable it again.
+    on_drop.defuse();
+}
@@ -18,7 +18,7 @@ use self::sealed::Input as _;
 use crate::interrupt::InterruptExt;
 use crate::ppi::{ConfigurableChannel, Event, Ppi, Task};
 use crate::timer::{Frequency, Instance as TimerInstance, Timer};
-use crate::{interrupt, pac, peripherals, Peripheral};
+use crate::{gpio, interrupt, pac, peripherals, Peripheral};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -28,6 +28,8 @@ pub enum Error {}
 /// One-shot and continuous SAADC.
 pub struct Saadc<'d, const N: usize> {
     _p: PeripheralRef<'d, peripherals::SAADC>,
+    /// GPIO pins of the positive and negative inputs of the channels, released on drop.
+    pins: [(u32, u32); N],
 }
 
 static WAKER: AtomicWaker = AtomicWaker::new();
@@ -66,6 +68,13 @@ pub struct ChannelConfig<'d> {
     pub resistor: Resistor,
     /// Acquisition time in microseconds.
     pub time: Time,
+    /// When oversampling, take all samples of this channel in a single burst after each `SAMPLE`
+    /// task. Without burst, every `SAMPLE` task takes a single sample, and a result is only
+    /// written to RAM once 2^`oversample` samples have been averaged.
+    ///
+    /// Burst must be enabled when oversampling more than one channel, [`Saadc::new`] panics
+    /// otherwise.
+    pub burst: bool,
     /// Positive channel to sample
     p_channel: PeripheralRef<'d, AnyInput>,
     /// An optional negative channel to sample
@@ -81,6 +90,7 @@ impl<'d> ChannelConfig<'d> {
             gain: Gain::GAIN1_6,
             resistor: Resistor::BYPASS,
             time: Time::_10US,
+            burst: true,
             p_channel: input.map_into(),
             n_channel: None,
         }
@@ -96,6 +106,7 @@ impl<'d> ChannelConfig<'d> {
             gain: Gain::GAIN1_6,
             resistor: Resistor::BYPASS,
             time: Time::_10US,
+            burst: true,
             p_channel: p_input.map_into(),
             n_channel: Some(n_input.map_into()),
         }
@@ -113,6 +124,7 @@ pub enum SamplerState {
 }
 
 impl<'d, const N: usize> Saadc<'d, N> {
+    #[track_caller]
     pub fn new(
         saadc: impl Peripheral<P = peripherals::SAADC> + 'd,
         irq: impl Peripheral<P = interrupt::SAADC> + 'd,
@@ -125,11 +137,26 @@ impl<'d, const N: usize> Saadc<'d, N> {
 
         let Config { resolution, oversample } = config;
 
+        // Without burst, the averaging of the channels would be interleaved.
+        assert!(
+            matches!(oversample, Oversample::BYPASS) || N == 1 || channel_configs.iter().all(|cc| cc.burst),
+            "burst must be enabled on every channel when oversampling more than one channel"
+        );
+
         // Configure channels
         r.enable.write(|w| w.enable().enabled());
         r.resolution.write(|w| w.val().variant(resolution.into()));
         r.oversample.write(|w| w.oversample().variant(oversample.into()));
 
+        let mut pins = [(DISCONNECTED, DISCONNECTED); N];
+        for (pins, cc) in pins.iter_mut().zip(&channel_configs) {
+            let n_pin = cc.n_channel.as_ref().map_or(DISCONNECTED, |n| n.pin_psel_bits());
+            *pins = (cc.p_channel.pin_psel_bits(), n_pin);
+        }
+        for pin in distinct_pins(&pins) {
+            gpio::claim_pin(pin);
+        }
+
         for (i, cc) in channel_configs.iter().enumerate() {
             r.ch[i].pselp.write(|w| w.pselp().variant(cc.p_channel.channel()));
             if let Some(n_channel) = &cc.n_channel {
@@ -148,7 +175,7 @@ impl<'d, const N: usize> Saadc<'d, N> {
                 }
                 w.resp().variant(cc.resistor.into());
                 w.resn().bypass();
-                if !matches!(oversample, Oversample::BYPASS) {
+                if cc.burst && !matches!(oversample, Oversample::BYPASS) {
                     w.burst().enabled();
                 } else {
                     w.burst().disabled();
@@ -164,7 +191,7 @@ impl<'d, const N: usize> Saadc<'d, N> {
         irq.unpend();
         irq.enable();
 
-        Self { _p: saadc }
+        Self { _p: saadc, pins }
     }
 
     fn on_interrupt(_ctx: *mut ()) {
@@ -225,7 +252,7 @@ impl<'d, const N: usize> Saadc<'d, N> {
     /// also cause the sampling to be stopped.
     pub async fn sample(&mut self, buf: &mut [i16; N]) {
         // In case the future is dropped, stop the task and wait for it to end.
-        OnDrop::new(Self::stop_sampling_immediately);
+        let _on_drop = OnDrop::new(Self::stop_sampling_immediately);
 
         let r = Self::regs();
 
@@ -324,6 +351,43 @@ impl<'d, const N: usize> Saadc<'d, N> {
         .await;
     }
 
+    /// Continuous sampling with double buffers, triggered by an arbitrary event.
+    ///
+    /// Each time `event` fires, all channels are sampled once, for example on every tick of an
+    /// RTC compare event so that the high frequency clock and a TIMER don't need to keep running
+    /// between samples. Two PPI channels are used, one to trigger the sampling and one to restart
+    /// the SAADC as soon as a buffer is full.
+    ///
+    /// See [`Self::run_task_sampler`] for how the sampler closure is used.
+    pub async fn run_event_sampler<S, const N0: usize>(
+        &mut self,
+        ppi_ch1: &mut impl ConfigurableChannel,
+        ppi_ch2: &mut impl ConfigurableChannel,
+        event: Event,
+        bufs: &mut [[[i16; N]; N0]; 2],
+        sampler: S,
+    ) where
+        S: FnMut(&[[i16; N]]) -> SamplerState,
+    {
+        let r = Self::regs();
+
+        let mut start_ppi =
+            Ppi::new_one_to_one(ppi_ch1, Event::from_reg(&r.events_end), Task::from_reg(&r.tasks_start));
+        start_ppi.enable();
+
+        let mut sample_ppi = Ppi::new_one_to_one(ppi_ch2, event, Task::from_reg(&r.tasks_sample));
+
+        self.run_sampler(
+            bufs,
+            None,
+            || {
+                sample_ppi.enable();
+            },
+            sampler,
+        )
+        .await;
+    }
+
     async fn run_sampler<I, S, const N0: usize>(
         &mut self,
         bufs: &mut [[[i16; N]; N0]; 2],
@@ -335,7 +399,7 @@ impl<'d, const N: usize> Saadc<'d, N> {
         S: FnMut(&[[i16; N]]) -> SamplerState,
     {
         // In case the future is dropped, stop the task and wait for it to end.
-        OnDrop::new(Self::stop_sampling_immediately);
+        let _on_drop = OnDrop::new(Self::stop_sampling_immediately);
 
         let r = Self::regs();
 
@@ -462,9 +526,24 @@ impl<'d, const N: usize> Drop for Saadc<'d, N> {
     fn drop(&mut self) {
         let r = Self::regs();
         r.enable.write(|w| w.enable().disabled());
+        for pin in distinct_pins(&self.pins) {
+            gpio::release_pin(pin);
+        }
     }
 }
 
+/// PSEL bits of a disconnected pin, for the inputs that aren't GPIO pins.
+const DISCONNECTED: u32 = 1 << 31;
+
+/// Each of the GPIO pins of the channels once, as several channels may sample the same pin.
+fn distinct_pins(pins: &[(u32, u32)]) -> impl Iterator<Item = u32> + '_ {
+    let all = pins.iter().flat_map(|&(p, n)| [p, n]);
+    all.clone()
+        .enumerate()
+        .filter(move |&(i, pin)| !all.clone().take(i).any(|other| other == pin))
+        .map(|(_, pin)| pin)
+}
+
 impl From<Gain> for GAIN_A {
     fn from(gain: Gain) -> Self {
         match gain {
@@ -647,6 +726,8 @@ pub(crate) mod sealed {
 
     pub trait Input {
         fn channel(&self) -> InputChannel;
+        /// PSEL bits of the GPIO pin of the input, disconnected for the internal inputs.
+        fn pin_psel_bits(&self) -> u32;
     }
 }
 
@@ -655,12 +736,14 @@ pub trait Input: sealed::Input + Into<AnyInput> + Peripheral<P = Self> + Sized +
     fn degrade_saadc(self) -> AnyInput {
         AnyInput {
             channel: self.channel(),
+            pin_psel_bits: self.pin_psel_bits(),
         }
     }
 }
 
 pub struct AnyInput {
     channel: InputChannel,
+    pin_psel_bits: u32,
 }
 
 impl_peripheral!(AnyInput);
@@ -669,19 +752,28 @@ impl sealed::Input for AnyInput {
     fn channel(&self) -> InputChannel {
         self.channel
     }
+    fn pin_psel_bits(&self) -> u32 {
+        self.pin_psel_bits
+    }
 }
 
 impl Input for AnyInput {}
 
 macro_rules! impl_saadc_input {
     ($pin:ident, $ch:ident) => {
-        impl_saadc_input!(@local, crate::peripherals::$pin, $ch);
+        impl_saadc_input!(@impl, crate::peripherals::$pin, $ch, crate::gpio::Pin::psel_bits);
     };
     (@local, $pin:ty, $ch:ident) => {
+        impl_saadc_input!(@impl, $pin, $ch, |_| 1 << 31);
+    };
+    (@impl, $pin:ty, $ch:ident, $pin_psel_bits:expr) => {
         impl crate::saadc::sealed::Input for $pin {
             fn channel(&self) -> crate::saadc::InputChannel {
                 crate::saadc::InputChannel::$ch
             }
+            fn pin_psel_bits(&self) -> u32 {
+                ($pin_psel_bits)(self)
+            }
         }
         impl crate::saadc::Input for $pin {}
 
@@ -13,6 +13,8 @@ use crate::chip::FORCE_COPY_BUFFER_SIZE;
 use crate::gpio::sealed::Pin as _;
 use crate::gpio::{self, AnyPin, Pin as GpioPin, PselBits};
 use crate::interrupt::{Interrupt, InterruptExt};
+#[cfg(feature = "nrf52840")]
+use crate::peripherals;
 use crate::util::{slice_in_ram_or, slice_ptr_parts, slice_ptr_parts_mut};
 use crate::{pac, Peripheral};
 
@@ -35,6 +37,9 @@ pub struct Spim<'d, T: Instance> {
 
 #[non_exhaustive]
 pub struct Config {
+    /// SCK frequency.
+    ///
+    /// On the nRF52840, `M16` and `M32` are only supported by SPIM3.
     pub frequency: Frequency,
     pub mode: Mode,
     pub orc: u8,
@@ -51,6 +56,7 @@ impl Default for Config {
 }
 
 impl<'d, T: Instance> Spim<'d, T> {
+    #[track_caller]
     pub fn new(
         spim: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -70,6 +76,7 @@ impl<'d, T: Instance> Spim<'d, T> {
         )
     }
 
+    #[track_caller]
     pub fn new_txonly(
         spim: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -81,6 +88,7 @@ impl<'d, T: Instance> Spim<'d, T> {
         Self::new_inner(spim, irq, sck.map_into(), None, Some(mosi.map_into()), config)
     }
 
+    #[track_caller]
     pub fn new_rxonly(
         spim: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -92,6 +100,7 @@ impl<'d, T: Instance> Spim<'d, T> {
         Self::new_inner(spim, irq, sck.map_into(), Some(miso.map_into()), None, config)
     }
 
+    #[track_caller]
     fn new_inner(
         spim: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -129,8 +138,11 @@ impl<'d, T: Instance> Spim<'d, T> {
         }
 
         // Select pins.
+        gpio::claim_pin(sck.psel_bits());
         r.psel.sck.write(|w| unsafe { w.bits(sck.psel_bits()) });
+        gpio::claim_pin(mosi.psel_bits());
         r.psel.mosi.write(|w| unsafe { w.bits(mosi.psel_bits()) });
+        gpio::claim_pin(miso.psel_bits());
         r.psel.miso.write(|w| unsafe { w.bits(miso.psel_bits()) });
 
         // Enable SPIM instance.
@@ -167,6 +179,7 @@ impl<'d, T: Instance> Spim<'d, T> {
 
         // Configure frequency.
         let frequency = config.frequency;
+        check_frequency::<T>(frequency);
         r.frequency.write(|w| w.frequency().variant(frequency));
 
         // Set over-read character
@@ -344,6 +357,79 @@ impl<'d, T: Instance> Spim<'d, T> {
     }
 }
 
+/// SPIM3 extended features: hardware D/CX pin control.
+#[cfg(feature = "nrf52840")]
+impl<'d> Spim<'d, peripherals::SPI3> {
+    /// Create a new transmit-only SPIM3 driver with a hardware D/CX (data/command) pin.
+    ///
+    /// This is meant for displays and similar devices that use an extra pin to tell command
+    /// bytes from data bytes. The pin is driven by the peripheral during transfers, see
+    /// [`write_dcx`](Self::write_dcx), so commands and data can be sent in a single transfer.
+    #[track_caller]
+    pub fn new_txonly_with_dcx(
+        spim: impl Peripheral<P = peripherals::SPI3> + 'd,
+        irq: impl Peripheral<P = crate::interrupt::SPIM3> + 'd,
+        sck: impl Peripheral<P = impl GpioPin> + 'd,
+        mosi: impl Peripheral<P = impl GpioPin> + 'd,
+        dcx: impl Peripheral<P = impl GpioPin> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(sck, mosi, dcx);
+
+        let this = Self::new_inner(spim, irq, sck.map_into(), None, Some(mosi.map_into()), config);
+
+        let r = <peripherals::SPI3 as sealed::Instance>::regs();
+        dcx.conf().write(|w| w.dir().output().drive().h0h1());
+        gpio::claim_pin(dcx.psel_bits());
+        r.pseldcx.write(|w| unsafe { w.bits(dcx.psel_bits()) });
+        r.dcxcnt.write(|w| unsafe { w.bits(0) });
+
+        this
+    }
+
+    fn set_dcx_count(&mut self, command_len: usize) {
+        assert!(command_len <= MAX_DCX_COMMAND_LEN);
+        let r = <peripherals::SPI3 as sealed::Instance>::regs();
+        r.dcxcnt.write(|w| unsafe { w.bits(command_len as u32) });
+    }
+
+    /// Sends `data`, with D/CX low for its first `command_len` bytes and high for the rest.
+    ///
+    /// Panics if `command_len` is larger than [`MAX_DCX_COMMAND_LEN`].
+    pub async fn write_dcx(&mut self, command_len: usize, data: &[u8]) -> Result<(), Error> {
+        self.set_dcx_count(command_len);
+        self.write(data).await
+    }
+
+    /// Sends `data`, with D/CX low for its first `command_len` bytes and high for the rest.
+    /// Blocks until the transmission is completed.
+    ///
+    /// Panics if `command_len` is larger than [`MAX_DCX_COMMAND_LEN`].
+    pub fn blocking_write_dcx(&mut self, command_len: usize, data: &[u8]) -> Result<(), Error> {
+        self.set_dcx_count(command_len);
+        self.blocking_write(data)
+    }
+}
+
+/// Maximum number of command bytes at the start of a [`Spim::write_dcx`] transfer.
+#[cfg(feature = "nrf52840")]
+pub const MAX_DCX_COMMAND_LEN: usize = 14;
+
+#[cfg(feature = "nrf52840")]
+fn is_spim3<T: Instance>() -> bool {
+    T::regs() as *const _ as *const () == pac::SPIM3::ptr() as *const ()
+}
+
+/// Panics if the instance doesn't support `frequency`.
+fn check_frequency<T: Instance>(frequency: Frequency) {
+    #[cfg(feature = "nrf52840")]
+    if matches!(frequency, Frequency::M16 | Frequency::M32) {
+        assert!(is_spim3::<T>(), "only SPIM3 supports 16 MHz and 32 MHz");
+    }
+    #[cfg(not(feature = "nrf52840"))]
+    let _ = frequency;
+}
+
 impl<'d, T: Instance> Drop for Spim<'d, T> {
     fn drop(&mut self) {
         trace!("spim drop");
@@ -357,6 +443,10 @@ impl<'d, T: Instance> Drop for Spim<'d, T> {
         gpio::deconfigure_pin(r.psel.sck.read().bits());
         gpio::deconfigure_pin(r.psel.miso.read().bits());
         gpio::deconfigure_pin(r.psel.mosi.read().bits());
+        #[cfg(feature = "nrf52840")]
+        if is_spim3::<T>() {
+            gpio::deconfigure_pin(r.pseldcx.read().bits());
+        }
 
         trace!("spim drop: done");
     }
@@ -555,6 +645,7 @@ impl<'d, T: Instance> SetConfig for Spim<'d, T> {
 
         // Configure frequency.
         let frequency = config.frequency;
+        check_frequency::<T>(frequency);
         r.frequency.write(|w| w.frequency().variant(frequency));
 
         // Set over-read character
@@ -1,4 +1,7 @@
 //! Temperature sensor interface.
+//!
+//! The die temperature is measured in about 36 µs. [`Temp::read`] waits for the `DATARDY` event
+//! instead of spinning, so the executor can sleep in the meantime.
 
 use core::future::poll_fn;
 use core::task::Poll;
@@ -14,36 +17,41 @@ use crate::{interrupt, pac, Peripheral};
 
 /// Integrated temperature sensor.
 pub struct Temp<'d> {
-    _irq: PeripheralRef<'d, interrupt::TEMP>,
+    irq: PeripheralRef<'d, interrupt::TEMP>,
 }
 
 static WAKER: AtomicWaker = AtomicWaker::new();
 
 impl<'d> Temp<'d> {
+    /// Create a new temperature sensor driver.
     pub fn new(_t: impl Peripheral<P = TEMP> + 'd, irq: impl Peripheral<P = interrupt::TEMP> + 'd) -> Self {
         into_ref!(_t, irq);
 
         // Enable interrupt that signals temperature values
         irq.disable();
+        Self::regs().events_datardy.reset();
         irq.set_handler(|_| {
             let t = Self::regs();
             t.intenclr.write(|w| w.datardy().clear());
             WAKER.wake();
         });
+        irq.unpend();
         irq.enable();
-        Self { _irq: irq }
+        Self { irq }
     }
 
     /// Perform an asynchronous temperature measurement. The returned future
     /// can be awaited to obtain the measurement.
     ///
+    /// The result is in degrees Celsius, with a resolution of 0.25 °C.
+    ///
     /// If the future is dropped, the measurement is cancelled.
     ///
     /// # Example
     ///
     /// ```no_run
     /// let mut t = Temp::new(p.TEMP, interrupt::take!(TEMP));
-    /// let v: u16 = t.read().await.to_num::<u16>();
+    /// let v: i16 = t.read().await.to_num::<i16>();
     /// ```
     pub async fn read(&mut self) -> I30F2 {
         // In case the future is dropped, stop the task and reset events.
@@ -76,3 +84,13 @@ impl<'d> Temp<'d> {
         unsafe { &*pac::TEMP::ptr() }
     }
 }
+
+impl<'d> Drop for Temp<'d> {
+    fn drop(&mut self) {
+        let t = Self::regs();
+        t.tasks_stop.write(|w| unsafe { w.bits(1) });
+        t.intenclr.write(|w| w.datardy().clear());
+        t.events_datardy.reset();
+        self.irq.disable();
+    }
+}
@@ -69,6 +69,28 @@ pub enum Error {
     Timeout,
 }
 
+/// An operation of a [`Twim::transaction`].
+pub enum Operation<'a> {
+    /// Read data into the buffer.
+    Read(&'a mut [u8]),
+    /// Write data from the buffer.
+    Write(&'a [u8]),
+}
+
+/// Anything that can be used as an [`Operation`] of a transaction.
+trait AsOperation {
+    fn as_operation(&mut self) -> Operation<'_>;
+}
+
+impl<'a> AsOperation for Operation<'a> {
+    fn as_operation(&mut self) -> Operation<'_> {
+        match self {
+            Operation::Read(buffer) => Operation::Read(buffer),
+            Operation::Write(buffer) => Operation::Write(buffer),
+        }
+    }
+}
+
 /// Interface to a TWIM instance using EasyDMA to offload the transmission and reception workload.
 ///
 /// For more details about EasyDMA, consult the module documentation.
@@ -77,6 +99,7 @@ pub struct Twim<'d, T: Instance> {
 }
 
 impl<'d, T: Instance> Twim<'d, T> {
+    #[track_caller]
     pub fn new(
         twim: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -117,7 +140,9 @@ impl<'d, T: Instance> Twim<'d, T> {
         });
 
         // Select pins.
+        gpio::claim_pin(sda.psel_bits());
         r.psel.sda.write(|w| unsafe { w.bits(sda.psel_bits()) });
+        gpio::claim_pin(scl.psel_bits());
         r.psel.scl.write(|w| unsafe { w.bits(scl.psel_bits()) });
 
         // Enable TWIM instance.
@@ -149,6 +174,10 @@ impl<'d, T: Instance> Twim<'d, T> {
             s.end_waker.wake();
             r.intenclr.write(|w| w.error().clear());
         }
+        if r.events_suspended.read().bits() != 0 {
+            s.end_waker.wake();
+            r.intenclr.write(|w| w.suspended().clear());
+        }
     }
 
     /// Set TX buffer, checking that it is in RAM and has suitable length.
@@ -256,7 +285,7 @@ impl<'d, T: Instance> Twim<'d, T> {
         }
     }
 
-    /// Wait for stop or error
+    /// Wait for stop, suspend or error
     fn blocking_wait(&mut self) {
         let r = T::regs();
         loop {
@@ -264,6 +293,10 @@ impl<'d, T: Instance> Twim<'d, T> {
                 r.events_stopped.reset();
                 break;
             }
+            if r.events_suspended.read().bits() != 0 {
+                r.events_suspended.reset();
+                break;
+            }
             if r.events_error.read().bits() != 0 {
                 r.events_error.reset();
                 r.tasks_stop.write(|w| unsafe { w.bits(1) });
@@ -271,7 +304,7 @@ impl<'d, T: Instance> Twim<'d, T> {
         }
     }
 
-    /// Wait for stop or error
+    /// Wait for stop, suspend or error
     #[cfg(feature = "time")]
     fn blocking_wait_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
         let r = T::regs();
@@ -281,6 +314,10 @@ impl<'d, T: Instance> Twim<'d, T> {
                 r.events_stopped.reset();
                 break;
             }
+            if r.events_suspended.read().bits() != 0 {
+                r.events_suspended.reset();
+                break;
+            }
             if r.events_error.read().bits() != 0 {
                 r.events_error.reset();
                 r.tasks_stop.write(|w| unsafe { w.bits(1) });
@@ -294,7 +331,7 @@ impl<'d, T: Instance> Twim<'d, T> {
         Ok(())
     }
 
-    /// Wait for stop or error
+    /// Wait for stop, suspend or error
     fn async_wait(&mut self) -> impl Future<Output = ()> {
         poll_fn(move |cx| {
             let r = T::regs();
@@ -306,6 +343,11 @@ impl<'d, T: Instance> Twim<'d, T> {
 
                 return Poll::Ready(());
             }
+            if r.events_suspended.read().bits() != 0 {
+                r.events_suspended.reset();
+
+                return Poll::Ready(());
+            }
 
             // stop if an error occured
             if r.events_error.read().bits() != 0 {
@@ -458,6 +500,209 @@ impl<'d, T: Instance> Twim<'d, T> {
         }
     }
 
+    /// Set up and start the first operations of a transaction, returning how many were started.
+    ///
+    /// A read and the writes following it, or writes and a final read, are chained with the
+    /// LASTTX/LASTRX shortcuts. Unless they are the last operations, the bus is then suspended
+    /// instead of stopped, so the next call continues the transaction with a repeated start.
+    /// `resume` must be set if the bus is suspended.
+    fn setup_operations(
+        &mut self,
+        address: u8,
+        operations: &mut [impl AsOperation],
+        resume: bool,
+        tx_ram_buf: &mut [u8; FORCE_COPY_BUFFER_SIZE],
+        inten: bool,
+    ) -> Result<usize, Error> {
+        let r = T::regs();
+
+        compiler_fence(SeqCst);
+
+        r.address.write(|w| unsafe { w.address().bits(address) });
+
+        // Clear events
+        r.events_stopped.reset();
+        r.events_suspended.reset();
+        r.events_error.reset();
+        self.clear_errorsrc();
+
+        if inten {
+            r.intenset.write(|w| w.stopped().set().suspended().set().error().set());
+        } else {
+            r.intenclr
+                .write(|w| w.stopped().clear().suspended().clear().error().clear());
+        }
+
+        let total = operations.len();
+        let first_is_read = matches!(operations[0].as_operation(), Operation::Read(_));
+        let (count, start_rx) = if first_is_read {
+            let (first, rest) = operations.split_first_mut().unwrap();
+            let rd_buffer = match first.as_operation() {
+                Operation::Read(buffer) => buffer,
+                Operation::Write(_) => unreachable!(),
+            };
+            unsafe { self.set_rx_buffer(rd_buffer)? };
+
+            let (writes, wr_buffer) = gather_writes(rest, tx_ram_buf)?;
+            if writes == 0 {
+                assert!(total == 1, "consecutive read operations are not supported");
+                r.shorts.write(|w| w.lastrx_stop().enabled());
+            } else {
+                unsafe { self.set_tx_buffer(wr_buffer)? };
+                r.shorts.write(|w| {
+                    w.lastrx_starttx().enabled();
+                    if 1 + writes == total {
+                        w.lasttx_stop().enabled();
+                    } else {
+                        w.lasttx_suspend().enabled();
+                    }
+                    w
+                });
+            }
+            (1 + writes, true)
+        } else {
+            let (writes, wr_buffer) = gather_writes(operations, tx_ram_buf)?;
+            unsafe { self.set_tx_buffer(wr_buffer)? };
+
+            if writes + 1 == total {
+                // Only a read is left, do it right away.
+                let rd_buffer = match operations[writes].as_operation() {
+                    Operation::Read(buffer) => buffer,
+                    Operation::Write(_) => unreachable!(),
+                };
+                unsafe { self.set_rx_buffer(rd_buffer)? };
+                r.shorts.write(|w| {
+                    w.lasttx_startrx().enabled();
+                    w.lastrx_stop().enabled();
+                    w
+                });
+                (total, false)
+            } else {
+                r.shorts.write(|w| {
+                    if writes == total {
+                        w.lasttx_stop().enabled();
+                    } else {
+                        w.lasttx_suspend().enabled();
+                    }
+                    w
+                });
+                (writes, false)
+            }
+        };
+
+        if start_rx {
+            r.tasks_startrx.write(|w| unsafe { w.bits(1) });
+        } else {
+            r.tasks_starttx.write(|w| unsafe { w.bits(1) });
+        }
+        if resume {
+            r.tasks_resume.write(|w| unsafe { w.bits(1) });
+        }
+
+        Ok(count)
+    }
+
+    /// Check the result of the operations started by [`setup_operations`](Self::setup_operations).
+    fn check_operations(&self, operations: &mut [impl AsOperation]) -> Result<(), Error> {
+        compiler_fence(SeqCst);
+        self.check_errorsrc()?;
+
+        let (mut tx_len, mut rx_len) = (None, None);
+        for op in operations {
+            match op.as_operation() {
+                Operation::Read(buffer) => *rx_len.get_or_insert(0) += buffer.len(),
+                Operation::Write(buffer) => *tx_len.get_or_insert(0) += buffer.len(),
+            }
+        }
+        if let Some(len) = tx_len {
+            self.check_tx(len)?;
+        }
+        if let Some(len) = rx_len {
+            self.check_rx(len)?;
+        }
+        Ok(())
+    }
+
+    /// Stop a transaction that was suspended because of an error.
+    fn blocking_stop_suspended(&mut self) {
+        let r = T::regs();
+        r.intenclr
+            .write(|w| w.stopped().clear().suspended().clear().error().clear());
+        r.tasks_stop.write(|w| unsafe { w.bits(1) });
+        r.tasks_resume.write(|w| unsafe { w.bits(1) });
+        while r.events_stopped.read().bits() == 0 {}
+        r.events_stopped.reset();
+    }
+
+    /// Panics if the transaction has zero-length operations it can't do.
+    fn check_transaction(operations: &mut [impl AsOperation]) {
+        if operations.len() > 1 {
+            for op in operations {
+                let len = match op.as_operation() {
+                    Operation::Read(buffer) => buffer.len(),
+                    Operation::Write(buffer) => buffer.len(),
+                };
+                assert!(len != 0, "zero-length operations are not supported in transactions");
+            }
+        }
+    }
+
+    fn blocking_transaction_inner(&mut self, address: u8, operations: &mut [impl AsOperation]) -> Result<(), Error> {
+        Self::check_transaction(operations);
+        if let [op] = operations {
+            return match op.as_operation() {
+                Operation::Read(buffer) => self.blocking_read(address, buffer),
+                Operation::Write(buffer) => self.blocking_write(address, buffer),
+            };
+        }
+
+        let mut tx_ram_buf = [0; FORCE_COPY_BUFFER_SIZE];
+        let mut resume = false;
+        let mut operations = operations;
+        while !operations.is_empty() {
+            let count = self.setup_operations(address, operations, resume, &mut tx_ram_buf, false)?;
+            self.blocking_wait();
+            let (done, rest) = core::mem::take(&mut operations).split_at_mut(count);
+            if let Err(e) = self.check_operations(done) {
+                if !rest.is_empty() {
+                    self.blocking_stop_suspended();
+                }
+                return Err(e);
+            }
+            operations = rest;
+            resume = true;
+        }
+        Ok(())
+    }
+
+    async fn transaction_inner(&mut self, address: u8, operations: &mut [impl AsOperation]) -> Result<(), Error> {
+        Self::check_transaction(operations);
+        if let [op] = operations {
+            return match op.as_operation() {
+                Operation::Read(buffer) => self.read(address, buffer).await,
+                Operation::Write(buffer) => self.write(address, buffer).await,
+            };
+        }
+
+        let mut tx_ram_buf = [0; FORCE_COPY_BUFFER_SIZE];
+        let mut resume = false;
+        let mut operations = operations;
+        while !operations.is_empty() {
+            let count = self.setup_operations(address, operations, resume, &mut tx_ram_buf, true)?;
+            self.async_wait().await;
+            let (done, rest) = core::mem::take(&mut operations).split_at_mut(count);
+            if let Err(e) = self.check_operations(done) {
+                if !rest.is_empty() {
+                    self.blocking_stop_suspended();
+                }
+                return Err(e);
+            }
+            operations = rest;
+            resume = true;
+        }
+        Ok(())
+    }
+
     /// Write to an I2C slave.
     ///
     /// The buffer must have a length of at most 255 bytes on the nRF52832
@@ -525,6 +770,21 @@ impl<'d, T: Instance> Twim<'d, T> {
         Ok(())
     }
 
+    /// Execute a transaction of several operations, without a STOP condition between them.
+    ///
+    /// This follows the `transaction` of `embedded-hal` 1.0: a read and a write following each
+    /// other are separated by a repeated start, and consecutive writes are sent back to back,
+    /// so devices such as EEPROMs can be given the memory address and the data in separate
+    /// buffers. Consecutive writes are concatenated into a RAM buffer, like writes from buffers
+    /// not in RAM are copied into it, so their total length is limited to the size of the copy
+    /// buffer used by [`write`](Twim::write).
+    ///
+    /// Panics if two read operations are consecutive, or if there are zero-length operations
+    /// in a transaction with more than one operation.
+    pub fn blocking_transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        self.blocking_transaction_inner(address, operations)
+    }
+
     // ===========================================
 
     /// Write to an I2C slave with timeout.
@@ -665,6 +925,60 @@ impl<'d, T: Instance> Twim<'d, T> {
         self.check_rx(rd_buffer.len())?;
         Ok(())
     }
+
+    /// Execute a transaction of several operations, without a STOP condition between them.
+    ///
+    /// See [`blocking_transaction`](Twim::blocking_transaction).
+    pub async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        self.transaction_inner(address, operations).await
+    }
+}
+
+/// Get the data of the write operations at the start of `operations`, with how many there are.
+///
+/// The hardware can't send separate buffers back to back, so several writes are concatenated
+/// into `ram_buf`. A single write is used in place, unless it has to be copied into RAM.
+fn gather_writes<'a>(
+    operations: &'a mut [impl AsOperation],
+    ram_buf: &'a mut [u8; FORCE_COPY_BUFFER_SIZE],
+) -> Result<(usize, &'a [u8]), Error> {
+    let mut count = 0;
+    while count < operations.len() && matches!(operations[count].as_operation(), Operation::Write(_)) {
+        count += 1;
+    }
+
+    if count == 1 {
+        return match operations[0].as_operation() {
+            Operation::Write(buffer) => Ok((1, copy_to_ram(buffer, ram_buf)?)),
+            Operation::Read(_) => unreachable!(),
+        };
+    }
+
+    let mut len = 0;
+    for op in &mut operations[..count] {
+        if let Operation::Write(buffer) = op.as_operation() {
+            if len + buffer.len() > FORCE_COPY_BUFFER_SIZE {
+                return Err(Error::TxBufferTooLong);
+            }
+            ram_buf[len..][..buffer.len()].copy_from_slice(buffer);
+            len += buffer.len();
+        }
+    }
+    Ok((count, &ram_buf[..len]))
+}
+
+/// Get `buffer` in RAM, copying it into `ram_buf` if needed.
+fn copy_to_ram<'a>(buffer: &'a [u8], ram_buf: &'a mut [u8; FORCE_COPY_BUFFER_SIZE]) -> Result<&'a [u8], Error> {
+    if slice_in_ram(buffer) {
+        Ok(buffer)
+    } else if buffer.len() > FORCE_COPY_BUFFER_SIZE {
+        Err(Error::DMABufferNotInDataMemory)
+    } else {
+        trace!("Copying TWIM tx buffer into RAM for DMA");
+        let ram_buf = &mut ram_buf[..buffer.len()];
+        ram_buf.copy_from_slice(buffer);
+        Ok(ram_buf)
+    }
 }
 
 impl<'a, T: Instance> Drop for Twim<'a, T> {
@@ -789,6 +1103,15 @@ mod eh1 {
         }
     }
 
+    impl<'a> AsOperation for embedded_hal_1::i2c::Operation<'a> {
+        fn as_operation(&mut self) -> Operation<'_> {
+            match self {
+                Self::Read(buffer) => Operation::Read(buffer),
+                Self::Write(buffer) => Operation::Write(buffer),
+            }
+        }
+    }
+
     impl<'d, T: Instance> embedded_hal_1::i2c::ErrorType for Twim<'d, T> {
         type Error = Error;
     }
@@ -822,10 +1145,10 @@ mod eh1 {
 
         fn transaction<'a>(
             &mut self,
-            _address: u8,
-            _operations: &mut [embedded_hal_1::i2c::Operation<'a>],
+            address: u8,
+            operations: &mut [embedded_hal_1::i2c::Operation<'a>],
         ) -> Result<(), Self::Error> {
-            todo!();
+            self.blocking_transaction_inner(address, operations)
         }
 
         fn transaction_iter<'a, O>(&mut self, _address: u8, _operations: O) -> Result<(), Self::Error>
@@ -871,9 +1194,7 @@ mod eha {
             address: u8,
             operations: &'a mut [embedded_hal_async::i2c::Operation<'b>],
         ) -> Self::TransactionFuture<'a, 'b> {
-            let _ = address;
-            let _ = operations;
-            async move { todo!() }
+            self.transaction_inner(address, operations)
         }
     }
 }
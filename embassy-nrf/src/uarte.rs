@@ -80,6 +80,7 @@ pub struct UarteRx<'d, T: Instance> {
 
 impl<'d, T: Instance> Uarte<'d, T> {
     /// Create a new UARTE without hardware flow control
+    #[track_caller]
     pub fn new(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -92,6 +93,7 @@ impl<'d, T: Instance> Uarte<'d, T> {
     }
 
     /// Create a new UARTE with hardware flow control (RTS/CTS)
+    #[track_caller]
     pub fn new_with_rtscts(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -113,6 +115,7 @@ impl<'d, T: Instance> Uarte<'d, T> {
         )
     }
 
+    #[track_caller]
     fn new_inner(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -127,21 +130,25 @@ impl<'d, T: Instance> Uarte<'d, T> {
         let r = T::regs();
 
         rxd.conf().write(|w| w.input().connect().drive().h0h1());
+        gpio::claim_pin(rxd.psel_bits());
         r.psel.rxd.write(|w| unsafe { w.bits(rxd.psel_bits()) });
 
         txd.set_high();
         txd.conf().write(|w| w.dir().output().drive().h0h1());
+        gpio::claim_pin(txd.psel_bits());
         r.psel.txd.write(|w| unsafe { w.bits(txd.psel_bits()) });
 
         if let Some(pin) = &cts {
             pin.conf().write(|w| w.input().connect().drive().h0h1());
         }
+        gpio::claim_pin(cts.psel_bits());
         r.psel.cts.write(|w| unsafe { w.bits(cts.psel_bits()) });
 
         if let Some(pin) = &rts {
             pin.set_high();
             pin.conf().write(|w| w.dir().output().drive().h0h1());
         }
+        gpio::claim_pin(rts.psel_bits());
         r.psel.rts.write(|w| unsafe { w.bits(rts.psel_bits()) });
 
         irq.set_handler(Self::on_interrupt);
@@ -243,6 +250,7 @@ fn configure(r: &RegisterBlock, config: Config, hardware_flow_control: bool) {
 
 impl<'d, T: Instance> UarteTx<'d, T> {
     /// Create a new tx-only UARTE without hardware flow control
+    #[track_caller]
     pub fn new(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -254,6 +262,7 @@ impl<'d, T: Instance> UarteTx<'d, T> {
     }
 
     /// Create a new tx-only UARTE with hardware flow control (RTS/CTS)
+    #[track_caller]
     pub fn new_with_rtscts(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -265,6 +274,7 @@ impl<'d, T: Instance> UarteTx<'d, T> {
         Self::new_inner(uarte, irq, txd.map_into(), Some(cts.map_into()), config)
     }
 
+    #[track_caller]
     fn new_inner(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -278,11 +288,13 @@ impl<'d, T: Instance> UarteTx<'d, T> {
 
         txd.set_high();
         txd.conf().write(|w| w.dir().output().drive().s0s1());
+        gpio::claim_pin(txd.psel_bits());
         r.psel.txd.write(|w| unsafe { w.bits(txd.psel_bits()) });
 
         if let Some(pin) = &cts {
             pin.conf().write(|w| w.input().connect().drive().h0h1());
         }
+        gpio::claim_pin(cts.psel_bits());
         r.psel.cts.write(|w| unsafe { w.bits(cts.psel_bits()) });
 
         r.psel.rxd.write(|w| w.connect().disconnected());
@@ -435,6 +447,7 @@ impl<'a, T: Instance> Drop for UarteTx<'a, T> {
 
 impl<'d, T: Instance> UarteRx<'d, T> {
     /// Create a new rx-only UARTE without hardware flow control
+    #[track_caller]
     pub fn new(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -446,6 +459,7 @@ impl<'d, T: Instance> UarteRx<'d, T> {
     }
 
     /// Create a new rx-only UARTE with hardware flow control (RTS/CTS)
+    #[track_caller]
     pub fn new_with_rtscts(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -457,6 +471,7 @@ impl<'d, T: Instance> UarteRx<'d, T> {
         Self::new_inner(uarte, irq, rxd.map_into(), Some(rts.map_into()), config)
     }
 
+    #[track_caller]
     fn new_inner(
         uarte: impl Peripheral<P = T> + 'd,
         irq: impl Peripheral<P = T::Interrupt> + 'd,
@@ -469,12 +484,14 @@ impl<'d, T: Instance> UarteRx<'d, T> {
         let r = T::regs();
 
         rxd.conf().write(|w| w.input().connect().drive().h0h1());
+        gpio::claim_pin(rxd.psel_bits());
         r.psel.rxd.write(|w| unsafe { w.bits(rxd.psel_bits()) });
 
         if let Some(pin) = &rts {
             pin.set_high();
             pin.conf().write(|w| w.dir().output().drive().h0h1());
         }
+        gpio::claim_pin(rts.psel_bits());
         r.psel.rts.write(|w| unsafe { w.bits(rts.psel_bits()) });
 
         r.psel.txd.write(|w| w.connect().disconnected());
@@ -674,6 +691,7 @@ pub struct UarteWithIdle<'d, U: Instance, T: TimerInstance> {
 
 impl<'d, U: Instance, T: TimerInstance> UarteWithIdle<'d, U, T> {
     /// Create a new UARTE without hardware flow control
+    #[track_caller]
     pub fn new(
         uarte: impl Peripheral<P = U> + 'd,
         timer: impl Peripheral<P = T> + 'd,
@@ -700,6 +718,7 @@ impl<'d, U: Instance, T: TimerInstance> UarteWithIdle<'d, U, T> {
     }
 
     /// Create a new UARTE with hardware flow control (RTS/CTS)
+    #[track_caller]
     pub fn new_with_rtscts(
         uarte: impl Peripheral<P = U> + 'd,
         timer: impl Peripheral<P = T> + 'd,
@@ -727,6 +746,7 @@ impl<'d, U: Instance, T: TimerInstance> UarteWithIdle<'d, U, T> {
         )
     }
 
+    #[track_caller]
     fn new_inner(
         uarte: impl Peripheral<P = U> + 'd,
         timer: impl Peripheral<P = T> + 'd,
@@ -748,13 +768,9 @@ impl<'d, U: Instance, T: TimerInstance> UarteWithIdle<'d, U, T> {
 
         let r = U::regs();
 
-        // BAUDRATE register values are `baudrate * 2^32 / 16000000`
-        // source: https://devzone.nordicsemi.com/f/nordic-q-a/391/uart-baudrate-register-values
-        //
         // We want to stop RX if line is idle for 2 bytes worth of time
         // That is 20 bits (each byte is 1 start bit + 8 data bits + 1 stop bit)
-        // This gives us the amount of 16M ticks for 20 bits.
-        let timeout = 0x8000_0000 / (baudrate as u32 / 40);
+        let timeout = idle_timeout_ticks(baudrate, DEFAULT_IDLE_TIMEOUT_BITS);
 
         timer.set_frequency(Frequency::F16MHz);
         timer.cc(0).write(timeout);
@@ -781,6 +797,7 @@ impl<'d, U: Instance, T: TimerInstance> UarteWithIdle<'d, U, T> {
             rx: UarteRxWithIdle {
                 rx,
                 timer,
+                baudrate,
                 ppi_ch1: ppi_ch1,
                 _ppi_ch2: ppi_ch2,
             },
@@ -810,6 +827,12 @@ impl<'d, U: Instance, T: TimerInstance> UarteWithIdle<'d, U, T> {
         self.tx.blocking_write(buffer)
     }
 
+    /// Set how long the line must be idle for [`read_until_idle`](Self::read_until_idle) to
+    /// return, in bit times at the configured baudrate.
+    pub fn set_idle_timeout(&mut self, bits: u32) {
+        self.rx.set_idle_timeout(bits)
+    }
+
     pub async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
         self.rx.read_until_idle(buffer).await
     }
@@ -819,14 +842,47 @@ impl<'d, U: Instance, T: TimerInstance> UarteWithIdle<'d, U, T> {
     }
 }
 
+/// Default idle gap after which `read_until_idle` returns: 2 bytes worth of time, as each
+/// byte is 1 start bit + 8 data bits + 1 stop bit.
+const DEFAULT_IDLE_TIMEOUT_BITS: u32 = 20;
+
+/// Number of 16 MHz timer ticks in `bits` bit times at `baudrate`.
+fn idle_timeout_ticks(baudrate: Baudrate, bits: u32) -> u32 {
+    // BAUDRATE register values are `baudrate * 2^32 / 16000000`
+    // source: https://devzone.nordicsemi.com/f/nordic-q-a/391/uart-baudrate-register-values
+    //
+    // So the amount of 16M ticks per bit is `2^32 / BAUDRATE`.
+    let ticks = ((bits as u64) << 32) / (baudrate as u32 as u64);
+    ticks.min(u32::MAX as u64) as u32
+}
+
+/// Receiver interface to an UARTE peripheral with idle detection, obtained via
+/// [UarteWithIdle]::split.
+///
+/// An additional timer is restarted via PPI on every received byte. When it expires, because
+/// the line has been idle for the configured gap, PPI stops the reception. This allows
+/// receiving variable-length packets with [`read_until_idle`](Self::read_until_idle).
 pub struct UarteRxWithIdle<'d, U: Instance, T: TimerInstance> {
     rx: UarteRx<'d, U>,
     timer: Timer<'d, T>,
+    baudrate: Baudrate,
     ppi_ch1: Ppi<'d, AnyConfigurableChannel, 1, 2>,
     _ppi_ch2: Ppi<'d, AnyConfigurableChannel, 1, 1>,
 }
 
 impl<'d, U: Instance, T: TimerInstance> UarteRxWithIdle<'d, U, T> {
+    /// Set how long the line must be idle for [`read_until_idle`](Self::read_until_idle) to
+    /// return, in bit times at the configured baudrate.
+    ///
+    /// The default is 20 bit times, which is 2 bytes worth of time. Protocols that specify an
+    /// inter-frame gap, such as Modbus RTU with its 3.5 characters, can set it here.
+    ///
+    /// Panics if `bits` is 0.
+    pub fn set_idle_timeout(&mut self, bits: u32) {
+        assert!(bits != 0);
+        self.timer.cc(0).write(idle_timeout_ticks(self.baudrate, bits));
+    }
+
     pub async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
         self.ppi_ch1.disable();
         self.rx.read(buffer).await
@@ -837,6 +893,11 @@ impl<'d, U: Instance, T: TimerInstance> UarteRxWithIdle<'d, U, T> {
         self.rx.blocking_read(buffer)
     }
 
+    /// Read into `buffer` until it is full or the line is idle for the configured gap
+    /// after receiving at least one byte, returning the number of bytes received.
+    ///
+    /// The idle timer only starts on the first received byte, so this waits as long as needed
+    /// for data to arrive. Wrap it in a timeout to bound the wait.
     pub async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
         if buffer.len() == 0 {
             return Err(Error::BufferZeroLength);
@@ -893,6 +954,7 @@ impl<'d, U: Instance, T: TimerInstance> UarteRxWithIdle<'d, U, T> {
         Ok(n)
     }
 
+    /// Blocking variant of [`read_until_idle`](Self::read_until_idle).
     pub fn blocking_read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
         if buffer.len() == 0 {
             return Err(Error::BufferZeroLength);
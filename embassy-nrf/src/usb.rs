@@ -24,6 +24,9 @@ static EP_IN_WAKERS: [AtomicWaker; 8] = [NEW_AW; 8];
 static EP_OUT_WAKERS: [AtomicWaker; 8] = [NEW_AW; 8];
 static READY_ENDPOINTS: AtomicU32 = AtomicU32::new(0);
 
+/// Index of the only isochronous endpoint, in each direction.
+const ISO_INDEX: usize = 8;
+
 /// There are multiple ways to detect USB power. The behavior
 /// here provides a hook into determining whether it is.
 pub trait UsbSupply {
@@ -44,6 +47,10 @@ pub struct Driver<'d, T: Instance, P: UsbSupply> {
 
 /// Uses the POWER peripheral to detect when power is available
 /// for USB. Unsuitable for usage with the nRF softdevice.
+///
+/// This is the right choice for self-powered devices, which can be plugged into the host
+/// at any point after boot: enumeration starts when VBUS is detected and the USB regulator
+/// is ready, and the peripheral is disabled again when VBUS goes away.
 #[cfg(not(feature = "_nrf5340-app"))]
 pub struct PowerUsb {
     _private: (),
@@ -60,9 +67,15 @@ static POWER_WAKER: AtomicWaker = NEW_AW;
 
 #[cfg(not(feature = "_nrf5340-app"))]
 impl PowerUsb {
+    /// Create a new `PowerUsb`, taking over the POWER interrupt.
     pub fn new(power_irq: impl Interrupt) -> Self {
         let regs = unsafe { &*pac::POWER::ptr() };
 
+        // Drop events from before we were created, the current state is read from USBREGSTATUS.
+        regs.events_usbdetected.reset();
+        regs.events_usbremoved.reset();
+        regs.events_usbpwrrdy.reset();
+
         power_irq.set_handler(Self::on_interrupt);
         power_irq.unpend();
         power_irq.enable();
@@ -211,6 +224,16 @@ impl<'d, T: Instance, P: UsbSupply> Driver<'d, T, P> {
             BUS_WAKER.wake();
         }
 
+        if regs.events_sof.read().bits() != 0 {
+            regs.events_sof.reset();
+
+            // The isochronous endpoint transfers one packet per frame in each direction, so
+            // it's ready for the next one at every start of frame.
+            READY_ENDPOINTS.fetch_or(In::mask(ISO_INDEX) | Out::mask(ISO_INDEX), Ordering::AcqRel);
+            In::waker(ISO_INDEX).wake();
+            Out::waker(ISO_INDEX).wake();
+        }
+
         if regs.events_epdata.read().bits() != 0 {
             regs.events_epdata.reset();
 
@@ -241,6 +264,9 @@ impl<'d, T: Instance, P: UsbSupply + 'd> driver::Driver<'d> for Driver<'d, T, P>
         packet_size: u16,
         interval: u8,
     ) -> Result<Self::EndpointIn, driver::EndpointAllocError> {
+        if ep_type == EndpointType::Isochronous && packet_size > 1023 {
+            return Err(driver::EndpointAllocError);
+        }
         let index = self.alloc_in.allocate(ep_type)?;
         let ep_addr = EndpointAddress::from_parts(index, Direction::In);
         Ok(Endpoint::new(EndpointInfo {
@@ -257,6 +283,9 @@ impl<'d, T: Instance, P: UsbSupply + 'd> driver::Driver<'d> for Driver<'d, T, P>
         packet_size: u16,
         interval: u8,
     ) -> Result<Self::EndpointOut, driver::EndpointAllocError> {
+        if ep_type == EndpointType::Isochronous && packet_size > 1023 {
+            return Err(driver::EndpointAllocError);
+        }
         let index = self.alloc_out.allocate(ep_type)?;
         let ep_addr = EndpointAddress::from_parts(index, Direction::Out);
         Ok(Endpoint::new(EndpointInfo {
@@ -268,11 +297,13 @@ impl<'d, T: Instance, P: UsbSupply + 'd> driver::Driver<'d> for Driver<'d, T, P>
     }
 
     fn start(mut self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        let iso_mask = 1 << ISO_INDEX;
         (
             Bus {
                 _p: unsafe { self._p.clone_unchecked() },
                 power_available: false,
                 usb_supply: self.usb_supply,
+                iso_split: self.alloc_in.used & iso_mask != 0 && self.alloc_out.used & iso_mask != 0,
             },
             ControlPipe {
                 _p: self._p,
@@ -286,6 +317,8 @@ pub struct Bus<'d, T: Instance, P: UsbSupply> {
     _p: PeripheralRef<'d, T>,
     power_available: bool,
     usb_supply: P,
+    /// Both isochronous endpoints are used, so they share the isochronous buffer.
+    iso_split: bool,
 }
 
 impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
@@ -317,6 +350,14 @@ impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
 
             errata::post_enable();
 
+            // The isochronous IN and OUT endpoints get 512 bytes of buffer each if both are
+            // used. The host gets a zero-length packet if no data is ready for the IN endpoint.
+            regs.isosplit.write(|w| match self.iso_split {
+                true => w.split().half_in(),
+                false => w.split().one_dir(),
+            });
+            regs.isoinconfig.write(|w| w.response().zero_data());
+
             unsafe { NVIC::unmask(pac::Interrupt::USBD) };
 
             regs.intenset.write(|w| {
@@ -339,6 +380,12 @@ impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
     fn disable(&mut self) -> Self::DisableFuture<'_> {
         async move {
             let regs = T::regs();
+
+            // Disconnect from the host first, so it sees a clean detach. Then make sure the
+            // peripheral isn't left in low power mode from a suspend, which would prevent it
+            // from becoming ready the next time it is enabled.
+            regs.usbpullup.write(|w| w.connect().disabled());
+            regs.lowpower.write(|w| w.lowpower().force_normal());
             regs.enable.write(|x| x.enable().disabled());
         }
     }
@@ -352,11 +399,15 @@ impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
                 regs.events_usbreset.reset();
                 regs.intenset.write(|w| w.usbreset().set());
 
+                // The host may reset a suspended device instead of resuming it.
+                regs.lowpower.write(|w| w.lowpower().force_normal());
+
                 // Disable all endpoints except EP0
                 regs.epinen.write(|w| unsafe { w.bits(0x01) });
                 regs.epouten.write(|w| unsafe { w.bits(0x01) });
                 READY_ENDPOINTS.store(In::mask(0), Ordering::Release);
-                for i in 1..=7 {
+                regs.intenclr.write(|w| w.sof().clear());
+                for i in 1..=ISO_INDEX {
                     In::waker(i).wake();
                     Out::waker(i).wake();
                 }
@@ -381,6 +432,8 @@ impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
             }
             if r.resume().bit() {
                 regs.eventcause.write(|w| w.resume().set_bit());
+                // Leave low power mode, the peripheral doesn't do it by itself on host resume.
+                regs.lowpower.write(|w| w.lowpower().force_normal());
                 return Poll::Ready(Event::Resume);
             }
             if r.ready().bit() {
@@ -409,27 +462,11 @@ impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
     }
 
     fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
-        let regs = T::regs();
-        unsafe {
-            if ep_addr.index() == 0 {
-                regs.tasks_ep0stall.write(|w| w.tasks_ep0stall().bit(stalled));
-            } else {
-                regs.epstall.write(|w| {
-                    w.ep().bits(ep_addr.index() as u8 & 0b111);
-                    w.io().bit(ep_addr.is_in());
-                    w.stall().bit(stalled)
-                });
-            }
-        }
+        endpoint_set_stalled::<T>(ep_addr, stalled)
     }
 
     fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
-        let regs = T::regs();
-        let i = ep_addr.index();
-        match ep_addr.direction() {
-            Direction::Out => regs.halted.epout[i].read().getstatus().is_halted(),
-            Direction::In => regs.halted.epin[i].read().getstatus().is_halted(),
-        }
+        endpoint_is_stalled::<T>(ep_addr)
     }
 
     fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
@@ -482,7 +519,9 @@ impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
                     // peripheral will NAK all incoming packets) until we write a zero to the SIZE
                     // register (see figure 203 of the 52840 manual). To avoid that we write a 0 to the
                     // SIZE register
-                    regs.size.epout[i].reset();
+                    if i != ISO_INDEX {
+                        regs.size.epout[i].reset();
+                    }
                 } else {
                     READY_ENDPOINTS.fetch_and(!ready_mask, Ordering::AcqRel);
                 }
@@ -490,6 +529,16 @@ impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
                 Out::waker(i).wake();
             }
         }
+
+        // The isochronous endpoint is driven by the start of frame event.
+        if i == ISO_INDEX {
+            let iso_mask = 1 << ISO_INDEX;
+            if regs.epinen.read().bits() & iso_mask != 0 || regs.epouten.read().bits() & iso_mask != 0 {
+                regs.intenset.write(|w| w.sof().set());
+            } else {
+                regs.intenclr.write(|w| w.sof().clear());
+            }
+        }
     }
 
     #[inline]
@@ -532,6 +581,43 @@ impl<'d, T: Instance, P: UsbSupply> driver::Bus for Bus<'d, T, P> {
     }
 }
 
+fn endpoint_set_stalled<T: Instance>(ep_addr: EndpointAddress, stalled: bool) {
+    let regs = T::regs();
+    unsafe {
+        if ep_addr.index() == 0 {
+            regs.tasks_ep0stall.write(|w| w.tasks_ep0stall().bit(stalled));
+        } else {
+            regs.epstall.write(|w| {
+                w.ep().bits(ep_addr.index() as u8 & 0b111);
+                w.io().bit(ep_addr.is_in());
+                w.stall().bit(stalled)
+            });
+            if !stalled {
+                // The endpoint is selected by a first write, which leaves its data toggle as is.
+                regs.dtoggle.write(|w| {
+                    w.ep().bits(ep_addr.index() as u8 & 0b111);
+                    w.io().bit(ep_addr.is_in());
+                    w.value().nop()
+                });
+                regs.dtoggle.write(|w| {
+                    w.ep().bits(ep_addr.index() as u8 & 0b111);
+                    w.io().bit(ep_addr.is_in());
+                    w.value().data0()
+                });
+            }
+        }
+    }
+}
+
+fn endpoint_is_stalled<T: Instance>(ep_addr: EndpointAddress) -> bool {
+    let regs = T::regs();
+    let i = ep_addr.index();
+    match ep_addr.direction() {
+        Direction::Out => regs.halted.epout[i].read().getstatus().is_halted(),
+        Direction::In => regs.halted.epin[i].read().getstatus().is_halted(),
+    }
+}
+
 pub enum Out {}
 pub enum In {}
 
@@ -609,6 +695,18 @@ impl<'d, T: Instance, Dir: EndpointDir> driver::Endpoint for Endpoint<'d, T, Dir
             }
         })
     }
+
+    fn set_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, true)
+    }
+
+    fn clear_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, false)
+    }
+
+    fn is_stalled(&self) -> bool {
+        endpoint_is_stalled::<T>(self.info.addr)
+    }
 }
 
 impl<'d, T: Instance, Dir> Endpoint<'d, T, Dir> {
@@ -711,6 +809,54 @@ unsafe fn write_dma<T: Instance>(i: usize, buf: &[u8]) {
     dma_end();
 }
 
+/// Reads the packet received in the last frame, if any.
+unsafe fn read_iso_dma<T: Instance>(buf: &mut [u8]) -> Option<Result<usize, EndpointError>> {
+    let regs = T::regs();
+
+    let r = regs.size.isoout.read().bits();
+    let size = (r & 0x3ff) as usize;
+    let zero = r & (1 << 16) != 0;
+    if size == 0 && !zero {
+        return None;
+    }
+    if size > buf.len() {
+        return Some(Err(EndpointError::BufferOverflow));
+    }
+    if size == 0 {
+        return Some(Ok(0));
+    }
+
+    regs.isoout.ptr.write(|w| w.bits(buf.as_ptr() as u32));
+    regs.isoout.maxcnt.write(|w| w.bits(size as u32));
+
+    dma_start();
+    regs.events_endisoout.reset();
+    regs.tasks_startisoout.write(|w| w.bits(1));
+    while regs.events_endisoout.read().bits() == 0 {}
+    regs.events_endisoout.reset();
+    dma_end();
+
+    Some(Ok(size))
+}
+
+/// Loads the packet sent in the current frame.
+unsafe fn write_iso_dma<T: Instance>(buf: &[u8]) {
+    let regs = T::regs();
+    // Isochronous packets can be up to 1023 bytes, too big to copy through the stack.
+    assert!(slice_in_ram(buf), "isochronous IN data must be in RAM");
+
+    regs.isoin.ptr.write(|w| w.bits(buf.as_ptr() as u32));
+    regs.isoin.maxcnt.write(|w| w.bits(buf.len() as u32));
+
+    regs.events_endisoin.reset();
+
+    dma_start();
+    regs.tasks_startisoin.write(|w| w.bits(1));
+    while regs.events_endisoin.read().bits() == 0 {}
+    regs.events_endisoin.reset();
+    dma_end();
+}
+
 impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
     type ReadFuture<'a> = impl Future<Output = Result<usize, EndpointError>> + 'a where Self: 'a;
 
@@ -719,6 +865,17 @@ impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
             let i = self.info.addr.index();
             assert!(i != 0);
 
+            if i == ISO_INDEX {
+                loop {
+                    self.wait_data_ready().await.map_err(|_| EndpointError::Disabled)?;
+
+                    // No packet was received in the last frame.
+                    if let Some(res) = unsafe { read_iso_dma::<T>(buf) } {
+                        return res;
+                    }
+                }
+            }
+
             self.wait_data_ready().await.map_err(|_| EndpointError::Disabled)?;
 
             unsafe { read_dma::<T>(i, buf) }
@@ -734,9 +891,17 @@ impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
             let i = self.info.addr.index();
             assert!(i != 0);
 
+            if i == ISO_INDEX && buf.len() > usize::from(self.info.max_packet_size) {
+                return Err(EndpointError::BufferOverflow);
+            }
+
             self.wait_data_ready().await.map_err(|_| EndpointError::Disabled)?;
 
-            unsafe { write_dma::<T>(i, buf) }
+            if i == ISO_INDEX {
+                unsafe { write_iso_dma::<T>(buf) }
+            } else {
+                unsafe { write_dma::<T>(i, buf) }
+            }
 
             Ok(())
         }
@@ -914,7 +1079,7 @@ impl Allocator {
         // Endpoint directions are allocated individually.
 
         let alloc_index = match ep_type {
-            EndpointType::Isochronous => 8,
+            EndpointType::Isochronous => ISO_INDEX,
             EndpointType::Control => return Err(driver::EndpointAllocError),
             EndpointType::Interrupt | EndpointType::Bulk => {
                 // Find rightmost zero bit in 1..=7
@@ -2,12 +2,28 @@
 //!
 //! This HAL implements a basic watchdog timer with 1..=8 handles.
 //! Once the watchdog has been started, it cannot be stopped.
+//!
+//! With the `time` feature, [`Watchdog::monitor`] can be used to find out which handle
+//! is not being pet before the watchdog resets the chip.
+
+#[cfg(feature = "time")]
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(feature = "time")]
+use embassy_time::{Duration, Instant, Timer};
 
 use crate::pac::WDT;
 use crate::peripherals;
 
 const MIN_TICKS: u32 = 15;
 
+/// Time of the last pet of each handle, in embassy-time ticks.
+#[cfg(feature = "time")]
+static LAST_PET: [AtomicU32; 8] = {
+    const NEW: AtomicU32 = AtomicU32::new(0);
+    [NEW; 8]
+};
+
 #[non_exhaustive]
 pub struct Config {
     /// Number of 32768 Hz ticks in each watchdog period.
@@ -36,6 +52,10 @@ impl Default for Config {
 /// An interface to the Watchdog.
 pub struct Watchdog {
     _private: (),
+    #[cfg(feature = "time")]
+    timeout_ticks: u32,
+    #[cfg(feature = "time")]
+    handles: u8,
 }
 
 impl Watchdog {
@@ -85,7 +105,13 @@ impl Watchdog {
             r.tasks_start.write(|w| unsafe { w.bits(1) });
         }
 
-        let this = Self { _private: () };
+        let this = Self {
+            _private: (),
+            #[cfg(feature = "time")]
+            timeout_ticks: crv,
+            #[cfg(feature = "time")]
+            handles: N as u8,
+        };
 
         const DUMMY_HANDLE: WatchdogHandle = WatchdogHandle { index: 0 };
         let mut handles = [DUMMY_HANDLE; N];
@@ -129,6 +155,61 @@ impl Watchdog {
         let status = r.reqstatus.read().bits();
         (status & enabled) == 0
     }
+
+    /// Watch the handles, and report the ones that are about to starve the watchdog.
+    ///
+    /// A handle is considered starving when it hasn't been pet for three quarters of the
+    /// watchdog period. Each starving handle is reported once with [`StarvedAction`] until it
+    /// is pet again. This lets you find out which task got stuck before the reset hits.
+    ///
+    /// The check uses embassy-time, which keeps running while the CPU sleeps and is halted for debug.
+    /// If [`Config::run_during_sleep`] or [`Config::run_during_debug_halt`] are disabled, the
+    /// watchdog can be further from a reset than the monitor thinks.
+    #[cfg(feature = "time")]
+    pub async fn monitor(&mut self, action: StarvedAction) -> ! {
+        let period = Duration::from_micros(self.timeout_ticks as u64 * 1_000_000 / 32768);
+        let threshold = (period.as_ticks() * 3 / 4) as u32;
+        let mut reported = 0u8;
+
+        loop {
+            Timer::after(period / 4).await;
+
+            let now = Instant::now().as_ticks() as u32;
+            for index in 0..self.handles as usize {
+                let age = now.wrapping_sub(LAST_PET[index].load(Ordering::Relaxed));
+                if age < threshold {
+                    reported &= !(1 << index);
+                    continue;
+                }
+                if reported & (1 << index) != 0 {
+                    continue;
+                }
+                reported |= 1 << index;
+
+                match action {
+                    StarvedAction::Log => {
+                        warn!(
+                            "watchdog handle {} not pet for {} ms",
+                            index,
+                            age as u64 * 1000 / embassy_time::TICK_HZ
+                        )
+                    }
+                    StarvedAction::Panic => panic!("watchdog handle {} starved", index),
+                }
+            }
+        }
+    }
+}
+
+/// What [`Watchdog::monitor`] does when it finds a starving handle.
+#[cfg(feature = "time")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StarvedAction {
+    /// Log a warning, and let the watchdog reset the chip if the handle is not pet in time.
+    Log,
+    /// Panic with a message naming the handle.
+    Panic,
 }
 
 pub struct WatchdogHandle {
@@ -146,6 +227,14 @@ impl WatchdogHandle {
     pub fn pet(&mut self) {
         let r = unsafe { &*WDT::ptr() };
         r.rr[self.index as usize].write(|w| w.rr().reload());
+
+        #[cfg(feature = "time")]
+        LAST_PET[self.index as usize].store(Instant::now().as_ticks() as u32, Ordering::Relaxed);
+    }
+
+    /// Index of this handle.
+    pub fn index(&self) -> u8 {
+        self.index
     }
 
     /// Has this handle been pet within the current window?
@@ -0,0 +1,396 @@
+//! Analog to Digital Converter (ADC)
+//!
+//! The ADC has a 12-bit SAR converter clocked at 48 MHz, taking 96 cycles per conversion, for
+//! up to 500 kS/s. Its inputs are the pins `PIN_26` to `PIN_29`, on channels 0 to 3, and the
+//! on-die temperature sensor on channel 4.
+//!
+//! Single conversions are done with [`Adc::read`] or [`Adc::blocking_read`]. For streaming,
+//! [`Adc::read_continuous`] samples a set of channels in round-robin, at a fixed rate, into a
+//! DMA ring buffer.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_cortex_m::interrupt::InterruptExt;
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::dma::{self, BlockList, Channel as DmaChannel, ContinuousTransfer};
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::{self, AnyPin};
+use crate::{interrupt, pac, peripherals};
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// DREQ of the ADC FIFO.
+const DREQ_ADC: u8 = 36;
+
+/// ADC input of the temperature sensor.
+const TEMP_SENSOR_CHANNEL: u8 = 4;
+
+/// Number of cycles of the ADC clock taken by a conversion.
+const CYCLES_PER_CONVERSION: u32 = 96;
+
+/// ADC error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The conversion failed.
+    ConversionFailed,
+    /// The oldest completed block of a continuous sampling was overwritten before it was handed
+    /// out. The samples of later blocks are unaffected.
+    Overrun,
+    /// The ADC FIFO overflowed because the DMA couldn't keep up, and samples were lost. The
+    /// samples no longer start with the first channel at the start of each block, so sampling
+    /// should be restarted.
+    FifoOverflow,
+}
+
+/// ADC configuration.
+#[non_exhaustive]
+pub struct Config {}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {}
+    }
+}
+
+/// An input of the ADC.
+///
+/// Pins are switched to analog use while the channel exists, with their digital input buffer
+/// disabled.
+pub struct Channel<'d> {
+    number: u8,
+    pin: Option<PeripheralRef<'d, AnyPin>>,
+}
+
+impl<'d> Channel<'d> {
+    /// Create a channel reading an ADC pin.
+    pub fn new_pin(pin: impl Peripheral<P = impl AdcPin> + 'd) -> Self {
+        into_ref!(pin);
+
+        let number = pin.channel();
+        unsafe {
+            pin.pad_ctrl().modify(|w| {
+                w.set_ie(false);
+                w.set_od(true);
+                w.set_pue(false);
+                w.set_pde(false);
+            });
+            pin.io().ctrl().write(|w| {
+                w.set_funcsel(pac::io::vals::Gpio0ctrlFuncsel::NULL.0);
+            });
+        }
+
+        Self {
+            number,
+            pin: Some(pin.map_into()),
+        }
+    }
+
+    /// Create a channel reading the on-die temperature sensor.
+    ///
+    /// The sensor is powered while the channel exists. Use [`convert_to_celsius`] to convert
+    /// its readings.
+    pub fn new_temp_sensor(_sensor: impl Peripheral<P = peripherals::ADC_TEMP_SENSOR> + 'd) -> Self {
+        unsafe { pac::ADC.cs().modify(|w| w.set_ts_en(true)) };
+
+        Self {
+            number: TEMP_SENSOR_CHANNEL,
+            pin: None,
+        }
+    }
+
+    /// Number of the ADC input this channel reads.
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+}
+
+impl<'d> Drop for Channel<'d> {
+    fn drop(&mut self) {
+        match &self.pin {
+            Some(pin) => unsafe { pin.pad_ctrl().write(|_| {}) },
+            None => unsafe { pac::ADC.cs().modify(|w| w.set_ts_en(false)) },
+        }
+    }
+}
+
+/// ADC driver.
+pub struct Adc<'d> {
+    _p: PhantomData<&'d mut peripherals::ADC>,
+}
+
+impl<'d> Adc<'d> {
+    /// Create a new ADC driver.
+    pub fn new(
+        _adc: impl Peripheral<P = peripherals::ADC> + 'd,
+        irq: impl Peripheral<P = interrupt::ADC_IRQ_FIFO> + 'd,
+        _config: Config,
+    ) -> Self {
+        into_ref!(irq);
+
+        let r = Self::regs();
+        unsafe {
+            r.cs().write(|w| w.set_en(true));
+            while !r.cs().read().ready() {}
+            r.inte().write(|_| {});
+        }
+
+        irq.disable();
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self { _p: PhantomData }
+    }
+
+    fn regs() -> pac::adc::Adc {
+        pac::ADC
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        unsafe { Self::regs().inte().write(|w| w.set_fifo(false)) };
+        WAKER.wake();
+    }
+
+    /// Stop any running conversion and empty the FIFO, dropping stale results.
+    fn reset_fifo(&mut self) {
+        let r = Self::regs();
+        unsafe {
+            r.cs().modify(|w| {
+                w.set_start_many(false);
+                w.set_rrobin(0);
+            });
+            while !r.cs().read().ready() {}
+            r.fcs().write(|_| {});
+            while !r.fcs().read().empty() {
+                let _ = r.fifo().read();
+            }
+            // Clear the sticky flags.
+            r.fcs().write(|w| {
+                w.set_over(true);
+                w.set_under(true);
+            });
+            r.cs().modify(|w| w.set_err_sticky(true));
+        }
+    }
+
+    /// Do a single conversion of `channel`.
+    pub async fn read(&mut self, channel: &mut Channel<'_>) -> Result<u16, Error> {
+        self.reset_fifo();
+
+        let r = Self::regs();
+        unsafe {
+            // Push the result to the FIFO, with its error flag, and interrupt once it's there.
+            r.fcs().write(|w| {
+                w.set_en(true);
+                w.set_err(true);
+                w.set_thresh(1);
+            });
+            r.cs().modify(|w| {
+                w.set_ainsel(channel.number);
+                w.set_start_once(true);
+            });
+        }
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            if unsafe { r.fcs().read().level() } > 0 {
+                Poll::Ready(())
+            } else {
+                unsafe { r.inte().write(|w| w.set_fifo(true)) };
+                Poll::Pending
+            }
+        })
+        .await;
+
+        let result = unsafe {
+            let result = r.fifo().read();
+            r.fcs().write(|_| {});
+            result
+        };
+        if result.err() {
+            Err(Error::ConversionFailed)
+        } else {
+            Ok(result.val())
+        }
+    }
+
+    /// Do a single conversion of `channel`, blocking until it is done.
+    pub fn blocking_read(&mut self, channel: &mut Channel<'_>) -> Result<u16, Error> {
+        self.reset_fifo();
+
+        let r = Self::regs();
+        unsafe {
+            r.cs().modify(|w| {
+                w.set_ainsel(channel.number);
+                w.set_start_once(true);
+            });
+            while !r.cs().read().ready() {}
+
+            if r.cs().read().err() {
+                Err(Error::ConversionFailed)
+            } else {
+                Ok(r.result().read().result())
+            }
+        }
+    }
+
+    /// Sample `channels` in round-robin into `buf`, until the returned [`ContinuousSampling`]
+    /// is dropped.
+    ///
+    /// Each channel is sampled `sample_rate` times per second. The samples are interleaved in
+    /// increasing channel number order, whatever the order of `channels`, and each block starts
+    /// with the lowest channel.
+    ///
+    /// `buf` is split into `blocks` blocks that are filled by DMA one after the other, looping
+    /// around, see [`dma::read_continuous`]. Completed blocks are handed out by
+    /// [`ContinuousSampling::wait_block`], with no CPU involvement per sample.
+    ///
+    /// Panics if `channels` is empty or contains the same channel twice, if the total rate of
+    /// conversions is higher than 500 kS/s or if the blocks don't hold a whole number of rounds
+    /// over `channels`. See [`dma::read_continuous`] for the constraints on `blocks`.
+    pub fn read_continuous<'a, C1: DmaChannel, C2: DmaChannel>(
+        &'a mut self,
+        channels: &'a [Channel<'a>],
+        sample_rate: u32,
+        data: impl Peripheral<P = C1> + 'a,
+        control: impl Peripheral<P = C2> + 'a,
+        buf: &'a mut [u16],
+        blocks: usize,
+        list: &'a mut BlockList,
+    ) -> ContinuousSampling<'a, C1, C2> {
+        let mut mask = 0u8;
+        for channel in channels {
+            assert!(mask & (1 << channel.number) == 0, "duplicate ADC channel");
+            mask |= 1 << channel.number;
+        }
+        assert!(mask != 0);
+        assert!(blocks > 0 && (buf.len() / blocks) % channels.len() == 0);
+
+        // The ADC starts a conversion every `div + 1` cycles.
+        let conversion_rate = sample_rate as u64 * channels.len() as u64;
+        let cycles_x256 = (crate::clocks::clk_adc_freq() as u64 * 256) / conversion_rate;
+        assert!(cycles_x256 >= (CYCLES_PER_CONVERSION as u64) * 256 && cycles_x256 <= (1 << 24));
+        let div = cycles_x256 - 256;
+
+        self.reset_fifo();
+
+        let r = Self::regs();
+        unsafe {
+            r.div().write(|w| {
+                w.set_int((div >> 8) as u16);
+                w.set_frac(div as u8);
+            });
+            // Plain 12-bit samples, with a DREQ for every one.
+            r.fcs().write(|w| {
+                w.set_en(true);
+                w.set_dreq_en(true);
+                w.set_thresh(1);
+            });
+        }
+
+        let transfer =
+            unsafe { dma::read_continuous(data, control, r.fifo().ptr() as *const u16, buf, blocks, list, DREQ_ADC) };
+
+        unsafe {
+            r.cs().modify(|w| {
+                w.set_ainsel(mask.trailing_zeros() as u8);
+                w.set_rrobin(mask);
+                w.set_start_many(true);
+            });
+        }
+
+        ContinuousSampling {
+            transfer,
+            _adc: PhantomData,
+        }
+    }
+}
+
+/// Convert a reading of the temperature sensor channel to degrees Celsius.
+///
+/// This uses the formula from the datasheet, `27 - (V - 0.706) / 0.001721`, assuming the ADC
+/// reference is 3.3 V. The sensor is not calibrated, so expect an error of a few degrees.
+pub fn convert_to_celsius(raw: u16) -> f32 {
+    let voltage = raw as f32 * 3.3 / 4096.0;
+    27.0 - (voltage - 0.706) / 0.001721
+}
+
+/// Free-running round-robin sampling into a DMA ring buffer, see [`Adc::read_continuous`].
+///
+/// Dropping it stops the sampling.
+pub struct ContinuousSampling<'a, C1: DmaChannel, C2: DmaChannel> {
+    transfer: ContinuousTransfer<'a, C1, C2, u16>,
+    _adc: PhantomData<&'a mut peripherals::ADC>,
+}
+
+impl<'a, C1: DmaChannel, C2: DmaChannel> ContinuousSampling<'a, C1, C2> {
+    /// Wait for the next block of samples, and return its index.
+    ///
+    /// The block can be accessed with [`block`](Self::block) until the DMA loops back to it.
+    /// Returns [`Error::Overrun`] if blocks completed faster than they were handed out, and
+    /// [`Error::FifoOverflow`] if samples were lost before reaching the buffer.
+    pub async fn wait_block(&mut self) -> Result<usize, Error> {
+        let r = Adc::regs();
+        if unsafe { r.fcs().read().over() } {
+            unsafe { r.fcs().write(|w| w.set_over(true)) };
+            return Err(Error::FifoOverflow);
+        }
+
+        self.transfer.wait_block().await.map_err(|_| Error::Overrun)
+    }
+
+    /// Access block `n` of the buffer.
+    pub fn block(&mut self, n: usize) -> &mut [u16] {
+        self.transfer.block(n)
+    }
+
+    /// Number of samples in each block.
+    pub fn block_len(&self) -> usize {
+        self.transfer.block_len()
+    }
+}
+
+impl<'a, C1: DmaChannel, C2: DmaChannel> Drop for ContinuousSampling<'a, C1, C2> {
+    fn drop(&mut self) {
+        // Stop converting, the transfer is stopped when dropped right after this.
+        let r = Adc::regs();
+        unsafe {
+            r.cs().modify(|w| {
+                w.set_start_many(false);
+                w.set_rrobin(0);
+            });
+            r.fcs().modify(|w| w.set_dreq_en(false));
+        }
+    }
+}
+
+pub(crate) mod sealed {
+    pub trait AdcPin: crate::gpio::sealed::Pin {
+        fn channel(&self) -> u8;
+    }
+}
+
+/// A pin that can be used as an ADC input.
+pub trait AdcPin: sealed::AdcPin + gpio::Pin {}
+
+macro_rules! impl_pin {
+    ($pin:ident, $channel:expr) => {
+        impl sealed::AdcPin for peripherals::$pin {
+            fn channel(&self) -> u8 {
+                $channel
+            }
+        }
+        impl AdcPin for peripherals::$pin {}
+    };
+}
+
+impl_pin!(PIN_26, 0);
+impl_pin!(PIN_27, 1);
+impl_pin!(PIN_28, 2);
+impl_pin!(PIN_29, 3);
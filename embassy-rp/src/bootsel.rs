@@ -0,0 +1,68 @@
+//! BOOTSEL button
+//!
+//! The BOOTSEL button of the Raspberry Pi Pico and similar boards pulls the chip select pin of
+//! the flash low, which the bootrom checks at reset. It can also be read afterwards, to use it as
+//! a button: [`Bootsel::is_pressed`] briefly stops driving the chip select pin and reads it.
+//!
+//! The flash can't be accessed during that time, so the read runs from RAM with interrupts
+//! disabled, and with the other core paused in RAM if it was started with
+//! [`spawn_core1`](crate::multicore::spawn_core1). This takes a few microseconds.
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use crate::gpio::sealed::Pin as _;
+use crate::peripherals::PIN_QSPI_SS;
+use crate::{multicore, pac, Peripheral};
+
+/// Output enable override field of the pin control register, and its value disabling the output.
+const CTRL_OEOVER_MASK: u32 = 0b11 << 12;
+const CTRL_OEOVER_DISABLE: u32 = 0b10 << 12;
+
+/// Number of reads of the pin before sampling it, letting the pull-up of the board bring it high
+/// if the button isn't pressed.
+const SETTLE_READS: u32 = 1000;
+
+/// BOOTSEL button driver.
+pub struct Bootsel<'d> {
+    pin: PeripheralRef<'d, PIN_QSPI_SS>,
+}
+
+impl<'d> Bootsel<'d> {
+    /// Create a new BOOTSEL button driver, from the chip select pin of the flash.
+    pub fn new(pin: impl Peripheral<P = PIN_QSPI_SS> + 'd) -> Self {
+        into_ref!(pin);
+        Self { pin }
+    }
+
+    /// Read whether the button is pressed.
+    pub fn is_pressed(&mut self) -> bool {
+        let ctrl = self.pin.io().ctrl().ptr() as *mut u32;
+        let input = self.pin.sio_in().ptr() as *const u32;
+        let mask = 1 << self.pin.pin();
+
+        let high = multicore::with_other_core_paused(|| {
+            cortex_m::interrupt::free(|_| unsafe { read_pin_in_ram(ctrl, input, mask) })
+        });
+        // The button pulls the pin low.
+        !high
+    }
+}
+
+/// Stop driving the pin whose control register is `ctrl`, and read its level through the `mask`
+/// bit of `input`. Nothing in flash may be called, as the flash is deselected meanwhile.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn read_pin_in_ram(ctrl: *mut u32, input: *const u32, mask: u32) -> bool {
+    let saved = core::ptr::read_volatile(ctrl);
+    core::ptr::write_volatile(ctrl, (saved & !CTRL_OEOVER_MASK) | CTRL_OEOVER_DISABLE);
+
+    let mut i = 0;
+    while i < SETTLE_READS {
+        core::ptr::read_volatile(input);
+        i += 1;
+    }
+    let high = core::ptr::read_volatile(input) & mask != 0;
+
+    core::ptr::write_volatile(ctrl, saved);
+    high
+}
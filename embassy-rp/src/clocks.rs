@@ -1,11 +1,192 @@
+//! Clock configuration
+//!
+//! The clocks are set up by [`init`](crate::init) from the [`ClockConfig`] of its
+//! [`Config`](crate::config::Config), and their resulting frequencies can be queried with the
+//! `*_freq` functions of this module. The system clock can be raised above its nominal
+//! 133 MHz, raising the core voltage as needed. The flash runs at half the system clock, and
+//! usually stops working above about 266 MHz.
+//!
+//! Clocks can also be output on pins with [`Gpout`].
+
+use embassy_hal_common::{into_ref, PeripheralRef};
 use pac::clocks::vals::*;
 
-use crate::{pac, reset};
+use crate::gpio::sealed::Pin as _;
+use crate::{gpio, pac, peripherals, reset, Peripheral};
 
 const XOSC_MHZ: u32 = 12;
 
+/// Frequency of the crystal oscillator, in Hz.
+pub const XOSC_FREQ: u32 = XOSC_MHZ * 1_000_000;
+
+/// PLL configuration. The output frequency is
+/// `XOSC_FREQ / refdiv * fbdiv / post_div1 / post_div2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PllConfig {
+    /// Reference divider, from 1 to 63.
+    pub refdiv: u8,
+    /// Feedback divider, from 16 to 320. The VCO runs at `XOSC_FREQ / refdiv * fbdiv`, which
+    /// must be between 750 and 1600 MHz.
+    pub fbdiv: u16,
+    /// First post divider, from 1 to 7.
+    pub post_div1: u8,
+    /// Second post divider, from 1 to `post_div1`.
+    pub post_div2: u8,
+}
+
+impl PllConfig {
+    /// 125 MHz, the default system clock.
+    pub const SYS_125MHZ: Self = Self {
+        refdiv: 1,
+        fbdiv: 125,
+        post_div1: 6,
+        post_div2: 2,
+    };
+
+    /// 48 MHz, required by USB.
+    pub const USB_48MHZ: Self = Self {
+        refdiv: 1,
+        fbdiv: 40,
+        post_div1: 5,
+        post_div2: 2,
+    };
+
+    /// Find a configuration generating exactly `freq` Hz, if there is one.
+    pub fn from_freq(freq: u32) -> Option<Self> {
+        // Prefer the highest VCO frequency, which has the lowest jitter.
+        for fbdiv in (16..=320).rev() {
+            let vco = XOSC_FREQ * fbdiv;
+            if !(750_000_000..=1_600_000_000).contains(&vco) {
+                continue;
+            }
+            for post_div1 in (1..=7).rev() {
+                for post_div2 in (1..=post_div1).rev() {
+                    if vco % (post_div1 * post_div2) == 0 && vco / (post_div1 * post_div2) == freq {
+                        return Some(Self {
+                            refdiv: 1,
+                            fbdiv: fbdiv as u16,
+                            post_div1: post_div1 as u8,
+                            post_div2: post_div2 as u8,
+                        });
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Output frequency, in Hz.
+    pub const fn freq(&self) -> u32 {
+        self.vco_freq() / self.post_div1 as u32 / self.post_div2 as u32
+    }
+
+    const fn vco_freq(&self) -> u32 {
+        XOSC_FREQ / self.refdiv as u32 * self.fbdiv as u32
+    }
+}
+
+/// Source of `clk_peri`, which clocks the UARTs and SPIs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PeriClkSrc {
+    /// The system clock.
+    Sys,
+    /// The system PLL.
+    PllSys,
+    /// The USB PLL, 48 MHz.
+    PllUsb,
+    /// The crystal oscillator, 12 MHz.
+    Xosc,
+}
+
+/// Source of `clk_adc`. The ADC is specified for 48 MHz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AdcClkSrc {
+    /// The USB PLL, 48 MHz.
+    PllUsb,
+    /// The system PLL.
+    PllSys,
+    /// The crystal oscillator, 12 MHz.
+    Xosc,
+}
+
+/// Core voltage, set by the on-chip regulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CoreVoltage {
+    /// 1.10 V, the default.
+    V1_10,
+    V1_15,
+    V1_20,
+    V1_25,
+    V1_30,
+}
+
+impl CoreVoltage {
+    /// Lowest voltage usually working at a system clock of `freq` Hz.
+    fn for_sys_freq(freq: u32) -> Self {
+        match freq {
+            0..=133_000_000 => Self::V1_10,
+            133_000_001..=200_000_000 => Self::V1_15,
+            _ => Self::V1_20,
+        }
+    }
+
+    fn vsel(self) -> u8 {
+        match self {
+            Self::V1_10 => 0b1011,
+            Self::V1_15 => 0b1100,
+            Self::V1_20 => 0b1101,
+            Self::V1_25 => 0b1110,
+            Self::V1_30 => 0b1111,
+        }
+    }
+}
+
+/// Clock configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct ClockConfig {
+    /// Configuration of the system PLL, which clocks `clk_sys`. Use [`PllConfig::from_freq`]
+    /// to get one for a given frequency.
+    pub sys_pll: PllConfig,
+    /// Source of `clk_peri`.
+    pub peri_clk_src: PeriClkSrc,
+    /// Source of `clk_adc`.
+    pub adc_clk_src: AdcClkSrc,
+    /// Core voltage. If `None`, it is raised as needed for the system clock.
+    pub core_voltage: Option<CoreVoltage>,
+}
+
+impl ClockConfig {
+    const DEFAULT: Self = Self {
+        sys_pll: PllConfig::SYS_125MHZ,
+        peri_clk_src: PeriClkSrc::Sys,
+        adc_clk_src: AdcClkSrc::PllUsb,
+        core_voltage: None,
+    };
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Configuration applied by `init`, from which the frequencies are computed.
+static mut CONFIG: ClockConfig = ClockConfig::DEFAULT;
+
+fn config() -> &'static ClockConfig {
+    // Only written by `init`, before anything can read it.
+    unsafe { &*core::ptr::addr_of!(CONFIG) }
+}
+
 /// safety: must be called exactly once at bootup
-pub unsafe fn init() {
+pub(crate) unsafe fn init(config: ClockConfig) {
+    CONFIG = config;
+
     // Reset everything except:
     // - QSPI (we're using it to run this code!)
     // - PLLs (it may be suicide if that's what's clocking us)
@@ -52,12 +233,29 @@ pub unsafe fn init() {
     c.clk_ref_ctrl().modify(|w| w.set_src(ClkRefCtrlSrc::ROSC_CLKSRC_PH));
     while c.clk_ref_selected().read() != 1 {}
 
-    // Configure PLLs
-    //                   REF     FBDIV VCO            POSTDIV
-    // PLL SYS: 12 / 1 = 12MHz * 125 = 1500MHZ / 6 / 2 = 125MHz
-    // PLL USB: 12 / 1 = 12MHz * 40  = 480 MHz / 5 / 2 =  48MHz
-    configure_pll(pac::PLL_SYS, 1, 1500_000_000, 6, 2);
-    configure_pll(pac::PLL_USB, 1, 480_000_000, 5, 2);
+    // Raise the voltage before the frequency.
+    let voltage = config
+        .core_voltage
+        .unwrap_or_else(|| CoreVoltage::for_sys_freq(config.sys_pll.freq()));
+    pac::VREG_AND_CHIP_RESET.vreg().modify(|w| w.set_vsel(voltage.vsel()));
+    // Give the regulator 1 ms to settle, at the 6.5 MHz of the ring oscillator.
+    cortex_m::asm::delay(6_500);
+
+    start_plls();
+
+    // Peripheral clocks should now all be running
+    let peris = reset::ALL_PERIPHERALS;
+    reset::unreset_wait(peris);
+}
+
+/// Start the PLLs, and run the clocks from them. `clk_ref` and `clk_sys` must be running from
+/// the crystal oscillator.
+pub(crate) unsafe fn start_plls() {
+    let c = pac::CLOCKS;
+    let config = config();
+
+    configure_pll(pac::PLL_SYS, config.sys_pll);
+    configure_pll(pac::PLL_USB, PllConfig::USB_48MHZ);
 
     // CLK_REF = XOSC (12MHz) / 1 = 12MHz2Mhz
     c.clk_ref_ctrl().write(|w| {
@@ -66,7 +264,7 @@ pub unsafe fn init() {
     while c.clk_ref_selected().read() != 1 << ClkRefCtrlSrc::XOSC_CLKSRC.0 {}
     c.clk_ref_div().write(|w| w.set_int(1));
 
-    // CLK SYS = PLL SYS (125MHz) / 1 = 125MHz
+    // CLK SYS = PLL SYS / 1
     c.clk_sys_ctrl().write(|w| {
         w.set_src(ClkSysCtrlSrc::CLK_REF);
     });
@@ -85,11 +283,15 @@ pub unsafe fn init() {
         w.set_auxsrc(ClkUsbCtrlAuxsrc::CLKSRC_PLL_USB);
     });
 
-    // CLK ADC = PLL USB (48MHZ) / 1 = 48MHz
+    // CLK ADC = source / 1
     c.clk_adc_div().write(|w| w.set_int(1));
     c.clk_adc_ctrl().write(|w| {
         w.set_enable(true);
-        w.set_auxsrc(ClkAdcCtrlAuxsrc::CLKSRC_PLL_USB);
+        w.set_auxsrc(match config.adc_clk_src {
+            AdcClkSrc::PllUsb => ClkAdcCtrlAuxsrc::CLKSRC_PLL_USB,
+            AdcClkSrc::PllSys => ClkAdcCtrlAuxsrc::CLKSRC_PLL_SYS,
+            AdcClkSrc::Xosc => ClkAdcCtrlAuxsrc::XOSC_CLKSRC,
+        });
     });
 
     // CLK RTC = PLL USB (48MHz) / 1024 = 46875Hz
@@ -102,27 +304,98 @@ pub unsafe fn init() {
         w.set_auxsrc(ClkRtcCtrlAuxsrc::CLKSRC_PLL_USB);
     });
 
-    // CLK PERI = clk_sys. Used as reference clock for Peripherals. No dividers so just select and enable
-    // Normally choose clk_sys or clk_usb
+    // CLK PERI = source. Used as reference clock for Peripherals. No dividers so just select and enable
     c.clk_peri_ctrl().write(|w| {
         w.set_enable(true);
-        w.set_auxsrc(ClkPeriCtrlAuxsrc::CLK_SYS);
+        w.set_auxsrc(match config.peri_clk_src {
+            PeriClkSrc::Sys => ClkPeriCtrlAuxsrc::CLK_SYS,
+            PeriClkSrc::PllSys => ClkPeriCtrlAuxsrc::CLKSRC_PLL_SYS,
+            PeriClkSrc::PllUsb => ClkPeriCtrlAuxsrc::CLKSRC_PLL_USB,
+            PeriClkSrc::Xosc => ClkPeriCtrlAuxsrc::XOSC_CLKSRC,
+        });
     });
+}
 
-    // Peripheral clocks should now all be running
-    let peris = reset::ALL_PERIPHERALS;
-    reset::unreset_wait(peris);
+/// Run `clk_sys` from the crystal oscillator and stop the PLLs, to save power. `clk_usb` and
+/// `clk_adc` are stopped, and `clk_rtc` keeps the same frequency from the crystal oscillator.
+/// [`start_plls`] undoes this.
+pub(crate) unsafe fn stop_plls() {
+    let c = pac::CLOCKS;
+
+    // clk_ref already runs from XOSC.
+    c.clk_sys_ctrl().modify(|w| w.set_src(ClkSysCtrlSrc::CLK_REF));
+    while c.clk_sys_selected().read() != 1 << ClkSysCtrlSrc::CLK_REF.0 {}
+
+    c.clk_usb_ctrl().modify(|w| w.set_enable(false));
+    c.clk_adc_ctrl().modify(|w| w.set_enable(false));
+
+    // The aux source can only be changed while the clock is stopped, which takes a few
+    // cycles of it.
+    c.clk_rtc_ctrl().modify(|w| w.set_enable(false));
+    cortex_m::asm::delay(3 * (XOSC_MHZ * 1_000_000 / clk_rtc_freq() + 1));
+    // CLK RTC = XOSC (12MHz) / 256 = 46875Hz
+    c.clk_rtc_div().write(|w| w.set_int(256));
+    c.clk_rtc_ctrl().write(|w| {
+        w.set_enable(true);
+        w.set_auxsrc(ClkRtcCtrlAuxsrc::XOSC_CLKSRC);
+    });
+
+    for p in [pac::PLL_SYS, pac::PLL_USB] {
+        p.pwr().write(|w| {
+            w.set_pd(true);
+            w.set_dsmpd(true);
+            w.set_postdivpd(true);
+            w.set_vcopd(true);
+        });
+    }
+}
+
+/// Frequency of the system PLL, in Hz.
+pub fn pll_sys_freq() -> u32 {
+    config().sys_pll.freq()
+}
+
+/// Frequency of the USB PLL, in Hz.
+pub fn pll_usb_freq() -> u32 {
+    PllConfig::USB_48MHZ.freq()
+}
+
+/// Frequency of `clk_ref`, in Hz.
+pub fn clk_ref_freq() -> u32 {
+    XOSC_FREQ
+}
+
+/// Frequency of `clk_sys`, which clocks the cores and most peripherals, in Hz.
+pub fn clk_sys_freq() -> u32 {
+    pll_sys_freq()
 }
 
-pub(crate) fn _clk_sys_freq() -> u32 {
-    125_000_000
+/// Frequency of `clk_peri`, which clocks the UARTs and SPIs, in Hz.
+pub fn clk_peri_freq() -> u32 {
+    match config().peri_clk_src {
+        PeriClkSrc::Sys => clk_sys_freq(),
+        PeriClkSrc::PllSys => pll_sys_freq(),
+        PeriClkSrc::PllUsb => pll_usb_freq(),
+        PeriClkSrc::Xosc => XOSC_FREQ,
+    }
 }
 
-pub(crate) fn clk_peri_freq() -> u32 {
-    125_000_000
+/// Frequency of `clk_usb`, in Hz.
+pub fn clk_usb_freq() -> u32 {
+    pll_usb_freq()
+}
+
+/// Frequency of `clk_adc`, in Hz.
+pub fn clk_adc_freq() -> u32 {
+    match config().adc_clk_src {
+        AdcClkSrc::PllUsb => pll_usb_freq(),
+        AdcClkSrc::PllSys => pll_sys_freq(),
+        AdcClkSrc::Xosc => XOSC_FREQ,
+    }
 }
 
-pub(crate) fn clk_rtc_freq() -> u32 {
+/// Frequency of `clk_rtc`, in Hz.
+pub fn clk_rtc_freq() -> u32 {
     46875
 }
 
@@ -141,11 +414,18 @@ unsafe fn start_xosc() {
     while !pac::XOSC.status().read().stable() {}
 }
 
-unsafe fn configure_pll(p: pac::pll::Pll, refdiv: u32, vco_freq: u32, post_div1: u8, post_div2: u8) {
-    let ref_freq = XOSC_MHZ * 1_000_000 / refdiv;
+unsafe fn configure_pll(p: pac::pll::Pll, config: PllConfig) {
+    let PllConfig {
+        refdiv,
+        fbdiv,
+        post_div1,
+        post_div2,
+    } = config;
+    let ref_freq = XOSC_FREQ / refdiv as u32;
+    let vco_freq = config.vco_freq();
 
-    let fbdiv = vco_freq / ref_freq;
     assert!(fbdiv >= 16 && fbdiv <= 320);
+    assert!(vco_freq >= 750_000_000 && vco_freq <= 1_600_000_000);
     assert!(post_div1 >= 1 && post_div1 <= 7);
     assert!(post_div2 >= 1 && post_div2 <= 7);
     assert!(post_div2 <= post_div1);
@@ -196,3 +476,136 @@ unsafe fn configure_pll(p: pac::pll::Pll, refdiv: u32, vco_freq: u32, post_div1:
     // Turn on post divider
     p.pwr().modify(|w| w.set_postdivpd(false));
 }
+
+/// Source of a clock output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum GpoutSrc {
+    PllSys = 0,
+    PllUsb = 3,
+    Rosc = 4,
+    Xosc = 5,
+    Sys = 6,
+    Usb = 7,
+    Adc = 8,
+    Rtc = 9,
+    Ref = 10,
+}
+
+/// Clock output, driving a pin with one of the clocks divided by a fractional divider.
+pub struct Gpout<'d, T: GpoutPin> {
+    pin: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: GpoutPin> Gpout<'d, T> {
+    /// Create a clock output on `pin`, initially outputting `clk_sys` undivided. It is not
+    /// enabled until [`enable`](Self::enable) is called.
+    pub fn new(pin: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(pin);
+
+        unsafe {
+            let c = pac::CLOCKS;
+            c.clk_gpout_ctrl(T::NUMBER)
+                .write(|w| w.set_auxsrc(ClkGpoutCtrlAuxsrc(GpoutSrc::Sys as _)));
+            c.clk_gpout_div(T::NUMBER).write(|w| w.set_int(1));
+            pin.io().ctrl().write(|w| w.set_funcsel(FUNCSEL_GPCK));
+        }
+
+        Self { pin }
+    }
+
+    /// Select the clock to output. The output is stopped while switching.
+    pub fn set_src(&mut self, src: GpoutSrc) {
+        unsafe {
+            let ctrl = pac::CLOCKS.clk_gpout_ctrl(T::NUMBER);
+            let enabled = ctrl.read().enable();
+            ctrl.modify(|w| w.set_enable(false));
+            // The divider stops glitchlessly on the falling edge of its output, which takes up to
+            // a whole period of the slowest source, the RTC clock.
+            cortex_m::asm::delay(clk_sys_freq() / clk_rtc_freq() * 2);
+            ctrl.modify(|w| w.set_auxsrc(ClkGpoutCtrlAuxsrc(src as _)));
+            ctrl.modify(|w| w.set_enable(enabled));
+        }
+    }
+
+    /// Divide the source clock by `int + frac / 256`. `int` must not be 0.
+    pub fn set_div(&mut self, int: u32, frac: u8) {
+        assert!(int >= 1 && int < 1 << 24);
+        unsafe {
+            pac::CLOCKS.clk_gpout_div(T::NUMBER).write(|w| {
+                w.set_int(int);
+                w.set_frac(frac);
+            });
+        }
+    }
+
+    /// Start outputting the clock.
+    pub fn enable(&mut self) {
+        unsafe { pac::CLOCKS.clk_gpout_ctrl(T::NUMBER).modify(|w| w.set_enable(true)) }
+    }
+
+    /// Stop outputting the clock.
+    pub fn disable(&mut self) {
+        unsafe { pac::CLOCKS.clk_gpout_ctrl(T::NUMBER).modify(|w| w.set_enable(false)) }
+    }
+
+    /// Frequency of the output, in Hz.
+    pub fn get_freq(&self) -> u32 {
+        let (ctrl, div) = unsafe {
+            let c = pac::CLOCKS;
+            (c.clk_gpout_ctrl(T::NUMBER).read(), c.clk_gpout_div(T::NUMBER).read())
+        };
+
+        let src = match ctrl.auxsrc().0 {
+            0 => pll_sys_freq(),
+            3 => pll_usb_freq(),
+            // The ring oscillator is not calibrated.
+            4 => 6_500_000,
+            5 => XOSC_FREQ,
+            6 => clk_sys_freq(),
+            7 => clk_usb_freq(),
+            8 => clk_adc_freq(),
+            9 => clk_rtc_freq(),
+            10 => clk_ref_freq(),
+            _ => unreachable!(),
+        };
+        ((src as u64 * 256) / ((div.int() as u64) << 8 | div.frac() as u64)) as u32
+    }
+}
+
+impl<'d, T: GpoutPin> Drop for Gpout<'d, T> {
+    fn drop(&mut self) {
+        self.disable();
+        unsafe {
+            self.pin.io().ctrl().write(|w| {
+                w.set_funcsel(pac::io::vals::Gpio0ctrlFuncsel::NULL.0);
+            });
+        }
+    }
+}
+
+const FUNCSEL_GPCK: u8 = 8;
+
+mod sealed {
+    pub trait GpoutPin {
+        const NUMBER: usize;
+    }
+}
+
+/// A pin that can output a clock.
+pub trait GpoutPin: gpio::Pin + sealed::GpoutPin {}
+
+macro_rules! impl_gpout_pin {
+    ($pin:ident, $number:expr) => {
+        impl sealed::GpoutPin for peripherals::$pin {
+            const NUMBER: usize = $number;
+        }
+        impl GpoutPin for peripherals::$pin {}
+    };
+}
+
+impl_gpout_pin!(PIN_21, 0);
+impl_gpout_pin!(PIN_23, 1);
+impl_gpout_pin!(PIN_24, 2);
+impl_gpout_pin!(PIN_25, 3);
@@ -1,8 +1,10 @@
-use core::future::Future;
+use core::future::{poll_fn, Future};
+use core::marker::PhantomData;
 use core::pin::Pin;
 use core::sync::atomic::{compiler_fence, Ordering};
 use core::task::{Context, Poll};
 
+use atomic_polyfill::AtomicU32;
 use embassy_cortex_m::interrupt::{Interrupt, InterruptExt};
 use embassy_hal_common::{impl_peripheral, into_ref, Peripheral, PeripheralRef};
 use embassy_sync::waitqueue::AtomicWaker;
@@ -21,6 +23,7 @@ unsafe fn DMA_IRQ_0() {
         }
 
         if ints0 & (1 << channel) == (1 << channel) {
+            CHANNEL_COMPLETIONS[channel].fetch_add(1, Ordering::Relaxed);
             CHANNEL_WAKERS[channel].wake();
         }
     }
@@ -81,10 +84,11 @@ pub unsafe fn write_repeated<'a, C: Channel, W: Word>(
     len: usize,
     dreq: u8,
 ) -> Transfer<'a, C> {
-    let dummy: u32 = 0;
+    // The DMA keeps reading it after this returns, so it can't be on the stack.
+    static DUMMY: u32 = 0;
     copy_inner(
         ch,
-        &dummy as *const u32,
+        &DUMMY as *const u32,
         to as *mut u32,
         len,
         W::size(),
@@ -194,6 +198,243 @@ impl<'a, C: Channel> Future for Transfer<'a, C> {
 const CHANNEL_COUNT: usize = 12;
 const NEW_AW: AtomicWaker = AtomicWaker::new();
 static CHANNEL_WAKERS: [AtomicWaker; CHANNEL_COUNT] = [NEW_AW; CHANNEL_COUNT];
+/// Number of times each channel completed a transfer, for [`ContinuousTransfer`].
+const NEW_COUNT: AtomicU32 = AtomicU32::new(0);
+static CHANNEL_COMPLETIONS: [AtomicU32; CHANNEL_COUNT] = [NEW_COUNT; CHANNEL_COUNT];
+
+/// Maximum number of blocks of a [`ContinuousTransfer`].
+pub const MAX_BLOCKS: usize = 8;
+
+/// Storage for the block addresses of a [`ContinuousTransfer`], read by the control channel.
+///
+/// It is aligned so the control channel can wrap around it with the DMA ring feature.
+#[repr(C, align(32))]
+pub struct BlockList {
+    addrs: [u32; MAX_BLOCKS],
+}
+
+impl BlockList {
+    pub const fn new() -> Self {
+        Self { addrs: [0; MAX_BLOCKS] }
+    }
+}
+
+/// The oldest completed block of a [`ContinuousTransfer`] was reused by the DMA before it was
+/// handed out by [`ContinuousTransfer::wait_block`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Overrun;
+
+/// Continuously transfer from a peripheral into the blocks of `to`, looping over them until the
+/// transfer is dropped.
+///
+/// `to` is split into `blocks` blocks of equal length. `data` does the transfers, and chains to
+/// `control` after each block, which points `data` to the next block and restarts it. The CPU is
+/// only involved to be notified of completed blocks, see [`ContinuousTransfer::wait_block`].
+///
+/// Panics if `blocks` isn't a power of two up to [`MAX_BLOCKS`], or doesn't divide the length
+/// of `to`.
+pub unsafe fn read_continuous<'a, C1: Channel, C2: Channel, W: Word>(
+    data: impl Peripheral<P = C1> + 'a,
+    control: impl Peripheral<P = C2> + 'a,
+    from: *const W,
+    to: &'a mut [W],
+    blocks: usize,
+    list: &'a mut BlockList,
+    dreq: u8,
+) -> ContinuousTransfer<'a, C1, C2, W> {
+    let len = to.len();
+    continuous_inner(
+        data,
+        control,
+        from as u32,
+        to.as_mut_ptr(),
+        len,
+        blocks,
+        list,
+        false,
+        dreq,
+    )
+}
+
+/// Continuously transfer from the blocks of `from` to a peripheral, looping over them until the
+/// transfer is dropped.
+///
+/// Works like [`read_continuous`]. Each block handed out by [`ContinuousTransfer::wait_block`]
+/// has been sent, and should be refilled before the DMA loops back to it.
+pub unsafe fn write_continuous<'a, C1: Channel, C2: Channel, W: Word>(
+    data: impl Peripheral<P = C1> + 'a,
+    control: impl Peripheral<P = C2> + 'a,
+    from: &'a mut [W],
+    to: *mut W,
+    blocks: usize,
+    list: &'a mut BlockList,
+    dreq: u8,
+) -> ContinuousTransfer<'a, C1, C2, W> {
+    let len = from.len();
+    continuous_inner(
+        data,
+        control,
+        to as u32,
+        from.as_mut_ptr(),
+        len,
+        blocks,
+        list,
+        true,
+        dreq,
+    )
+}
+
+fn continuous_inner<'a, C1: Channel, C2: Channel, W: Word>(
+    data: impl Peripheral<P = C1> + 'a,
+    control: impl Peripheral<P = C2> + 'a,
+    peripheral: u32,
+    buffer: *mut W,
+    len: usize,
+    blocks: usize,
+    list: &'a mut BlockList,
+    memory_to_peripheral: bool,
+    dreq: u8,
+) -> ContinuousTransfer<'a, C1, C2, W> {
+    into_ref!(data, control);
+
+    assert!(blocks.is_power_of_two() && blocks <= MAX_BLOCKS);
+    assert!(len % blocks == 0 && len > 0);
+    let block_len = len / blocks;
+
+    for (i, addr) in list.addrs[..blocks].iter_mut().enumerate() {
+        *addr = unsafe { buffer.add(i * block_len) } as u32;
+    }
+
+    let completed = CHANNEL_COMPLETIONS[data.number() as usize].load(Ordering::Relaxed);
+
+    unsafe {
+        let d = data.regs();
+        let c = control.regs();
+
+        // Set up the data channel without starting it, the control channel starts it by writing
+        // the address of the first block to a trigger register.
+        let mut ctrl = pac::dma::regs::CtrlTrig(((dreq as u32) & 0x3f) << 15usize);
+        ctrl.set_data_size(W::size());
+        ctrl.set_incr_read(memory_to_peripheral);
+        ctrl.set_incr_write(!memory_to_peripheral);
+        ctrl.set_chain_to(control.number());
+        ctrl.set_en(true);
+        d.al1_ctrl().write_value(ctrl.0);
+        // The count is reloaded every time the channel is triggered.
+        d.trans_count().write_value(block_len as u32);
+        let trigger = if memory_to_peripheral {
+            d.write_addr().write_value(peripheral);
+            d.al3_read_addr_trig().ptr()
+        } else {
+            d.read_addr().write_value(peripheral);
+            d.al2_write_addr_trig().ptr()
+        };
+
+        c.read_addr().write_value(list.addrs.as_ptr() as u32);
+        c.write_addr().write_value(trigger as u32);
+        c.trans_count().write_value(1);
+
+        compiler_fence(Ordering::SeqCst);
+
+        c.ctrl_trig().write(|w| {
+            w.0 = ((vals::TreqSel::PERMANENT.0 as u32) & 0x3f) << 15usize;
+            w.set_data_size(vals::DataSize::SIZE_WORD);
+            w.set_incr_read(true);
+            w.set_incr_write(false);
+            // Wrap around the block list.
+            w.set_ring_sel(false);
+            w.set_ring_size((blocks * 4).trailing_zeros() as u8);
+            // Chaining to itself disables chaining.
+            w.set_chain_to(control.number());
+            w.set_irq_quiet(true);
+            w.set_en(true);
+        });
+
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    ContinuousTransfer {
+        data,
+        control,
+        buffer,
+        block_len,
+        blocks,
+        completed,
+        _list: PhantomData,
+    }
+}
+
+/// A transfer looping over the blocks of a buffer, see [`read_continuous`] and [`write_continuous`].
+///
+/// Dropping it stops the transfer.
+pub struct ContinuousTransfer<'a, C1: Channel, C2: Channel, W: Word> {
+    data: PeripheralRef<'a, C1>,
+    control: PeripheralRef<'a, C2>,
+    buffer: *mut W,
+    block_len: usize,
+    blocks: usize,
+    /// Number of completed blocks handed out so far, compared against `CHANNEL_COMPLETIONS`.
+    completed: u32,
+    _list: PhantomData<(&'a mut BlockList, &'a mut [W])>,
+}
+
+impl<'a, C1: Channel, C2: Channel, W: Word> ContinuousTransfer<'a, C1, C2, W> {
+    /// Wait for the next block to complete, and return its index.
+    ///
+    /// The block can be accessed with [`block`](Self::block) until the DMA loops back to it.
+    /// Returns [`Overrun`] if blocks completed faster than they were handed out, in which case
+    /// the next call hands out the block completed last.
+    pub async fn wait_block(&mut self) -> Result<usize, Overrun> {
+        let n = self.data.number() as usize;
+        poll_fn(|cx| {
+            CHANNEL_WAKERS[n].register(cx.waker());
+
+            let count = CHANNEL_COMPLETIONS[n].load(Ordering::Relaxed);
+            let pending = count.wrapping_sub(self.completed);
+            if pending == 0 {
+                return Poll::Pending;
+            }
+            if pending as usize >= self.blocks {
+                // Skip to the last completed block.
+                self.completed = count.wrapping_sub(1);
+                return Poll::Ready(Err(Overrun));
+            }
+
+            let block = self.completed as usize % self.blocks;
+            self.completed = self.completed.wrapping_add(1);
+            Poll::Ready(Ok(block))
+        })
+        .await
+    }
+
+    /// Access block `n` of the buffer.
+    ///
+    /// Only blocks handed out by [`wait_block`](Self::wait_block) should be accessed, before
+    /// the DMA loops back to them.
+    pub fn block(&mut self, n: usize) -> &mut [W] {
+        assert!(n < self.blocks);
+        unsafe { core::slice::from_raw_parts_mut(self.buffer.add(n * self.block_len), self.block_len) }
+    }
+
+    /// Number of words in each block.
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+}
+
+impl<'a, C1: Channel, C2: Channel, W: Word> Drop for ContinuousTransfer<'a, C1, C2, W> {
+    fn drop(&mut self) {
+        unsafe {
+            // Stop the control channel from restarting the data channel, then abort both.
+            self.control.regs().al1_ctrl().write_value(0);
+            pac::DMA
+                .chan_abort()
+                .modify(|m| m.set_chan_abort((1 << self.data.number()) | (1 << self.control.number())));
+            while self.data.regs().ctrl_trig().read().busy() || self.control.regs().ctrl_trig().read().busy() {}
+        }
+    }
+}
 
 mod sealed {
     pub trait Channel {}
@@ -0,0 +1,309 @@
+//! Flash driver, erasing and programming the external QSPI flash with the bootrom routines.
+//!
+//! The flash can't be read while it is erased or programmed, and the code normally runs from
+//! it through XIP. Each operation therefore runs from RAM with interrupts disabled, and with
+//! the other core paused in RAM if it was started with
+//! [`spawn_core1`](crate::multicore::spawn_core1). Afterwards, XIP is set up again with the
+//! second stage bootloader. DMA transfers must not read from flash during an operation.
+//!
+//! Operations are done 4 KiB sector by sector for erases and 256-byte page by page for writes,
+//! so interrupts are only disabled for one sector or page at a time: about 50 ms for an erase,
+//! and 1 ms for a write.
+//!
+//! The unique ID and the JEDEC ID of the flash chip can also be read, for example to derive a
+//! serial number or to check the size of the flash.
+//!
+//! The size of the flash depends on the board, and is given as the `FLASH_SIZE` parameter of
+//! [`Flash`]. Offsets are from the start of the flash, which is mapped at [`FLASH_BASE`] and
+//! also holds the program, so the region used for data must be kept out of it in `memory.x`.
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embedded_storage::nor_flash::{
+    ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::peripherals::FLASH;
+use crate::{multicore, pac, rom_data, Peripheral};
+
+/// Address at which the flash is mapped through XIP.
+pub const FLASH_BASE: usize = 0x1000_0000;
+
+/// Erase size of the flash in bytes.
+pub const ERASE_SIZE: usize = 4096;
+
+/// Size of the pages programmed by the bootrom, in bytes.
+pub const PAGE_SIZE: usize = 256;
+
+/// Size of the unique ID of the flash chip, in bytes.
+pub const UNIQUE_ID_SIZE: usize = 8;
+
+// Flash commands, and the number of dummy bytes sent before the response.
+const CMD_READ_UNIQUE_ID: u8 = 0x4b;
+const UNIQUE_ID_DUMMY_BYTES: usize = 4;
+const CMD_READ_JEDEC_ID: u8 = 0x9f;
+
+/// Longest command sent with [`Operation::Command`], including its response.
+const MAX_COMMAND_LEN: usize = 1 + UNIQUE_ID_DUMMY_BYTES + UNIQUE_ID_SIZE;
+
+/// Error type for flash operations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Operation using a location not in flash.
+    OutOfBounds,
+    /// Erase not aligned to [`ERASE_SIZE`].
+    Unaligned,
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::Unaligned => NorFlashErrorKind::NotAligned,
+        }
+    }
+}
+
+/// Flash driver for a flash of `FLASH_SIZE` bytes, implementing the `embedded-storage` traits.
+///
+/// Writes can start at any byte, and bytes can be written several times as long as bits only
+/// go from 1 to 0.
+pub struct Flash<'d, const FLASH_SIZE: usize> {
+    _p: PeripheralRef<'d, FLASH>,
+}
+
+impl<'d, const FLASH_SIZE: usize> Flash<'d, FLASH_SIZE> {
+    /// Create a new flash driver.
+    pub fn new(_p: impl Peripheral<P = FLASH> + 'd) -> Self {
+        into_ref!(_p);
+        Self { _p }
+    }
+
+    fn check_range(offset: u32, len: usize) -> Result<(), Error> {
+        if offset as usize > FLASH_SIZE || len > FLASH_SIZE - offset as usize {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Read flash at `offset` into `bytes`.
+    pub fn blocking_read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        Self::check_range(offset, bytes.len())?;
+
+        let flash_data =
+            unsafe { core::slice::from_raw_parts((FLASH_BASE + offset as usize) as *const u8, bytes.len()) };
+        bytes.copy_from_slice(flash_data);
+        Ok(())
+    }
+
+    /// Erase the sectors from `from` to `to`, which must be aligned to [`ERASE_SIZE`].
+    pub fn blocking_erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if to < from {
+            return Err(Error::OutOfBounds);
+        }
+        Self::check_range(from, (to - from) as usize)?;
+        if from as usize % ERASE_SIZE != 0 || to as usize % ERASE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+
+        for sector in (from..to).step_by(ERASE_SIZE) {
+            unsafe { run(&Operation::Erase { addr: sector }) };
+        }
+        Ok(())
+    }
+
+    /// Write `bytes` at `offset`. The flash must have been erased first, or the written bytes
+    /// are ANDed with the previous ones.
+    pub fn blocking_write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        Self::check_range(offset, bytes.len())?;
+
+        // The bootrom programs whole pages, from RAM. Bytes outside of `bytes` are left as they
+        // are by programming them to 0xFF.
+        let mut page = [0xFFu8; PAGE_SIZE];
+        let mut offset = offset as usize;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let page_start = offset - offset % PAGE_SIZE;
+            let start = offset - page_start;
+            let len = bytes.len().min(PAGE_SIZE - start);
+
+            page.fill(0xFF);
+            page[start..start + len].copy_from_slice(&bytes[..len]);
+            unsafe {
+                run(&Operation::Program {
+                    addr: page_start as u32,
+                    data: page.as_ptr(),
+                })
+            };
+
+            offset += len;
+            bytes = &bytes[len..];
+        }
+        Ok(())
+    }
+
+    /// Read the 64-bit unique ID of the flash chip.
+    pub fn blocking_unique_id(&mut self) -> [u8; UNIQUE_ID_SIZE] {
+        let mut buf = [0; MAX_COMMAND_LEN];
+        buf[0] = CMD_READ_UNIQUE_ID;
+        command(&mut buf);
+
+        let mut uid = [0; UNIQUE_ID_SIZE];
+        uid.copy_from_slice(&buf[1 + UNIQUE_ID_DUMMY_BYTES..]);
+        uid
+    }
+
+    /// Read the JEDEC ID of the flash chip: the manufacturer ID in bits 16 to 23, followed by the
+    /// memory type and the capacity. For most chips, the capacity byte is the base 2 logarithm of
+    /// the size of the flash in bytes.
+    pub fn blocking_jedec_id(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        buf[0] = CMD_READ_JEDEC_ID;
+        command(&mut buf);
+
+        u32::from_be_bytes([0, buf[1], buf[2], buf[3]])
+    }
+}
+
+/// Send the command in `buf` to the flash chip, replacing `buf` with the bytes read back.
+fn command(buf: &mut [u8]) {
+    assert!(buf.len() <= MAX_COMMAND_LEN);
+    unsafe {
+        run(&Operation::Command {
+            buf: buf.as_mut_ptr(),
+            len: buf.len(),
+        })
+    };
+}
+
+/// Block size and command used by the bootrom for erases larger than a block, which never
+/// happens as erases are done sector by sector.
+const BLOCK_SIZE: u32 = 1 << 16;
+const BLOCK_ERASE_CMD: u8 = 0xd8;
+
+enum Operation {
+    /// Erase the sector at `addr`.
+    Erase { addr: u32 },
+    /// Program the page at `addr` with the `PAGE_SIZE` bytes at `data`, which must be in RAM.
+    Program { addr: u32, data: *const u8 },
+    /// Send the `len` bytes at `buf`, which must be in RAM, to the flash chip, replacing them
+    /// with the bytes read back.
+    Command { buf: *mut u8, len: usize },
+}
+
+// Bits of the control register of the chip select pin, forcing it low or high.
+const CS_OUTOVER_MASK: u32 = 0b11 << 8;
+const CS_OUTOVER_LOW: u32 = 0b10 << 8;
+const CS_OUTOVER_HIGH: u32 = 0b11 << 8;
+
+// Bits of the status register of the SSI.
+const SSI_SR_TFNF: u32 = 1 << 1;
+const SSI_SR_RFNE: u32 = 1 << 3;
+
+/// Number of bytes sent ahead of the received ones, keeping the 16-byte RX FIFO from
+/// overflowing.
+const SSI_MAX_IN_FLIGHT: usize = 14;
+
+/// The functions called while XIP is disabled. They must all be in ROM or RAM.
+struct FlashFunctions {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    /// Copy of the second stage bootloader in RAM, which sets up XIP again with the fast
+    /// read mode of the flash chip.
+    enter_xip: unsafe extern "C" fn(),
+    /// Registers used to send commands.
+    cs_ctrl: *mut u32,
+    ssi_sr: *const u32,
+    ssi_dr0: *mut u32,
+}
+
+/// Run `op`, with XIP disabled.
+unsafe fn run(op: &Operation) {
+    // Looking up the bootrom functions runs code in flash, so it is done beforehand.
+    let mut boot2 = [0u32; 64];
+    core::ptr::copy_nonoverlapping(crate::BOOT2.as_ptr(), boot2.as_mut_ptr() as *mut u8, crate::BOOT2.len());
+    let functions = FlashFunctions {
+        connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+        flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+        flash_range_erase: rom_data::flash_range_erase::ptr(),
+        flash_range_program: rom_data::flash_range_program::ptr(),
+        flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        // The second stage bootloader is Thumb code.
+        enter_xip: core::mem::transmute(boot2.as_ptr() as usize | 1),
+        cs_ctrl: pac::IO_QSPI.gpio(1).ctrl().ptr() as *mut u32,
+        ssi_sr: pac::XIP_SSI.sr().ptr() as *const u32,
+        ssi_dr0: pac::XIP_SSI.dr0().ptr() as *mut u32,
+    };
+
+    multicore::with_other_core_paused(|| cortex_m::interrupt::free(|_| run_in_ram(&functions, op)));
+}
+
+/// Run `op` from RAM. Only the functions in `functions` are called, and there must be no
+/// other calls, as code in flash can't run until XIP is set up again. The registers in
+/// `functions` are accessed directly for the same reason.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn run_in_ram(functions: &FlashFunctions, op: &Operation) {
+    (functions.connect_internal_flash)();
+    (functions.flash_exit_xip)();
+    match *op {
+        Operation::Erase { addr } => (functions.flash_range_erase)(addr, ERASE_SIZE, BLOCK_SIZE, BLOCK_ERASE_CMD),
+        Operation::Program { addr, data } => (functions.flash_range_program)(addr, data, PAGE_SIZE),
+        Operation::Command { buf, len } => {
+            let cs = core::ptr::read_volatile(functions.cs_ctrl) & !CS_OUTOVER_MASK;
+            core::ptr::write_volatile(functions.cs_ctrl, cs | CS_OUTOVER_LOW);
+
+            let (mut tx, mut rx) = (0, 0);
+            while rx < len {
+                let sr = core::ptr::read_volatile(functions.ssi_sr);
+                if sr & SSI_SR_TFNF != 0 && tx < len && tx - rx < SSI_MAX_IN_FLIGHT {
+                    core::ptr::write_volatile(functions.ssi_dr0, *buf.add(tx) as u32);
+                    tx += 1;
+                }
+                if sr & SSI_SR_RFNE != 0 {
+                    *buf.add(rx) = core::ptr::read_volatile(functions.ssi_dr0) as u8;
+                    rx += 1;
+                }
+            }
+
+            // Deselect the chip. The bootrom removes the override when flushing the cache.
+            core::ptr::write_volatile(functions.cs_ctrl, cs | CS_OUTOVER_HIGH);
+        }
+    }
+    (functions.flash_flush_cache)();
+    (functions.enter_xip)();
+}
+
+impl<'d, const FLASH_SIZE: usize> ErrorType for Flash<'d, FLASH_SIZE> {
+    type Error = Error;
+}
+
+impl<'d, const FLASH_SIZE: usize> ReadNorFlash for Flash<'d, FLASH_SIZE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.blocking_read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl<'d, const FLASH_SIZE: usize> MultiwriteNorFlash for Flash<'d, FLASH_SIZE> {}
+
+impl<'d, const FLASH_SIZE: usize> NorFlash for Flash<'d, FLASH_SIZE> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.blocking_erase(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.blocking_write(offset, bytes)
+    }
+}
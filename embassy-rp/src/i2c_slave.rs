@@ -0,0 +1,333 @@
+//! I2C slave (target) mode.
+//!
+//! [`I2cSlave`] answers to a 7-bit address on the bus, and optionally to the general call
+//! address. Transactions are handled in two steps: [`I2cSlave::listen`] waits for the master to
+//! address the slave, receiving any bytes it writes, and returns a [`Command`]. For read
+//! commands, the master is kept waiting by clock stretching until the data is handed to
+//! [`I2cSlave::respond_to_read`].
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_cortex_m::interrupt::{Interrupt, InterruptExt};
+use embassy_hal_common::into_ref;
+use pac::i2c;
+
+use crate::gpio::sealed::Pin as _;
+use crate::i2c::{i2c_reserved_addr, AbortReason, Instance, SclPin, SdaPin, FIFO_SIZE};
+use crate::{pac, Peripheral};
+
+/// I2C slave error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The master aborted the transfer.
+    Abort(AbortReason),
+    /// The master wrote more bytes than fit in the buffer. The buffer holds the first bytes,
+    /// the rest were dropped.
+    PartialWrite(usize),
+    /// The master sent a general call with more bytes than fit in the buffer. The buffer holds
+    /// the first bytes, the rest were dropped.
+    PartialGeneralCall(usize),
+}
+
+/// Transaction started by the master, returned by [`I2cSlave::listen`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// The master sent a general call, with this number of bytes.
+    GeneralCall(usize),
+    /// The master wants to read. Respond with [`I2cSlave::respond_to_read`].
+    Read,
+    /// The master wrote this number of bytes.
+    Write(usize),
+    /// The master wrote this number of bytes, then wants to read after a repeated start.
+    /// Respond with [`I2cSlave::respond_to_read`].
+    WriteRead(usize),
+}
+
+/// Outcome of [`I2cSlave::respond_to_read`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReadStatus {
+    /// The master read all the bytes of the buffer, and ended the transfer.
+    Done,
+    /// The master read all the bytes of the buffer, and wants more. Call
+    /// [`I2cSlave::respond_to_read`] again.
+    NeedMoreBytes,
+    /// The master ended the transfer before reading this number of bytes of the buffer.
+    LeftoverBytes(u16),
+}
+
+/// I2C slave configuration.
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// 7-bit address the slave answers to.
+    pub addr: u16,
+    /// Whether to answer to general calls, at address 0.
+    pub general_call: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: 0x55,
+            general_call: true,
+        }
+    }
+}
+
+/// I2C slave driver.
+pub struct I2cSlave<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> I2cSlave<'d, T> {
+    /// Create a new I2C slave driver.
+    ///
+    /// Panics if the address is reserved or larger than 7 bits.
+    pub fn new(
+        _peri: impl Peripheral<P = T> + 'd,
+        scl: impl Peripheral<P = impl SclPin<T>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
+        irq: impl Peripheral<P = T::Interrupt> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(_peri, scl, sda, irq);
+
+        assert!(config.addr < 0x80 && !i2c_reserved_addr(config.addr));
+
+        let p = T::regs();
+        unsafe {
+            p.ic_enable().write(|w| w.set_enable(false));
+
+            p.ic_sar().write(|w| w.set_ic_sar(config.addr));
+            p.ic_con().modify(|w| {
+                w.set_master_mode(false);
+                w.set_ic_slave_disable(false);
+                w.set_tx_empty_ctrl(true);
+                // Stretch the clock instead of dropping bytes when the FIFO is full.
+                w.set_rx_fifo_full_hld_ctrl(true);
+                // Only report stops of transfers that addressed us.
+                w.set_stop_det_ifaddressed(true);
+            });
+            p.ic_ack_general_call()
+                .write(|w| w.set_ack_gen_call(config.general_call));
+
+            // Interrupt as soon as there is a received byte.
+            p.ic_tx_tl().write(|w| w.set_tx_tl(0));
+            p.ic_rx_tl().write(|w| w.set_rx_tl(0));
+            p.ic_intr_mask().write_value(i2c::regs::IcIntrMask(0));
+
+            // Configure SCL & SDA pins
+            scl.io().ctrl().write(|w| w.set_funcsel(3));
+            sda.io().ctrl().write(|w| w.set_funcsel(3));
+
+            scl.pad_ctrl().write(|w| {
+                w.set_schmitt(true);
+                w.set_ie(true);
+                w.set_od(false);
+                w.set_pue(true);
+                w.set_pde(false);
+            });
+            sda.pad_ctrl().write(|w| {
+                w.set_schmitt(true);
+                w.set_ie(true);
+                w.set_od(false);
+                w.set_pue(true);
+                w.set_pde(false);
+            });
+
+            p.ic_clr_intr().read();
+            p.ic_enable().write(|w| w.set_enable(true));
+        }
+
+        irq.disable();
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self { phantom: PhantomData }
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        // Mask everything, the futures unmask what they wait for before waiting again.
+        unsafe { T::regs().ic_intr_mask().write_value(i2c::regs::IcIntrMask(0)) };
+        T::waker().wake();
+    }
+
+    /// Wait for `f` to return `Ready`, enabling the interrupts set by `mask` between polls.
+    async fn wait_on<R>(&mut self, mut f: impl FnMut() -> Poll<R>, mask: impl Fn(&mut i2c::regs::IcIntrMask)) -> R {
+        poll_fn(|cx| {
+            T::waker().register(cx.waker());
+            let r = f();
+            if r.is_pending() {
+                unsafe { T::regs().ic_intr_mask().write(|w| mask(w)) };
+            }
+            r
+        })
+        .await
+    }
+
+    /// Move received bytes from the FIFO to `buffer` starting at `len`, returning the new
+    /// length. Bytes that don't fit are dropped, setting `overflow`.
+    fn drain_fifo(buffer: &mut [u8], mut len: usize, overflow: &mut bool) -> usize {
+        let p = T::regs();
+        unsafe {
+            for _ in 0..p.ic_rxflr().read().rxflr() {
+                let byte = p.ic_data_cmd().read().dat();
+                match buffer.get_mut(len) {
+                    Some(slot) => {
+                        *slot = byte;
+                        len += 1;
+                    }
+                    None => *overflow = true,
+                }
+            }
+        }
+        len
+    }
+
+    /// Wait for the master to address this slave, receiving the bytes it writes into `buffer`.
+    ///
+    /// For [`Command::Read`] and [`Command::WriteRead`], the master waits until the data is
+    /// passed to [`respond_to_read`](Self::respond_to_read), which must be called next.
+    pub async fn listen(&mut self, buffer: &mut [u8]) -> Result<Command, Error> {
+        let p = T::regs();
+        let mut len = 0;
+        let mut overflow = false;
+        let mut general_call = false;
+
+        self.wait_on(
+            || unsafe {
+                let stat = p.ic_raw_intr_stat().read();
+                len = Self::drain_fifo(buffer, len, &mut overflow);
+
+                if stat.gen_call() {
+                    p.ic_clr_gen_call().read();
+                    general_call = true;
+                }
+
+                if stat.rd_req() {
+                    // Leave RD_REQ set, `respond_to_read` clears it once the FIFO is filled.
+                    p.ic_clr_restart_det().read();
+                    return Poll::Ready(if overflow {
+                        Err(Error::PartialWrite(len))
+                    } else if len > 0 {
+                        Ok(Command::WriteRead(len))
+                    } else {
+                        Ok(Command::Read)
+                    });
+                }
+
+                if stat.stop_det() {
+                    p.ic_clr_stop_det().read();
+                    p.ic_clr_restart_det().read();
+                    if len == 0 && !general_call {
+                        // A zero-length write, for example an address probe.
+                        return Poll::Ready(Ok(Command::Write(0)));
+                    }
+                    return Poll::Ready(match (general_call, overflow) {
+                        (true, false) => Ok(Command::GeneralCall(len)),
+                        (true, true) => Err(Error::PartialGeneralCall(len)),
+                        (false, false) => Ok(Command::Write(len)),
+                        (false, true) => Err(Error::PartialWrite(len)),
+                    });
+                }
+
+                Poll::Pending
+            },
+            |w| {
+                w.set_m_rx_full(true);
+                w.set_m_rd_req(true);
+                w.set_m_stop_det(true);
+                w.set_m_gen_call(true);
+            },
+        )
+        .await
+    }
+
+    /// Send `buffer` to the master, after [`listen`](Self::listen) returned a read command.
+    ///
+    /// Returns [`ReadStatus::NeedMoreBytes`] if the master keeps reading after the whole
+    /// buffer was sent, in which case this must be called again with more data.
+    pub async fn respond_to_read(&mut self, buffer: &[u8]) -> Result<ReadStatus, Error> {
+        let p = T::regs();
+        let mut sent = 0;
+
+        self.wait_on(
+            || unsafe {
+                let stat = p.ic_raw_intr_stat().read();
+
+                if stat.tx_abrt() {
+                    let abort_reason = p.ic_tx_abrt_source().read();
+                    p.ic_clr_tx_abrt().read();
+                    // The master ending the read while there is data left in the FIFO flushes
+                    // it, which isn't an error.
+                    if !abort_reason.abrt_slvflush_txfifo() {
+                        return Poll::Ready(Err(Error::Abort(AbortReason::Other(abort_reason.0))));
+                    }
+                }
+
+                if stat.rx_done() || stat.stop_det() {
+                    // The master NACKed the last byte it wanted. Bytes left in the FIFO are
+                    // flushed by the hardware on the next read request.
+                    p.ic_clr_rx_done().read();
+                    p.ic_clr_stop_det().read();
+                    let unsent = (buffer.len() - sent) as u16 + p.ic_txflr().read().txflr() as u16;
+                    return Poll::Ready(Ok(if unsent == 0 {
+                        ReadStatus::Done
+                    } else {
+                        ReadStatus::LeftoverBytes(unsent)
+                    }));
+                }
+
+                if stat.rd_req() {
+                    if sent == buffer.len() {
+                        return Poll::Ready(Ok(ReadStatus::NeedMoreBytes));
+                    }
+
+                    let space = FIFO_SIZE - p.ic_txflr().read().txflr();
+                    for &byte in buffer[sent..].iter().take(space as usize) {
+                        p.ic_data_cmd().write(|w| w.set_dat(byte));
+                        sent += 1;
+                    }
+                    // The data is ready, let the master clock it out.
+                    p.ic_clr_rd_req().read();
+                }
+
+                Poll::Pending
+            },
+            |w| {
+                w.set_m_rd_req(true);
+                w.set_m_rx_done(true);
+                w.set_m_tx_abrt(true);
+                w.set_m_stop_det(true);
+            },
+        )
+        .await
+    }
+
+    /// Send `buffer` to the master, then `fill` for as long as it keeps reading.
+    pub async fn respond_and_fill(&mut self, buffer: &[u8], fill: u8) -> Result<(), Error> {
+        let mut status = self.respond_to_read(buffer).await?;
+        while status == ReadStatus::NeedMoreBytes {
+            status = self.respond_to_read(&[fill]).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<'d, T: Instance> Drop for I2cSlave<'d, T> {
+    fn drop(&mut self) {
+        let p = T::regs();
+        unsafe {
+            T::Interrupt::steal().disable();
+            p.ic_intr_mask().write_value(i2c::regs::IcIntrMask(0));
+            p.ic_enable().write(|w| w.set_enable(false));
+        }
+    }
+}
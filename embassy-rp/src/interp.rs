@@ -0,0 +1,230 @@
+//! Interpolators (SIO INTERP)
+//!
+//! Each core has two interpolators, `INTERP0` and `INTERP1`, with two lanes each. A lane
+//! shifts and masks its accumulator, adds a base to it, and optionally adds the result back to
+//! the accumulator, all in a single cycle when its result is popped. This speeds up loops doing
+//! fixed-point arithmetic, like texture mapping or DSP.
+//!
+//! The interpolator registers are per core: an [`Interp`] uses the interpolators of the core it
+//! runs on. Their state is not saved when an interrupt handler runs, so an [`Interp`] must not
+//! be used from both thread mode and an interrupt on the same core.
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use crate::{pac, peripherals, Peripheral};
+
+/// A lane of an interpolator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Lane {
+    Lane0,
+    Lane1,
+}
+
+impl Lane {
+    fn index(self) -> usize {
+        match self {
+            Lane::Lane0 => 0,
+            Lane::Lane1 => 1,
+        }
+    }
+}
+
+/// Configuration of a lane.
+///
+/// The lane result is `base + ((accumulator >> shift) & mask)`, where the mask keeps bits
+/// `mask_lsb` to `mask_msb` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LaneConfig {
+    /// Logical right shift applied to the accumulator, from 0 to 31.
+    pub shift: u8,
+    /// Least significant bit kept by the mask, from 0 to 31.
+    pub mask_lsb: u8,
+    /// Most significant bit kept by the mask, from 0 to 31.
+    pub mask_msb: u8,
+    /// Sign-extend the masked value from `mask_msb` before adding the base.
+    pub signed: bool,
+    /// Use the accumulator of the other lane as input.
+    pub cross_input: bool,
+    /// Feed the result of the other lane back into the accumulator when popping.
+    pub cross_result: bool,
+    /// Add the unshifted and unmasked input to the base for the lane result, which is what is
+    /// written back to the accumulator. The full result still uses the shifted and masked value.
+    pub add_raw: bool,
+    /// ORed into bits 29 to 31 of the result, for example to point into a memory region.
+    pub force_msb: u8,
+}
+
+impl Default for LaneConfig {
+    fn default() -> Self {
+        Self {
+            shift: 0,
+            mask_lsb: 0,
+            mask_msb: 31,
+            signed: false,
+            cross_input: false,
+            cross_result: false,
+            add_raw: false,
+            force_msb: 0,
+        }
+    }
+}
+
+impl LaneConfig {
+    fn bits(&self) -> u32 {
+        assert!(self.shift < 32 && self.mask_lsb < 32 && self.mask_msb < 32 && self.force_msb < 4);
+        assert!(self.mask_lsb <= self.mask_msb);
+
+        (self.shift as u32)
+            | (self.mask_lsb as u32) << 5
+            | (self.mask_msb as u32) << 10
+            | (self.signed as u32) << 15
+            | (self.cross_input as u32) << 16
+            | (self.cross_result as u32) << 17
+            | (self.add_raw as u32) << 18
+            | (self.force_msb as u32) << 19
+    }
+}
+
+const CTRL_BLEND: u32 = 1 << 21;
+const CTRL_CLAMP: u32 = 1 << 22;
+
+/// Interpolator driver.
+pub struct Interp<'d, T: Instance> {
+    _p: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: Instance> Interp<'d, T> {
+    /// Create a new interpolator driver, with both lanes in their default configuration and
+    /// the accumulators and bases cleared.
+    pub fn new(interp: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(interp);
+
+        let mut this = Self { _p: interp };
+        this.configure_lane(Lane::Lane0, &LaneConfig::default());
+        this.configure_lane(Lane::Lane1, &LaneConfig::default());
+        for n in 0..3 {
+            this.set_base(n, 0);
+        }
+        this.set_accumulator(Lane::Lane0, 0);
+        this.set_accumulator(Lane::Lane1, 0);
+        this
+    }
+
+    fn regs() -> pac::sio::Interp {
+        pac::SIO.interp(T::NUMBER)
+    }
+
+    /// Configure `lane`.
+    ///
+    /// This keeps the blend and clamp modes, set with `set_blend` and `set_clamp`.
+    pub fn configure_lane(&mut self, lane: Lane, config: &LaneConfig) {
+        let bits = config.bits();
+        unsafe {
+            Self::regs()
+                .ctrl_lane(lane.index())
+                .modify(|w| w.0 = (w.0 & (CTRL_BLEND | CTRL_CLAMP)) | bits);
+        }
+    }
+
+    /// Set the accumulator of `lane`.
+    pub fn set_accumulator(&mut self, lane: Lane, value: u32) {
+        unsafe { Self::regs().accum(lane.index()).write_value(value) }
+    }
+
+    /// Get the accumulator of `lane`.
+    pub fn accumulator(&self, lane: Lane) -> u32 {
+        unsafe { Self::regs().accum(lane.index()).read() }
+    }
+
+    /// Add `value` to the accumulator of `lane`, in a single write.
+    pub fn add_accumulator(&mut self, lane: Lane, value: u32) {
+        unsafe { Self::regs().accum_add(lane.index()).write_value(value) }
+    }
+
+    /// Set base `n`, from 0 to 2. Bases 0 and 1 are added to the results of lanes 0 and 1, and
+    /// base 2 to the full result.
+    pub fn set_base(&mut self, n: usize, value: u32) {
+        assert!(n < 3);
+        unsafe { Self::regs().base(n).write_value(value) }
+    }
+
+    /// Get base `n`, from 0 to 2.
+    pub fn base(&self, n: usize) -> u32 {
+        assert!(n < 3);
+        unsafe { Self::regs().base(n).read() }
+    }
+
+    /// Get the result of `lane`, without updating the accumulators.
+    pub fn peek(&self, lane: Lane) -> u32 {
+        unsafe { Self::regs().peek(lane.index()).read() }
+    }
+
+    /// Get the result of `lane`, writing the lane results back to the accumulators.
+    pub fn pop(&mut self, lane: Lane) -> u32 {
+        unsafe { Self::regs().pop(lane.index()).read() }
+    }
+
+    /// Get the full result, `base2` plus both lane results, without updating the accumulators.
+    pub fn peek_full(&self) -> u32 {
+        unsafe { Self::regs().peek_full().read() }
+    }
+
+    /// Get the full result, `base2` plus both lane results, writing the lane results back to
+    /// the accumulators.
+    pub fn pop_full(&mut self) -> u32 {
+        unsafe { Self::regs().pop_full().read() }
+    }
+
+    fn set_ctrl_lane0_flag(&mut self, flag: u32, enabled: bool) {
+        unsafe {
+            Self::regs()
+                .ctrl_lane(0)
+                .modify(|w| if enabled { w.0 |= flag } else { w.0 &= !flag })
+        }
+    }
+}
+
+impl<'d> Interp<'d, peripherals::INTERP0> {
+    /// Enable blend mode.
+    ///
+    /// The result of lane 1 becomes a linear interpolation between base 0 and base 1, by the
+    /// fraction given by the lowest 8 bits of the lane 1 shift and mask output, over 256. The
+    /// result of lane 0 is unaffected, and the full result is lane 1 plus base 2.
+    pub fn set_blend(&mut self, enabled: bool) {
+        self.set_ctrl_lane0_flag(CTRL_BLEND, enabled)
+    }
+}
+
+impl<'d> Interp<'d, peripherals::INTERP1> {
+    /// Enable clamp mode.
+    ///
+    /// The shifted and masked value of lane 0 is clamped between base 0 and base 1, and the
+    /// result of lane 0 is that clamped value, without adding a base. Lane 0 should be
+    /// `signed` to clamp signed values.
+    pub fn set_clamp(&mut self, enabled: bool) {
+        self.set_ctrl_lane0_flag(CTRL_CLAMP, enabled)
+    }
+}
+
+mod sealed {
+    pub trait Instance {
+        const NUMBER: usize;
+    }
+}
+
+/// Interpolator instance.
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {}
+
+macro_rules! impl_instance {
+    ($type:ident, $number:expr) => {
+        impl sealed::Instance for peripherals::$type {
+            const NUMBER: usize = $number;
+        }
+        impl Instance for peripherals::$type {}
+    };
+}
+
+impl_instance!(INTERP0, 0);
+impl_instance!(INTERP1, 1);
@@ -6,20 +6,31 @@ pub(crate) mod fmt;
 
 mod intrinsics;
 
+pub mod adc;
+pub mod bootsel;
+pub mod clocks;
 pub mod dma;
+pub mod flash;
 pub mod gpio;
 pub mod i2c;
+pub mod i2c_slave;
+pub mod interp;
 pub mod interrupt;
+pub mod multicore;
+pub mod pio;
+pub mod power;
+pub mod pwm;
 pub mod rom_data;
 pub mod rtc;
 pub mod spi;
+pub mod spinlock;
 #[cfg(feature = "time-driver")]
 pub mod timer;
 pub mod uart;
 #[cfg(feature = "nightly")]
 pub mod usb;
+pub mod watchdog;
 
-mod clocks;
 mod reset;
 
 // Reexports
@@ -95,6 +106,30 @@ embassy_hal_common::peripherals! {
     USB,
 
     RTC,
+
+    ADC,
+    ADC_TEMP_SENSOR,
+
+    PWM_CH0,
+    PWM_CH1,
+    PWM_CH2,
+    PWM_CH3,
+    PWM_CH4,
+    PWM_CH5,
+    PWM_CH6,
+    PWM_CH7,
+
+    PIO0,
+    PIO1,
+
+    INTERP0,
+    INTERP1,
+
+    CORE1,
+
+    WATCHDOG,
+
+    FLASH,
 }
 
 #[link_section = ".boot2"]
@@ -102,26 +137,33 @@ embassy_hal_common::peripherals! {
 static BOOT2: [u8; 256] = *include_bytes!("boot2.bin");
 
 pub mod config {
+    use crate::clocks::ClockConfig;
+
     #[non_exhaustive]
-    pub struct Config {}
+    pub struct Config {
+        pub clocks: ClockConfig,
+    }
 
     impl Default for Config {
         fn default() -> Self {
-            Self {}
+            Self {
+                clocks: ClockConfig::default(),
+            }
         }
     }
 }
 
-pub fn init(_config: config::Config) -> Peripherals {
+pub fn init(config: config::Config) -> Peripherals {
     // Do this first, so that it panics if user is calling `init` a second time
     // before doing anything important.
     let peripherals = Peripherals::take();
 
     unsafe {
-        clocks::init();
+        clocks::init(config.clocks);
         #[cfg(feature = "time-driver")]
         timer::init();
         dma::init();
+        pio::init();
     }
 
     peripherals
@@ -0,0 +1,485 @@
+//! Multicore support: starting core1 and exchanging messages between the cores.
+//!
+//! [`spawn_core1`] starts core1 on a function of its own, with its own stack. It is usually
+//! used to run an executor on core1, pinning the tasks spawned on it to that core:
+//!
+//! - A thread mode `Executor` works on either core. It sleeps with `wfe`, and tasks woken from
+//!   the other core wake it up with `sev`, which signals both cores.
+//! - An `InterruptExecutor` must be started from the core that should run it, as each core has
+//!   its own NVIC and the interrupt is only enabled on the core starting the executor. Its tasks
+//!   must only be woken from that same core, as waking a task pends the interrupt on the NVIC
+//!   of the core doing it.
+//!
+//! Interrupts of the HAL drivers are enabled on core0, which handles them whichever core the
+//! driver is used from.
+//!
+//! Data shared between the cores must be protected with multicore-safe primitives. In particular,
+//! the `critical-section-single-core` implementation of `cortex-m` only masks the interrupts of
+//! the current core, so it is not sound once core1 is running. Use the `critical-section-impl`
+//! feature of embassy-rp instead, or a [`HardwareSpinlockMutex`](crate::spinlock::HardwareSpinlockMutex).
+//!
+//! Simple messages can be exchanged with a [`Fifo`], which sends 32-bit words to the other
+//! core over the SIO FIFOs. A word can be a pointer to a larger message, as long as the
+//! sender doesn't touch it until the other core is done with it.
+//!
+//! Once core1 is started, the FIFO interrupts of both cores are used by embassy-rp, which also
+//! uses them to pause the other core while it writes to flash. They must not be enabled or
+//! given a handler by the application.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU32, Ordering};
+use core::task::Poll;
+
+use embassy_cortex_m::interrupt::{Interrupt, InterruptExt};
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::{interrupt, pac, peripherals};
+
+const NEW_AW: AtomicWaker = AtomicWaker::new();
+/// Wakers of the receivers, indexed by the core receiving.
+static RX_WAKERS: [AtomicWaker; 2] = [NEW_AW; 2];
+/// Wakers of the senders, indexed by the core sending.
+static TX_WAKERS: [AtomicWaker; 2] = [NEW_AW; 2];
+
+/// Whether core1 was started, and can be paused.
+static CORE1_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Words sent over the FIFOs to control the other core. Data words with these values are sent
+/// prefixed with `ESCAPE`.
+const ESCAPE: u32 = 0xe5ca_9e00;
+const PAUSE: u32 = 0xe5ca_9e01;
+
+const NEW_BOOL: AtomicBool = AtomicBool::new(false);
+/// Pause requests, indexed by the core to pause. The paused core waits until it is cleared.
+static PAUSE_REQUESTED: [AtomicBool; 2] = [NEW_BOOL; 2];
+/// Set by a core while it is paused.
+static PAUSED: [AtomicBool; 2] = [NEW_BOOL; 2];
+
+/// Size of the receive queues, in words.
+const QUEUE_SIZE: usize = 16;
+
+/// Words received by a core, moved out of the hardware FIFO by its interrupt handler so that
+/// control words are handled even if nobody is receiving.
+///
+/// A sender only sends a word if the queue of the other core has space for it, counting the
+/// words still in the hardware FIFO, so the handler never has to leave words in the FIFO.
+struct Queue {
+    buf: [AtomicU32; QUEUE_SIZE],
+    /// Number of words pushed, only written by the interrupt handler.
+    pushed: AtomicU32,
+    /// Number of words received, only written by the receiver.
+    received: AtomicU32,
+    /// Number of words sent to this queue, only written by the sender on the other core.
+    sent: AtomicU32,
+    /// The last word read from the FIFO was `ESCAPE`.
+    escaped: AtomicBool,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        const NEW_WORD: AtomicU32 = AtomicU32::new(0);
+        Self {
+            buf: [NEW_WORD; QUEUE_SIZE],
+            pushed: AtomicU32::new(0),
+            received: AtomicU32::new(0),
+            sent: AtomicU32::new(0),
+            escaped: AtomicBool::new(false),
+        }
+    }
+
+    fn reset(&self) {
+        self.pushed.store(0, Ordering::Relaxed);
+        self.received.store(0, Ordering::Relaxed);
+        self.sent.store(0, Ordering::Relaxed);
+        self.escaped.store(false, Ordering::Relaxed);
+    }
+
+    fn push(&self, value: u32) {
+        let pushed = self.pushed.load(Ordering::Relaxed);
+        self.buf[pushed as usize % QUEUE_SIZE].store(value, Ordering::Relaxed);
+        self.pushed.store(pushed.wrapping_add(1), Ordering::Release);
+    }
+
+    fn pop(&self) -> Option<u32> {
+        let received = self.received.load(Ordering::Relaxed);
+        if received == self.pushed.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = self.buf[received as usize % QUEUE_SIZE].load(Ordering::Relaxed);
+        self.received.store(received.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    fn has_space(&self) -> bool {
+        let in_flight = self
+            .sent
+            .load(Ordering::Relaxed)
+            .wrapping_sub(self.received.load(Ordering::Acquire));
+        (in_flight as usize) < QUEUE_SIZE
+    }
+
+    fn count_sent(&self) {
+        let sent = self.sent.load(Ordering::Relaxed);
+        self.sent.store(sent.wrapping_add(1), Ordering::Release);
+    }
+}
+
+/// Receive queues, indexed by the core receiving.
+static QUEUES: [Queue; 2] = [Queue::new(), Queue::new()];
+
+/// Stack for core1.
+///
+/// It must live forever, as core1 keeps using it, so it is usually a `static mut`.
+#[repr(C, align(8))]
+pub struct Stack<const SIZE: usize> {
+    /// Memory used as the stack.
+    pub mem: [u8; SIZE],
+}
+
+impl<const SIZE: usize> Stack<SIZE> {
+    /// Create a new stack, filled with zeros.
+    pub const fn new() -> Self {
+        Self { mem: [0; SIZE] }
+    }
+}
+
+/// Start core1, running `entry` on `stack`.
+///
+/// `entry` must never return. Core1 is reset first, so this can also restart it.
+///
+/// Panics if core1 doesn't respond to the bootrom launch protocol.
+pub fn spawn_core1<F, const SIZE: usize>(
+    core1: impl Peripheral<P = peripherals::CORE1> + 'static,
+    stack: &'static mut Stack<SIZE>,
+    entry: F,
+) where
+    F: FnOnce() -> ! + Send + 'static,
+{
+    into_ref!(core1);
+
+    // The first two ignored `u64` parameters take up all the argument registers, so the other
+    // arguments are taken from the stack, where core0 puts them.
+    extern "C" fn core1_startup<F: FnOnce() -> !>(_: u64, _: u64, entry: &mut ManuallyDrop<F>) -> ! {
+        let entry = unsafe { ManuallyDrop::take(entry) };
+        enable_fifo_interrupt::<interrupt::SIO_IRQ_PROC1>();
+        // Tell core0 the entry function was moved out, so it can return.
+        fifo_write(1);
+        entry()
+    }
+
+    // The launch sequence goes through the FIFOs, so the interrupt must not take the words.
+    unsafe { interrupt::SIO_IRQ_PROC0::steal() }.disable();
+    CORE1_RUNNING.store(false, Ordering::Relaxed);
+    reset_core1();
+    QUEUES[0].reset();
+    QUEUES[1].reset();
+
+    // Round the top of the stack down, as it must be 8-byte aligned.
+    let mut stack_ptr = unsafe { stack.mem.as_mut_ptr().add(SIZE & !7) } as *mut usize;
+
+    // `entry` is moved to core1, which takes it from this stack frame before core0 returns.
+    let mut entry = ManuallyDrop::new(entry);
+
+    // Push the argument of `core1_startup`, keeping the stack aligned.
+    unsafe {
+        stack_ptr = stack_ptr.sub(2);
+        stack_ptr.cast::<&mut ManuallyDrop<F>>().write(&mut entry);
+    }
+
+    // The RP2040 has no caches, so core1 sees the writes to its stack as long as the compiler
+    // doesn't move them after the FIFO writes below.
+    compiler_fence(Ordering::Release);
+
+    let vector_table = unsafe { (*cortex_m::peripheral::SCB::PTR).vtor.read() };
+
+    // After reset, the bootrom of core1 waits for this sequence on the FIFO, echoing each word.
+    // It then sets the vector table and the stack pointer, and jumps to the entry point.
+    let cmd_seq = [
+        0,
+        0,
+        1,
+        vector_table as usize,
+        stack_ptr as usize,
+        core1_startup::<F> as usize,
+    ];
+
+    let mut seq = 0;
+    let mut fails = 0;
+    while seq < cmd_seq.len() {
+        let cmd = cmd_seq[seq] as u32;
+        if cmd == 0 {
+            fifo_drain();
+            cortex_m::asm::sev();
+        }
+        fifo_write(cmd);
+
+        if fifo_read() == cmd {
+            seq += 1;
+        } else {
+            seq = 0;
+            fails += 1;
+            if fails > 16 {
+                panic!("core1 not responding");
+            }
+        }
+    }
+
+    // Wait for core1 to take `entry` before its storage goes away.
+    fifo_read();
+
+    enable_fifo_interrupt::<interrupt::SIO_IRQ_PROC0>();
+    CORE1_RUNNING.store(true, Ordering::Release);
+}
+
+fn reset_core1() {
+    unsafe {
+        pac::PSM.frce_off().modify(|w| w.set_proc1(true));
+        while !pac::PSM.frce_off().read().proc1() {}
+        pac::PSM.frce_off().modify(|w| w.set_proc1(false));
+    }
+}
+
+/// Write a word to the FIFO to the other core, blocking while it is full.
+fn fifo_write(value: u32) {
+    unsafe {
+        let fifo = pac::SIO.fifo();
+        while !fifo.st().read().rdy() {}
+        fifo.wr().write_value(value);
+    }
+    // The bootrom of core1 sleeps with `wfe` while waiting for data.
+    cortex_m::asm::sev();
+}
+
+/// Read a word from the FIFO from the other core, blocking while it is empty.
+fn fifo_read() -> u32 {
+    unsafe {
+        let fifo = pac::SIO.fifo();
+        while !fifo.st().read().vld() {}
+        fifo.rd().read()
+    }
+}
+
+/// Discard the contents of the FIFO from the other core.
+fn fifo_drain() {
+    unsafe {
+        let fifo = pac::SIO.fifo();
+        while fifo.st().read().vld() {
+            let _ = fifo.rd().read();
+        }
+    }
+}
+
+/// Number of the core running this code, 0 or 1.
+pub(crate) fn current_core() -> usize {
+    unsafe { pac::SIO.cpuid().read() as usize }
+}
+
+/// Enable the FIFO interrupt of the current core, which moves received words to its queue.
+fn enable_fifo_interrupt<T: FifoInterrupt>() {
+    let irq = unsafe { T::steal() };
+    irq.disable();
+    // Clear the error flags, the interrupt fires when they are set.
+    unsafe { pac::SIO.fifo().st().write(|_| {}) };
+    irq.set_handler(on_fifo_interrupt::<T>);
+    irq.unpend();
+    irq.enable();
+}
+
+fn on_fifo_interrupt<T: FifoInterrupt>(_: *mut ()) {
+    let queue = &QUEUES[T::CORE];
+    let mut received = false;
+    unsafe {
+        let fifo = pac::SIO.fifo();
+        while fifo.st().read().vld() {
+            let value = fifo.rd().read();
+            if queue.escaped.load(Ordering::Relaxed) {
+                queue.escaped.store(false, Ordering::Relaxed);
+            } else if value == ESCAPE {
+                queue.escaped.store(true, Ordering::Relaxed);
+                continue;
+            } else if value == PAUSE {
+                pause(T::CORE);
+                continue;
+            }
+            queue.push(value);
+            received = true;
+        }
+    }
+    if received {
+        RX_WAKERS[T::CORE].wake();
+    }
+}
+
+/// Pause the current core until the other core clears its pause request.
+fn pause(core: usize) {
+    cortex_m::interrupt::free(|_| unsafe {
+        pause_in_ram(
+            &PAUSED[core] as *const AtomicBool as *mut u8,
+            &PAUSE_REQUESTED[core] as *const AtomicBool as *const u8,
+        )
+    })
+}
+
+/// Set `paused`, wait for `requested` to be cleared, and clear `paused`, running from RAM.
+///
+/// This is written in assembly so that no code in flash runs while `paused` is set, even in
+/// unoptimized builds.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn pause_in_ram(paused: *mut u8, requested: *const u8) {
+    core::arch::asm!(
+        "movs {tmp}, #1",
+        "strb {tmp}, [{paused}]",
+        "1:",
+        "ldrb {tmp}, [{requested}]",
+        "cmp {tmp}, #0",
+        "bne 1b",
+        "strb {tmp}, [{paused}]",
+        paused = in(reg) paused,
+        requested = in(reg) requested,
+        tmp = out(reg) _,
+        options(nostack),
+    );
+}
+
+/// Run `f` with the other core paused in RAM, with its interrupts disabled, so that `f` can
+/// disable XIP.
+///
+/// The other core can only be paused while its interrupts are enabled, so this must not be
+/// called with interrupts disabled, in particular from a critical section. Only one core may
+/// pause the other at a time.
+pub(crate) fn with_other_core_paused<R>(f: impl FnOnce() -> R) -> R {
+    if !CORE1_RUNNING.load(Ordering::Acquire) {
+        return f();
+    }
+
+    let other = 1 - current_core();
+    PAUSE_REQUESTED[other].store(true, Ordering::Release);
+    fifo_write(PAUSE);
+    while !PAUSED[other].load(Ordering::Acquire) {}
+
+    let r = f();
+
+    PAUSE_REQUESTED[other].store(false, Ordering::Release);
+    r
+}
+
+/// Error returned by [`Fifo::try_send`] when the FIFO to the other core is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Full;
+
+/// Async access to the SIO FIFOs of the current core.
+///
+/// Each core has a FIFO to the other core. A `Fifo` sends words to the other core, and
+/// receives the words the other core sends. It is created on the core using it, from that
+/// core's FIFO interrupt: `SIO_IRQ_PROC0` on core0, `SIO_IRQ_PROC1` on core1. The interrupt
+/// moves the received words to a queue of 16 words, and senders wait for space in the queue
+/// of the other core.
+///
+/// The FIFO interrupt wakes up receivers when data arrives, and receiving a word wakes up the
+/// sender of the other core if it was waiting for space. As for any task woken from the other
+/// core, this only works for tasks running on a thread mode `Executor`, see the
+/// [module docs](self).
+///
+/// The FIFOs are also used by [`spawn_core1`], so a `Fifo` must not be used while core1 is
+/// being started.
+pub struct Fifo<'d, T: FifoInterrupt> {
+    _irq: PeripheralRef<'d, T>,
+    // The FIFOs seen by a core are its own, so the driver must stay on it.
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl<'d, T: FifoInterrupt> Fifo<'d, T> {
+    /// Create a new FIFO driver for the current core.
+    ///
+    /// Panics if `irq` is not the FIFO interrupt of the current core.
+    pub fn new(irq: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(irq);
+        assert_eq!(current_core(), T::CORE, "FIFO interrupt of the other core");
+
+        // Already done by `spawn_core1` once core1 is running.
+        if !CORE1_RUNNING.load(Ordering::Acquire) {
+            enable_fifo_interrupt::<T>();
+        }
+
+        Self {
+            _irq: irq,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Send a word to the other core, if there is space for it.
+    pub fn try_send(&mut self, value: u32) -> Result<(), Full> {
+        let queue = &QUEUES[1 - T::CORE];
+        unsafe {
+            let fifo = pac::SIO.fifo();
+            if !queue.has_space() || !fifo.st().read().rdy() {
+                return Err(Full);
+            }
+            queue.count_sent();
+            if value == ESCAPE || value == PAUSE {
+                fifo.wr().write_value(ESCAPE);
+                // The interrupt of the other core empties the FIFO quickly.
+                while !fifo.st().read().rdy() {}
+            }
+            fifo.wr().write_value(value);
+        }
+        Ok(())
+    }
+
+    /// Send a word to the other core, waiting for space for it.
+    pub async fn send(&mut self, value: u32) {
+        poll_fn(|cx| {
+            TX_WAKERS[T::CORE].register(cx.waker());
+            match self.try_send(value) {
+                Ok(()) => Poll::Ready(()),
+                Err(Full) => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Receive a word from the other core, if there is one.
+    pub fn try_receive(&mut self) -> Option<u32> {
+        let value = QUEUES[T::CORE].pop()?;
+        // There is space for one more word from the other core now.
+        TX_WAKERS[1 - T::CORE].wake();
+        Some(value)
+    }
+
+    /// Receive a word from the other core, waiting for one to arrive.
+    pub async fn receive(&mut self) -> u32 {
+        poll_fn(|cx| {
+            RX_WAKERS[T::CORE].register(cx.waker());
+            match self.try_receive() {
+                Some(value) => Poll::Ready(value),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+mod sealed {
+    pub trait FifoInterrupt {
+        /// The core this interrupt is wired to.
+        const CORE: usize;
+    }
+}
+
+/// The SIO FIFO interrupt of a core.
+pub trait FifoInterrupt: Interrupt + sealed::FifoInterrupt {}
+
+impl sealed::FifoInterrupt for interrupt::SIO_IRQ_PROC0 {
+    const CORE: usize = 0;
+}
+impl FifoInterrupt for interrupt::SIO_IRQ_PROC0 {}
+
+impl sealed::FifoInterrupt for interrupt::SIO_IRQ_PROC1 {
+    const CORE: usize = 1;
+}
+impl FifoInterrupt for interrupt::SIO_IRQ_PROC1 {}
@@ -0,0 +1,160 @@
+//! I2S audio output on PIO, for DACs and amplifiers.
+//!
+//! A state machine generates the bit clock and the word select (LRCLK) signal, and shifts out
+//! stereo frames of two 16-bit samples. The frames are fed by two DMA channels looping over the
+//! blocks of a buffer, see [`dma::write_continuous`], so the audio keeps playing at the sample
+//! rate without CPU involvement per sample: the application only refills the blocks handed out
+//! by [`I2sOutput::wait_block`].
+
+use embassy_hal_common::Peripheral;
+
+use super::sealed::Instance as _;
+use super::{Common, Config as PioConfig, FifoJoin, Instance, LoadError, PioPin, ShiftDirection, StateMachine};
+use crate::dma::{self, BlockList, Channel, ContinuousTransfer, Overrun};
+use crate::gpio::Pin as GpioPin;
+
+/// Bits per frame: two 16-bit samples.
+const BITS_PER_FRAME: u32 = 32;
+
+/// Cycles of the state machine per bit.
+const CYCLES_PER_BIT: u32 = 2;
+
+/// I2S output configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    /// Frames per second.
+    pub sample_rate: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { sample_rate: 48_000 }
+    }
+}
+
+/// Pack a stereo frame in a buffer word.
+pub const fn frame(left: i16, right: i16) -> u32 {
+    (right as u16 as u32) << 16 | left as u16 as u32
+}
+
+/// I2S output, playing the frames of a buffer in a loop.
+///
+/// The buffer holds stereo frames packed with [`frame`].
+pub struct I2sOutput<'d, PIO: Instance, const SM: usize, C1: Channel, C2: Channel> {
+    transfer: ContinuousTransfer<'d, C1, C2, u32>,
+    sm: StateMachine<'d, PIO, SM>,
+    clkdiv: u32,
+    _pins: [PioPin<'d, PIO>; 3],
+}
+
+impl<'d, PIO: Instance, const SM: usize, C1: Channel, C2: Channel> I2sOutput<'d, PIO, SM, C1, C2> {
+    /// Create a new I2S output, loading its program in the instruction memory of `pio`, and
+    /// start playing `buf`.
+    ///
+    /// `buf` should hold the first frames to play, or silence. It is split into `blocks` blocks,
+    /// see [`dma::write_continuous`] for the constraints. `lrclk` must be the pin after `bclk`.
+    pub fn new(
+        pio: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        data_dma: impl Peripheral<P = C1> + 'd,
+        control_dma: impl Peripheral<P = C2> + 'd,
+        data: impl Peripheral<P = impl GpioPin> + 'd,
+        bclk: impl Peripheral<P = impl GpioPin> + 'd,
+        lrclk: impl Peripheral<P = impl GpioPin> + 'd,
+        buf: &'d mut [u32],
+        blocks: usize,
+        list: &'d mut BlockList,
+        config: Config,
+    ) -> Result<Self, LoadError> {
+        // The side-set drives BCLK with bit 0 and LRCLK with bit 1. Each bit is shifted out while
+        // BCLK is low, and LRCLK changes one bit before the end of each sample, as I2S requires.
+        let side_set = pio::SideSet::new(false, 2, false);
+        let mut a: pio::Assembler<32> = pio::Assembler::new_with_side_set(side_set);
+
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        let mut right = a.label();
+        let mut left = a.label();
+        a.set_with_side_set(pio::SetDestination::X, 14, 0b11);
+        a.bind(&mut wrap_target);
+        // The upper 16 bits of the frame, the right sample.
+        a.bind(&mut right);
+        a.out_with_side_set(pio::OutDestination::PINS, 1, 0b10);
+        a.jmp_with_side_set(pio::JmpCondition::XDecNonZero, &mut right, 0b11);
+        a.out_with_side_set(pio::OutDestination::PINS, 1, 0b00);
+        a.set_with_side_set(pio::SetDestination::X, 14, 0b01);
+        // The lower 16 bits, the left sample.
+        a.bind(&mut left);
+        a.out_with_side_set(pio::OutDestination::PINS, 1, 0b00);
+        a.jmp_with_side_set(pio::JmpCondition::XDecNonZero, &mut left, 0b01);
+        a.out_with_side_set(pio::OutDestination::PINS, 1, 0b10);
+        a.set_with_side_set(pio::SetDestination::X, 14, 0b11);
+        a.bind(&mut wrap_source);
+        let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+        let program = pio.load_program(&program)?;
+        let data = pio.make_pio_pin(data);
+        let bclk = pio.make_pio_pin(bclk);
+        let lrclk = pio.make_pio_pin(lrclk);
+
+        let mut cfg = PioConfig::default();
+        cfg.use_program(&program, &[&bclk, &lrclk]);
+        cfg.set_out_pins(&[&data]);
+        cfg.set_frequency(config.sample_rate * BITS_PER_FRAME * CYCLES_PER_BIT);
+        cfg.fifo_join = FifoJoin::TxOnly;
+        cfg.shift_out.auto_fill = true;
+        cfg.shift_out.threshold = 32;
+        cfg.shift_out.direction = ShiftDirection::Left;
+        sm.set_config(&cfg);
+
+        sm.set_pins(false, &[&data, &bclk, &lrclk]);
+        sm.set_pin_dirs(true, &[&data, &bclk, &lrclk]);
+
+        let dreq = PIO::PIO_NO * 8 + SM as u8;
+        let txf = PIO::PIO.txf(SM).ptr() as *mut u32;
+        let transfer = unsafe { dma::write_continuous(data_dma, control_dma, buf, txf, blocks, list, dreq) };
+
+        // Start once the DMA has filled the FIFO, so the first frames aren't cut.
+        while !sm.tx_full() {}
+        sm.set_enable(true);
+
+        Ok(Self {
+            transfer,
+            sm,
+            clkdiv: (cfg.clkdiv_int as u32) << 8 | cfg.clkdiv_frac as u32,
+            _pins: [data, bclk, lrclk],
+        })
+    }
+
+    /// Actual sample rate, which differs from the configured one if the system clock isn't a
+    /// multiple of it. The fractional clock divider adds jitter but keeps this average rate.
+    pub fn sample_rate(&self) -> u32 {
+        let cycles_per_frame = (BITS_PER_FRAME * CYCLES_PER_BIT) as u64;
+        ((crate::clocks::clk_sys_freq() as u64 * 256) / (self.clkdiv as u64 * cycles_per_frame)) as u32
+    }
+
+    /// Wait for the next block to be played, and return its index.
+    ///
+    /// The block should be refilled with [`block`](Self::block) before the DMA loops back to
+    /// it. Returns [`Overrun`] if the DMA looped back to blocks before they were handed out, in
+    /// which case they were played again.
+    pub async fn wait_block(&mut self) -> Result<usize, Overrun> {
+        self.transfer.wait_block().await
+    }
+
+    /// Access block `n` of the buffer.
+    pub fn block(&mut self, n: usize) -> &mut [u32] {
+        self.transfer.block(n)
+    }
+
+    /// Number of frames in each block.
+    pub fn block_len(&self) -> usize {
+        self.transfer.block_len()
+    }
+
+    /// Pause or resume the output. The clocks stop while paused.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.sm.set_enable(!paused)
+    }
+}
@@ -0,0 +1,867 @@
+//! Programmable I/O (PIO)
+//!
+//! Each of the two PIO blocks has 32 words of shared instruction memory, four state machines
+//! and eight IRQ flags. [`Pio::new`] splits a block into a [`Common`] handle, used to load
+//! programs and hand pins to the block, the four [`StateMachine`]s and the four [`Irq`] flags
+//! that can be waited on.
+//!
+//! Programs are assembled with the [`pio`](https://docs.rs/pio) crate, either at compile time
+//! with `pio_proc::pio_asm!` or at runtime with `pio::Assembler`. [`Common::load_program`]
+//! places them in free instruction memory, so drivers can share a block without agreeing on
+//! offsets, and [`Common::unload_program`] frees them once no state machine runs them anymore.
+//!
+//! Ready-made drivers built on PIO live in the submodules.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::Poll;
+
+use embassy_cortex_m::interrupt::{Interrupt, InterruptExt};
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+use pac::pio::vals::SmExecctrlStatusSel;
+
+use crate::dma::{self, Channel, Transfer, Word};
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::{AnyPin, Pin as GpioPin, Pull};
+use crate::{interrupt, pac, peripherals, RegExt};
+
+/// Number of state machines in each PIO block.
+pub const SM_COUNT: usize = 4;
+
+/// Number of words of instruction memory in each PIO block.
+pub const INSTR_MEM_SIZE: usize = 32;
+
+// The interrupt bits are the RXNEMPTY bits of the four state machines, then the TXNFULL bits,
+// then IRQ flags 0 to 3.
+const INT_RXNEMPTY: usize = 0;
+const INT_TXNFULL: usize = 4;
+const INT_IRQ: usize = 8;
+const INT_COUNT: usize = 12;
+
+const NEW_AW: AtomicWaker = AtomicWaker::new();
+const NEW_AWS: [AtomicWaker; INT_COUNT] = [NEW_AW; INT_COUNT];
+static WAKERS: [[AtomicWaker; INT_COUNT]; 2] = [NEW_AWS; 2];
+
+/// Instructions of the program each state machine is configured to run, as a mask of
+/// instruction memory addresses.
+const NEW_MASK: AtomicU32 = AtomicU32::new(0);
+const NEW_MASKS: [AtomicU32; SM_COUNT] = [NEW_MASK; SM_COUNT];
+static SM_PROGRAMS: [[AtomicU32; SM_COUNT]; 2] = [NEW_MASKS; 2];
+
+#[interrupt]
+unsafe fn PIO0_IRQ_0() {
+    on_interrupt::<peripherals::PIO0>();
+}
+
+#[interrupt]
+unsafe fn PIO1_IRQ_0() {
+    on_interrupt::<peripherals::PIO1>();
+}
+
+unsafe fn on_interrupt<PIO: Instance>() {
+    let irqs = PIO::PIO.irqs(0);
+    let ints = irqs.ints().read().0;
+
+    // The interrupts are level triggered, so disable the ones that fired. Waiting futures
+    // enable them again if they need to keep waiting.
+    irqs.inte().write_clear(|m| m.0 = ints);
+
+    for n in 0..INT_COUNT {
+        if ints & (1 << n) != 0 {
+            WAKERS[PIO::PIO_NO as usize][n].wake();
+        }
+    }
+}
+
+pub(crate) unsafe fn init() {
+    let irq = interrupt::PIO0_IRQ_0::steal();
+    irq.disable();
+    irq.set_priority(interrupt::Priority::P3);
+    pac::PIO0.irqs(0).inte().write(|m| m.0 = 0);
+    irq.enable();
+
+    let irq = interrupt::PIO1_IRQ_0::steal();
+    irq.disable();
+    irq.set_priority(interrupt::Priority::P3);
+    pac::PIO1.irqs(0).inte().write(|m| m.0 = 0);
+    irq.enable();
+}
+
+/// Wait until `f` returns a value, using interrupt `int` of the PIO block to wake up.
+async fn wait_for<PIO: Instance, T>(int: usize, mut f: impl FnMut() -> Option<T>) -> T {
+    poll_fn(|cx| {
+        WAKERS[PIO::PIO_NO as usize][int].register(cx.waker());
+
+        if let Some(val) = f() {
+            return Poll::Ready(val);
+        }
+
+        // The interrupt sources are levels, so the interrupt fires right away if the condition
+        // became true since `f` checked it.
+        unsafe { PIO::PIO.irqs(0).inte().write_set(|m| m.0 = 1 << int) };
+        Poll::Pending
+    })
+    .await
+}
+
+/// A PIO block, split into its parts.
+pub struct Pio<'d, PIO: Instance> {
+    pub common: Common<'d, PIO>,
+    pub irq0: Irq<'d, PIO, 0>,
+    pub irq1: Irq<'d, PIO, 1>,
+    pub irq2: Irq<'d, PIO, 2>,
+    pub irq3: Irq<'d, PIO, 3>,
+    pub sm0: StateMachine<'d, PIO, 0>,
+    pub sm1: StateMachine<'d, PIO, 1>,
+    pub sm2: StateMachine<'d, PIO, 2>,
+    pub sm3: StateMachine<'d, PIO, 3>,
+}
+
+impl<'d, PIO: Instance> Pio<'d, PIO> {
+    /// Take a PIO block, stopping all of its state machines.
+    pub fn new(pio: impl Peripheral<P = PIO> + 'd) -> Self {
+        into_ref!(pio);
+
+        unsafe {
+            PIO::PIO.ctrl().write(|w| w.set_sm_enable(0));
+            PIO::PIO.irqs(0).inte().write(|m| m.0 = 0);
+            PIO::PIO.irq().write(|w| w.set_irq(0xff));
+        }
+        for mask in &SM_PROGRAMS[PIO::PIO_NO as usize] {
+            mask.store(0, Ordering::Relaxed);
+        }
+
+        Self {
+            common: Common {
+                instructions_used: 0,
+                _pio: pio,
+            },
+            irq0: Irq { _pio: PhantomData },
+            irq1: Irq { _pio: PhantomData },
+            irq2: Irq { _pio: PhantomData },
+            irq3: Irq { _pio: PhantomData },
+            sm0: StateMachine { _pio: PhantomData },
+            sm1: StateMachine { _pio: PhantomData },
+            sm2: StateMachine { _pio: PhantomData },
+            sm3: StateMachine { _pio: PhantomData },
+        }
+    }
+}
+
+/// Errors when loading a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum LoadError {
+    /// There isn't a free run of instruction memory large enough for the program.
+    InsufficientSpace,
+    /// The program must be loaded at a fixed origin, but that memory is already in use.
+    AddressInUse,
+}
+
+/// A program loaded in the instruction memory of a PIO block.
+///
+/// Dropping it leaves the program loaded, so state machines can keep running it. Its memory is
+/// only freed by [`Common::unload_program`].
+pub struct LoadedProgram<'d, PIO: Instance> {
+    origin: u8,
+    len: u8,
+    wrap_top: u8,
+    wrap_bottom: u8,
+    side_set: pio::SideSet,
+    _pio: PhantomData<&'d PIO>,
+}
+
+impl<'d, PIO: Instance> LoadedProgram<'d, PIO> {
+    /// Address of the first instruction of the program.
+    pub fn origin(&self) -> u8 {
+        self.origin
+    }
+
+    /// Number of instructions of the program.
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    /// Instruction memory used by the program, as a mask of addresses.
+    pub fn mask(&self) -> u32 {
+        instr_mask(self.origin, self.len)
+    }
+}
+
+/// Mask of the `len` addresses of instruction memory from `origin`.
+fn instr_mask(origin: u8, len: u8) -> u32 {
+    (((1u64 << len) - 1) as u32) << origin
+}
+
+/// Parts of a PIO block shared by all its state machines.
+pub struct Common<'d, PIO: Instance> {
+    instructions_used: u32,
+    _pio: PeripheralRef<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> Common<'d, PIO> {
+    /// Load a program into instruction memory.
+    ///
+    /// Programs with an `.origin` are loaded there, others are placed in the highest free run
+    /// of memory that fits them. Jumps are relocated to the address the program is loaded at.
+    pub fn load_program<const N: usize>(
+        &mut self,
+        program: &pio::Program<N>,
+    ) -> Result<LoadedProgram<'d, PIO>, LoadError> {
+        let len = program.code.len();
+        assert!(len > 0 && len <= INSTR_MEM_SIZE);
+
+        let mask = instr_mask(0, len as u8);
+        let fits = |origin: usize| self.instructions_used & (mask << origin) == 0;
+        let origin = match program.origin {
+            Some(origin) => {
+                let origin = origin as usize;
+                if origin + len > INSTR_MEM_SIZE || !fits(origin) {
+                    return Err(LoadError::AddressInUse);
+                }
+                origin
+            }
+            None => (0..=INSTR_MEM_SIZE - len)
+                .rev()
+                .find(|&origin| fits(origin))
+                .ok_or(LoadError::InsufficientSpace)?,
+        };
+
+        for (i, &instr) in program.code.iter().enumerate() {
+            let instr = relocate(instr, origin as u8);
+            unsafe {
+                PIO::PIO.instr_mem(origin + i).write(|w| w.set_instr_mem(instr));
+            }
+        }
+        self.instructions_used |= mask << origin;
+
+        Ok(LoadedProgram {
+            origin: origin as u8,
+            len: len as u8,
+            wrap_top: origin as u8 + program.wrap.source,
+            wrap_bottom: origin as u8 + program.wrap.target,
+            side_set: program.side_set,
+            _pio: PhantomData,
+        })
+    }
+
+    /// Unload `program`, freeing its instruction memory for other programs.
+    ///
+    /// Panics if a state machine is still configured to run it, see
+    /// [`state_machines_using`](Self::state_machines_using).
+    pub fn unload_program(&mut self, program: LoadedProgram<'d, PIO>) {
+        assert!(
+            self.state_machines_using(&program) == 0,
+            "PIO program still used by a state machine"
+        );
+        self.instructions_used &= !program.mask();
+    }
+
+    /// State machines configured to run `program`, as a mask of state machine numbers.
+    ///
+    /// A state machine is using a program from [`StateMachine::set_config`] with a [`Config`]
+    /// for it, until it is dropped or configured with another program.
+    pub fn state_machines_using(&self, program: &LoadedProgram<'d, PIO>) -> u8 {
+        let mut users = 0;
+        for (sm, mask) in SM_PROGRAMS[PIO::PIO_NO as usize].iter().enumerate() {
+            if mask.load(Ordering::Relaxed) & program.mask() != 0 {
+                users |= 1 << sm;
+            }
+        }
+        users
+    }
+
+    /// Instruction memory used by loaded programs, as a mask of addresses.
+    pub fn used_instructions(&self) -> u32 {
+        self.instructions_used
+    }
+
+    /// Hand `pin` over to this PIO block.
+    ///
+    /// All state machines of the block can drive the pin, depending on how they are configured.
+    pub fn make_pio_pin(&mut self, pin: impl Peripheral<P = impl GpioPin> + 'd) -> PioPin<'d, PIO> {
+        into_ref!(pin);
+
+        unsafe {
+            pin.pad_ctrl().write(|w| w.set_ie(true));
+            pin.io().ctrl().write(|w| w.set_funcsel(PIO::FUNCSEL));
+        }
+
+        PioPin {
+            pin: pin.map_into(),
+            _pio: PhantomData,
+        }
+    }
+
+    /// Returns whether IRQ flag `n` is set.
+    pub fn check_irq(&self, n: u8) -> bool {
+        assert!(n < 8);
+        unsafe { PIO::PIO.irq().read().irq() & (1 << n) != 0 }
+    }
+
+    /// Set the IRQ flags in `mask`, as a state machine's `irq set` instruction does.
+    pub fn set_irq(&mut self, mask: u8) {
+        unsafe { PIO::PIO.irq_force().write(|w| w.set_irq_force(mask)) }
+    }
+
+    /// Clear the IRQ flags in `mask`, which releases state machines waiting on them.
+    pub fn clear_irq(&mut self, mask: u8) {
+        unsafe { PIO::PIO.irq().write(|w| w.set_irq(mask)) }
+    }
+}
+
+/// Relocate a JMP instruction of a program loaded at `origin`.
+fn relocate(instr: u16, origin: u8) -> u16 {
+    // JMP is the only instruction with opcode 0b000, its target is in the low 5 bits.
+    if instr & 0xe000 == 0 {
+        (instr & !0x1f) | ((instr + origin as u16) & 0x1f)
+    } else {
+        instr
+    }
+}
+
+/// A pin driven by a PIO block, see [`Common::make_pio_pin`].
+pub struct PioPin<'d, PIO: Instance> {
+    pin: PeripheralRef<'d, AnyPin>,
+    _pio: PhantomData<&'d mut PIO>,
+}
+
+impl<'d, PIO: Instance> PioPin<'d, PIO> {
+    /// GPIO number of the pin.
+    pub fn pin(&self) -> u8 {
+        self.pin.pin()
+    }
+
+    /// Set the pin's pull.
+    pub fn set_pull(&mut self, pull: Pull) {
+        unsafe {
+            self.pin.pad_ctrl().modify(|w| {
+                w.set_pue(pull == Pull::Up);
+                w.set_pde(pull == Pull::Down);
+            });
+        }
+    }
+
+    /// Bypass the 2-cycle input synchronizer of the pin.
+    ///
+    /// This lowers input latency, but is only safe for signals synchronous to the system clock.
+    pub fn set_input_sync_bypass(&mut self, bypass: bool) {
+        let mask = 1 << self.pin();
+        unsafe {
+            if bypass {
+                PIO::PIO.input_sync_bypass().write_set(|w| *w = mask);
+            } else {
+                PIO::PIO.input_sync_bypass().write_clear(|w| *w = mask);
+            }
+        }
+    }
+}
+
+impl<'d, PIO: Instance> Drop for PioPin<'d, PIO> {
+    fn drop(&mut self) {
+        unsafe {
+            self.pin.pad_ctrl().write(|_| {});
+            self.pin.io().ctrl().write(|w| {
+                w.set_funcsel(pac::io::vals::Gpio0ctrlFuncsel::NULL.0);
+            });
+        }
+    }
+}
+
+/// One of the IRQ flags 0 to 3 of a PIO block, which can interrupt the CPU.
+pub struct Irq<'d, PIO: Instance, const N: usize> {
+    _pio: PhantomData<&'d mut PIO>,
+}
+
+impl<'d, PIO: Instance, const N: usize> Irq<'d, PIO, N> {
+    /// Wait for a state machine to set the flag, then clear it.
+    pub async fn wait(&mut self) {
+        wait_for::<PIO, _>(INT_IRQ + N, || unsafe {
+            if PIO::PIO.irq().read().irq() & (1 << N) != 0 {
+                PIO::PIO.irq().write(|w| w.set_irq(1 << N));
+                Some(())
+            } else {
+                None
+            }
+        })
+        .await
+    }
+}
+
+/// Source of the status used by `mov x, status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StatusSource {
+    /// All ones while the TX FIFO holds fewer than `status_n` words, all zeros otherwise.
+    TxFifoLevel,
+    /// All ones while the RX FIFO holds fewer than `status_n` words, all zeros otherwise.
+    RxFifoLevel,
+}
+
+/// Direction a shift register shifts in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ShiftDirection {
+    Left,
+    Right,
+}
+
+/// Configuration of the input or output shift register of a state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ShiftConfig {
+    /// Number of bits shifted before an autopush or autopull happens, 1 to 32.
+    pub threshold: u8,
+    pub direction: ShiftDirection,
+    /// Autopush for the input shift register, autopull for the output one.
+    pub auto_fill: bool,
+}
+
+impl Default for ShiftConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 32,
+            direction: ShiftDirection::Right,
+            auto_fill: false,
+        }
+    }
+}
+
+/// How the FIFOs of a state machine are joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FifoJoin {
+    /// A 4-word TX FIFO and a 4-word RX FIFO.
+    Duplex,
+    /// An 8-word RX FIFO, and no TX FIFO.
+    RxOnly,
+    /// An 8-word TX FIFO, and no RX FIFO.
+    TxOnly,
+}
+
+/// State machine configuration.
+pub struct Config<'d, PIO: Instance> {
+    /// Integer part of the clock divider, 1 to 65535. The state machine runs at
+    /// `clk_sys / (clkdiv_int + clkdiv_frac / 256)`.
+    pub clkdiv_int: u16,
+    /// Fractional part of the clock divider, in 1/256ths.
+    pub clkdiv_frac: u8,
+    /// Keep driving the last `out` data on the pins, instead of only for the `out` cycle.
+    pub out_sticky: bool,
+    /// Use a bit of the `out` data as an auxiliary write enable.
+    pub inline_out_en: bool,
+    /// Bit used as write enable when `inline_out_en` is set.
+    pub out_en_sel: u8,
+    pub status_sel: StatusSource,
+    /// FIFO level compared against by `status_sel`.
+    pub status_n: u8,
+    pub shift_in: ShiftConfig,
+    pub shift_out: ShiftConfig,
+    pub fifo_join: FifoJoin,
+    start: u8,
+    /// Instructions of the program, see [`LoadedProgram::mask`].
+    program_mask: u32,
+    wrap_top: u8,
+    wrap_bottom: u8,
+    side_set: Option<pio::SideSet>,
+    sideset_base: u8,
+    out_base: u8,
+    out_count: u8,
+    set_base: u8,
+    set_count: u8,
+    in_base: u8,
+    jmp_pin: u8,
+    _pio: PhantomData<&'d mut PIO>,
+}
+
+impl<'d, PIO: Instance> Default for Config<'d, PIO> {
+    fn default() -> Self {
+        Self {
+            clkdiv_int: 1,
+            clkdiv_frac: 0,
+            out_sticky: false,
+            inline_out_en: false,
+            out_en_sel: 0,
+            status_sel: StatusSource::TxFifoLevel,
+            status_n: 0,
+            shift_in: ShiftConfig::default(),
+            shift_out: ShiftConfig::default(),
+            fifo_join: FifoJoin::Duplex,
+            start: 0,
+            program_mask: 0,
+            wrap_top: INSTR_MEM_SIZE as u8 - 1,
+            wrap_bottom: 0,
+            side_set: None,
+            sideset_base: 0,
+            out_base: 0,
+            out_count: 0,
+            set_base: 0,
+            set_count: 0,
+            in_base: 0,
+            jmp_pin: 0,
+            _pio: PhantomData,
+        }
+    }
+}
+
+impl<'d, PIO: Instance> Config<'d, PIO> {
+    /// Run `program`, with `side_set` as the pins driven by its side-set.
+    ///
+    /// Panics if the number of side-set pins doesn't match the program, or if the pins
+    /// aren't consecutive.
+    pub fn use_program(&mut self, program: &LoadedProgram<'d, PIO>, side_set: &[&PioPin<'d, PIO>]) {
+        let side_set_bits = program.side_set.bits() - program.side_set.optional() as u8;
+        assert_eq!(side_set.len(), side_set_bits as usize);
+
+        self.start = program.origin;
+        self.program_mask = program.mask();
+        self.wrap_top = program.wrap_top;
+        self.wrap_bottom = program.wrap_bottom;
+        self.side_set = Some(program.side_set);
+        self.sideset_base = pin_base(side_set);
+    }
+
+    /// Set the pins written by `out` instructions. Panics if the pins aren't consecutive.
+    pub fn set_out_pins(&mut self, pins: &[&PioPin<'d, PIO>]) {
+        self.out_base = pin_base(pins);
+        self.out_count = pins.len() as u8;
+    }
+
+    /// Set the pins written by `set` instructions. Panics if the pins aren't consecutive,
+    /// or if there are more than 5.
+    pub fn set_set_pins(&mut self, pins: &[&PioPin<'d, PIO>]) {
+        assert!(pins.len() <= 5);
+        self.set_base = pin_base(pins);
+        self.set_count = pins.len() as u8;
+    }
+
+    /// Set the first of the pins read by `in` instructions and `wait pin`.
+    pub fn set_in_pins(&mut self, base: &PioPin<'d, PIO>) {
+        self.in_base = base.pin();
+    }
+
+    /// Set the pin tested by `jmp pin`.
+    pub fn set_jmp_pin(&mut self, pin: &PioPin<'d, PIO>) {
+        self.jmp_pin = pin.pin();
+    }
+
+    /// Set the clock divider so the state machine runs at `hz`, or as close to it as possible.
+    ///
+    /// Panics if `hz` is higher than the system clock, or so low the divider overflows.
+    pub fn set_frequency(&mut self, hz: u32) {
+        let div = ((crate::clocks::clk_sys_freq() as u64) << 8) / hz as u64;
+        assert!(div >= 1 << 8 && div < 1 << 24);
+        self.clkdiv_int = (div >> 8) as u16;
+        self.clkdiv_frac = div as u8;
+    }
+}
+
+/// Returns the number of the first of `pins`, checking that they are consecutive.
+fn pin_base<PIO: Instance>(pins: &[&PioPin<'_, PIO>]) -> u8 {
+    let base = pins.first().map_or(0, |p| p.pin());
+    for (i, pin) in pins.iter().enumerate() {
+        assert_eq!(pin.pin(), (base + i as u8) % 32, "PIO pins must be consecutive");
+    }
+    base
+}
+
+/// A state machine of a PIO block.
+///
+/// `SM` is the number of the state machine in its block, 0 to 3.
+pub struct StateMachine<'d, PIO: Instance, const SM: usize> {
+    _pio: PhantomData<&'d mut PIO>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> StateMachine<'d, PIO, SM> {
+    fn regs() -> pac::pio::StateMachine {
+        PIO::PIO.sm(SM)
+    }
+
+    /// Configure the state machine, and jump to the start of its program.
+    ///
+    /// The state machine is disabled first, enable it again with [`set_enable`](Self::set_enable).
+    pub fn set_config(&mut self, config: &Config<'d, PIO>) {
+        assert!(config.clkdiv_int != 0);
+        assert!(config.shift_in.threshold >= 1 && config.shift_in.threshold <= 32);
+        assert!(config.shift_out.threshold >= 1 && config.shift_out.threshold <= 32);
+
+        self.set_enable(false);
+
+        let sm = Self::regs();
+        unsafe {
+            sm.clkdiv().write(|w| {
+                w.set_int(config.clkdiv_int);
+                w.set_frac(config.clkdiv_frac);
+            });
+            sm.execctrl().write(|w| {
+                if let Some(side_set) = config.side_set {
+                    w.set_side_en(side_set.optional());
+                    w.set_side_pindir(side_set.pindirs());
+                }
+                w.set_jmp_pin(config.jmp_pin);
+                w.set_out_en_sel(config.out_en_sel);
+                w.set_inline_out_en(config.inline_out_en);
+                w.set_out_sticky(config.out_sticky);
+                w.set_wrap_top(config.wrap_top);
+                w.set_wrap_bottom(config.wrap_bottom);
+                w.set_status_sel(match config.status_sel {
+                    StatusSource::TxFifoLevel => SmExecctrlStatusSel::TXLEVEL,
+                    StatusSource::RxFifoLevel => SmExecctrlStatusSel::RXLEVEL,
+                });
+                w.set_status_n(config.status_n);
+            });
+            sm.shiftctrl().write(|w| {
+                w.set_fjoin_rx(config.fifo_join == FifoJoin::RxOnly);
+                w.set_fjoin_tx(config.fifo_join == FifoJoin::TxOnly);
+                // A threshold of 32 is written as 0.
+                w.set_pull_thresh(config.shift_out.threshold % 32);
+                w.set_push_thresh(config.shift_in.threshold % 32);
+                w.set_out_shiftdir(config.shift_out.direction == ShiftDirection::Right);
+                w.set_in_shiftdir(config.shift_in.direction == ShiftDirection::Right);
+                w.set_autopull(config.shift_out.auto_fill);
+                w.set_autopush(config.shift_in.auto_fill);
+            });
+            sm.pinctrl().write(|w| {
+                w.set_sideset_count(config.side_set.map_or(0, |s| s.bits()));
+                w.set_set_count(config.set_count);
+                w.set_out_count(config.out_count);
+                w.set_in_base(config.in_base);
+                w.set_sideset_base(config.sideset_base);
+                w.set_set_base(config.set_base);
+                w.set_out_base(config.out_base);
+            });
+        }
+
+        SM_PROGRAMS[PIO::PIO_NO as usize][SM].store(config.program_mask, Ordering::Relaxed);
+
+        self.restart();
+        self.clkdiv_restart();
+        // `jmp` to an absolute address encodes as the address itself.
+        unsafe { self.exec_instr(config.start as u16) };
+    }
+
+    /// Start or stop the state machine.
+    pub fn set_enable(&mut self, enable: bool) {
+        unsafe {
+            if enable {
+                PIO::PIO.ctrl().write_set(|w| w.set_sm_enable(1 << SM));
+            } else {
+                PIO::PIO.ctrl().write_clear(|w| w.set_sm_enable(1 << SM));
+            }
+        }
+    }
+
+    /// Returns whether the state machine is running.
+    pub fn is_enabled(&self) -> bool {
+        unsafe { PIO::PIO.ctrl().read().sm_enable() & (1 << SM) != 0 }
+    }
+
+    /// Clear the internal state of the state machine: shift registers, stalls and delays.
+    ///
+    /// The program counter, the scratch registers and the FIFO contents are kept.
+    pub fn restart(&mut self) {
+        unsafe { PIO::PIO.ctrl().write_set(|w| w.set_sm_restart(1 << SM)) }
+    }
+
+    /// Restart the clock divider, so its phase is aligned with other state machines restarted at the same time.
+    pub fn clkdiv_restart(&mut self) {
+        unsafe { PIO::PIO.ctrl().write_set(|w| w.set_clkdiv_restart(1 << SM)) }
+    }
+
+    /// Current program counter of the state machine.
+    pub fn pc(&self) -> u8 {
+        unsafe { Self::regs().addr().read().addr() }
+    }
+
+    /// Execute a single instruction right away, interrupting the program.
+    ///
+    /// # Safety
+    /// The instruction can jump anywhere in instruction memory, and change any state of the
+    /// state machine.
+    pub unsafe fn exec_instr(&mut self, instr: u16) {
+        Self::regs().instr().write(|w| w.set_instr(instr));
+    }
+
+    /// Set the level of `pins`, using `set pins` instructions.
+    ///
+    /// This works whether or not the state machine is running, but the program may overwrite
+    /// the levels at any time.
+    pub fn set_pins(&mut self, level: bool, pins: &[&PioPin<'d, PIO>]) {
+        // SET PINS, value
+        self.set_each_pin(0xe000 | level as u16, pins)
+    }
+
+    /// Set `pins` as outputs or inputs, using `set pindirs` instructions.
+    pub fn set_pin_dirs(&mut self, output: bool, pins: &[&PioPin<'d, PIO>]) {
+        // SET PINDIRS, value
+        self.set_each_pin(0xe080 | output as u16, pins)
+    }
+
+    fn set_each_pin(&mut self, instr: u16, pins: &[&PioPin<'d, PIO>]) {
+        let sm = Self::regs();
+        unsafe {
+            let pinctrl = sm.pinctrl().read();
+            for pin in pins {
+                sm.pinctrl().write(|w| {
+                    w.set_set_base(pin.pin());
+                    w.set_set_count(1);
+                });
+                self.exec_instr(instr);
+            }
+            sm.pinctrl().write_value(pinctrl);
+        }
+    }
+
+    /// Discard the contents of both FIFOs.
+    pub fn clear_fifos(&mut self) {
+        // Changing the FIFO join clears the FIFOs, so toggle it twice.
+        let sm = Self::regs();
+        unsafe {
+            let shiftctrl = sm.shiftctrl().read();
+            sm.shiftctrl().write(|w| {
+                *w = shiftctrl;
+                w.set_fjoin_rx(!shiftctrl.fjoin_rx());
+            });
+            sm.shiftctrl().write_value(shiftctrl);
+        }
+    }
+
+    fn fstat_bit(shift: usize) -> bool {
+        unsafe { PIO::PIO.fstat().read().0 & (1 << (shift + SM)) != 0 }
+    }
+
+    /// Returns whether the RX FIFO is empty.
+    pub fn rx_empty(&self) -> bool {
+        Self::fstat_bit(8)
+    }
+
+    /// Returns whether the RX FIFO is full.
+    pub fn rx_full(&self) -> bool {
+        Self::fstat_bit(0)
+    }
+
+    /// Number of words in the RX FIFO.
+    pub fn rx_level(&self) -> u8 {
+        unsafe { (PIO::PIO.flevel().read().0 >> (SM * 8 + 4)) as u8 & 0x0f }
+    }
+
+    /// Returns whether the TX FIFO is empty.
+    pub fn tx_empty(&self) -> bool {
+        Self::fstat_bit(24)
+    }
+
+    /// Returns whether the TX FIFO is full.
+    pub fn tx_full(&self) -> bool {
+        Self::fstat_bit(16)
+    }
+
+    /// Number of words in the TX FIFO.
+    pub fn tx_level(&self) -> u8 {
+        unsafe { (PIO::PIO.flevel().read().0 >> (SM * 8)) as u8 & 0x0f }
+    }
+
+    /// Push a word to the TX FIFO, or return `false` if it is full.
+    pub fn try_push_tx(&mut self, value: u32) -> bool {
+        if self.tx_full() {
+            return false;
+        }
+        unsafe { PIO::PIO.txf(SM).write_value(value) };
+        true
+    }
+
+    /// Push a word to the TX FIFO, blocking while it is full.
+    pub fn push_tx(&mut self, value: u32) {
+        while !self.try_push_tx(value) {}
+    }
+
+    /// Push a word to the TX FIFO, waiting while it is full.
+    pub async fn wait_push(&mut self, value: u32) {
+        wait_for::<PIO, _>(INT_TXNFULL + SM, || self.try_push_tx(value).then_some(())).await
+    }
+
+    /// Pull a word from the RX FIFO, or return `None` if it is empty.
+    pub fn try_pull_rx(&mut self) -> Option<u32> {
+        if self.rx_empty() {
+            return None;
+        }
+        Some(unsafe { PIO::PIO.rxf(SM).read() })
+    }
+
+    /// Pull a word from the RX FIFO, blocking while it is empty.
+    pub fn pull_rx(&mut self) -> u32 {
+        loop {
+            if let Some(value) = self.try_pull_rx() {
+                return value;
+            }
+        }
+    }
+
+    /// Pull a word from the RX FIFO, waiting while it is empty.
+    pub async fn wait_pull(&mut self) -> u32 {
+        wait_for::<PIO, _>(INT_RXNEMPTY + SM, || self.try_pull_rx()).await
+    }
+
+    /// Push `data` to the TX FIFO using DMA.
+    ///
+    /// Words narrower than 32 bits are replicated across the FIFO word, so shift them out
+    /// from the least significant bits.
+    pub fn dma_push<'a, C: Channel, W: Word>(
+        &'a mut self,
+        ch: impl Peripheral<P = C> + 'a,
+        data: &'a [W],
+    ) -> Transfer<'a, C> {
+        let dreq = PIO::PIO_NO * 8 + SM as u8;
+        unsafe { dma::write(ch, data, PIO::PIO.txf(SM).ptr() as *mut W, dreq) }
+    }
+
+    /// Fill `data` from the RX FIFO using DMA.
+    pub fn dma_pull<'a, C: Channel, W: Word>(
+        &'a mut self,
+        ch: impl Peripheral<P = C> + 'a,
+        data: &'a mut [W],
+    ) -> Transfer<'a, C> {
+        let dreq = PIO::PIO_NO * 8 + 4 + SM as u8;
+        unsafe { dma::read(ch, PIO::PIO.rxf(SM).ptr() as *const W, data, dreq) }
+    }
+}
+
+impl<'d, PIO: Instance, const SM: usize> Drop for StateMachine<'d, PIO, SM> {
+    fn drop(&mut self) {
+        self.set_enable(false);
+        SM_PROGRAMS[PIO::PIO_NO as usize][SM].store(0, Ordering::Relaxed);
+    }
+}
+
+pub mod i2s;
+pub mod quadrature;
+pub mod uart;
+pub mod ws2812;
+
+mod sealed {
+    use super::*;
+
+    pub trait Instance {
+        const PIO_NO: u8;
+        const PIO: pac::pio::Pio;
+        const FUNCSEL: u8;
+    }
+}
+
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + Sized + 'static {}
+
+macro_rules! impl_instance {
+    ($type:ident, $no:expr, $funcsel:expr) => {
+        impl sealed::Instance for peripherals::$type {
+            const PIO_NO: u8 = $no;
+            const PIO: pac::pio::Pio = pac::$type;
+            const FUNCSEL: u8 = $funcsel;
+        }
+        impl Instance for peripherals::$type {}
+    };
+}
+
+impl_instance!(PIO0, 0, 6);
+impl_instance!(PIO1, 1, 7);
@@ -0,0 +1,207 @@
+//! Quadrature encoder reader.
+//!
+//! A PIO state machine decodes the two encoder signals and keeps the position count, so no
+//! steps are lost however busy the CPU is. The program is the quadrature encoder from the
+//! Raspberry Pi Pico examples, and must be loaded at address 0 of the instruction memory.
+
+use embassy_hal_common::Peripheral;
+use embassy_time::{Duration, Instant, Timer};
+
+use super::{Common, Config as PioConfig, Instance, LoadError, PioPin, ShiftDirection, StateMachine};
+use crate::gpio::{Pin as GpioPin, Pull};
+
+/// Maximum number of cycles of the state machine between two samples of the pins.
+const CYCLES_PER_SAMPLE: u32 = 14;
+
+/// Quadrature encoder configuration.
+#[non_exhaustive]
+pub struct Config {
+    /// Highest step rate to count, in steps per second, or 0 to sample as fast as possible.
+    ///
+    /// Sampling slower lowers the power used by the state machine.
+    pub max_step_rate: u32,
+    /// Pull to apply to both pins.
+    pub pull: Pull,
+    /// Interval between checks of the position in [`QuadratureEncoder::wait_moved`].
+    pub poll_interval: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_step_rate: 0,
+            pull: Pull::Up,
+            poll_interval: Duration::from_millis(1),
+        }
+    }
+}
+
+/// Quadrature encoder reader.
+pub struct QuadratureEncoder<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    poll_interval: Duration,
+    last_position: i32,
+    last_instant: Instant,
+    _pins: [PioPin<'d, PIO>; 2],
+}
+
+impl<'d, PIO: Instance, const SM: usize> QuadratureEncoder<'d, PIO, SM> {
+    /// Create a new quadrature encoder reader.
+    ///
+    /// Counting up means `pin_b` leads `pin_a`. The program is loaded at address 0 of `pio`,
+    /// which fails with [`LoadError::AddressInUse`] if another program is there already.
+    /// Encoders on the other state machines of the same PIO block can share the program,
+    /// see [`new_with_program`](Self::new_with_program).
+    pub fn new(
+        pio: &mut Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        pin_a: impl Peripheral<P = impl GpioPin> + 'd,
+        pin_b: impl Peripheral<P = impl GpioPin> + 'd,
+        config: Config,
+    ) -> Result<Self, LoadError> {
+        let program = Self::load_program(pio)?;
+        Ok(Self::new_with_program(pio, &program, sm, pin_a, pin_b, config))
+    }
+
+    /// Load the encoder program into instruction memory, for use with
+    /// [`new_with_program`](Self::new_with_program).
+    pub fn load_program(pio: &mut Common<'d, PIO>) -> Result<super::LoadedProgram<'d, PIO>, LoadError> {
+        let prg = pio_proc::pio_asm!(
+            ".origin 0",
+            // The program must be at address 0, it uses computed jumps: the jump table is indexed
+            // with the previous and the current state of the pins.
+            "    jmp update",    // read 00
+            "    jmp decrement", // read 01
+            "    jmp increment", // read 10
+            "    jmp update",    // read 11
+            "    jmp increment", // read 00
+            "    jmp update",    // read 01
+            "    jmp update",    // read 10
+            "    jmp decrement", // read 11
+            "    jmp decrement", // read 00
+            "    jmp update",    // read 01
+            "    jmp update",    // read 10
+            "    jmp increment", // read 11
+            // The last two entries are implemented in place.
+            "    jmp update",    // read 00
+            "    jmp increment", // read 01
+            "decrement:",
+            // A decrement with no other effect, as the jump goes to the next address either way.
+            "    jmp y--, update", // read 10
+            ".wrap_target",
+            "update:",
+            "    mov isr, y", // read 11
+            "    push noblock",
+            // Shift the previous state of the pins (in OSR) and the current state into ISR.
+            // The push above and the out below clear the other bits of ISR.
+            "    out isr, 2",
+            "    in pins, 2",
+            // Keep the state in OSR, then jump to the table entry for the transition.
+            "    mov osr, isr",
+            "    mov pc, isr",
+            // There is no increment instruction, so negate, decrement and negate again.
+            "increment:",
+            "    mov y, ~y",
+            "    jmp y--, increment_cont",
+            "increment_cont:",
+            "    mov y, ~y",
+            ".wrap",
+        );
+
+        pio.load_program(&prg.program)
+    }
+
+    /// Create a new quadrature encoder reader, running a program loaded with
+    /// [`load_program`](Self::load_program).
+    ///
+    /// Panics if `pin_b` isn't the pin after `pin_a`.
+    pub fn new_with_program(
+        pio: &mut Common<'d, PIO>,
+        program: &super::LoadedProgram<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        pin_a: impl Peripheral<P = impl GpioPin> + 'd,
+        pin_b: impl Peripheral<P = impl GpioPin> + 'd,
+        config: Config,
+    ) -> Self {
+        assert_eq!(
+            program.origin(),
+            0,
+            "the quadrature encoder program must be at address 0"
+        );
+
+        let mut pin_a = pio.make_pio_pin(pin_a);
+        let mut pin_b = pio.make_pio_pin(pin_b);
+        assert_eq!(pin_b.pin(), pin_a.pin() + 1, "pin_b must be the pin after pin_a");
+        pin_a.set_pull(config.pull);
+        pin_b.set_pull(config.pull);
+
+        let mut cfg = PioConfig::default();
+        cfg.use_program(program, &[]);
+        cfg.set_in_pins(&pin_a);
+        cfg.shift_in.direction = ShiftDirection::Left;
+        cfg.shift_out.direction = ShiftDirection::Right;
+        let sample_rate = config.max_step_rate.saturating_mul(CYCLES_PER_SAMPLE);
+        if sample_rate != 0 && sample_rate < crate::clocks::clk_sys_freq() {
+            cfg.set_frequency(sample_rate);
+        }
+        sm.set_config(&cfg);
+
+        sm.set_pin_dirs(false, &[&pin_a, &pin_b]);
+        // Start counting from 0.
+        unsafe {
+            // SET Y, 0
+            sm.exec_instr(0xe040);
+        }
+        sm.set_enable(true);
+
+        Self {
+            sm,
+            poll_interval: config.poll_interval,
+            last_position: 0,
+            last_instant: Instant::now(),
+            _pins: [pin_a, pin_b],
+        }
+    }
+
+    /// Current position of the encoder, in steps.
+    ///
+    /// The count wraps around on overflow.
+    pub fn position(&mut self) -> i32 {
+        // The state machine pushes the count all the time, so the FIFO holds old values. Drain
+        // it, then take the next value, which is at most a sample old.
+        for _ in 0..self.sm.rx_level() {
+            self.sm.pull_rx();
+        }
+        self.sm.pull_rx() as i32
+    }
+
+    /// Average speed of the encoder since the previous call, in steps per second.
+    ///
+    /// The first call measures the speed since the reader was created.
+    pub fn velocity(&mut self) -> i32 {
+        let position = self.position();
+        let now = Instant::now();
+
+        let steps = position.wrapping_sub(self.last_position) as i64;
+        let elapsed = (now - self.last_instant).as_micros().max(1) as i64;
+        self.last_position = position;
+        self.last_instant = now;
+
+        (steps * 1_000_000 / elapsed) as i32
+    }
+
+    /// Wait until the encoder is at least `steps` steps away from its current position,
+    /// in either direction, and return the new position.
+    ///
+    /// The position is checked every [`Config::poll_interval`].
+    pub async fn wait_moved(&mut self, steps: u32) -> i32 {
+        let start = self.position();
+        loop {
+            let position = self.position();
+            if position.wrapping_sub(start).unsigned_abs() >= steps {
+                return position;
+            }
+            Timer::after(self.poll_interval).await;
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! UART on PIO, for when the two hardware UARTs aren't enough.
+//!
+//! Frames have 8 data bits, an optional parity bit and 1 stop bit. Each direction uses its own
+//! state machine, and the drivers implement the same `embedded-io` traits as the hardware
+//! UARTs.
+
+use embassy_hal_common::Peripheral;
+use embassy_time::{Duration, Timer};
+
+use super::{Common, Config as PioConfig, FifoJoin, Instance, LoadError, PioPin, ShiftDirection, StateMachine};
+use crate::gpio::{Pin as GpioPin, Pull};
+pub use crate::uart::{Error, Parity};
+
+/// Cycles of the state machines per bit.
+const CYCLES_PER_BIT: u32 = 8;
+
+/// PIO UART configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    pub baudrate: u32,
+    pub parity: Parity,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            baudrate: 115200,
+            parity: Parity::ParityNone,
+        }
+    }
+}
+
+impl Config {
+    /// Number of data and parity bits in a frame.
+    fn bits(&self) -> u8 {
+        match self.parity {
+            Parity::ParityNone => 8,
+            Parity::ParityEven | Parity::ParityOdd => 9,
+        }
+    }
+
+    /// Returns `byte` with its parity bit, if any, as bit 8.
+    fn add_parity(&self, byte: u8) -> u32 {
+        match self.parity {
+            Parity::ParityNone => byte as u32,
+            Parity::ParityEven => byte as u32 | (byte.count_ones() & 1) << 8,
+            Parity::ParityOdd => byte as u32 | (!byte.count_ones() & 1) << 8,
+        }
+    }
+}
+
+/// Transmit half of a PIO UART.
+pub struct PioUartTx<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    config: Config,
+    _pin: PioPin<'d, PIO>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioUartTx<'d, PIO, SM> {
+    /// Create a new PIO UART transmitter, loading its program in the instruction memory of `pio`.
+    pub fn new(
+        pio: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        tx: impl Peripheral<P = impl GpioPin> + 'd,
+        config: Config,
+    ) -> Result<Self, LoadError> {
+        let side_set = pio::SideSet::new(true, 1, false);
+        let mut a: pio::Assembler<32> = pio::Assembler::new_with_side_set(side_set);
+
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        let mut bitloop = a.label();
+        a.bind(&mut wrap_target);
+        // Send the stop bit, then stall with the line idle until there's data.
+        a.pull_with_delay_and_side_set(false, true, 7, 1);
+        // Send the start bit.
+        a.set_with_delay_and_side_set(pio::SetDestination::X, config.bits() - 1, 7, 0);
+        // Send the bits, LSB first.
+        a.bind(&mut bitloop);
+        a.out(pio::OutDestination::PINS, 1);
+        a.jmp_with_delay(pio::JmpCondition::XDecNonZero, &mut bitloop, 6);
+        a.bind(&mut wrap_source);
+        let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+        let program = pio.load_program(&program)?;
+        let pin = pio.make_pio_pin(tx);
+
+        let mut cfg = PioConfig::default();
+        cfg.use_program(&program, &[&pin]);
+        cfg.set_out_pins(&[&pin]);
+        cfg.set_frequency(config.baudrate * CYCLES_PER_BIT);
+        cfg.fifo_join = FifoJoin::TxOnly;
+        cfg.shift_out.direction = ShiftDirection::Right;
+        sm.set_config(&cfg);
+
+        sm.set_pins(true, &[&pin]);
+        sm.set_pin_dirs(true, &[&pin]);
+        sm.set_enable(true);
+
+        Ok(Self { sm, config, _pin: pin })
+    }
+
+    /// Write `buffer`, waiting while the FIFO is full.
+    pub async fn write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        for &byte in buffer {
+            self.sm.wait_push(self.config.add_parity(byte)).await;
+        }
+        Ok(())
+    }
+
+    /// Write `buffer`, blocking while the FIFO is full.
+    pub fn blocking_write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        for &byte in buffer {
+            self.sm.push_tx(self.config.add_parity(byte));
+        }
+        Ok(())
+    }
+
+    /// Wait until all the data written has been sent.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        while !self.sm.tx_empty() {
+            Timer::after(self.frame_time()).await;
+        }
+        // The last frame may still be going out.
+        Timer::after(self.frame_time()).await;
+        Ok(())
+    }
+
+    fn frame_time(&self) -> Duration {
+        // Start bit, data and parity bits, stop bit.
+        let bits = self.config.bits() as u64 + 2;
+        Duration::from_micros((bits * 1_000_000 + self.config.baudrate as u64 - 1) / self.config.baudrate as u64)
+    }
+}
+
+/// Receive half of a PIO UART.
+pub struct PioUartRx<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    config: Config,
+    _pin: PioPin<'d, PIO>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioUartRx<'d, PIO, SM> {
+    /// Create a new PIO UART receiver, loading its program in the instruction memory of `pio`.
+    ///
+    /// The pin is pulled up, so the line is idle when nothing is connected.
+    pub fn new(
+        pio: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        rx: impl Peripheral<P = impl GpioPin> + 'd,
+        config: Config,
+    ) -> Result<Self, LoadError> {
+        let mut a: pio::Assembler<32> = pio::Assembler::new();
+
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        let mut bitloop = a.label();
+        a.bind(&mut wrap_target);
+        // Wait for the start bit, then wait until the middle of the first data bit.
+        a.wait(0, pio::WaitSource::PIN, 0, false);
+        a.set_with_delay(pio::SetDestination::X, config.bits(), 10);
+        // Sample the data bits, the parity bit and the stop bit. The stop bit is checked by
+        // the CPU, as there's no room to report errors otherwise.
+        a.bind(&mut bitloop);
+        a.in_(pio::InSource::PINS, 1);
+        a.jmp_with_delay(pio::JmpCondition::XDecNonZero, &mut bitloop, 6);
+        a.push(false, true);
+        // After a framing error or a break, wait for the line to be idle again.
+        a.wait(1, pio::WaitSource::PIN, 0, false);
+        a.bind(&mut wrap_source);
+        let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+        let program = pio.load_program(&program)?;
+        let mut pin = pio.make_pio_pin(rx);
+        pin.set_pull(Pull::Up);
+
+        let mut cfg = PioConfig::default();
+        cfg.use_program(&program, &[]);
+        cfg.set_in_pins(&pin);
+        cfg.set_frequency(config.baudrate * CYCLES_PER_BIT);
+        cfg.fifo_join = FifoJoin::RxOnly;
+        cfg.shift_in.direction = ShiftDirection::Right;
+        sm.set_config(&cfg);
+
+        sm.set_pin_dirs(false, &[&pin]);
+        sm.set_enable(true);
+
+        Ok(Self { sm, config, _pin: pin })
+    }
+
+    /// Decode a word pushed by the state machine.
+    fn decode(&self, word: u32) -> Result<u8, Error> {
+        // The bits were shifted in from the left, so they're at the top of the word.
+        let bits = self.config.bits() as u32 + 1;
+        let frame = word >> (32 - bits);
+        let byte = frame as u8;
+
+        if frame & (1 << (bits - 1)) == 0 {
+            // The stop bit is missing. If the whole frame is low, it's a break.
+            return Err(if frame == 0 { Error::Break } else { Error::Framing });
+        }
+        if self.config.parity != Parity::ParityNone && self.config.add_parity(byte) != frame & 0x1ff {
+            return Err(Error::Parity);
+        }
+        Ok(byte)
+    }
+
+    /// Read into `buffer`, waiting until it is full.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        for byte in buffer {
+            let word = self.sm.wait_pull().await;
+            *byte = self.decode(word)?;
+        }
+        Ok(())
+    }
+
+    /// Read into `buffer`, blocking until it is full.
+    pub fn blocking_read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        for byte in buffer {
+            let word = self.sm.pull_rx();
+            *byte = self.decode(word)?;
+        }
+        Ok(())
+    }
+
+    /// Read at least one byte into `buffer`, and as many more as were already received.
+    ///
+    /// Returns the number of bytes read.
+    pub async fn read_available(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let word = self.sm.wait_pull().await;
+        buffer[0] = self.decode(word)?;
+        let mut n = 1;
+        while n < buffer.len() {
+            match self.sm.try_pull_rx() {
+                Some(word) => buffer[n] = self.decode(word)?,
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "nightly")]
+mod eio {
+    use core::future::Future;
+
+    use super::*;
+
+    impl<'d, PIO: Instance, const SM: usize> embedded_io::Io for PioUartTx<'d, PIO, SM> {
+        type Error = Error;
+    }
+
+    impl<'d, PIO: Instance, const SM: usize> embedded_io::Io for PioUartRx<'d, PIO, SM> {
+        type Error = Error;
+    }
+
+    impl<'d, PIO: Instance, const SM: usize> embedded_io::asynch::Read for PioUartRx<'d, PIO, SM> {
+        type ReadFuture<'a> = impl Future<Output = Result<usize, Self::Error>>
+        where
+            Self: 'a;
+
+        fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+            self.read_available(buf)
+        }
+    }
+
+    impl<'d, PIO: Instance, const SM: usize> embedded_io::asynch::Write for PioUartTx<'d, PIO, SM> {
+        type WriteFuture<'a> = impl Future<Output = Result<usize, Self::Error>>
+        where
+            Self: 'a;
+
+        fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+            async move {
+                self.write(buf).await?;
+                Ok(buf.len())
+            }
+        }
+
+        type FlushFuture<'a> = impl Future<Output = Result<(), Self::Error>>
+        where
+            Self: 'a;
+
+        fn flush<'a>(&'a mut self) -> Self::FlushFuture<'a> {
+            self.flush()
+        }
+    }
+}
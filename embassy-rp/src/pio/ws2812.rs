@@ -0,0 +1,165 @@
+//! WS2812 ("NeoPixel") LED driver.
+//!
+//! The LEDs are driven by a PIO state machine generating the 800 kHz bit timing, fed by DMA,
+//! so the CPU is free while a frame is sent.
+
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+use embassy_time::{Duration, Timer};
+
+use super::{Common, Config, FifoJoin, Instance, LoadError, PioPin, ShiftDirection, StateMachine};
+use crate::dma::Channel;
+use crate::gpio::Pin as GpioPin;
+
+/// Bit rate of the WS2812 protocol.
+const BIT_RATE: u32 = 800_000;
+
+// Cycles of the state machine spent in each part of a bit. A bit is high for T1 cycles, then
+// high for T2 more cycles if it's a 1 and low otherwise, then low for T3 cycles.
+const T1: u8 = 2;
+const T2: u8 = 5;
+const T3: u8 = 3;
+
+/// Time the line must be held low for the LEDs to latch the data, including margin for newer
+/// parts that need more than 50 µs.
+const RESET_TIME: Duration = Duration::from_micros(300);
+
+/// Number of words converted and sent to DMA at a time.
+const CHUNK_LEN: usize = 32;
+
+/// Color of an LED.
+///
+/// The white channel is ignored for [`ColorOrder`]s without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub w: u8,
+}
+
+impl Color {
+    /// A color for LEDs without a white channel.
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, w: 0 }
+    }
+
+    /// A color for LEDs with a white channel.
+    pub const fn rgbw(r: u8, g: u8, b: u8, w: u8) -> Self {
+        Self { r, g, b, w }
+    }
+}
+
+/// Order the LEDs expect the color channels in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ColorOrder {
+    Rgb,
+    /// The order of the original WS2812 and most of its clones.
+    Grb,
+    Rgbw,
+    /// The order of SK6812 RGBW LEDs.
+    Grbw,
+}
+
+impl ColorOrder {
+    fn bits(self) -> u8 {
+        match self {
+            ColorOrder::Rgb | ColorOrder::Grb => 24,
+            ColorOrder::Rgbw | ColorOrder::Grbw => 32,
+        }
+    }
+
+    /// Pack `color` in a FIFO word. The state machine shifts out the most significant bits first.
+    fn pack(self, c: Color) -> u32 {
+        let [a, b, c, d] = match self {
+            ColorOrder::Rgb => [c.r, c.g, c.b, 0],
+            ColorOrder::Grb => [c.g, c.r, c.b, 0],
+            ColorOrder::Rgbw => [c.r, c.g, c.b, c.w],
+            ColorOrder::Grbw => [c.g, c.r, c.b, c.w],
+        };
+        u32::from_be_bytes([a, b, c, d])
+    }
+}
+
+/// WS2812 driver, driving a chain of LEDs from a single pin.
+pub struct Ws2812<'d, PIO: Instance, const SM: usize, C: Channel> {
+    sm: StateMachine<'d, PIO, SM>,
+    dma: PeripheralRef<'d, C>,
+    order: ColorOrder,
+    _pin: PioPin<'d, PIO>,
+}
+
+impl<'d, PIO: Instance, const SM: usize, C: Channel> Ws2812<'d, PIO, SM, C> {
+    /// Create a new WS2812 driver, loading its program in the instruction memory of `pio`.
+    pub fn new(
+        pio: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        dma: impl Peripheral<P = C> + 'd,
+        pin: impl Peripheral<P = impl GpioPin> + 'd,
+        order: ColorOrder,
+    ) -> Result<Self, LoadError> {
+        into_ref!(dma);
+
+        let side_set = pio::SideSet::new(false, 1, false);
+        let mut a: pio::Assembler<32> = pio::Assembler::new_with_side_set(side_set);
+
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        let mut do_zero = a.label();
+        a.bind(&mut wrap_target);
+        // Low for T3 cycles, while shifting out the next bit.
+        a.out_with_delay_and_side_set(pio::OutDestination::X, 1, T3 - 1, 0);
+        // High for T1 cycles.
+        a.jmp_with_delay_and_side_set(pio::JmpCondition::XIsZero, &mut do_zero, T1 - 1, 1);
+        // High for T2 more cycles for a 1.
+        a.jmp_with_delay_and_side_set(pio::JmpCondition::Always, &mut wrap_target, T2 - 1, 1);
+        a.bind(&mut do_zero);
+        // Low for T2 cycles for a 0.
+        a.nop_with_delay_and_side_set(T2 - 1, 0);
+        a.bind(&mut wrap_source);
+        let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+        let program = pio.load_program(&program)?;
+        let pin = pio.make_pio_pin(pin);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program, &[&pin]);
+        cfg.set_frequency(BIT_RATE * (T1 + T2 + T3) as u32);
+        cfg.fifo_join = FifoJoin::TxOnly;
+        cfg.shift_out.auto_fill = true;
+        cfg.shift_out.threshold = order.bits();
+        cfg.shift_out.direction = ShiftDirection::Left;
+        sm.set_config(&cfg);
+
+        sm.set_pins(false, &[&pin]);
+        sm.set_pin_dirs(true, &[&pin]);
+        sm.set_enable(true);
+
+        Ok(Self {
+            sm,
+            dma,
+            order,
+            _pin: pin,
+        })
+    }
+
+    /// Send `colors` to the LEDs, the first color going to the LED closest to the pin.
+    ///
+    /// This returns once the LEDs have latched the new colors.
+    pub async fn write(&mut self, colors: &[Color]) {
+        let mut words = [0u32; CHUNK_LEN];
+        for chunk in colors.chunks(CHUNK_LEN) {
+            for (word, &color) in words.iter_mut().zip(chunk) {
+                *word = self.order.pack(color);
+            }
+            self.sm.dma_push(self.dma.reborrow(), &words[..chunk.len()]).await;
+        }
+
+        // Wait for the last bits to go out, then hold the line low so the LEDs latch the data.
+        while !self.sm.tx_empty() {
+            embassy_futures::yield_now().await;
+        }
+        Timer::after(RESET_TIME).await;
+    }
+}
@@ -0,0 +1,213 @@
+//! Low-power modes
+//!
+//! In [`sleep`], the cores stop until an interrupt, and the clocks of the peripherals that
+//! aren't needed to wake up are gated. The PLLs can also be stopped while sleeping, running
+//! from the crystal oscillator instead. Clock gating only happens while both cores are
+//! sleeping, so core1 must be sleeping too, or not started.
+//!
+//! [`dormant_until_pin`] goes further and stops the crystal oscillator, and with it every
+//! clock, until a pin changes. This is the lowest power mode, but time stands still: the
+//! timer and the RTC don't count while dormant, so `embassy-time` lags behind afterwards.
+//!
+//! The clocks are set up again as configured by [`init`](crate::init) when waking up.
+
+use embassy_hal_common::into_ref;
+
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::{Bank, InterruptTrigger, Pin, Pull};
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::rtc::{self, DateTimeFilter, RealTimeClock};
+use crate::{clocks, interrupt, pac, Peripheral};
+
+/// Written to the DORMANT register of the crystal oscillator to stop it ("coma").
+const XOSC_DORMANT: u32 = 0x636f6d61;
+
+// Bits of the SLEEP_EN0 and SLEEP_EN1 registers, for the clocks that can wake up the chip.
+const EN0_SYS_IO: u32 = 1 << 8;
+const EN0_SYS_PADS: u32 = 1 << 11;
+const EN0_RTC_RTC: u32 = 1 << 21;
+const EN1_SYS_TIMER: u32 = 1 << 5;
+const EN1_SYS_USBCTRL: u32 = 1 << 10;
+const EN1_USB_USBCTRL: u32 = 1 << 11;
+const EN1_SYS_WATCHDOG: u32 = 1 << 12;
+
+/// Reset values of SLEEP_EN0 and SLEEP_EN1, with every clock running in sleep.
+const EN0_ALL: u32 = 0xffff_ffff;
+const EN1_ALL: u32 = 0x0000_7fff;
+
+// Bits of the System Control Register of the core.
+const SCR_SLEEPDEEP: u32 = 1 << 2;
+const SCR_SEVONPEND: u32 = 1 << 4;
+
+/// Clocks kept running in [`sleep`]. The clocks of everything else are gated.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct SleepConfig {
+    /// Keep the RTC running, to wake up with an RTC alarm.
+    pub rtc: bool,
+    /// Keep the timer running, to wake up with an `embassy-time` alarm.
+    pub timer: bool,
+    /// Keep the GPIOs running, to wake up with a GPIO interrupt.
+    pub gpio: bool,
+    /// Keep the USB controller running. Not possible with `stop_plls`.
+    pub usb: bool,
+    /// Stop the PLLs while sleeping, running `clk_sys` from the 12 MHz crystal oscillator.
+    /// This saves power, but USB and the ADC stop.
+    pub stop_plls: bool,
+}
+
+impl Default for SleepConfig {
+    fn default() -> Self {
+        Self {
+            rtc: true,
+            timer: true,
+            gpio: true,
+            usb: false,
+            stop_plls: false,
+        }
+    }
+}
+
+impl SleepConfig {
+    fn sleep_en(&self) -> (u32, u32) {
+        let mut en0 = 0;
+        let mut en1 = 0;
+        if self.rtc {
+            en0 |= EN0_RTC_RTC;
+        }
+        if self.timer {
+            // The timer counts the ticks of the watchdog.
+            en1 |= EN1_SYS_TIMER | EN1_SYS_WATCHDOG;
+        }
+        if self.gpio {
+            en0 |= EN0_SYS_IO | EN0_SYS_PADS;
+        }
+        if self.usb && !self.stop_plls {
+            en1 |= EN1_SYS_USBCTRL | EN1_USB_USBCTRL;
+        }
+        (en0, en1)
+    }
+}
+
+/// Run `wait` with the clocks set by `config` for when the core sleeps, and interrupts
+/// disabled.
+fn with_sleep_clocks(config: &SleepConfig, wait: impl FnOnce()) {
+    // Interrupts are taken once the clocks are restored.
+    cortex_m::interrupt::free(|_| unsafe {
+        if config.stop_plls {
+            clocks::stop_plls();
+        }
+
+        let (en0, en1) = config.sleep_en();
+        let c = pac::CLOCKS;
+        c.sleep_en0().write_value(pac::clocks::regs::SleepEn0(en0));
+        c.sleep_en1().write_value(pac::clocks::regs::SleepEn1(en1));
+
+        let scb = &*cortex_m::peripheral::SCB::PTR;
+        scb.scr.modify(|r| r | SCR_SLEEPDEEP);
+        wait();
+        scb.scr.modify(|r| r & !SCR_SLEEPDEEP);
+
+        c.sleep_en0().write_value(pac::clocks::regs::SleepEn0(EN0_ALL));
+        c.sleep_en1().write_value(pac::clocks::regs::SleepEn1(EN1_ALL));
+
+        if config.stop_plls {
+            clocks::start_plls();
+        }
+    })
+}
+
+/// Sleep until an enabled interrupt is pending, with the clocks set by `config`.
+///
+/// The interrupt is taken after waking up.
+pub fn sleep(config: &SleepConfig) {
+    with_sleep_clocks(config, cortex_m::asm::wfi);
+}
+
+/// Sleep until the RTC alarm set by `filter` fires, with the clocks set by `config`. The RTC
+/// keeps running whatever `config` says.
+///
+/// The RTC interrupt doesn't need to be enabled.
+pub fn sleep_until_alarm<T: rtc::Instance>(
+    rtc: &mut RealTimeClock<'_, T>,
+    filter: DateTimeFilter,
+    config: &SleepConfig,
+) {
+    let config = SleepConfig { rtc: true, ..*config };
+
+    rtc.schedule_alarm(filter);
+    unsafe { pac::RTC.inte().write(|w| w.set_rtc(true)) };
+
+    with_sleep_clocks(&config, || unsafe {
+        // With SEVONPEND, pending interrupts wake the core up even if they are disabled.
+        let scb = &*cortex_m::peripheral::SCB::PTR;
+        scb.scr.modify(|r| r | SCR_SEVONPEND);
+        while !pac::RTC.ints().read().rtc() {
+            cortex_m::asm::wfe();
+        }
+        scb.scr.modify(|r| r & !SCR_SEVONPEND);
+    });
+
+    rtc.disable_alarm();
+    unsafe {
+        pac::RTC.inte().write(|w| w.set_rtc(false));
+        interrupt::RTC_IRQ::steal().unpend();
+    }
+}
+
+/// Go dormant until `trigger` happens on `pin`, stopping every clock.
+///
+/// `pin` is set up as an input with `pull`. Dormant mode affects both cores.
+pub fn dormant_until_pin<'d>(pin: impl Peripheral<P = impl Pin> + 'd, trigger: InterruptTrigger, pull: Pull) {
+    into_ref!(pin);
+
+    let io = match pin.bank() {
+        Bank::Bank0 => pac::IO_BANK0,
+        Bank::Qspi => pac::IO_QSPI,
+    };
+    let n = pin.pin() as usize;
+    let (reg, group) = (n / 8, n % 8);
+
+    critical_section::with(|_| unsafe {
+        pin.pad_ctrl().modify(|w| {
+            w.set_ie(true);
+            w.set_pue(pull == Pull::Up);
+            w.set_pde(pull == Pull::Down);
+        });
+
+        // Clear edges detected before.
+        io.intr(reg).write(|w| {
+            w.set_edge_high(group, true);
+            w.set_edge_low(group, true);
+        });
+        io.dormant_wake().inte(reg).modify(|w| match trigger {
+            InterruptTrigger::LevelLow => w.set_level_low(group, true),
+            InterruptTrigger::LevelHigh => w.set_level_high(group, true),
+            InterruptTrigger::EdgeLow => w.set_edge_low(group, true),
+            InterruptTrigger::EdgeHigh => w.set_edge_high(group, true),
+            InterruptTrigger::AnyEdge => {
+                w.set_edge_low(group, true);
+                w.set_edge_high(group, true);
+            }
+        });
+
+        clocks::stop_plls();
+
+        // Execution stops here until the pin starts the oscillator again.
+        pac::XOSC.dormant().write_value(XOSC_DORMANT);
+        while !pac::XOSC.status().read().stable() {}
+
+        io.dormant_wake().inte(reg).modify(|w| {
+            w.set_level_low(group, false);
+            w.set_level_high(group, false);
+            w.set_edge_low(group, false);
+            w.set_edge_high(group, false);
+        });
+        io.intr(reg).write(|w| {
+            w.set_edge_high(group, true);
+            w.set_edge_low(group, true);
+        });
+
+        clocks::start_plls();
+    });
+}
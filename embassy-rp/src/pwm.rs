@@ -0,0 +1,207 @@
+//! Pulse Width Modulation (PWM)
+//!
+//! The PWM block has 8 slices, each with a 16-bit counter and two pins, A and B. Instead of
+//! generating a signal, a slice can count using its B pin as an input, which [`PwmInput`] uses
+//! to measure the frequency and duty cycle of a signal, for example from a fan tachometer or
+//! an IR receiver.
+
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+use embassy_time::{Duration, Instant, Timer};
+use pac::pwm::vals::Divmode;
+
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::{self, AnyPin, Pull};
+use crate::{clocks, pac, peripherals};
+
+/// Function select value of the GPIOs for PWM.
+const FUNCSEL_PWM: u8 = 4;
+
+/// PWM input error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// The counter overflowed during the gate interval. Use a shorter gate interval.
+    Overflow,
+}
+
+/// Result of a [`PwmInput::measure`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Measurement {
+    /// Frequency of the signal, in Hz.
+    pub frequency: u32,
+    /// Fraction of the time the signal was high, from 0.0 to 1.0.
+    pub duty_cycle: f32,
+}
+
+/// Measures a signal on the B pin of a PWM slice.
+///
+/// Each measurement counts during a gate interval, timed with `embassy-time`. A longer interval
+/// gives more precise results: the frequency is known to 1 / gate Hz, and the duty cycle to the
+/// accuracy of the timer. The counter is 16 bits, so frequency measurements fail with
+/// [`Error::Overflow`] if more than 65535 edges happen during the interval.
+pub struct PwmInput<'d, T: Slice> {
+    slice: PeripheralRef<'d, T>,
+    pin: PeripheralRef<'d, AnyPin>,
+}
+
+impl<'d, T: Slice> PwmInput<'d, T> {
+    /// Create a new PWM input, measuring the signal on `pin`.
+    pub fn new(slice: impl Peripheral<P = T> + 'd, pin: impl Peripheral<P = impl PwmPinB<T>> + 'd, pull: Pull) -> Self {
+        into_ref!(slice, pin);
+
+        unsafe {
+            pin.pad_ctrl().write(|w| {
+                w.set_ie(true);
+                w.set_pue(pull == Pull::Up);
+                w.set_pde(pull == Pull::Down);
+            });
+            pin.io().ctrl().write(|w| w.set_funcsel(FUNCSEL_PWM));
+
+            let r = slice.regs();
+            r.csr().write(|_| {});
+            r.top().write(|w| w.set_top(0xFFFF));
+        }
+
+        Self {
+            slice,
+            pin: pin.map_into(),
+        }
+    }
+
+    /// Count during `gate` with the slice in `mode`, returning the count and the actual length
+    /// of the interval.
+    async fn count(&mut self, mode: Divmode, div: u8, gate: Duration) -> Result<(u32, Duration), Error> {
+        let r = self.slice.regs();
+        let n = self.slice.number();
+
+        unsafe {
+            r.csr().write(|w| w.set_divmode(mode));
+            r.div().write(|w| {
+                w.set_int(div);
+                w.set_frac(0);
+            });
+            r.ctr().write(|w| w.set_ctr(0));
+            pac::PWM.intr().write_value(1 << n);
+            r.csr().modify(|w| w.set_en(true));
+        }
+        let start = Instant::now();
+
+        Timer::after(gate).await;
+
+        unsafe { r.csr().modify(|w| w.set_en(false)) };
+        let elapsed = Instant::now() - start;
+
+        // The counter wraps from TOP to 0, setting the raw interrupt flag of the slice.
+        if unsafe { pac::PWM.intr().read() } & (1 << n) != 0 {
+            return Err(Error::Overflow);
+        }
+
+        Ok((unsafe { r.ctr().read().ctr() } as u32, elapsed))
+    }
+
+    /// Measure the frequency of the signal in Hz, by counting its rising edges during `gate`.
+    pub async fn measure_frequency(&mut self, gate: Duration) -> Result<u32, Error> {
+        let (edges, elapsed) = self.count(Divmode::RISE, 1, gate).await?;
+        Ok((edges as u64 * 1_000_000 / elapsed.as_micros().max(1)) as u32)
+    }
+
+    /// Measure the fraction of the time the signal is high during `gate`, from 0.0 to 1.0.
+    ///
+    /// Panics if `gate` is longer than about 130 ms, as the counter would overflow even with
+    /// the largest clock divider.
+    pub async fn measure_duty_cycle(&mut self, gate: Duration) -> Result<f32, Error> {
+        // Divide the clock so the counter can't overflow during the interval, with some margin
+        // as the interval can be a bit longer than asked for.
+        let cycles = gate.as_micros() * (clocks::clk_sys_freq() as u64 / 1_000_000);
+        let div = cycles * 9 / 8 / 0xFFFF + 1;
+        assert!(div <= 0xFF, "gate interval too long");
+
+        let (high, elapsed) = self.count(Divmode::LEVEL, div as u8, gate).await?;
+        let high_cycles = high as u64 * div;
+        let total_cycles = elapsed.as_micros() * (clocks::clk_sys_freq() as u64 / 1_000_000);
+        Ok((high_cycles as f32 / total_cycles.max(1) as f32).min(1.0))
+    }
+
+    /// Measure both the frequency and the duty cycle of the signal, one after the other,
+    /// each over `gate`.
+    pub async fn measure(&mut self, gate: Duration) -> Result<Measurement, Error> {
+        Ok(Measurement {
+            frequency: self.measure_frequency(gate).await?,
+            duty_cycle: self.measure_duty_cycle(gate).await?,
+        })
+    }
+}
+
+impl<'d, T: Slice> Drop for PwmInput<'d, T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.slice.regs().csr().write(|_| {});
+            self.pin.pad_ctrl().write(|_| {});
+            self.pin.io().ctrl().write(|w| {
+                w.set_funcsel(pac::io::vals::Gpio0ctrlFuncsel::NULL.0);
+            });
+        }
+    }
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Slice {
+        fn number(&self) -> usize;
+
+        fn regs(&self) -> pac::pwm::Ch {
+            pac::PWM.ch(self.number())
+        }
+    }
+}
+
+/// A PWM slice.
+pub trait Slice: Peripheral<P = Self> + sealed::Slice + Sized + 'static {}
+
+macro_rules! impl_slice {
+    ($name:ident, $number:expr) => {
+        impl sealed::Slice for peripherals::$name {
+            fn number(&self) -> usize {
+                $number
+            }
+        }
+        impl Slice for peripherals::$name {}
+    };
+}
+
+impl_slice!(PWM_CH0, 0);
+impl_slice!(PWM_CH1, 1);
+impl_slice!(PWM_CH2, 2);
+impl_slice!(PWM_CH3, 3);
+impl_slice!(PWM_CH4, 4);
+impl_slice!(PWM_CH5, 5);
+impl_slice!(PWM_CH6, 6);
+impl_slice!(PWM_CH7, 7);
+
+/// A pin that is the B pin of slice `T`.
+pub trait PwmPinB<T: Slice>: gpio::Pin {}
+
+macro_rules! impl_pin_b {
+    ($pin:ident, $slice:ident) => {
+        impl PwmPinB<peripherals::$slice> for peripherals::$pin {}
+    };
+}
+
+impl_pin_b!(PIN_1, PWM_CH0);
+impl_pin_b!(PIN_3, PWM_CH1);
+impl_pin_b!(PIN_5, PWM_CH2);
+impl_pin_b!(PIN_7, PWM_CH3);
+impl_pin_b!(PIN_9, PWM_CH4);
+impl_pin_b!(PIN_11, PWM_CH5);
+impl_pin_b!(PIN_13, PWM_CH6);
+impl_pin_b!(PIN_15, PWM_CH7);
+impl_pin_b!(PIN_17, PWM_CH0);
+impl_pin_b!(PIN_19, PWM_CH1);
+impl_pin_b!(PIN_21, PWM_CH2);
+impl_pin_b!(PIN_23, PWM_CH3);
+impl_pin_b!(PIN_25, PWM_CH4);
+impl_pin_b!(PIN_27, PWM_CH5);
+impl_pin_b!(PIN_29, PWM_CH6);
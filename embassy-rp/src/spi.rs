@@ -14,7 +14,8 @@ use crate::{pac, peripherals, Peripheral};
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub enum Error {
-    // No errors for now
+    /// In slave mode, the master clocked data while the slave wasn't ready to receive it.
+    Overrun,
 }
 
 #[non_exhaustive]
@@ -409,6 +410,110 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
     }
 }
 
+impl<'d, T: Instance> Spi<'d, T, Slave> {
+    /// Create a new SPI driver in slave mode.
+    ///
+    /// The master drives `clk` and `cs`. `rx` receives data from the master and `tx` sends data
+    /// to it: they are the SPI RX and SPI TX pins of the datasheet, used as MISO and MOSI in
+    /// master mode. The frequency of `config` is ignored, but the master clock must be at most
+    /// 1/12 of `clk_peri`.
+    pub fn new_slave(
+        inner: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T> + 'd> + 'd,
+        rx: impl Peripheral<P = impl MisoPin<T> + 'd> + 'd,
+        tx: impl Peripheral<P = impl MosiPin<T> + 'd> + 'd,
+        cs: impl Peripheral<P = impl CsPin<T> + 'd> + 'd,
+        tx_dma: impl Peripheral<P = impl Channel> + 'd,
+        rx_dma: impl Peripheral<P = impl Channel> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(tx_dma, rx_dma, clk, rx, tx, cs);
+        let this = Self::new_inner(
+            inner,
+            Some(clk.map_into()),
+            Some(tx.map_into()),
+            Some(rx.map_into()),
+            Some(cs.map_into()),
+            Some(tx_dma.map_into()),
+            Some(rx_dma.map_into()),
+            config,
+        );
+
+        unsafe {
+            let p = this.inner.regs();
+            // The mode can only be changed while disabled.
+            p.cr1().write(|w| w.set_sse(false));
+            p.cr1().write(|w| {
+                w.set_ms(true);
+                w.set_sse(true);
+            });
+        }
+
+        this
+    }
+
+    /// Exchange data with the master, until it has clocked `read.len()` bytes.
+    ///
+    /// `write` is sent while receiving. If it is shorter than `read`, the master reads zeros
+    /// after it, instead of the hardware repeating the last byte on TX underrun.
+    ///
+    /// Returns [`Error::Overrun`] without transferring anything if the master clocked data while
+    /// no transfer was running, as the master received garbage and that data is dropped. It is
+    /// also returned if data was lost during the transfer.
+    pub async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        // Kept as an address, as a pointer held across an await makes the future non-Send.
+        let dr = unsafe {
+            let p = self.inner.regs();
+            if p.sr().read().rne() || p.ris().read().roris() {
+                while p.sr().read().rne() {
+                    let _: u16 = p.dr().read().data();
+                }
+                p.icr().write(|w| w.set_roric(true));
+                return Err(Error::Overrun);
+            }
+
+            p.dmacr().write(|reg| {
+                reg.set_rxdmae(true);
+                reg.set_txdmae(true);
+            });
+            p.dr().ptr() as usize
+        };
+
+        let len = read.len();
+        let data_len = write.len().min(len);
+
+        let tx_ch = self.tx_dma.as_mut().unwrap();
+        let tx_transfer = async move {
+            if data_len > 0 {
+                let transfer = unsafe { crate::dma::write(&mut *tx_ch, &write[..data_len], dr as *mut u8, T::TX_DREQ) };
+                transfer.await;
+            }
+            if data_len < len {
+                let transfer = unsafe { crate::dma::write_repeated(tx_ch, dr as *mut u8, len - data_len, T::TX_DREQ) };
+                transfer.await;
+            }
+        };
+        let rx_ch = self.rx_dma.as_mut().unwrap();
+        let rx_transfer = unsafe { crate::dma::read(rx_ch, dr as *const u8, read, T::RX_DREQ) };
+        join(tx_transfer, rx_transfer).await;
+
+        unsafe {
+            let p = self.inner.regs();
+            if p.ris().read().roris() {
+                p.icr().write(|w| w.set_roric(true));
+                return Err(Error::Overrun);
+            }
+        }
+        Ok(())
+    }
+
+    /// Receive data from the master, until it has clocked `read.len()` bytes. The master reads
+    /// zeros.
+    pub async fn read(&mut self, read: &mut [u8]) -> Result<(), Error> {
+        self.transfer(read, &[]).await
+    }
+}
+
 mod sealed {
     use super::*;
 
@@ -483,9 +588,11 @@ macro_rules! impl_mode {
 
 pub struct Blocking;
 pub struct Async;
+pub struct Slave;
 
 impl_mode!(Blocking);
 impl_mode!(Async);
+impl_mode!(Slave);
 
 // ====================
 
@@ -515,7 +622,9 @@ mod eh1 {
 
     impl embedded_hal_1::spi::Error for Error {
         fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
-            match *self {}
+            match *self {
+                Self::Overrun => embedded_hal_1::spi::ErrorKind::Overrun,
+            }
         }
     }
 
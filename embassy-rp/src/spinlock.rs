@@ -0,0 +1,120 @@
+//! Hardware spinlocks.
+//!
+//! The SIO has 32 hardware spinlocks shared by both cores, which make it possible to protect
+//! data shared between the cores. [`HardwareSpinlockMutex`] is a [`RawMutex`] built on them,
+//! so it can be used with the `embassy-sync` primitives from both cores.
+//!
+//! With the `critical-section-impl` feature, embassy-rp also provides an implementation of
+//! `critical-section` that disables interrupts on the current core and takes spinlock 31,
+//! making `CriticalSectionRawMutex` sound on both cores. In that case the `cortex-m`
+//! `critical-section-single-core` feature must not be enabled.
+//!
+//! Spinlocks are not released when a core is reset, so restarting core1 while it holds one
+//! leaves it locked.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+
+use crate::pac;
+
+/// Spinlock used by the `critical-section` implementation.
+const CRITICAL_SECTION_SPINLOCK: usize = 31;
+
+/// Take spinlock `n`, spinning until it is free.
+fn claim(n: usize) {
+    // Reading the spinlock claims it, and returns 0 if it was already claimed.
+    while unsafe { pac::SIO.spinlock(n).read() } == 0 {}
+    compiler_fence(Ordering::Acquire);
+}
+
+/// Release spinlock `n`.
+fn release(n: usize) {
+    compiler_fence(Ordering::Release);
+    // Writing any value releases the spinlock.
+    unsafe { pac::SIO.spinlock(n).write_value(1) };
+}
+
+/// A mutex that allows borrowing data across cores, executors and interrupts.
+///
+/// Locking it disables interrupts on the current core and takes hardware spinlock `N`. The
+/// lock is not reentrant: locking it again while it is locked deadlocks, as does locking any
+/// other `HardwareSpinlockMutex` with the same `N`. Spinlock 31 is reserved for the
+/// `critical-section` implementation, so `N` must be less than 31.
+pub struct HardwareSpinlockMutex<const N: usize> {
+    _private: (),
+}
+
+impl<const N: usize> HardwareSpinlockMutex<N> {
+    const VALID: () = assert!(N < CRITICAL_SECTION_SPINLOCK, "spinlock number out of range");
+
+    /// Create a new `HardwareSpinlockMutex`.
+    pub const fn new() -> Self {
+        let () = Self::VALID;
+        Self { _private: () }
+    }
+}
+
+unsafe impl<const N: usize> RawMutex for HardwareSpinlockMutex<N> {
+    const INIT: Self = Self::new();
+
+    fn lock<R>(&self, f: impl FnOnce() -> R) -> R {
+        let interrupts_active = cortex_m::register::primask::read().is_active();
+        cortex_m::interrupt::disable();
+        claim(N);
+
+        let r = f();
+
+        release(N);
+        if interrupts_active {
+            unsafe { cortex_m::interrupt::enable() };
+        }
+        r
+    }
+}
+
+#[cfg(feature = "critical-section-impl")]
+mod critical_section_impl {
+    use core::sync::atomic::{AtomicU8, Ordering};
+
+    use super::{claim, release, CRITICAL_SECTION_SPINLOCK};
+    use crate::multicore::current_core;
+
+    struct RpSpinlockCs;
+    critical_section::set_impl!(RpSpinlockCs);
+
+    /// Core holding the critical section plus one, or 0 if it is free.
+    static LOCK_OWNER: AtomicU8 = AtomicU8::new(0);
+
+    /// Token returned by nested acquires, which must not release the lock.
+    const LOCK_ALREADY_OWNED: u8 = 2;
+
+    unsafe impl critical_section::Impl for RpSpinlockCs {
+        unsafe fn acquire() -> u8 {
+            let interrupts_active = cortex_m::register::primask::read().is_active();
+            cortex_m::interrupt::disable();
+
+            // Only this core can set the owner to itself, so there's no race here.
+            let core = current_core() as u8 + 1;
+            if LOCK_OWNER.load(Ordering::Relaxed) == core {
+                return LOCK_ALREADY_OWNED;
+            }
+
+            claim(CRITICAL_SECTION_SPINLOCK);
+            LOCK_OWNER.store(core, Ordering::Relaxed);
+            interrupts_active as u8
+        }
+
+        unsafe fn release(token: u8) {
+            if token == LOCK_ALREADY_OWNED {
+                return;
+            }
+
+            LOCK_OWNER.store(0, Ordering::Relaxed);
+            release(CRITICAL_SECTION_SPINLOCK);
+            if token != 0 {
+                cortex_m::interrupt::enable();
+            }
+        }
+    }
+}
@@ -1,33 +1,138 @@
+//! Time driver, multiplexing any number of alarms onto a single hardware alarm.
+//!
+//! All alarms wait in one queue sorted by timestamp, whose entries live in the alarms themselves:
+//! the alarms the time driver allocates to the executors, and the ones of [`wait_until`] and
+//! [`wait_for`], of which any number can be pending at once.
+
 use core::cell::Cell;
+use core::future::poll_fn;
+use core::ptr::NonNull;
+use core::task::{Poll, Waker};
 
 use atomic_polyfill::{AtomicU8, Ordering};
 use critical_section::CriticalSection;
+use embassy_hal_common::drop::OnDrop;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_time::driver::{AlarmHandle, Driver};
+use embassy_time::{Duration, Instant};
 
 use crate::interrupt::{Interrupt, InterruptExt};
 use crate::{interrupt, pac};
 
-struct AlarmState {
+/// Entry of the alarm queue.
+struct Node {
     timestamp: Cell<u64>,
+    /// Called when the alarm triggers, for the alarms of the time driver.
     callback: Cell<Option<(fn(*mut ()), *mut ())>>,
+    /// Woken when the alarm triggers, for the others.
+    waker: Cell<Option<Waker>>,
+    /// Next alarm in the queue.
+    next: Cell<Option<NonNull<Node>>>,
+    queued: Cell<bool>,
+}
+unsafe impl Send for Node {}
+
+impl Node {
+    const fn new() -> Self {
+        Self {
+            timestamp: Cell::new(u64::MAX),
+            callback: Cell::new(None),
+            waker: Cell::new(None),
+            next: Cell::new(None),
+            queued: Cell::new(false),
+        }
+    }
+}
+
+/// Alarms waiting to trigger, linked through `Node::next` in timestamp order.
+///
+/// A node is removed before it is dropped, so the queue never points to a dead one.
+struct Queue {
+    head: Cell<Option<NonNull<Node>>>,
+}
+unsafe impl Send for Queue {}
+
+impl Queue {
+    const fn new() -> Self {
+        Self { head: Cell::new(None) }
+    }
+
+    fn first(&self) -> Option<&Node> {
+        self.head.get().map(|p| unsafe { p.as_ref() })
+    }
+
+    /// Insert `node` after the alarms with an earlier or equal timestamp.
+    fn insert(&self, node: &Node) {
+        let timestamp = node.timestamp.get();
+
+        let mut prev: Option<&Node> = None;
+        let mut cur = self.first();
+        while let Some(n) = cur {
+            if n.timestamp.get() > timestamp {
+                break;
+            }
+            prev = cur;
+            cur = n.next.get().map(|p| unsafe { p.as_ref() });
+        }
+
+        node.next.set(cur.map(NonNull::from));
+        node.queued.set(true);
+        match prev {
+            Some(p) => p.next.set(Some(NonNull::from(node))),
+            None => self.head.set(Some(NonNull::from(node))),
+        }
+    }
+
+    /// Remove `node`, if it is queued.
+    fn remove(&self, node: &Node) {
+        if !node.queued.get() {
+            return;
+        }
+
+        let mut prev: Option<&Node> = None;
+        let mut cur = self.first();
+        while let Some(n) = cur {
+            if core::ptr::eq(n, node) {
+                match prev {
+                    Some(p) => p.next.set(node.next.take()),
+                    None => self.head.set(node.next.take()),
+                }
+                node.queued.set(false);
+                return;
+            }
+            prev = cur;
+            cur = n.next.get().map(|p| unsafe { p.as_ref() });
+        }
+    }
+
+    /// Remove and return the first alarm, if its timestamp has passed.
+    fn pop_expired(&self, now: u64) -> Option<&Node> {
+        let node = self.first().filter(|n| n.timestamp.get() <= now)?;
+        self.head.set(node.next.take());
+        node.queued.set(false);
+        node.timestamp.set(u64::MAX);
+        Some(node)
+    }
 }
-unsafe impl Send for AlarmState {}
 
+/// Number of alarms the time driver can allocate, one per executor. The alarms of
+/// [`wait_until`] and [`wait_for`] don't count.
 const ALARM_COUNT: usize = 4;
-const DUMMY_ALARM: AlarmState = AlarmState {
-    timestamp: Cell::new(0),
-    callback: Cell::new(None),
-};
+const DUMMY_ALARM: Node = Node::new();
+
+/// The hardware alarm used to wake up for the first alarm of the queue.
+const HW_ALARM: usize = 0;
 
 struct TimerDriver {
-    alarms: Mutex<CriticalSectionRawMutex, [AlarmState; ALARM_COUNT]>,
+    alarms: Mutex<CriticalSectionRawMutex, [Node; ALARM_COUNT]>,
+    queue: Mutex<CriticalSectionRawMutex, Queue>,
     next_alarm: AtomicU8,
 }
 
 embassy_time::time_driver_impl!(static DRIVER: TimerDriver = TimerDriver{
     alarms:  Mutex::const_new(CriticalSectionRawMutex::new(), [DUMMY_ALARM; ALARM_COUNT]),
+    queue: Mutex::const_new(CriticalSectionRawMutex::new(), Queue::new()),
     next_alarm: AtomicU8::new(0),
 });
 
@@ -70,98 +175,113 @@ impl Driver for TimerDriver {
 
     fn set_alarm(&self, alarm: AlarmHandle, timestamp: u64) {
         let n = alarm.id() as usize;
-        critical_section::with(|cs| {
-            let alarm = &self.alarms.borrow(cs)[n];
-            alarm.timestamp.set(timestamp);
-
-            // Arm it.
-            // Note that we're not checking the high bits at all. This means the irq may fire early
-            // if the alarm is more than 72 minutes (2^32 us) in the future. This is OK, since on irq fire
-            // it is checked if the alarm time has passed.
-            unsafe { pac::TIMER.alarm(n).write_value(timestamp as u32) };
-
-            let now = self.now();
-
-            // If alarm timestamp has passed, trigger it instantly.
-            // This disarms it.
-            if timestamp <= now {
-                self.trigger_alarm(n, cs);
-            }
-        })
+        critical_section::with(|cs| self.schedule(&self.alarms.borrow(cs)[n], timestamp, cs))
     }
 }
 
 impl TimerDriver {
-    fn check_alarm(&self, n: usize) {
-        critical_section::with(|cs| {
-            let alarm = &self.alarms.borrow(cs)[n];
-            let timestamp = alarm.timestamp.get();
-            if timestamp <= self.now() {
-                self.trigger_alarm(n, cs)
-            } else {
-                // Not elapsed, arm it again.
-                // This can happen if it was set more than 2^32 us in the future.
-                unsafe { pac::TIMER.alarm(n).write_value(timestamp as u32) };
-            }
-        });
-
-        // clear the irq
-        unsafe { pac::TIMER.intr().write(|w| w.set_alarm(n, true)) }
+    /// Move `node` in the queue to trigger at `timestamp`, or never if `u64::MAX`.
+    fn schedule(&self, node: &Node, timestamp: u64, cs: CriticalSection) {
+        let queue = self.queue.borrow(cs);
+        queue.remove(node);
+        node.timestamp.set(timestamp);
+        if timestamp != u64::MAX {
+            queue.insert(node);
+        }
+        self.arm(cs);
     }
 
-    fn trigger_alarm(&self, n: usize, cs: CriticalSection) {
-        // disarm
-        unsafe { pac::TIMER.armed().write(|w| w.set_armed(1 << n)) }
+    /// Arm the hardware alarm for the first alarm of the queue.
+    fn arm(&self, cs: CriticalSection) {
+        let timestamp = match self.queue.borrow(cs).first() {
+            Some(node) => node.timestamp.get(),
+            None => {
+                // Nothing left in the queue, disarm.
+                unsafe { pac::TIMER.armed().write(|w| w.set_armed(1 << HW_ALARM)) }
+                return;
+            }
+        };
 
-        let alarm = &self.alarms.borrow(cs)[n];
-        alarm.timestamp.set(u64::MAX);
+        // Note that we're not checking the high bits at all. This means the irq may fire early
+        // if the alarm is more than 72 minutes (2^32 us) in the future. This is OK, since on irq fire
+        // it is checked if the alarm time has passed.
+        unsafe { pac::TIMER.alarm(HW_ALARM).write_value(timestamp as u32) };
 
-        // Call after clearing alarm, so the callback can set another alarm.
-        if let Some((f, ctx)) = alarm.callback.get() {
-            f(ctx);
+        // If the timestamp has passed, the hardware alarm won't fire before it wraps around.
+        // Trigger the alarm from the interrupt instead, as callbacks must not run from here.
+        if timestamp <= self.now() {
+            unsafe { interrupt::TIMER_IRQ_0::steal() }.pend();
         }
     }
-}
 
-/// safety: must be called exactly once at bootup
-pub unsafe fn init() {
-    // init alarms
-    critical_section::with(|cs| {
-        let alarms = DRIVER.alarms.borrow(cs);
-        for a in alarms {
-            a.timestamp.set(u64::MAX);
+    fn check_alarm(&self) {
+        // Clear the irq first, so that it isn't lost if the hardware alarm fires again while
+        // it is being re-armed.
+        unsafe { pac::TIMER.intr().write(|w| w.set_alarm(HW_ALARM, true)) }
+
+        // Each expired alarm is removed from the queue before its callback runs, outside of the
+        // critical section, so the callback can set alarms again.
+        loop {
+            let expired = critical_section::with(|cs| match self.queue.borrow(cs).pop_expired(self.now()) {
+                Some(node) => Some((node.callback.get(), node.waker.take())),
+                None => {
+                    self.arm(cs);
+                    None
+                }
+            });
+            match expired {
+                Some((callback, waker)) => {
+                    if let Some((f, ctx)) = callback {
+                        f(ctx);
+                    }
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+                None => break,
+            }
         }
-    });
-
-    // enable all irqs
-    pac::TIMER.inte().write(|w| {
-        w.set_alarm(0, true);
-        w.set_alarm(1, true);
-        w.set_alarm(2, true);
-        w.set_alarm(3, true);
-    });
-    interrupt::TIMER_IRQ_0::steal().enable();
-    interrupt::TIMER_IRQ_1::steal().enable();
-    interrupt::TIMER_IRQ_2::steal().enable();
-    interrupt::TIMER_IRQ_3::steal().enable();
+    }
 }
 
-#[interrupt]
-unsafe fn TIMER_IRQ_0() {
-    DRIVER.check_alarm(0)
+/// Wait until `at`.
+///
+/// Unlike [`embassy_time::Timer`], this doesn't go through the timer queue of the executor, and
+/// works in executors built without the `integrated-timers` feature. Any number of tasks can
+/// wait at once: the queue entry lives in the returned future.
+pub async fn wait_until(at: Instant) {
+    let node = Node::new();
+    // The future is pinned while it is polled, so the node stays in place while it is queued.
+    let _unlink = OnDrop::new(|| critical_section::with(|cs| DRIVER.queue.borrow(cs).remove(&node)));
+
+    poll_fn(|cx| {
+        critical_section::with(|cs| {
+            if DRIVER.now() >= at.as_ticks() {
+                return Poll::Ready(());
+            }
+            node.waker.set(Some(cx.waker().clone()));
+            if !node.queued.get() {
+                DRIVER.schedule(&node, at.as_ticks(), cs);
+            }
+            Poll::Pending
+        })
+    })
+    .await
 }
 
-#[interrupt]
-unsafe fn TIMER_IRQ_1() {
-    DRIVER.check_alarm(1)
+/// Wait for `duration`, see [`wait_until`].
+pub async fn wait_for(duration: Duration) {
+    wait_until(Instant::now() + duration).await
 }
 
-#[interrupt]
-unsafe fn TIMER_IRQ_2() {
-    DRIVER.check_alarm(2)
+/// safety: must be called exactly once at bootup
+pub unsafe fn init() {
+    // enable irq
+    pac::TIMER.inte().write(|w| w.set_alarm(HW_ALARM, true));
+    interrupt::TIMER_IRQ_0::steal().enable();
 }
 
 #[interrupt]
-unsafe fn TIMER_IRQ_3() {
-    DRIVER.check_alarm(3)
+unsafe fn TIMER_IRQ_0() {
+    DRIVER.check_alarm()
 }
@@ -1,7 +1,7 @@
 use core::future::{poll_fn, Future};
 use core::marker::PhantomData;
 use core::slice;
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicU8, Ordering};
 use core::task::Poll;
 
 use atomic_polyfill::compiler_fence;
@@ -48,6 +48,50 @@ static BUS_WAKER: AtomicWaker = NEW_AW;
 static EP_IN_WAKERS: [AtomicWaker; EP_COUNT] = [NEW_AW; EP_COUNT];
 static EP_OUT_WAKERS: [AtomicWaker; EP_COUNT] = [NEW_AW; EP_COUNT];
 
+// Buffer the CPU handles next on double-buffered endpoints, 0 or 1. The controller alternates
+// between the two buffers, starting from buffer 0 when the endpoint is enabled.
+const NEW_NEXT_BUF: AtomicU8 = AtomicU8::new(0);
+static EP_IN_NEXT_BUF: [AtomicU8; EP_COUNT] = [NEW_NEXT_BUF; EP_COUNT];
+static EP_OUT_NEXT_BUF: [AtomicU8; EP_COUNT] = [NEW_NEXT_BUF; EP_COUNT];
+
+type BufferControlReg = pac::common::Reg<pac::usb_dpram::regs::EpBufferControl, pac::common::RW>;
+
+/// Write the half of a buffer control register of buffer `n`, leaving the other half alone as
+/// the controller may be using the other buffer.
+///
+/// The buffer control registers are in the DPSRAM, which takes 8, 16 and 32-bit accesses like
+/// the other SRAMs. The narrow write caveats of the RP2040 datasheet ("Narrow IO Register
+/// Writes") are about the peripheral registers, and don't apply here. A 32-bit
+/// read-modify-write wouldn't do: the controller may update the half of the other buffer
+/// between the read and the write, which would then restore its stale AVAILABLE bit.
+unsafe fn write_buffer_control(
+    reg: BufferControlReg,
+    n: usize,
+    f: impl Fn(&mut pac::usb_dpram::regs::EpBufferControl),
+) {
+    let mut val = pac::usb_dpram::regs::EpBufferControl(0);
+    f(&mut val);
+    let ptr = (reg.ptr() as *mut u16).add(n);
+    ptr.write_volatile((val.0 >> (n * 16)) as u16);
+}
+
+/// Hand buffer `n` to the controller. The AVAILABLE bit must be set a few cycles after the
+/// rest of the buffer control register.
+unsafe fn arm_buffer(reg: BufferControlReg, n: usize, f: impl Fn(&mut pac::usb_dpram::regs::EpBufferControl)) {
+    write_buffer_control(reg, n, &f);
+    cortex_m::asm::delay(12);
+    write_buffer_control(reg, n, |w| {
+        f(w);
+        w.set_available(n, true);
+    });
+}
+
+/// Value of the DOUBLE_BUFFER_ISO_OFFSET field of the buffer control registers, which sets the
+/// distance between the buffers of a double-buffered isochronous endpoint.
+fn iso_offset_bits(stride: u16) -> u32 {
+    ((stride.trailing_zeros() - 7) as u32) << 27
+}
+
 struct EndpointBuffer<T: Instance> {
     addr: u16,
     len: u16,
@@ -86,6 +130,7 @@ struct EndpointData {
     ep_type: EndpointType, // only valid if used
     max_packet_size: u16,
     used: bool,
+    double_buffered: bool,
 }
 
 impl EndpointData {
@@ -94,8 +139,35 @@ impl EndpointData {
             ep_type: EndpointType::Bulk,
             max_packet_size: 0,
             used: false,
+            double_buffered: false,
         }
     }
+
+    /// Size of one buffer of the endpoint, which is also the distance between the two buffers
+    /// of a double-buffered endpoint.
+    fn buffer_stride(&self) -> u16 {
+        match self.ep_type {
+            // The second buffer of isochronous endpoints is 128 to 1024 bytes after the first.
+            EndpointType::Isochronous => self.max_packet_size.next_power_of_two().max(128),
+            // ep mem addrs must be 64-byte aligned, so there's no point in trying
+            // to allocate smaller chunks to save memory.
+            _ => 64,
+        }
+    }
+
+    /// Bits of the buffer control register that stay the same for every buffer.
+    fn buffer_control_bits(&self) -> u32 {
+        match (self.ep_type, self.double_buffered) {
+            (EndpointType::Isochronous, true) => iso_offset_bits(self.buffer_stride()),
+            _ => 0,
+        }
+    }
+
+    /// PID of the packets of buffer `n` of a double-buffered endpoint. The buffers alternate,
+    /// so each of them always holds the same PID. Full-speed isochronous packets are DATA0.
+    fn double_buffer_pid(&self, n: usize) -> bool {
+        self.ep_type != EndpointType::Isochronous && n == 1
+    }
 }
 
 pub struct Driver<'d, T: Instance> {
@@ -103,6 +175,7 @@ pub struct Driver<'d, T: Instance> {
     ep_in: [EndpointData; EP_COUNT],
     ep_out: [EndpointData; EP_COUNT],
     ep_mem_free: u16, // first free address in EP mem, in bytes.
+    bulk_double_buffering: bool,
 }
 
 impl<'d, T: Instance> Driver<'d, T> {
@@ -147,9 +220,19 @@ impl<'d, T: Instance> Driver<'d, T> {
             ep_in: [EndpointData::new(); EP_COUNT],
             ep_out: [EndpointData::new(); EP_COUNT],
             ep_mem_free: 0x180, // data buffer region
+            bulk_double_buffering: false,
         }
     }
 
+    /// Double-buffer the bulk endpoints allocated from now on, so that the controller can
+    /// transfer a packet while the previous one is handled, at the cost of twice the endpoint
+    /// memory. This must be called before the driver is passed to the `embassy-usb` builder.
+    ///
+    /// Isochronous endpoints are always double-buffered.
+    pub fn set_bulk_double_buffering(&mut self, enabled: bool) {
+        self.bulk_double_buffering = enabled;
+    }
+
     fn on_interrupt(_: *mut ()) {
         unsafe {
             let regs = T::regs();
@@ -220,33 +303,51 @@ impl<'d, T: Instance> Driver<'d, T> {
         let (index, ep) = index.ok_or(EndpointAllocError)?;
         assert!(!ep.used);
 
-        if max_packet_size > 64 {
+        let max_allowed = match ep_type {
+            EndpointType::Isochronous => 1023,
+            _ => 64,
+        };
+        if max_packet_size > max_allowed {
             warn!("max_packet_size too high: {}", max_packet_size);
             return Err(EndpointAllocError);
         }
 
-        // ep mem addrs must be 64-byte aligned, so there's no point in trying
-        // to allocate smaller chunks to save memory.
-        let len = 64;
+        let data = EndpointData {
+            ep_type,
+            max_packet_size,
+            used: true,
+            double_buffered: match ep_type {
+                EndpointType::Isochronous => true,
+                EndpointType::Bulk => self.bulk_double_buffering,
+                _ => false,
+            },
+        };
+
+        let stride = data.buffer_stride();
+        let len = if data.double_buffered { 2 * stride } else { stride };
 
         let addr = self.ep_mem_free;
-        if addr + len > EP_MEMORY_SIZE as _ {
+        if addr as usize + len as usize > EP_MEMORY_SIZE {
             warn!("Endpoint memory full");
             return Err(EndpointAllocError);
         }
         self.ep_mem_free += len;
 
-        let buf = EndpointBuffer {
+        let second_addr = if data.double_buffered { addr + stride } else { addr };
+        let bufs = [
+            EndpointBuffer::new(addr, stride),
+            EndpointBuffer::new(second_addr, stride),
+        ];
+
+        trace!(
+            "  index={} addr={} len={} double_buffered={}",
+            index,
             addr,
             len,
-            _phantom: PhantomData,
-        };
-
-        trace!("  index={} addr={} len={}", index, buf.addr, buf.len);
+            data.double_buffered
+        );
 
-        ep.ep_type = ep_type;
-        ep.used = true;
-        ep.max_packet_size = max_packet_size;
+        *ep = data;
 
         let ep_type_reg = match ep_type {
             EndpointType::Bulk => pac::usb_dpram::vals::EpControlEndpointType::BULK,
@@ -261,6 +362,7 @@ impl<'d, T: Instance> Driver<'d, T> {
                     w.set_enable(false);
                     w.set_buffer_address(addr);
                     w.set_interrupt_per_buff(true);
+                    w.set_double_buffered(data.double_buffered);
                     w.set_endpoint_type(ep_type_reg);
                 })
             },
@@ -269,6 +371,7 @@ impl<'d, T: Instance> Driver<'d, T> {
                     w.set_enable(false);
                     w.set_buffer_address(addr);
                     w.set_interrupt_per_buff(true);
+                    w.set_double_buffered(data.double_buffered);
                     w.set_endpoint_type(ep_type_reg);
                 })
             },
@@ -282,7 +385,8 @@ impl<'d, T: Instance> Driver<'d, T> {
                 max_packet_size,
                 interval,
             },
-            buf,
+            data,
+            bufs,
         })
     }
 }
@@ -335,6 +439,7 @@ impl<'d, T: Instance> driver::Driver<'d> for Driver<'d, T> {
             Bus {
                 phantom: PhantomData,
                 inited: false,
+                ep_in: self.ep_in,
                 ep_out: self.ep_out,
             },
             ControlPipe {
@@ -347,6 +452,7 @@ impl<'d, T: Instance> driver::Driver<'d> for Driver<'d, T> {
 
 pub struct Bus<'d, T: Instance> {
     phantom: PhantomData<&'d mut T>,
+    ep_in: [EndpointData; EP_COUNT],
     ep_out: [EndpointData; EP_COUNT],
     inited: bool,
 }
@@ -415,12 +521,16 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
         unsafe { regs.addr_endp().write(|w| w.set_address(addr)) }
     }
 
-    fn endpoint_set_stalled(&mut self, _ep_addr: EndpointAddress, _stalled: bool) {
-        todo!();
+    fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
+        let ep = match ep_addr.direction() {
+            Direction::In => &self.ep_in[ep_addr.index()],
+            Direction::Out => &self.ep_out[ep_addr.index()],
+        };
+        unsafe { endpoint_set_stalled::<T>(ep_addr, ep, stalled) }
     }
 
-    fn endpoint_is_stalled(&mut self, _ep_addr: EndpointAddress) -> bool {
-        todo!();
+    fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
+        endpoint_is_stalled::<T>(ep_addr)
     }
 
     fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
@@ -430,29 +540,17 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
         }
 
         let n = ep_addr.index();
-        match ep_addr.direction() {
-            Direction::In => unsafe {
-                T::dpram().ep_in_control(n - 1).modify(|w| w.set_enable(enabled));
-                T::dpram().ep_in_buffer_control(ep_addr.index()).write(|w| {
-                    w.set_pid(0, true); // first packet is DATA0, but PID is flipped before
-                });
-                EP_IN_WAKERS[n].wake();
-            },
-            Direction::Out => unsafe {
-                T::dpram().ep_out_control(n - 1).modify(|w| w.set_enable(enabled));
-
-                T::dpram().ep_out_buffer_control(ep_addr.index()).write(|w| {
-                    w.set_pid(0, false);
-                    w.set_length(0, self.ep_out[n].max_packet_size);
-                });
-                cortex_m::asm::delay(12);
-                T::dpram().ep_out_buffer_control(ep_addr.index()).write(|w| {
-                    w.set_pid(0, false);
-                    w.set_length(0, self.ep_out[n].max_packet_size);
-                    w.set_available(0, true);
-                });
-                EP_OUT_WAKERS[n].wake();
-            },
+        unsafe {
+            match ep_addr.direction() {
+                Direction::In => {
+                    T::dpram().ep_in_control(n - 1).modify(|w| w.set_enable(enabled));
+                    reset_buffers::<T>(ep_addr, &self.ep_in[n]);
+                }
+                Direction::Out => {
+                    T::dpram().ep_out_control(n - 1).modify(|w| w.set_enable(enabled));
+                    reset_buffers::<T>(ep_addr, &self.ep_out[n]);
+                }
+            }
         }
     }
 
@@ -471,13 +569,100 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
     type RemoteWakeupFuture<'a> =  impl Future<Output = Result<(), Unsupported>> + 'a where Self: 'a;
 
     fn remote_wakeup(&mut self) -> Self::RemoteWakeupFuture<'_> {
-        async move { Err(Unsupported) }
+        async move {
+            // The SIE drives the resume signaling for the required time by itself, the host then
+            // takes over and the resume is reported as a bus event.
+            let regs = T::regs();
+            unsafe { regs.sie_ctrl().modify(|w| w.set_resume(true)) };
+            Ok(())
+        }
+    }
+}
+
+/// Re-initializes the buffers of a non-control endpoint, which resets its data toggle and clears
+/// its STALL condition, and arms the buffers of an OUT endpoint to receive.
+unsafe fn reset_buffers<T: Instance>(ep_addr: EndpointAddress, ep: &EndpointData) {
+    let n = ep_addr.index();
+    match ep_addr.direction() {
+        Direction::In => {
+            if ep.double_buffered {
+                T::dpram().ep_in_buffer_control(n).write(|w| {
+                    w.0 = ep.buffer_control_bits();
+                    w.set_reset(true);
+                });
+                EP_IN_NEXT_BUF[n].store(0, Ordering::Relaxed);
+            } else {
+                T::dpram().ep_in_buffer_control(n).write(|w| {
+                    w.set_pid(0, true); // first packet is DATA0, but PID is flipped before
+                });
+            }
+            EP_IN_WAKERS[n].wake();
+        }
+        Direction::Out => {
+            let bufcontrol = T::dpram().ep_out_buffer_control(n);
+            if ep.double_buffered {
+                bufcontrol.write(|w| {
+                    w.0 = ep.buffer_control_bits();
+                    w.set_reset(true);
+                });
+                EP_OUT_NEXT_BUF[n].store(0, Ordering::Relaxed);
+                for i in 0..2 {
+                    arm_buffer(bufcontrol, i, |w| {
+                        w.0 = ep.buffer_control_bits();
+                        w.set_pid(i, ep.double_buffer_pid(i));
+                        w.set_length(i, ep.max_packet_size);
+                    });
+                }
+            } else {
+                bufcontrol.write(|w| {
+                    w.set_pid(0, false);
+                    w.set_length(0, ep.max_packet_size);
+                });
+                cortex_m::asm::delay(12);
+                bufcontrol.write(|w| {
+                    w.set_pid(0, false);
+                    w.set_length(0, ep.max_packet_size);
+                    w.set_available(0, true);
+                });
+            }
+            EP_OUT_WAKERS[n].wake();
+        }
+    }
+}
+
+fn buffer_control<T: Instance>(ep_addr: EndpointAddress) -> BufferControlReg {
+    match ep_addr.direction() {
+        Direction::In => T::dpram().ep_in_buffer_control(ep_addr.index()),
+        Direction::Out => T::dpram().ep_out_buffer_control(ep_addr.index()),
+    }
+}
+
+unsafe fn endpoint_set_stalled<T: Instance>(ep_addr: EndpointAddress, ep: &EndpointData, stalled: bool) {
+    let bufcontrol = buffer_control::<T>(ep_addr);
+    if ep_addr.index() == 0 {
+        // The control endpoint only stalls once armed, and the next SETUP packet clears it.
+        if stalled {
+            T::regs().ep_stall_arm().write_set(|w| match ep_addr.direction() {
+                Direction::In => w.set_ep0_in(true),
+                Direction::Out => w.set_ep0_out(true),
+            });
+        }
+        bufcontrol.modify(|w| w.set_stall(stalled));
+    } else if stalled {
+        bufcontrol.modify(|w| w.set_stall(true));
+    } else {
+        reset_buffers::<T>(ep_addr, ep);
     }
 }
 
+fn endpoint_is_stalled<T: Instance>(ep_addr: EndpointAddress) -> bool {
+    unsafe { buffer_control::<T>(ep_addr).read() }.stall()
+}
+
 trait Dir {
     fn dir() -> Direction;
     fn waker(i: usize) -> &'static AtomicWaker;
+    fn next_buf(i: usize) -> &'static AtomicU8;
 }
 
 pub enum In {}
@@ -490,6 +675,11 @@ impl Dir for In {
     fn waker(i: usize) -> &'static AtomicWaker {
         &EP_IN_WAKERS[i]
     }
+
+    #[inline]
+    fn next_buf(i: usize) -> &'static AtomicU8 {
+        &EP_IN_NEXT_BUF[i]
+    }
 }
 
 pub enum Out {}
@@ -502,12 +692,37 @@ impl Dir for Out {
     fn waker(i: usize) -> &'static AtomicWaker {
         &EP_OUT_WAKERS[i]
     }
+
+    #[inline]
+    fn next_buf(i: usize) -> &'static AtomicU8 {
+        &EP_OUT_NEXT_BUF[i]
+    }
 }
 
 pub struct Endpoint<'d, T: Instance, D> {
     _phantom: PhantomData<(&'d mut T, D)>,
     info: EndpointInfo,
-    buf: EndpointBuffer<T>,
+    data: EndpointData,
+    // Both are the same buffer if the endpoint is not double-buffered.
+    bufs: [EndpointBuffer<T>; 2],
+}
+
+impl<'d, T: Instance, D: Dir> Endpoint<'d, T, D> {
+    /// Buffer to handle next, 0 or 1.
+    fn next_buf(&self) -> usize {
+        if self.data.double_buffered {
+            D::next_buf(self.info.addr.index()).load(Ordering::Relaxed) as usize
+        } else {
+            0
+        }
+    }
+
+    /// Move on to the other buffer, after handling buffer `n`.
+    fn advance_buf(&self, n: usize) {
+        if self.data.double_buffered {
+            D::next_buf(self.info.addr.index()).store(n as u8 ^ 1, Ordering::Relaxed);
+        }
+    }
 }
 
 impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
@@ -534,6 +749,18 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
             trace!("wait_enabled IN OK");
         }
     }
+
+    fn set_stall(&mut self) {
+        unsafe { endpoint_set_stalled::<T>(self.info.addr, &self.data, true) }
+    }
+
+    fn clear_stall(&mut self) {
+        unsafe { endpoint_set_stalled::<T>(self.info.addr, &self.data, false) }
+    }
+
+    fn is_stalled(&self) -> bool {
+        endpoint_is_stalled::<T>(self.info.addr)
+    }
 }
 
 impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
@@ -560,6 +787,18 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
             trace!("wait_enabled OUT OK");
         }
     }
+
+    fn set_stall(&mut self) {
+        unsafe { endpoint_set_stalled::<T>(self.info.addr, &self.data, true) }
+    }
+
+    fn clear_stall(&mut self) {
+        unsafe { endpoint_set_stalled::<T>(self.info.addr, &self.data, false) }
+    }
+
+    fn is_stalled(&self) -> bool {
+        endpoint_is_stalled::<T>(self.info.addr)
+    }
 }
 
 impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
@@ -569,10 +808,11 @@ impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
         async move {
             trace!("READ WAITING, buf.len() = {}", buf.len());
             let index = self.info.addr.index();
+            let n = self.next_buf();
             let val = poll_fn(|cx| unsafe {
                 EP_OUT_WAKERS[index].register(cx.waker());
                 let val = T::dpram().ep_out_buffer_control(index).read();
-                if val.available(0) {
+                if val.available(n) {
                     Poll::Pending
                 } else {
                     Poll::Ready(val)
@@ -580,29 +820,42 @@ impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
             })
             .await;
 
-            let rx_len = val.length(0) as usize;
-            if rx_len > buf.len() {
-                return Err(EndpointError::BufferOverflow);
-            }
-            self.buf.read(&mut buf[..rx_len]);
-
-            trace!("READ OK, rx_len = {}", rx_len);
+            let rx_len = val.length(n) as usize;
+            let result = if rx_len > buf.len() {
+                Err(EndpointError::BufferOverflow)
+            } else {
+                self.bufs[n].read(&mut buf[..rx_len]);
+                trace!("READ OK, rx_len = {}", rx_len);
+                Ok(rx_len)
+            };
 
             unsafe {
-                let pid = !val.pid(0);
-                T::dpram().ep_out_buffer_control(index).write(|w| {
-                    w.set_pid(0, pid);
-                    w.set_length(0, self.info.max_packet_size);
-                });
-                cortex_m::asm::delay(12);
-                T::dpram().ep_out_buffer_control(index).write(|w| {
-                    w.set_pid(0, pid);
-                    w.set_length(0, self.info.max_packet_size);
-                    w.set_available(0, true);
-                });
+                let bufcontrol = T::dpram().ep_out_buffer_control(index);
+                if self.data.double_buffered {
+                    // The packet is dropped on overflow, as the controller goes on with the
+                    // other buffer.
+                    self.advance_buf(n);
+                    arm_buffer(bufcontrol, n, |w| {
+                        w.0 = self.data.buffer_control_bits();
+                        w.set_pid(n, self.data.double_buffer_pid(n));
+                        w.set_length(n, self.info.max_packet_size);
+                    });
+                } else if result.is_ok() {
+                    let pid = !val.pid(0);
+                    bufcontrol.write(|w| {
+                        w.set_pid(0, pid);
+                        w.set_length(0, self.info.max_packet_size);
+                    });
+                    cortex_m::asm::delay(12);
+                    bufcontrol.write(|w| {
+                        w.set_pid(0, pid);
+                        w.set_length(0, self.info.max_packet_size);
+                        w.set_available(0, true);
+                    });
+                }
             }
 
-            Ok(rx_len)
+            result
         }
     }
 }
@@ -619,10 +872,11 @@ impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
             trace!("WRITE WAITING");
 
             let index = self.info.addr.index();
+            let n = self.next_buf();
             let val = poll_fn(|cx| unsafe {
                 EP_IN_WAKERS[index].register(cx.waker());
                 let val = T::dpram().ep_in_buffer_control(index).read();
-                if val.available(0) {
+                if val.available(n) {
                     Poll::Pending
                 } else {
                     Poll::Ready(val)
@@ -630,22 +884,33 @@ impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
             })
             .await;
 
-            self.buf.write(buf);
+            self.bufs[n].write(buf);
 
             unsafe {
-                let pid = !val.pid(0);
-                T::dpram().ep_in_buffer_control(index).write(|w| {
-                    w.set_pid(0, pid);
-                    w.set_length(0, buf.len() as _);
-                    w.set_full(0, true);
-                });
-                cortex_m::asm::delay(12);
-                T::dpram().ep_in_buffer_control(index).write(|w| {
-                    w.set_pid(0, pid);
-                    w.set_length(0, buf.len() as _);
-                    w.set_full(0, true);
-                    w.set_available(0, true);
-                });
+                let bufcontrol = T::dpram().ep_in_buffer_control(index);
+                if self.data.double_buffered {
+                    self.advance_buf(n);
+                    arm_buffer(bufcontrol, n, |w| {
+                        w.0 = self.data.buffer_control_bits();
+                        w.set_pid(n, self.data.double_buffer_pid(n));
+                        w.set_length(n, buf.len() as _);
+                        w.set_full(n, true);
+                    });
+                } else {
+                    let pid = !val.pid(0);
+                    bufcontrol.write(|w| {
+                        w.set_pid(0, pid);
+                        w.set_length(0, buf.len() as _);
+                        w.set_full(0, true);
+                    });
+                    cortex_m::asm::delay(12);
+                    bufcontrol.write(|w| {
+                        w.set_pid(0, pid);
+                        w.set_length(0, buf.len() as _);
+                        w.set_full(0, true);
+                        w.set_available(0, true);
+                    });
+                }
             }
 
             trace!("WRITE OK");
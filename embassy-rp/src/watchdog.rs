@@ -0,0 +1,179 @@
+//! Watchdog
+//!
+//! The watchdog resets the chip unless it is fed before its timer runs out. It also keeps eight
+//! scratch registers across watchdog resets, and records whether the last reset was caused by
+//! it. The bootrom uses scratch registers 4 to 7 to jump to an address after a watchdog reset,
+//! which [`Watchdog::reboot_to`] uses, so only registers 0 to 3 are available through
+//! [`Watchdog::set_scratch`].
+
+use core::marker::PhantomData;
+
+use embassy_time::Duration;
+
+use crate::{pac, peripherals, Peripheral};
+
+/// Magic value in scratch register 4 telling the bootrom to jump to the address in scratch 7.
+const BOOT_MAGIC: u32 = 0xb007c0d3;
+
+/// Number of scratch registers available to the application.
+pub const SCRATCH_COUNT: usize = 4;
+
+/// Reason for the last reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetReason {
+    /// Power-on or brownout.
+    PowerOn,
+    /// The RUN pin was pulled low.
+    RunPin,
+    /// Reset from the debug port.
+    Debug,
+    /// The watchdog timer ran out.
+    WatchdogTimeout,
+    /// The watchdog reset was triggered by software, for example with [`Watchdog::trigger_reset`].
+    WatchdogForced,
+}
+
+/// Make the bootrom boot normally after a watchdog reset.
+fn clear_boot_magic() {
+    unsafe { pac::WATCHDOG.scratch(4).write_value(0) }
+}
+
+/// Get the reason for the last reset.
+pub fn reset_reason() -> ResetReason {
+    unsafe {
+        // A chip-level reset clears the watchdog reason, so it's only set if the watchdog was
+        // the last one to reset the chip.
+        let reason = pac::WATCHDOG.reason().read();
+        if reason.timer() {
+            return ResetReason::WatchdogTimeout;
+        }
+        if reason.force() {
+            return ResetReason::WatchdogForced;
+        }
+
+        let chip_reset = pac::VREG_AND_CHIP_RESET.chip_reset().read();
+        if chip_reset.had_psm_restart() {
+            ResetReason::Debug
+        } else if chip_reset.had_run() {
+            ResetReason::RunPin
+        } else {
+            ResetReason::PowerOn
+        }
+    }
+}
+
+/// Watchdog driver.
+pub struct Watchdog<'d> {
+    load_value: u32,
+    phantom: PhantomData<&'d mut peripherals::WATCHDOG>,
+}
+
+impl<'d> Watchdog<'d> {
+    /// Create a new watchdog driver. The watchdog is left as is until [`start`](Self::start)
+    /// is called.
+    pub fn new(_watchdog: impl Peripheral<P = peripherals::WATCHDOG> + 'd) -> Self {
+        Self {
+            load_value: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Pause the watchdog timer while a debugger halts the cores.
+    pub fn pause_on_debug(&mut self, pause: bool) {
+        unsafe {
+            pac::WATCHDOG.ctrl().modify(|w| {
+                w.set_pause_dbg0(pause);
+                w.set_pause_dbg1(pause);
+                w.set_pause_jtag(pause);
+            })
+        }
+    }
+
+    fn load_counter(&self, counter: u32) {
+        unsafe { pac::WATCHDOG.load().write_value(pac::watchdog::regs::Load(counter)) };
+    }
+
+    fn enable(&self, enable: bool) {
+        unsafe {
+            if enable {
+                // Reset everything but the oscillators, which keep the watchdog tick running.
+                pac::PSM.wdsel().write(|w| {
+                    w.0 = 0x0001ffff;
+                    w.set_xosc(false);
+                    w.set_rosc(false);
+                });
+            }
+            pac::WATCHDOG.ctrl().modify(|w| w.set_enable(enable))
+        }
+    }
+
+    /// Start the watchdog, resetting the chip if it isn't fed within `period`.
+    ///
+    /// Panics if `period` is longer than about 8.3 seconds.
+    pub fn start(&mut self, period: Duration) {
+        // Due to erratum RP2040-E1, the counter decrements twice per microsecond tick.
+        let load_value = period.as_micros() * 2;
+        assert!(load_value <= 0xff_ffff, "period too long");
+        self.load_value = load_value as u32;
+
+        self.enable(false);
+        clear_boot_magic();
+        self.load_counter(self.load_value);
+        self.enable(true);
+    }
+
+    /// Feed the watchdog, restarting its timer.
+    pub fn feed(&mut self) {
+        self.load_counter(self.load_value)
+    }
+
+    /// Stop the watchdog.
+    pub fn stop(&mut self) {
+        self.enable(false)
+    }
+
+    /// Reset the chip now, through the watchdog.
+    pub fn trigger_reset(&mut self) -> ! {
+        clear_boot_magic();
+        self.trigger()
+    }
+
+    fn trigger(&mut self) -> ! {
+        self.enable(true);
+        unsafe { pac::WATCHDOG.ctrl().modify(|w| w.set_trigger(true)) };
+        loop {
+            cortex_m::asm::nop();
+        }
+    }
+
+    /// Reset the chip now, and have the bootrom jump to `pc` with the stack pointer at `sp`
+    /// instead of booting from flash.
+    ///
+    /// `pc` must point to Thumb code in RAM or ROM, as flash is not set up yet when the bootrom
+    /// jumps to it.
+    pub fn reboot_to(&mut self, pc: u32, sp: u32) -> ! {
+        let pc = pc | 1;
+        unsafe {
+            let w = pac::WATCHDOG;
+            w.scratch(4).write_value(BOOT_MAGIC);
+            w.scratch(5).write_value(pc ^ BOOT_MAGIC.wrapping_neg());
+            w.scratch(6).write_value(sp);
+            w.scratch(7).write_value(pc);
+        }
+        self.trigger()
+    }
+
+    /// Store `value` in scratch register `n`, from 0 to [`SCRATCH_COUNT`] - 1. It is kept
+    /// across watchdog resets, but not power-on or RUN pin resets.
+    pub fn set_scratch(&mut self, n: usize, value: u32) {
+        assert!(n < SCRATCH_COUNT);
+        unsafe { pac::WATCHDOG.scratch(n).write_value(value) }
+    }
+
+    /// Read scratch register `n`, from 0 to [`SCRATCH_COUNT`] - 1.
+    pub fn get_scratch(&self, n: usize) -> u32 {
+        assert!(n < SCRATCH_COUNT);
+        unsafe { pac::WATCHDOG.scratch(n).read() }
+    }
+}
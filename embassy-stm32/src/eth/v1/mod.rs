@@ -41,6 +41,8 @@ pub struct Ethernet<'d, T: Instance, P: PHY, const TX: usize, const RX: usize> {
     clock_range: Cr,
     phy_addr: u8,
     mac_addr: [u8; 6],
+    /// Number of multicast groups joined, which are received while nonzero.
+    multicast_groups: u8,
 }
 
 #[cfg(eth_v1a)]
@@ -225,6 +227,7 @@ impl<'d, T: Instance, P: PHY, const TX: usize, const RX: usize> Ethernet<'d, T,
             clock_range,
             phy_addr,
             mac_addr,
+            multicast_groups: 0,
         };
 
         this.state.with(|s| {
@@ -332,6 +335,22 @@ impl<'d, T: Instance, P: PHY, const TX: usize, const RX: usize> Device for Ether
     fn ethernet_address(&self) -> [u8; 6] {
         self.mac_addr
     }
+
+    // The frames of every multicast group are received while any group is joined, which is
+    // simpler than managing the hash filter.
+    fn join_multicast(&mut self, _address: [u8; 6]) {
+        self.multicast_groups += 1;
+        // NOTE(unsafe) The interrupt doesn't use this register
+        unsafe { ETH.ethernet_mac().macffr().modify(|w| w.set_pam(true)) };
+    }
+
+    fn leave_multicast(&mut self, _address: [u8; 6]) {
+        self.multicast_groups = self.multicast_groups.saturating_sub(1);
+        if self.multicast_groups == 0 {
+            // NOTE(unsafe) The interrupt doesn't use this register
+            unsafe { ETH.ethernet_mac().macffr().modify(|w| w.set_pam(false)) };
+        }
+    }
 }
 
 impl<'d, T: Instance, P: PHY, const TX: usize, const RX: usize> Drop for Ethernet<'d, T, P, TX, RX> {
@@ -8,7 +8,7 @@ use core::task::Poll;
 use atomic_polyfill::{AtomicBool, AtomicU8};
 use embassy_hal_common::into_ref;
 use embassy_sync::waitqueue::AtomicWaker;
-use embassy_time::{block_for, Duration};
+use embassy_time::{block_for, Duration, Timer};
 use embassy_usb_driver as driver;
 use embassy_usb_driver::{
     Direction, EndpointAddress, EndpointAllocError, EndpointError, EndpointInfo, EndpointType, Event, Unsupported,
@@ -39,6 +39,9 @@ static IRQ_FLAGS: AtomicU8 = AtomicU8::new(0);
 const IRQ_FLAG_RESET: u8 = 0x01;
 const IRQ_FLAG_SUSPEND: u8 = 0x02;
 const IRQ_FLAG_RESUME: u8 = 0x04;
+// Isochronous endpoints which completed a transaction, as bitmasks of endpoint indices.
+static ISO_IN_DONE: AtomicU8 = AtomicU8::new(0);
+static ISO_OUT_DONE: AtomicU8 = AtomicU8::new(0);
 
 fn convert_type(t: EndpointType) -> EpType {
     match t {
@@ -230,6 +233,14 @@ impl<'d, T: Instance> Driver<'d, T> {
             if istr.ctr() {
                 let index = istr.ep_id() as usize;
                 let mut epr = regs.epr(index).read();
+                if epr.ep_type() == EpType::ISO {
+                    if epr.ctr_rx() {
+                        ISO_OUT_DONE.fetch_or(1 << index, Ordering::AcqRel);
+                    }
+                    if epr.ctr_tx() {
+                        ISO_IN_DONE.fetch_or(1 << index, Ordering::AcqRel);
+                    }
+                }
                 if epr.ctr_rx() {
                     if index == 0 && epr.setup() {
                         EP0_SETUP.store(true, Ordering::Relaxed);
@@ -284,7 +295,8 @@ impl<'d, T: Instance> Driver<'d, T> {
                 Direction::Out => ep.used_out,
                 Direction::In => ep.used_in,
             };
-            !used || (ep.ep_type == ep_type && !used_dir)
+            // Isochronous endpoints use the buffers of both directions for double buffering.
+            !used || (ep.ep_type == ep_type && ep_type != EndpointType::Isochronous && !used_dir)
         });
 
         let (index, ep) = match index {
@@ -308,6 +320,15 @@ impl<'d, T: Instance> Driver<'d, T> {
                     ep_out_len::<T>(index).write_value(len_bits);
                 }
 
+                if ep_type == EndpointType::Isochronous {
+                    // Second buffer, right after the first one.
+                    let addr = self.alloc_ep_mem(len);
+                    unsafe {
+                        ep_in_addr::<T>(index).write_value(addr);
+                        ep_in_len::<T>(index).write_value(len_bits);
+                    }
+                }
+
                 EndpointBuffer {
                     addr,
                     len,
@@ -326,6 +347,12 @@ impl<'d, T: Instance> Driver<'d, T> {
                     // ep_in_len is written when actually TXing packets.
                 }
 
+                if ep_type == EndpointType::Isochronous {
+                    // Second buffer, right after the first one.
+                    let addr = self.alloc_ep_mem(len);
+                    unsafe { ep_out_addr::<T>(index).write_value(addr) };
+                }
+
                 EndpointBuffer {
                     addr,
                     len,
@@ -467,6 +494,9 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
                         })
                     }
 
+                    ISO_IN_DONE.store(0, Ordering::Release);
+                    ISO_OUT_DONE.store(0, Ordering::Release);
+
                     for w in &EP_IN_WAKERS {
                         w.wake()
                     }
@@ -503,69 +533,44 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
     }
 
     fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
-        // This can race, so do a retry loop.
-        let reg = T::regs().epr(ep_addr.index() as _);
-        match ep_addr.direction() {
-            Direction::In => {
-                loop {
-                    let r = unsafe { reg.read() };
-                    match r.stat_tx() {
-                        Stat::DISABLED => break, // if disabled, stall does nothing.
-                        Stat::STALL => break,    // done!
-                        _ => {
-                            let want_stat = match stalled {
-                                false => Stat::NAK,
-                                true => Stat::STALL,
-                            };
-                            let mut w = invariant(r);
-                            w.set_stat_tx(Stat(r.stat_tx().0 ^ want_stat.0));
-                            unsafe { reg.write_value(w) };
-                        }
-                    }
-                }
-                EP_IN_WAKERS[ep_addr.index()].wake();
-            }
-            Direction::Out => {
-                loop {
-                    let r = unsafe { reg.read() };
-                    match r.stat_rx() {
-                        Stat::DISABLED => break, // if disabled, stall does nothing.
-                        Stat::STALL => break,    // done!
-                        _ => {
-                            let want_stat = match stalled {
-                                false => Stat::VALID,
-                                true => Stat::STALL,
-                            };
-                            let mut w = invariant(r);
-                            w.set_stat_rx(Stat(r.stat_rx().0 ^ want_stat.0));
-                            unsafe { reg.write_value(w) };
-                        }
-                    }
-                }
-                EP_OUT_WAKERS[ep_addr.index()].wake();
-            }
-        }
+        endpoint_set_stalled::<T>(ep_addr, stalled)
     }
 
     fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
-        let regs = T::regs();
-        let epr = unsafe { regs.epr(ep_addr.index() as _).read() };
-        match ep_addr.direction() {
-            Direction::In => epr.stat_tx() == Stat::STALL,
-            Direction::Out => epr.stat_rx() == Stat::STALL,
-        }
+        endpoint_is_stalled::<T>(ep_addr)
     }
 
     fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
         trace!("set_enabled {:x} {}", ep_addr, enabled);
         // This can race, so do a retry loop.
-        let reg = T::regs().epr(ep_addr.index() as _);
+        let index = ep_addr.index();
+        let reg = T::regs().epr(index as _);
         trace!("EPR before: {:04x}", unsafe { reg.read() }.0);
+
+        // Isochronous endpoints don't handshake, they stay VALID while enabled.
+        let iso = index != 0 && self.ep_types[index - 1] == EpType::ISO;
+        if iso && enabled {
+            match ep_addr.direction() {
+                Direction::In => {
+                    ISO_IN_DONE.fetch_and(!(1 << index), Ordering::AcqRel);
+                    // Send zero-length packets until there's data.
+                    unsafe {
+                        ep_in_len::<T>(index).write_value(0);
+                        ep_out_len::<T>(index).write_value(0);
+                    }
+                }
+                Direction::Out => {
+                    ISO_OUT_DONE.fetch_and(!(1 << index), Ordering::AcqRel);
+                }
+            }
+        }
+
         match ep_addr.direction() {
             Direction::In => {
                 loop {
                     let want_stat = match enabled {
                         false => Stat::DISABLED,
+                        true if iso => Stat::VALID,
                         true => Stat::NAK,
                     };
                     let r = unsafe { reg.read() };
@@ -576,7 +581,7 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
                     w.set_stat_tx(Stat(r.stat_tx().0 ^ want_stat.0));
                     unsafe { reg.write_value(w) };
                 }
-                EP_IN_WAKERS[ep_addr.index()].wake();
+                EP_IN_WAKERS[index].wake();
             }
             Direction::Out => {
                 loop {
@@ -592,7 +597,7 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
                     w.set_stat_rx(Stat(r.stat_rx().0 ^ want_stat.0));
                     unsafe { reg.write_value(w) };
                 }
-                EP_OUT_WAKERS[ep_addr.index()].wake();
+                EP_OUT_WAKERS[index].wake();
             }
         }
         trace!("EPR after: {:04x}", unsafe { reg.read() }.0);
@@ -613,7 +618,90 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
     type RemoteWakeupFuture<'a> =  impl Future<Output = Result<(), Unsupported>> + 'a where Self: 'a;
 
     fn remote_wakeup(&mut self) -> Self::RemoteWakeupFuture<'_> {
-        async move { Err(Unsupported) }
+        async move {
+            let regs = T::regs();
+
+            // Leave low power mode, then drive the resume signaling for 1 to 15 ms.
+            unsafe {
+                regs.cntr().modify(|w| {
+                    w.set_fsusp(false);
+                    w.set_lpmode(false);
+                });
+                regs.cntr().modify(|w| w.set_resume(true));
+            }
+            Timer::after(Duration::from_millis(5)).await;
+            unsafe { regs.cntr().modify(|w| w.set_resume(false)) };
+
+            Ok(())
+        }
+    }
+}
+
+fn endpoint_set_stalled<T: Instance>(ep_addr: EndpointAddress, stalled: bool) {
+    // This can race, so do a retry loop.
+    let reg = T::regs().epr(ep_addr.index() as _);
+    match ep_addr.direction() {
+        Direction::In => {
+            let want_stat = match stalled {
+                false => Stat::NAK,
+                true => Stat::STALL,
+            };
+            loop {
+                let r = unsafe { reg.read() };
+                match r.stat_tx() {
+                    Stat::DISABLED => break,      // if disabled, stall does nothing.
+                    s if s == want_stat => break, // done!
+                    _ => {
+                        let mut w = invariant(r);
+                        w.set_stat_tx(Stat(r.stat_tx().0 ^ want_stat.0));
+                        unsafe { reg.write_value(w) };
+                    }
+                }
+            }
+            let r = unsafe { reg.read() };
+            if !stalled && r.dtog_tx() {
+                // Writing 1 toggles the bit back to DATA0.
+                let mut w = invariant(r);
+                w.set_dtog_tx(true);
+                unsafe { reg.write_value(w) };
+            }
+            EP_IN_WAKERS[ep_addr.index()].wake();
+        }
+        Direction::Out => {
+            let want_stat = match stalled {
+                false => Stat::VALID,
+                true => Stat::STALL,
+            };
+            loop {
+                let r = unsafe { reg.read() };
+                match r.stat_rx() {
+                    Stat::DISABLED => break,      // if disabled, stall does nothing.
+                    s if s == want_stat => break, // done!
+                    _ => {
+                        let mut w = invariant(r);
+                        w.set_stat_rx(Stat(r.stat_rx().0 ^ want_stat.0));
+                        unsafe { reg.write_value(w) };
+                    }
+                }
+            }
+            let r = unsafe { reg.read() };
+            if !stalled && r.dtog_rx() {
+                // Writing 1 toggles the bit back to DATA0.
+                let mut w = invariant(r);
+                w.set_dtog_rx(true);
+                unsafe { reg.write_value(w) };
+            }
+            EP_OUT_WAKERS[ep_addr.index()].wake();
+        }
+    }
+}
+
+fn endpoint_is_stalled<T: Instance>(ep_addr: EndpointAddress) -> bool {
+    let regs = T::regs();
+    let epr = unsafe { regs.epr(ep_addr.index() as _).read() };
+    match ep_addr.direction() {
+        Direction::In => epr.stat_tx() == Stat::STALL,
+        Direction::Out => epr.stat_rx() == Stat::STALL,
     }
 }
 
@@ -659,6 +747,47 @@ impl<'d, T: Instance, D> Endpoint<'d, T, D> {
         unsafe { ep_in_len::<T>(index).write_value(buf.len() as _) };
     }
 
+    /// The buffer of an isochronous endpoint the application uses while the peripheral uses
+    /// the other one, with its count register.
+    fn iso_buffer(&self, dtog: bool) -> (EndpointBuffer<T>, Reg<u16, RW>) {
+        let index = self.info.addr.index();
+        // Buffer 0 uses the TX descriptor entries, buffer 1 the RX ones.
+        let (addr, count) = match dtog {
+            true => (ep_in_addr::<T>(index), ep_in_len::<T>(index)),
+            false => (ep_out_addr::<T>(index), ep_out_len::<T>(index)),
+        };
+        let buf = EndpointBuffer {
+            addr: unsafe { addr.read() },
+            len: self.buf.len,
+            _phantom: PhantomData,
+        };
+        (buf, count)
+    }
+
+    async fn wait_iso_done(&mut self, done: &AtomicU8) -> Result<(), EndpointError>
+    where
+        D: Dir,
+    {
+        let index = self.info.addr.index();
+        let mask = 1 << index;
+        poll_fn(|cx| {
+            D::waker(index).register(cx.waker());
+            let epr = unsafe { T::regs().epr(index).read() };
+            let stat = match D::dir() {
+                Direction::In => epr.stat_tx(),
+                Direction::Out => epr.stat_rx(),
+            };
+            if stat == Stat::DISABLED {
+                Poll::Ready(Err(EndpointError::Disabled))
+            } else if done.fetch_and(!mask, Ordering::AcqRel) & mask != 0 {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
     fn read_data(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
         let index = self.info.addr.index();
         let rx_len = unsafe { ep_out_len::<T>(index).read() as usize } & 0x3FF;
@@ -695,6 +824,18 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
             trace!("wait_enabled OUT OK");
         }
     }
+
+    fn set_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, true)
+    }
+
+    fn clear_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, false)
+    }
+
+    fn is_stalled(&self) -> bool {
+        endpoint_is_stalled::<T>(self.info.addr)
+    }
 }
 
 impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
@@ -721,6 +862,18 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
             trace!("wait_enabled OUT OK");
         }
     }
+
+    fn set_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, true)
+    }
+
+    fn clear_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, false)
+    }
+
+    fn is_stalled(&self) -> bool {
+        endpoint_is_stalled::<T>(self.info.addr)
+    }
 }
 
 impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
@@ -728,6 +881,20 @@ impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
 
     fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
         async move {
+            if self.info.ep_type == EndpointType::Isochronous {
+                self.wait_iso_done(&ISO_OUT_DONE).await?;
+
+                // The peripheral toggled DTOG_RX, the packet is in the buffer it no longer uses.
+                let dtog = unsafe { T::regs().epr(self.info.addr.index()).read() }.dtog_rx();
+                let (mut ep_buf, count) = self.iso_buffer(dtog);
+                let rx_len = unsafe { count.read() as usize } & 0x3FF;
+                if rx_len > buf.len() {
+                    return Err(EndpointError::BufferOverflow);
+                }
+                ep_buf.read(&mut buf[..rx_len]);
+                return Ok(rx_len);
+            }
+
             trace!("READ WAITING, buf.len() = {}", buf.len());
             let index = self.info.addr.index();
             let stat = poll_fn(|cx| {
@@ -775,6 +942,18 @@ impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
                 return Err(EndpointError::BufferOverflow);
             }
 
+            if self.info.ep_type == EndpointType::Isochronous {
+                // Wait for the previous packet to be sent, the peripheral then sends this one in
+                // the next frame from the buffer it doesn't use now.
+                self.wait_iso_done(&ISO_IN_DONE).await?;
+
+                let dtog = unsafe { T::regs().epr(self.info.addr.index()).read() }.dtog_tx();
+                let (mut ep_buf, count) = self.iso_buffer(dtog);
+                ep_buf.write(buf);
+                unsafe { count.write_value(buf.len() as _) };
+                return Ok(());
+            }
+
             let index = self.info.addr.index();
 
             trace!("WRITE WAITING");
@@ -1,24 +1,12 @@
-use core::marker::PhantomData;
+use crate::interrupt::Interrupt;
+use crate::rcc::RccPeripheral;
 
-use embassy_hal_common::into_ref;
+#[cfg(feature = "nightly")]
+mod usb;
+#[cfg(feature = "nightly")]
+pub use usb::*;
 
-use crate::gpio::sealed::AFType;
-use crate::rcc::RccPeripheral;
-use crate::{peripherals, Peripheral};
-
-macro_rules! config_ulpi_pins {
-    ($($pin:ident),*) => {
-        into_ref!($($pin),*);
-        // NOTE(unsafe) Exclusive access to the registers
-        critical_section::with(|_| unsafe {
-            $(
-                $pin.set_as_af($pin.af_num(), AFType::OutputPushPull);
-                #[cfg(gpio_v2)]
-                $pin.set_speed(crate::gpio::Speed::VeryHigh);
-            )*
-        })
-    };
-}
+mod regs;
 
 /// USB PHY type
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -35,76 +23,39 @@ pub enum PhyType {
     ExternalHighSpeed,
 }
 
-pub struct UsbOtg<'d, T: Instance> {
-    phantom: PhantomData<&'d mut T>,
-    _phy_type: PhyType,
-}
-
-impl<'d, T: Instance> UsbOtg<'d, T> {
-    /// Initializes USB OTG peripheral with internal Full-Speed PHY
-    pub fn new_fs(
-        _peri: impl Peripheral<P = T> + 'd,
-        dp: impl Peripheral<P = impl DpPin<T>> + 'd,
-        dm: impl Peripheral<P = impl DmPin<T>> + 'd,
-    ) -> Self {
-        into_ref!(dp, dm);
-
-        unsafe {
-            dp.set_as_af(dp.af_num(), AFType::OutputPushPull);
-            dm.set_as_af(dm.af_num(), AFType::OutputPushPull);
-        }
-
-        Self {
-            phantom: PhantomData,
-            _phy_type: PhyType::InternalFullSpeed,
+impl PhyType {
+    /// Whether the PHY is part of the chip.
+    pub fn internal(&self) -> bool {
+        match self {
+            PhyType::InternalFullSpeed | PhyType::InternalHighSpeed => true,
+            PhyType::ExternalHighSpeed => false,
         }
     }
 
-    /// Initializes USB OTG peripheral with external High-Speed PHY
-    pub fn new_hs_ulpi(
-        _peri: impl Peripheral<P = T> + 'd,
-        ulpi_clk: impl Peripheral<P = impl UlpiClkPin<T>> + 'd,
-        ulpi_dir: impl Peripheral<P = impl UlpiDirPin<T>> + 'd,
-        ulpi_nxt: impl Peripheral<P = impl UlpiNxtPin<T>> + 'd,
-        ulpi_stp: impl Peripheral<P = impl UlpiStpPin<T>> + 'd,
-        ulpi_d0: impl Peripheral<P = impl UlpiD0Pin<T>> + 'd,
-        ulpi_d1: impl Peripheral<P = impl UlpiD1Pin<T>> + 'd,
-        ulpi_d2: impl Peripheral<P = impl UlpiD2Pin<T>> + 'd,
-        ulpi_d3: impl Peripheral<P = impl UlpiD3Pin<T>> + 'd,
-        ulpi_d4: impl Peripheral<P = impl UlpiD4Pin<T>> + 'd,
-        ulpi_d5: impl Peripheral<P = impl UlpiD5Pin<T>> + 'd,
-        ulpi_d6: impl Peripheral<P = impl UlpiD6Pin<T>> + 'd,
-        ulpi_d7: impl Peripheral<P = impl UlpiD7Pin<T>> + 'd,
-    ) -> Self {
-        config_ulpi_pins!(
-            ulpi_clk, ulpi_dir, ulpi_nxt, ulpi_stp, ulpi_d0, ulpi_d1, ulpi_d2, ulpi_d3, ulpi_d4, ulpi_d5, ulpi_d6,
-            ulpi_d7
-        );
-
-        Self {
-            phantom: PhantomData,
-            _phy_type: PhyType::ExternalHighSpeed,
+    /// Whether the PHY supports High-Speed.
+    pub fn high_speed(&self) -> bool {
+        match self {
+            PhyType::InternalFullSpeed => false,
+            PhyType::ExternalHighSpeed | PhyType::InternalHighSpeed => true,
         }
     }
 }
 
-impl<'d, T: Instance> Drop for UsbOtg<'d, T> {
-    fn drop(&mut self) {
-        T::reset();
-        T::disable();
-    }
-}
-
 pub(crate) mod sealed {
     pub trait Instance {
-        const REGISTERS: *const ();
         const HIGH_SPEED: bool;
         const FIFO_DEPTH_WORDS: usize;
         const ENDPOINT_COUNT: usize;
+
+        fn regs() -> super::regs::Otg;
+        #[cfg(feature = "nightly")]
+        fn state() -> &'static super::State;
     }
 }
 
-pub trait Instance: sealed::Instance + RccPeripheral {}
+pub trait Instance: sealed::Instance + RccPeripheral + 'static {
+    type Interrupt: Interrupt;
+}
 
 // Internal PHY pins
 pin_trait!(DpPin, Instance);
@@ -124,10 +75,9 @@ pin_trait!(UlpiD5Pin, Instance);
 pin_trait!(UlpiD6Pin, Instance);
 pin_trait!(UlpiD7Pin, Instance);
 
-foreach_peripheral!(
-    (otgfs, $inst:ident) => {
-        impl sealed::Instance for peripherals::$inst {
-            const REGISTERS: *const () = crate::pac::$inst.0 as *const ();
+foreach_interrupt!(
+    ($inst:ident, otgfs, $block:ident, GLOBAL, $irq:ident) => {
+        impl sealed::Instance for crate::peripherals::$inst {
             const HIGH_SPEED: bool = false;
 
             cfg_if::cfg_if! {
@@ -169,14 +119,25 @@ foreach_peripheral!(
                     compile_error!("USB_OTG_FS peripheral is not supported by this chip.");
                 }
             }
+
+            fn regs() -> regs::Otg {
+                regs::Otg(crate::pac::$inst.0 as *mut u8)
+            }
+
+            #[cfg(feature = "nightly")]
+            fn state() -> &'static State {
+                static STATE: State = State::new();
+                &STATE
+            }
         }
 
-        impl Instance for peripherals::$inst {}
+        impl Instance for crate::peripherals::$inst {
+            type Interrupt = crate::interrupt::$irq;
+        }
     };
 
-    (otghs, $inst:ident) => {
-        impl sealed::Instance for peripherals::$inst {
-            const REGISTERS: *const () = crate::pac::$inst.0 as *const ();
+    ($inst:ident, otghs, $block:ident, GLOBAL, $irq:ident) => {
+        impl sealed::Instance for crate::peripherals::$inst {
             const HIGH_SPEED: bool = true;
 
             cfg_if::cfg_if! {
@@ -206,8 +167,20 @@ foreach_peripheral!(
                     compile_error!("USB_OTG_HS peripheral is not supported by this chip.");
                 }
             }
+
+            fn regs() -> regs::Otg {
+                regs::Otg(crate::pac::$inst.0 as *mut u8)
+            }
+
+            #[cfg(feature = "nightly")]
+            fn state() -> &'static State {
+                static STATE: State = State::new();
+                &STATE
+            }
         }
 
-        impl Instance for peripherals::$inst {}
+        impl Instance for crate::peripherals::$inst {
+            type Interrupt = crate::interrupt::$irq;
+        }
     };
 );
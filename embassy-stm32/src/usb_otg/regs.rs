@@ -0,0 +1,418 @@
+//! Registers of the Synopsys USB OTG core, in device mode.
+//!
+//! The PAC doesn't describe them, so they are laid out here like the PAC does, with only the
+//! registers and fields the driver uses.
+#![allow(dead_code)]
+
+use crate::pac::common::{Reg, RW};
+
+trait Field {
+    fn from_bits(bits: u32) -> Self;
+    fn into_bits(self) -> u32;
+}
+
+impl Field for bool {
+    fn from_bits(bits: u32) -> Self {
+        bits != 0
+    }
+    fn into_bits(self) -> u32 {
+        self as u32
+    }
+}
+
+macro_rules! impl_field {
+    ($($ty:ty),*) => {
+        $(
+            impl Field for $ty {
+                fn from_bits(bits: u32) -> Self {
+                    bits as $ty
+                }
+                fn into_bits(self) -> u32 {
+                    self as u32
+                }
+            }
+        )*
+    };
+}
+
+impl_field!(u8, u16, u32);
+
+const fn mask(width: u32) -> u32 {
+    if width == 32 {
+        !0
+    } else {
+        (1 << width) - 1
+    }
+}
+
+macro_rules! register {
+    ($(#[$attr:meta])* $name:ident { $($(#[$field_attr:meta])* $get:ident, $set:ident: $ty:ty = $offset:literal, $width:literal;)* }) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        #[derive(Copy, Clone, Eq, PartialEq, Default)]
+        pub struct $name(pub u32);
+
+        impl $name {
+            $(
+                $(#[$field_attr])*
+                #[inline(always)]
+                pub fn $get(&self) -> $ty {
+                    Field::from_bits((self.0 >> $offset) & mask($width))
+                }
+
+                #[inline(always)]
+                pub fn $set(&mut self, val: $ty) {
+                    self.0 = (self.0 & !(mask($width) << $offset)) | ((val.into_bits() & mask($width)) << $offset);
+                }
+            )*
+        }
+    };
+}
+
+/// USB OTG core.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Otg(pub *mut u8);
+unsafe impl Send for Otg {}
+unsafe impl Sync for Otg {}
+
+impl Otg {
+    fn reg<T: Copy>(self, offset: usize) -> Reg<T, RW> {
+        unsafe { Reg::from_ptr(self.0.add(offset) as _) }
+    }
+
+    /// Control and status register.
+    pub fn gotgctl(self) -> Reg<Gotgctl, RW> {
+        self.reg(0x000)
+    }
+    /// AHB configuration register.
+    pub fn gahbcfg(self) -> Reg<Gahbcfg, RW> {
+        self.reg(0x008)
+    }
+    /// USB configuration register.
+    pub fn gusbcfg(self) -> Reg<Gusbcfg, RW> {
+        self.reg(0x00C)
+    }
+    /// Reset register.
+    pub fn grstctl(self) -> Reg<Grstctl, RW> {
+        self.reg(0x010)
+    }
+    /// Core interrupt register.
+    pub fn gintsts(self) -> Reg<Gintsts, RW> {
+        self.reg(0x014)
+    }
+    /// Interrupt mask register, with the layout of GINTSTS.
+    pub fn gintmsk(self) -> Reg<Gintsts, RW> {
+        self.reg(0x018)
+    }
+    /// Receive status pop register.
+    pub fn grxstsp(self) -> Reg<Grxstsp, RW> {
+        self.reg(0x020)
+    }
+    /// Receive FIFO size register.
+    pub fn grxfsiz(self) -> Reg<Grxfsiz, RW> {
+        self.reg(0x024)
+    }
+    /// Endpoint 0 transmit FIFO size register.
+    pub fn dieptxf0(self) -> Reg<Fsiz, RW> {
+        self.reg(0x028)
+    }
+    /// General core configuration register.
+    pub fn gccfg(self) -> Reg<Gccfg, RW> {
+        self.reg(0x038)
+    }
+    /// Core ID register.
+    pub fn cid(self) -> Reg<u32, RW> {
+        self.reg(0x03C)
+    }
+    /// Transmit FIFO size register of IN endpoint `n + 1`.
+    pub fn dieptxf(self, n: usize) -> Reg<Fsiz, RW> {
+        assert!(n < 15);
+        self.reg(0x104 + n * 4)
+    }
+    /// Device configuration register.
+    pub fn dcfg(self) -> Reg<Dcfg, RW> {
+        self.reg(0x800)
+    }
+    /// Device control register.
+    pub fn dctl(self) -> Reg<Dctl, RW> {
+        self.reg(0x804)
+    }
+    /// Device status register.
+    pub fn dsts(self) -> Reg<Dsts, RW> {
+        self.reg(0x808)
+    }
+    /// Device IN endpoint common interrupt mask register.
+    pub fn diepmsk(self) -> Reg<Depint, RW> {
+        self.reg(0x810)
+    }
+    /// Device all endpoints interrupt register.
+    pub fn daint(self) -> Reg<Daint, RW> {
+        self.reg(0x818)
+    }
+    /// Device all endpoints interrupt mask register.
+    pub fn daintmsk(self) -> Reg<Daint, RW> {
+        self.reg(0x81C)
+    }
+    /// Control register of IN endpoint `n`.
+    pub fn diepctl(self, n: usize) -> Reg<Depctl, RW> {
+        assert!(n < 16);
+        self.reg(0x900 + n * 0x20)
+    }
+    /// Interrupt register of IN endpoint `n`.
+    pub fn diepint(self, n: usize) -> Reg<Depint, RW> {
+        assert!(n < 16);
+        self.reg(0x908 + n * 0x20)
+    }
+    /// Transfer size register of IN endpoint `n`.
+    pub fn dieptsiz(self, n: usize) -> Reg<Deptsiz, RW> {
+        assert!(n < 16);
+        self.reg(0x910 + n * 0x20)
+    }
+    /// Control register of OUT endpoint `n`.
+    pub fn doepctl(self, n: usize) -> Reg<Depctl, RW> {
+        assert!(n < 16);
+        self.reg(0xB00 + n * 0x20)
+    }
+    /// Transfer size register of OUT endpoint `n`.
+    pub fn doeptsiz(self, n: usize) -> Reg<Deptsiz, RW> {
+        assert!(n < 16);
+        self.reg(0xB10 + n * 0x20)
+    }
+    /// Power and clock gating control register.
+    pub fn pcgcctl(self) -> Reg<Pcgcctl, RW> {
+        self.reg(0xE00)
+    }
+    /// Data FIFO of endpoint `n`. Reading any of them pops the receive FIFO.
+    pub fn fifo(self, n: usize) -> Reg<u32, RW> {
+        assert!(n < 16);
+        self.reg(0x1000 + n * 0x1000)
+    }
+}
+
+register!(
+    /// Control and status register.
+    Gotgctl {
+        /// B-peripheral session valid override enable.
+        bvaloen, set_bvaloen: bool = 6, 1;
+        /// B-peripheral session valid override value.
+        bvaloval, set_bvaloval: bool = 7, 1;
+    }
+);
+
+register!(
+    /// AHB configuration register.
+    Gahbcfg {
+        /// Global interrupt mask.
+        gint, set_gint: bool = 0, 1;
+    }
+);
+
+register!(
+    /// USB configuration register.
+    Gusbcfg {
+        /// Full-speed internal PHY select.
+        physel, set_physel: bool = 6, 1;
+        /// USB turnaround time, in PHY clocks.
+        trdt, set_trdt: u8 = 10, 4;
+        /// Force host mode.
+        fhmod, set_fhmod: bool = 29, 1;
+        /// Force device mode.
+        fdmod, set_fdmod: bool = 30, 1;
+    }
+);
+
+register!(
+    /// Reset register.
+    Grstctl {
+        /// Core soft reset.
+        csrst, set_csrst: bool = 0, 1;
+        /// Receive FIFO flush.
+        rxfflsh, set_rxfflsh: bool = 4, 1;
+        /// Transmit FIFO flush.
+        txfflsh, set_txfflsh: bool = 5, 1;
+        /// Transmit FIFO to flush, 0x10 for all of them.
+        txfnum, set_txfnum: u8 = 6, 5;
+        /// AHB master idle.
+        ahbidl, set_ahbidl: bool = 31, 1;
+    }
+);
+
+register!(
+    /// Core interrupt register.
+    Gintsts {
+        /// Receive FIFO non-empty.
+        rxflvl, set_rxflvl: bool = 4, 1;
+        /// Global OUT NAK effective.
+        gonakeff, set_gonakeff: bool = 7, 1;
+        /// USB suspend.
+        usbsusp, set_usbsusp: bool = 11, 1;
+        /// USB reset.
+        usbrst, set_usbrst: bool = 12, 1;
+        /// Enumeration done.
+        enumdne, set_enumdne: bool = 13, 1;
+        /// IN endpoint interrupt.
+        iepint, set_iepint: bool = 18, 1;
+        /// OUT endpoint interrupt.
+        oepint, set_oepint: bool = 19, 1;
+        /// Incomplete isochronous IN transfer.
+        iisoixfr, set_iisoixfr: bool = 20, 1;
+        /// Incomplete isochronous OUT transfer.
+        incompisoout, set_incompisoout: bool = 21, 1;
+        /// Resume or remote wakeup detected.
+        wkupint, set_wkupint: bool = 31, 1;
+    }
+);
+
+register!(
+    /// Receive status pop register.
+    Grxstsp {
+        /// Endpoint number.
+        epnum, set_epnum: u8 = 0, 4;
+        /// Byte count.
+        bcnt, set_bcnt: u16 = 4, 11;
+        /// Packet status.
+        pktstsd, set_pktstsd: u8 = 17, 4;
+    }
+);
+
+register!(
+    /// Receive FIFO size register.
+    Grxfsiz {
+        /// Receive FIFO depth, in words.
+        rxfd, set_rxfd: u16 = 0, 16;
+    }
+);
+
+register!(
+    /// Transmit FIFO size register.
+    Fsiz {
+        /// Start address of the FIFO, in words.
+        sa, set_sa: u16 = 0, 16;
+        /// Depth of the FIFO, in words.
+        fd, set_fd: u16 = 16, 16;
+    }
+);
+
+register!(
+    /// General core configuration register.
+    Gccfg {
+        /// Transceiver enabled.
+        pwrdwn, set_pwrdwn: bool = 16, 1;
+        /// VBUS sensing disabled, on cores older than 0x2000.
+        novbussens, set_novbussens: bool = 21, 1;
+        /// VBUS detection enabled, on cores from 0x2000.
+        vbden, set_vbden: bool = 21, 1;
+    }
+);
+
+register!(
+    /// Device configuration register.
+    Dcfg {
+        /// Device speed.
+        dspd, set_dspd: u8 = 0, 2;
+        /// Device address.
+        dad, set_dad: u8 = 4, 7;
+        /// Periodic frame interval.
+        pfivl, set_pfivl: u8 = 11, 2;
+    }
+);
+
+register!(
+    /// Device control register.
+    Dctl {
+        /// Remote wakeup signaling.
+        rwusig, set_rwusig: bool = 0, 1;
+        /// Soft disconnect.
+        sdis, set_sdis: bool = 1, 1;
+        /// Set global OUT NAK.
+        sgonak, set_sgonak: bool = 9, 1;
+        /// Clear global OUT NAK.
+        cgonak, set_cgonak: bool = 10, 1;
+    }
+);
+
+register!(
+    /// Device status register.
+    Dsts {
+        /// Enumerated speed.
+        enumspd, set_enumspd: u8 = 1, 2;
+        /// Frame number of the received SOF.
+        fnsof, set_fnsof: u16 = 8, 14;
+    }
+);
+
+register!(
+    /// Device all endpoints interrupt register.
+    Daint {
+        /// IN endpoint interrupt bits.
+        iepint, set_iepint: u16 = 0, 16;
+        /// OUT endpoint interrupt bits.
+        oepint, set_oepint: u16 = 16, 16;
+    }
+);
+
+register!(
+    /// Endpoint control register.
+    Depctl {
+        /// Maximum packet size. For endpoint 0, 0 is 64 bytes, 1 is 32, 2 is 16 and 3 is 8.
+        mpsiz, set_mpsiz: u16 = 0, 11;
+        /// USB active endpoint.
+        usbaep, set_usbaep: bool = 15, 1;
+        /// Even or odd frame of isochronous endpoints, data PID of the others.
+        eonum_dpid, set_eonum_dpid: bool = 16, 1;
+        /// NAK status.
+        naksts, set_naksts: bool = 17, 1;
+        /// Endpoint type.
+        eptyp, set_eptyp: u8 = 18, 2;
+        /// STALL handshake.
+        stall, set_stall: bool = 21, 1;
+        /// Transmit FIFO number, of IN endpoints.
+        txfnum, set_txfnum: u8 = 22, 4;
+        /// Clear NAK.
+        cnak, set_cnak: bool = 26, 1;
+        /// Set NAK.
+        snak, set_snak: bool = 27, 1;
+        /// Set DATA0 PID, or even frame of isochronous endpoints.
+        sd0pid_sevnfrm, set_sd0pid_sevnfrm: bool = 28, 1;
+        /// Set odd frame, of isochronous endpoints.
+        soddfrm, set_soddfrm: bool = 29, 1;
+        /// Endpoint disable.
+        epdis, set_epdis: bool = 30, 1;
+        /// Endpoint enable.
+        epena, set_epena: bool = 31, 1;
+    }
+);
+
+register!(
+    /// Endpoint interrupt register.
+    Depint {
+        /// Transfer completed.
+        xfrc, set_xfrc: bool = 0, 1;
+        /// Endpoint disabled.
+        epdisd, set_epdisd: bool = 1, 1;
+        /// IN endpoint NAK effective.
+        inepne, set_inepne: bool = 6, 1;
+    }
+);
+
+register!(
+    /// Endpoint transfer size register.
+    Deptsiz {
+        /// Transfer size.
+        xfrsiz, set_xfrsiz: u32 = 0, 19;
+        /// Packet count.
+        pktcnt, set_pktcnt: u16 = 19, 10;
+        /// Packets per microframe of periodic IN endpoints, SETUP packet count of OUT endpoint 0.
+        mcnt_stupcnt, set_mcnt_stupcnt: u8 = 29, 2;
+    }
+);
+
+register!(
+    /// Power and clock gating control register.
+    Pcgcctl {
+        /// Stop PHY clock.
+        stppclk, set_stppclk: bool = 0, 1;
+        /// Gate HCLK.
+        gatehclk, set_gatehclk: bool = 1, 1;
+    }
+);
@@ -0,0 +1,1393 @@
+use core::cell::UnsafeCell;
+use core::future::{poll_fn, Future};
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use atomic_polyfill::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{Duration, Timer};
+use embassy_usb_driver as driver;
+use embassy_usb_driver::{
+    Direction, EndpointAddress, EndpointAllocError, EndpointError, EndpointIn, EndpointInfo, EndpointOut, EndpointType,
+    Event, Unsupported,
+};
+use pac::common::{Reg, RW};
+
+use super::{regs, *};
+use crate::gpio::sealed::AFType;
+use crate::interrupt::InterruptExt;
+use crate::rcc::sealed::RccPeripheral;
+use crate::time::Hertz;
+use crate::{pac, Peripheral};
+
+macro_rules! config_ulpi_pins {
+    ($($pin:ident),*) => {
+        into_ref!($($pin),*);
+        // NOTE(unsafe) Exclusive access to the registers
+        critical_section::with(|_| unsafe {
+            $(
+                $pin.set_as_af($pin.af_num(), AFType::OutputPushPull);
+                #[cfg(gpio_v2)]
+                $pin.set_speed(crate::gpio::Speed::VeryHigh);
+            )*
+        })
+    };
+}
+
+/// Largest endpoint count of the OTG peripherals.
+const MAX_EP_COUNT: usize = 9;
+
+/// Words of the RX FIFO for the SETUP packets and the status entries, on top of the largest packet.
+const RX_FIFO_EXTRA_SIZE_WORDS: u16 = 30;
+/// Smallest TX FIFO depth, in words.
+const TX_FIFO_MIN_SIZE_WORDS: u16 = 16;
+/// Largest packet of the control endpoint.
+const EP0_MAX_PACKET_SIZE: usize = 64;
+
+const EP_OUT_BUFFER_EMPTY: u16 = u16::MAX;
+
+const IRQ_FLAG_RESET: u8 = 0x01;
+const IRQ_FLAG_SUSPEND: u8 = 0x02;
+const IRQ_FLAG_RESUME: u8 = 0x04;
+const IRQ_FLAG_ENUM_DONE: u8 = 0x08;
+
+// Packet status of the RX FIFO entries.
+const PKTSTS_OUT_DATA_RX: u8 = 0b0010;
+const PKTSTS_OUT_DATA_DONE: u8 = 0b0011;
+const PKTSTS_SETUP_DATA_DONE: u8 = 0b0100;
+const PKTSTS_SETUP_DATA_RX: u8 = 0b0110;
+
+// Device speeds.
+const DSPD_HIGH_SPEED: u8 = 0b00;
+const DSPD_FULL_SPEED_INTERNAL: u8 = 0b11;
+
+/// Endpoint type, as written to DIEPCTL/DOEPCTL.
+fn convert_type(t: EndpointType) -> u8 {
+    t as u8
+}
+
+/// State shared with the interrupt.
+pub struct State {
+    bus_waker: AtomicWaker,
+    irq_flags: AtomicU8,
+    ep0_setup_ready: AtomicBool,
+    ep0_setup_data: UnsafeCell<[u8; 8]>,
+    ep_in_wakers: [AtomicWaker; MAX_EP_COUNT],
+    ep_out_wakers: [AtomicWaker; MAX_EP_COUNT],
+    /// Buffer of each OUT endpoint. The peripheral has a single RX FIFO, that the interrupt
+    /// empties into them.
+    ep_out_buffers: [UnsafeCell<(*mut u8, usize)>; MAX_EP_COUNT],
+    /// Bytes received by each OUT endpoint in the transfer in progress.
+    ep_out_fill: [AtomicU16; MAX_EP_COUNT],
+    /// Length of the transfer waiting in the buffer of each OUT endpoint, or `EP_OUT_BUFFER_EMPTY`.
+    ep_out_size: [AtomicU16; MAX_EP_COUNT],
+}
+
+unsafe impl Send for State {}
+unsafe impl Sync for State {}
+
+impl State {
+    #[allow(clippy::declare_interior_mutable_const)]
+    pub(crate) const fn new() -> Self {
+        const NEW_AW: AtomicWaker = AtomicWaker::new();
+        const NEW_BUF: UnsafeCell<(*mut u8, usize)> = UnsafeCell::new((core::ptr::null_mut(), 0));
+        const NEW_FILL: AtomicU16 = AtomicU16::new(0);
+        const NEW_SIZE: AtomicU16 = AtomicU16::new(EP_OUT_BUFFER_EMPTY);
+
+        Self {
+            bus_waker: NEW_AW,
+            irq_flags: AtomicU8::new(0),
+            ep0_setup_ready: AtomicBool::new(false),
+            ep0_setup_data: UnsafeCell::new([0; 8]),
+            ep_in_wakers: [NEW_AW; MAX_EP_COUNT],
+            ep_out_wakers: [NEW_AW; MAX_EP_COUNT],
+            ep_out_buffers: [NEW_BUF; MAX_EP_COUNT],
+            ep_out_fill: [NEW_FILL; MAX_EP_COUNT],
+            ep_out_size: [NEW_SIZE; MAX_EP_COUNT],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct EndpointData {
+    ep_type: EndpointType,
+    max_packet_size: u16,
+    /// Additional transactions per microframe, of high-bandwidth isochronous endpoints.
+    additional_transactions: u8,
+}
+
+impl EndpointData {
+    /// Largest transfer of the endpoint, in a (micro)frame.
+    fn transfer_size(&self) -> usize {
+        self.max_packet_size as usize * (1 + self.additional_transactions as usize)
+    }
+
+    fn fifo_size_words(&self) -> u16 {
+        ((self.transfer_size() + 3) / 4) as u16
+    }
+}
+
+pub struct Driver<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+    ep_in: [Option<EndpointData>; MAX_EP_COUNT],
+    ep_out: [Option<EndpointData>; MAX_EP_COUNT],
+    ep_out_buffer: &'d mut [u8],
+    ep_out_buffer_offset: usize,
+    phy_type: PhyType,
+}
+
+impl<'d, T: Instance> Driver<'d, T> {
+    /// Initializes the USB OTG peripheral with the internal Full-Speed PHY.
+    ///
+    /// The packets received by the OUT endpoints are kept in `ep_out_buffer` until they're read.
+    /// It must fit the largest transfer of each OUT endpoint, plus 64 bytes for the control
+    /// endpoint.
+    pub fn new_fs(
+        _peri: impl Peripheral<P = T> + 'd,
+        irq: impl Peripheral<P = T::Interrupt> + 'd,
+        dp: impl Peripheral<P = impl DpPin<T>> + 'd,
+        dm: impl Peripheral<P = impl DmPin<T>> + 'd,
+        ep_out_buffer: &'d mut [u8],
+    ) -> Self {
+        into_ref!(irq, dp, dm);
+
+        unsafe {
+            dp.set_as_af(dp.af_num(), AFType::OutputPushPull);
+            dm.set_as_af(dm.af_num(), AFType::OutputPushPull);
+        }
+
+        Self::new(irq, ep_out_buffer, PhyType::InternalFullSpeed)
+    }
+
+    /// Initializes the USB OTG peripheral with an external High-Speed PHY, over ULPI.
+    ///
+    /// See [`new_fs`](Self::new_fs) for `ep_out_buffer`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_hs_ulpi(
+        _peri: impl Peripheral<P = T> + 'd,
+        irq: impl Peripheral<P = T::Interrupt> + 'd,
+        ulpi_clk: impl Peripheral<P = impl UlpiClkPin<T>> + 'd,
+        ulpi_dir: impl Peripheral<P = impl UlpiDirPin<T>> + 'd,
+        ulpi_nxt: impl Peripheral<P = impl UlpiNxtPin<T>> + 'd,
+        ulpi_stp: impl Peripheral<P = impl UlpiStpPin<T>> + 'd,
+        ulpi_d0: impl Peripheral<P = impl UlpiD0Pin<T>> + 'd,
+        ulpi_d1: impl Peripheral<P = impl UlpiD1Pin<T>> + 'd,
+        ulpi_d2: impl Peripheral<P = impl UlpiD2Pin<T>> + 'd,
+        ulpi_d3: impl Peripheral<P = impl UlpiD3Pin<T>> + 'd,
+        ulpi_d4: impl Peripheral<P = impl UlpiD4Pin<T>> + 'd,
+        ulpi_d5: impl Peripheral<P = impl UlpiD5Pin<T>> + 'd,
+        ulpi_d6: impl Peripheral<P = impl UlpiD6Pin<T>> + 'd,
+        ulpi_d7: impl Peripheral<P = impl UlpiD7Pin<T>> + 'd,
+        ep_out_buffer: &'d mut [u8],
+    ) -> Self {
+        assert!(T::HIGH_SPEED, "Peripheral is not capable of high-speed USB");
+
+        into_ref!(irq);
+        config_ulpi_pins!(
+            ulpi_clk, ulpi_dir, ulpi_nxt, ulpi_stp, ulpi_d0, ulpi_d1, ulpi_d2, ulpi_d3, ulpi_d4, ulpi_d5, ulpi_d6,
+            ulpi_d7
+        );
+
+        Self::new(irq, ep_out_buffer, PhyType::ExternalHighSpeed)
+    }
+
+    fn new(irq: PeripheralRef<'d, T::Interrupt>, ep_out_buffer: &'d mut [u8], phy_type: PhyType) -> Self {
+        irq.set_handler(Self::on_interrupt);
+        irq.unpend();
+        irq.enable();
+
+        Self {
+            phantom: PhantomData,
+            ep_in: [None; MAX_EP_COUNT],
+            ep_out: [None; MAX_EP_COUNT],
+            ep_out_buffer,
+            ep_out_buffer_offset: 0,
+            phy_type,
+        }
+    }
+
+    fn on_interrupt(_: *mut ()) {
+        unsafe {
+            let r = T::regs();
+            let state = T::state();
+
+            let ints = r.gintsts().read();
+
+            let mut flags: u8 = 0;
+            if ints.usbrst() {
+                flags |= IRQ_FLAG_RESET;
+            }
+            if ints.enumdne() {
+                flags |= IRQ_FLAG_ENUM_DONE;
+            }
+            if ints.usbsusp() {
+                flags |= IRQ_FLAG_SUSPEND;
+            }
+            if ints.wkupint() {
+                flags |= IRQ_FLAG_RESUME;
+            }
+
+            if flags != 0 {
+                // Write 1 to clear.
+                let mut clear = regs::Gintsts(0);
+                clear.set_usbrst(ints.usbrst());
+                clear.set_enumdne(ints.enumdne());
+                clear.set_usbsusp(ints.usbsusp());
+                clear.set_wkupint(ints.wkupint());
+                r.gintsts().write_value(clear);
+
+                // Send irqs to main thread.
+                state.irq_flags.fetch_or(flags, Ordering::AcqRel);
+                state.bus_waker.wake();
+            }
+
+            // Packets received by the OUT endpoints, and the completion of their transfers.
+            while r.gintsts().read().rxflvl() {
+                let status = r.grxstsp().read();
+                let index = status.epnum() as usize;
+                let len = status.bcnt() as usize;
+
+                match status.pktstsd() {
+                    PKTSTS_SETUP_DATA_RX => {
+                        let data = &mut *state.ep0_setup_data.get();
+                        if len == data.len() {
+                            read_fifo::<T>(data);
+                        } else {
+                            discard_fifo::<T>(len);
+                        }
+                    }
+                    PKTSTS_SETUP_DATA_DONE => {
+                        state.ep0_setup_ready.store(true, Ordering::Release);
+                        state.ep_out_wakers[0].wake();
+                    }
+                    PKTSTS_OUT_DATA_RX => {
+                        let (ptr, cap) = *state.ep_out_buffers[index].get();
+                        let fill = state.ep_out_fill[index].load(Ordering::Relaxed) as usize;
+                        if fill + len <= cap {
+                            read_fifo::<T>(core::slice::from_raw_parts_mut(ptr.add(fill), len));
+                            state.ep_out_fill[index].store((fill + len) as u16, Ordering::Relaxed);
+                        } else {
+                            trace!("OUT packet of endpoint {} doesn't fit its buffer", index);
+                            discard_fifo::<T>(len);
+                        }
+                    }
+                    PKTSTS_OUT_DATA_DONE => {
+                        let fill = state.ep_out_fill[index].load(Ordering::Relaxed);
+                        state.ep_out_size[index].store(fill, Ordering::Release);
+                        state.ep_out_wakers[index].wake();
+                    }
+                    _ => {}
+                }
+            }
+
+            if ints.iepint() {
+                let mut ep_mask = r.daint().read().iepint();
+                let mut index = 0;
+                while ep_mask != 0 {
+                    if ep_mask & 1 != 0 {
+                        // Write 1 to clear. Only the transfer completion is unmasked.
+                        let mut clear = regs::Depint(0);
+                        clear.set_xfrc(true);
+                        r.diepint(index).write_value(clear);
+                        state.ep_in_wakers[index].wake();
+                    }
+                    ep_mask >>= 1;
+                    index += 1;
+                }
+            }
+
+            // An isochronous endpoint wasn't polled in the (micro)frame its transfer was for, so
+            // the transfer waits for the next one.
+            if ints.iisoixfr() || ints.incompisoout() {
+                let odd = next_frame_odd::<T>();
+                for index in 1..T::ENDPOINT_COUNT {
+                    if ints.iisoixfr() {
+                        retarget_iso::<T>(r.diepctl(index), odd);
+                    }
+                    if ints.incompisoout() {
+                        retarget_iso::<T>(r.doepctl(index), odd);
+                    }
+                }
+
+                // Write 1 to clear.
+                let mut clear = regs::Gintsts(0);
+                clear.set_iisoixfr(ints.iisoixfr());
+                clear.set_incompisoout(ints.incompisoout());
+                r.gintsts().write_value(clear);
+            }
+        }
+    }
+
+    /// Words of the peripheral FIFO RAM used by the allocated endpoints, counting the control
+    /// endpoint even before it's allocated.
+    fn fifo_words_used(&self) -> usize {
+        let ep0 = EndpointData {
+            ep_type: EndpointType::Control,
+            max_packet_size: EP0_MAX_PACKET_SIZE as u16,
+            additional_transactions: 0,
+        };
+
+        let rx = self
+            .ep_out
+            .iter()
+            .flatten()
+            .map(|ep| ep.fifo_size_words())
+            .max()
+            .unwrap_or(0)
+            .max(ep0.fifo_size_words())
+            + RX_FIFO_EXTRA_SIZE_WORDS;
+        let tx: u16 = self
+            .ep_in
+            .iter()
+            .enumerate()
+            .filter_map(|(i, ep)| if i == 0 { Some(ep.unwrap_or(ep0)) } else { *ep })
+            .map(|ep| ep.fifo_size_words().max(TX_FIFO_MIN_SIZE_WORDS))
+            .sum();
+
+        rx as usize + tx as usize
+    }
+
+    fn alloc_endpoint<D: Dir>(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+        additional_transactions: u8,
+    ) -> Result<Endpoint<'d, T, D>, EndpointAllocError> {
+        trace!(
+            "allocating type={:?} mps={:?} interval={} additional_transactions={}, dir={:?}",
+            ep_type,
+            max_packet_size,
+            interval,
+            additional_transactions,
+            D::dir()
+        );
+
+        // Only high-speed devices have microframes with several transactions.
+        if additional_transactions > 2 || (additional_transactions != 0 && !self.phy_type.high_speed()) {
+            return Err(EndpointAllocError);
+        }
+
+        let data = EndpointData {
+            ep_type,
+            max_packet_size,
+            additional_transactions,
+        };
+
+        let eps = match D::dir() {
+            Direction::Out => &mut self.ep_out,
+            Direction::In => &mut self.ep_in,
+        };
+        let index = eps[..T::ENDPOINT_COUNT].iter().enumerate().position(|(i, ep)| {
+            // Endpoint 0 is reserved for the control pipe.
+            ep.is_none() && (i == 0) == (ep_type == EndpointType::Control)
+        });
+        let index = match index {
+            Some(index) => index,
+            None => return Err(EndpointAllocError),
+        };
+        eps[index] = Some(data);
+
+        if self.fifo_words_used() > T::FIFO_DEPTH_WORDS {
+            trace!("  FIFO RAM full");
+            match D::dir() {
+                Direction::Out => self.ep_out[index] = None,
+                Direction::In => self.ep_in[index] = None,
+            }
+            return Err(EndpointAllocError);
+        }
+
+        if D::dir() == Direction::Out {
+            // Keep room for the control endpoint, allocated last.
+            let reserved = if index == 0 { 0 } else { EP0_MAX_PACKET_SIZE };
+            let len = data.transfer_size();
+            if self.ep_out_buffer_offset + len + reserved > self.ep_out_buffer.len() {
+                trace!("  ep_out_buffer full");
+                self.ep_out[index] = None;
+                return Err(EndpointAllocError);
+            }
+
+            let buf = &mut self.ep_out_buffer[self.ep_out_buffer_offset..][..len];
+            self.ep_out_buffer_offset += len;
+            // The endpoint isn't enabled yet, so the interrupt doesn't use its buffer.
+            unsafe { *T::state().ep_out_buffers[index].get() = (buf.as_mut_ptr(), len) };
+        }
+
+        trace!("  index={}", index);
+
+        Ok(Endpoint {
+            _phantom: PhantomData,
+            info: EndpointInfo {
+                addr: EndpointAddress::from_parts(index, D::dir()),
+                ep_type,
+                max_packet_size,
+                interval,
+            },
+            data,
+        })
+    }
+}
+
+impl<'d, T: Instance> driver::Driver<'d> for Driver<'d, T> {
+    type EndpointOut = Endpoint<'d, T, Out>;
+    type EndpointIn = Endpoint<'d, T, In>;
+    type ControlPipe = ControlPipe<'d, T>;
+    type Bus = Bus<'d, T>;
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        self.alloc_endpoint(ep_type, max_packet_size, interval, 0)
+    }
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        self.alloc_endpoint(ep_type, max_packet_size, interval, 0)
+    }
+
+    fn alloc_endpoint_out_isochronous(
+        &mut self,
+        max_packet_size: u16,
+        interval: u8,
+        additional_transactions: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        self.alloc_endpoint(
+            EndpointType::Isochronous,
+            max_packet_size,
+            interval,
+            additional_transactions,
+        )
+    }
+
+    fn alloc_endpoint_in_isochronous(
+        &mut self,
+        max_packet_size: u16,
+        interval: u8,
+        additional_transactions: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        self.alloc_endpoint(
+            EndpointType::Isochronous,
+            max_packet_size,
+            interval,
+            additional_transactions,
+        )
+    }
+
+    fn start(mut self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        assert!(matches!(control_max_packet_size, 8 | 16 | 32 | 64));
+
+        let ep_out = self
+            .alloc_endpoint(EndpointType::Control, control_max_packet_size, 0, 0)
+            .unwrap();
+        let ep_in = self
+            .alloc_endpoint(EndpointType::Control, control_max_packet_size, 0, 0)
+            .unwrap();
+        assert_eq!(ep_out.info.addr.index(), 0);
+        assert_eq!(ep_in.info.addr.index(), 0);
+
+        trace!("start");
+
+        (
+            Bus {
+                phantom: PhantomData,
+                ep_in: self.ep_in,
+                ep_out: self.ep_out,
+                phy_type: self.phy_type,
+                inited: false,
+            },
+            ControlPipe {
+                _phantom: PhantomData,
+                max_packet_size: control_max_packet_size,
+                ep_in,
+                ep_out,
+            },
+        )
+    }
+}
+
+pub struct Bus<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+    ep_in: [Option<EndpointData>; MAX_EP_COUNT],
+    ep_out: [Option<EndpointData>; MAX_EP_COUNT],
+    phy_type: PhyType,
+    inited: bool,
+}
+
+impl<'d, T: Instance> Bus<'d, T> {
+    async fn init(&mut self) {
+        let r = T::regs();
+
+        <T as RccPeripheral>::enable();
+        <T as RccPeripheral>::reset();
+
+        #[cfg(stm32f7)]
+        critical_section::with(|_| unsafe {
+            pac::RCC.ahb1enr().modify(|w| {
+                if T::HIGH_SPEED {
+                    w.set_usb_otg_hsulpien(!self.phy_type.internal());
+                }
+            })
+        });
+
+        #[cfg(stm32h7)]
+        critical_section::with(|_| unsafe {
+            pac::RCC.ahb1enr().modify(|w| {
+                if T::HIGH_SPEED {
+                    w.set_usb_otg_hs_ulpien(!self.phy_type.internal());
+                }
+            })
+        });
+
+        let speed = match self.phy_type {
+            PhyType::InternalFullSpeed => DSPD_FULL_SPEED_INTERNAL,
+            PhyType::ExternalHighSpeed => DSPD_HIGH_SPEED,
+            PhyType::InternalHighSpeed => panic!("The internal High-Speed PHY is not supported"),
+        };
+
+        unsafe {
+            let core_id = r.cid().read();
+            trace!("core id {:08x}", core_id);
+
+            // Wait for the AHB master to be idle, then select the PHY and reset the core.
+            while !r.grstctl().read().ahbidl() {}
+            r.gusbcfg().write(|w| w.set_physel(self.phy_type.internal()));
+            r.grstctl().write(|w| w.set_csrst(true));
+            while r.grstctl().read().csrst() {}
+
+            // Enable the internal transceiver, and sense no VBUS: the device is always powered.
+            if core_id < 0x0000_2000 {
+                r.gccfg().write(|w| {
+                    w.set_pwrdwn(self.phy_type.internal());
+                    w.set_novbussens(true);
+                });
+            } else {
+                r.gccfg().write(|w| {
+                    w.set_pwrdwn(self.phy_type.internal());
+                    w.set_vbden(false);
+                });
+                r.gotgctl().modify(|w| {
+                    w.set_bvaloen(true);
+                    w.set_bvaloval(true);
+                });
+            }
+
+            r.gusbcfg().modify(|w| {
+                w.set_fdmod(true);
+                w.set_trdt(calculate_trdt(speed == DSPD_HIGH_SPEED, T::frequency()));
+            });
+        }
+
+        // The core takes up to 25 ms to switch to device mode.
+        Timer::after(Duration::from_millis(25)).await;
+
+        unsafe {
+            // Stay disconnected until enabled.
+            r.dctl().write(|w| w.set_sdis(true));
+            r.pcgcctl().write(|_| {});
+            r.dcfg().write(|w| w.set_dspd(speed));
+
+            self.init_fifo();
+
+            r.diepmsk().write(|w| w.set_xfrc(true));
+            r.daintmsk().write(|w| w.set_iepint(0xFFFF));
+
+            // Clear the pending interrupts, then unmask them.
+            r.gintsts().write_value(regs::Gintsts(!0));
+            r.gintmsk().write(|w| {
+                w.set_usbrst(true);
+                w.set_enumdne(true);
+                w.set_usbsusp(true);
+                w.set_wkupint(true);
+                w.set_rxflvl(true);
+                w.set_iepint(true);
+                w.set_iisoixfr(true);
+                w.set_incompisoout(true);
+            });
+            r.gahbcfg().write(|w| w.set_gint(true));
+        }
+    }
+
+    /// Lays out the FIFO RAM: the RX FIFO first, then a TX FIFO for each IN endpoint.
+    fn init_fifo(&mut self) {
+        let r = T::regs();
+
+        let rx_fifo_size_words = RX_FIFO_EXTRA_SIZE_WORDS
+            + self
+                .ep_out
+                .iter()
+                .flatten()
+                .map(|ep| ep.fifo_size_words())
+                .max()
+                .unwrap_or(0);
+        trace!("RX FIFO: {} words", rx_fifo_size_words);
+
+        unsafe {
+            r.grxfsiz().write(|w| w.set_rxfd(rx_fifo_size_words));
+
+            let mut fifo_top = rx_fifo_size_words;
+            for (index, ep) in self.ep_in.iter().enumerate() {
+                if let Some(ep) = ep {
+                    let size = ep.fifo_size_words().max(TX_FIFO_MIN_SIZE_WORDS);
+                    trace!("TX FIFO {}: {} words at {}", index, size, fifo_top);
+
+                    let mut fsiz = regs::Fsiz(0);
+                    fsiz.set_sa(fifo_top);
+                    fsiz.set_fd(size);
+                    match index {
+                        0 => r.dieptxf0().write_value(fsiz),
+                        _ => r.dieptxf(index - 1).write_value(fsiz),
+                    }
+
+                    fifo_top += size;
+                }
+            }
+            assert!(fifo_top as usize <= T::FIFO_DEPTH_WORDS);
+        }
+
+        flush_rx_fifo::<T>();
+        flush_tx_fifo::<T>(0x10);
+    }
+
+    /// Aborts the transfers in progress, after a USB reset.
+    fn reset(&mut self) {
+        let r = T::regs();
+        let state = T::state();
+
+        unsafe {
+            r.dcfg().modify(|w| w.set_dad(0));
+
+            // The core deactivates the endpoints, NAK them until they're enabled again.
+            critical_section::with(|_| {
+                for index in 0..T::ENDPOINT_COUNT {
+                    let epena = r.diepctl(index).read().epena();
+                    modify_ep_ctl(r.diepctl(index), |w| {
+                        w.set_snak(true);
+                        w.set_epdis(epena);
+                    });
+                    modify_ep_ctl(r.doepctl(index), |w| w.set_snak(true));
+                }
+            });
+        }
+
+        flush_rx_fifo::<T>();
+        flush_tx_fifo::<T>(0x10);
+
+        state.ep0_setup_ready.store(false, Ordering::Release);
+        for index in 0..MAX_EP_COUNT {
+            state.ep_out_fill[index].store(0, Ordering::Relaxed);
+            state.ep_out_size[index].store(EP_OUT_BUFFER_EMPTY, Ordering::Release);
+            state.ep_in_wakers[index].wake();
+            state.ep_out_wakers[index].wake();
+        }
+    }
+
+    /// Sets up the control endpoint, once the speed of the bus is known.
+    fn enum_done(&mut self) {
+        let r = T::regs();
+        let ep0 = self.ep_out[0].unwrap();
+
+        unsafe {
+            let high_speed = r.dsts().read().enumspd() == DSPD_HIGH_SPEED;
+            trace!("enumeration done, high speed: {}", high_speed);
+
+            r.gusbcfg()
+                .modify(|w| w.set_trdt(calculate_trdt(high_speed, T::frequency())));
+
+            // Coded as 0 for 64 bytes, 1 for 32, 2 for 16 and 3 for 8.
+            let mpsiz = match ep0.max_packet_size {
+                8 => 0b11,
+                16 => 0b10,
+                32 => 0b01,
+                _ => 0b00,
+            };
+            critical_section::with(|_| {
+                modify_ep_ctl(r.diepctl(0), |w| {
+                    w.set_mpsiz(mpsiz);
+                    w.set_txfnum(0);
+                })
+            });
+        }
+
+        arm_ep_out::<T>(0, &ep0);
+    }
+}
+
+impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
+    type PollFuture<'a> = impl Future<Output = Event> + 'a where Self: 'a;
+
+    fn poll<'a>(&'a mut self) -> Self::PollFuture<'a> {
+        async move {
+            if !self.inited {
+                self.init().await;
+                self.inited = true;
+                return Event::PowerDetected;
+            }
+
+            poll_fn(move |cx| {
+                let state = T::state();
+                state.bus_waker.register(cx.waker());
+
+                let flags = state.irq_flags.load(Ordering::Acquire);
+
+                if flags & IRQ_FLAG_RESUME != 0 {
+                    state.irq_flags.fetch_and(!IRQ_FLAG_RESUME, Ordering::AcqRel);
+                    return Poll::Ready(Event::Resume);
+                }
+
+                if flags & IRQ_FLAG_RESET != 0 {
+                    state.irq_flags.fetch_and(!IRQ_FLAG_RESET, Ordering::AcqRel);
+                    self.reset();
+                    return Poll::Ready(Event::Reset);
+                }
+
+                if flags & IRQ_FLAG_ENUM_DONE != 0 {
+                    state.irq_flags.fetch_and(!IRQ_FLAG_ENUM_DONE, Ordering::AcqRel);
+                    self.enum_done();
+                }
+
+                if flags & IRQ_FLAG_SUSPEND != 0 {
+                    state.irq_flags.fetch_and(!IRQ_FLAG_SUSPEND, Ordering::AcqRel);
+                    return Poll::Ready(Event::Suspend);
+                }
+
+                Poll::Pending
+            })
+            .await
+        }
+    }
+
+    #[inline]
+    fn set_address(&mut self, addr: u8) {
+        trace!("setting addr: {}", addr);
+        unsafe { T::regs().dcfg().modify(|w| w.set_dad(addr)) }
+    }
+
+    fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
+        endpoint_set_stalled::<T>(ep_addr, stalled)
+    }
+
+    fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
+        endpoint_is_stalled::<T>(ep_addr)
+    }
+
+    fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
+        trace!("set_enabled {:x} {}", ep_addr, enabled);
+
+        let r = T::regs();
+        let state = T::state();
+        let index = ep_addr.index();
+
+        match ep_addr.direction() {
+            Direction::In => {
+                let ep = unwrap!(self.ep_in[index]);
+                if enabled {
+                    critical_section::with(|_| unsafe {
+                        modify_ep_ctl(r.diepctl(index), |w| {
+                            w.set_usbaep(true);
+                            w.set_mpsiz(ep.max_packet_size);
+                            w.set_eptyp(convert_type(ep.ep_type));
+                            w.set_txfnum(index as u8);
+                            w.set_snak(true);
+                            if ep.ep_type != EndpointType::Isochronous {
+                                // DATA0
+                                w.set_sd0pid_sevnfrm(true);
+                            }
+                        })
+                    });
+                } else {
+                    ep_in_disable::<T>(index);
+                }
+                state.ep_in_wakers[index].wake();
+            }
+            Direction::Out => {
+                let ep = unwrap!(self.ep_out[index]);
+                if enabled {
+                    critical_section::with(|_| unsafe {
+                        modify_ep_ctl(r.doepctl(index), |w| {
+                            w.set_usbaep(true);
+                            w.set_mpsiz(ep.max_packet_size);
+                            w.set_eptyp(convert_type(ep.ep_type));
+                            if ep.ep_type != EndpointType::Isochronous {
+                                // DATA0
+                                w.set_sd0pid_sevnfrm(true);
+                            }
+                        })
+                    });
+                    arm_ep_out::<T>(index, &ep);
+                } else {
+                    ep_out_disable::<T>(index);
+                }
+                state.ep_out_wakers[index].wake();
+            }
+        }
+    }
+
+    type EnableFuture<'a> = impl Future<Output = ()> + 'a where Self: 'a;
+
+    fn enable(&mut self) -> Self::EnableFuture<'_> {
+        async move {
+            // Connect to the bus.
+            unsafe { T::regs().dctl().modify(|w| w.set_sdis(false)) };
+        }
+    }
+
+    type DisableFuture<'a> = impl Future<Output = ()> + 'a where Self: 'a;
+
+    fn disable(&mut self) -> Self::DisableFuture<'_> {
+        async move {
+            unsafe { T::regs().dctl().modify(|w| w.set_sdis(true)) };
+        }
+    }
+
+    type RemoteWakeupFuture<'a> =  impl Future<Output = Result<(), Unsupported>> + 'a where Self: 'a;
+
+    fn remote_wakeup(&mut self) -> Self::RemoteWakeupFuture<'_> {
+        async move {
+            let r = T::regs();
+
+            // Restart the PHY clock, then drive the resume signaling for 1 to 15 ms.
+            unsafe {
+                r.pcgcctl().write(|_| {});
+                r.dctl().modify(|w| w.set_rwusig(true));
+            }
+            Timer::after(Duration::from_millis(5)).await;
+            unsafe { r.dctl().modify(|w| w.set_rwusig(false)) };
+
+            Ok(())
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for Bus<'d, T> {
+    fn drop(&mut self) {
+        <T as RccPeripheral>::reset();
+        <T as RccPeripheral>::disable();
+    }
+}
+
+/// Modifies an endpoint control register, without enabling or disabling the endpoint unless
+/// `f` asks to. EPENA and EPDIS are cleared by the core, and writing 0 to them does nothing, so
+/// writing back the value read could restart a transfer that just completed.
+unsafe fn modify_ep_ctl(reg: Reg<regs::Depctl, RW>, f: impl FnOnce(&mut regs::Depctl)) {
+    let mut w = reg.read();
+    w.set_epena(false);
+    w.set_epdis(false);
+    f(&mut w);
+    reg.write_value(w);
+}
+
+/// Whether the next (micro)frame has an odd number.
+fn next_frame_odd<T: Instance>() -> bool {
+    unsafe { T::regs().dsts().read() }.fnsof() & 1 == 0
+}
+
+/// Targets the transfer of an isochronous endpoint to the (micro)frame after the current one.
+fn set_frame(w: &mut regs::Depctl, odd: bool) {
+    match odd {
+        true => w.set_soddfrm(true),
+        false => w.set_sd0pid_sevnfrm(true),
+    }
+}
+
+/// Moves the transfer of an isochronous endpoint to the next (micro)frame, if it's still pending.
+unsafe fn retarget_iso<T: Instance>(reg: Reg<regs::Depctl, RW>, odd: bool) {
+    let ctl = reg.read();
+    if ctl.epena() && ctl.eptyp() == convert_type(EndpointType::Isochronous) {
+        modify_ep_ctl(reg, |w| set_frame(w, odd));
+    }
+}
+
+/// Prepares OUT endpoint `index` to receive a transfer into its buffer, dropping the one in it.
+fn arm_ep_out<T: Instance>(index: usize, ep: &EndpointData) {
+    let r = T::regs();
+    let state = T::state();
+
+    state.ep_out_fill[index].store(0, Ordering::Relaxed);
+    state.ep_out_size[index].store(EP_OUT_BUFFER_EMPTY, Ordering::Release);
+
+    let packets = 1 + ep.additional_transactions as u16;
+    critical_section::with(|_| unsafe {
+        // The transfer size can't change while the endpoint is enabled, which it stays on
+        // endpoint 0 when a SETUP packet interrupts the data stage.
+        if !r.doepctl(index).read().epena() {
+            r.doeptsiz(index).write(|w| {
+                w.set_xfrsiz(ep.transfer_size() as u32);
+                w.set_pktcnt(packets);
+                if index == 0 {
+                    w.set_mcnt_stupcnt(3);
+                }
+            });
+        }
+
+        modify_ep_ctl(r.doepctl(index), |w| {
+            w.set_epena(true);
+            w.set_cnak(true);
+            if ep.ep_type == EndpointType::Isochronous {
+                set_frame(w, next_frame_odd::<T>());
+            }
+        });
+    });
+}
+
+fn ep_in_disable<T: Instance>(index: usize) {
+    let r = T::regs();
+
+    unsafe {
+        if r.diepctl(index).read().epena() {
+            // Stop answering the host, then abort the transfer.
+            critical_section::with(|_| modify_ep_ctl(r.diepctl(index), |w| w.set_snak(true)));
+            while !r.diepint(index).read().inepne() {}
+            critical_section::with(|_| modify_ep_ctl(r.diepctl(index), |w| w.set_epdis(true)));
+            while r.diepctl(index).read().epena() {}
+
+            let mut clear = regs::Depint(0);
+            clear.set_inepne(true);
+            clear.set_epdisd(true);
+            r.diepint(index).write_value(clear);
+        }
+
+        flush_tx_fifo::<T>(index as u8);
+        critical_section::with(|_| modify_ep_ctl(r.diepctl(index), |w| w.set_usbaep(false)));
+    }
+}
+
+fn ep_out_disable<T: Instance>(index: usize) {
+    let r = T::regs();
+    let state = T::state();
+
+    unsafe {
+        if r.doepctl(index).read().epena() {
+            // OUT endpoints can only be disabled while the core NAKs all of them.
+            r.dctl().modify(|w| w.set_sgonak(true));
+            while !r.gintsts().read().gonakeff() {}
+            critical_section::with(|_| {
+                modify_ep_ctl(r.doepctl(index), |w| {
+                    w.set_snak(true);
+                    w.set_epdis(true);
+                })
+            });
+            while r.doepctl(index).read().epena() {}
+            r.dctl().modify(|w| w.set_cgonak(true));
+        }
+
+        critical_section::with(|_| modify_ep_ctl(r.doepctl(index), |w| w.set_usbaep(false)));
+    }
+
+    state.ep_out_fill[index].store(0, Ordering::Relaxed);
+    state.ep_out_size[index].store(EP_OUT_BUFFER_EMPTY, Ordering::Release);
+}
+
+fn endpoint_set_stalled<T: Instance>(ep_addr: EndpointAddress, stalled: bool) {
+    let r = T::regs();
+    let state = T::state();
+    let index = ep_addr.index();
+
+    let reg = match ep_addr.direction() {
+        Direction::In => r.diepctl(index),
+        Direction::Out => r.doepctl(index),
+    };
+    critical_section::with(|_| unsafe {
+        modify_ep_ctl(reg, |w| {
+            w.set_stall(stalled);
+            if !stalled && index != 0 {
+                // DATA0
+                w.set_sd0pid_sevnfrm(true);
+            }
+        })
+    });
+
+    match ep_addr.direction() {
+        Direction::In => state.ep_in_wakers[index].wake(),
+        Direction::Out => state.ep_out_wakers[index].wake(),
+    }
+}
+
+fn endpoint_is_stalled<T: Instance>(ep_addr: EndpointAddress) -> bool {
+    let r = T::regs();
+    let ctl = unsafe {
+        match ep_addr.direction() {
+            Direction::In => r.diepctl(ep_addr.index()).read(),
+            Direction::Out => r.doepctl(ep_addr.index()).read(),
+        }
+    };
+    ctl.stall()
+}
+
+/// Pops `buf.len()` bytes from the RX FIFO, in whole words.
+fn read_fifo<T: Instance>(buf: &mut [u8]) {
+    let fifo = T::regs().fifo(0);
+    for chunk in buf.chunks_mut(4) {
+        let data = unsafe { fifo.read() }.to_le_bytes();
+        chunk.copy_from_slice(&data[..chunk.len()]);
+    }
+}
+
+/// Pops `len` bytes from the RX FIFO, in whole words, and drops them.
+fn discard_fifo<T: Instance>(len: usize) {
+    let fifo = T::regs().fifo(0);
+    for _ in 0..(len + 3) / 4 {
+        unsafe { fifo.read() };
+    }
+}
+
+/// Pushes `buf` to the TX FIFO of IN endpoint `index`, in whole words.
+fn write_fifo<T: Instance>(index: usize, buf: &[u8]) {
+    let fifo = T::regs().fifo(index);
+    for chunk in buf.chunks(4) {
+        let mut data = [0; 4];
+        data[..chunk.len()].copy_from_slice(chunk);
+        unsafe { fifo.write_value(u32::from_le_bytes(data)) };
+    }
+}
+
+fn flush_rx_fifo<T: Instance>() {
+    let r = T::regs();
+    unsafe {
+        r.grstctl().write(|w| w.set_rxfflsh(true));
+        while r.grstctl().read().rxfflsh() {}
+    }
+}
+
+/// Flushes the TX FIFO of IN endpoint `index`, or all of them for 0x10.
+fn flush_tx_fifo<T: Instance>(index: u8) {
+    let r = T::regs();
+    unsafe {
+        r.grstctl().write(|w| {
+            w.set_txfflsh(true);
+            w.set_txfnum(index);
+        });
+        while r.grstctl().read().txfflsh() {}
+    }
+}
+
+/// USB turnaround time, in PHY clocks, for the AHB frequency.
+fn calculate_trdt(high_speed: bool, ahb_freq: Hertz) -> u8 {
+    if high_speed {
+        // The ULPI PHY clock runs at 60 MHz.
+        if ahb_freq.0 >= 30_000_000 {
+            0x9
+        } else {
+            0xC
+        }
+    } else {
+        match ahb_freq.0 {
+            0..=14_999_999 => 0xF,
+            15_000_000..=15_999_999 => 0xE,
+            16_000_000..=17_199_999 => 0xD,
+            17_200_000..=18_499_999 => 0xC,
+            18_500_000..=19_999_999 => 0xB,
+            20_000_000..=21_799_999 => 0xA,
+            21_800_000..=23_999_999 => 0x9,
+            24_000_000..=27_499_999 => 0x8,
+            27_500_000..=31_999_999 => 0x7,
+            _ => 0x6,
+        }
+    }
+}
+
+trait Dir {
+    fn dir() -> Direction;
+}
+
+pub enum In {}
+impl Dir for In {
+    fn dir() -> Direction {
+        Direction::In
+    }
+}
+
+pub enum Out {}
+impl Dir for Out {
+    fn dir() -> Direction {
+        Direction::Out
+    }
+}
+
+pub struct Endpoint<'d, T: Instance, D> {
+    _phantom: PhantomData<(&'d mut T, D)>,
+    info: EndpointInfo,
+    data: EndpointData,
+}
+
+impl<'d, T: Instance> Endpoint<'d, T, In> {
+    /// Waits for the transfer in progress to complete.
+    async fn wait_in_done(&mut self) -> Result<(), EndpointError> {
+        let index = self.info.addr.index();
+        let state = T::state();
+        poll_fn(|cx| {
+            state.ep_in_wakers[index].register(cx.waker());
+            let ctl = unsafe { T::regs().diepctl(index).read() };
+            if !ctl.usbaep() || (index == 0 && state.ep0_setup_ready.load(Ordering::Acquire)) {
+                // Endpoint 0 aborts its data stage when a new SETUP packet comes.
+                Poll::Ready(Err(EndpointError::Disabled))
+            } else if !ctl.epena() {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    type WaitEnabledFuture<'a> = impl Future<Output = ()> + 'a where Self: 'a;
+
+    fn wait_enabled(&mut self) -> Self::WaitEnabledFuture<'_> {
+        async move {
+            trace!("wait_enabled IN WAITING");
+            let index = self.info.addr.index();
+            poll_fn(|cx| {
+                T::state().ep_in_wakers[index].register(cx.waker());
+                if unsafe { T::regs().diepctl(index).read() }.usbaep() {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+            trace!("wait_enabled IN OK");
+        }
+    }
+
+    fn set_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, true)
+    }
+
+    fn clear_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, false)
+    }
+
+    fn is_stalled(&self) -> bool {
+        endpoint_is_stalled::<T>(self.info.addr)
+    }
+}
+
+impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    type WaitEnabledFuture<'a> = impl Future<Output = ()> + 'a where Self: 'a;
+
+    fn wait_enabled(&mut self) -> Self::WaitEnabledFuture<'_> {
+        async move {
+            trace!("wait_enabled OUT WAITING");
+            let index = self.info.addr.index();
+            poll_fn(|cx| {
+                T::state().ep_out_wakers[index].register(cx.waker());
+                if unsafe { T::regs().doepctl(index).read() }.usbaep() {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+            trace!("wait_enabled OUT OK");
+        }
+    }
+
+    fn set_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, true)
+    }
+
+    fn clear_stall(&mut self) {
+        endpoint_set_stalled::<T>(self.info.addr, false)
+    }
+
+    fn is_stalled(&self) -> bool {
+        endpoint_is_stalled::<T>(self.info.addr)
+    }
+}
+
+impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
+    type ReadFuture<'a> = impl Future<Output = Result<usize, EndpointError>> + 'a where Self: 'a;
+
+    /// Reads the data of a transfer. For high-bandwidth isochronous endpoints, this is the data
+    /// of all the transactions of a microframe.
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        async move {
+            trace!("READ WAITING, buf.len() = {}", buf.len());
+            let index = self.info.addr.index();
+            let state = T::state();
+
+            let len = poll_fn(|cx| {
+                state.ep_out_wakers[index].register(cx.waker());
+                let ctl = unsafe { T::regs().doepctl(index).read() };
+                if !ctl.usbaep() || (index == 0 && state.ep0_setup_ready.load(Ordering::Acquire)) {
+                    // Endpoint 0 aborts its data stage when a new SETUP packet comes.
+                    return Poll::Ready(Err(EndpointError::Disabled));
+                }
+                match state.ep_out_size[index].load(Ordering::Acquire) {
+                    EP_OUT_BUFFER_EMPTY => Poll::Pending,
+                    len => Poll::Ready(Ok(len as usize)),
+                }
+            })
+            .await?;
+
+            let result = if len > buf.len() {
+                Err(EndpointError::BufferOverflow)
+            } else {
+                // The endpoint NAKs until it's armed again, so the interrupt leaves the buffer alone.
+                let (ptr, _) = unsafe { *state.ep_out_buffers[index].get() };
+                buf[..len].copy_from_slice(unsafe { core::slice::from_raw_parts(ptr, len) });
+                Ok(len)
+            };
+
+            arm_ep_out::<T>(index, &self.data);
+            trace!("READ OK, rx_len = {:?}", result);
+
+            result
+        }
+    }
+}
+
+impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
+    type WriteFuture<'a> = impl Future<Output = Result<(), EndpointError>> + 'a where Self: 'a;
+
+    /// Writes the data of a transfer. For high-bandwidth isochronous endpoints, this is the data
+    /// of all the transactions of a microframe, split in packets of `max_packet_size`.
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+        async move {
+            if buf.len() > self.data.transfer_size() {
+                return Err(EndpointError::BufferOverflow);
+            }
+
+            trace!("WRITE WAITING");
+            // The TX FIFO fits one transfer, wait for the previous one to complete.
+            self.wait_in_done().await?;
+
+            let r = T::regs();
+            let index = self.info.addr.index();
+            let iso = self.data.ep_type == EndpointType::Isochronous;
+            let mps = self.info.max_packet_size as usize;
+            let packets = ((buf.len() + mps - 1) / mps).max(1);
+
+            unsafe {
+                r.dieptsiz(index).write(|w| {
+                    w.set_xfrsiz(buf.len() as u32);
+                    w.set_pktcnt(packets as u16);
+                    if iso {
+                        w.set_mcnt_stupcnt(packets as u8);
+                    }
+                });
+
+                critical_section::with(|_| {
+                    modify_ep_ctl(r.diepctl(index), |w| {
+                        w.set_epena(true);
+                        w.set_cnak(true);
+                        if iso {
+                            set_frame(w, next_frame_odd::<T>());
+                        }
+                    })
+                });
+            }
+
+            write_fifo::<T>(index, buf);
+            trace!("WRITE OK");
+
+            Ok(())
+        }
+    }
+}
+
+pub struct ControlPipe<'d, T: Instance> {
+    _phantom: PhantomData<&'d mut T>,
+    max_packet_size: u16,
+    ep_in: Endpoint<'d, T, In>,
+    ep_out: Endpoint<'d, T, Out>,
+}
+
+impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
+    type SetupFuture<'a> = impl Future<Output = [u8;8]> + 'a where Self: 'a;
+    type DataOutFuture<'a> = impl Future<Output = Result<usize, EndpointError>> + 'a where Self: 'a;
+    type DataInFuture<'a> = impl Future<Output = Result<(), EndpointError>> + 'a where Self: 'a;
+    type AcceptFuture<'a> = impl Future<Output = ()> + 'a where Self: 'a;
+    type RejectFuture<'a> = impl Future<Output = ()> + 'a where Self: 'a;
+
+    fn max_packet_size(&self) -> usize {
+        usize::from(self.max_packet_size)
+    }
+
+    fn setup<'a>(&'a mut self) -> Self::SetupFuture<'a> {
+        async move {
+            trace!("SETUP read waiting");
+            let state = T::state();
+            let data = poll_fn(|cx| {
+                state.ep_out_wakers[0].register(cx.waker());
+                critical_section::with(|_| {
+                    if state.ep0_setup_ready.load(Ordering::Acquire) {
+                        state.ep0_setup_ready.store(false, Ordering::Release);
+                        Poll::Ready(unsafe { *state.ep0_setup_data.get() })
+                    } else {
+                        Poll::Pending
+                    }
+                })
+            })
+            .await;
+
+            // Drop what's left of the previous request, and get ready for the data stage. Endpoint 0
+            // is always active, so this only aborts its transfer.
+            ep_in_disable::<T>(0);
+            arm_ep_out::<T>(0, &self.ep_out.data);
+
+            trace!("SETUP read ok");
+            data
+        }
+    }
+
+    fn data_out<'a>(&'a mut self, buf: &'a mut [u8], _first: bool, _last: bool) -> Self::DataOutFuture<'a> {
+        async move {
+            trace!("control: data_out");
+            self.ep_out.read(buf).await
+        }
+    }
+
+    fn data_in<'a>(&'a mut self, buf: &'a [u8], _first: bool, _last: bool) -> Self::DataInFuture<'a> {
+        async move {
+            trace!("control: data_in");
+            // The host sends the status stage on its own, endpoint 0 OUT is armed for it.
+            self.ep_in.write(buf).await
+        }
+    }
+
+    fn accept<'a>(&'a mut self) -> Self::AcceptFuture<'a> {
+        async move {
+            trace!("control: accept");
+
+            // Wait for the status stage to complete, so that embassy-usb doesn't set the
+            // address too soon.
+            if self.ep_in.write(&[]).await.is_ok() {
+                self.ep_in.wait_in_done().await.ok();
+            }
+
+            trace!("control: accept OK");
+        }
+    }
+
+    fn reject<'a>(&'a mut self) -> Self::RejectFuture<'a> {
+        async move {
+            trace!("control: reject");
+
+            // The core clears the STALL condition on the next SETUP packet.
+            let r = T::regs();
+            critical_section::with(|_| unsafe {
+                modify_ep_ctl(r.diepctl(0), |w| w.set_stall(true));
+                modify_ep_ctl(r.doepctl(0), |w| w.set_stall(true));
+            });
+        }
+    }
+}
@@ -26,7 +26,7 @@ pub enum EndpointType {
     /// Control endpoint. Used for device management. Only the host can initiate requests. Usually
     /// used only endpoint 0.
     Control = 0b00,
-    /// Isochronous endpoint. Used for time-critical unreliable data. Not implemented yet.
+    /// Isochronous endpoint. Used for time-critical unreliable data. Not supported by all drivers.
     Isochronous = 0b01,
     /// Bulk endpoint. Used for large amounts of best-effort reliable data.
     Bulk = 0b10,
@@ -137,6 +137,44 @@ pub trait Driver<'a> {
         interval: u8,
     ) -> Result<Self::EndpointIn, EndpointAllocError>;
 
+    /// Allocates an isochronous OUT endpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_packet_size` - Maximum packet size in bytes, per transaction.
+    /// * `interval` - Polling interval, as the exponent `n` of a 2^(n-1) (micro)frame period, from 1 to 16.
+    /// * `additional_transactions` - Number of additional transactions per microframe, from 0 to 2.
+    ///   Only high-speed devices support additional transactions.
+    ///
+    /// The default implementation allocates the endpoint with
+    /// [`alloc_endpoint_out`](Self::alloc_endpoint_out), and fails if additional transactions are requested.
+    fn alloc_endpoint_out_isochronous(
+        &mut self,
+        max_packet_size: u16,
+        interval: u8,
+        additional_transactions: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        if additional_transactions != 0 {
+            return Err(EndpointAllocError);
+        }
+        self.alloc_endpoint_out(EndpointType::Isochronous, max_packet_size, interval)
+    }
+
+    /// Allocates an isochronous IN endpoint.
+    ///
+    /// See [`alloc_endpoint_out_isochronous`](Self::alloc_endpoint_out_isochronous) for the arguments.
+    fn alloc_endpoint_in_isochronous(
+        &mut self,
+        max_packet_size: u16,
+        interval: u8,
+        additional_transactions: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        if additional_transactions != 0 {
+            return Err(EndpointAllocError);
+        }
+        self.alloc_endpoint_in(EndpointType::Isochronous, max_packet_size, interval)
+    }
+
     /// Start operation of the USB device.
     ///
     /// This returns the `Bus` and `ControlPipe` instances that are used to operate
@@ -183,11 +221,16 @@ pub trait Bus {
     /// Enables or disables an endpoint.
     fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool);
 
-    /// Sets or clears the STALL condition for an endpoint. If the endpoint is an OUT endpoint, it
-    /// should be prepared to receive data again. Only used during control transfers.
+    /// Sets or clears the STALL condition for an endpoint. Clearing it resets the data toggle of
+    /// the endpoint to DATA0, and if the endpoint is an OUT endpoint, it should be prepared to
+    /// receive data again.
+    ///
+    /// This is used for the SET_FEATURE and CLEAR_FEATURE(ENDPOINT_HALT) requests of the host,
+    /// and acts on the same condition as [`Endpoint::set_stall`] and [`Endpoint::clear_stall`].
     fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool);
 
-    /// Gets whether the STALL condition is set for an endpoint. Only used during control transfers.
+    /// Gets whether the STALL condition is set for an endpoint, whether it was set by the host or
+    /// with [`Endpoint::set_stall`]. This is used for the GET_STATUS requests of the host.
     fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool;
 
     /// Simulates a disconnect from the USB bus, causing the host to reset and re-enumerate the
@@ -210,6 +253,23 @@ pub trait Bus {
     /// * [`Unsupported`](crate::driver::Unsupported) - This UsbBus implementation doesn't support
     ///   remote wakeup or it has not been enabled at creation time.
     fn remote_wakeup(&mut self) -> Self::RemoteWakeupFuture<'_>;
+
+    /// Puts the device in a test mode selected by the host, for compliance testing of high-speed
+    /// devices.
+    ///
+    /// This is called while handling the SET_FEATURE request selecting the test mode, before its
+    /// status stage: the device must only enter the test mode once the status stage completes, and
+    /// stays in it until it's powered off.
+    ///
+    /// The default implementation just returns `Unsupported`.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::driver::Unsupported) - This UsbBus implementation doesn't support
+    ///   the test mode, in which case the request is rejected.
+    fn set_test_mode(&mut self, _mode: TestMode) -> Result<(), Unsupported> {
+        Err(Unsupported)
+    }
 }
 
 pub trait Endpoint {
@@ -222,6 +282,19 @@ pub trait Endpoint {
 
     /// Waits for the endpoint to be enabled.
     fn wait_enabled(&mut self) -> Self::WaitEnabledFuture<'_>;
+
+    /// Sets the STALL condition for the endpoint, to signal an error to the host. The endpoint
+    /// answers every transaction with a STALL handshake until the condition is cleared, by
+    /// [`clear_stall`](Self::clear_stall) or by the host with a CLEAR_FEATURE(ENDPOINT_HALT)
+    /// request.
+    fn set_stall(&mut self);
+
+    /// Clears the STALL condition for the endpoint, and resets its data toggle to DATA0. If the
+    /// endpoint is an OUT endpoint, it is prepared to receive data again.
+    fn clear_stall(&mut self);
+
+    /// Gets whether the STALL condition is set for the endpoint.
+    fn is_stalled(&self) -> bool;
 }
 
 pub trait EndpointOut: Endpoint {
@@ -233,6 +306,9 @@ pub trait EndpointOut: Endpoint {
     /// the packet.
     ///
     /// This should also clear any NAK flags and prepare the endpoint to receive the next packet.
+    ///
+    /// For isochronous endpoints, this waits for the packet of the next (micro)frame the host
+    /// sends one in. Packets received with errors are dropped.
     fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a>;
 }
 
@@ -287,6 +363,9 @@ pub trait EndpointIn: Endpoint {
         Self: 'a;
 
     /// Writes a single packet of data to the endpoint.
+    ///
+    /// For isochronous endpoints, the packet is sent in the next (micro)frame the host polls
+    /// the endpoint in, so writes are paced by the host. Packets are never retried.
     fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a>;
 }
 
@@ -312,6 +391,21 @@ pub enum Event {
     PowerRemoved,
 }
 
+/// Test mode selected by the host with [`Bus::set_test_mode`], USB 2.0 spec, 7.1.20.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TestMode {
+    /// Test_J: the transceiver drives the J state continuously.
+    J,
+    /// Test_K: the transceiver drives the K state continuously.
+    K,
+    /// Test_SE0_NAK: the transceiver stays in receive mode, and the device answers IN tokens with
+    /// NAK.
+    Se0Nak,
+    /// Test_Packet: the device sends the test packet repeatedly.
+    Packet,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct EndpointAllocError;
@@ -1,10 +1,15 @@
 use heapless::Vec;
 
 use crate::control::ControlHandler;
-use crate::descriptor::{BosWriter, DescriptorWriter};
+use crate::descriptor::{capability_type, BosWriter, DescriptorWriter, SynchronizationType, UsageType};
 use crate::driver::{Driver, Endpoint, EndpointType};
+use crate::msos::{DeviceLevelDescriptor, FunctionLevelDescriptor, MsOsDescriptorWriter};
 use crate::types::*;
-use crate::{DeviceStateHandler, Interface, UsbDevice, MAX_INTERFACE_COUNT, STRING_INDEX_CUSTOM_START};
+use crate::{
+    Configuration, DeviceParts, DeviceStateHandler, Interface, StringHandler, UsbDevice, CONFIGURATION_VALUE,
+    MAX_CONFIGURATION_COUNT, MAX_INTERFACE_COUNT, MAX_TOTAL_INTERFACE_COUNT, STRING_INDEX_CUSTOM_START,
+    STRING_INDEX_MANUFACTURER, STRING_INDEX_PRODUCT, STRING_INDEX_SERIAL_NUMBER,
+};
 
 #[derive(Debug, Copy, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -78,6 +83,8 @@ pub struct Config<'a> {
     /// This should be set to `true` even if the device is sometimes self-powered and may not
     /// always draw power from the USB bus.
     ///
+    /// This applies to the first configuration, see [`Builder::configuration`] for the others.
+    ///
     /// Default: `false`
     ///
     /// See also: `max_power`
@@ -88,6 +95,8 @@ pub struct Config<'a> {
     /// The default is 100 mA. If your device always uses an external power source and never draws
     /// power from the USB bus, this can be set to 0.
     ///
+    /// This applies to the first configuration, see [`Builder::configuration`] for the others.
+    ///
     /// See also: `self_powered`
     ///
     /// Default: 100mA
@@ -121,7 +130,10 @@ impl<'a> Config<'a> {
 pub struct Builder<'d, D: Driver<'d>> {
     config: Config<'d>,
     handler: Option<&'d dyn DeviceStateHandler>,
-    interfaces: Vec<Interface<'d>, MAX_INTERFACE_COUNT>,
+    interfaces: Vec<Interface<'d>, MAX_TOTAL_INTERFACE_COUNT>,
+    configurations: Vec<Configuration, MAX_CONFIGURATION_COUNT>,
+    vendor_handler: Option<&'d mut dyn ControlHandler>,
+    string_handler: Option<&'d mut dyn StringHandler>,
     control_buf: &'d mut [u8],
 
     driver: D,
@@ -130,6 +142,7 @@ pub struct Builder<'d, D: Driver<'d>> {
     device_descriptor: DescriptorWriter<'d>,
     config_descriptor: DescriptorWriter<'d>,
     bos_descriptor: BosWriter<'d>,
+    msos_descriptor: Option<MsOsDescriptorWriter<'d>>,
 }
 
 impl<'d, D: Driver<'d>> Builder<'d, D> {
@@ -154,9 +167,7 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
             panic!("if composite_with_iads is set, you must set device_class = 0xEF, device_sub_class = 0x02, device_protocol = 0x01");
         }
 
-        if config.max_power > 500 {
-            panic!("The maximum allowed value for `max_power` is 500mA");
-        }
+        check_max_power(config.max_power);
 
         match config.max_packet_size_0 {
             8 | 16 | 32 | 64 => {}
@@ -164,50 +175,201 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
         }
 
         let mut device_descriptor = DescriptorWriter::new(device_descriptor_buf);
-        let mut config_descriptor = DescriptorWriter::new(config_descriptor_buf);
+        let config_descriptor = DescriptorWriter::new(config_descriptor_buf);
         let mut bos_descriptor = BosWriter::new(DescriptorWriter::new(bos_descriptor_buf));
 
         device_descriptor.device(&config);
-        config_descriptor.configuration(&config);
         bos_descriptor.bos();
 
-        Builder {
+        let mut builder = Builder {
             driver,
             handler,
             config,
             interfaces: Vec::new(),
+            configurations: Vec::new(),
+            vendor_handler: None,
+            string_handler: None,
             control_buf,
             next_string_index: STRING_INDEX_CUSTOM_START,
 
             device_descriptor,
             config_descriptor,
             bos_descriptor,
-        }
+            msos_descriptor: None,
+        };
+        builder.start_configuration(config.self_powered, config.max_power);
+        builder
     }
 
     /// Creates the [`UsbDevice`] instance with the configuration in this builder.
     pub fn build(mut self) -> UsbDevice<'d, D> {
-        self.config_descriptor.end_configuration();
+        self.end_configuration();
+        self.device_descriptor.buf[17] = self.configurations.len() as u8; // bNumConfigurations
+
+        // Reference the strings only the handler supplies.
+        if let Some(h) = &mut self.string_handler {
+            if h.manufacturer().is_some() {
+                self.device_descriptor.buf[14] = STRING_INDEX_MANUFACTURER; // iManufacturer
+            }
+            if h.product().is_some() {
+                self.device_descriptor.buf[15] = STRING_INDEX_PRODUCT; // iProduct
+            }
+            if h.serial_number().is_some() {
+                self.device_descriptor.buf[16] = STRING_INDEX_SERIAL_NUMBER; // iSerialNumber
+            }
+        }
+
+        // Windows loads its composite driver for devices with several interfaces, unless they
+        // have a device class.
+        let composite = self.configurations[0].num_interfaces > 1
+            && (self.config.device_class == 0 || self.config.composite_with_iads);
+        let msos_descriptor = self.msos_descriptor.map(|writer| {
+            let (set, capability) = writer.end(composite);
+            self.bos_descriptor.capability(capability_type::PLATFORM, &capability);
+            set
+        });
         self.bos_descriptor.end_bos();
 
         UsbDevice::build(
             self.driver,
-            self.config,
-            self.handler,
-            self.device_descriptor.into_buf(),
-            self.config_descriptor.into_buf(),
-            self.bos_descriptor.writer.into_buf(),
-            self.interfaces,
-            self.control_buf,
+            DeviceParts {
+                config: self.config,
+                handler: self.handler,
+                device_descriptor: self.device_descriptor.into_buf(),
+                config_descriptor: self.config_descriptor.into_buf(),
+                bos_descriptor: self.bos_descriptor.writer.into_buf(),
+                msos_descriptor,
+                interfaces: self.interfaces,
+                configurations: self.configurations,
+                vendor_handler: self.vendor_handler,
+                string_handler: self.string_handler,
+                control_buf: self.control_buf,
+            },
         )
     }
 
+    /// Starts a new configuration, to which the functions added next belong.
+    ///
+    /// [`Builder::new`] starts the first configuration, with the power settings of [`Config`].
+    /// The host selects one configuration among all of them, such as a self-powered one drawing
+    /// less current from the bus, or one exposing only some of the functions. Each configuration
+    /// needs its own instances of the classes, and interface numbers start from 0 again. The
+    /// endpoints aren't shared either: each class instance allocates its own, so the classes of
+    /// all configurations together must fit in the endpoints of the hardware. Each configuration
+    /// has up to [`MAX_INTERFACE_COUNT`] interfaces.
+    ///
+    /// The host learns about the other configurations, but doesn't always look for the best one:
+    /// most hosts select the first one unless told otherwise.
+    pub fn configuration(&mut self, self_powered: bool, max_power: u16) {
+        check_max_power(max_power);
+        self.end_configuration();
+        self.start_configuration(self_powered, max_power);
+    }
+
+    fn start_configuration(&mut self, self_powered: bool, max_power: u16) {
+        let value = CONFIGURATION_VALUE + self.configurations.len() as u8;
+        let descriptor_start = self.config_descriptor.position();
+        self.config_descriptor
+            .configuration(value, self_powered, self.config.supports_remote_wakeup, max_power);
+
+        let config = Configuration {
+            descriptor_start,
+            descriptor_end: descriptor_start,
+            first_interface: self.interfaces.len(),
+            num_interfaces: 0,
+            self_powered,
+        };
+        if self.configurations.push(config).is_err() {
+            panic!("max configuration count reached")
+        }
+    }
+
+    fn end_configuration(&mut self) {
+        self.config_descriptor.end_configuration();
+
+        let config = self.configurations.last_mut().unwrap();
+        config.descriptor_end = self.config_descriptor.position();
+        config.num_interfaces = self.interfaces.len() - config.first_interface;
+    }
+
+    /// Returns the interface `number` of the current configuration.
+    fn interface_mut(&mut self, number: InterfaceNumber) -> &mut Interface<'d> {
+        let first_interface = self.configurations.last().unwrap().first_interface;
+        &mut self.interfaces[first_interface + number.0 as usize]
+    }
+
+    /// Returns the number of the next interface of the current configuration.
+    fn next_interface_number(&self) -> InterfaceNumber {
+        let first_interface = self.configurations.last().unwrap().first_interface;
+        InterfaceNumber::new((self.interfaces.len() - first_interface) as _)
+    }
+
     /// Returns the size of the control request data buffer. Can be used by
     /// classes to validate the buffer is large enough for their needs.
     pub fn control_buf_len(&self) -> usize {
         self.control_buf.len()
     }
 
+    /// Adds a device capability descriptor to the BOS descriptor.
+    ///
+    /// See [`capability_type`] for the standard capability types.
+    pub fn bos_capability(&mut self, capability_type: u8, data: &[u8]) {
+        self.bos_descriptor.capability(capability_type, data)
+    }
+
+    /// Sets the handler for vendor requests addressed to the device rather than to an interface.
+    ///
+    /// Only one handler can be set. Requests for the Microsoft OS 2.0 descriptor set are answered
+    /// by the device before reaching it.
+    pub fn vendor_handler(&mut self, handler: &'d mut dyn ControlHandler) {
+        if self.vendor_handler.is_some() {
+            panic!("vendor handler already set");
+        }
+        self.vendor_handler = Some(handler);
+    }
+
+    /// Sets the handler supplying the device strings at request time, instead of [`Config`].
+    ///
+    /// Only one handler can be set.
+    pub fn string_handler(&mut self, handler: &'d mut dyn StringHandler) {
+        if self.string_handler.is_some() {
+            panic!("string handler already set");
+        }
+        self.string_handler = Some(handler);
+    }
+
+    /// Enables the Microsoft OS 2.0 descriptor set, written to `buf`.
+    ///
+    /// The device then advertises the set in its BOS descriptor, and serves it on vendor request
+    /// `vendor_code`, which must not clash with the other vendor requests of the device.
+    /// `windows_version` is the minimum Windows version the set applies to, see
+    /// [`windows_version`](crate::msos::windows_version).
+    ///
+    /// This must be called before adding any MS OS feature.
+    pub fn msos_descriptor(&mut self, buf: &'d mut [u8], windows_version: u32, vendor_code: u8) {
+        if self.msos_descriptor.is_some() {
+            panic!("msos_descriptor already called");
+        }
+        self.msos_descriptor = Some(MsOsDescriptorWriter::new(buf, windows_version, vendor_code));
+    }
+
+    /// Adds a Microsoft OS 2.0 feature descriptor applying to the whole device.
+    ///
+    /// Device level features must be added before any function level one.
+    pub fn msos_feature<T: DeviceLevelDescriptor>(&mut self, desc: T) {
+        match &mut self.msos_descriptor {
+            Some(writer) => writer.device_feature(desc),
+            None => panic!("msos_feature called before msos_descriptor"),
+        }
+    }
+
+    /// Returns whether the Microsoft OS 2.0 descriptor set is enabled.
+    ///
+    /// Classes can use this to add their own MS OS features only when the application opted in.
+    pub fn msos_enabled(&self) -> bool {
+        self.msos_descriptor.is_some()
+    }
+
     /// Add an USB function.
     ///
     /// If [`Config::composite_with_iads`] is set, this will add an IAD descriptor
@@ -215,14 +377,11 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
     ///
     /// If it's not set, no IAD descriptor is added.
     pub fn function(&mut self, class: u8, subclass: u8, protocol: u8) -> FunctionBuilder<'_, 'd, D> {
+        let first_interface = self.next_interface_number();
+
         let iface_count_index = if self.config.composite_with_iads {
-            self.config_descriptor.iad(
-                InterfaceNumber::new(self.interfaces.len() as _),
-                0,
-                class,
-                subclass,
-                protocol,
-            );
+            self.config_descriptor
+                .iad(first_interface, 0, class, subclass, protocol);
 
             Some(self.config_descriptor.position() - 5)
         } else {
@@ -232,6 +391,7 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
         FunctionBuilder {
             builder: self,
             iface_count_index,
+            first_interface,
         }
     }
 }
@@ -244,6 +404,7 @@ impl<'d, D: Driver<'d>> Builder<'d, D> {
 pub struct FunctionBuilder<'a, 'd, D: Driver<'d>> {
     builder: &'a mut Builder<'d, D>,
     iface_count_index: Option<usize>,
+    first_interface: InterfaceNumber,
 }
 
 impl<'a, 'd, D: Driver<'d>> FunctionBuilder<'a, 'd, D> {
@@ -255,24 +416,44 @@ impl<'a, 'd, D: Driver<'d>> FunctionBuilder<'a, 'd, D> {
             self.builder.config_descriptor.buf[i] += 1;
         }
 
-        let number = self.builder.interfaces.len() as _;
+        let number = self.builder.next_interface_number();
+        if number.0 as usize >= MAX_INTERFACE_COUNT {
+            panic!("max interface count reached")
+        }
+
         let iface = Interface {
             handler: None,
             current_alt_setting: 0,
             num_alt_settings: 0,
             num_strings: 0,
+            vendor_requests: false,
         };
 
-        if self.builder.interfaces.push(iface).is_err() {
-            panic!("max interface count reached")
-        }
+        // Can't fail, each configuration has room for its interfaces.
+        let _ = self.builder.interfaces.push(iface);
 
         InterfaceBuilder {
             builder: self.builder,
-            interface_number: InterfaceNumber::new(number),
+            interface_number: number,
             next_alt_setting_number: 0,
         }
     }
+
+    /// Adds a Microsoft OS 2.0 feature descriptor applying to this function.
+    ///
+    /// Windows only reads function level features of composite devices. If the device turns out
+    /// not to be composite, the features of its function apply to the whole device instead.
+    ///
+    /// Only functions of the first configuration can have features.
+    pub fn msos_feature<T: FunctionLevelDescriptor>(&mut self, desc: T) {
+        if self.builder.configurations.len() > 1 {
+            panic!("msos_feature called outside of the first configuration");
+        }
+        match &mut self.builder.msos_descriptor {
+            Some(writer) => writer.function_feature(self.first_interface, desc),
+            None => panic!("msos_feature called before msos_descriptor"),
+        }
+    }
 }
 
 /// Interface builder.
@@ -289,14 +470,22 @@ impl<'a, 'd, D: Driver<'d>> InterfaceBuilder<'a, 'd, D> {
     }
 
     pub fn handler(&mut self, handler: &'d mut dyn ControlHandler) {
-        self.builder.interfaces[self.interface_number.0 as usize].handler = Some(handler);
+        self.builder.interface_mut(self.interface_number).handler = Some(handler);
+    }
+
+    /// Passes the vendor requests addressed to the interface to its handler, in addition to the
+    /// class requests. The handler then has to check [`Request::request_type`].
+    ///
+    /// [`Request::request_type`]: crate::control::Request::request_type
+    pub fn vendor_requests(&mut self) {
+        self.builder.interface_mut(self.interface_number).vendor_requests = true;
     }
 
     /// Allocates a new string index.
     pub fn string(&mut self) -> StringIndex {
         let index = self.builder.next_string_index;
         self.builder.next_string_index += 1;
-        self.builder.interfaces[self.interface_number.0 as usize].num_strings += 1;
+        self.builder.interface_mut(self.interface_number).num_strings += 1;
 
         StringIndex::new(index)
     }
@@ -309,7 +498,7 @@ impl<'a, 'd, D: Driver<'d>> InterfaceBuilder<'a, 'd, D> {
     pub fn alt_setting(&mut self, class: u8, subclass: u8, protocol: u8) -> InterfaceAltBuilder<'_, 'd, D> {
         let number = self.next_alt_setting_number;
         self.next_alt_setting_number += 1;
-        self.builder.interfaces[self.interface_number.0 as usize].num_alt_settings += 1;
+        self.builder.interface_mut(self.interface_number).num_alt_settings += 1;
 
         self.builder
             .config_descriptor
@@ -401,4 +590,74 @@ impl<'a, 'd, D: Driver<'d>> InterfaceAltBuilder<'a, 'd, D> {
     pub fn endpoint_interrupt_out(&mut self, max_packet_size: u16, interval: u8) -> D::EndpointOut {
         self.endpoint_out(EndpointType::Interrupt, max_packet_size, interval)
     }
+
+    /// Allocate an ISOCHRONOUS IN endpoint and write its descriptor.
+    ///
+    /// `interval` is the exponent `n` of the 2^(n-1) (micro)frame polling period, from 1 to 16.
+    /// `additional_transactions` is the number of extra transactions per microframe, from 0
+    /// to 2, which only high-speed drivers support.
+    ///
+    /// Descriptors are written in the order builder functions are called. Note that some
+    /// classes care about the order. Not all drivers support isochronous endpoints.
+    pub fn endpoint_isochronous_in(
+        &mut self,
+        max_packet_size: u16,
+        interval: u8,
+        additional_transactions: u8,
+        synchronization: SynchronizationType,
+        usage: UsageType,
+    ) -> D::EndpointIn {
+        assert!((1..=16).contains(&interval));
+        assert!(additional_transactions <= 2);
+
+        let ep = self
+            .builder
+            .driver
+            .alloc_endpoint_in_isochronous(max_packet_size, interval, additional_transactions)
+            .expect("alloc_endpoint_in_isochronous failed");
+
+        self.builder
+            .config_descriptor
+            .endpoint_isochronous(ep.info(), synchronization, usage, additional_transactions);
+
+        ep
+    }
+
+    /// Allocate an ISOCHRONOUS OUT endpoint and write its descriptor.
+    ///
+    /// `interval` is the exponent `n` of the 2^(n-1) (micro)frame polling period, from 1 to 16.
+    /// `additional_transactions` is the number of extra transactions per microframe, from 0
+    /// to 2, which only high-speed drivers support.
+    ///
+    /// Descriptors are written in the order builder functions are called. Note that some
+    /// classes care about the order. Not all drivers support isochronous endpoints.
+    pub fn endpoint_isochronous_out(
+        &mut self,
+        max_packet_size: u16,
+        interval: u8,
+        additional_transactions: u8,
+        synchronization: SynchronizationType,
+        usage: UsageType,
+    ) -> D::EndpointOut {
+        assert!((1..=16).contains(&interval));
+        assert!(additional_transactions <= 2);
+
+        let ep = self
+            .builder
+            .driver
+            .alloc_endpoint_out_isochronous(max_packet_size, interval, additional_transactions)
+            .expect("alloc_endpoint_out_isochronous failed");
+
+        self.builder
+            .config_descriptor
+            .endpoint_isochronous(ep.info(), synchronization, usage, additional_transactions);
+
+        ep
+    }
+}
+
+fn check_max_power(max_power: u16) {
+    if max_power > 500 {
+        panic!("The maximum allowed value for `max_power` is 500mA");
+    }
 }
@@ -0,0 +1,633 @@
+//! USB Audio Class 2.0, for speakers and microphones.
+//!
+//! Each class is an audio function streaming PCM samples in one direction, through an isochronous
+//! endpoint, with a sample rate the host picks among a fixed list, and mute and volume controls.
+//! A headset is a [`Speaker`] and a [`Microphone`] in the same device.
+//!
+//! The clock of the audio peripheral, such as an I2S or SAI, is never exactly the one of the host.
+//! A [`RateEstimator`] measures it against the USB frames, for the explicit feedback of a speaker,
+//! or to size the packets of a microphone, so neither side runs out of samples.
+//!
+//! Audio functions are made of several interfaces, so
+//! [`Config::composite_with_iads`](crate::Config::composite_with_iads) has to be set. The endpoints
+//! are sized for full-speed devices, where a packet carries the samples of a 1 ms frame.
+
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicI16, AtomicU32, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use crate::builder::{FunctionBuilder, InterfaceAltBuilder};
+use crate::control::{self, ControlHandler, InResponse, OutResponse, Request};
+use crate::descriptor::{SynchronizationType, UsageType};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::Builder;
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_CLASS_AUDIO: u8 = 0x01;
+
+const AUDIO_FUNCTION_SUBCLASS_UNDEFINED: u8 = 0x00;
+const AUDIO_SUBCLASS_AUDIOCONTROL: u8 = 0x01;
+const AUDIO_SUBCLASS_AUDIOSTREAMING: u8 = 0x02;
+const AUDIO_PROTOCOL_IP_VERSION_02_00: u8 = 0x20;
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+const AC_TYPE_HEADER: u8 = 0x01;
+const AC_TYPE_INPUT_TERMINAL: u8 = 0x02;
+const AC_TYPE_OUTPUT_TERMINAL: u8 = 0x03;
+const AC_TYPE_FEATURE_UNIT: u8 = 0x06;
+const AC_TYPE_CLOCK_SOURCE: u8 = 0x0a;
+const AS_TYPE_GENERAL: u8 = 0x01;
+const AS_TYPE_FORMAT_TYPE: u8 = 0x02;
+const EP_TYPE_GENERAL: u8 = 0x01;
+
+const CATEGORY_DESKTOP_SPEAKER: u8 = 0x01;
+const CATEGORY_MICROPHONE: u8 = 0x03;
+
+const TERMINAL_USB_STREAMING: u16 = 0x0101;
+const TERMINAL_MICROPHONE: u16 = 0x0201;
+const TERMINAL_SPEAKER: u16 = 0x0301;
+
+const FORMAT_TYPE_I: u8 = 0x01;
+const FORMAT_PCM: u32 = 0x0000_0001;
+
+const REQ_CUR: u8 = 0x01;
+const REQ_RANGE: u8 = 0x02;
+
+const CS_SAM_FREQ_CONTROL: u8 = 0x01;
+const CS_CLOCK_VALID_CONTROL: u8 = 0x02;
+const FU_MUTE_CONTROL: u8 = 0x01;
+const FU_VOLUME_CONTROL: u8 = 0x02;
+
+// Entities of the audio function, all in a single chain.
+const INPUT_TERMINAL_ID: u8 = 1;
+const FEATURE_UNIT_ID: u8 = 2;
+const OUTPUT_TERMINAL_ID: u8 = 3;
+const CLOCK_SOURCE_ID: u8 = 4;
+
+const MAX_CHANNELS: u8 = 8;
+
+/// Gain of the proportional correction of the [`RateEstimator`], as a negative power of two.
+const RATE_LOOP_SHIFT: u32 = 6;
+/// Largest number of samples counted in a frame, to ignore a reset counter.
+const MAX_FRAME_SAMPLES: u32 = 0xffff;
+
+/// Configuration of an audio function.
+pub struct Config<'d> {
+    /// Number of interleaved channels, up to 8.
+    pub channels: u8,
+    /// Bytes taken by each sample in a packet: 1, 2, 3 or 4.
+    pub subslot_size: u8,
+    /// Significant bits of each sample, at most `8 * subslot_size`.
+    pub bit_resolution: u8,
+    /// Sample rates in Hz the host can pick from. The first one is used until the host sets one.
+    pub sample_rates: &'d [u32],
+    /// Lowest volume, in 1/256 dB.
+    pub min_volume: i16,
+    /// Highest volume, in 1/256 dB.
+    pub max_volume: i16,
+    /// Volume step, in 1/256 dB.
+    pub volume_resolution: i16,
+    /// Whether a speaker has an explicit feedback endpoint.
+    ///
+    /// With feedback, the speaker plays samples at the rate of its own clock and reports that rate
+    /// with [`Speaker::write_feedback`]. Without it, the speaker adapts to the rate the host sends
+    /// samples at. Microphones always send samples at the rate of their own clock.
+    pub feedback: bool,
+}
+
+impl<'d> Config<'d> {
+    /// Size of the largest packet: the samples of a frame at the highest rate, plus one to absorb
+    /// the drift between the clocks.
+    fn max_packet_size(&self) -> u16 {
+        let max_rate = self.sample_rates.iter().copied().max().unwrap_or(0);
+        let samples = (max_rate + 999) / 1000 + 1;
+        (samples * self.channels as u32 * self.subslot_size as u32) as u16
+    }
+
+    fn channel_config(&self) -> u32 {
+        match self.channels {
+            // Front left and front right.
+            2 => 0x0000_0003,
+            _ => 0,
+        }
+    }
+}
+
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    shared: ControlShared,
+}
+
+impl<'d> State<'d> {
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: ControlShared {
+                sample_rate: AtomicU32::new(0),
+                muted: AtomicBool::new(false),
+                volume: AtomicI16::new(0),
+                changed: Signal::new(),
+            },
+        }
+    }
+}
+
+/// Shared data between Control and the audio classes
+struct ControlShared {
+    sample_rate: AtomicU32,
+    muted: AtomicBool,
+    volume: AtomicI16,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+struct Control<'d> {
+    shared: &'d ControlShared,
+    sample_rates: &'d [u32],
+    min_volume: i16,
+    max_volume: i16,
+    volume_resolution: i16,
+}
+
+impl<'d> ControlHandler for Control<'d> {
+    fn control_out(&mut self, req: control::Request, data: &[u8]) -> OutResponse {
+        let entity = (req.index >> 8) as u8;
+        let selector = (req.value >> 8) as u8;
+        let channel = req.value as u8;
+        if req.request != REQ_CUR {
+            return OutResponse::Rejected;
+        }
+
+        match (entity, selector, data.len()) {
+            (CLOCK_SOURCE_ID, CS_SAM_FREQ_CONTROL, 4) => {
+                let rate = u32::from_le_bytes(data.try_into().unwrap());
+                if !self.sample_rates.contains(&rate) {
+                    warn!("audio: unsupported sample rate {}", rate);
+                    return OutResponse::Rejected;
+                }
+                debug!("audio: sample rate {}", rate);
+                self.shared.sample_rate.store(rate, Ordering::Relaxed);
+            }
+            (FEATURE_UNIT_ID, FU_MUTE_CONTROL, 1) if channel == 0 => {
+                self.shared.muted.store(data[0] != 0, Ordering::Relaxed);
+            }
+            (FEATURE_UNIT_ID, FU_VOLUME_CONTROL, 2) if channel == 0 => {
+                let volume = i16::from_le_bytes([data[0], data[1]]).clamp(self.min_volume, self.max_volume);
+                self.shared.volume.store(volume, Ordering::Relaxed);
+            }
+            _ => return OutResponse::Rejected,
+        }
+        self.shared.changed.signal(());
+        OutResponse::Accepted
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        let entity = (req.index >> 8) as u8;
+        let selector = (req.value >> 8) as u8;
+        let channel = req.value as u8;
+
+        let len = match (entity, selector, req.request) {
+            (CLOCK_SOURCE_ID, CS_SAM_FREQ_CONTROL, REQ_CUR) => {
+                buf[..4].copy_from_slice(&self.shared.sample_rate.load(Ordering::Relaxed).to_le_bytes());
+                4
+            }
+            (CLOCK_SOURCE_ID, CS_SAM_FREQ_CONTROL, REQ_RANGE) => {
+                // One subrange of a single rate per supported rate.
+                buf[..2].copy_from_slice(&(self.sample_rates.len() as u16).to_le_bytes());
+                for (range, rate) in buf[2..].chunks_mut(12).zip(self.sample_rates) {
+                    range[0..4].copy_from_slice(&rate.to_le_bytes());
+                    range[4..8].copy_from_slice(&rate.to_le_bytes());
+                    range[8..12].fill(0);
+                }
+                2 + 12 * self.sample_rates.len()
+            }
+            (CLOCK_SOURCE_ID, CS_CLOCK_VALID_CONTROL, REQ_CUR) => {
+                buf[0] = 1;
+                1
+            }
+            (FEATURE_UNIT_ID, FU_MUTE_CONTROL, REQ_CUR) if channel == 0 => {
+                buf[0] = self.shared.muted.load(Ordering::Relaxed) as u8;
+                1
+            }
+            (FEATURE_UNIT_ID, FU_VOLUME_CONTROL, REQ_CUR) if channel == 0 => {
+                buf[..2].copy_from_slice(&self.shared.volume.load(Ordering::Relaxed).to_le_bytes());
+                2
+            }
+            (FEATURE_UNIT_ID, FU_VOLUME_CONTROL, REQ_RANGE) if channel == 0 => {
+                buf[0..2].copy_from_slice(&1u16.to_le_bytes());
+                buf[2..4].copy_from_slice(&self.min_volume.to_le_bytes());
+                buf[4..6].copy_from_slice(&self.max_volume.to_le_bytes());
+                buf[6..8].copy_from_slice(&self.volume_resolution.to_le_bytes());
+                8
+            }
+            _ => return InResponse::Rejected,
+        };
+        InResponse::Accepted(&buf[..len])
+    }
+}
+
+/// Values of the controls the host sets on an audio function.
+#[derive(Clone, Copy)]
+pub struct Controls<'d> {
+    shared: &'d ControlShared,
+}
+
+impl<'d> Controls<'d> {
+    /// Gets the current sample rate, in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.shared.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Gets whether the host muted the function.
+    pub fn muted(&self) -> bool {
+        self.shared.muted.load(Ordering::Relaxed)
+    }
+
+    /// Gets the current volume, in 1/256 dB.
+    pub fn volume(&self) -> i16 {
+        self.shared.volume.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the host to change the sample rate, mute or volume.
+    pub async fn wait_changed(&self) {
+        self.shared.changed.wait().await
+    }
+}
+
+/// Audio function playing the samples the host sends.
+pub struct Speaker<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    feedback_ep: Option<D::EndpointIn>,
+    controls: Controls<'d>,
+}
+
+impl<'d, D: Driver<'d>> Speaker<'d, D> {
+    /// Creates a new Speaker.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: &Config<'d>) -> Self {
+        let (mut func, controls) = audio_control(builder, state, config, CATEGORY_DESKTOP_SPEAKER, TERMINAL_SPEAKER);
+
+        let mut iface = func.interface();
+        iface.alt_setting(
+            USB_CLASS_AUDIO,
+            AUDIO_SUBCLASS_AUDIOSTREAMING,
+            AUDIO_PROTOCOL_IP_VERSION_02_00,
+        );
+        let mut alt = iface.alt_setting(
+            USB_CLASS_AUDIO,
+            AUDIO_SUBCLASS_AUDIOSTREAMING,
+            AUDIO_PROTOCOL_IP_VERSION_02_00,
+        );
+        audio_streaming(&mut alt, config, INPUT_TERMINAL_ID);
+        let synchronization = match config.feedback {
+            true => SynchronizationType::Asynchronous,
+            false => SynchronizationType::Adaptive,
+        };
+        let read_ep =
+            alt.endpoint_isochronous_out(config.max_packet_size(), 1, 0, synchronization, UsageType::DataEndpoint);
+        alt.descriptor(CS_ENDPOINT, &[EP_TYPE_GENERAL, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let feedback_ep = config.feedback.then(|| {
+            // 10.14 samples per frame in 3 bytes, the full-speed format.
+            alt.endpoint_isochronous_in(
+                3,
+                1,
+                0,
+                SynchronizationType::NoSynchronization,
+                UsageType::FeedbackEndpoint,
+            )
+        });
+
+        Speaker {
+            read_ep,
+            feedback_ep,
+            controls,
+        }
+    }
+
+    /// Gets the controls of the speaker, which can be moved to another task.
+    pub fn controls(&self) -> Controls<'d> {
+        self.controls
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Waits for the host to start streaming.
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+
+    /// Reads the samples of a frame. `buf` must be at least `max_packet_size` long.
+    ///
+    /// Returns [`EndpointError::Disabled`] once the host stops streaming.
+    pub async fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        self.read_ep.read(buf).await
+    }
+
+    /// Reports the rate samples are played at, as samples per frame in 16.16 fixed point.
+    ///
+    /// The host adjusts the number of samples it sends to it. Should be called once per frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the speaker was configured without feedback.
+    pub async fn write_feedback(&mut self, samples_per_frame: u32) -> Result<(), EndpointError> {
+        let ep = unwrap!(self.feedback_ep.as_mut());
+        let value = (samples_per_frame >> 2).to_le_bytes();
+        ep.write(&value[..3]).await
+    }
+}
+
+/// Audio function recording samples for the host.
+pub struct Microphone<'d, D: Driver<'d>> {
+    write_ep: D::EndpointIn,
+    controls: Controls<'d>,
+}
+
+impl<'d, D: Driver<'d>> Microphone<'d, D> {
+    /// Creates a new Microphone. `feedback` in the configuration is ignored.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: &Config<'d>) -> Self {
+        let (mut func, controls) = audio_control(builder, state, config, CATEGORY_MICROPHONE, TERMINAL_MICROPHONE);
+
+        let mut iface = func.interface();
+        iface.alt_setting(
+            USB_CLASS_AUDIO,
+            AUDIO_SUBCLASS_AUDIOSTREAMING,
+            AUDIO_PROTOCOL_IP_VERSION_02_00,
+        );
+        let mut alt = iface.alt_setting(
+            USB_CLASS_AUDIO,
+            AUDIO_SUBCLASS_AUDIOSTREAMING,
+            AUDIO_PROTOCOL_IP_VERSION_02_00,
+        );
+        audio_streaming(&mut alt, config, OUTPUT_TERMINAL_ID);
+        let write_ep = alt.endpoint_isochronous_in(
+            config.max_packet_size(),
+            1,
+            0,
+            SynchronizationType::Asynchronous,
+            UsageType::DataEndpoint,
+        );
+        alt.descriptor(CS_ENDPOINT, &[EP_TYPE_GENERAL, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        Microphone { write_ep, controls }
+    }
+
+    /// Gets the controls of the microphone, which can be moved to another task.
+    pub fn controls(&self) -> Controls<'d> {
+        self.controls
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        self.write_ep.info().max_packet_size
+    }
+
+    /// Waits for the host to start streaming.
+    pub async fn wait_connection(&mut self) {
+        self.write_ep.wait_enabled().await
+    }
+
+    /// Writes the samples of a frame, at most `max_packet_size` bytes.
+    ///
+    /// The number of samples in each packet tells the host the rate of the clock of the
+    /// microphone, see [`RateEstimator::next_packet_len`].
+    ///
+    /// Returns [`EndpointError::Disabled`] once the host stops streaming.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        self.write_ep.write(data).await
+    }
+}
+
+/// Estimates the rate of the clock of an audio peripheral, such as an I2S or SAI, in samples per
+/// USB frame.
+///
+/// The estimator is given a counter of the samples transferred by the peripheral, read once per
+/// frame, for instance after each packet of the isochronous endpoint. It tracks the counter with a
+/// loop filtering out the jitter of the reads over a few hundred frames, so a peripheral which
+/// transfers the samples by blocks, as DMA does, can be used as it is.
+pub struct RateEstimator {
+    /// Samples per frame, with 32 fractional bits.
+    rate: i64,
+    /// Samples counted but not tracked yet, with 32 fractional bits.
+    lag: i64,
+    /// Samples tracked but not sent in packets yet, with 32 fractional bits.
+    due: i64,
+    last_count: Option<u32>,
+}
+
+impl RateEstimator {
+    /// Creates a new RateEstimator, starting from the nominal `sample_rate` in Hz.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            rate: ((sample_rate as i64) << 32) / 1000,
+            lag: 0,
+            due: 0,
+            last_count: None,
+        }
+    }
+
+    /// Starts over from the nominal `sample_rate` in Hz, when the host changes it or the
+    /// peripheral is restarted.
+    pub fn reset(&mut self, sample_rate: u32) {
+        *self = Self::new(sample_rate);
+    }
+
+    /// Updates the estimate with the value of the sample counter of the peripheral, which can
+    /// wrap around. Should be called once per frame.
+    pub fn update(&mut self, sample_count: u32) {
+        let last = self.last_count.replace(sample_count);
+        let samples = match last {
+            Some(last) => sample_count.wrapping_sub(last).min(MAX_FRAME_SAMPLES),
+            None => return,
+        };
+
+        // Proportional and integral corrections of a critically damped loop.
+        self.lag += ((samples as i64) << 32) - self.rate;
+        let step = self.rate + (self.lag >> RATE_LOOP_SHIFT);
+        self.lag -= self.lag >> RATE_LOOP_SHIFT;
+        self.rate += self.lag >> (2 * RATE_LOOP_SHIFT + 2);
+        self.due += step;
+    }
+
+    /// Gets the estimated rate, as samples per frame in 16.16 fixed point.
+    ///
+    /// This is the value reported by [`Speaker::write_feedback`].
+    pub fn samples_per_frame(&self) -> u32 {
+        (self.rate >> 16) as u32
+    }
+
+    /// Gets the estimated sample rate, in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        ((self.rate * 1000) >> 32) as u32
+    }
+
+    /// Gets the number of samples per channel to send in the next packet of a [`Microphone`].
+    ///
+    /// Packets follow the samples counted since the previous one, smoothed: the buffer between the
+    /// peripheral and USB should have a margin of a few frames, plus one block of the peripheral.
+    /// With blocks longer than a frame, the length can exceed the max packet size, and has to be
+    /// capped to it.
+    pub fn next_packet_len(&mut self) -> usize {
+        let samples = (self.due >> 32).max(0);
+        self.due -= samples << 32;
+        samples as usize
+    }
+}
+
+/// Adds the audio function and writes its control interface, a chain from the input terminal to
+/// the output terminal through a feature unit, with the given terminal on the device side.
+fn audio_control<'a, 'd, D: Driver<'d>>(
+    builder: &'a mut Builder<'d, D>,
+    state: &'d mut State<'d>,
+    config: &Config<'d>,
+    category: u8,
+    terminal_type: u16,
+) -> (FunctionBuilder<'a, 'd, D>, Controls<'d>) {
+    assert!(config.channels > 0 && config.channels <= MAX_CHANNELS);
+    assert!(!config.sample_rates.is_empty());
+    assert!(config.max_packet_size() <= 1023);
+    assert!(builder.control_buf_len() >= 2 + 12 * config.sample_rates.len());
+
+    let shared = &state.shared;
+    shared.sample_rate.store(config.sample_rates[0], Ordering::Relaxed);
+    shared
+        .volume
+        .store(0.clamp(config.min_volume, config.max_volume), Ordering::Relaxed);
+    let control = state.control.write(Control {
+        shared,
+        sample_rates: config.sample_rates,
+        min_volume: config.min_volume,
+        max_volume: config.max_volume,
+        volume_resolution: config.volume_resolution,
+    });
+
+    // The USB streaming terminal is the input of speakers, and the output of microphones.
+    let (input_type, output_type) = match terminal_type {
+        TERMINAL_SPEAKER => (TERMINAL_USB_STREAMING, terminal_type),
+        _ => (terminal_type, TERMINAL_USB_STREAMING),
+    };
+    let programmable = config.sample_rates.len() > 1;
+    let channel_config = config.channel_config().to_le_bytes();
+    let feature_unit_len = 6 + 4 * (config.channels as usize + 1);
+    let total_len = (9 + 8 + 17 + feature_unit_len + 12) as u16;
+
+    let mut func = builder.function(
+        USB_CLASS_AUDIO,
+        AUDIO_FUNCTION_SUBCLASS_UNDEFINED,
+        AUDIO_PROTOCOL_IP_VERSION_02_00,
+    );
+
+    // Control interface
+    let mut iface = func.interface();
+    iface.handler(control);
+    let mut alt = iface.alt_setting(
+        USB_CLASS_AUDIO,
+        AUDIO_SUBCLASS_AUDIOCONTROL,
+        AUDIO_PROTOCOL_IP_VERSION_02_00,
+    );
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            AC_TYPE_HEADER, // bDescriptorSubtype
+            0x00,
+            0x02,     // bcdADC (2.0)
+            category, // bCategory
+            total_len as u8,
+            (total_len >> 8) as u8, // wTotalLength
+            0x00,                   // bmControls
+        ],
+    );
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            AC_TYPE_CLOCK_SOURCE,                   // bDescriptorSubtype
+            CLOCK_SOURCE_ID,                        // bClockID
+            if programmable { 0x03 } else { 0x01 }, // bmAttributes (internal, programmable or fixed)
+            if programmable { 0x07 } else { 0x05 }, // bmControls (frequency, validity)
+            0x00,                                   // bAssocTerminal
+            0x00,                                   // iClockSource
+        ],
+    );
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            AC_TYPE_INPUT_TERMINAL, // bDescriptorSubtype
+            INPUT_TERMINAL_ID,      // bTerminalID
+            input_type as u8,
+            (input_type >> 8) as u8, // wTerminalType
+            0x00,                    // bAssocTerminal
+            CLOCK_SOURCE_ID,         // bCSourceID
+            config.channels,         // bNrChannels
+            channel_config[0],
+            channel_config[1],
+            channel_config[2],
+            channel_config[3], // bmChannelConfig
+            0x00,              // iChannelNames
+            0x00,
+            0x00, // bmControls
+            0x00, // iTerminal
+        ],
+    );
+    let mut feature_unit = [0; 6 + 4 * (MAX_CHANNELS as usize + 1) - 2];
+    feature_unit[0] = AC_TYPE_FEATURE_UNIT; // bDescriptorSubtype
+    feature_unit[1] = FEATURE_UNIT_ID; // bUnitID
+    feature_unit[2] = INPUT_TERMINAL_ID; // bSourceID
+    feature_unit[3] = 0x0f; // bmaControls(0) (mute, volume); other channels have none
+    alt.descriptor(CS_INTERFACE, &feature_unit[..feature_unit_len - 2]);
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            AC_TYPE_OUTPUT_TERMINAL, // bDescriptorSubtype
+            OUTPUT_TERMINAL_ID,      // bTerminalID
+            output_type as u8,
+            (output_type >> 8) as u8, // wTerminalType
+            0x00,                     // bAssocTerminal
+            FEATURE_UNIT_ID,          // bSourceID
+            CLOCK_SOURCE_ID,          // bCSourceID
+            0x00,
+            0x00, // bmControls
+            0x00, // iTerminal
+        ],
+    );
+
+    (func, Controls { shared })
+}
+
+/// Writes the class descriptors of the streaming alternate setting.
+fn audio_streaming<'d, D: Driver<'d>>(alt: &mut InterfaceAltBuilder<'_, 'd, D>, config: &Config<'d>, terminal: u8) {
+    let formats = FORMAT_PCM.to_le_bytes();
+    let channel_config = config.channel_config().to_le_bytes();
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            AS_TYPE_GENERAL, // bDescriptorSubtype
+            terminal,        // bTerminalLink
+            0x00,            // bmControls
+            FORMAT_TYPE_I,   // bFormatType
+            formats[0],
+            formats[1],
+            formats[2],
+            formats[3],      // bmFormats
+            config.channels, // bNrChannels
+            channel_config[0],
+            channel_config[1],
+            channel_config[2],
+            channel_config[3], // bmChannelConfig
+            0x00,              // iChannelNames
+        ],
+    );
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            AS_TYPE_FORMAT_TYPE,   // bDescriptorSubtype
+            FORMAT_TYPE_I,         // bFormatType
+            config.subslot_size,   // bSubslotSize
+            config.bit_resolution, // bBitResolution
+        ],
+    );
+}
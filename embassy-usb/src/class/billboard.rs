@@ -0,0 +1,229 @@
+//! USB Billboard device class, for USB Type-C devices with alternate modes.
+//!
+//! A device using alternate modes of its Type-C connector, such as DisplayPort, exposes a
+//! billboard telling the host which modes it supports, and whether they could be entered. When
+//! the host and the device fail to agree on a mode, the host can then tell the user why the device
+//! doesn't work, instead of failing silently.
+//!
+//! The billboard is described by capability descriptors in the BOS descriptor, and its interface
+//! has no endpoints. The host only reads the descriptors when the device is enumerated: the device
+//! reports the state of the modes once their negotiation is over, typically by connecting after it
+//! fails. A device which is only a billboard uses [`USB_CLASS_BILLBOARD`] as `device_class`.
+
+use crate::control::ControlHandler;
+use crate::descriptor::capability_type;
+use crate::driver::Driver;
+use crate::types::*;
+use crate::Builder;
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_CLASS_BILLBOARD: u8 = 0x11;
+
+const BILLBOARD_SUBCLASS: u8 = 0x00;
+const BILLBOARD_PROTOCOL: u8 = 0x00;
+
+const BILLBOARD_VERSION: u16 = 0x0121;
+
+/// Maximum number of alternate modes of a billboard.
+pub const MAX_ALT_MODES: usize = 52;
+
+const CAPABILITY_LEN: usize = 41 + 4 * MAX_ALT_MODES;
+
+/// State of the configuration of an alternate mode.
+#[repr(u8)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AltModeState {
+    /// The configuration failed for an unspecified reason.
+    Unspecified = 0b00,
+    /// The configuration was not attempted, or the mode was exited.
+    NotAttempted = 0b01,
+    /// The configuration was attempted, but failed.
+    Unsuccessful = 0b10,
+    /// The mode is configured.
+    Successful = 0b11,
+}
+
+/// VCONN power needed by the device for its alternate modes.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VconnPower {
+    /// The device doesn't need VCONN power.
+    NotRequired,
+    /// 1 W.
+    OneWatt,
+    /// 1.5 W.
+    OneAndHalfWatts,
+    /// 2 W.
+    TwoWatts,
+    /// 3 W.
+    ThreeWatts,
+    /// 4 W.
+    FourWatts,
+    /// 5 W.
+    FiveWatts,
+    /// 6 W.
+    SixWatts,
+}
+
+impl VconnPower {
+    fn bits(self) -> u16 {
+        match self {
+            VconnPower::NotRequired => 0x8000,
+            VconnPower::OneWatt => 0,
+            VconnPower::OneAndHalfWatts => 1,
+            VconnPower::TwoWatts => 2,
+            VconnPower::ThreeWatts => 3,
+            VconnPower::FourWatts => 4,
+            VconnPower::FiveWatts => 5,
+            VconnPower::SixWatts => 6,
+        }
+    }
+}
+
+/// Alternate mode supported by the device.
+pub struct AltMode<'d> {
+    /// Standard or vendor ID of the mode, such as 0xff01 for DisplayPort.
+    pub svid: u16,
+    /// Index of the mode among the modes of the SVID, as in the Discover Modes response.
+    pub mode: u8,
+    /// Mode VDO of the mode, as in the Discover Modes response.
+    pub vdo: u32,
+    /// State of the configuration of the mode.
+    pub state: AltModeState,
+    /// Description of the mode, shown to the user.
+    pub description: Option<&'d str>,
+}
+
+/// Configuration of the [`BillboardClass`].
+pub struct Config<'d> {
+    /// Alternate modes of the device, up to [`MAX_ALT_MODES`].
+    pub alt_modes: &'d [AltMode<'d>],
+
+    /// Index in `alt_modes` of the mode the device prefers.
+    pub preferred_alt_mode: u8,
+
+    /// VCONN power needed by the device.
+    pub vconn_power: VconnPower,
+
+    /// URL of a page with more information about the device and its alternate modes.
+    pub additional_info_url: &'d str,
+
+    /// The alternate modes couldn't be entered because the power available was too low.
+    pub insufficient_power: bool,
+
+    /// The alternate modes couldn't be entered because the USB Power Delivery communication
+    /// failed.
+    pub pd_failure: bool,
+
+    /// UUID identifying the device, the same for all its USB functions, and for each connection.
+    pub container_id: [u8; 16],
+}
+
+/// Internal state for the billboard class.
+pub struct State<'d> {
+    control: Control<'d>,
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: Control {
+                additional_info_url: "",
+                alt_modes: &[],
+                first_string: 0,
+            },
+        }
+    }
+}
+
+struct Control<'d> {
+    additional_info_url: &'d str,
+    alt_modes: &'d [AltMode<'d>],
+    first_string: u8,
+}
+
+impl<'d> ControlHandler for Control<'d> {
+    fn get_string(&mut self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+        // The URL is the first string of the interface, followed by the mode descriptions.
+        match u8::from(index).checked_sub(self.first_string)? {
+            0 => Some(self.additional_info_url),
+            n => self.alt_modes.iter().filter_map(|m| m.description).nth(n as usize - 1),
+        }
+    }
+}
+
+/// USB Billboard device class, made of the billboard capability descriptors and an interface
+/// without endpoints.
+pub struct BillboardClass {
+    data_if: InterfaceNumber,
+}
+
+impl BillboardClass {
+    /// Creates a new BillboardClass, and writes its capability descriptors to the BOS descriptor.
+    pub fn new<'d, D: Driver<'d>>(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: &Config<'d>) -> Self {
+        let alt_modes = config.alt_modes;
+        assert!(alt_modes.len() <= MAX_ALT_MODES);
+        assert!((config.preferred_alt_mode as usize) < alt_modes.len());
+
+        let mut func = builder.function(USB_CLASS_BILLBOARD, BILLBOARD_SUBCLASS, BILLBOARD_PROTOCOL);
+        let mut iface = func.interface();
+        let data_if = iface.interface_number();
+
+        let url_string = iface.string();
+        let mut mode_strings = [0; MAX_ALT_MODES];
+        for (mode, string) in alt_modes.iter().zip(&mut mode_strings) {
+            if mode.description.is_some() {
+                *string = iface.string().into();
+            }
+        }
+
+        state.control = Control {
+            additional_info_url: config.additional_info_url,
+            alt_modes,
+            first_string: url_string.into(),
+        };
+        iface.handler(&mut state.control);
+        iface.alt_setting(USB_CLASS_BILLBOARD, BILLBOARD_SUBCLASS, BILLBOARD_PROTOCOL);
+
+        let mut container_id = [0; 17];
+        // container_id[0] is bReserved.
+        container_id[1..].copy_from_slice(&config.container_id);
+        builder.bos_capability(capability_type::CONTAINER_ID, &container_id);
+
+        let mut capability = [0; CAPABILITY_LEN];
+        capability[0] = url_string.into();
+        capability[1] = alt_modes.len() as u8;
+        capability[2] = config.preferred_alt_mode;
+        capability[3..5].copy_from_slice(&config.vconn_power.bits().to_le_bytes());
+        for (i, mode) in alt_modes.iter().enumerate() {
+            // bmConfigured holds 2 bits per mode.
+            capability[5 + i / 4] |= (mode.state as u8) << (i % 4 * 2);
+        }
+        capability[37..39].copy_from_slice(&BILLBOARD_VERSION.to_le_bytes());
+        capability[39] = (config.insufficient_power as u8) | (config.pd_failure as u8) << 1;
+        // capability[40] is bReserved.
+        for (i, mode) in alt_modes.iter().enumerate() {
+            let entry = &mut capability[41 + i * 4..][..4];
+            entry[0..2].copy_from_slice(&mode.svid.to_le_bytes());
+            entry[2] = mode.mode;
+            entry[3] = mode_strings[i];
+        }
+        builder.bos_capability(capability_type::BILLBOARD, &capability[..41 + 4 * alt_modes.len()]);
+
+        for (i, mode) in alt_modes.iter().enumerate() {
+            let mut capability = [0; 5];
+            capability[0] = i as u8;
+            capability[1..5].copy_from_slice(&mode.vdo.to_le_bytes());
+            builder.bos_capability(capability_type::BILLBOARD_ALT_MODE, &capability);
+        }
+
+        BillboardClass { data_if }
+    }
+
+    /// Gets the number of the billboard interface.
+    pub fn interface_number(&self) -> InterfaceNumber {
+        self.data_if
+    }
+}
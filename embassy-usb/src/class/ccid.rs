@@ -0,0 +1,448 @@
+//! USB Chip/Smart Card Interface Device class.
+//!
+//! [`CcidClass`] presents the device as a smart card reader with a single slot, holding the
+//! [`Card`] it is given: a real card behind a reader interface, or a card emulated by the
+//! firmware, as in a security token. The host talks to it with its usual smart card stack, such
+//! as PC/SC, without a specific driver.
+//!
+//! APDUs are exchanged whole (short APDU level exchange), with the T=1 protocol. The host polls
+//! the slot for card insertion and removal.
+
+use core::future::Future;
+use core::mem::MaybeUninit;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use crate::control::{self, ControlHandler, OutResponse};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::Builder;
+
+const USB_CLASS_CCID: u8 = 0x0b;
+const CCID_SUBCLASS: u8 = 0x00;
+const CCID_PROTOCOL: u8 = 0x00;
+
+const CS_CCID: u8 = 0x21;
+
+const REQ_ABORT: u8 = 0x01;
+
+const PC_TO_RDR_SET_PARAMETERS: u8 = 0x61;
+const PC_TO_RDR_ICC_POWER_ON: u8 = 0x62;
+const PC_TO_RDR_ICC_POWER_OFF: u8 = 0x63;
+const PC_TO_RDR_GET_SLOT_STATUS: u8 = 0x65;
+const PC_TO_RDR_SECURE: u8 = 0x69;
+const PC_TO_RDR_ESCAPE: u8 = 0x6b;
+const PC_TO_RDR_GET_PARAMETERS: u8 = 0x6c;
+const PC_TO_RDR_RESET_PARAMETERS: u8 = 0x6d;
+const PC_TO_RDR_XFR_BLOCK: u8 = 0x6f;
+const PC_TO_RDR_ABORT: u8 = 0x72;
+const PC_TO_RDR_SET_DATA_RATE_AND_CLOCK_FREQUENCY: u8 = 0x73;
+
+const RDR_TO_PC_DATA_BLOCK: u8 = 0x80;
+const RDR_TO_PC_SLOT_STATUS: u8 = 0x81;
+const RDR_TO_PC_PARAMETERS: u8 = 0x82;
+const RDR_TO_PC_ESCAPE: u8 = 0x83;
+const RDR_TO_PC_DATA_RATE_AND_CLOCK_FREQUENCY: u8 = 0x84;
+
+const ICC_STATUS_ACTIVE: u8 = 0;
+const ICC_STATUS_INACTIVE: u8 = 1;
+const ICC_STATUS_NOT_PRESENT: u8 = 2;
+const COMMAND_STATUS_FAILED: u8 = 1 << 6;
+
+const ERROR_CMD_NOT_SUPPORTED: u8 = 0x00;
+/// Offset of the dwLength field, reported when it is invalid.
+const ERROR_BAD_LENGTH: u8 = 1;
+/// Offset of the bSlot field, reported when the slot doesn't exist.
+const ERROR_BAD_SLOT: u8 = 5;
+/// Offset of the bProtocolNum field, reported when the protocol isn't supported.
+const ERROR_BAD_PROTOCOL: u8 = 7;
+const ERROR_HW_ERROR: u8 = 0xfb;
+const ERROR_ICC_MUTE: u8 = 0xfe;
+
+const PROTOCOL_T1: u8 = 0x01;
+
+/// Default T=1 protocol data structure: Fi=372, Di=1, LRC, BWI=4, CWI=13 and IFSC=254.
+const T1_PARAMETERS: [u8; 7] = [0x11, 0x10, 0x00, 0x4d, 0x00, 0xfe, 0x00];
+
+const FEATURE_AUTO_PARAMETERS_FROM_ATR: u32 = 0x0000_0002;
+const FEATURE_AUTO_ACTIVATION: u32 = 0x0000_0004;
+const FEATURE_AUTO_VOLTAGE: u32 = 0x0000_0008;
+const FEATURE_AUTO_CLOCK: u32 = 0x0000_0010;
+const FEATURE_AUTO_BAUD_RATE: u32 = 0x0000_0020;
+const FEATURE_AUTO_PARAMETERS: u32 = 0x0000_0040;
+const FEATURE_AUTO_PPS: u32 = 0x0000_0080;
+const FEATURE_SHORT_APDU: u32 = 0x0002_0000;
+
+const HEADER_LEN: usize = 10;
+
+/// Maximum length in bytes of a command APDU.
+pub const MAX_COMMAND_LEN: usize = 261;
+/// Maximum length in bytes of a response APDU, including the status word.
+pub const MAX_RESPONSE_LEN: usize = 258;
+
+const MAX_MESSAGE_LEN: usize = HEADER_LEN + MAX_COMMAND_LEN;
+
+/// Size of the transfer buffers, the longest message rounded up to a multiple of all the valid
+/// bulk max packet sizes.
+const BUF_LEN: usize = 320;
+
+/// Error reported by a [`Card`], sent to the host as the result of the command.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The card didn't answer.
+    Mute,
+    /// The card or its interface failed.
+    Hardware,
+}
+
+impl Error {
+    fn code(self) -> u8 {
+        match self {
+            Error::Mute => ERROR_ICC_MUTE,
+            Error::Hardware => ERROR_HW_ERROR,
+        }
+    }
+}
+
+/// Smart card in the slot of a [`CcidClass`].
+pub trait Card {
+    /// Whether the card is in the slot. A card which can't be removed is always present.
+    fn present(&self) -> bool {
+        true
+    }
+
+    /// Future returned by `power_on`.
+    type PowerOnFuture<'a>: Future<Output = Result<&'a [u8], Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Powers the card on, or resets it if already powered, and returns its Answer To Reset.
+    ///
+    /// The ATR must announce the T=1 protocol.
+    fn power_on(&mut self) -> Self::PowerOnFuture<'_>;
+
+    /// Powers the card off.
+    fn power_off(&mut self);
+
+    /// Future returned by `transmit`.
+    type TransmitFuture<'a>: Future<Output = Result<usize, Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Sends a command APDU to the powered card, and writes its response APDU, ending with the
+    /// status word, to `response`. Returns the length of the response.
+    ///
+    /// `response` holds [`MAX_RESPONSE_LEN`] bytes.
+    fn transmit<'a>(&'a mut self, command: &'a [u8], response: &'a mut [u8]) -> Self::TransmitFuture<'a>;
+}
+
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    reset: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d> State<'d> {
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            reset: Signal::new(),
+        }
+    }
+}
+
+struct Control<'d> {
+    reset: &'d Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d> ControlHandler for Control<'d> {
+    fn reset(&mut self) {
+        self.reset.signal(());
+    }
+
+    fn control_out(&mut self, req: control::Request, _data: &[u8]) -> OutResponse {
+        match req.request {
+            REQ_ABORT => {
+                // Commands are processed one at a time, there is nothing else to abort than the
+                // PC_to_RDR_Abort command which follows.
+                debug!("ccid: abort");
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+}
+
+/// Response to a command, with its data written to the response buffer after the header.
+struct Response {
+    message_type: u8,
+    status: u8,
+    error: u8,
+    specific: u8,
+    len: usize,
+}
+
+/// Smart card reader with a single slot, holding a [`Card`].
+pub struct CcidClass<'d, D: Driver<'d>, C: Card> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    reset: &'d Signal<CriticalSectionRawMutex, ()>,
+    card: C,
+    powered: bool,
+    buf: [u8; BUF_LEN],
+    response: [u8; BUF_LEN],
+}
+
+impl<'d, D: Driver<'d>, C: Card> CcidClass<'d, D, C> {
+    /// Creates a new CcidClass holding `card`. For full-speed devices, `max_packet_size` has to
+    /// be one of 8, 16, 32 or 64.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, card: C, max_packet_size: u16) -> Self {
+        let control = state.control.write(Control { reset: &state.reset });
+
+        let mut func = builder.function(USB_CLASS_CCID, CCID_SUBCLASS, CCID_PROTOCOL);
+        let mut iface = func.interface();
+        iface.handler(control);
+        let mut alt = iface.alt_setting(USB_CLASS_CCID, CCID_SUBCLASS, CCID_PROTOCOL);
+
+        let features = FEATURE_AUTO_PARAMETERS_FROM_ATR
+            | FEATURE_AUTO_ACTIVATION
+            | FEATURE_AUTO_VOLTAGE
+            | FEATURE_AUTO_CLOCK
+            | FEATURE_AUTO_BAUD_RATE
+            | FEATURE_AUTO_PARAMETERS
+            | FEATURE_AUTO_PPS
+            | FEATURE_SHORT_APDU;
+        let mut desc = [0; 52];
+        desc[0..2].copy_from_slice(&0x0110u16.to_le_bytes()); // bcdCCID
+        desc[2] = 0; // bMaxSlotIndex
+        desc[3] = 0x07; // bVoltageSupport, 5V, 3V and 1.8V
+        desc[4..8].copy_from_slice(&(1u32 << PROTOCOL_T1).to_le_bytes()); // dwProtocols
+        desc[8..12].copy_from_slice(&3580u32.to_le_bytes()); // dwDefaultClock, kHz
+        desc[12..16].copy_from_slice(&3580u32.to_le_bytes()); // dwMaximumClock
+        desc[16] = 0; // bNumClockSupported
+        desc[17..21].copy_from_slice(&9600u32.to_le_bytes()); // dwDataRate, bps
+        desc[21..25].copy_from_slice(&9600u32.to_le_bytes()); // dwMaxDataRate
+        desc[25] = 0; // bNumDataRatesSupported
+        desc[26..30].copy_from_slice(&(T1_PARAMETERS[5] as u32).to_le_bytes()); // dwMaxIFSD
+        desc[30..34].copy_from_slice(&0u32.to_le_bytes()); // dwSynchProtocols
+        desc[34..38].copy_from_slice(&0u32.to_le_bytes()); // dwMechanical
+        desc[38..42].copy_from_slice(&features.to_le_bytes()); // dwFeatures
+        desc[42..46].copy_from_slice(&(MAX_MESSAGE_LEN as u32).to_le_bytes()); // dwMaxCCIDMessageLength
+        desc[46] = 0xff; // bClassGetResponse, echo the class of the APDU
+        desc[47] = 0xff; // bClassEnvelope
+        desc[48..50].copy_from_slice(&0u16.to_le_bytes()); // wLcdLayout, no LCD
+        desc[50] = 0; // bPINSupport
+        desc[51] = 1; // bMaxCCIDBusySlots
+        alt.descriptor(CS_CCID, &desc);
+
+        let read_ep = alt.endpoint_bulk_out(max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(max_packet_size);
+
+        CcidClass {
+            read_ep,
+            write_ep,
+            reset: &state.reset,
+            card,
+            powered: false,
+            buf: [0; BUF_LEN],
+            response: [0; BUF_LEN],
+        }
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        // The size is the same for both endpoints.
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+
+    /// Handles the commands of the host.
+    pub async fn run(&mut self) -> ! {
+        let reset = self.reset;
+        loop {
+            self.wait_connection().await;
+            reset.reset();
+            loop {
+                match select(self.handle_command(), reset.wait()).await {
+                    Either::First(Ok(())) => {}
+                    Either::First(Err(EndpointError::BufferOverflow)) => warn!("ccid: host sent a packet too large"),
+                    Either::First(Err(EndpointError::Disabled)) => break,
+                    Either::Second(()) => self.power_off(),
+                }
+            }
+            self.power_off();
+        }
+    }
+
+    fn power_off(&mut self) {
+        if self.powered {
+            self.card.power_off();
+            self.powered = false;
+        }
+    }
+
+    fn icc_status(&self) -> u8 {
+        match (self.card.present(), self.powered) {
+            (false, _) => ICC_STATUS_NOT_PRESENT,
+            (true, true) => ICC_STATUS_ACTIVE,
+            (true, false) => ICC_STATUS_INACTIVE,
+        }
+    }
+
+    async fn handle_command(&mut self) -> Result<(), EndpointError> {
+        let len = self.read_message().await?;
+        if len < HEADER_LEN {
+            warn!("ccid: invalid message");
+            return Ok(());
+        }
+        let message_type = self.buf[0];
+        let data_len = u32::from_le_bytes(self.buf[1..5].try_into().unwrap()) as usize;
+        trace!("ccid: command {:x}", message_type);
+
+        if !self.card.present() {
+            // Removed while powered.
+            self.powered = false;
+        }
+
+        let response = if data_len > MAX_COMMAND_LEN || HEADER_LEN + data_len != len {
+            self.failed(message_type, ERROR_BAD_LENGTH)
+        } else if self.buf[5] != 0 {
+            self.failed(message_type, ERROR_BAD_SLOT)
+        } else {
+            self.execute(message_type, data_len).await
+        };
+
+        self.response[0] = response.message_type;
+        self.response[1..5].copy_from_slice(&(response.len as u32).to_le_bytes());
+        self.response[5] = self.buf[5]; // bSlot
+        self.response[6] = self.buf[6]; // bSeq
+        self.response[7] = response.status;
+        self.response[8] = response.error;
+        self.response[9] = response.specific;
+        self.write_message(HEADER_LEN + response.len).await
+    }
+
+    async fn execute(&mut self, message_type: u8, data_len: usize) -> Response {
+        match message_type {
+            PC_TO_RDR_ICC_POWER_ON => {
+                if !self.card.present() {
+                    return self.failed(message_type, ERROR_ICC_MUTE);
+                }
+                let len = match self.card.power_on().await {
+                    Ok(atr) => {
+                        let len = atr.len().min(MAX_RESPONSE_LEN);
+                        self.response[HEADER_LEN..HEADER_LEN + len].copy_from_slice(&atr[..len]);
+                        len
+                    }
+                    Err(e) => {
+                        warn!("ccid: failed to power the card on: {:?}", e);
+                        self.powered = false;
+                        return self.failed(message_type, e.code());
+                    }
+                };
+                self.powered = true;
+                self.processed(message_type, len)
+            }
+            PC_TO_RDR_ICC_POWER_OFF => {
+                self.power_off();
+                self.processed(message_type, 0)
+            }
+            PC_TO_RDR_GET_SLOT_STATUS | PC_TO_RDR_ABORT => self.processed(message_type, 0),
+            PC_TO_RDR_XFR_BLOCK => {
+                if !self.powered {
+                    return self.failed(message_type, ERROR_ICC_MUTE);
+                }
+                let command = &self.buf[HEADER_LEN..HEADER_LEN + data_len];
+                let response = &mut self.response[HEADER_LEN..HEADER_LEN + MAX_RESPONSE_LEN];
+                match self.card.transmit(command, response).await {
+                    Ok(len) => self.processed(message_type, len.min(MAX_RESPONSE_LEN)),
+                    Err(e) => {
+                        warn!("ccid: failed to transmit to the card: {:?}", e);
+                        self.failed(message_type, e.code())
+                    }
+                }
+            }
+            PC_TO_RDR_GET_PARAMETERS | PC_TO_RDR_RESET_PARAMETERS | PC_TO_RDR_SET_PARAMETERS => {
+                if message_type == PC_TO_RDR_SET_PARAMETERS && self.buf[7] != PROTOCOL_T1 {
+                    return self.failed(message_type, ERROR_BAD_PROTOCOL);
+                }
+                // The parameters are fixed, the APDUs being exchanged whole.
+                self.response[HEADER_LEN..HEADER_LEN + T1_PARAMETERS.len()].copy_from_slice(&T1_PARAMETERS);
+                let mut response = self.processed(message_type, T1_PARAMETERS.len());
+                response.specific = PROTOCOL_T1;
+                response
+            }
+            _ => self.failed(message_type, ERROR_CMD_NOT_SUPPORTED),
+        }
+    }
+
+    fn processed(&self, command: u8, len: usize) -> Response {
+        Response {
+            message_type: response_type(command),
+            status: self.icc_status(),
+            error: 0,
+            specific: 0,
+            len,
+        }
+    }
+
+    fn failed(&self, command: u8, error: u8) -> Response {
+        Response {
+            message_type: response_type(command),
+            status: self.icc_status() | COMMAND_STATUS_FAILED,
+            error,
+            specific: 0,
+            len: 0,
+        }
+    }
+
+    /// Reads a message into the transfer buffer, and returns its length. The data of a message
+    /// too long for the buffer is dropped, only its header is kept.
+    async fn read_message(&mut self) -> Result<usize, EndpointError> {
+        let mps = self.max_packet_size() as usize;
+        let mut len = 0;
+        loop {
+            let pos = len.min(BUF_LEN - mps);
+            let n = self.read_ep.read(&mut self.buf[pos..pos + mps]).await?;
+            len += n;
+            if n < mps {
+                return Ok(len);
+            }
+            if len >= HEADER_LEN {
+                let data_len = u32::from_le_bytes(self.buf[1..5].try_into().unwrap()) as usize;
+                if len >= HEADER_LEN.saturating_add(data_len) {
+                    return Ok(len);
+                }
+            }
+        }
+    }
+
+    /// Writes a message from the response buffer.
+    async fn write_message(&mut self, len: usize) -> Result<(), EndpointError> {
+        let mps = self.max_packet_size() as usize;
+        for chunk in self.response[..len].chunks(mps) {
+            self.write_ep.write(chunk).await?;
+        }
+        if len % mps == 0 {
+            // End the transfer with a short packet.
+            self.write_ep.write(&[]).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Type of the response message to a command.
+fn response_type(command: u8) -> u8 {
+    match command {
+        PC_TO_RDR_ICC_POWER_ON | PC_TO_RDR_XFR_BLOCK | PC_TO_RDR_SECURE => RDR_TO_PC_DATA_BLOCK,
+        PC_TO_RDR_GET_PARAMETERS | PC_TO_RDR_RESET_PARAMETERS | PC_TO_RDR_SET_PARAMETERS => RDR_TO_PC_PARAMETERS,
+        PC_TO_RDR_ESCAPE => RDR_TO_PC_ESCAPE,
+        PC_TO_RDR_SET_DATA_RATE_AND_CLOCK_FREQUENCY => RDR_TO_PC_DATA_RATE_AND_CLOCK_FREQUENCY,
+        _ => RDR_TO_PC_SLOT_STATUS,
+    }
+}
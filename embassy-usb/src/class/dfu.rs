@@ -0,0 +1,375 @@
+//! USB Device Firmware Upgrade (DFU) class, writing updates to the embassy-boot DFU partition.
+//!
+//! The class comes in two halves, following the DFU 1.1 specification:
+//!
+//! - [`DfuRuntime`] is added to the normal functions of the application. When the host asks it to
+//!   detach, the application restarts in DFU mode.
+//! - [`DfuMode`] is the only function of the device in DFU mode. The host downloads the update into
+//!   the DFU partition, or uploads it back, and the update is marked to be swapped in by the
+//!   bootloader once complete.
+//!
+//! This is what `dfu-util` and other standard DFU hosts expect, so no custom tooling is needed:
+//!
+//! ```text
+//! dfu-util -d c0de:cafe -D firmware.bin
+//! ```
+//!
+//! Flash operations are done within the control requests, so the executor running the USB device
+//! blocks during them.
+
+use core::mem::MaybeUninit;
+
+use embassy_boot::FirmwareUpdater;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::control::{self, ControlHandler, InResponse, OutResponse, Request};
+use crate::driver::Driver;
+use crate::Builder;
+
+const USB_CLASS_APPN_SPEC: u8 = 0xfe;
+const APPN_SPEC_SUBCLASS_DFU: u8 = 0x01;
+const DFU_PROTOCOL_RT: u8 = 0x01;
+const DFU_PROTOCOL_DFU: u8 = 0x02;
+
+const DESC_DFU_FUNCTIONAL: u8 = 0x21;
+
+const REQ_DETACH: u8 = 0x00;
+const REQ_DNLOAD: u8 = 0x01;
+const REQ_UPLOAD: u8 = 0x02;
+const REQ_GETSTATUS: u8 = 0x03;
+const REQ_CLRSTATUS: u8 = 0x04;
+const REQ_GETSTATE: u8 = 0x05;
+const REQ_ABORT: u8 = 0x06;
+
+// The device can download and upload, and detaches by itself. It isn't manifestation tolerant:
+// the update only runs after a reset.
+const DFU_ATTRIBUTES: u8 = 0x0b;
+
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum DfuState {
+    AppIdle = 0,
+    AppDetach = 1,
+    DfuIdle = 2,
+    DnloadSync = 3,
+    DnloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    ManifestWaitReset = 8,
+    UploadIdle = 9,
+    Error = 10,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum DfuStatus {
+    Ok = 0x00,
+    ErrWrite = 0x03,
+    ErrAddress = 0x08,
+    ErrNotDone = 0x09,
+    ErrFirmware = 0x0a,
+    ErrStalledPkt = 0x0f,
+}
+
+/// Configuration of the DFU classes.
+pub struct Config {
+    /// Time in milliseconds the host waits for the device to restart in DFU mode after detaching.
+    pub detach_timeout: u16,
+    /// Size of the blocks of firmware transferred in each control request.
+    ///
+    /// It has to be a multiple of the erase size of the flash, and fit in the control buffer.
+    pub transfer_size: u16,
+}
+
+fn functional_descriptor(config: &Config) -> [u8; 7] {
+    [
+        DFU_ATTRIBUTES, // bmAttributes
+        config.detach_timeout as u8,
+        (config.detach_timeout >> 8) as u8, // wDetachTimeOut
+        config.transfer_size as u8,
+        (config.transfer_size >> 8) as u8, // wTransferSize
+        0x10,
+        0x01, // bcdDFUVersion (1.1)
+    ]
+}
+
+/// Response to DFU_GETSTATUS, with a poll timeout of 0: requests are completed by the time their
+/// status is asked.
+fn status(buf: &mut [u8], status: DfuStatus, state: DfuState) -> InResponse<'_> {
+    buf[..6].copy_from_slice(&[status as u8, 0x00, 0x00, 0x00, state as u8, 0x00]);
+    InResponse::Accepted(&buf[..6])
+}
+
+pub struct RuntimeState<'d> {
+    control: MaybeUninit<RuntimeControl<'d>>,
+    detach: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d> RuntimeState<'d> {
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            detach: Signal::new(),
+        }
+    }
+}
+
+struct RuntimeControl<'d> {
+    detach: &'d Signal<CriticalSectionRawMutex, ()>,
+    state: DfuState,
+}
+
+impl<'d> ControlHandler for RuntimeControl<'d> {
+    fn control_out(&mut self, req: control::Request, _data: &[u8]) -> OutResponse {
+        match req.request {
+            REQ_DETACH => {
+                debug!("dfu: detach");
+                self.state = DfuState::AppDetach;
+                self.detach.signal(());
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        match req.request {
+            REQ_GETSTATUS => status(buf, DfuStatus::Ok, self.state),
+            REQ_GETSTATE => {
+                buf[0] = self.state as u8;
+                InResponse::Accepted(&buf[..1])
+            }
+            _ => InResponse::Rejected,
+        }
+    }
+}
+
+/// DFU run-time interface, letting the host switch the device to DFU mode.
+pub struct DfuRuntime<'d> {
+    detach: &'d Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d> DfuRuntime<'d> {
+    /// Creates a new DfuRuntime.
+    pub fn new<D: Driver<'d>>(builder: &mut Builder<'d, D>, state: &'d mut RuntimeState<'d>, config: &Config) -> Self {
+        let control = state.control.write(RuntimeControl {
+            detach: &state.detach,
+            state: DfuState::AppIdle,
+        });
+
+        let mut func = builder.function(USB_CLASS_APPN_SPEC, APPN_SPEC_SUBCLASS_DFU, DFU_PROTOCOL_RT);
+        let mut iface = func.interface();
+        iface.handler(control);
+        let mut alt = iface.alt_setting(USB_CLASS_APPN_SPEC, APPN_SPEC_SUBCLASS_DFU, DFU_PROTOCOL_RT);
+        alt.descriptor(DESC_DFU_FUNCTIONAL, &functional_descriptor(config));
+
+        DfuRuntime { detach: &state.detach }
+    }
+
+    /// Waits for the host to ask the device to detach.
+    ///
+    /// The application should then restart in DFU mode within the detach timeout, for instance by
+    /// keeping a flag in memory retained across a reset.
+    pub async fn wait_detach(&self) {
+        self.detach.wait().await
+    }
+}
+
+pub struct State<'d, F: NorFlash> {
+    control: MaybeUninit<Control<'d, F>>,
+    manifested: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d, F: NorFlash> State<'d, F> {
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            manifested: Signal::new(),
+        }
+    }
+}
+
+struct Control<'d, F: NorFlash> {
+    flash: F,
+    updater: FirmwareUpdater,
+    buffer: &'d mut [u8],
+    transfer_size: usize,
+    state: DfuState,
+    status: DfuStatus,
+    manifested: &'d Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d, F: NorFlash> Control<'d, F> {
+    fn fail(&mut self, status: DfuStatus) {
+        self.state = DfuState::Error;
+        self.status = status;
+    }
+
+    /// Writes a block to the DFU partition.
+    fn download(&mut self, block: u16, data: &[u8]) -> Result<(), DfuStatus> {
+        let offset = block as usize * self.transfer_size;
+        // The last block is padded to whole flash pages.
+        let len = (data.len() + F::ERASE_SIZE - 1) / F::ERASE_SIZE * F::ERASE_SIZE;
+        if data.len() > self.transfer_size || offset + len > self.updater.firmware_len() {
+            warn!("dfu: block {} out of the DFU partition", block);
+            return Err(DfuStatus::ErrAddress);
+        }
+
+        self.buffer[..data.len()].copy_from_slice(data);
+        self.buffer[data.len()..len].fill(0xff);
+        self.updater
+            .write_firmware_blocking(offset, &self.buffer[..len], &mut self.flash, len)
+            .map_err(|_| DfuStatus::ErrWrite)
+    }
+}
+
+impl<'d, F: NorFlash> ControlHandler for Control<'d, F> {
+    fn reset(&mut self) {
+        if self.state != DfuState::ManifestWaitReset {
+            self.state = DfuState::DfuIdle;
+            self.status = DfuStatus::Ok;
+        }
+    }
+
+    fn control_out(&mut self, req: control::Request, data: &[u8]) -> OutResponse {
+        match (req.request, self.state) {
+            (REQ_DNLOAD, DfuState::DfuIdle | DfuState::DnloadIdle) if !data.is_empty() => {
+                match self.download(req.value, data) {
+                    Ok(()) => self.state = DfuState::DnloadSync,
+                    Err(status) => self.fail(status),
+                }
+                OutResponse::Accepted
+            }
+            (REQ_DNLOAD, DfuState::DnloadIdle) => {
+                // The end of the download, the update is marked when its status is asked.
+                self.state = DfuState::ManifestSync;
+                OutResponse::Accepted
+            }
+            (REQ_DNLOAD, _) => {
+                self.fail(DfuStatus::ErrNotDone);
+                OutResponse::Rejected
+            }
+            (REQ_CLRSTATUS, DfuState::Error) => {
+                self.state = DfuState::DfuIdle;
+                self.status = DfuStatus::Ok;
+                OutResponse::Accepted
+            }
+            (REQ_ABORT, DfuState::DfuIdle | DfuState::DnloadIdle | DfuState::UploadIdle) => {
+                self.state = DfuState::DfuIdle;
+                OutResponse::Accepted
+            }
+            _ => {
+                self.fail(DfuStatus::ErrStalledPkt);
+                OutResponse::Rejected
+            }
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        match (req.request, self.state) {
+            (REQ_GETSTATUS, DfuState::DnloadSync) => {
+                self.state = DfuState::DnloadIdle;
+                status(buf, self.status, self.state)
+            }
+            (REQ_GETSTATUS, DfuState::ManifestSync) => {
+                let aligned = &mut self.buffer[..F::WRITE_SIZE];
+                match self.updater.mark_updated_blocking(&mut self.flash, aligned) {
+                    Ok(()) => {
+                        debug!("dfu: update marked");
+                        self.state = DfuState::ManifestWaitReset;
+                        self.manifested.signal(());
+                        status(buf, self.status, DfuState::Manifest)
+                    }
+                    Err(_) => {
+                        self.fail(DfuStatus::ErrFirmware);
+                        status(buf, self.status, self.state)
+                    }
+                }
+            }
+            (REQ_GETSTATUS, _) => status(buf, self.status, self.state),
+            (REQ_GETSTATE, _) => {
+                buf[0] = self.state as u8;
+                InResponse::Accepted(&buf[..1])
+            }
+            (REQ_UPLOAD, DfuState::DfuIdle | DfuState::UploadIdle) => {
+                let offset = req.value as usize * self.transfer_size;
+                let len = (req.length as usize)
+                    .min(self.transfer_size)
+                    .min(buf.len())
+                    .min(self.updater.firmware_len().saturating_sub(offset));
+                if self
+                    .updater
+                    .read_dfu_blocking(offset, &mut buf[..len], &mut self.flash)
+                    .is_err()
+                {
+                    self.fail(DfuStatus::ErrFirmware);
+                    return InResponse::Rejected;
+                }
+                // A short block ends the upload.
+                self.state = match len < req.length as usize {
+                    true => DfuState::DfuIdle,
+                    false => DfuState::UploadIdle,
+                };
+                InResponse::Accepted(&buf[..len])
+            }
+            _ => {
+                self.fail(DfuStatus::ErrStalledPkt);
+                InResponse::Rejected
+            }
+        }
+    }
+}
+
+/// DFU mode interface, downloading updates to the DFU partition of embassy-boot.
+pub struct DfuMode<'d> {
+    manifested: &'d Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d> DfuMode<'d> {
+    /// Creates a new DfuMode writing to `flash` through `updater`.
+    ///
+    /// `buffer` holds a block while it is written, it must be at least `transfer_size` long.
+    pub fn new<D: Driver<'d>, F: NorFlash + 'd>(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut State<'d, F>,
+        flash: F,
+        updater: FirmwareUpdater,
+        buffer: &'d mut [u8],
+        config: &Config,
+    ) -> Self {
+        let transfer_size = config.transfer_size as usize;
+        assert!(transfer_size % F::ERASE_SIZE == 0);
+        assert!(transfer_size <= builder.control_buf_len());
+        assert!(buffer.len() >= transfer_size);
+
+        let control = state.control.write(Control {
+            flash,
+            updater,
+            buffer,
+            transfer_size,
+            state: DfuState::DfuIdle,
+            status: DfuStatus::Ok,
+            manifested: &state.manifested,
+        });
+
+        let mut func = builder.function(USB_CLASS_APPN_SPEC, APPN_SPEC_SUBCLASS_DFU, DFU_PROTOCOL_DFU);
+        let mut iface = func.interface();
+        iface.handler(control);
+        let mut alt = iface.alt_setting(USB_CLASS_APPN_SPEC, APPN_SPEC_SUBCLASS_DFU, DFU_PROTOCOL_DFU);
+        alt.descriptor(DESC_DFU_FUNCTIONAL, &functional_descriptor(config));
+
+        DfuMode {
+            manifested: &state.manifested,
+        }
+    }
+
+    /// Waits for a complete update to be downloaded and marked to be swapped in.
+    ///
+    /// The application should then reset, after a short delay for the host to get the last status,
+    /// so the bootloader runs the update.
+    pub async fn wait_manifested(&self) {
+        self.manifested.wait().await
+    }
+}
@@ -1,7 +1,11 @@
+use core::cell::RefCell;
 use core::mem::MaybeUninit;
 use core::ops::Range;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_sync::signal::Signal;
 #[cfg(feature = "usbd-hid")]
 use ssmarshal::serialize;
 #[cfg(feature = "usbd-hid")]
@@ -63,6 +67,12 @@ impl ReportId {
             _ => Err(()),
         }
     }
+
+    fn id(&self) -> u8 {
+        match *self {
+            ReportId::In(id) | ReportId::Out(id) | ReportId::Feature(id) => id,
+        }
+    }
 }
 
 pub struct State<'d> {
@@ -172,6 +182,13 @@ impl<'d, D: Driver<'d>, const READ_N: usize, const WRITE_N: usize> HidReaderWrit
         self.writer.write(report).await
     }
 
+    /// Writes the input report with ID `id` to its interrupt endpoint.
+    ///
+    /// See [`HidWriter::write_report`].
+    pub async fn write_report(&mut self, id: u8, data: &[u8]) -> Result<(), EndpointError> {
+        self.writer.write_report(id, data).await
+    }
+
     /// Reads an output report from the Interrupt Out pipe.
     ///
     /// See [`HidReader::read`].
@@ -257,6 +274,19 @@ impl<'d, D: Driver<'d>, const N: usize> HidWriter<'d, D, N> {
 
         Ok(())
     }
+
+    /// Writes the input report with ID `id` to its interrupt endpoint.
+    ///
+    /// `data` is the report without its ID, which is prepended to it. Use this
+    /// when the report descriptor declares several reports with their own IDs.
+    pub async fn write_report(&mut self, id: u8, data: &[u8]) -> Result<(), EndpointError> {
+        assert!(data.len() < N);
+
+        let mut buf: [u8; N] = [0; N];
+        buf[0] = id;
+        buf[1..data.len() + 1].copy_from_slice(data);
+        self.write(&buf[..data.len() + 1]).await
+    }
 }
 
 impl<'d, D: Driver<'d>, const N: usize> HidReader<'d, D, N> {
@@ -378,6 +408,139 @@ pub trait RequestHandler {
     }
 }
 
+/// The value of one report, shared between the application and the HID interface.
+///
+/// Reports sent by the host, over the control pipe or the Interrupt Out pipe,
+/// are stored here and wake up [`Report::wait`]. The stored value is also
+/// what the host reads with a GET_REPORT request. Reports are routed to the
+/// matching `Report` by a [`ReportRouter`].
+///
+/// Values are stored without their report ID and are at most `N` bytes long.
+pub struct Report<const N: usize> {
+    id: ReportId,
+    value: CriticalSectionMutex<RefCell<([u8; N], usize)>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<const N: usize> Report<N> {
+    /// Creates a new, empty report.
+    pub const fn new(id: ReportId) -> Self {
+        Self {
+            id,
+            value: CriticalSectionMutex::new(RefCell::new(([0; N], 0))),
+            changed: Signal::new(),
+        }
+    }
+
+    /// Returns the ID of this report.
+    pub fn id(&self) -> ReportId {
+        self.id
+    }
+
+    /// Sets the value of this report, as returned to the host by GET_REPORT.
+    ///
+    /// This does not wake up [`Report::wait`], which is only woken by the host.
+    pub fn set(&self, data: &[u8]) {
+        assert!(data.len() <= N);
+        self.value.lock(|v| {
+            let mut v = v.borrow_mut();
+            v.0[..data.len()].copy_from_slice(data);
+            v.1 = data.len();
+        });
+    }
+
+    /// Copies the value of this report into `buf`, returning its length.
+    pub fn get(&self, buf: &mut [u8]) -> usize {
+        self.value.lock(|v| {
+            let v = v.borrow();
+            buf[..v.1].copy_from_slice(&v.0[..v.1]);
+            v.1
+        })
+    }
+
+    /// Waits for the host to set this report, then copies its value into
+    /// `buf`, returning its length.
+    ///
+    /// If the host set the report several times since the last call, only
+    /// the latest value is returned.
+    pub async fn wait(&self, buf: &mut [u8]) -> usize {
+        self.changed.wait().await;
+        self.get(buf)
+    }
+
+    fn host_set(&self, data: &[u8]) -> OutResponse {
+        // With report IDs, the report data is prefixed by its ID.
+        let data = match (self.id.id(), data.split_first()) {
+            (0, _) => data,
+            (id, Some((&first, rest))) if first == id => rest,
+            _ => return OutResponse::Rejected,
+        };
+        if data.len() > N {
+            return OutResponse::Rejected;
+        }
+
+        self.set(data);
+        self.changed.signal(());
+        OutResponse::Accepted
+    }
+
+    fn host_get(&self, buf: &mut [u8]) -> Option<usize> {
+        let id = self.id.id();
+        let prefix = usize::from(id != 0);
+        self.value.lock(|v| {
+            let v = v.borrow();
+            if prefix + v.1 > buf.len() {
+                return None;
+            }
+            if id != 0 {
+                buf[0] = id;
+            }
+            buf[prefix..prefix + v.1].copy_from_slice(&v.0[..v.1]);
+            Some(prefix + v.1)
+        })
+    }
+}
+
+/// A [`RequestHandler`] routing reports to a set of [`Report`]s by their ID.
+///
+/// This lets a single interface carry several reports, for example those of
+/// a keyboard, a mouse and a vendor-defined feature report, each handled by
+/// its own task. Use it as the `request_handler` in [`Config`] for reports
+/// sent over the control pipe, and pass it to [`HidReader::run`] for output
+/// reports sent over the Interrupt Out pipe.
+///
+/// Reports the host sends which have no matching [`Report`] are rejected.
+pub struct ReportRouter<'d, const N: usize> {
+    reports: &'d [Report<N>],
+}
+
+impl<'d, const N: usize> ReportRouter<'d, N> {
+    /// Creates a new router for `reports`.
+    pub const fn new(reports: &'d [Report<N>]) -> Self {
+        Self { reports }
+    }
+
+    fn find(&self, id: ReportId) -> Option<&'d Report<N>> {
+        self.reports.iter().find(|r| r.id == id)
+    }
+}
+
+impl<'d, const N: usize> RequestHandler for ReportRouter<'d, N> {
+    fn get_report(&self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        self.find(id).and_then(|r| r.host_get(buf))
+    }
+
+    fn set_report(&self, id: ReportId, data: &[u8]) -> OutResponse {
+        match self.find(id) {
+            Some(r) => r.host_set(data),
+            None => {
+                debug!("HID: no report for {:?}", id);
+                OutResponse::Rejected
+            }
+        }
+    }
+}
+
 struct Control<'d> {
     report_descriptor: &'d [u8],
     request_handler: Option<&'d dyn RequestHandler>,
@@ -502,3 +665,263 @@ impl<'d> ControlHandler for Control<'d> {
         }
     }
 }
+
+/// HID usage page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UsagePage(pub u16);
+
+impl UsagePage {
+    pub const GENERIC_DESKTOP: Self = Self(0x01);
+    pub const SIMULATION: Self = Self(0x02);
+    pub const KEYBOARD: Self = Self(0x07);
+    pub const LED: Self = Self(0x08);
+    pub const BUTTON: Self = Self(0x09);
+    pub const CONSUMER: Self = Self(0x0c);
+    pub const DIGITIZER: Self = Self(0x0d);
+
+    /// Vendor-defined usage page `page` (0xFF00 to 0xFFFF).
+    pub const fn vendor(page: u8) -> Self {
+        Self(0xff00 | page as u16)
+    }
+}
+
+/// HID collection type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Collection {
+    Physical = 0x00,
+    Application = 0x01,
+    Logical = 0x02,
+    Report = 0x03,
+    NamedArray = 0x04,
+    UsageSwitch = 0x05,
+    UsageModifier = 0x06,
+}
+
+/// Flags of an Input, Output or Feature item.
+///
+/// The default, `ItemFlags::DATA`, is data, array, absolute. Combine flags
+/// with [`ItemFlags::with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ItemFlags(pub u16);
+
+impl ItemFlags {
+    pub const DATA: Self = Self(0x000);
+    pub const CONSTANT: Self = Self(0x001);
+    pub const VARIABLE: Self = Self(0x002);
+    pub const RELATIVE: Self = Self(0x004);
+    pub const WRAP: Self = Self(0x008);
+    pub const NON_LINEAR: Self = Self(0x010);
+    pub const NO_PREFERRED_STATE: Self = Self(0x020);
+    pub const NULL_STATE: Self = Self(0x040);
+    /// Only valid for Output and Feature items.
+    pub const VOLATILE: Self = Self(0x080);
+    pub const BUFFERED_BYTES: Self = Self(0x100);
+
+    /// Returns the union of `self` and `other`.
+    pub const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+// Short item prefixes, without the size bits.
+const ITEM_INPUT: u8 = 0x80;
+const ITEM_OUTPUT: u8 = 0x90;
+const ITEM_FEATURE: u8 = 0xb0;
+const ITEM_COLLECTION: u8 = 0xa0;
+const ITEM_END_COLLECTION: u8 = 0xc0;
+const ITEM_USAGE_PAGE: u8 = 0x04;
+const ITEM_LOGICAL_MINIMUM: u8 = 0x14;
+const ITEM_LOGICAL_MAXIMUM: u8 = 0x24;
+const ITEM_PHYSICAL_MINIMUM: u8 = 0x34;
+const ITEM_PHYSICAL_MAXIMUM: u8 = 0x44;
+const ITEM_UNIT_EXPONENT: u8 = 0x54;
+const ITEM_UNIT: u8 = 0x64;
+const ITEM_REPORT_SIZE: u8 = 0x74;
+const ITEM_REPORT_ID: u8 = 0x84;
+const ITEM_REPORT_COUNT: u8 = 0x94;
+const ITEM_USAGE: u8 = 0x08;
+const ITEM_USAGE_MINIMUM: u8 = 0x18;
+const ITEM_USAGE_MAXIMUM: u8 = 0x28;
+
+/// Builder for HID report descriptors of up to `N` bytes.
+///
+/// All methods are `const`, so descriptors can be built at compile time:
+///
+/// ```ignore
+/// static MOUSE: ReportDescriptorBuilder<64> = ReportDescriptorBuilder::new()
+///     .usage_page(UsagePage::GENERIC_DESKTOP)
+///     .usage(0x02) // Mouse
+///     .collection(Collection::Application)
+///     .report_id(1)
+///     .usage_page(UsagePage::BUTTON)
+///     .usage_minimum(1)
+///     .usage_maximum(3)
+///     .logical_minimum(0)
+///     .logical_maximum(1)
+///     .report_size(1)
+///     .report_count(3)
+///     .input(ItemFlags::DATA.with(ItemFlags::VARIABLE))
+///     .report_count(5)
+///     .input(ItemFlags::CONSTANT)
+///     .end_collection();
+/// ```
+///
+/// Values are encoded in the shortest form that holds them. Running out of
+/// space panics, which fails the build when used in a `const` or `static`.
+pub struct ReportDescriptorBuilder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> ReportDescriptorBuilder<N> {
+    /// Creates an empty report descriptor.
+    pub const fn new() -> Self {
+        Self { buf: [0; N], len: 0 }
+    }
+
+    /// Returns the report descriptor built so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    const fn item(mut self, prefix: u8, data: u32, size: usize) -> Self {
+        if self.len + 1 + size > N {
+            panic!("HID report descriptor buffer too small");
+        }
+        // Size code 3 means 4 bytes.
+        let size_code = if size == 4 { 3 } else { size as u8 };
+        self.buf[self.len] = prefix | size_code;
+        let mut i = 0;
+        while i < size {
+            self.buf[self.len + 1 + i] = (data >> (8 * i)) as u8;
+            i += 1;
+        }
+        self.len += 1 + size;
+        self
+    }
+
+    const fn unsigned(self, prefix: u8, data: u32) -> Self {
+        let size = if data <= 0xff {
+            1
+        } else if data <= 0xffff {
+            2
+        } else {
+            4
+        };
+        self.item(prefix, data, size)
+    }
+
+    const fn signed(self, prefix: u8, data: i32) -> Self {
+        let size = if data >= i8::MIN as i32 && data <= i8::MAX as i32 {
+            1
+        } else if data >= i16::MIN as i32 && data <= i16::MAX as i32 {
+            2
+        } else {
+            4
+        };
+        self.item(prefix, data as u32, size)
+    }
+
+    const fn main(self, prefix: u8, flags: ItemFlags) -> Self {
+        self.unsigned(prefix, flags.0 as u32)
+    }
+
+    /// Adds an Input item.
+    pub const fn input(self, flags: ItemFlags) -> Self {
+        self.main(ITEM_INPUT, flags)
+    }
+
+    /// Adds an Output item.
+    pub const fn output(self, flags: ItemFlags) -> Self {
+        self.main(ITEM_OUTPUT, flags)
+    }
+
+    /// Adds a Feature item.
+    pub const fn feature(self, flags: ItemFlags) -> Self {
+        self.main(ITEM_FEATURE, flags)
+    }
+
+    /// Opens a collection, closed by [`end_collection`](Self::end_collection).
+    pub const fn collection(self, collection: Collection) -> Self {
+        self.item(ITEM_COLLECTION, collection as u32, 1)
+    }
+
+    /// Closes the innermost open collection.
+    pub const fn end_collection(self) -> Self {
+        self.item(ITEM_END_COLLECTION, 0, 0)
+    }
+
+    /// Sets the usage page of the following items.
+    pub const fn usage_page(self, page: UsagePage) -> Self {
+        self.unsigned(ITEM_USAGE_PAGE, page.0 as u32)
+    }
+
+    /// Sets the logical minimum of the following items.
+    pub const fn logical_minimum(self, min: i32) -> Self {
+        self.signed(ITEM_LOGICAL_MINIMUM, min)
+    }
+
+    /// Sets the logical maximum of the following items.
+    pub const fn logical_maximum(self, max: i32) -> Self {
+        self.signed(ITEM_LOGICAL_MAXIMUM, max)
+    }
+
+    /// Sets the physical minimum of the following items.
+    pub const fn physical_minimum(self, min: i32) -> Self {
+        self.signed(ITEM_PHYSICAL_MINIMUM, min)
+    }
+
+    /// Sets the physical maximum of the following items.
+    pub const fn physical_maximum(self, max: i32) -> Self {
+        self.signed(ITEM_PHYSICAL_MAXIMUM, max)
+    }
+
+    /// Sets the unit exponent of the following items, from -8 to 7.
+    pub const fn unit_exponent(self, exponent: i8) -> Self {
+        self.item(ITEM_UNIT_EXPONENT, (exponent as u32) & 0x0f, 1)
+    }
+
+    /// Sets the unit of the following items, as defined by the HID specification.
+    pub const fn unit(self, unit: u32) -> Self {
+        self.unsigned(ITEM_UNIT, unit)
+    }
+
+    /// Sets the size in bits of each field of the following items.
+    pub const fn report_size(self, bits: u32) -> Self {
+        self.unsigned(ITEM_REPORT_SIZE, bits)
+    }
+
+    /// Sets the ID of the report the following items belong to.
+    ///
+    /// ID 0 is reserved, and once used, all items must belong to a report with an ID.
+    pub const fn report_id(self, id: u8) -> Self {
+        if id == 0 {
+            panic!("HID report ID 0 is reserved");
+        }
+        self.item(ITEM_REPORT_ID, id as u32, 1)
+    }
+
+    /// Sets the number of fields of the following items.
+    pub const fn report_count(self, count: u32) -> Self {
+        self.unsigned(ITEM_REPORT_COUNT, count)
+    }
+
+    /// Adds a usage on the current usage page.
+    pub const fn usage(self, usage: u16) -> Self {
+        self.unsigned(ITEM_USAGE, usage as u32)
+    }
+
+    /// Starts a range of usages on the current usage page.
+    pub const fn usage_minimum(self, usage: u16) -> Self {
+        self.unsigned(ITEM_USAGE_MINIMUM, usage as u32)
+    }
+
+    /// Ends a range of usages on the current usage page.
+    pub const fn usage_maximum(self, usage: u16) -> Self {
+        self.unsigned(ITEM_USAGE_MAXIMUM, usage as u32)
+    }
+}
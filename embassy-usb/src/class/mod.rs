@@ -1,3 +1,15 @@
+pub mod audio;
+pub mod billboard;
+pub mod ccid;
 pub mod cdc_acm;
 pub mod cdc_ncm;
+#[cfg(feature = "dfu")]
+pub mod dfu;
 pub mod hid;
+#[cfg(feature = "msc")]
+pub mod msc;
+pub mod mtp;
+pub mod printer;
+pub mod rndis;
+pub mod vendor;
+pub mod web_usb;
@@ -0,0 +1,483 @@
+//! USB Mass Storage Class, with the Bulk-Only Transport and the SCSI transparent command set.
+//!
+//! [`MscClass`] exposes a [`BlockDevice`], such as an SD card or a region of internal flash, as a
+//! USB drive. The host formats and mounts it: the device must not access the blocks while the
+//! host has the drive mounted, as neither side sees the caches of the other.
+
+use core::future::pending;
+use core::mem::{self, MaybeUninit};
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub use embassy_embedded_hal::block_device::{Block, BlockDevice, BLOCK_SIZE};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use crate::control::{self, ControlHandler, InResponse, OutResponse, Request};
+use crate::driver::{Driver, Endpoint, EndpointAddress, EndpointError, EndpointIn, EndpointOut};
+use crate::Builder;
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_CLASS_MSC: u8 = 0x08;
+
+const MSC_SUBCLASS_SCSI: u8 = 0x06;
+const MSC_PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+const REQ_GET_MAX_LUN: u8 = 0xfe;
+const REQ_BULK_ONLY_RESET: u8 = 0xff;
+
+const CBW_SIGNATURE: u32 = 0x43425355;
+const CBW_LEN: usize = 31;
+const CSW_SIGNATURE: u32 = 0x53425355;
+const CSW_LEN: usize = 13;
+
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_INQUIRY: u8 = 0x12;
+const SCSI_MODE_SENSE_6: u8 = 0x1a;
+const SCSI_START_STOP_UNIT: u8 = 0x1b;
+const SCSI_PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const SCSI_READ_FORMAT_CAPACITIES: u8 = 0x23;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2a;
+const SCSI_VERIFY_10: u8 = 0x2f;
+const SCSI_SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const SCSI_MODE_SENSE_10: u8 = 0x5a;
+
+const SENSE_KEY_MEDIUM_ERROR: u8 = 0x03;
+const SENSE_KEY_ILLEGAL_REQUEST: u8 = 0x05;
+const SENSE_KEY_DATA_PROTECT: u8 = 0x07;
+
+const ASC_WRITE_ERROR: u8 = 0x0c;
+const ASC_UNRECOVERED_READ_ERROR: u8 = 0x11;
+const ASC_INVALID_COMMAND: u8 = 0x20;
+const ASC_LBA_OUT_OF_RANGE: u8 = 0x21;
+const ASC_INVALID_FIELD_IN_CDB: u8 = 0x24;
+const ASC_WRITE_PROTECTED: u8 = 0x27;
+
+/// Configuration of the [`MscClass`].
+pub struct Config<'d> {
+    /// Vendor identification reported to the host, up to 8 ASCII characters.
+    pub vendor: &'d str,
+    /// Product identification, up to 16 ASCII characters.
+    pub product: &'d str,
+    /// Product revision, up to 4 ASCII characters.
+    pub revision: &'d str,
+    /// Whether the medium is reported as removable, as for a card reader.
+    pub removable: bool,
+    /// Whether the host is refused writes.
+    pub write_protected: bool,
+    /// Max packet size for both bulk endpoints.
+    pub max_packet_size: u16,
+}
+
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    reset: Signal<CriticalSectionRawMutex, ()>,
+    halted: AtomicBool,
+}
+
+impl<'d> State<'d> {
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            reset: Signal::new(),
+            halted: AtomicBool::new(false),
+        }
+    }
+}
+
+struct Control<'d> {
+    reset: &'d Signal<CriticalSectionRawMutex, ()>,
+    halted: &'d AtomicBool,
+}
+
+impl<'d> ControlHandler for Control<'d> {
+    fn reset(&mut self) {
+        self.halted.store(false, Ordering::Relaxed);
+        self.reset.signal(());
+    }
+
+    fn clear_halt(&mut self, _ep_addr: EndpointAddress) -> bool {
+        // After an invalid command, the endpoints stay halted until the bulk-only reset.
+        !self.halted.load(Ordering::Relaxed)
+    }
+
+    fn control_out(&mut self, req: control::Request, _data: &[u8]) -> OutResponse {
+        match req.request {
+            REQ_BULK_ONLY_RESET => {
+                debug!("msc: bulk-only reset");
+                self.halted.store(false, Ordering::Relaxed);
+                self.reset.signal(());
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        match req.request {
+            REQ_GET_MAX_LUN if req.length >= 1 => {
+                // A single logical unit, number 0.
+                buf[0] = 0;
+                InResponse::Accepted(&buf[..1])
+            }
+            _ => InResponse::Rejected,
+        }
+    }
+}
+
+/// Command Block Wrapper, which starts each command.
+struct Cbw {
+    tag: u32,
+    data_len: u32,
+    data_in: bool,
+    cb: [u8; 16],
+}
+
+impl Cbw {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() != CBW_LEN || u32::from_le_bytes(buf[0..4].try_into().unwrap()) != CBW_SIGNATURE {
+            return None;
+        }
+        let mut cb = [0; 16];
+        cb.copy_from_slice(&buf[15..31]);
+        Some(Self {
+            tag: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            data_len: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            data_in: buf[12] & 0x80 != 0,
+            cb,
+        })
+    }
+}
+
+/// Status reported in the Command Status Wrapper.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Passed = 0,
+    Failed = 1,
+    PhaseError = 2,
+}
+
+/// Sense data of the last failed command, reported by REQUEST SENSE.
+#[derive(Copy, Clone, Default)]
+struct Sense {
+    key: u8,
+    asc: u8,
+}
+
+/// Mass storage device exposing a [`BlockDevice`] to the host.
+pub struct MscClass<'d, D: Driver<'d>, B: BlockDevice> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    reset: &'d Signal<CriticalSectionRawMutex, ()>,
+    halted: &'d AtomicBool,
+    device: B,
+    inquiry: [u8; 36],
+    write_protected: bool,
+    sense: Sense,
+    block: Block,
+}
+
+impl<'d, D: Driver<'d>, B: BlockDevice> MscClass<'d, D, B> {
+    /// Creates a new MscClass exposing `device`. For full-speed devices, `max_packet_size` has
+    /// to be one of 8, 16, 32 or 64.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, device: B, config: Config<'d>) -> Self {
+        let control = state.control.write(Control {
+            reset: &state.reset,
+            halted: &state.halted,
+        });
+
+        let mut func = builder.function(USB_CLASS_MSC, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BULK_ONLY);
+        let mut iface = func.interface();
+        iface.handler(control);
+        let mut alt = iface.alt_setting(USB_CLASS_MSC, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BULK_ONLY);
+        let read_ep = alt.endpoint_bulk_out(config.max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(config.max_packet_size);
+
+        // Standard INQUIRY data: a direct-access block device, SPC-2, padded identification.
+        let mut inquiry = [b' '; 36];
+        inquiry[0..8].copy_from_slice(&[
+            0x00,
+            if config.removable { 0x80 } else { 0x00 },
+            0x04,
+            0x02,
+            36 - 5,
+            0x00,
+            0x00,
+            0x00,
+        ]);
+        for (dest, s) in [
+            (8..16, config.vendor),
+            (16..32, config.product),
+            (32..36, config.revision),
+        ] {
+            let len = s.len().min(dest.len());
+            inquiry[dest][..len].copy_from_slice(&s.as_bytes()[..len]);
+        }
+
+        MscClass {
+            read_ep,
+            write_ep,
+            reset: &state.reset,
+            halted: &state.halted,
+            device,
+            inquiry,
+            write_protected: config.write_protected,
+            sense: Sense::default(),
+            block: [0; BLOCK_SIZE],
+        }
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        // The size is the same for both endpoints.
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+
+    /// Handles the commands of the host.
+    pub async fn run(&mut self) -> ! {
+        let reset = self.reset;
+        loop {
+            self.wait_connection().await;
+            reset.reset();
+            loop {
+                match select(self.handle_command(), reset.wait()).await {
+                    Either::First(Ok(())) => {}
+                    Either::First(Err(EndpointError::BufferOverflow)) => warn!("msc: host sent a packet too large"),
+                    Either::First(Err(EndpointError::Disabled)) => break,
+                    // Abandon the current command, the next packet is a new one.
+                    Either::Second(()) => self.sense = Sense::default(),
+                }
+            }
+        }
+    }
+
+    async fn handle_command(&mut self) -> Result<(), EndpointError> {
+        let mps = self.max_packet_size() as usize;
+
+        // The wrapper spans several packets when they are smaller than it, and ends with a short one.
+        let mut n = 0;
+        while n < CBW_LEN {
+            let len = self.read_ep.read(&mut self.block[n..][..mps]).await?;
+            n += len;
+            if len < mps {
+                break;
+            }
+        }
+        let cbw = match Cbw::parse(&self.block[..n]) {
+            Some(cbw) => cbw,
+            None => {
+                // The host recovers by resetting the interface and clearing the halts.
+                warn!("msc: invalid command block wrapper, halting until reset");
+                self.halted.store(true, Ordering::Relaxed);
+                self.read_ep.set_stall();
+                self.write_ep.set_stall();
+                pending::<()>().await;
+                return Ok(());
+            }
+        };
+        trace!("msc: command {:x}", cbw.cb[0]);
+
+        let mut residue = cbw.data_len;
+        let status = self.execute(&cbw, &mut residue).await?;
+
+        // End the data stage of the data the command didn't transfer.
+        if residue > 0 {
+            if cbw.data_in {
+                if (cbw.data_len - residue) as usize % mps == 0 {
+                    self.write_ep.write(&[]).await?;
+                }
+            } else {
+                while residue > 0 {
+                    let n = self.read_ep.read(&mut self.block[..mps]).await?;
+                    residue -= (n as u32).min(residue);
+                    if n < mps {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut csw = [0; CSW_LEN];
+        csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+        csw[4..8].copy_from_slice(&cbw.tag.to_le_bytes());
+        csw[8..12].copy_from_slice(&residue.to_le_bytes());
+        csw[12] = status as u8;
+        self.write_ep.write(&csw).await
+    }
+
+    async fn execute(&mut self, cbw: &Cbw, residue: &mut u32) -> Result<Status, EndpointError> {
+        let cb = &cbw.cb;
+        match cb[0] {
+            SCSI_TEST_UNIT_READY
+            | SCSI_PREVENT_ALLOW_MEDIUM_REMOVAL
+            | SCSI_START_STOP_UNIT
+            | SCSI_VERIFY_10
+            | SCSI_SYNCHRONIZE_CACHE_10 => Ok(Status::Passed),
+            SCSI_REQUEST_SENSE => {
+                let sense = mem::take(&mut self.sense);
+                let buf = &mut self.block[..18];
+                buf.fill(0);
+                // Current error, fixed format.
+                buf[0] = 0x70;
+                buf[2] = sense.key;
+                buf[7] = 10;
+                buf[12] = sense.asc;
+                self.respond(cbw, 18, cb[4] as usize, residue).await
+            }
+            SCSI_INQUIRY => {
+                if cb[1] & 0x01 != 0 {
+                    // Vital product data isn't supported.
+                    return Ok(self.fail(SENSE_KEY_ILLEGAL_REQUEST, ASC_INVALID_FIELD_IN_CDB));
+                }
+                self.block[..36].copy_from_slice(&self.inquiry);
+                let alloc_len = u16::from_be_bytes([cb[3], cb[4]]) as usize;
+                self.respond(cbw, 36, alloc_len, residue).await
+            }
+            SCSI_MODE_SENSE_6 => {
+                // Header only: no block descriptors nor mode pages.
+                let protect = if self.write_protected { 0x80 } else { 0x00 };
+                self.block[..4].copy_from_slice(&[3, 0, protect, 0]);
+                self.respond(cbw, 4, cb[4] as usize, residue).await
+            }
+            SCSI_MODE_SENSE_10 => {
+                let protect = if self.write_protected { 0x80 } else { 0x00 };
+                self.block[..8].copy_from_slice(&[0, 6, 0, protect, 0, 0, 0, 0]);
+                let alloc_len = u16::from_be_bytes([cb[7], cb[8]]) as usize;
+                self.respond(cbw, 8, alloc_len, residue).await
+            }
+            SCSI_READ_CAPACITY_10 => {
+                let last_block = self.device.block_count().saturating_sub(1);
+                self.block[..4].copy_from_slice(&last_block.to_be_bytes());
+                self.block[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                self.respond(cbw, 8, 8, residue).await
+            }
+            SCSI_READ_FORMAT_CAPACITIES => {
+                // A single descriptor, of the current formatted capacity.
+                let block_size = (BLOCK_SIZE as u32).to_be_bytes();
+                self.block[..4].copy_from_slice(&[0, 0, 0, 8]);
+                self.block[4..8].copy_from_slice(&self.device.block_count().to_be_bytes());
+                self.block[8..12].copy_from_slice(&[0x02, block_size[1], block_size[2], block_size[3]]);
+                let alloc_len = u16::from_be_bytes([cb[7], cb[8]]) as usize;
+                self.respond(cbw, 12, alloc_len, residue).await
+            }
+            SCSI_READ_10 => {
+                let (address, count) = read_write_10(cb);
+                if count > 0 && !cbw.data_in {
+                    return Ok(Status::PhaseError);
+                }
+                if !self.in_range(address, count) {
+                    return Ok(self.fail(SENSE_KEY_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE));
+                }
+                for address in address..address + count {
+                    if *residue < BLOCK_SIZE as u32 {
+                        return Ok(Status::PhaseError);
+                    }
+                    if self
+                        .device
+                        .read(address, slice::from_mut(&mut self.block))
+                        .await
+                        .is_err()
+                    {
+                        warn!("msc: failed to read block {}", address);
+                        return Ok(self.fail(SENSE_KEY_MEDIUM_ERROR, ASC_UNRECOVERED_READ_ERROR));
+                    }
+                    write_data(&mut self.write_ep, &self.block, residue).await?;
+                }
+                Ok(Status::Passed)
+            }
+            SCSI_WRITE_10 => {
+                let (address, count) = read_write_10(cb);
+                if count > 0 && cbw.data_in {
+                    return Ok(Status::PhaseError);
+                }
+                if self.write_protected {
+                    return Ok(self.fail(SENSE_KEY_DATA_PROTECT, ASC_WRITE_PROTECTED));
+                }
+                if !self.in_range(address, count) {
+                    return Ok(self.fail(SENSE_KEY_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE));
+                }
+                for address in address..address + count {
+                    if *residue < BLOCK_SIZE as u32 {
+                        return Ok(Status::PhaseError);
+                    }
+                    if read_data(&mut self.read_ep, &mut self.block, residue).await? < BLOCK_SIZE {
+                        return Ok(Status::PhaseError);
+                    }
+                    if self.device.write(address, slice::from_ref(&self.block)).await.is_err() {
+                        warn!("msc: failed to write block {}", address);
+                        return Ok(self.fail(SENSE_KEY_MEDIUM_ERROR, ASC_WRITE_ERROR));
+                    }
+                }
+                Ok(Status::Passed)
+            }
+            _ => Ok(self.fail(SENSE_KEY_ILLEGAL_REQUEST, ASC_INVALID_COMMAND)),
+        }
+    }
+
+    /// Send the first `len` bytes of the block buffer, truncated to the allocation length of the
+    /// command.
+    async fn respond(
+        &mut self,
+        cbw: &Cbw,
+        len: usize,
+        alloc_len: usize,
+        residue: &mut u32,
+    ) -> Result<Status, EndpointError> {
+        if !cbw.data_in {
+            return Ok(Status::PhaseError);
+        }
+        write_data(&mut self.write_ep, &self.block[..len.min(alloc_len)], residue).await?;
+        Ok(Status::Passed)
+    }
+
+    fn in_range(&self, address: u32, count: u32) -> bool {
+        address as u64 + count as u64 <= self.device.block_count() as u64
+    }
+
+    fn fail(&mut self, key: u8, asc: u8) -> Status {
+        self.sense = Sense { key, asc };
+        Status::Failed
+    }
+}
+
+/// Logical block address and number of blocks of a READ(10) or WRITE(10) command.
+fn read_write_10(cb: &[u8; 16]) -> (u32, u32) {
+    let address = u32::from_be_bytes([cb[2], cb[3], cb[4], cb[5]]);
+    let count = u16::from_be_bytes([cb[7], cb[8]]) as u32;
+    (address, count)
+}
+
+/// Write `data` to the host, in packets, up to the remaining length of the data stage.
+async fn write_data<E: EndpointIn>(ep: &mut E, data: &[u8], residue: &mut u32) -> Result<(), EndpointError> {
+    let data = &data[..data.len().min(*residue as usize)];
+    for packet in data.chunks(ep.info().max_packet_size as usize) {
+        ep.write(packet).await?;
+    }
+    *residue -= data.len() as u32;
+    Ok(())
+}
+
+/// Fill `buf` with data from the host, stopping at a short packet. Returns the number of bytes
+/// read.
+async fn read_data<E: EndpointOut>(ep: &mut E, buf: &mut [u8], residue: &mut u32) -> Result<usize, EndpointError> {
+    let mps = ep.info().max_packet_size as usize;
+    let mut pos = 0;
+    while pos < buf.len() {
+        let n = ep.read(&mut buf[pos..][..mps]).await?;
+        pos += n;
+        if n < mps {
+            break;
+        }
+    }
+    *residue -= (pos as u32).min(*residue);
+    Ok(pos)
+}
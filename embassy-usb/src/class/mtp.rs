@@ -0,0 +1,1171 @@
+//! USB Media Transfer Protocol class.
+//!
+//! [`MtpClass`] exposes the objects of an [`ObjectStore`], files and folders, to the host, which
+//! browses them with its file manager. Unlike with mass storage, the host never accesses the
+//! filesystem itself, only whole objects through the device, so the device can keep using its
+//! files while connected.
+//!
+//! Only the operations needed to browse, read, create and delete objects are supported, on a
+//! single store.
+
+use core::future::Future;
+use core::mem::MaybeUninit;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use crate::control::{self, ControlHandler, InResponse, OutResponse, Request};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::Builder;
+
+const USB_CLASS_STILL_IMAGE: u8 = 0x06;
+const STILL_IMAGE_SUBCLASS: u8 = 0x01;
+const STILL_IMAGE_PROTOCOL_PIMA_15740: u8 = 0x01;
+
+const REQ_CANCEL: u8 = 0x64;
+const REQ_DEVICE_RESET: u8 = 0x66;
+const REQ_GET_DEVICE_STATUS: u8 = 0x67;
+
+const CONTAINER_HEADER_LEN: usize = 12;
+const CONTAINER_COMMAND: u16 = 1;
+const CONTAINER_DATA: u16 = 2;
+const CONTAINER_RESPONSE: u16 = 3;
+
+const OP_GET_DEVICE_INFO: u16 = 0x1001;
+const OP_OPEN_SESSION: u16 = 0x1002;
+const OP_CLOSE_SESSION: u16 = 0x1003;
+const OP_GET_STORAGE_IDS: u16 = 0x1004;
+const OP_GET_STORAGE_INFO: u16 = 0x1005;
+const OP_GET_NUM_OBJECTS: u16 = 0x1006;
+const OP_GET_OBJECT_HANDLES: u16 = 0x1007;
+const OP_GET_OBJECT_INFO: u16 = 0x1008;
+const OP_GET_OBJECT: u16 = 0x1009;
+const OP_DELETE_OBJECT: u16 = 0x100b;
+const OP_SEND_OBJECT_INFO: u16 = 0x100c;
+const OP_SEND_OBJECT: u16 = 0x100d;
+const OP_GET_OBJECT_PROPS_SUPPORTED: u16 = 0x9801;
+const OP_GET_OBJECT_PROP_DESC: u16 = 0x9802;
+const OP_GET_OBJECT_PROP_VALUE: u16 = 0x9803;
+
+const OPERATIONS: [u16; 15] = [
+    OP_GET_DEVICE_INFO,
+    OP_OPEN_SESSION,
+    OP_CLOSE_SESSION,
+    OP_GET_STORAGE_IDS,
+    OP_GET_STORAGE_INFO,
+    OP_GET_NUM_OBJECTS,
+    OP_GET_OBJECT_HANDLES,
+    OP_GET_OBJECT_INFO,
+    OP_GET_OBJECT,
+    OP_DELETE_OBJECT,
+    OP_SEND_OBJECT_INFO,
+    OP_SEND_OBJECT,
+    OP_GET_OBJECT_PROPS_SUPPORTED,
+    OP_GET_OBJECT_PROP_DESC,
+    OP_GET_OBJECT_PROP_VALUE,
+];
+
+const RESPONSE_OK: u16 = 0x2001;
+const RESPONSE_GENERAL_ERROR: u16 = 0x2002;
+const RESPONSE_SESSION_NOT_OPEN: u16 = 0x2003;
+const RESPONSE_OPERATION_NOT_SUPPORTED: u16 = 0x2005;
+const RESPONSE_PARAMETER_NOT_SUPPORTED: u16 = 0x2006;
+const RESPONSE_INCOMPLETE_TRANSFER: u16 = 0x2007;
+const RESPONSE_INVALID_STORAGE_ID: u16 = 0x2008;
+const RESPONSE_INVALID_OBJECT_HANDLE: u16 = 0x2009;
+const RESPONSE_STORE_FULL: u16 = 0x200c;
+const RESPONSE_OBJECT_WRITE_PROTECTED: u16 = 0x200d;
+const RESPONSE_STORE_READ_ONLY: u16 = 0x200e;
+const RESPONSE_ACCESS_DENIED: u16 = 0x200f;
+const RESPONSE_SPECIFICATION_BY_FORMAT_UNSUPPORTED: u16 = 0x2014;
+const RESPONSE_NO_VALID_OBJECT_INFO: u16 = 0x2015;
+const RESPONSE_INVALID_PARENT_OBJECT: u16 = 0x201a;
+const RESPONSE_INVALID_PARAMETER: u16 = 0x201d;
+const RESPONSE_SESSION_ALREADY_OPEN: u16 = 0x201e;
+const RESPONSE_INVALID_DATASET: u16 = 0x2023;
+const RESPONSE_INVALID_OBJECT_PROP_CODE: u16 = 0xa801;
+
+const PROP_STORAGE_ID: u16 = 0xdc01;
+const PROP_OBJECT_FORMAT: u16 = 0xdc02;
+const PROP_PROTECTION_STATUS: u16 = 0xdc03;
+const PROP_OBJECT_SIZE: u16 = 0xdc04;
+const PROP_OBJECT_FILE_NAME: u16 = 0xdc07;
+const PROP_PARENT_OBJECT: u16 = 0xdc0b;
+const PROP_PERSISTENT_UID: u16 = 0xdc41;
+const PROP_NAME: u16 = 0xdc44;
+
+const PROPS: [u16; 8] = [
+    PROP_STORAGE_ID,
+    PROP_OBJECT_FORMAT,
+    PROP_PROTECTION_STATUS,
+    PROP_OBJECT_SIZE,
+    PROP_OBJECT_FILE_NAME,
+    PROP_PARENT_OBJECT,
+    PROP_PERSISTENT_UID,
+    PROP_NAME,
+];
+
+const TYPE_UINT16: u16 = 0x0004;
+const TYPE_UINT32: u16 = 0x0006;
+const TYPE_UINT64: u16 = 0x0008;
+const TYPE_UINT128: u16 = 0x000a;
+const TYPE_STR: u16 = 0xffff;
+
+const ACCESS_READ_WRITE: u16 = 0x0000;
+const ACCESS_READ_ONLY: u16 = 0x0001;
+
+const ASSOCIATION_UNDEFINED: u16 = 0x0000;
+const ASSOCIATION_GENERIC_FOLDER: u16 = 0x0001;
+
+/// ID of the only store.
+const STORAGE_ID: u32 = 0x0001_0001;
+/// Object handle parameter meaning "the root", or "all objects" for some operations.
+const HANDLE_ALL: u32 = 0xffff_ffff;
+
+/// Size of the transfer buffer, a multiple of all the valid bulk max packet sizes.
+const BUF_LEN: usize = 512;
+
+/// Maximum length in bytes of the name of an object created by the host, encoded in UTF-8.
+pub const MAX_NAME_LEN: usize = 128;
+
+/// Object format codes.
+pub mod object_format {
+    /// A file of unknown format.
+    pub const UNDEFINED: u16 = 0x3000;
+    /// A folder.
+    pub const ASSOCIATION: u16 = 0x3001;
+    /// A text file.
+    pub const TEXT: u16 = 0x3004;
+    /// An HTML file.
+    pub const HTML: u16 = 0x3005;
+    /// A WAV file.
+    pub const WAV: u16 = 0x3008;
+    /// A JPEG file.
+    pub const EXIF_JPEG: u16 = 0x3801;
+    /// A PNG file.
+    pub const PNG: u16 = 0x380b;
+}
+
+/// Handle of an object of the [`ObjectStore`].
+///
+/// `0` and `0xFFFF_FFFF` are reserved by the protocol and must not be used.
+pub type ObjectHandle = u32;
+
+/// Error reported by an [`ObjectStore`], sent to the host as the response of the operation.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// There is no object with this handle.
+    InvalidObjectHandle,
+    /// The parent object doesn't exist or isn't a folder.
+    InvalidParentObject,
+    /// There is not enough space left.
+    StoreFull,
+    /// The object can't be modified or deleted.
+    ObjectWriteProtected,
+    /// The operation isn't allowed.
+    AccessDenied,
+    /// Any other error.
+    General,
+}
+
+impl Error {
+    fn code(self) -> u16 {
+        match self {
+            Error::InvalidObjectHandle => RESPONSE_INVALID_OBJECT_HANDLE,
+            Error::InvalidParentObject => RESPONSE_INVALID_PARENT_OBJECT,
+            Error::StoreFull => RESPONSE_STORE_FULL,
+            Error::ObjectWriteProtected => RESPONSE_OBJECT_WRITE_PROTECTED,
+            Error::AccessDenied => RESPONSE_ACCESS_DENIED,
+            Error::General => RESPONSE_GENERAL_ERROR,
+        }
+    }
+}
+
+/// Objects listed by [`ObjectStore::list`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Parent {
+    /// All the objects of the store, in any folder.
+    All,
+    /// The objects at the root of the store.
+    Root,
+    /// The objects in a folder.
+    Object(ObjectHandle),
+}
+
+/// Description of an object.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ObjectInfo<'a> {
+    /// Folder the object is in, `None` at the root of the store.
+    pub parent: Option<ObjectHandle>,
+    /// Format of the object, one of [`object_format`]. Folders are [`object_format::ASSOCIATION`].
+    pub format: u16,
+    /// Size of the object in bytes, 0 for folders.
+    pub size: u32,
+    /// Name of the object.
+    pub name: &'a str,
+}
+
+/// Objects exposed by an [`MtpClass`], usually backed by a filesystem.
+pub trait ObjectStore {
+    /// Future returned by `list`.
+    type ListFuture<'a>: Future<Output = Result<usize, Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Writes the handles of the objects in `parent` to `handles`, skipping the first `offset`
+    /// ones. Returns the number of handles written, which is less than the length of `handles`
+    /// only when there are no more objects.
+    fn list<'a>(&'a mut self, parent: Parent, offset: usize, handles: &'a mut [ObjectHandle]) -> Self::ListFuture<'a>;
+
+    /// Future returned by `info`.
+    type InfoFuture<'a>: Future<Output = Result<ObjectInfo<'a>, Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Describes an object.
+    fn info(&mut self, handle: ObjectHandle) -> Self::InfoFuture<'_>;
+
+    /// Future returned by `read`.
+    type ReadFuture<'a>: Future<Output = Result<usize, Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Reads the content of a file, starting at `offset`, into `buf`. Returns the number of bytes
+    /// read, which can only be 0 past the end of the file.
+    fn read<'a>(&'a mut self, handle: ObjectHandle, offset: u32, buf: &'a mut [u8]) -> Self::ReadFuture<'a>;
+
+    /// Future returned by `create`.
+    type CreateFuture<'a>: Future<Output = Result<ObjectHandle, Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Creates an object, and returns its handle.
+    ///
+    /// The content of a file is then written by `write`, in order, up to `info.size` bytes. The
+    /// host may also never send it, leaving an empty file.
+    fn create<'a>(&'a mut self, info: ObjectInfo<'a>) -> Self::CreateFuture<'a>;
+
+    /// Future returned by `write`.
+    type WriteFuture<'a>: Future<Output = Result<(), Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Writes `data` to a file created by `create`, at `offset`.
+    fn write<'a>(&'a mut self, handle: ObjectHandle, offset: u32, data: &'a [u8]) -> Self::WriteFuture<'a>;
+
+    /// Future returned by `delete`.
+    type DeleteFuture<'a>: Future<Output = Result<(), Error>> + 'a
+    where
+        Self: 'a;
+
+    /// Deletes an object, with the objects it contains if it's a folder.
+    fn delete(&mut self, handle: ObjectHandle) -> Self::DeleteFuture<'_>;
+
+    /// Total size of the store, in bytes.
+    fn capacity(&self) -> u64;
+
+    /// Free space of the store, in bytes.
+    fn free_space(&self) -> u64;
+}
+
+/// Configuration of the [`MtpClass`].
+pub struct Config<'d> {
+    /// Manufacturer of the device.
+    pub manufacturer: &'d str,
+    /// Model of the device.
+    pub model: &'d str,
+    /// Version of the device firmware.
+    pub device_version: &'d str,
+    /// Serial number of the device.
+    pub serial_number: &'d str,
+    /// Description of the store, shown as its name by the host.
+    pub storage_description: &'d str,
+    /// Whether the host is refused creating and deleting objects.
+    pub read_only: bool,
+    /// Max packet size for both bulk endpoints.
+    pub max_packet_size: u16,
+}
+
+/// Request aborting the current transaction, from a class request or a bus reset.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Abort {
+    /// The host cancelled the transaction.
+    Cancel,
+    /// The host reset the device, closing the session.
+    Reset,
+}
+
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    abort: Signal<CriticalSectionRawMutex, Abort>,
+}
+
+impl<'d> State<'d> {
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            abort: Signal::new(),
+        }
+    }
+}
+
+struct Control<'d> {
+    abort: &'d Signal<CriticalSectionRawMutex, Abort>,
+}
+
+impl<'d> ControlHandler for Control<'d> {
+    fn reset(&mut self) {
+        self.abort.signal(Abort::Reset);
+    }
+
+    fn control_out(&mut self, req: control::Request, _data: &[u8]) -> OutResponse {
+        match req.request {
+            REQ_CANCEL => {
+                debug!("mtp: cancel");
+                self.abort.signal(Abort::Cancel);
+                OutResponse::Accepted
+            }
+            REQ_DEVICE_RESET => {
+                debug!("mtp: device reset");
+                self.abort.signal(Abort::Reset);
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        match req.request {
+            REQ_GET_DEVICE_STATUS if req.length >= 4 => {
+                // Transactions are abandoned as soon as they are cancelled, the device is never busy.
+                buf[..2].copy_from_slice(&4u16.to_le_bytes());
+                buf[2..4].copy_from_slice(&RESPONSE_OK.to_le_bytes());
+                InResponse::Accepted(&buf[..4])
+            }
+            _ => InResponse::Rejected,
+        }
+    }
+}
+
+/// Response of an operation, with up to 3 parameters.
+struct Response {
+    code: u16,
+    params: [u32; 3],
+    num_params: usize,
+}
+
+impl Response {
+    fn new(code: u16) -> Self {
+        Self {
+            code,
+            params: [0; 3],
+            num_params: 0,
+        }
+    }
+
+    fn ok(params: &[u32]) -> Self {
+        let mut response = Self::new(RESPONSE_OK);
+        response.params[..params.len()].copy_from_slice(params);
+        response.num_params = params.len();
+        response
+    }
+}
+
+impl From<Error> for Response {
+    fn from(e: Error) -> Self {
+        Self::new(e.code())
+    }
+}
+
+/// A file created by SendObjectInfo, waiting for its content.
+struct PendingObject {
+    handle: ObjectHandle,
+    size: u32,
+}
+
+/// Media Transfer Protocol responder exposing an [`ObjectStore`] to the host.
+pub struct MtpClass<'d, D: Driver<'d>, S: ObjectStore> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    _event_ep: D::EndpointIn,
+    abort: &'d Signal<CriticalSectionRawMutex, Abort>,
+    store: S,
+    config: Config<'d>,
+    session: Option<u32>,
+    pending: Option<PendingObject>,
+    buf: [u8; BUF_LEN],
+}
+
+impl<'d, D: Driver<'d>, S: ObjectStore> MtpClass<'d, D, S> {
+    /// Creates a new MtpClass exposing `store`. For full-speed devices, `max_packet_size` has
+    /// to be one of 8, 16, 32 or 64.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, store: S, config: Config<'d>) -> Self {
+        let control = state.control.write(Control { abort: &state.abort });
+
+        let mut func = builder.function(
+            USB_CLASS_STILL_IMAGE,
+            STILL_IMAGE_SUBCLASS,
+            STILL_IMAGE_PROTOCOL_PIMA_15740,
+        );
+        let mut iface = func.interface();
+        iface.handler(control);
+        let mut alt = iface.alt_setting(
+            USB_CLASS_STILL_IMAGE,
+            STILL_IMAGE_SUBCLASS,
+            STILL_IMAGE_PROTOCOL_PIMA_15740,
+        );
+        let read_ep = alt.endpoint_bulk_out(config.max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(config.max_packet_size);
+        // Events are not sent, but the endpoint is expected by hosts.
+        let event_ep = alt.endpoint_interrupt_in(28, 10);
+
+        MtpClass {
+            read_ep,
+            write_ep,
+            _event_ep: event_ep,
+            abort: &state.abort,
+            store,
+            config,
+            session: None,
+            pending: None,
+            buf: [0; BUF_LEN],
+        }
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        // The size is the same for both endpoints.
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+
+    /// Handles the operations of the host.
+    pub async fn run(&mut self) -> ! {
+        let abort = self.abort;
+        loop {
+            self.wait_connection().await;
+            abort.reset();
+            loop {
+                match select(self.handle_transaction(), abort.wait()).await {
+                    Either::First(Ok(())) => {}
+                    Either::First(Err(EndpointError::BufferOverflow)) => warn!("mtp: host sent a packet too large"),
+                    Either::First(Err(EndpointError::Disabled)) => break,
+                    // Abandon the current transaction, the next packet is a new one.
+                    Either::Second(Abort::Cancel) => self.pending = None,
+                    Either::Second(Abort::Reset) => self.close_session(),
+                }
+            }
+            self.close_session();
+        }
+    }
+
+    fn close_session(&mut self) {
+        self.session = None;
+        self.pending = None;
+    }
+
+    async fn handle_transaction(&mut self) -> Result<(), EndpointError> {
+        let mps = self.max_packet_size() as usize;
+
+        let n = self.read_ep.read(&mut self.buf[..mps]).await?;
+        if n == 0 {
+            // Ends the data phase of a refused operation.
+            return Ok(());
+        }
+        let header = match Header::parse(&self.buf[..n]) {
+            Some(header) => header,
+            None => {
+                warn!("mtp: invalid container");
+                return Ok(());
+            }
+        };
+        if header.kind != CONTAINER_COMMAND {
+            // Data of an operation refused before its data phase, which the host sends anyway.
+            let mut remaining = remaining_len(header.len, n, mps);
+            while remaining > 0 {
+                read_data(&mut self.read_ep, &mut self.buf, &mut remaining).await?;
+            }
+            return Ok(());
+        }
+
+        let mut params = [0; 5];
+        let end = n.min(header.len);
+        for (param, bytes) in params
+            .iter_mut()
+            .zip(self.buf[CONTAINER_HEADER_LEN..end].chunks_exact(4))
+        {
+            *param = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        trace!("mtp: operation {:x}", header.code);
+
+        let response = self.execute(header.code, header.transaction_id, &params).await?;
+
+        let len = CONTAINER_HEADER_LEN + response.num_params * 4;
+        write_header(
+            &mut self.buf,
+            len,
+            CONTAINER_RESPONSE,
+            response.code,
+            header.transaction_id,
+        );
+        for (i, param) in response.params[..response.num_params].iter().enumerate() {
+            let pos = CONTAINER_HEADER_LEN + i * 4;
+            self.buf[pos..pos + 4].copy_from_slice(&param.to_le_bytes());
+        }
+        self.write_ep.write(&self.buf[..len]).await
+    }
+
+    async fn execute(&mut self, code: u16, transaction_id: u32, params: &[u32; 5]) -> Result<Response, EndpointError> {
+        if self.session.is_none() && code != OP_GET_DEVICE_INFO && code != OP_OPEN_SESSION {
+            return Ok(Response::new(RESPONSE_SESSION_NOT_OPEN));
+        }
+
+        match code {
+            OP_GET_DEVICE_INFO => {
+                let mut w = DatasetWriter::new(&mut self.buf);
+                w.u16(100); // Standard version
+                w.u32(6); // Vendor extension ID, Microsoft
+                w.u16(100); // Vendor extension version
+                w.string("microsoft.com: 1.0;");
+                w.u16(0); // Functional mode
+                w.array_u16(&OPERATIONS);
+                w.array_u16(&[]); // Events
+                w.array_u16(&[]); // Device properties
+                w.array_u16(&[]); // Capture formats
+                w.array_u16(&[object_format::UNDEFINED, object_format::ASSOCIATION]);
+                w.string(self.config.manufacturer);
+                w.string(self.config.model);
+                w.string(self.config.device_version);
+                w.string(self.config.serial_number);
+                let len = w.len();
+                self.send_dataset(code, transaction_id, len).await
+            }
+            OP_OPEN_SESSION => Ok(match (self.session, params[0]) {
+                (_, 0) => Response::new(RESPONSE_INVALID_PARAMETER),
+                (Some(id), _) => {
+                    let mut response = Response::new(RESPONSE_SESSION_ALREADY_OPEN);
+                    response.params[0] = id;
+                    response.num_params = 1;
+                    response
+                }
+                (None, id) => {
+                    self.session = Some(id);
+                    Response::ok(&[])
+                }
+            }),
+            OP_CLOSE_SESSION => {
+                self.close_session();
+                Ok(Response::ok(&[]))
+            }
+            OP_GET_STORAGE_IDS => {
+                let mut w = DatasetWriter::new(&mut self.buf);
+                w.u32(1);
+                w.u32(STORAGE_ID);
+                let len = w.len();
+                self.send_dataset(code, transaction_id, len).await
+            }
+            OP_GET_STORAGE_INFO => {
+                if params[0] != STORAGE_ID {
+                    return Ok(Response::new(RESPONSE_INVALID_STORAGE_ID));
+                }
+                let mut w = DatasetWriter::new(&mut self.buf);
+                w.u16(0x0003); // Storage type, fixed RAM
+                w.u16(0x0002); // Filesystem type, generic hierarchical
+                w.u16(match self.config.read_only {
+                    false => ACCESS_READ_WRITE,
+                    true => ACCESS_READ_ONLY,
+                });
+                w.u64(self.store.capacity());
+                w.u64(self.store.free_space());
+                w.u32(0xffff_ffff); // Free space in objects, unknown
+                w.string(self.config.storage_description);
+                w.string(""); // Volume identifier
+                let len = w.len();
+                self.send_dataset(code, transaction_id, len).await
+            }
+            OP_GET_NUM_OBJECTS | OP_GET_OBJECT_HANDLES => {
+                if params[0] != STORAGE_ID && params[0] != HANDLE_ALL {
+                    return Ok(Response::new(RESPONSE_INVALID_STORAGE_ID));
+                }
+                if params[1] != 0 {
+                    return Ok(Response::new(RESPONSE_SPECIFICATION_BY_FORMAT_UNSUPPORTED));
+                }
+                let parent = match params[2] {
+                    0 => Parent::All,
+                    HANDLE_ALL => Parent::Root,
+                    handle => Parent::Object(handle),
+                };
+                let count = match self.count(parent).await {
+                    Ok(count) => count,
+                    Err(e) => return Ok(e.into()),
+                };
+                match code {
+                    OP_GET_NUM_OBJECTS => Ok(Response::ok(&[count as u32])),
+                    _ => self.send_handles(code, transaction_id, parent, count).await,
+                }
+            }
+            OP_GET_OBJECT_INFO => {
+                let info = match self.store.info(params[0]).await {
+                    Ok(info) => info,
+                    Err(e) => return Ok(e.into()),
+                };
+                let mut w = DatasetWriter::new(&mut self.buf);
+                w.u32(STORAGE_ID);
+                w.u16(info.format);
+                w.u16(0); // Protection status
+                w.u32(info.size);
+                w.u16(0); // Thumb format
+                w.u32(0); // Thumb compressed size
+                w.u32(0); // Thumb width
+                w.u32(0); // Thumb height
+                w.u32(0); // Image width
+                w.u32(0); // Image height
+                w.u32(0); // Image bit depth
+                w.u32(info.parent.unwrap_or(0));
+                w.u16(match info.format {
+                    object_format::ASSOCIATION => ASSOCIATION_GENERIC_FOLDER,
+                    _ => ASSOCIATION_UNDEFINED,
+                });
+                w.u32(0); // Association description
+                w.u32(0); // Sequence number
+                w.string(info.name);
+                w.string(""); // Capture date
+                w.string(""); // Modification date
+                w.string(""); // Keywords
+                let len = w.len();
+                self.send_dataset(code, transaction_id, len).await
+            }
+            OP_GET_OBJECT => self.send_object(code, transaction_id, params[0]).await,
+            OP_DELETE_OBJECT => {
+                if self.config.read_only {
+                    return Ok(Response::new(RESPONSE_STORE_READ_ONLY));
+                }
+                if params[0] == HANDLE_ALL || params[1] != 0 {
+                    return Ok(Response::new(RESPONSE_PARAMETER_NOT_SUPPORTED));
+                }
+                Ok(match self.store.delete(params[0]).await {
+                    Ok(()) => Response::ok(&[]),
+                    Err(e) => e.into(),
+                })
+            }
+            OP_SEND_OBJECT_INFO => self.receive_object_info(params).await,
+            OP_SEND_OBJECT => self.receive_object().await,
+            OP_GET_OBJECT_PROPS_SUPPORTED => {
+                let mut w = DatasetWriter::new(&mut self.buf);
+                w.array_u16(&PROPS);
+                let len = w.len();
+                self.send_dataset(code, transaction_id, len).await
+            }
+            OP_GET_OBJECT_PROP_DESC => {
+                let data_type = match prop_data_type(params[0] as u16) {
+                    Some(data_type) => data_type,
+                    None => return Ok(Response::new(RESPONSE_INVALID_OBJECT_PROP_CODE)),
+                };
+                let mut w = DatasetWriter::new(&mut self.buf);
+                w.u16(params[0] as u16);
+                w.u16(data_type);
+                w.u8(0); // Get only
+
+                // Default value, zero or an empty string.
+                match data_type {
+                    TYPE_UINT16 => w.u16(0),
+                    TYPE_UINT32 => w.u32(0),
+                    TYPE_UINT64 => w.u64(0),
+                    TYPE_UINT128 => w.bytes(&[0; 16]),
+                    _ => w.string(""),
+                }
+                w.u32(0); // Group code
+                w.u8(0); // Form flag, none
+                let len = w.len();
+                self.send_dataset(code, transaction_id, len).await
+            }
+            OP_GET_OBJECT_PROP_VALUE => {
+                let info = match self.store.info(params[0]).await {
+                    Ok(info) => info,
+                    Err(e) => return Ok(e.into()),
+                };
+                let mut w = DatasetWriter::new(&mut self.buf);
+                match params[1] as u16 {
+                    PROP_STORAGE_ID => w.u32(STORAGE_ID),
+                    PROP_OBJECT_FORMAT => w.u16(info.format),
+                    PROP_PROTECTION_STATUS => w.u16(0),
+                    PROP_OBJECT_SIZE => w.u64(info.size as u64),
+                    PROP_OBJECT_FILE_NAME | PROP_NAME => w.string(info.name),
+                    PROP_PARENT_OBJECT => w.u32(info.parent.unwrap_or(0)),
+                    PROP_PERSISTENT_UID => {
+                        w.u32(params[0]);
+                        w.bytes(&[0; 12]);
+                    }
+                    _ => return Ok(Response::new(RESPONSE_INVALID_OBJECT_PROP_CODE)),
+                }
+                let len = w.len();
+                self.send_dataset(code, transaction_id, len).await
+            }
+            _ => Ok(Response::new(RESPONSE_OPERATION_NOT_SUPPORTED)),
+        }
+    }
+
+    /// Counts the objects in `parent`.
+    async fn count(&mut self, parent: Parent) -> Result<usize, Error> {
+        let mut handles = [0; 16];
+        let mut count = 0;
+        loop {
+            let n = self.store.list(parent, count, &mut handles).await?;
+            count += n;
+            if n < handles.len() {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Sends a dataset written to the transfer buffer after the container header, or fails the
+    /// operation if it didn't fit.
+    async fn send_dataset(
+        &mut self,
+        code: u16,
+        transaction_id: u32,
+        len: Option<usize>,
+    ) -> Result<Response, EndpointError> {
+        let len = match len {
+            Some(len) => len,
+            None => {
+                warn!("mtp: dataset too large");
+                return Ok(Response::new(RESPONSE_GENERAL_ERROR));
+            }
+        };
+        let mut data = DataIn::start(&mut self.write_ep, &mut self.buf, code, transaction_id, len);
+        data.advance(len);
+        data.finish().await?;
+        Ok(Response::ok(&[]))
+    }
+
+    async fn send_handles(
+        &mut self,
+        code: u16,
+        transaction_id: u32,
+        parent: Parent,
+        count: usize,
+    ) -> Result<Response, EndpointError> {
+        let mut data = DataIn::start(&mut self.write_ep, &mut self.buf, code, transaction_id, 4 + count * 4);
+        data.push(&(count as u32).to_le_bytes()).await?;
+
+        let mut handles = [0; 16];
+        let mut offset = 0;
+        while offset < count {
+            let len = handles.len().min(count - offset);
+            let n = match self.store.list(parent, offset, &mut handles[..len]).await {
+                Ok(n) if n > 0 => n,
+                // The objects changed since they were counted, the rest is padded.
+                _ => break,
+            };
+            for handle in &handles[..n] {
+                data.push(&handle.to_le_bytes()).await?;
+            }
+            offset += n;
+        }
+
+        data.finish().await?;
+        Ok(Response::ok(&[]))
+    }
+
+    async fn send_object(
+        &mut self,
+        code: u16,
+        transaction_id: u32,
+        handle: ObjectHandle,
+    ) -> Result<Response, EndpointError> {
+        let size = match self.store.info(handle).await {
+            Ok(info) if info.format == object_format::ASSOCIATION => return Ok(Error::InvalidObjectHandle.into()),
+            Ok(info) => info.size,
+            Err(e) => return Ok(e.into()),
+        };
+
+        let mut data = DataIn::start(&mut self.write_ep, &mut self.buf, code, transaction_id, size as usize);
+        let mut offset = 0;
+        let mut result = Ok(());
+        while offset < size {
+            let buf = data.space();
+            let len = buf.len().min((size - offset) as usize);
+            match self.store.read(handle, offset, &mut buf[..len]).await {
+                Ok(n) if n > 0 => {
+                    data.advance(n);
+                    offset += n as u32;
+                    data.flush().await?;
+                }
+                Ok(_) => {
+                    result = Err(Error::General);
+                    break;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        // The host still expects the announced length, the rest is padded.
+        data.finish().await?;
+
+        Ok(match result {
+            Ok(()) => Response::ok(&[]),
+            Err(e) => {
+                warn!("mtp: failed to read object {}: {:?}", handle, e);
+                Response::new(RESPONSE_INCOMPLETE_TRANSFER)
+            }
+        })
+    }
+
+    /// Reads the first packet of a data phase, and returns the number of bytes read and the
+    /// length of the container. Returns `None` if the host sent the response phase instead.
+    async fn receive_data_header(&mut self) -> Result<Option<(usize, usize)>, EndpointError> {
+        let mps = self.max_packet_size() as usize;
+        let n = self.read_ep.read(&mut self.buf[..mps]).await?;
+        match Header::parse(&self.buf[..n]) {
+            Some(header) if header.kind == CONTAINER_DATA => Ok(Some((n, header.len))),
+            _ => Ok(None),
+        }
+    }
+
+    async fn receive_object_info(&mut self, params: &[u32; 5]) -> Result<Response, EndpointError> {
+        let mps = self.max_packet_size() as usize;
+        let (mut pos, len) = match self.receive_data_header().await? {
+            Some(header) => header,
+            None => return Ok(Response::new(RESPONSE_INVALID_DATASET)),
+        };
+        let mut remaining = remaining_len(len, pos, mps);
+        pos += read_data(&mut self.read_ep, &mut self.buf[pos..], &mut remaining).await?;
+        if remaining > 0 {
+            // Larger than any valid dataset, drain it.
+            while remaining > 0 {
+                read_data(&mut self.read_ep, &mut self.buf, &mut remaining).await?;
+            }
+            return Ok(Response::new(RESPONSE_INVALID_DATASET));
+        }
+
+        if params[0] != 0 && params[0] != STORAGE_ID {
+            return Ok(Response::new(RESPONSE_INVALID_STORAGE_ID));
+        }
+        if self.config.read_only {
+            return Ok(Response::new(RESPONSE_STORE_READ_ONLY));
+        }
+
+        let dataset = &self.buf[CONTAINER_HEADER_LEN..pos.min(len).max(CONTAINER_HEADER_LEN)];
+        if dataset.len() < 53 {
+            return Ok(Response::new(RESPONSE_INVALID_DATASET));
+        }
+        let format = u16::from_le_bytes([dataset[4], dataset[5]]);
+        let size = u32::from_le_bytes(dataset[8..12].try_into().unwrap());
+        let mut name = [0; MAX_NAME_LEN];
+        let name = match read_string(&dataset[52..], &mut name) {
+            Some(name) if !name.is_empty() => name,
+            _ => return Ok(Response::new(RESPONSE_INVALID_DATASET)),
+        };
+        let parent = match params[1] {
+            0 | HANDLE_ALL => None,
+            handle => Some(handle),
+        };
+
+        let info = ObjectInfo {
+            parent,
+            format,
+            size,
+            name,
+        };
+        debug!("mtp: creating {:?}", info);
+        match self.store.create(info).await {
+            Ok(handle) => {
+                self.pending = match format {
+                    object_format::ASSOCIATION => None,
+                    _ => Some(PendingObject { handle, size }),
+                };
+                Ok(Response::ok(&[STORAGE_ID, parent.unwrap_or(HANDLE_ALL), handle]))
+            }
+            Err(e) => Ok(e.into()),
+        }
+    }
+
+    async fn receive_object(&mut self) -> Result<Response, EndpointError> {
+        let mps = self.max_packet_size() as usize;
+        let (n, len) = match self.receive_data_header().await? {
+            Some(header) => header,
+            None => return Ok(Response::new(RESPONSE_INCOMPLETE_TRANSFER)),
+        };
+        let pending = self.pending.take();
+
+        let mut remaining = remaining_len(len, n, mps);
+        let mut fill = n.min(len).max(CONTAINER_HEADER_LEN);
+        let mut start = CONTAINER_HEADER_LEN;
+        let mut offset = 0;
+        let mut result = match pending {
+            Some(_) => Ok(()),
+            None => Err(Response::new(RESPONSE_NO_VALID_OBJECT_INFO)),
+        };
+        loop {
+            fill += read_data(&mut self.read_ep, &mut self.buf[fill..], &mut remaining).await?;
+            if let (Some(pending), Ok(())) = (&pending, &result) {
+                let data = &self.buf[start..fill];
+                if offset + data.len() as u32 > pending.size {
+                    result = Err(Response::new(RESPONSE_STORE_FULL));
+                } else if let Err(e) = self.store.write(pending.handle, offset, data).await {
+                    warn!("mtp: failed to write object {}: {:?}", pending.handle, e);
+                    result = Err(e.into());
+                }
+                offset += data.len() as u32;
+            }
+            if remaining == 0 {
+                break;
+            }
+            fill = 0;
+            start = 0;
+        }
+
+        Ok(match (pending, result) {
+            (_, Err(response)) => response,
+            (Some(pending), Ok(())) if offset < pending.size => Response::new(RESPONSE_INCOMPLETE_TRANSFER),
+            _ => Response::ok(&[]),
+        })
+    }
+}
+
+/// Data type of an object property.
+fn prop_data_type(prop: u16) -> Option<u16> {
+    match prop {
+        PROP_OBJECT_FORMAT | PROP_PROTECTION_STATUS => Some(TYPE_UINT16),
+        PROP_STORAGE_ID | PROP_PARENT_OBJECT => Some(TYPE_UINT32),
+        PROP_OBJECT_SIZE => Some(TYPE_UINT64),
+        PROP_PERSISTENT_UID => Some(TYPE_UINT128),
+        PROP_OBJECT_FILE_NAME | PROP_NAME => Some(TYPE_STR),
+        _ => None,
+    }
+}
+
+/// Header of a container, which starts each phase of a transaction.
+struct Header {
+    len: usize,
+    kind: u16,
+    code: u16,
+    transaction_id: u32,
+}
+
+impl Header {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < CONTAINER_HEADER_LEN {
+            return None;
+        }
+        Some(Self {
+            len: u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize,
+            kind: u16::from_le_bytes([buf[4], buf[5]]),
+            code: u16::from_le_bytes([buf[6], buf[7]]),
+            transaction_id: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+        })
+    }
+}
+
+fn write_header(buf: &mut [u8], len: usize, kind: u16, code: u16, transaction_id: u32) {
+    buf[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+    buf[4..6].copy_from_slice(&kind.to_le_bytes());
+    buf[6..8].copy_from_slice(&code.to_le_bytes());
+    buf[8..12].copy_from_slice(&transaction_id.to_le_bytes());
+}
+
+/// Reads a string of a dataset, converted to UTF-8 in `buf`.
+fn read_string<'a>(data: &[u8], buf: &'a mut [u8]) -> Option<&'a str> {
+    // The number of characters includes the terminating nul.
+    let num_chars = (*data.first()? as usize).saturating_sub(1);
+    let units = data.get(1..1 + num_chars * 2)?;
+    let units = units.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]));
+
+    let mut len = 0;
+    for c in char::decode_utf16(units) {
+        let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+        let dest = buf.get_mut(len..len + c.len_utf8())?;
+        c.encode_utf8(dest);
+        len += c.len_utf8();
+    }
+    core::str::from_utf8(&buf[..len]).ok()
+}
+
+/// Writes a dataset after the container header.
+struct DatasetWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    overflow: bool,
+}
+
+impl<'a> DatasetWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            pos: CONTAINER_HEADER_LEN,
+            overflow: false,
+        }
+    }
+
+    /// Length of the dataset, or `None` if it didn't fit.
+    fn len(&self) -> Option<usize> {
+        match self.overflow {
+            false => Some(self.pos - CONTAINER_HEADER_LEN),
+            true => None,
+        }
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        match self.buf.get_mut(self.pos..self.pos + data.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(data);
+                self.pos += data.len();
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn array_u16(&mut self, values: &[u16]) {
+        self.u32(values.len() as u32);
+        for value in values {
+            self.u16(*value);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        // Up to 254 characters, followed by a nul. The empty string has no nul.
+        let num_chars = s.encode_utf16().take(254).count();
+        if num_chars == 0 {
+            self.u8(0);
+            return;
+        }
+        self.u8(num_chars as u8 + 1);
+        for c in s.encode_utf16().take(num_chars) {
+            self.u16(c);
+        }
+        self.u16(0);
+    }
+}
+
+/// The data phase of an operation sending data to the host.
+///
+/// Data is gathered in the transfer buffer, sent each time it is full.
+struct DataIn<'a, E: EndpointIn> {
+    ep: &'a mut E,
+    buf: &'a mut [u8],
+    fill: usize,
+    /// Bytes of the container not yet added to the buffer.
+    remaining: usize,
+    len: usize,
+}
+
+impl<'a, E: EndpointIn> DataIn<'a, E> {
+    /// Starts a data container, with `len` bytes of data.
+    fn start(ep: &'a mut E, buf: &'a mut [u8], code: u16, transaction_id: u32, len: usize) -> Self {
+        let len = CONTAINER_HEADER_LEN + len;
+        write_header(buf, len, CONTAINER_DATA, code, transaction_id);
+        Self {
+            ep,
+            buf,
+            fill: CONTAINER_HEADER_LEN,
+            remaining: len - CONTAINER_HEADER_LEN,
+            len,
+        }
+    }
+
+    /// The free part of the buffer, to be filled then passed to `advance`.
+    fn space(&mut self) -> &mut [u8] {
+        let end = self.buf.len().min(self.fill + self.remaining);
+        &mut self.buf[self.fill..end]
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.fill += n;
+        self.remaining -= n;
+    }
+
+    /// Sends the buffer if it is full.
+    async fn flush(&mut self) -> Result<(), EndpointError> {
+        if self.fill == self.buf.len() {
+            for packet in self.buf.chunks(self.ep.info().max_packet_size as usize) {
+                self.ep.write(packet).await?;
+            }
+            self.fill = 0;
+        }
+        Ok(())
+    }
+
+    async fn push(&mut self, mut data: &[u8]) -> Result<(), EndpointError> {
+        while !data.is_empty() {
+            let space = self.space();
+            let n = space.len().min(data.len());
+            space[..n].copy_from_slice(&data[..n]);
+            self.advance(n);
+            data = &data[n..];
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Pads the data up to the announced length, and sends the rest of the buffer.
+    async fn finish(mut self) -> Result<(), EndpointError> {
+        while self.remaining > 0 {
+            let space = self.space();
+            let n = space.len();
+            space.fill(0);
+            self.advance(n);
+            self.flush().await?;
+        }
+
+        let mps = self.ep.info().max_packet_size as usize;
+        for packet in self.buf[..self.fill].chunks(mps) {
+            self.ep.write(packet).await?;
+        }
+        if self.len % mps == 0 {
+            self.ep.write(&[]).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Bytes of a container left to read after its first packet of `n` bytes. A short packet ends the
+/// container, whatever its announced length.
+fn remaining_len(len: usize, n: usize, mps: usize) -> usize {
+    match n < mps {
+        true => 0,
+        false => len.saturating_sub(n),
+    }
+}
+
+/// Fill `buf` with data from the host, stopping at a short packet or at the end of the container.
+/// Returns the number of bytes read.
+async fn read_data<E: EndpointOut>(ep: &mut E, buf: &mut [u8], remaining: &mut usize) -> Result<usize, EndpointError> {
+    let mps = ep.info().max_packet_size as usize;
+    let mut pos = 0;
+    while pos + mps <= buf.len() && *remaining > 0 {
+        let n = ep.read(&mut buf[pos..][..mps]).await?;
+        pos += n;
+        *remaining -= n.min(*remaining);
+        if n < mps {
+            *remaining = 0;
+        }
+    }
+    Ok(pos)
+}
@@ -0,0 +1,262 @@
+//! USB printer class.
+//!
+//! [`PrinterClass`] receives the print data of the host, in the page description or command
+//! language of the printer, such as ESC/POS for receipt printers. With the bidirectional
+//! protocol, the printer can also send data back, such as replies to status queries.
+//!
+//! The host identifies the printer and picks its driver with the IEEE 1284 device ID given in
+//! [`Config::device_id`], and polls its [`PortStatus`].
+//!
+//! Hosts put the interface number in the high byte of the index of the device ID request, so the
+//! printer interface has to be the first interface of the device, with number 0.
+
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::control::{self, ControlHandler, InResponse, OutResponse, Request};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::Builder;
+
+const USB_CLASS_PRINTER: u8 = 0x07;
+const PRINTER_SUBCLASS: u8 = 0x01;
+const PRINTER_PROTOCOL_UNIDIRECTIONAL: u8 = 0x01;
+const PRINTER_PROTOCOL_BIDIRECTIONAL: u8 = 0x02;
+
+const REQ_GET_DEVICE_ID: u8 = 0x00;
+const REQ_GET_PORT_STATUS: u8 = 0x01;
+const REQ_SOFT_RESET: u8 = 0x02;
+
+const PORT_STATUS_NOT_ERROR: u8 = 1 << 3;
+const PORT_STATUS_SELECTED: u8 = 1 << 4;
+const PORT_STATUS_PAPER_EMPTY: u8 = 1 << 5;
+
+/// Protocol of the printer interface.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protocol {
+    /// The host only sends data to the printer.
+    Unidirectional,
+    /// The printer also sends data to the host.
+    Bidirectional,
+}
+
+/// Configuration of the [`PrinterClass`].
+pub struct Config<'d> {
+    /// Protocol of the interface.
+    pub protocol: Protocol,
+
+    /// IEEE 1284 device ID, made of `KEY:value;` pairs, such as
+    /// `"MFG:Embassy;MDL:Receipt Printer;CMD:ESC/POS;CLS:PRINTER;"`.
+    ///
+    /// The manufacturer (`MFG` or `MANUFACTURER`), model (`MDL` or `MODEL`) and command set
+    /// (`CMD` or `COMMAND SET`) keys are required. The control buffer has to hold the device ID
+    /// and its 2-byte length.
+    pub device_id: &'d str,
+
+    /// Max packet size of the bulk endpoints.
+    pub max_packet_size: u16,
+}
+
+/// Status of the printer, reported to the host.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PortStatus {
+    /// The printer is out of paper.
+    pub paper_empty: bool,
+    /// The printer is online, ready to print.
+    pub selected: bool,
+    /// The printer is in an error state, such as an open cover or a paper jam.
+    pub error: bool,
+}
+
+impl PortStatus {
+    fn to_bits(self) -> u8 {
+        let mut bits = 0;
+        if self.paper_empty {
+            bits |= PORT_STATUS_PAPER_EMPTY;
+        }
+        if self.selected {
+            bits |= PORT_STATUS_SELECTED;
+        }
+        if !self.error {
+            bits |= PORT_STATUS_NOT_ERROR;
+        }
+        bits
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            paper_empty: bits & PORT_STATUS_PAPER_EMPTY != 0,
+            selected: bits & PORT_STATUS_SELECTED != 0,
+            error: bits & PORT_STATUS_NOT_ERROR == 0,
+        }
+    }
+}
+
+impl Default for PortStatus {
+    /// Online, with paper and no error.
+    fn default() -> Self {
+        Self {
+            paper_empty: false,
+            selected: true,
+            error: false,
+        }
+    }
+}
+
+/// Internal state for the printer class.
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+    shared: ControlShared,
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: ControlShared {
+                port_status: AtomicU8::new(PortStatus::default().to_bits()),
+                soft_reset: AtomicBool::new(false),
+            },
+        }
+    }
+}
+
+/// Shared data between Control and PrinterClass
+struct ControlShared {
+    port_status: AtomicU8,
+    soft_reset: AtomicBool,
+}
+
+struct Control<'d> {
+    device_id: &'d str,
+    shared: &'d ControlShared,
+}
+
+impl<'d> ControlHandler for Control<'d> {
+    fn reset(&mut self) {
+        self.shared.soft_reset.store(false, Ordering::Relaxed);
+    }
+
+    fn control_out(&mut self, req: control::Request, _data: &[u8]) -> OutResponse {
+        match req.request {
+            REQ_SOFT_RESET => {
+                debug!("printer: soft reset");
+                self.shared.soft_reset.store(true, Ordering::Relaxed);
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        match req.request {
+            REQ_GET_DEVICE_ID => {
+                // The length is big-endian, and includes its own 2 bytes.
+                let len = 2 + self.device_id.len();
+                buf[..2].copy_from_slice(&(len as u16).to_be_bytes());
+                buf[2..len].copy_from_slice(self.device_id.as_bytes());
+                InResponse::Accepted(&buf[..len])
+            }
+            REQ_GET_PORT_STATUS => {
+                buf[0] = self.shared.port_status.load(Ordering::Relaxed);
+                InResponse::Accepted(&buf[..1])
+            }
+            _ => InResponse::Rejected,
+        }
+    }
+}
+
+/// USB printer class, made of a printer interface with a bulk OUT endpoint, and a bulk IN
+/// endpoint with the bidirectional protocol.
+pub struct PrinterClass<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    write_ep: Option<D::EndpointIn>,
+    control: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> PrinterClass<'d, D> {
+    /// Creates a new PrinterClass. For full-speed devices, `max_packet_size` in the configuration
+    /// has to be one of 8, 16, 32 or 64.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: &Config<'d>) -> Self {
+        assert!(builder.control_buf_len() >= 2 + config.device_id.len());
+
+        let control = state.control.write(Control {
+            device_id: config.device_id,
+            shared: &state.shared,
+        });
+
+        let protocol = match config.protocol {
+            Protocol::Unidirectional => PRINTER_PROTOCOL_UNIDIRECTIONAL,
+            Protocol::Bidirectional => PRINTER_PROTOCOL_BIDIRECTIONAL,
+        };
+
+        let mut func = builder.function(USB_CLASS_PRINTER, PRINTER_SUBCLASS, protocol);
+        let mut iface = func.interface();
+        iface.handler(control);
+        let mut alt = iface.alt_setting(USB_CLASS_PRINTER, PRINTER_SUBCLASS, protocol);
+        let read_ep = alt.endpoint_bulk_out(config.max_packet_size);
+        let write_ep = match config.protocol {
+            Protocol::Unidirectional => None,
+            Protocol::Bidirectional => Some(alt.endpoint_bulk_in(config.max_packet_size)),
+        };
+
+        PrinterClass {
+            read_ep,
+            write_ep,
+            control: &state.shared,
+        }
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        // The size is the same for both endpoints.
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Gets the status reported to the host.
+    pub fn port_status(&self) -> PortStatus {
+        PortStatus::from_bits(self.control.port_status.load(Ordering::Relaxed))
+    }
+
+    /// Sets the status reported to the host.
+    pub fn set_port_status(&self, status: PortStatus) {
+        self.control.port_status.store(status.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Returns whether the host requested a soft reset since the last call, and clears the
+    /// request.
+    ///
+    /// On a soft reset, the printer discards the data of the current job: the data read next
+    /// starts a new one.
+    pub fn take_soft_reset(&self) -> bool {
+        let soft_reset = self.control.soft_reset.load(Ordering::Relaxed);
+        if soft_reset {
+            self.control.soft_reset.store(false, Ordering::Relaxed);
+        }
+        soft_reset
+    }
+
+    /// Reads a single packet of print data from the OUT endpoint.
+    pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
+        self.read_ep.read(data).await
+    }
+
+    /// Writes a single packet into the IN endpoint.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the protocol is [`Protocol::Unidirectional`].
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        match &mut self.write_ep {
+            Some(write_ep) => write_ep.write(data).await,
+            None => panic!("printer: no IN endpoint with the unidirectional protocol"),
+        }
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+}
@@ -0,0 +1,658 @@
+//! Remote NDIS (RNDIS) class, providing an Ethernet link to hosts lacking CDC-NCM support.
+//!
+//! The device presents itself with the RNDIS class codes recognized by Windows and Linux. If the
+//! Microsoft OS 2.0 descriptor set is enabled with [`Builder::msos_descriptor`], the function also
+//! gets the RNDIS compatible ID, so Windows binds its driver without an INF file.
+//!
+//! Control messages are carried by encapsulated commands on the communication interface, and
+//! Ethernet frames are sent and received on the data interface, each with a packet message header.
+//! See the [Remote NDIS specification](https://learn.microsoft.com/en-us/windows-hardware/drivers/network/remote-ndis--rndis-2)
+//! for details.
+
+use core::cell::Cell;
+use core::mem::{self, MaybeUninit};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_sync::signal::Signal;
+
+use crate::control::{ControlHandler, InResponse, OutResponse, Request};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::msos::CompatibleIdFeatureDescriptor;
+use crate::types::*;
+use crate::Builder;
+
+const USB_CLASS_WIRELESS_CONTROLLER: u8 = 0xe0;
+const USB_SUBCLASS_RF: u8 = 0x01;
+const USB_PROTOCOL_RNDIS: u8 = 0x03;
+
+const USB_CLASS_CDC_DATA: u8 = 0x0a;
+
+const CS_INTERFACE: u8 = 0x24;
+const CDC_TYPE_HEADER: u8 = 0x00;
+const CDC_TYPE_CALL_MANAGEMENT: u8 = 0x01;
+const CDC_TYPE_ACM: u8 = 0x02;
+const CDC_TYPE_UNION: u8 = 0x06;
+
+const REQ_SEND_ENCAPSULATED_COMMAND: u8 = 0x00;
+const REQ_GET_ENCAPSULATED_RESPONSE: u8 = 0x01;
+
+const NOTIF_MAX_PACKET_SIZE: u16 = 8;
+const NOTIF_POLL_INTERVAL: u8 = 1;
+const NOTIF_RESPONSE_AVAILABLE: u32 = 0x01;
+
+const MSG_PACKET: u32 = 0x0000_0001;
+const MSG_INITIALIZE: u32 = 0x0000_0002;
+const MSG_HALT: u32 = 0x0000_0003;
+const MSG_QUERY: u32 = 0x0000_0004;
+const MSG_SET: u32 = 0x0000_0005;
+const MSG_RESET: u32 = 0x0000_0006;
+const MSG_KEEPALIVE: u32 = 0x0000_0008;
+const MSG_COMPLETION: u32 = 0x8000_0000;
+
+const STATUS_SUCCESS: u32 = 0x0000_0000;
+const STATUS_NOT_SUPPORTED: u32 = 0xC000_00BB;
+const STATUS_INVALID_DATA: u32 = 0xC001_0015;
+
+const OID_GEN_SUPPORTED_LIST: u32 = 0x0001_0101;
+const OID_GEN_HARDWARE_STATUS: u32 = 0x0001_0102;
+const OID_GEN_MEDIA_SUPPORTED: u32 = 0x0001_0103;
+const OID_GEN_MEDIA_IN_USE: u32 = 0x0001_0104;
+const OID_GEN_MAXIMUM_FRAME_SIZE: u32 = 0x0001_0106;
+const OID_GEN_LINK_SPEED: u32 = 0x0001_0107;
+const OID_GEN_TRANSMIT_BLOCK_SIZE: u32 = 0x0001_010A;
+const OID_GEN_RECEIVE_BLOCK_SIZE: u32 = 0x0001_010B;
+const OID_GEN_VENDOR_ID: u32 = 0x0001_010C;
+const OID_GEN_VENDOR_DESCRIPTION: u32 = 0x0001_010D;
+const OID_GEN_CURRENT_PACKET_FILTER: u32 = 0x0001_010E;
+const OID_GEN_MAXIMUM_TOTAL_SIZE: u32 = 0x0001_0111;
+const OID_GEN_MEDIA_CONNECT_STATUS: u32 = 0x0001_0114;
+const OID_GEN_PHYSICAL_MEDIUM: u32 = 0x0001_0202;
+const OID_GEN_RNDIS_CONFIG_PARAMETER: u32 = 0x0001_021B;
+const OID_GEN_XMIT_OK: u32 = 0x0002_0101;
+const OID_GEN_RCV_OK: u32 = 0x0002_0102;
+const OID_GEN_XMIT_ERROR: u32 = 0x0002_0103;
+const OID_GEN_RCV_ERROR: u32 = 0x0002_0104;
+const OID_GEN_RCV_NO_BUFFER: u32 = 0x0002_0105;
+const OID_802_3_PERMANENT_ADDRESS: u32 = 0x0101_0101;
+const OID_802_3_CURRENT_ADDRESS: u32 = 0x0101_0102;
+const OID_802_3_MULTICAST_LIST: u32 = 0x0101_0103;
+const OID_802_3_MAXIMUM_LIST_SIZE: u32 = 0x0101_0104;
+const OID_802_3_MAC_OPTIONS: u32 = 0x0101_0113;
+const OID_802_3_RCV_ERROR_ALIGNMENT: u32 = 0x0102_0101;
+const OID_802_3_XMIT_ONE_COLLISION: u32 = 0x0102_0102;
+const OID_802_3_XMIT_MORE_COLLISIONS: u32 = 0x0102_0103;
+
+const SUPPORTED_OIDS: [u32; 27] = [
+    OID_GEN_SUPPORTED_LIST,
+    OID_GEN_HARDWARE_STATUS,
+    OID_GEN_MEDIA_SUPPORTED,
+    OID_GEN_MEDIA_IN_USE,
+    OID_GEN_MAXIMUM_FRAME_SIZE,
+    OID_GEN_LINK_SPEED,
+    OID_GEN_TRANSMIT_BLOCK_SIZE,
+    OID_GEN_RECEIVE_BLOCK_SIZE,
+    OID_GEN_VENDOR_ID,
+    OID_GEN_VENDOR_DESCRIPTION,
+    OID_GEN_CURRENT_PACKET_FILTER,
+    OID_GEN_MAXIMUM_TOTAL_SIZE,
+    OID_GEN_MEDIA_CONNECT_STATUS,
+    OID_GEN_PHYSICAL_MEDIUM,
+    OID_GEN_XMIT_OK,
+    OID_GEN_RCV_OK,
+    OID_GEN_XMIT_ERROR,
+    OID_GEN_RCV_ERROR,
+    OID_GEN_RCV_NO_BUFFER,
+    OID_802_3_PERMANENT_ADDRESS,
+    OID_802_3_CURRENT_ADDRESS,
+    OID_802_3_MULTICAST_LIST,
+    OID_802_3_MAXIMUM_LIST_SIZE,
+    OID_802_3_MAC_OPTIONS,
+    OID_802_3_RCV_ERROR_ALIGNMENT,
+    OID_802_3_XMIT_ONE_COLLISION,
+    OID_802_3_XMIT_MORE_COLLISIONS,
+];
+
+const VENDOR_DESCRIPTION: &[u8] = b"Embassy RNDIS\0";
+
+const MEDIUM_802_3: u32 = 0;
+const DF_CONNECTIONLESS: u32 = 0x01;
+
+/// Largest Ethernet frame, without FCS.
+const MAX_FRAME_SIZE: usize = 1514;
+/// Length of the packet message header preceding each frame.
+const PACKET_HEADER_LEN: usize = 44;
+const MAX_TRANSFER_SIZE: usize = PACKET_HEADER_LEN + MAX_FRAME_SIZE;
+/// Size of the buffer receiving a transfer, a multiple of any bulk max packet size.
+const TRANSFER_BUFFER_SIZE: usize = 2048;
+/// Largest bulk max packet size, sizing the buffer of the first packet sent.
+const MAX_PACKET_SIZE: usize = 512;
+
+/// Largest response, the query completion listing the supported OIDs.
+const MAX_RESPONSE_SIZE: usize = QUERY_COMPLETE_HEADER_LEN + SUPPORTED_OIDS.len() * 4;
+const QUERY_COMPLETE_HEADER_LEN: usize = 24;
+
+/// Internal state for the RNDIS class.
+pub struct State<'a> {
+    control: MaybeUninit<Control<'a>>,
+    shared: ControlShared,
+}
+
+impl<'a> State<'a> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: Default::default(),
+        }
+    }
+}
+
+/// Shared data between Control and the Receiver.
+struct ControlShared {
+    mac_addr: [u8; 6],
+    packet_filter: AtomicU32,
+    connected: AtomicBool,
+    response_pending: CriticalSectionMutex<Cell<bool>>,
+    event: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl Default for ControlShared {
+    fn default() -> Self {
+        ControlShared {
+            mac_addr: [0; 6],
+            packet_filter: AtomicU32::new(0),
+            connected: AtomicBool::new(false),
+            response_pending: CriticalSectionMutex::new(Cell::new(false)),
+            event: Signal::new(),
+        }
+    }
+}
+
+impl ControlShared {
+    fn set_packet_filter(&self, filter: u32) {
+        self.packet_filter.store(filter, Ordering::Relaxed);
+        self.connected.store(filter != 0, Ordering::Relaxed);
+    }
+}
+
+struct Control<'a> {
+    shared: &'a ControlShared,
+    link_speed: u32,
+    response: [u8; MAX_RESPONSE_SIZE],
+    response_len: usize,
+}
+
+impl<'a> Control<'a> {
+    fn respond(&mut self, len: usize) {
+        self.response_len = len;
+        self.shared.response_pending.lock(|p| p.set(true));
+        self.shared.event.signal(());
+    }
+
+    /// Writes the completion of a message, made of its header followed by `fields`, and by an
+    /// information buffer of `info_len` bytes already written.
+    fn complete(&mut self, msg_type: u32, fields: &[u32], info_len: usize) {
+        let len = 8 + fields.len() * 4 + info_len;
+        let header = [msg_type | MSG_COMPLETION, len as u32];
+        for (dest, word) in self.response.chunks_exact_mut(4).zip(header.iter().chain(fields)) {
+            dest.copy_from_slice(&word.to_le_bytes());
+        }
+        self.respond(len);
+    }
+
+    fn initialize(&mut self, request_id: u32) {
+        let fields = [
+            request_id,
+            STATUS_SUCCESS,
+            1, // MajorVersion
+            0, // MinorVersion
+            DF_CONNECTIONLESS,
+            MEDIUM_802_3,
+            1, // MaxPacketsPerTransfer
+            MAX_TRANSFER_SIZE as u32,
+            0, // PacketAlignmentFactor
+            0, // AFListOffset
+            0, // AFListSize
+        ];
+        self.complete(MSG_INITIALIZE, &fields, 0);
+    }
+
+    fn query(&mut self, request_id: u32, oid: u32) {
+        let shared = self.shared;
+        let link_speed = self.link_speed;
+        let buf = &mut self.response[QUERY_COMPLETE_HEADER_LEN..];
+
+        let len = match oid {
+            OID_GEN_SUPPORTED_LIST => {
+                for (dest, oid) in buf.chunks_exact_mut(4).zip(SUPPORTED_OIDS) {
+                    dest.copy_from_slice(&oid.to_le_bytes());
+                }
+                Some(SUPPORTED_OIDS.len() * 4)
+            }
+            OID_GEN_VENDOR_DESCRIPTION => {
+                buf[..VENDOR_DESCRIPTION.len()].copy_from_slice(VENDOR_DESCRIPTION);
+                Some(VENDOR_DESCRIPTION.len())
+            }
+            OID_802_3_PERMANENT_ADDRESS | OID_802_3_CURRENT_ADDRESS => {
+                buf[..6].copy_from_slice(&shared.mac_addr);
+                Some(6)
+            }
+            OID_802_3_MULTICAST_LIST => Some(0),
+            _ => {
+                let value = match oid {
+                    OID_GEN_HARDWARE_STATUS => Some(0), // ready
+                    OID_GEN_MEDIA_SUPPORTED | OID_GEN_MEDIA_IN_USE => Some(MEDIUM_802_3),
+                    OID_GEN_MAXIMUM_FRAME_SIZE => Some(MAX_FRAME_SIZE as u32 - 14),
+                    OID_GEN_LINK_SPEED => Some(link_speed),
+                    OID_GEN_TRANSMIT_BLOCK_SIZE | OID_GEN_RECEIVE_BLOCK_SIZE => Some(MAX_FRAME_SIZE as u32),
+                    OID_GEN_VENDOR_ID => Some(0x00ff_ffff),
+                    OID_GEN_CURRENT_PACKET_FILTER => Some(shared.packet_filter.load(Ordering::Relaxed)),
+                    OID_GEN_MAXIMUM_TOTAL_SIZE => Some(MAX_TRANSFER_SIZE as u32),
+                    OID_GEN_MEDIA_CONNECT_STATUS => Some(0), // connected
+                    OID_GEN_PHYSICAL_MEDIUM => Some(0),      // unspecified
+                    OID_802_3_MAXIMUM_LIST_SIZE => Some(1),
+                    OID_802_3_MAC_OPTIONS => Some(0),
+                    // Statistics aren't kept.
+                    OID_GEN_XMIT_OK
+                    | OID_GEN_RCV_OK
+                    | OID_GEN_XMIT_ERROR
+                    | OID_GEN_RCV_ERROR
+                    | OID_GEN_RCV_NO_BUFFER
+                    | OID_802_3_RCV_ERROR_ALIGNMENT
+                    | OID_802_3_XMIT_ONE_COLLISION
+                    | OID_802_3_XMIT_MORE_COLLISIONS => Some(0),
+                    _ => None,
+                };
+                value.map(|value| {
+                    buf[..4].copy_from_slice(&value.to_le_bytes());
+                    4
+                })
+            }
+        };
+
+        match len {
+            Some(len) => {
+                // The information buffer offset is relative to the request ID.
+                let fields = [
+                    request_id,
+                    STATUS_SUCCESS,
+                    len as u32,
+                    (QUERY_COMPLETE_HEADER_LEN - 8) as u32,
+                ];
+                self.complete(MSG_QUERY, &fields, len);
+            }
+            None => {
+                debug!("rndis: unsupported query of OID {:08x}", oid);
+                self.complete(MSG_QUERY, &[request_id, STATUS_NOT_SUPPORTED, 0, 0], 0);
+            }
+        }
+    }
+
+    fn set(&mut self, request_id: u32, oid: u32, info: &[u8]) {
+        let status = match oid {
+            OID_GEN_CURRENT_PACKET_FILTER => match read_u32(info, 0) {
+                Some(filter) => {
+                    debug!("rndis: packet filter set to {:08x}", filter);
+                    self.shared.set_packet_filter(filter);
+                    STATUS_SUCCESS
+                }
+                None => STATUS_INVALID_DATA,
+            },
+            // Multicast frames are all forwarded anyway, and parameters are ignored.
+            OID_802_3_MULTICAST_LIST | OID_GEN_RNDIS_CONFIG_PARAMETER => STATUS_SUCCESS,
+            _ => {
+                debug!("rndis: unsupported set of OID {:08x}", oid);
+                STATUS_NOT_SUPPORTED
+            }
+        };
+        self.complete(MSG_SET, &[request_id, status], 0);
+    }
+
+    fn handle_message(&mut self, msg: &[u8]) -> Option<()> {
+        let msg_type = read_u32(msg, 0)?;
+        let msg_len = (read_u32(msg, 4)? as usize).min(msg.len());
+        let msg = &msg[..msg_len];
+
+        match msg_type {
+            MSG_INITIALIZE => self.initialize(read_u32(msg, 8)?),
+            MSG_QUERY => self.query(read_u32(msg, 8)?, read_u32(msg, 12)?),
+            MSG_SET => {
+                // The information buffer offset is relative to the request ID.
+                let info_len = read_u32(msg, 16)? as usize;
+                let info_offset = read_u32(msg, 20)? as usize + 8;
+                let info = msg.get(info_offset..info_offset.checked_add(info_len)?)?;
+                self.set(read_u32(msg, 8)?, read_u32(msg, 12)?, info);
+            }
+            MSG_KEEPALIVE => self.complete(MSG_KEEPALIVE, &[read_u32(msg, 8)?, STATUS_SUCCESS], 0),
+            MSG_RESET => {
+                // The host sets the packet filter again after the reset, as addressing is reset.
+                self.shared.set_packet_filter(0);
+                // The reset completion has no request ID, and tells the addressing was reset.
+                self.complete(MSG_RESET, &[STATUS_SUCCESS, 1], 0);
+            }
+            MSG_HALT => {
+                self.shared.set_packet_filter(0);
+                self.response_len = 0;
+                self.shared.event.signal(());
+            }
+            _ => {
+                warn!("rndis: unknown message type {:08x}", msg_type);
+                return None;
+            }
+        }
+
+        Some(())
+    }
+}
+
+impl<'a> ControlHandler for Control<'a> {
+    fn reset(&mut self) {
+        self.shared.set_packet_filter(0);
+        self.shared.response_pending.lock(|p| p.set(false));
+        self.response_len = 0;
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> OutResponse {
+        match req.request {
+            REQ_SEND_ENCAPSULATED_COMMAND => match self.handle_message(data) {
+                Some(()) => OutResponse::Accepted,
+                None => OutResponse::Rejected,
+            },
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn control_in<'b>(&'b mut self, req: Request, buf: &'b mut [u8]) -> InResponse<'b> {
+        match req.request {
+            REQ_GET_ENCAPSULATED_RESPONSE => match mem::take(&mut self.response_len) {
+                // Without a response, a single zero byte is sent.
+                0 => {
+                    buf[0] = 0;
+                    InResponse::Accepted(&buf[..1])
+                }
+                len => InResponse::Accepted(&self.response[..len]),
+            },
+            _ => InResponse::Rejected,
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// RNDIS class, made of a communication interface carrying the control messages and a data
+/// interface carrying the Ethernet frames.
+pub struct RndisClass<'d, D: Driver<'d>> {
+    _comm_if: InterfaceNumber,
+    comm_ep: D::EndpointIn,
+
+    _data_if: InterfaceNumber,
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+
+    shared: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> RndisClass<'d, D> {
+    /// Creates a new RndisClass.
+    ///
+    /// `mac_address` is the address of the host's side of the link, which the host uses for its
+    /// network adapter.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut State<'d>,
+        mac_address: [u8; 6],
+        max_packet_size: u16,
+    ) -> Self {
+        assert!(max_packet_size as usize <= MAX_PACKET_SIZE);
+        state.shared.mac_addr = mac_address;
+
+        let msos_enabled = builder.msos_enabled();
+        let mut func = builder.function(USB_CLASS_WIRELESS_CONTROLLER, USB_SUBCLASS_RF, USB_PROTOCOL_RNDIS);
+        if msos_enabled {
+            func.msos_feature(CompatibleIdFeatureDescriptor::new("RNDIS", "5162001"));
+        }
+
+        // Control interface
+        let mut iface = func.interface();
+        // Speeds are in units of 100 bps.
+        let link_speed = match max_packet_size {
+            0..=64 => 120_000,
+            _ => 4_800_000,
+        };
+        iface.handler(state.control.write(Control {
+            shared: &state.shared,
+            link_speed,
+            response: [0; MAX_RESPONSE_SIZE],
+            response_len: 0,
+        }));
+        let comm_if = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_CLASS_WIRELESS_CONTROLLER, USB_SUBCLASS_RF, USB_PROTOCOL_RNDIS);
+
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_HEADER, // bDescriptorSubtype
+                0x10,
+                0x01, // bcdCDC (1.10)
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_CALL_MANAGEMENT, // bDescriptorSubtype
+                0x00,                     // bmCapabilities
+                u8::from(comm_if) + 1,    // bDataInterface
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_ACM, // bDescriptorSubtype
+                0x00,         // bmCapabilities
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_UNION,        // bDescriptorSubtype
+                comm_if.into(),        // bControlInterface
+                u8::from(comm_if) + 1, // bSubordinateInterface
+            ],
+        );
+
+        let comm_ep = alt.endpoint_interrupt_in(NOTIF_MAX_PACKET_SIZE, NOTIF_POLL_INTERVAL);
+
+        // Data interface
+        let mut iface = func.interface();
+        let data_if = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_CLASS_CDC_DATA, 0x00, 0x00);
+        let read_ep = alt.endpoint_bulk_out(max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(max_packet_size);
+
+        RndisClass {
+            _comm_if: comm_if,
+            comm_ep,
+            _data_if: data_if,
+            read_ep,
+            write_ep,
+            shared: &state.shared,
+        }
+    }
+
+    /// Splits the class into a sender and a receiver, to use them from separate tasks.
+    ///
+    /// The receiver also answers the host's control messages, so it must be kept reading.
+    pub fn split(self) -> (Sender<'d, D>, Receiver<'d, D>) {
+        (
+            Sender {
+                write_ep: self.write_ep,
+            },
+            Receiver {
+                comm_ep: self.comm_ep,
+                read_ep: self.read_ep,
+                shared: self.shared,
+            },
+        )
+    }
+}
+
+/// Sending part of the RNDIS class.
+pub struct Sender<'d, D: Driver<'d>> {
+    write_ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> Sender<'d, D> {
+    /// Writes an Ethernet frame to the host.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        if data.len() > MAX_FRAME_SIZE {
+            return Err(EndpointError::BufferOverflow);
+        }
+
+        let max_packet_size = self.write_ep.info().max_packet_size as usize;
+        let total_len = PACKET_HEADER_LEN + data.len();
+
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let words = [
+            MSG_PACKET,
+            total_len as u32,
+            (PACKET_HEADER_LEN - 8) as u32, // DataOffset, relative to itself
+            data.len() as u32,
+        ];
+        for (dest, word) in buf.chunks_exact_mut(4).zip(words) {
+            dest.copy_from_slice(&word.to_le_bytes());
+        }
+        // The out-of-band data and per-packet info fields are left zero.
+
+        // Build first packet on a buffer, send next packets straight from `data`.
+        let (d1, d2) = data.split_at(data.len().min(max_packet_size - PACKET_HEADER_LEN));
+        buf[PACKET_HEADER_LEN..][..d1.len()].copy_from_slice(d1);
+        self.write_ep.write(&buf[..PACKET_HEADER_LEN + d1.len()]).await?;
+
+        for chunk in d2.chunks(max_packet_size) {
+            self.write_ep.write(chunk).await?;
+        }
+
+        // Send ZLP if needed.
+        if total_len % max_packet_size == 0 {
+            self.write_ep.write(&[]).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Receiving part of the RNDIS class, which also answers the host's control messages.
+pub struct Receiver<'d, D: Driver<'d>> {
+    comm_ep: D::EndpointIn,
+    read_ep: D::EndpointOut,
+    shared: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> Receiver<'d, D> {
+    /// Reads an Ethernet frame from the host.
+    ///
+    /// Returns `EndpointError::Disabled` if the host stops the link, after which
+    /// [`wait_connection`](Self::wait_connection) can be called again.
+    pub async fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        // Retry loop
+        loop {
+            // Read a transfer, answering control messages meanwhile.
+            let mut transfer = [0u8; TRANSFER_BUFFER_SIZE];
+            let mut pos = 0;
+            loop {
+                match select(self.read_ep.read(&mut transfer[pos..]), self.shared.event.wait()).await {
+                    Either::First(n) => {
+                        let n = n?;
+                        pos += n;
+                        if n < self.read_ep.info().max_packet_size as usize || pos == TRANSFER_BUFFER_SIZE {
+                            break;
+                        }
+                    }
+                    Either::Second(()) => {
+                        self.notify_response().await?;
+                        if !self.shared.connected.load(Ordering::Relaxed) {
+                            return Err(EndpointError::Disabled);
+                        }
+                    }
+                }
+            }
+
+            let transfer = &transfer[..pos];
+
+            // Process the packet message header. Transfers may have a padding byte at the end.
+            let (msg_type, msg_len) = match (read_u32(transfer, 0), read_u32(transfer, 4)) {
+                (Some(msg_type), Some(msg_len)) => (msg_type, msg_len as usize),
+                _ => {
+                    warn!("Received too short RNDIS transfer");
+                    continue;
+                }
+            };
+            if msg_type != MSG_PACKET || msg_len > transfer.len() {
+                warn!("Received bad RNDIS packet message.");
+                continue;
+            }
+            let msg = &transfer[..msg_len];
+
+            // The data offset is relative to itself.
+            let data_offset = read_u32(msg, 8).map(|offset| offset as usize + 8);
+            let data_len = read_u32(msg, 12).map(|len| len as usize);
+            let data = match (data_offset, data_len) {
+                (Some(offset), Some(len)) => msg.get(offset..offset.saturating_add(len)),
+                _ => None,
+            };
+            let data = match data {
+                Some(x) => x,
+                None => {
+                    warn!("RNDIS packet message has data out of range.");
+                    continue;
+                }
+            };
+
+            if data.len() > buf.len() {
+                return Err(EndpointError::BufferOverflow);
+            }
+            buf[..data.len()].copy_from_slice(data);
+
+            return Ok(data.len());
+        }
+    }
+
+    /// Waits for the USB host to enable this interface, and to start the link.
+    pub async fn wait_connection(&mut self) -> Result<(), EndpointError> {
+        loop {
+            self.read_ep.wait_enabled().await;
+            self.comm_ep.wait_enabled().await;
+
+            loop {
+                if self.shared.connected.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+
+                self.shared.event.wait().await;
+                match self.notify_response().await {
+                    Ok(()) => {}
+                    Err(EndpointError::Disabled) => break, // Got disabled again, wait again.
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    /// Tells the host a response is available, if it hasn't been told yet.
+    async fn notify_response(&mut self) -> Result<(), EndpointError> {
+        if !self.shared.response_pending.lock(|p| p.replace(false)) {
+            return Ok(());
+        }
+
+        let mut buf = [0; 8];
+        buf[..4].copy_from_slice(&NOTIF_RESPONSE_AVAILABLE.to_le_bytes());
+        // The other 4 bytes are reserved.
+        self.comm_ep.write(&buf).await
+    }
+}
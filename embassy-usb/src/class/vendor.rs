@@ -0,0 +1,217 @@
+//! Vendor-specific class, for simple custom protocols.
+//!
+//! [`VendorClass`] is a vendor interface with a pair of bulk endpoints, and passes the vendor
+//! requests addressed to the interface to a [`RequestHandler`]. If the Microsoft OS 2.0
+//! descriptor set is enabled with [`Builder::msos_descriptor`], the interface is bound to WinUSB,
+//! so applications on Windows can open it with libusb or WinUSB without installing a driver.
+
+use core::mem::MaybeUninit;
+
+use crate::control::{ControlHandler, InResponse, OutResponse, Request, RequestType};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::msos::{CompatibleIdFeatureDescriptor, RegistryPropertyFeatureDescriptor};
+use crate::types::*;
+use crate::Builder;
+
+const USB_CLASS_VENDOR: u8 = 0xff;
+
+/// Configuration of the [`VendorClass`].
+pub struct Config<'d> {
+    /// Subclass code of the interface, for the application to identify it.
+    pub subclass: u8,
+
+    /// Protocol code of the interface, for the application to identify it.
+    pub protocol: u8,
+
+    /// Handler of the vendor requests addressed to the interface. Without one, they are rejected.
+    pub request_handler: Option<&'d dyn RequestHandler>,
+
+    /// Max packet size of both bulk endpoints.
+    pub max_packet_size: u16,
+
+    /// Device interface GUIDs registered for the interface on Windows, so applications can find
+    /// it. Formatted with braces, such as `"{6B75AE32-3D0C-4E8E-9C2D-8A2C9A1F2B4D}"`.
+    ///
+    /// Only used if the Microsoft OS 2.0 descriptor set is enabled.
+    pub device_interface_guids: &'d [&'d str],
+}
+
+/// Handler of the vendor requests addressed to the interface of a [`VendorClass`].
+///
+/// The host addresses the requests to the interface by putting its number in `req.index`; the
+/// other fields are free for the protocol to use.
+pub trait RequestHandler {
+    /// Handles a request with an OUT data stage, or without data stage.
+    fn control_out(&self, req: Request, data: &[u8]) -> OutResponse {
+        let _ = (req, data);
+        OutResponse::Rejected
+    }
+
+    /// Handles a request with an IN data stage, writing the data to `buf` and returning its
+    /// size.
+    ///
+    /// Returns `None` to reject the request.
+    fn control_in(&self, req: Request, buf: &mut [u8]) -> Option<usize> {
+        let _ = (req, buf);
+        None
+    }
+}
+
+/// Internal state for the vendor class.
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        State {
+            control: MaybeUninit::uninit(),
+        }
+    }
+}
+
+struct Control<'d> {
+    request_handler: Option<&'d dyn RequestHandler>,
+}
+
+impl<'d> ControlHandler for Control<'d> {
+    fn control_out(&mut self, req: Request, data: &[u8]) -> OutResponse {
+        match (req.request_type, self.request_handler) {
+            (RequestType::Vendor, Some(handler)) => handler.control_out(req, data),
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        match (req.request_type, self.request_handler) {
+            (RequestType::Vendor, Some(handler)) => match handler.control_in(req, buf) {
+                Some(len) => InResponse::Accepted(&buf[..len]),
+                None => InResponse::Rejected,
+            },
+            _ => InResponse::Rejected,
+        }
+    }
+}
+
+/// Vendor-specific class, made of a vendor interface with a pair of bulk endpoints.
+pub struct VendorClass<'d, D: Driver<'d>> {
+    data_if: InterfaceNumber,
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> VendorClass<'d, D> {
+    /// Creates a new VendorClass. For full-speed devices, `max_packet_size` in the configuration
+    /// has to be one of 8, 16, 32 or 64.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: &Config<'d>) -> Self {
+        let control = state.control.write(Control {
+            request_handler: config.request_handler,
+        });
+
+        let msos_enabled = builder.msos_enabled();
+        let mut func = builder.function(USB_CLASS_VENDOR, config.subclass, config.protocol);
+        if msos_enabled {
+            func.msos_feature(CompatibleIdFeatureDescriptor::winusb());
+            if !config.device_interface_guids.is_empty() {
+                func.msos_feature(RegistryPropertyFeatureDescriptor::device_interface_guids(
+                    config.device_interface_guids,
+                ));
+            }
+        }
+
+        let mut iface = func.interface();
+        iface.handler(control);
+        iface.vendor_requests();
+        let data_if = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_CLASS_VENDOR, config.subclass, config.protocol);
+        let read_ep = alt.endpoint_bulk_out(config.max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(config.max_packet_size);
+
+        VendorClass {
+            data_if,
+            read_ep,
+            write_ep,
+        }
+    }
+
+    /// Gets the number of the interface, which the host puts in the vendor requests.
+    pub fn interface_number(&self) -> InterfaceNumber {
+        self.data_if
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        // The size is the same for both endpoints.
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Writes a single packet into the IN endpoint.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        self.write_ep.write(data).await
+    }
+
+    /// Reads a single packet from the OUT endpoint.
+    pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
+        self.read_ep.read(data).await
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+
+    /// Splits the class into a sender and a receiver, to use them from separate tasks.
+    pub fn split(self) -> (Sender<'d, D>, Receiver<'d, D>) {
+        (
+            Sender {
+                write_ep: self.write_ep,
+            },
+            Receiver { read_ep: self.read_ep },
+        )
+    }
+}
+
+/// Sending part of the vendor class.
+pub struct Sender<'d, D: Driver<'d>> {
+    write_ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> Sender<'d, D> {
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        self.write_ep.info().max_packet_size
+    }
+
+    /// Writes a single packet into the IN endpoint.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        self.write_ep.write(data).await
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.write_ep.wait_enabled().await
+    }
+}
+
+/// Receiving part of the vendor class.
+pub struct Receiver<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+}
+
+impl<'d, D: Driver<'d>> Receiver<'d, D> {
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Reads a single packet from the OUT endpoint.
+    pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
+        self.read_ep.read(data).await
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+}
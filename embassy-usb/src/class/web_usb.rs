@@ -0,0 +1,201 @@
+//! WebUSB class, letting web pages access the device through a vendor interface.
+//!
+//! The device advertises WebUSB support in its BOS descriptor, and the browser can show the
+//! landing page URL when the device is plugged in. If the Microsoft OS 2.0 descriptor set is
+//! enabled with [`Builder::msos_descriptor`], the vendor interface is bound to WinUSB so browsers
+//! on Windows can access it without an INF file.
+//!
+//! See the [WebUSB specification](https://wicg.github.io/webusb/) for details.
+
+use core::mem::MaybeUninit;
+
+use crate::control::{ControlHandler, InResponse, Request};
+use crate::descriptor::capability_type;
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::msos::{CompatibleIdFeatureDescriptor, RegistryPropertyFeatureDescriptor};
+use crate::types::*;
+use crate::Builder;
+
+const USB_CLASS_VENDOR: u8 = 0xff;
+
+/// UUID of the WebUSB platform capability, {3408b638-09a9-47a0-8bfd-a0768815b665}.
+const PLATFORM_CAPABILITY_UUID: [u8; 16] = [
+    0x38, 0xB6, 0x08, 0x34, 0xA9, 0x09, 0xA0, 0x47, 0x8B, 0xFD, 0xA0, 0x76, 0x88, 0x15, 0xB6, 0x65,
+];
+
+const WEBUSB_REQUEST_GET_URL: u16 = 2;
+const WEBUSB_DESCRIPTOR_TYPE_URL: u8 = 3;
+const LANDING_PAGE_INDEX: u8 = 1;
+
+/// A URL sent to the host, such as the landing page.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Url<'d> {
+    scheme: u8,
+    url: &'d str,
+}
+
+impl<'d> Url<'d> {
+    /// Creates a URL from its string form.
+    ///
+    /// `http://` and `https://` prefixes are sent as a scheme code, any other URL is sent as is.
+    /// Panics if the URL is too long to fit a descriptor.
+    pub fn new(url: &'d str) -> Self {
+        let (scheme, url) = if let Some(rest) = url.strip_prefix("https://") {
+            (1, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (0, rest)
+        } else {
+            (255, url)
+        };
+
+        if url.len() > 252 {
+            panic!("URL too long");
+        }
+
+        Self { scheme, url }
+    }
+}
+
+/// Configuration of the WebUSB class.
+pub struct Config<'d> {
+    /// Page the browser suggests opening when the device is plugged in.
+    pub landing_url: Option<Url<'d>>,
+
+    /// Code of the vendor requests used to retrieve URLs.
+    ///
+    /// It can be the same as the Microsoft OS 2.0 vendor code.
+    pub vendor_code: u8,
+
+    /// Max packet size of the bulk endpoints of the vendor interface.
+    pub max_packet_size: u16,
+
+    /// Device interface GUIDs registered for the vendor interface on Windows, so applications using
+    /// WinUSB directly can find it. Formatted with braces, such as
+    /// `"{6B75AE32-3D0C-4E8E-9C2D-8A2C9A1F2B4D}"`.
+    ///
+    /// Only used if the Microsoft OS 2.0 descriptor set is enabled.
+    pub device_interface_guids: &'d [&'d str],
+}
+
+/// Internal state for the WebUSB class.
+pub struct State<'d> {
+    control: MaybeUninit<Control<'d>>,
+}
+
+impl<'d> State<'d> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        State {
+            control: MaybeUninit::uninit(),
+        }
+    }
+}
+
+struct Control<'d> {
+    landing_url: Option<Url<'d>>,
+    vendor_code: u8,
+}
+
+impl<'d> ControlHandler for Control<'d> {
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> InResponse<'a> {
+        if req.request != self.vendor_code || req.index != WEBUSB_REQUEST_GET_URL {
+            return InResponse::Rejected;
+        }
+
+        match self.landing_url {
+            Some(url) if req.value == LANDING_PAGE_INDEX as u16 => {
+                let len = url.url.len() + 3;
+                if buf.len() < len {
+                    warn!("control buffer too small for the landing page URL");
+                    return InResponse::Rejected;
+                }
+
+                buf[0] = len as u8;
+                buf[1] = WEBUSB_DESCRIPTOR_TYPE_URL;
+                buf[2] = url.scheme;
+                buf[3..len].copy_from_slice(url.url.as_bytes());
+                InResponse::Accepted(&buf[..len])
+            }
+            _ => InResponse::Rejected,
+        }
+    }
+}
+
+/// WebUSB class, made of a vendor interface with a pair of bulk endpoints.
+///
+/// It answers the vendor requests addressed to the device, so the device can't have another
+/// vendor handler.
+pub struct WebUsb<'d, D: Driver<'d>> {
+    _data_if: InterfaceNumber,
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> WebUsb<'d, D> {
+    /// Creates a new WebUsb class.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: &Config<'d>) -> Self {
+        let landing_page = match config.landing_url {
+            Some(_) => LANDING_PAGE_INDEX,
+            None => 0,
+        };
+
+        let mut capability = [0; 21];
+        // capability[0] is bReserved.
+        capability[1..17].copy_from_slice(&PLATFORM_CAPABILITY_UUID);
+        capability[17..19].copy_from_slice(&0x0100u16.to_le_bytes()); // bcdVersion 1.0
+        capability[19] = config.vendor_code;
+        capability[20] = landing_page;
+        builder.bos_capability(capability_type::PLATFORM, &capability);
+
+        let control = state.control.write(Control {
+            landing_url: config.landing_url,
+            vendor_code: config.vendor_code,
+        });
+        builder.vendor_handler(control);
+
+        let msos_enabled = builder.msos_enabled();
+        let mut func = builder.function(USB_CLASS_VENDOR, 0x00, 0x00);
+        if msos_enabled {
+            func.msos_feature(CompatibleIdFeatureDescriptor::winusb());
+            if !config.device_interface_guids.is_empty() {
+                func.msos_feature(RegistryPropertyFeatureDescriptor::device_interface_guids(
+                    config.device_interface_guids,
+                ));
+            }
+        }
+
+        let mut iface = func.interface();
+        let data_if = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_CLASS_VENDOR, 0x00, 0x00);
+        let read_ep = alt.endpoint_bulk_out(config.max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(config.max_packet_size);
+
+        WebUsb {
+            _data_if: data_if,
+            read_ep,
+            write_ep,
+        }
+    }
+
+    /// Gets the maximum packet size in bytes.
+    pub fn max_packet_size(&self) -> u16 {
+        // The size is the same for both endpoints.
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Writes a single packet into the IN endpoint.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        self.write_ep.write(data).await
+    }
+
+    /// Reads a single packet from the OUT endpoint.
+    pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
+        self.read_ep.read(data).await
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+}
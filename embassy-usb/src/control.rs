@@ -1,7 +1,7 @@
 //! USB control data types.
 use core::mem;
 
-use crate::driver::Direction;
+use crate::driver::{Direction, EndpointAddress};
 use crate::types::StringIndex;
 
 /// Control request type.
@@ -100,6 +100,9 @@ impl Request {
     /// Standard USB feature Device Remote Wakeup for Set/Clear Feature
     pub const FEATURE_DEVICE_REMOTE_WAKEUP: u16 = 1;
 
+    /// Standard USB feature Test Mode for Set Feature
+    pub const FEATURE_TEST_MODE: u16 = 2;
+
     /// Parses a USB control request from a byte array.
     pub fn parse(buf: &[u8; 8]) -> Request {
         let rt = buf[0];
@@ -148,10 +151,22 @@ pub trait ControlHandler {
     /// Called after a USB reset after the bus reset sequence is complete.
     fn reset(&mut self) {}
 
+    /// Called when the host selects an alternate setting of the interface, including the default
+    /// setting 0 when the device is reset or configured.
     fn set_alternate_setting(&mut self, alternate_setting: u8) {
         let _ = alternate_setting;
     }
 
+    /// Called when the host clears the halt of an endpoint of the interface with a
+    /// CLEAR_FEATURE(ENDPOINT_HALT) request, before the STALL condition is cleared.
+    ///
+    /// Returns whether to clear it. Returning `false` keeps the endpoint halted, as required
+    /// by some protocols until the host resets the interface with a class request.
+    fn clear_halt(&mut self, ep_addr: EndpointAddress) -> bool {
+        let _ = ep_addr;
+        true
+    }
+
     /// Called when a control request is received with direction HostToDevice.
     ///
     /// # Arguments
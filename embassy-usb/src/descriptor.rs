@@ -1,7 +1,6 @@
 use crate::builder::Config;
 use crate::driver::EndpointInfo;
 use crate::types::*;
-use crate::CONFIGURATION_VALUE;
 
 /// Standard descriptor types
 #[allow(missing_docs)]
@@ -32,12 +31,43 @@ pub mod capability_type {
     pub const SS_USB_DEVICE: u8 = 3;
     pub const CONTAINER_ID: u8 = 4;
     pub const PLATFORM: u8 = 5;
+    pub const BILLBOARD: u8 = 0x0d;
+    pub const BILLBOARD_ALT_MODE: u8 = 0x0f;
+}
+
+/// Synchronization type of an isochronous endpoint.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SynchronizationType {
+    /// No synchronization.
+    NoSynchronization = 0b00,
+    /// The endpoint runs from a clock of the device, not locked to the USB frames.
+    Asynchronous = 0b01,
+    /// The endpoint adapts to the data rate of the other side.
+    Adaptive = 0b10,
+    /// The endpoint is locked to the USB start of frames.
+    Synchronous = 0b11,
+}
+
+/// Usage type of an isochronous endpoint.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsageType {
+    /// The endpoint carries data.
+    DataEndpoint = 0b00,
+    /// The endpoint carries explicit feedback for a data endpoint.
+    FeedbackEndpoint = 0b01,
+    /// The endpoint carries data that also serves as feedback for another data endpoint.
+    ImplicitFeedbackDataEndpoint = 0b10,
 }
 
 /// A writer for USB descriptors.
 pub(crate) struct DescriptorWriter<'a> {
     pub buf: &'a mut [u8],
     position: usize,
+    configuration_mark: Option<usize>,
     num_interfaces_mark: Option<usize>,
     num_endpoints_mark: Option<usize>,
 }
@@ -47,6 +77,7 @@ impl<'a> DescriptorWriter<'a> {
         DescriptorWriter {
             buf,
             position: 0,
+            configuration_mark: None,
             num_interfaces_mark: None,
             num_endpoints_mark: None,
         }
@@ -103,20 +134,26 @@ impl<'a> DescriptorWriter<'a> {
         )
     }
 
-    pub(crate) fn configuration(&mut self, config: &Config) {
+    pub(crate) fn configuration(
+        &mut self,
+        value: u8,
+        self_powered: bool,
+        supports_remote_wakeup: bool,
+        max_power: u16,
+    ) {
+        self.configuration_mark = Some(self.position);
         self.num_interfaces_mark = Some(self.position + 4);
 
         self.write(
             descriptor_type::CONFIGURATION,
             &[
                 0,
-                0,                   // wTotalLength
-                0,                   // bNumInterfaces
-                CONFIGURATION_VALUE, // bConfigurationValue
-                0,                   // iConfiguration
-                0x80 | if config.self_powered { 0x40 } else { 0x00 }
-                    | if config.supports_remote_wakeup { 0x20 } else { 0x00 }, // bmAttributes
-                (config.max_power / 2) as u8, // bMaxPower
+                0,     // wTotalLength
+                0,     // bNumInterfaces
+                value, // bConfigurationValue
+                0,     // iConfiguration
+                0x80 | if self_powered { 0x40 } else { 0x00 } | if supports_remote_wakeup { 0x20 } else { 0x00 }, // bmAttributes
+                (max_power / 2) as u8, // bMaxPower
             ],
         )
     }
@@ -127,8 +164,9 @@ impl<'a> DescriptorWriter<'a> {
     }
 
     pub(crate) fn end_configuration(&mut self) {
-        let position = self.position as u16;
-        self.buf[2..4].copy_from_slice(&position.to_le_bytes());
+        let start = self.configuration_mark.take().unwrap();
+        let length = (self.position - start) as u16;
+        self.buf[start + 2..start + 4].copy_from_slice(&length.to_le_bytes());
     }
 
     /// Writes a interface association descriptor. Call from `UsbClass::get_configuration_descriptors`
@@ -224,6 +262,32 @@ impl<'a> DescriptorWriter<'a> {
     /// * `endpoint` - Endpoint previously allocated with
     ///   [`UsbDeviceBuilder`](crate::bus::UsbDeviceBuilder).
     pub fn endpoint(&mut self, endpoint: &EndpointInfo) {
+        self.endpoint_with_attributes(endpoint, endpoint.ep_type as u8, endpoint.max_packet_size)
+    }
+
+    /// Writes an endpoint descriptor for an isochronous endpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - Endpoint previously allocated with
+    ///   [`UsbDeviceBuilder`](crate::bus::UsbDeviceBuilder).
+    /// * `synchronization` - Synchronization type of the endpoint.
+    /// * `usage` - Usage type of the endpoint.
+    /// * `additional_transactions` - Number of additional transactions per microframe, for
+    ///   high-bandwidth high-speed endpoints.
+    pub fn endpoint_isochronous(
+        &mut self,
+        endpoint: &EndpointInfo,
+        synchronization: SynchronizationType,
+        usage: UsageType,
+        additional_transactions: u8,
+    ) {
+        let attributes = endpoint.ep_type as u8 | (synchronization as u8) << 2 | (usage as u8) << 4;
+        let max_packet_size = endpoint.max_packet_size | u16::from(additional_transactions) << 11;
+        self.endpoint_with_attributes(endpoint, attributes, max_packet_size)
+    }
+
+    fn endpoint_with_attributes(&mut self, endpoint: &EndpointInfo, attributes: u8, max_packet_size: u16) {
         match self.num_endpoints_mark {
             Some(mark) => self.buf[mark] += 1,
             None => panic!("you can only call `endpoint` after `interface/interface_alt`."),
@@ -232,11 +296,11 @@ impl<'a> DescriptorWriter<'a> {
         self.write(
             descriptor_type::ENDPOINT,
             &[
-                endpoint.addr.into(),   // bEndpointAddress
-                endpoint.ep_type as u8, // bmAttributes
-                endpoint.max_packet_size as u8,
-                (endpoint.max_packet_size >> 8) as u8, // wMaxPacketSize
-                endpoint.interval,                     // bInterval
+                endpoint.addr.into(), // bEndpointAddress
+                attributes,           // bmAttributes
+                max_packet_size as u8,
+                (max_packet_size >> 8) as u8, // wMaxPacketSize
+                endpoint.interval,            // bInterval
             ],
         );
     }
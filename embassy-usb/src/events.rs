@@ -0,0 +1,101 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::channel::Channel;
+
+use crate::DeviceStateHandler;
+
+/// Change of the state of the device, reported by [`DeviceEvents`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceEvent {
+    /// The USB device has been enabled or disabled.
+    Enabled(bool),
+    /// The host reset the device.
+    Reset,
+    /// The host set the address of the device.
+    Addressed(u8),
+    /// The host enabled or disabled the configuration of the device.
+    Configured(bool),
+    /// The bus entered or exited the suspend state.
+    Suspended(bool),
+}
+
+/// A [`DeviceStateHandler`] queuing the changes of the state of the device, for application tasks
+/// to wait for them.
+///
+/// While the bus is suspended, a bus-powered device must draw at most 2.5 mA: a task can wait for
+/// [`DeviceEvent::Suspended`] to enter a low-power mode, and leave it on the next event.
+///
+/// Up to `N` events are queued. When the queue is full, the oldest event is dropped; the current
+/// state is still given by [`is_suspended`](Self::is_suspended) and
+/// [`is_configured`](Self::is_configured).
+pub struct DeviceEvents<M: RawMutex, const N: usize> {
+    events: Channel<M, DeviceEvent, N>,
+    suspended: AtomicBool,
+    configured: AtomicBool,
+}
+
+impl<M: RawMutex, const N: usize> DeviceEvents<M, N> {
+    /// Creates a new DeviceEvents, to be passed to [`Builder::new`](crate::Builder::new).
+    pub const fn new() -> Self {
+        Self {
+            events: Channel::new(),
+            suspended: AtomicBool::new(false),
+            configured: AtomicBool::new(false),
+        }
+    }
+
+    /// Waits for the next event.
+    pub async fn next(&self) -> DeviceEvent {
+        self.events.recv().await
+    }
+
+    /// Returns whether the bus is suspended.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the device is configured, and can draw the current it announced.
+    pub fn is_configured(&self) -> bool {
+        self.configured.load(Ordering::Relaxed)
+    }
+
+    fn push(&self, event: DeviceEvent) {
+        if self.events.try_send(event).is_err() {
+            warn!("usb: event queue full, dropping the oldest event");
+            let _ = self.events.try_recv();
+            let _ = self.events.try_send(event);
+        }
+    }
+}
+
+impl<M: RawMutex, const N: usize> DeviceStateHandler for DeviceEvents<M, N> {
+    fn enabled(&self, enabled: bool) {
+        if !enabled {
+            self.suspended.store(false, Ordering::Relaxed);
+            self.configured.store(false, Ordering::Relaxed);
+        }
+        self.push(DeviceEvent::Enabled(enabled));
+    }
+
+    fn reset(&self) {
+        self.suspended.store(false, Ordering::Relaxed);
+        self.configured.store(false, Ordering::Relaxed);
+        self.push(DeviceEvent::Reset);
+    }
+
+    fn addressed(&self, addr: u8) {
+        self.push(DeviceEvent::Addressed(addr));
+    }
+
+    fn configured(&self, configured: bool) {
+        self.configured.store(configured, Ordering::Relaxed);
+        self.push(DeviceEvent::Configured(configured));
+    }
+
+    fn suspended(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::Relaxed);
+        self.push(DeviceEvent::Suspended(suspended));
+    }
+}
@@ -11,16 +11,20 @@ pub mod class;
 pub mod control;
 pub mod descriptor;
 mod descriptor_reader;
+mod events;
+pub mod msos;
 pub mod types;
 
 use embassy_futures::select::{select, Either};
 use heapless::Vec;
 
 pub use crate::builder::{Builder, Config};
+pub use crate::events::{DeviceEvent, DeviceEvents};
 use crate::control::*;
 use crate::descriptor::*;
 use crate::descriptor_reader::foreach_endpoint;
-use crate::driver::{Bus, ControlPipe, Direction, Driver, EndpointAddress, Event};
+use crate::driver::{Bus, ControlPipe, Direction, Driver, EndpointAddress, Event, TestMode};
+use crate::msos::{MsOsDescriptorSet, MS_OS_20_DESCRIPTOR_INDEX};
 use crate::types::*;
 
 /// The global state of the USB device.
@@ -62,11 +66,20 @@ impl From<driver::Unsupported> for RemoteWakeupError {
 /// The bConfiguration value for the not configured state.
 pub const CONFIGURATION_NONE: u8 = 0;
 
-/// The bConfiguration value for the single configuration supported by this device.
+/// The bConfiguration value of the first configuration of this device.
+///
+/// The configurations added with [`Builder::configuration`] have the next values.
 pub const CONFIGURATION_VALUE: u8 = 1;
 
+/// Maximum number of interfaces of each configuration.
 pub const MAX_INTERFACE_COUNT: usize = 4;
 
+/// Maximum number of configurations.
+pub const MAX_CONFIGURATION_COUNT: usize = 4;
+
+/// Maximum number of interfaces, counting those of all configurations.
+const MAX_TOTAL_INTERFACE_COUNT: usize = MAX_INTERFACE_COUNT * MAX_CONFIGURATION_COUNT;
+
 const STRING_INDEX_MANUFACTURER: u8 = 1;
 const STRING_INDEX_PRODUCT: u8 = 2;
 const STRING_INDEX_SERIAL_NUMBER: u8 = 3;
@@ -86,6 +99,16 @@ pub trait DeviceStateHandler {
     /// Called when the host has enabled or disabled the configuration of the device.
     fn configured(&self, _configured: bool) {}
 
+    /// Called when the host has selected the configuration with value `configuration`, before
+    /// [`configured(true)`](Self::configured).
+    ///
+    /// Only useful for devices with several configurations, added with [`Builder::configuration`].
+    fn configuration_selected(&self, _configuration: u8) {}
+
+    /// Called when the host has selected alternate setting `alternate_setting` of interface
+    /// `iface`, in the current configuration.
+    fn alternate_setting_selected(&self, _iface: InterfaceNumber, _alternate_setting: u8) {}
+
     /// Called when the bus has entered or exited the suspend state.
     fn suspended(&self, _suspended: bool) {}
 
@@ -93,11 +116,57 @@ pub trait DeviceStateHandler {
     fn remote_wakeup_enabled(&self, _enabled: bool) {}
 }
 
+/// A handler supplying the device strings when the host requests them.
+///
+/// Unlike the strings of [`Config`], they can be generated on demand in a buffer owned by the
+/// handler, such as a serial number formatted from the unique ID of the chip.
+///
+/// Each method is called once when building the device to find out which strings exist, and then
+/// each time the host requests the string. Strings the handler doesn't supply are taken from
+/// [`Config`].
+pub trait StringHandler {
+    /// Returns the manufacturer name.
+    fn manufacturer(&mut self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the product name.
+    fn product(&mut self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the serial number.
+    fn serial_number(&mut self) -> Option<&str> {
+        None
+    }
+}
+
 struct Interface<'d> {
     handler: Option<&'d mut dyn ControlHandler>,
     current_alt_setting: u8,
     num_alt_settings: u8,
     num_strings: u8,
+    vendor_requests: bool,
+}
+
+struct Configuration {
+    /// Range of the configuration in the configuration descriptor buffer.
+    descriptor_start: usize,
+    descriptor_end: usize,
+    /// Range of the interfaces of the configuration in the interface list.
+    first_interface: usize,
+    num_interfaces: usize,
+    self_powered: bool,
+}
+
+impl Configuration {
+    fn descriptor<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        &buf[self.descriptor_start..self.descriptor_end]
+    }
+
+    fn interfaces<'a, 'd>(&self, interfaces: &'a mut [Interface<'d>]) -> &'a mut [Interface<'d>] {
+        &mut interfaces[self.first_interface..][..self.num_interfaces]
+    }
 }
 
 pub struct UsbDevice<'d, D: Driver<'d>> {
@@ -114,6 +183,7 @@ struct Inner<'d, D: Driver<'d>> {
     device_descriptor: &'d [u8],
     config_descriptor: &'d [u8],
     bos_descriptor: &'d [u8],
+    msos_descriptor: Option<MsOsDescriptorSet<'d>>,
 
     device_state: UsbDeviceState,
     suspended: bool,
@@ -128,20 +198,45 @@ struct Inner<'d, D: Driver<'d>> {
     /// If true, do a set_addr after finishing the current control req.
     set_address_pending: bool,
 
-    interfaces: Vec<Interface<'d>, MAX_INTERFACE_COUNT>,
+    interfaces: Vec<Interface<'d>, MAX_TOTAL_INTERFACE_COUNT>,
+    configurations: Vec<Configuration, MAX_CONFIGURATION_COUNT>,
+    /// Index of the selected configuration, or of the first one if none is selected.
+    configuration: usize,
+    vendor_handler: Option<&'d mut dyn ControlHandler>,
+    string_handler: Option<&'d mut dyn StringHandler>,
+}
+
+/// Everything the [`Builder`] sets up for a [`UsbDevice`], besides the driver.
+pub(crate) struct DeviceParts<'d> {
+    pub config: Config<'d>,
+    pub handler: Option<&'d dyn DeviceStateHandler>,
+    pub device_descriptor: &'d [u8],
+    pub config_descriptor: &'d [u8],
+    pub bos_descriptor: &'d [u8],
+    pub msos_descriptor: Option<MsOsDescriptorSet<'d>>,
+    pub interfaces: Vec<Interface<'d>, MAX_TOTAL_INTERFACE_COUNT>,
+    pub configurations: Vec<Configuration, MAX_CONFIGURATION_COUNT>,
+    pub vendor_handler: Option<&'d mut dyn ControlHandler>,
+    pub string_handler: Option<&'d mut dyn StringHandler>,
+    pub control_buf: &'d mut [u8],
 }
 
 impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
-    pub(crate) fn build(
-        driver: D,
-        config: Config<'d>,
-        handler: Option<&'d dyn DeviceStateHandler>,
-        device_descriptor: &'d [u8],
-        config_descriptor: &'d [u8],
-        bos_descriptor: &'d [u8],
-        interfaces: Vec<Interface<'d>, MAX_INTERFACE_COUNT>,
-        control_buf: &'d mut [u8],
-    ) -> UsbDevice<'d, D> {
+    pub(crate) fn build(driver: D, parts: DeviceParts<'d>) -> UsbDevice<'d, D> {
+        let DeviceParts {
+            config,
+            handler,
+            device_descriptor,
+            config_descriptor,
+            bos_descriptor,
+            msos_descriptor,
+            interfaces,
+            configurations,
+            vendor_handler,
+            string_handler,
+            control_buf,
+        } = parts;
+
         // Start the USB bus.
         // This prevent further allocation by consuming the driver.
         let (bus, control) = driver.start(config.max_packet_size_0 as u16);
@@ -156,14 +251,19 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
                 device_descriptor,
                 config_descriptor,
                 bos_descriptor,
+                msos_descriptor,
 
                 device_state: UsbDeviceState::Unpowered,
                 suspended: false,
                 remote_wakeup_enabled: false,
-                self_powered: false,
+                self_powered: config.self_powered,
                 address: 0,
                 set_address_pending: false,
                 interfaces,
+                configurations,
+                configuration: 0,
+                vendor_handler,
+                string_handler,
             },
         }
     }
@@ -222,6 +322,14 @@ impl<'d, D: Driver<'d>> UsbDevice<'d, D> {
         }
     }
 
+    /// Returns whether the host enabled remote wakeup.
+    ///
+    /// The host enables it before suspending the bus if [`Config::supports_remote_wakeup`] is set
+    /// and it allows the device to wake it up.
+    pub fn remote_wakeup_enabled(&self) -> bool {
+        self.inner.remote_wakeup_enabled
+    }
+
     /// Initiates a device remote wakeup on the USB bus.
     ///
     /// If the bus is not suspended or remote wakeup is not enabled, an error
@@ -352,6 +460,10 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                     }
                 }
 
+                if let Some(h) = &mut self.vendor_handler {
+                    h.reset();
+                }
+
                 if let Some(h) = &self.handler {
                     h.reset();
                 }
@@ -384,6 +496,16 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                 self.bus.disable().await;
                 self.device_state = UsbDeviceState::Unpowered;
 
+                // A device unplugged while suspended must not stay suspended, or it would
+                // wait for a resume that never comes after being plugged back in.
+                if self.suspended {
+                    self.suspended = false;
+                    if let Some(h) = &self.handler {
+                        h.suspended(false);
+                    }
+                }
+                self.remote_wakeup_enabled = false;
+
                 if let Some(h) = &self.handler {
                     h.enabled(false);
                 }
@@ -391,9 +513,25 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
         }
     }
 
+    /// Leaves the configured state, disabling the endpoints of the current configuration.
+    fn unconfigure(&mut self) {
+        self.device_state = UsbDeviceState::Addressed;
+
+        // Disable all endpoints.
+        let config = &self.configurations[self.configuration];
+        foreach_endpoint(config.descriptor(self.config_descriptor), |ep| {
+            self.bus.endpoint_set_enabled(ep.ep_address, false);
+        })
+        .unwrap();
+
+        // Notify handler.
+        if let Some(h) = &self.handler {
+            h.configured(false);
+        }
+    }
+
     fn handle_control_out(&mut self, req: Request, data: &[u8]) -> OutResponse {
         const CONFIGURATION_NONE_U16: u16 = CONFIGURATION_NONE as u16;
-        const CONFIGURATION_VALUE_U16: u16 = CONFIGURATION_VALUE as u16;
 
         match (req.request_type, req.recipient) {
             (RequestType::Standard, Recipient::Device) => match (req.request, req.value) {
@@ -405,12 +543,32 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                     OutResponse::Accepted
                 }
                 (Request::SET_FEATURE, Request::FEATURE_DEVICE_REMOTE_WAKEUP) => {
+                    if !self.config.supports_remote_wakeup {
+                        return OutResponse::Rejected;
+                    }
                     self.remote_wakeup_enabled = true;
                     if let Some(h) = &self.handler {
                         h.remote_wakeup_enabled(true);
                     }
                     OutResponse::Accepted
                 }
+                (Request::SET_FEATURE, Request::FEATURE_TEST_MODE) => {
+                    // The selector is in the upper byte of wIndex, the lower byte must be zero.
+                    let mode = match req.index {
+                        0x0100 => TestMode::J,
+                        0x0200 => TestMode::K,
+                        0x0300 => TestMode::Se0Nak,
+                        0x0400 => TestMode::Packet,
+                        _ => return OutResponse::Rejected,
+                    };
+                    match self.bus.set_test_mode(mode) {
+                        Ok(()) => {
+                            debug!("SET_FEATURE: test mode {:?}", mode);
+                            OutResponse::Accepted
+                        }
+                        Err(_) => OutResponse::Rejected,
+                    }
+                }
                 (Request::SET_ADDRESS, addr @ 1..=127) => {
                     self.address = addr as u8;
                     self.set_address_pending = true;
@@ -420,13 +578,46 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                     }
                     OutResponse::Accepted
                 }
-                (Request::SET_CONFIGURATION, CONFIGURATION_VALUE_U16) => {
-                    debug!("SET_CONFIGURATION: configured");
+                (Request::SET_CONFIGURATION, CONFIGURATION_NONE_U16) => match self.device_state {
+                    UsbDeviceState::Default => OutResponse::Accepted,
+                    _ => {
+                        debug!("SET_CONFIGURATION: unconfigured");
+                        self.unconfigure();
+                        OutResponse::Accepted
+                    }
+                },
+                (Request::SET_CONFIGURATION, value) => {
+                    let index = value as usize - 1;
+                    if index >= self.configurations.len() {
+                        warn!("SET_CONFIGURATION: unknown configuration {}", value);
+                        return OutResponse::Rejected;
+                    }
+
+                    debug!("SET_CONFIGURATION: configured with {}", value);
+                    if self.device_state == UsbDeviceState::Configured && self.configuration != index {
+                        self.unconfigure();
+                    }
                     self.device_state = UsbDeviceState::Configured;
+                    self.configuration = index;
+
+                    let config = &self.configurations[index];
+                    self.self_powered = config.self_powered;
+
+                    // Alternate settings are reset to the default ones.
+                    for (number, iface) in config.interfaces(&mut self.interfaces).iter_mut().enumerate() {
+                        iface.current_alt_setting = 0;
+                        if let Some(h) = &mut iface.handler {
+                            h.set_alternate_setting(0);
+                        }
+                        if let Some(h) = &self.handler {
+                            h.alternate_setting_selected(InterfaceNumber::new(number as u8), 0);
+                        }
+                    }
 
                     // Enable all endpoints of selected alt settings.
-                    foreach_endpoint(self.config_descriptor, |ep| {
-                        let iface = &self.interfaces[ep.interface as usize];
+                    let interfaces = config.interfaces(&mut self.interfaces);
+                    foreach_endpoint(config.descriptor(self.config_descriptor), |ep| {
+                        let iface = &interfaces[ep.interface as usize];
                         self.bus
                             .endpoint_set_enabled(ep.ep_address, iface.current_alt_setting == ep.interface_alt);
                     })
@@ -434,35 +625,17 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
 
                     // Notify handler.
                     if let Some(h) = &self.handler {
+                        h.configuration_selected(value as u8);
                         h.configured(true);
                     }
 
                     OutResponse::Accepted
                 }
-                (Request::SET_CONFIGURATION, CONFIGURATION_NONE_U16) => match self.device_state {
-                    UsbDeviceState::Default => OutResponse::Accepted,
-                    _ => {
-                        debug!("SET_CONFIGURATION: unconfigured");
-                        self.device_state = UsbDeviceState::Addressed;
-
-                        // Disable all endpoints.
-                        foreach_endpoint(self.config_descriptor, |ep| {
-                            self.bus.endpoint_set_enabled(ep.ep_address, false);
-                        })
-                        .unwrap();
-
-                        // Notify handler.
-                        if let Some(h) = &self.handler {
-                            h.configured(false);
-                        }
-
-                        OutResponse::Accepted
-                    }
-                },
                 _ => OutResponse::Rejected,
             },
             (RequestType::Standard, Recipient::Interface) => {
-                let iface = match self.interfaces.get_mut(req.index as usize) {
+                let config = &self.configurations[self.configuration];
+                let iface = match config.interfaces(&mut self.interfaces).get_mut(req.index as usize) {
                     Some(iface) => iface,
                     None => return OutResponse::Rejected,
                 };
@@ -479,7 +652,7 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                         iface.current_alt_setting = new_altsetting;
 
                         // Enable/disable EPs of this interface as needed.
-                        foreach_endpoint(self.config_descriptor, |ep| {
+                        foreach_endpoint(config.descriptor(self.config_descriptor), |ep| {
                             if ep.interface == req.index as u8 {
                                 self.bus
                                     .endpoint_set_enabled(ep.ep_address, iface.current_alt_setting == ep.interface_alt);
@@ -487,12 +660,12 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                         })
                         .unwrap();
 
-                        // TODO check it is valid (not out of range)
-                        // TODO actually enable/disable endpoints.
-
                         if let Some(handler) = &mut iface.handler {
                             handler.set_alternate_setting(new_altsetting);
                         }
+                        if let Some(h) = &self.handler {
+                            h.alternate_setting_selected(InterfaceNumber::new(req.index as u8), new_altsetting);
+                        }
                         OutResponse::Accepted
                     }
                     _ => OutResponse::Rejected,
@@ -506,21 +679,48 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                 }
                 (Request::CLEAR_FEATURE, Request::FEATURE_ENDPOINT_HALT) => {
                     let ep_addr = ((req.index as u8) & 0x8f).into();
-                    self.bus.endpoint_set_stalled(ep_addr, false);
+
+                    // Let the handler of the interface of the endpoint keep it halted.
+                    let mut clear = true;
+                    if self.device_state == UsbDeviceState::Configured {
+                        let config = &self.configurations[self.configuration];
+                        let interfaces = config.interfaces(&mut self.interfaces);
+                        foreach_endpoint(config.descriptor(self.config_descriptor), |ep| {
+                            let iface = &mut interfaces[ep.interface as usize];
+                            if ep.ep_address == ep_addr && iface.current_alt_setting == ep.interface_alt {
+                                if let Some(handler) = &mut iface.handler {
+                                    clear = handler.clear_halt(ep_addr);
+                                }
+                            }
+                        })
+                        .unwrap();
+                    }
+
+                    if clear {
+                        self.bus.endpoint_set_stalled(ep_addr, false);
+                    }
                     OutResponse::Accepted
                 }
                 _ => OutResponse::Rejected,
             },
-            (RequestType::Class, Recipient::Interface) => {
-                let iface = match self.interfaces.get_mut(req.index as usize) {
+            (RequestType::Class | RequestType::Vendor, Recipient::Interface) => {
+                let config = &self.configurations[self.configuration];
+                let iface = match config.interfaces(&mut self.interfaces).get_mut(req.index as usize) {
                     Some(iface) => iface,
                     None => return OutResponse::Rejected,
                 };
+                if req.request_type == RequestType::Vendor && !iface.vendor_requests {
+                    return OutResponse::Rejected;
+                }
                 match &mut iface.handler {
                     Some(handler) => handler.control_out(req, data),
                     None => OutResponse::Rejected,
                 }
             }
+            (RequestType::Vendor, Recipient::Device) => match &mut self.vendor_handler {
+                Some(handler) => handler.control_out(req, data),
+                None => OutResponse::Rejected,
+            },
             _ => OutResponse::Rejected,
         }
     }
@@ -542,7 +742,7 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                 Request::GET_DESCRIPTOR => self.handle_get_descriptor(req, buf),
                 Request::GET_CONFIGURATION => {
                     let status = match self.device_state {
-                        UsbDeviceState::Configured => CONFIGURATION_VALUE,
+                        UsbDeviceState::Configured => CONFIGURATION_VALUE + self.configuration as u8,
                         _ => CONFIGURATION_NONE,
                     };
                     buf[0] = status;
@@ -551,7 +751,8 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                 _ => InResponse::Rejected,
             },
             (RequestType::Standard, Recipient::Interface) => {
-                let iface = match self.interfaces.get_mut(req.index as usize) {
+                let config = &self.configurations[self.configuration];
+                let iface = match config.interfaces(&mut self.interfaces).get_mut(req.index as usize) {
                     Some(iface) => iface,
                     None => return InResponse::Rejected,
                 };
@@ -585,17 +786,33 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                 }
                 _ => InResponse::Rejected,
             },
-            (RequestType::Class, Recipient::Interface) => {
-                let iface = match self.interfaces.get_mut(req.index as usize) {
+            (RequestType::Class | RequestType::Vendor, Recipient::Interface) => {
+                let config = &self.configurations[self.configuration];
+                let iface = match config.interfaces(&mut self.interfaces).get_mut(req.index as usize) {
                     Some(iface) => iface,
                     None => return InResponse::Rejected,
                 };
+                if req.request_type == RequestType::Vendor && !iface.vendor_requests {
+                    return InResponse::Rejected;
+                }
 
                 match &mut iface.handler {
                     Some(handler) => handler.control_in(req, buf),
                     None => InResponse::Rejected,
                 }
             }
+            (RequestType::Vendor, Recipient::Device) => {
+                if let Some(msos) = &self.msos_descriptor {
+                    if req.request == msos.vendor_code && req.index == MS_OS_20_DESCRIPTOR_INDEX {
+                        return InResponse::Accepted(msos.descriptor);
+                    }
+                }
+
+                match &mut self.vendor_handler {
+                    Some(handler) => handler.control_in(req, buf),
+                    None => InResponse::Rejected,
+                }
+            }
             _ => InResponse::Rejected,
         }
     }
@@ -606,7 +823,10 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
         match dtype {
             descriptor_type::BOS => InResponse::Accepted(self.bos_descriptor),
             descriptor_type::DEVICE => InResponse::Accepted(self.device_descriptor),
-            descriptor_type::CONFIGURATION => InResponse::Accepted(self.config_descriptor),
+            descriptor_type::CONFIGURATION => match self.configurations.get(index as usize) {
+                Some(config) => InResponse::Accepted(config.descriptor(self.config_descriptor)),
+                None => InResponse::Rejected,
+            },
             descriptor_type::STRING => {
                 if index == 0 {
                     buf[0] = 4; // len
@@ -616,9 +836,18 @@ impl<'d, D: Driver<'d>> Inner<'d, D> {
                     InResponse::Accepted(&buf[..4])
                 } else {
                     let s = match index {
-                        STRING_INDEX_MANUFACTURER => self.config.manufacturer,
-                        STRING_INDEX_PRODUCT => self.config.product,
-                        STRING_INDEX_SERIAL_NUMBER => self.config.serial_number,
+                        STRING_INDEX_MANUFACTURER => {
+                            let s = self.string_handler.as_mut().and_then(|h| h.manufacturer());
+                            s.or(self.config.manufacturer)
+                        }
+                        STRING_INDEX_PRODUCT => {
+                            let s = self.string_handler.as_mut().and_then(|h| h.product());
+                            s.or(self.config.product)
+                        }
+                        STRING_INDEX_SERIAL_NUMBER => {
+                            let s = self.string_handler.as_mut().and_then(|h| h.serial_number());
+                            s.or(self.config.serial_number)
+                        }
                         _ => {
                             // Find out which iface owns this string index.
                             let mut index_left = index - STRING_INDEX_CUSTOM_START;
@@ -0,0 +1,452 @@
+//! Microsoft OS 2.0 descriptors.
+//!
+//! These let a device tell Windows (8.1 and later) which driver to bind to it, most commonly
+//! WinUSB, without shipping an INF file. The descriptor set is served through a vendor request
+//! advertised by a platform capability in the BOS descriptor, both handled by
+//! [`UsbDevice`](crate::UsbDevice) once enabled with [`Builder::msos_descriptor`](crate::Builder::msos_descriptor).
+//!
+//! See the "Microsoft OS 2.0 Descriptors Specification" for details.
+
+use crate::types::InterfaceNumber;
+
+/// Windows version constants, for [`Builder::msos_descriptor`](crate::Builder::msos_descriptor).
+pub mod windows_version {
+    /// Windows 8.1, the first version to support Microsoft OS 2.0 descriptors.
+    pub const WIN8_1: u32 = 0x06030000;
+    /// Windows 10.
+    pub const WIN10: u32 = 0x0A000000;
+}
+
+/// `wIndex` of the vendor request retrieving the descriptor set.
+pub(crate) const MS_OS_20_DESCRIPTOR_INDEX: u16 = 7;
+
+/// UUID of the Microsoft OS 2.0 platform capability, {D8DD60DF-4589-4CC7-9CD2-659D9E648A9F}.
+const PLATFORM_CAPABILITY_UUID: [u8; 16] = [
+    0xDF, 0x60, 0xDD, 0xD8, 0x89, 0x45, 0xC7, 0x4C, 0x9C, 0xD2, 0x65, 0x9D, 0x9E, 0x64, 0x8A, 0x9F,
+];
+
+/// Microsoft OS 2.0 descriptor types.
+#[repr(u16)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DescriptorType {
+    /// Descriptor set header.
+    SetHeaderDescriptor = 0,
+    /// Configuration subset header.
+    SubsetHeaderConfiguration = 1,
+    /// Function subset header.
+    SubsetHeaderFunction = 2,
+    /// Compatible ID feature descriptor.
+    FeatureCompatibleId = 3,
+    /// Registry property feature descriptor.
+    FeatureRegProperty = 4,
+    /// Minimum USB resume time feature descriptor.
+    FeatureMinResumeTime = 5,
+    /// Model ID feature descriptor.
+    FeatureModelId = 6,
+    /// CCGP device feature descriptor.
+    FeatureCcgpDevice = 7,
+    /// Vendor revision feature descriptor.
+    FeatureVendorRevision = 8,
+}
+
+/// A Microsoft OS 2.0 feature descriptor.
+pub trait Descriptor {
+    /// The descriptor type.
+    const TYPE: DescriptorType;
+
+    /// Total size of the descriptor, in bytes, including its `wLength` and `wDescriptorType`.
+    fn size(&self) -> usize;
+
+    /// Writes the descriptor fields following `wLength` and `wDescriptorType` to `buf`.
+    ///
+    /// `buf` is exactly `size() - 4` bytes long.
+    fn write_to(&self, buf: &mut [u8]);
+}
+
+/// A feature descriptor that applies to the whole device.
+pub trait DeviceLevelDescriptor: Descriptor {}
+
+/// A feature descriptor that applies to a single function.
+pub trait FunctionLevelDescriptor: Descriptor {}
+
+/// Compatible ID feature descriptor, telling Windows which driver to load.
+///
+/// Both IDs are ASCII strings of up to 8 characters, padded with zeros.
+pub struct CompatibleIdFeatureDescriptor {
+    compatible_id: [u8; 8],
+    sub_compatible_id: [u8; 8],
+}
+
+impl CompatibleIdFeatureDescriptor {
+    /// Creates a compatible ID descriptor.
+    ///
+    /// Panics if an ID is longer than 8 characters.
+    pub fn new(compatible_id: &str, sub_compatible_id: &str) -> Self {
+        Self {
+            compatible_id: id_bytes(compatible_id),
+            sub_compatible_id: id_bytes(sub_compatible_id),
+        }
+    }
+
+    /// Creates the descriptor binding the WinUSB driver.
+    pub fn winusb() -> Self {
+        Self::new("WINUSB", "")
+    }
+}
+
+impl Descriptor for CompatibleIdFeatureDescriptor {
+    const TYPE: DescriptorType = DescriptorType::FeatureCompatibleId;
+
+    fn size(&self) -> usize {
+        20
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        buf[..8].copy_from_slice(&self.compatible_id);
+        buf[8..].copy_from_slice(&self.sub_compatible_id);
+    }
+}
+
+impl DeviceLevelDescriptor for CompatibleIdFeatureDescriptor {}
+impl FunctionLevelDescriptor for CompatibleIdFeatureDescriptor {}
+
+fn id_bytes(id: &str) -> [u8; 8] {
+    if id.len() > 8 {
+        panic!("compatible IDs are at most 8 characters long");
+    }
+    let mut bytes = [0; 8];
+    bytes[..id.len()].copy_from_slice(id.as_bytes());
+    bytes
+}
+
+/// Data of a registry property.
+#[derive(Copy, Clone, Debug)]
+pub enum PropertyData<'a> {
+    /// A string, `REG_SZ`.
+    Sz(&'a str),
+    /// A string with environment variable references, `REG_EXPAND_SZ`.
+    ExpandSz(&'a str),
+    /// Free-form binary data, `REG_BINARY`.
+    Binary(&'a [u8]),
+    /// A little-endian 32-bit number, `REG_DWORD_LITTLE_ENDIAN`.
+    DwordLittleEndian(u32),
+    /// A big-endian 32-bit number, `REG_DWORD_BIG_ENDIAN`.
+    DwordBigEndian(u32),
+    /// A symbolic link, `REG_LINK`.
+    Link(&'a str),
+    /// A list of strings, `REG_MULTI_SZ`.
+    RegMultiSz(&'a [&'a str]),
+}
+
+impl<'a> PropertyData<'a> {
+    fn data_type(&self) -> u16 {
+        match self {
+            PropertyData::Sz(_) => 1,
+            PropertyData::ExpandSz(_) => 2,
+            PropertyData::Binary(_) => 3,
+            PropertyData::DwordLittleEndian(_) => 4,
+            PropertyData::DwordBigEndian(_) => 5,
+            PropertyData::Link(_) => 6,
+            PropertyData::RegMultiSz(_) => 7,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PropertyData::Sz(s) | PropertyData::ExpandSz(s) | PropertyData::Link(s) => utf16_size(s),
+            PropertyData::Binary(b) => b.len(),
+            PropertyData::DwordLittleEndian(_) | PropertyData::DwordBigEndian(_) => 4,
+            PropertyData::RegMultiSz(strs) => strs.iter().map(|s| utf16_size(s)).sum::<usize>() + 2,
+        }
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        match self {
+            PropertyData::Sz(s) | PropertyData::ExpandSz(s) | PropertyData::Link(s) => {
+                write_utf16(buf, s);
+            }
+            PropertyData::Binary(b) => buf.copy_from_slice(b),
+            PropertyData::DwordLittleEndian(v) => buf.copy_from_slice(&v.to_le_bytes()),
+            PropertyData::DwordBigEndian(v) => buf.copy_from_slice(&v.to_be_bytes()),
+            PropertyData::RegMultiSz(strs) => {
+                let mut pos = 0;
+                for s in strs.iter() {
+                    pos += write_utf16(&mut buf[pos..], s);
+                }
+                buf[pos..pos + 2].fill(0);
+            }
+        }
+    }
+}
+
+/// Size of a nul-terminated UTF-16 string.
+fn utf16_size(s: &str) -> usize {
+    (s.encode_utf16().count() + 1) * 2
+}
+
+/// Writes a nul-terminated UTF-16 string, returning the number of bytes written.
+fn write_utf16(buf: &mut [u8], s: &str) -> usize {
+    let mut pos = 0;
+    for c in s.encode_utf16().chain(core::iter::once(0)) {
+        buf[pos..pos + 2].copy_from_slice(&c.to_le_bytes());
+        pos += 2;
+    }
+    pos
+}
+
+/// Registry property feature descriptor, adding a property to the device's registry key.
+pub struct RegistryPropertyFeatureDescriptor<'a> {
+    name: &'a str,
+    data: PropertyData<'a>,
+}
+
+impl<'a> RegistryPropertyFeatureDescriptor<'a> {
+    /// Creates a registry property descriptor.
+    pub fn new(name: &'a str, data: PropertyData<'a>) -> Self {
+        Self { name, data }
+    }
+
+    /// Creates the `DeviceInterfaceGUIDs` property, through which applications find a WinUSB device.
+    ///
+    /// GUIDs are formatted with braces, such as `"{6B75AE32-3D0C-4E8E-9C2D-8A2C9A1F2B4D}"`.
+    pub fn device_interface_guids(guids: &'a [&'a str]) -> Self {
+        Self::new("DeviceInterfaceGUIDs", PropertyData::RegMultiSz(guids))
+    }
+}
+
+impl<'a> Descriptor for RegistryPropertyFeatureDescriptor<'a> {
+    const TYPE: DescriptorType = DescriptorType::FeatureRegProperty;
+
+    fn size(&self) -> usize {
+        10 + utf16_size(self.name) + self.data.size()
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        let name_size = utf16_size(self.name);
+        let data_size = self.data.size();
+
+        buf[0..2].copy_from_slice(&self.data.data_type().to_le_bytes());
+        buf[2..4].copy_from_slice(&(name_size as u16).to_le_bytes());
+        write_utf16(&mut buf[4..4 + name_size], self.name);
+        let pos = 4 + name_size;
+        buf[pos..pos + 2].copy_from_slice(&(data_size as u16).to_le_bytes());
+        self.data.write_to(&mut buf[pos + 2..pos + 2 + data_size]);
+    }
+}
+
+impl<'a> DeviceLevelDescriptor for RegistryPropertyFeatureDescriptor<'a> {}
+impl<'a> FunctionLevelDescriptor for RegistryPropertyFeatureDescriptor<'a> {}
+
+/// Minimum USB resume time feature descriptor.
+pub struct MinimumRecoveryTimeDescriptor {
+    /// Time to wait after resume before sending traffic to the device, in milliseconds, up to 10.
+    pub resume_recovery_time: u8,
+    /// Time the device needs to signal remote wakeup, in milliseconds, from 1 to 20.
+    pub resume_signaling_time: u8,
+}
+
+impl Descriptor for MinimumRecoveryTimeDescriptor {
+    const TYPE: DescriptorType = DescriptorType::FeatureMinResumeTime;
+
+    fn size(&self) -> usize {
+        6
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        buf[0] = self.resume_recovery_time;
+        buf[1] = self.resume_signaling_time;
+    }
+}
+
+impl DeviceLevelDescriptor for MinimumRecoveryTimeDescriptor {}
+
+/// Model ID feature descriptor, a UUID identifying the physical device across its interfaces.
+pub struct ModelIdDescriptor {
+    /// The model UUID, in the order its bytes are sent.
+    pub model_id: [u8; 16],
+}
+
+impl Descriptor for ModelIdDescriptor {
+    const TYPE: DescriptorType = DescriptorType::FeatureModelId;
+
+    fn size(&self) -> usize {
+        20
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.model_id);
+    }
+}
+
+impl DeviceLevelDescriptor for ModelIdDescriptor {}
+
+/// CCGP device feature descriptor, making Windows treat the device as composite.
+pub struct CcgpDeviceDescriptor;
+
+impl Descriptor for CcgpDeviceDescriptor {
+    const TYPE: DescriptorType = DescriptorType::FeatureCcgpDevice;
+
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn write_to(&self, _buf: &mut [u8]) {}
+}
+
+impl DeviceLevelDescriptor for CcgpDeviceDescriptor {}
+
+/// Vendor revision feature descriptor.
+///
+/// Windows reads the descriptor set again when the revision changes.
+pub struct VendorRevisionDescriptor {
+    /// The revision, starting from 1.
+    pub revision: u16,
+}
+
+impl Descriptor for VendorRevisionDescriptor {
+    const TYPE: DescriptorType = DescriptorType::FeatureVendorRevision;
+
+    fn size(&self) -> usize {
+        6
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.revision.to_le_bytes());
+    }
+}
+
+impl DeviceLevelDescriptor for VendorRevisionDescriptor {}
+impl FunctionLevelDescriptor for VendorRevisionDescriptor {}
+
+/// A writer for the Microsoft OS 2.0 descriptor set.
+pub(crate) struct MsOsDescriptorWriter<'d> {
+    buf: &'d mut [u8],
+    position: usize,
+    windows_version: u32,
+    vendor_code: u8,
+    config_mark: Option<usize>,
+    function_mark: Option<usize>,
+    first_interface: Option<InterfaceNumber>,
+}
+
+impl<'d> MsOsDescriptorWriter<'d> {
+    pub(crate) fn new(buf: &'d mut [u8], windows_version: u32, vendor_code: u8) -> Self {
+        let mut writer = Self {
+            buf,
+            position: 0,
+            windows_version,
+            vendor_code,
+            config_mark: None,
+            function_mark: None,
+            first_interface: None,
+        };
+
+        let mut header = [0; 6];
+        header[..4].copy_from_slice(&windows_version.to_le_bytes());
+        // wTotalLength is filled in by `end`.
+        writer.write_raw(DescriptorType::SetHeaderDescriptor, 10, &header);
+        writer
+    }
+
+    fn write_raw(&mut self, descriptor_type: DescriptorType, length: usize, data: &[u8]) {
+        let start = self.position;
+        self.reserve(length);
+        self.buf[start..start + 2].copy_from_slice(&(length as u16).to_le_bytes());
+        self.buf[start + 2..start + 4].copy_from_slice(&(descriptor_type as u16).to_le_bytes());
+        self.buf[start + 4..start + length].copy_from_slice(data);
+    }
+
+    fn reserve(&mut self, length: usize) {
+        if self.position + length > self.buf.len() || self.position + length > u16::MAX as usize {
+            panic!("MS OS descriptor buffer full");
+        }
+        self.position += length;
+    }
+
+    fn write<T: Descriptor>(&mut self, desc: &T) {
+        let start = self.position;
+        let length = desc.size();
+        self.reserve(length);
+        self.buf[start..start + 2].copy_from_slice(&(length as u16).to_le_bytes());
+        self.buf[start + 2..start + 4].copy_from_slice(&(T::TYPE as u16).to_le_bytes());
+        desc.write_to(&mut self.buf[start + 4..start + length]);
+    }
+
+    /// Writes a feature descriptor applying to the whole device.
+    pub(crate) fn device_feature<T: DeviceLevelDescriptor>(&mut self, desc: T) {
+        if self.config_mark.is_some() {
+            panic!("device level MS OS features must be added before function level ones");
+        }
+        self.write(&desc);
+    }
+
+    /// Writes a feature descriptor applying to the function starting at `first_interface`.
+    pub(crate) fn function_feature<T: FunctionLevelDescriptor>(&mut self, first_interface: InterfaceNumber, desc: T) {
+        if self.config_mark.is_none() {
+            self.config_mark = Some(self.position);
+            // bConfigurationValue is the configuration index, the only one being 0.
+            self.write_raw(DescriptorType::SubsetHeaderConfiguration, 8, &[0, 0, 0, 0]);
+        }
+
+        if self.first_interface != Some(first_interface) {
+            self.end_function();
+            self.first_interface = Some(first_interface);
+            self.function_mark = Some(self.position);
+            self.write_raw(DescriptorType::SubsetHeaderFunction, 8, &[first_interface.0, 0, 0, 0]);
+        }
+
+        self.write(&desc);
+    }
+
+    fn end_function(&mut self) {
+        if let Some(mark) = self.function_mark.take() {
+            let length = (self.position - mark) as u16;
+            self.buf[mark + 6..mark + 8].copy_from_slice(&length.to_le_bytes());
+        }
+    }
+
+    /// Finishes the descriptor set, and returns the BOS platform capability advertising it.
+    ///
+    /// Windows only reads subsets on composite devices. On other devices, a single function
+    /// subset is dropped and its features apply to the whole device instead.
+    pub(crate) fn end(mut self, composite: bool) -> (MsOsDescriptorSet<'d>, [u8; 25]) {
+        self.end_function();
+        if let (false, Some(mark)) = (composite, self.config_mark) {
+            // The configuration subset header is directly followed by the first function subset header.
+            let function_length = u16::from_le_bytes([self.buf[mark + 14], self.buf[mark + 15]]) as usize;
+            if mark + 8 + function_length == self.position {
+                self.buf.copy_within(mark + 16..self.position, mark);
+                self.position -= 16;
+                self.config_mark = None;
+            }
+        }
+        if let Some(mark) = self.config_mark.take() {
+            let length = (self.position - mark) as u16;
+            self.buf[mark + 6..mark + 8].copy_from_slice(&length.to_le_bytes());
+        }
+
+        let total_length = self.position as u16;
+        self.buf[8..10].copy_from_slice(&total_length.to_le_bytes());
+
+        let mut capability = [0; 25];
+        // capability[0] is bReserved.
+        capability[1..17].copy_from_slice(&PLATFORM_CAPABILITY_UUID);
+        capability[17..21].copy_from_slice(&self.windows_version.to_le_bytes());
+        capability[21..23].copy_from_slice(&total_length.to_le_bytes());
+        capability[23] = self.vendor_code;
+        // capability[24] is bAltEnumCode, alternate enumeration is not supported.
+
+        let set = MsOsDescriptorSet {
+            descriptor: &self.buf[..self.position],
+            vendor_code: self.vendor_code,
+        };
+        (set, capability)
+    }
+}
+
+/// A finished Microsoft OS 2.0 descriptor set, served by the device.
+pub(crate) struct MsOsDescriptorSet<'d> {
+    pub(crate) descriptor: &'d [u8],
+    pub(crate) vendor_code: u8,
+}
@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![macro_use]
+#![feature(type_alias_impl_trait)]
+
+use core::mem;
+
+use embassy_boot_nrf::FirmwareUpdater;
+use embassy_executor::Spawner;
+use embassy_futures::join::join;
+use embassy_nrf::nvmc::Nvmc;
+use embassy_nrf::usb::{Driver, PowerUsb};
+use embassy_nrf::{interrupt, pac};
+use embassy_time::{Duration, Timer};
+use embassy_usb::class::dfu::{self, DfuMode, State};
+use embassy_usb::{Builder, Config};
+use panic_reset as _;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // This firmware may be an update that was just swapped in, it works well enough to keep it
+    let mut nvmc = Nvmc::new(p.NVMC);
+    let mut magic = [0; 4];
+    FirmwareUpdater::default()
+        .mark_booted_blocking(&mut nvmc, &mut magic)
+        .unwrap();
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB-DFU example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors, and a control buffer holding a whole
+    // block of firmware.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 4096];
+
+    let mut state = State::new();
+    let mut block = [0; 4096];
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // Blocks of a flash page, written to the DFU partition.
+    let dfu_config = dfu::Config {
+        detach_timeout: 1000,
+        transfer_size: 4096,
+    };
+    let dfu = DfuMode::new(
+        &mut builder,
+        &mut state,
+        nvmc,
+        FirmwareUpdater::default(),
+        &mut block,
+        &dfu_config,
+    );
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device, and reset into the bootloader once an update is downloaded.
+    let dfu_fut = async {
+        dfu.wait_manifested().await;
+        Timer::after(Duration::from_millis(100)).await;
+        cortex_m::peripheral::SCB::sys_reset();
+    };
+    join(usb.run(), dfu_fut).await;
+}
@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::config::{Config, HfclkSource};
+use embassy_nrf::interrupt;
+use embassy_nrf::radio::ble::{self, Address, Radio};
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut config = Config::default();
+    config.hfclk_source = HfclkSource::ExternalXtal;
+    let p = embassy_nrf::init(config);
+
+    let irq = interrupt::take!(RADIO);
+    let mut radio = Radio::new(p.RADIO, irq, ble::Config::default());
+
+    let address = Address::from_ficr();
+    info!("advertising as {:02x}", address.bytes);
+
+    #[rustfmt::skip]
+    let data = [
+        // Flags: LE General Discoverable, BR/EDR not supported.
+        0x02, 0x01, 0x06,
+        // Complete local name.
+        0x07, 0x09, b'e', b'm', b'b', b'a', b's', b's',
+    ];
+
+    loop {
+        radio.advertise(&address, &data).await.unwrap();
+        Timer::after(Duration::from_millis(100)).await;
+    }
+}
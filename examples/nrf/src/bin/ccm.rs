@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use embassy_nrf::ccm::{Ccm, Direction, Packet};
+use embassy_nrf::interrupt;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let key = [0x42; 16];
+    let iv = [0x24; 8];
+    let mut ccm = Ccm::new(p.CCM, interrupt::take!(CCM_AAR), key, iv);
+
+    let plain = Packet::new(0x02, b"Hello CCM!");
+    let mut encrypted = Packet::empty();
+    let mut decrypted = Packet::empty();
+
+    for counter in 0..4 {
+        unwrap!(
+            ccm.encrypt(counter, Direction::MasterToSlave, &plain, &mut encrypted)
+                .await
+        );
+        info!("encrypted: {:x}", encrypted.payload());
+
+        unwrap!(
+            ccm.decrypt(counter, Direction::MasterToSlave, &encrypted, &mut decrypted)
+                .await
+        );
+        info!("decrypted: {:a}", decrypted.payload());
+    }
+
+    // Decrypting with the wrong counter fails authentication.
+    let res = ccm
+        .decrypt(5, Direction::MasterToSlave, &encrypted, &mut decrypted)
+        .await;
+    info!("decrypt with wrong counter: {:?}", res);
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::egu::Egu;
+use embassy_nrf::gpio::{Input, Pull};
+use embassy_nrf::gpiote::{InputChannel, InputChannelPolarity};
+use embassy_nrf::interrupt;
+use embassy_nrf::ppi::Ppi;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+
+    let button = InputChannel::new(
+        p.GPIOTE_CH0,
+        Input::new(p.P0_11, Pull::Up),
+        InputChannelPolarity::HiToLo,
+    );
+
+    let irq = interrupt::take!(SWI0_EGU0);
+    let mut egu = Egu::new(p.EGU0, irq);
+    let mut pressed = egu.channel(0);
+
+    // Button press -> EGU event, without going through the GPIOTE interrupt.
+    let mut ppi = Ppi::new_one_to_one(p.PPI_CH0, button.event_in(), pressed.task());
+    ppi.enable();
+
+    loop {
+        pressed.wait().await;
+        info!("Button pressed!");
+    }
+}
@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::config::{Config, HfclkSource};
+use embassy_nrf::interrupt;
+use embassy_nrf::radio::esb::{self, Esb};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut config = Config::default();
+    config.hfclk_source = HfclkSource::ExternalXtal;
+    let p = embassy_nrf::init(config);
+
+    let irq = interrupt::take!(RADIO);
+    let mut esb = Esb::new(p.RADIO, irq, esb::Config::default());
+
+    let mut received = 0u32;
+    loop {
+        // Sent back to the transmitter with the acknowledgement of the next packet.
+        esb.set_ack_payload(0, &received.to_le_bytes()).unwrap();
+
+        let packet = esb.receive().await;
+        info!("pipe {}: {:02x}", packet.pipe, packet.payload);
+        received += 1;
+    }
+}
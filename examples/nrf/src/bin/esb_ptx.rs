@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::{info, warn};
+use embassy_executor::Spawner;
+use embassy_nrf::config::{Config, HfclkSource};
+use embassy_nrf::interrupt;
+use embassy_nrf::radio::esb::{self, Esb};
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut config = Config::default();
+    config.hfclk_source = HfclkSource::ExternalXtal;
+    let p = embassy_nrf::init(config);
+
+    let irq = interrupt::take!(RADIO);
+    let mut esb = Esb::new(p.RADIO, irq, esb::Config::default());
+
+    let mut counter = 0u32;
+    loop {
+        match esb.send(0, &counter.to_le_bytes()).await {
+            Ok(ack) => info!(
+                "sent {}, {} retransmits, ack payload {:02x}",
+                counter, ack.retransmits, ack.payload
+            ),
+            Err(e) => warn!("send failed: {:?}", e),
+        }
+        counter += 1;
+        Timer::after(Duration::from_millis(500)).await;
+    }
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use embassy_nrf::gpio::{AnyPin, Input, Pin as _, Pull};
+use embassy_nrf::gpiote::{InputChannel, InputChannelPolarity};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::task(pool_size = 3)]
+async fn sense_task(n: usize, mut pin: Input<'static, AnyPin>) {
+    // Sense mode: no GPIOTE channel is used, so any number of pins can be awaited.
+    loop {
+        pin.wait_for_any_edge().await;
+        info!("Button {:?} changed (sense mode)", n);
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    info!("Starting!");
+
+    let btn2 = Input::new(p.P0_12.degrade(), Pull::Up);
+    let btn3 = Input::new(p.P0_24.degrade(), Pull::Up);
+    let btn4 = Input::new(p.P0_25.degrade(), Pull::Up);
+
+    unwrap!(spawner.spawn(sense_task(2, btn2)));
+    unwrap!(spawner.spawn(sense_task(3, btn3)));
+    unwrap!(spawner.spawn(sense_task(4, btn4)));
+
+    // Channel mode: button 1 gets a dedicated GPIOTE channel for low-latency edge detection.
+    let mut button1 = InputChannel::new(
+        p.GPIOTE_CH0,
+        Input::new(p.P0_11, Pull::Up),
+        InputChannelPolarity::HiToLo,
+    );
+
+    loop {
+        button1.wait_for_falling_edge().await;
+        info!("Button 1 pressed (channel mode)");
+        button1.wait_for_high().await;
+        info!("Button 1 released (channel mode)");
+    }
+}
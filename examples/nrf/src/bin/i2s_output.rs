@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::i2s::{self, I2s, MasterClock, MckFreq, Ratio, StreamState};
+use embassy_nrf::interrupt;
+use {defmt_rtt as _, panic_probe as _};
+
+// Plays a 440 Hz square wave on a MAX98357A amplifier.
+#[embassy_executor::main]
+async fn main(_p: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+
+    // 32 MHz / 23 / 64 = ~21.7 kHz
+    let master_clock = MasterClock::new(MckFreq::_32MDiv23, Ratio::_64x);
+    let sample_rate = master_clock.sample_rate();
+    info!("Sample rate: {}", sample_rate);
+
+    let mut config = i2s::Config::default();
+    config.channels = i2s::Channels::MonoLeft;
+
+    let irq = interrupt::take!(I2S);
+    let mut output = I2s::new_master(p.I2S, irq, p.P0_28, p.P0_29, master_clock, config).output(p.P0_30);
+
+    let half_period = sample_rate / 440 / 2;
+    let mut phase = 0;
+    let mut bufs = [[0i16; 512]; 2];
+    output
+        .stream(&mut bufs, |buf| {
+            for sample in buf.iter_mut() {
+                *sample = if phase < half_period { 4096 } else { -4096 };
+                phase = (phase + 1) % (2 * half_period);
+            }
+            StreamState::Running
+        })
+        .await
+        .unwrap();
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::config::{Config, HfclkSource};
+use embassy_nrf::interrupt;
+use embassy_nrf::radio::ieee802154::{self, Packet, Radio};
+use {defmt_rtt as _, panic_probe as _};
+
+// Logs every 802.15.4 frame received on channel 15.
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut config = Config::default();
+    config.hfclk_source = HfclkSource::ExternalXtal;
+    let p = embassy_nrf::init(config);
+
+    let mut config = ieee802154::Config::default();
+    config.channel = 15;
+    config.promiscuous = true;
+
+    let irq = interrupt::take!(RADIO);
+    let mut radio = Radio::new(p.RADIO, irq, config);
+
+    let mut packet = Packet::new();
+    loop {
+        radio.receive(&mut packet).await;
+        info!(
+            "{}: lqi {} {:02x}",
+            packet.timestamp().as_micros(),
+            packet.lqi(),
+            &packet[..]
+        );
+    }
+}
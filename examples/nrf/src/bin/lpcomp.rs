@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::interrupt;
+use embassy_nrf::lpcomp::{Config, Level, Lpcomp, Reference};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_p: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+
+    let mut config = Config::default();
+    config.reference = Reference::Vdd5_8;
+    config.hysteresis = true;
+
+    let irq = interrupt::take!(COMP_LPCOMP);
+    let mut lpcomp = Lpcomp::new(p.LPCOMP, irq, p.P0_02, config);
+
+    info!("initial level: {}", lpcomp.sample());
+
+    loop {
+        match lpcomp.wait_for_cross().await {
+            Level::Above => info!("input rose above 5/8 VDD"),
+            Level::Below => info!("input fell below 5/8 VDD"),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use embassy_nrf::interrupt;
+use embassy_nrf::nfct::{ndef, Config, Error, NfcId, NfcT, Protocol, Type2Tag};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_p: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+
+    let uid = [0x5F, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+    let config = Config::new(NfcId::DoubleSize(uid), Protocol::Type2);
+    let irq = interrupt::take!(NFCT);
+    let mut nfct = NfcT::new(p.NFCT, irq, config);
+
+    // Tag memory: UID and lock bytes, capability container, then the NDEF message.
+    let mut memory = [0u8; 64];
+    memory[..3].copy_from_slice(&uid[..3]);
+    memory[4..8].copy_from_slice(&uid[3..]);
+    let mut message = [0u8; 32];
+    let len = unwrap!(ndef::write_uri_record(&mut message, "https://embassy.dev"));
+    unwrap!(ndef::write_type2_tlv(&mut memory, &message[..len]));
+
+    loop {
+        nfct.wait_for_field().await;
+        info!("field detected");
+
+        match Type2Tag::new(&mut nfct, &mut memory).run().await {
+            Ok(()) => info!("halted by reader"),
+            Err(Error::FieldLost) => info!("field lost"),
+            Err(e) => info!("error: {}", e),
+        }
+    }
+}
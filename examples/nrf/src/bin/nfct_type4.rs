@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use embassy_nrf::interrupt;
+use embassy_nrf::nfct::{ndef, Config, Error, NfcId, NfcT, Protocol, Type4Tag};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_p: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+
+    let uid = [0x5F, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+    let config = Config::new(NfcId::DoubleSize(uid), Protocol::Type4);
+    let irq = interrupt::take!(NFCT);
+    let mut nfct = NfcT::new(p.NFCT, irq, config);
+
+    // NDEF file: length of the message, then the message. Readers may overwrite it.
+    let mut file = [0u8; 256];
+    let mut message = [0u8; 64];
+    let len = unwrap!(ndef::write_text_record(&mut message, "en", "Hello from embassy"));
+    unwrap!(ndef::write_type4_file(&mut file, &message[..len]));
+
+    loop {
+        nfct.wait_for_field().await;
+        info!("field detected");
+
+        match Type4Tag::new(&mut nfct, &mut file, true).run().await {
+            Ok(()) => info!("deselected by reader"),
+            Err(Error::FieldLost) => info!("field lost"),
+            Err(e) => info!("error: {}", e),
+        }
+    }
+}
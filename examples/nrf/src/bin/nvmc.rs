@@ -28,6 +28,12 @@ async fn main(_spawner: Spawner) {
     info!("Erasing...");
     unwrap!(f.erase(ADDR, ADDR + 4096));
 
+    info!("Writing...");
+    unwrap!(f.write(ADDR, &[5, 6, 7, 8]));
+
+    info!("Erasing without stalling other tasks...");
+    unwrap!(f.erase_partial(ADDR, ADDR + 4096).await);
+
     info!("Reading...");
     let mut buf = [0u8; 4];
     unwrap!(f.read(ADDR, &mut buf));
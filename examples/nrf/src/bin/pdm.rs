@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::interrupt;
+use embassy_nrf::pdm::{Config, Pdm, SamplerState};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_p: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let irq = interrupt::take!(PDM);
+    let mut pdm = Pdm::new(p.PDM, irq, p.P0_01, p.P0_00, Config::default());
+
+    // Let the decimation filter settle before looking at the samples.
+    let mut buf = [0i16; 1024];
+    pdm.sample(&mut buf).await.unwrap();
+
+    let mut bufs = [[0i16; 256]; 2];
+    let mut count = 0;
+    pdm.run_sampler(&mut bufs, move |buf| {
+        let mean = buf.iter().map(|v| i32::from(*v)).sum::<i32>() / buf.len() as i32;
+        info!("mean: {=i32}", mean);
+        count += 1;
+        if count < 100 {
+            SamplerState::Sampled
+        } else {
+            SamplerState::Stopped
+        }
+    })
+    .await
+    .unwrap();
+    info!("done sampling");
+}
@@ -4,6 +4,8 @@
 
 use defmt::*;
 use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_nrf::interrupt;
 use embassy_nrf::pwm::{Config, Prescaler, SequenceConfig, SequencePwm, SingleSequenceMode, SingleSequencer};
 use embassy_time::{Duration, Timer};
 use {defmt_rtt as _, panic_probe as _};
@@ -21,9 +23,9 @@ async fn main(_spawner: Spawner) {
     // want 5000/8 = 625 periods total to occur, so 624 (we get the one period for free remember)
     let mut seq_config = SequenceConfig::default();
     seq_config.refresh = 624;
-    // thus our sequence takes 5 * 5000ms or 25 seconds
+    // thus our sequence takes 5 * 5000ms or 25 seconds, more than we're willing to wait
 
-    let mut pwm = unwrap!(SequencePwm::new_1ch(p.PWM0, p.P0_13, config,));
+    let mut pwm = unwrap!(SequencePwm::new_1ch(p.PWM0, p.P0_13, config)).with_interrupt(interrupt::take!(PWM0));
 
     let sequencer = SingleSequencer::new(&mut pwm, &seq_words, seq_config);
     unwrap!(sequencer.start(SingleSequenceMode::Times(1)));
@@ -31,6 +33,8 @@ async fn main(_spawner: Spawner) {
     // we can abort a sequence if we need to before its complete with pwm.stop()
     // or stop is also implicitly called when the pwm peripheral is dropped
     // when it goes out of scope
-    Timer::after(Duration::from_millis(20000)).await;
-    info!("pwm stopped early!");
+    match select(sequencer.wait_complete(), Timer::after(Duration::from_millis(20000))).await {
+        Either::First(()) => info!("pwm sequence complete!"),
+        Either::Second(()) => info!("pwm stopped early!"),
+    }
 }
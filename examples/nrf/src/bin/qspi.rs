@@ -72,5 +72,16 @@ async fn main(_spawner: Spawner) {
         }
     }
 
+    info!("verifying through XIP...");
+    let xip = q.xip();
+    for j in 0..8 * PAGE_SIZE {
+        assert_eq!(xip[j], pattern(j as u32));
+    }
+
+    // Read the SFDP header, which is longer than what fits in a regular custom instruction.
+    let mut sfdp = [0; 12];
+    unwrap!(q.custom_instruction_long(0x5A, &[0, 0, 0, 0], &mut sfdp).await);
+    info!("sfdp: {:x}", &sfdp[4..]);
+
     info!("done!")
 }
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::interrupt;
+use embassy_nrf::rtc::Rtc;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+
+    // 32768 Hz / (4095 + 1) = 8 Hz
+    let mut rtc = Rtc::new(p.RTC2, interrupt::take!(RTC2), 4095);
+    rtc.start();
+
+    for _ in 0..16 {
+        rtc.wait_tick().await;
+        info!("tick, counter = {}", rtc.counter());
+    }
+
+    loop {
+        // Wake up every 5 seconds.
+        let next = rtc.counter() + 5 * 8;
+        let mut cc = rtc.cc(0);
+        cc.write(next);
+        cc.wait().await;
+        info!("compare, counter = {}", rtc.counter());
+    }
+}
@@ -0,0 +1,52 @@
+//! Example on how to drive an ST7789 display with SPIM3 at 32 MHz, using the hardware
+//! D/CX pin so commands and their parameters go out in a single transfer.
+//!
+//! Connect SCK to P0.29, MOSI to P0.30, D/CX to P0.28 and CS to P0.31
+
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use embassy_nrf::gpio::{Level, Output, OutputDrive};
+use embassy_nrf::{interrupt, spim};
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    info!("running!");
+
+    let mut config = spim::Config::default();
+    config.frequency = spim::Frequency::M32;
+    config.mode = spim::MODE_3;
+
+    let irq = interrupt::take!(SPIM3);
+    let mut spim = spim::Spim::new_txonly_with_dcx(p.SPI3, irq, p.P0_29, p.P0_30, p.P0_28, config);
+
+    let mut ncs = Output::new(p.P0_31, Level::High, OutputDrive::Standard);
+    ncs.set_low();
+
+    // SLPOUT, a command without parameters.
+    unwrap!(spim.write_dcx(1, &[0x11]).await);
+    Timer::after(Duration::from_millis(120)).await;
+
+    // COLMOD: 16 bits per pixel. The first byte is the command, the second one its parameter.
+    unwrap!(spim.write_dcx(1, &[0x3A, 0x55]).await);
+
+    // DISPON
+    unwrap!(spim.write_dcx(1, &[0x29]).await);
+
+    // Fill a line with red pixels: RAMWR followed by the pixel data.
+    let mut line = [0u8; 1 + 240 * 2];
+    line[0] = 0x2C;
+    for pixel in line[1..].chunks_mut(2) {
+        pixel.copy_from_slice(&[0xF8, 0x00]);
+    }
+    unwrap!(spim.write_dcx(1, &line).await);
+
+    ncs.set_high();
+    info!("done!");
+}
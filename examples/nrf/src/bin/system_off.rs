@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_nrf::gpio::{Level, Output, OutputDrive, Pull};
+use embassy_nrf::power;
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+
+    let reason = power::reset_reason();
+    power::clear_reset_reason();
+    if reason.system_off_gpio() {
+        info!("Woken up from System OFF by button 1!");
+    } else {
+        info!("Reset reason: {:x}", reason.bits());
+    }
+
+    let mut led = Output::new(p.P0_13, Level::Low, OutputDrive::Standard);
+    Timer::after(Duration::from_secs(3)).await;
+    led.set_high();
+
+    info!("Entering System OFF, press button 1 to wake up");
+    power::wake_on_pin(p.P0_11, Pull::Up, Level::Low);
+    power::system_off();
+}
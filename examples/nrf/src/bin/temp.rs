@@ -17,7 +17,7 @@ async fn main(_spawner: Spawner) {
 
     loop {
         let value = temp.read().await;
-        info!("temperature: {}℃", value.to_num::<u16>());
+        info!("temperature: {}℃", value.to_num::<f32>());
         Timer::after(Duration::from_secs(1)).await;
     }
 }
@@ -0,0 +1,46 @@
+//! Example on how to write a page of a 24C/24LC i2c eeprom, with the memory address
+//! and the data in separate buffers, then read it back.
+//!
+//! Connect SDA to P0.03, SCL to P0.04
+
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_nrf::interrupt;
+use embassy_nrf::twim::{self, Operation, Twim};
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+const ADDRESS: u8 = 0x50;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    info!("Initializing TWI...");
+    let config = twim::Config::default();
+    let irq = interrupt::take!(SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0);
+    let mut twi = Twim::new(p.TWISPI0, irq, p.P0_03, p.P0_04, config);
+
+    let data = [0xC0, 0xFF, 0xEE, 0x42];
+
+    info!("Writing...");
+    unwrap!(
+        twi.transaction(ADDRESS, &mut [Operation::Write(&[0x00]), Operation::Write(&data)])
+            .await
+    );
+
+    // Wait for the write cycle to complete.
+    Timer::after(Duration::from_millis(5)).await;
+
+    info!("Reading...");
+    let mut buf = [0u8; 4];
+    unwrap!(
+        twi.transaction(ADDRESS, &mut [Operation::Write(&[0x00]), Operation::Read(&mut buf)])
+            .await
+    );
+
+    info!("Read: {=[u8]:x}", buf);
+}
@@ -17,6 +17,9 @@ async fn main(_spawner: Spawner) {
     let irq = interrupt::take!(UARTE0_UART0);
     let mut uart = uarte::UarteWithIdle::new(p.UARTE0, p.TIMER0, p.PPI_CH0, p.PPI_CH1, irq, p.P0_08, p.P0_06, config);
 
+    // Consider a message complete after 4 bytes worth of idle line.
+    uart.set_idle_timeout(40);
+
     info!("uarte initialized!");
 
     // Message must be in SRAM
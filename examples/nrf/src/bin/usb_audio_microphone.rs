@@ -0,0 +1,221 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::cell::RefCell;
+use core::mem;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::join::join4;
+use embassy_nrf::i2s::{self, I2s, MasterClock, MckFreq, Ratio, StreamState};
+use embassy_nrf::usb::{Driver, Instance, PowerUsb, UsbSupply};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::audio::{Config as AudioConfig, Controls, Microphone, RateEstimator, State};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::{Builder, Config};
+use {defmt_rtt as _, panic_probe as _};
+
+const SAMPLE_RATE: u32 = 48_000;
+// Largest packet: the samples of a 1 ms frame, plus one for the clock drift.
+const MAX_PACKET_SAMPLES: usize = (SAMPLE_RATE / 1000) as usize + 1;
+// Samples received from the I2S at once.
+const BLOCK_SAMPLES: usize = 64;
+// Samples kept in the FIFO when streaming starts, to absorb the jitter of the packet lengths.
+const FIFO_MARGIN: usize = 4 * MAX_PACKET_SAMPLES;
+
+/// Samples recorded by the I2S, waiting to be sent.
+struct Fifo {
+    buf: [i16; 1024],
+    start: usize,
+    len: usize,
+    /// Samples received since the I2S started.
+    count: u32,
+}
+
+impl Fifo {
+    fn push(&mut self, samples: &[i16]) {
+        for &sample in samples {
+            if self.len == self.buf.len() {
+                // Nobody is listening, drop the oldest sample.
+                self.start = (self.start + 1) % self.buf.len();
+                self.len -= 1;
+            }
+            self.buf[(self.start + self.len) % self.buf.len()] = sample;
+            self.len += 1;
+        }
+        self.count = self.count.wrapping_add(samples.len() as u32);
+    }
+
+    fn pop(&mut self) -> Option<i16> {
+        if self.len == 0 {
+            return None;
+        }
+        let sample = self.buf[self.start];
+        self.start = (self.start + 1) % self.buf.len();
+        self.len -= 1;
+        Some(sample)
+    }
+
+    /// Drops the oldest samples, keeping at most `len`.
+    fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Record from an I2S microphone, at 32 MHz / 21 / 32 = ~47.6 kHz: the nRF can't generate
+    // exactly 48 kHz, packets follow the rate of its clock.
+    let master_clock = MasterClock::new(MckFreq::_32MDiv21, Ratio::_32x);
+    let mut i2s_config = i2s::Config::default();
+    i2s_config.channels = i2s::Channels::MonoLeft;
+    let i2s_irq = interrupt::take!(I2S);
+    let mut input = I2s::new_master(p.I2S, i2s_irq, p.P0_28, p.P0_29, master_clock, i2s_config).input(p.P0_31);
+
+    // Create embassy-usb Config
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB microphone example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Required for audio functions, made of several interfaces.
+    config.device_class = 0xEF;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // Create classes on the builder.
+    // A mono, 16-bit microphone, sent on the isochronous IN endpoint.
+    let audio_config = AudioConfig {
+        channels: 1,
+        subslot_size: 2,
+        bit_resolution: 16,
+        sample_rates: &[SAMPLE_RATE],
+        min_volume: -60 * 256,
+        max_volume: 0,
+        volume_resolution: 256,
+        feedback: false,
+    };
+    let mut mic = Microphone::new(&mut builder, &mut state, &audio_config);
+    let controls = mic.controls();
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device.
+    let usb_fut = usb.run();
+
+    // Receive the samples of the I2S.
+    let fifo = RefCell::new(Fifo {
+        buf: [0; 1024],
+        start: 0,
+        len: 0,
+        count: 0,
+    });
+    let i2s_fut = async {
+        let mut bufs = [[0i16; BLOCK_SAMPLES]; 2];
+        unwrap!(
+            input
+                .stream(&mut bufs, |buf| {
+                    fifo.borrow_mut().push(buf);
+                    StreamState::Running
+                })
+                .await
+        );
+    };
+
+    // Send them while the host listens.
+    let mic_fut = async {
+        loop {
+            mic.wait_connection().await;
+            info!("Streaming");
+            let _ = stream(&mut mic, &controls, &fifo).await;
+            info!("Stopped");
+        }
+    };
+
+    let controls_fut = async {
+        loop {
+            controls.wait_changed().await;
+            info!(
+                "sample rate: {} Hz, muted: {}, volume: {} dB",
+                controls.sample_rate(),
+                controls.muted(),
+                controls.volume() / 256
+            );
+        }
+    };
+
+    // Run everything concurrently.
+    // If we had made everything `'static` above instead, we could do this using separate tasks instead.
+    join4(usb_fut, i2s_fut, mic_fut, controls_fut).await;
+}
+
+async fn stream<'d, T: Instance + 'd, P: UsbSupply + 'd>(
+    mic: &mut Microphone<'d, Driver<'d, T, P>>,
+    controls: &Controls<'d>,
+    fifo: &RefCell<Fifo>,
+) -> Result<(), EndpointError> {
+    let mut estimator = RateEstimator::new(controls.sample_rate());
+    fifo.borrow_mut().truncate(FIFO_MARGIN);
+
+    let mut buf = [0u8; MAX_PACKET_SAMPLES * 2];
+    let mut frames = 0u32;
+    loop {
+        // Packets are sent once per frame, measure the clock of the I2S against them.
+        let mut fifo = fifo.borrow_mut();
+        estimator.update(fifo.count);
+        let len = estimator.next_packet_len().min(MAX_PACKET_SAMPLES);
+        for sample in buf[..len * 2].chunks_mut(2) {
+            let value = match (fifo.pop(), controls.muted()) {
+                (Some(value), false) => value,
+                _ => 0,
+            };
+            sample.copy_from_slice(&value.to_le_bytes());
+        }
+        drop(fifo);
+
+        mic.write_packet(&buf[..len * 2]).await?;
+
+        frames += 1;
+        if frames % 5000 == 0 {
+            info!("I2S sample rate: {} Hz", estimator.sample_rate());
+        }
+    }
+}
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::mem;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_nrf::usb::{Driver, PowerUsb};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::billboard::{
+    AltMode, AltModeState, BillboardClass, Config as BillboardConfig, State, VconnPower, USB_CLASS_BILLBOARD,
+};
+use embassy_usb::{Builder, Config};
+use {defmt_rtt as _, panic_probe as _};
+
+// DisplayPort alternate mode, as discovered by the host with USB Power Delivery.
+const DISPLAYPORT_SVID: u16 = 0xff01;
+const DISPLAYPORT_MODE: u8 = 1;
+// UFP_D, pin assignments C and D, DP v1.3 signaling.
+const DISPLAYPORT_VDO: u32 = 0x0000_0c05;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config
+    // The device is only a billboard, shown when the DisplayPort mode couldn't be entered.
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB-billboard example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+    config.device_class = USB_CLASS_BILLBOARD;
+    config.device_sub_class = 0x00;
+    config.device_protocol = 0x00;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // Create classes on the builder.
+    let alt_modes = [AltMode {
+        svid: DISPLAYPORT_SVID,
+        mode: DISPLAYPORT_MODE,
+        vdo: DISPLAYPORT_VDO,
+        state: AltModeState::Unsuccessful,
+        description: Some("DisplayPort"),
+    }];
+    let billboard_config = BillboardConfig {
+        alt_modes: &alt_modes,
+        preferred_alt_mode: 0,
+        vconn_power: VconnPower::NotRequired,
+        additional_info_url: "https://embassy.dev",
+        insufficient_power: false,
+        pd_failure: false,
+        container_id: [
+            0x6e, 0x3b, 0x0c, 0x52, 0x9a, 0x41, 0x4d, 0x2e, 0x8f, 0x17, 0x3c, 0x60, 0xb5, 0x0d, 0x94, 0x21,
+        ],
+    };
+    BillboardClass::new(&mut builder, &mut state, &billboard_config);
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device, the host reads the billboard when enumerating it.
+    usb.run().await;
+}
@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::future::Future;
+use core::mem;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::join::join;
+use embassy_nrf::usb::{Driver, PowerUsb};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::ccid::{Card, CcidClass, Error, State};
+use embassy_usb::{Builder, Config};
+use {defmt_rtt as _, panic_probe as _};
+
+/// Answer To Reset announcing the T=1 protocol, without historical bytes.
+const ATR: [u8; 5] = [0x3b, 0x80, 0x80, 0x01, 0x01];
+
+const INS_SELECT: u8 = 0xa4;
+const INS_GET_CHALLENGE: u8 = 0x84;
+
+const SW_OK: [u8; 2] = [0x90, 0x00];
+const SW_WRONG_LENGTH: [u8; 2] = [0x67, 0x00];
+const SW_INS_NOT_SUPPORTED: [u8; 2] = [0x6d, 0x00];
+
+/// A card emulated in firmware, answering SELECT and GET CHALLENGE.
+struct VirtualCard {
+    seed: u32,
+}
+
+impl VirtualCard {
+    /// Next pseudo-random byte, not suitable for cryptography.
+    fn next_byte(&mut self) -> u8 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as u8
+    }
+}
+
+impl Card for VirtualCard {
+    type PowerOnFuture<'a> = impl Future<Output = Result<&'a [u8], Error>> + 'a where Self: 'a;
+
+    fn power_on(&mut self) -> Self::PowerOnFuture<'_> {
+        async move {
+            info!("Card powered on");
+            Ok(&ATR[..])
+        }
+    }
+
+    fn power_off(&mut self) {
+        info!("Card powered off");
+    }
+
+    type TransmitFuture<'a> = impl Future<Output = Result<usize, Error>> + 'a where Self: 'a;
+
+    fn transmit<'a>(&'a mut self, command: &'a [u8], response: &'a mut [u8]) -> Self::TransmitFuture<'a> {
+        async move {
+            info!("APDU: {:x}", command);
+            if command.len() < 4 {
+                response[..2].copy_from_slice(&SW_WRONG_LENGTH);
+                return Ok(2);
+            }
+            match command[1] {
+                INS_SELECT => {
+                    response[..2].copy_from_slice(&SW_OK);
+                    Ok(2)
+                }
+                INS_GET_CHALLENGE if command.len() == 5 => {
+                    // Le of 0 means 256 bytes.
+                    let len = match command[4] {
+                        0 => 256,
+                        le => le as usize,
+                    };
+                    for b in &mut response[..len] {
+                        *b = self.next_byte();
+                    }
+                    response[len..len + 2].copy_from_slice(&SW_OK);
+                    Ok(len + 2)
+                }
+                INS_GET_CHALLENGE => {
+                    response[..2].copy_from_slice(&SW_WRONG_LENGTH);
+                    Ok(2)
+                }
+                _ => {
+                    response[..2].copy_from_slice(&SW_INS_NOT_SUPPORTED);
+                    Ok(2)
+                }
+            }
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB-CCID example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // Create classes on the builder.
+    let card = VirtualCard { seed: 0x1234_5678 };
+    let mut class = CcidClass::new(&mut builder, &mut state, card, 64);
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device, and answer the commands of the host.
+    join(usb.run(), class.run()).await;
+}
@@ -0,0 +1,216 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::mem;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::join::join5;
+use embassy_nrf::gpio::{Input, Pin, Pull};
+use embassy_nrf::usb::{Driver, PowerUsb};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::hid::{
+    Collection, HidReaderWriter, ItemFlags, Report, ReportDescriptorBuilder, ReportId, ReportRouter, State, UsagePage,
+};
+use embassy_usb::{Builder, Config};
+use {defmt_rtt as _, panic_probe as _};
+
+const KEYBOARD_ID: u8 = 1;
+const MOUSE_ID: u8 = 2;
+const VENDOR_ID: u8 = 3;
+
+// A keyboard with LEDs, a mouse and a vendor-defined feature report, all on one interface.
+static REPORT_DESCRIPTOR: ReportDescriptorBuilder<160> = ReportDescriptorBuilder::new()
+    // Keyboard
+    .usage_page(UsagePage::GENERIC_DESKTOP)
+    .usage(0x06)
+    .collection(Collection::Application)
+    .report_id(KEYBOARD_ID)
+    // Modifiers
+    .usage_page(UsagePage::KEYBOARD)
+    .usage_minimum(0xe0)
+    .usage_maximum(0xe7)
+    .logical_minimum(0)
+    .logical_maximum(1)
+    .report_size(1)
+    .report_count(8)
+    .input(ItemFlags::DATA.with(ItemFlags::VARIABLE))
+    // Reserved
+    .report_size(8)
+    .report_count(1)
+    .input(ItemFlags::CONSTANT)
+    // LEDs
+    .usage_page(UsagePage::LED)
+    .usage_minimum(1)
+    .usage_maximum(5)
+    .report_size(1)
+    .report_count(5)
+    .output(ItemFlags::DATA.with(ItemFlags::VARIABLE))
+    .report_size(3)
+    .report_count(1)
+    .output(ItemFlags::CONSTANT)
+    // Keycodes
+    .usage_page(UsagePage::KEYBOARD)
+    .usage_minimum(0)
+    .usage_maximum(0xff)
+    .logical_minimum(0)
+    .logical_maximum(0xff)
+    .report_size(8)
+    .report_count(6)
+    .input(ItemFlags::DATA)
+    .end_collection()
+    // Mouse
+    .usage_page(UsagePage::GENERIC_DESKTOP)
+    .usage(0x02)
+    .collection(Collection::Application)
+    .report_id(MOUSE_ID)
+    .usage(0x01)
+    .collection(Collection::Physical)
+    .usage_page(UsagePage::BUTTON)
+    .usage_minimum(1)
+    .usage_maximum(3)
+    .logical_minimum(0)
+    .logical_maximum(1)
+    .report_size(1)
+    .report_count(3)
+    .input(ItemFlags::DATA.with(ItemFlags::VARIABLE))
+    .report_size(5)
+    .report_count(1)
+    .input(ItemFlags::CONSTANT)
+    .usage_page(UsagePage::GENERIC_DESKTOP)
+    .usage(0x30)
+    .usage(0x31)
+    .logical_minimum(-127)
+    .logical_maximum(127)
+    .report_size(8)
+    .report_count(2)
+    .input(ItemFlags::DATA.with(ItemFlags::VARIABLE).with(ItemFlags::RELATIVE))
+    .end_collection()
+    .end_collection()
+    // Vendor-defined configuration
+    .usage_page(UsagePage::vendor(0x00))
+    .usage(0x01)
+    .collection(Collection::Application)
+    .report_id(VENDOR_ID)
+    .usage(0x01)
+    .logical_minimum(0)
+    .logical_maximum(0xff)
+    .report_size(8)
+    .report_count(4)
+    .feature(ItemFlags::DATA.with(ItemFlags::VARIABLE))
+    .end_collection();
+
+// Reports the host sends or reads, routed by their ID.
+static REPORTS: [Report<8>; 2] = [
+    Report::new(ReportId::Out(KEYBOARD_ID)),
+    Report::new(ReportId::Feature(VENDOR_ID)),
+];
+static ROUTER: ReportRouter<8> = ReportRouter::new(&REPORTS);
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("HID composite example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // The initial value of the feature report, as read by the host.
+    REPORTS[1].set(&[0x01, 0x02, 0x03, 0x04]);
+
+    // Create classes on the builder.
+    let config = embassy_usb::class::hid::Config {
+        report_descriptor: REPORT_DESCRIPTOR.as_bytes(),
+        request_handler: Some(&ROUTER),
+        poll_ms: 10,
+        max_packet_size: 64,
+    };
+    let hid = HidReaderWriter::<_, 8, 9>::new(&mut builder, &mut state, config);
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device.
+    let usb_fut = usb.run();
+
+    let mut button = Input::new(p.P0_11.degrade(), Pull::Up);
+
+    let (reader, mut writer) = hid.split();
+
+    // Type an 'a' and nudge the mouse on each button press.
+    let in_fut = async {
+        loop {
+            button.wait_for_low().await;
+            info!("PRESSED");
+            if let Err(e) = writer.write_report(KEYBOARD_ID, &[0, 0, 4, 0, 0, 0, 0, 0]).await {
+                warn!("Failed to send report: {:?}", e);
+            }
+            if let Err(e) = writer.write_report(MOUSE_ID, &[0, 10, 10]).await {
+                warn!("Failed to send report: {:?}", e);
+            }
+
+            button.wait_for_high().await;
+            info!("RELEASED");
+            if let Err(e) = writer.write_report(KEYBOARD_ID, &[0; 8]).await {
+                warn!("Failed to send report: {:?}", e);
+            }
+        }
+    };
+
+    // Output reports sent over the Interrupt Out pipe go through the router too.
+    let out_fut = reader.run(true, &ROUTER);
+
+    let leds_fut = async {
+        let mut buf = [0; 8];
+        loop {
+            let len = REPORTS[0].wait(&mut buf).await;
+            info!("Keyboard LEDs: {=[u8]:x}", &buf[..len]);
+        }
+    };
+
+    let vendor_fut = async {
+        let mut buf = [0; 8];
+        loop {
+            let len = REPORTS[1].wait(&mut buf).await;
+            info!("Vendor configuration: {=[u8]:x}", &buf[..len]);
+        }
+    };
+
+    // Run everything concurrently.
+    // If we had made everything `'static` above instead, we could do this using separate tasks instead.
+    join5(usb_fut, in_fut, out_fut, leds_fut, vendor_fut).await;
+}
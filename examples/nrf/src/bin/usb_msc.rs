@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::convert::Infallible;
+use core::future::Future;
+use core::mem;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::join::join;
+use embassy_nrf::usb::{Driver, PowerUsb};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::msc::{self, Block, BlockDevice, MscClass, State};
+use embassy_usb::{Builder, Config};
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+const DISK_BLOCKS: usize = 64;
+
+/// A 32 KiB disk in RAM, to be formatted by the host.
+struct RamDisk {
+    blocks: &'static mut [Block; DISK_BLOCKS],
+}
+
+impl BlockDevice for RamDisk {
+    type Error = Infallible;
+
+    type ReadFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+    fn read<'a>(&'a mut self, block_address: u32, blocks: &'a mut [Block]) -> Self::ReadFuture<'a> {
+        async move {
+            let start = block_address as usize;
+            blocks.copy_from_slice(&self.blocks[start..start + blocks.len()]);
+            Ok(())
+        }
+    }
+
+    type WriteFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+    fn write<'a>(&'a mut self, block_address: u32, blocks: &'a [Block]) -> Self::WriteFuture<'a> {
+        async move {
+            let start = block_address as usize;
+            self.blocks[start..start + blocks.len()].copy_from_slice(blocks);
+            Ok(())
+        }
+    }
+
+    fn block_count(&self) -> u32 {
+        DISK_BLOCKS as u32
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB-MSC example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // Create the disk, and expose it with the class.
+    static DISK: StaticCell<[Block; DISK_BLOCKS]> = StaticCell::new();
+    let disk = RamDisk {
+        blocks: DISK.init([[0; 512]; DISK_BLOCKS]),
+    };
+    let msc_config = msc::Config {
+        vendor: "Embassy",
+        product: "RAM disk",
+        revision: "0.1",
+        removable: true,
+        write_protected: false,
+        max_packet_size: 64,
+    };
+    let mut class = MscClass::new(&mut builder, &mut state, disk, msc_config);
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device, and serve the disk to the host.
+    join(usb.run(), class.run()).await;
+}
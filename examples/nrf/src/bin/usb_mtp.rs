@@ -0,0 +1,223 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::future::Future;
+use core::mem;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::join::join;
+use embassy_nrf::usb::{Driver, PowerUsb};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::mtp::{
+    self, object_format, Error, MtpClass, ObjectHandle, ObjectInfo, ObjectStore, Parent, State,
+};
+use embassy_usb::{Builder, Config};
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+const MAX_FILES: usize = 8;
+const MAX_FILE_NAME: usize = 32;
+const MAX_FILE_SIZE: usize = 2048;
+
+/// A file in RAM. Handles are the slot index plus one, as 0 is reserved.
+struct File {
+    used: bool,
+    name: [u8; MAX_FILE_NAME],
+    name_len: usize,
+    data: [u8; MAX_FILE_SIZE],
+    size: usize,
+}
+
+/// A flat store of a few small files in RAM, without folders.
+struct RamStore {
+    files: &'static mut [File; MAX_FILES],
+}
+
+impl RamStore {
+    fn file(&mut self, handle: ObjectHandle) -> Result<&mut File, Error> {
+        match self.files.get_mut((handle as usize).wrapping_sub(1)) {
+            Some(file) if file.used => Ok(file),
+            _ => Err(Error::InvalidObjectHandle),
+        }
+    }
+}
+
+impl ObjectStore for RamStore {
+    type ListFuture<'a> = impl Future<Output = Result<usize, Error>> + 'a where Self: 'a;
+
+    fn list<'a>(&'a mut self, parent: Parent, offset: usize, handles: &'a mut [ObjectHandle]) -> Self::ListFuture<'a> {
+        async move {
+            if let Parent::Object(_) = parent {
+                return Err(Error::InvalidParentObject);
+            }
+            let used = (1..).zip(self.files.iter()).filter(|(_, f)| f.used).skip(offset);
+            let mut n = 0;
+            for ((handle, _), dest) in used.zip(handles.iter_mut()) {
+                *dest = handle;
+                n += 1;
+            }
+            Ok(n)
+        }
+    }
+
+    type InfoFuture<'a> = impl Future<Output = Result<ObjectInfo<'a>, Error>> + 'a where Self: 'a;
+
+    fn info(&mut self, handle: ObjectHandle) -> Self::InfoFuture<'_> {
+        async move {
+            let file = self.file(handle)?;
+            Ok(ObjectInfo {
+                parent: None,
+                format: object_format::UNDEFINED,
+                size: file.size as u32,
+                name: core::str::from_utf8(&file.name[..file.name_len]).unwrap(),
+            })
+        }
+    }
+
+    type ReadFuture<'a> = impl Future<Output = Result<usize, Error>> + 'a where Self: 'a;
+
+    fn read<'a>(&'a mut self, handle: ObjectHandle, offset: u32, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        async move {
+            let file = self.file(handle)?;
+            let data = file.data[..file.size].get(offset as usize..).unwrap_or(&[]);
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            Ok(n)
+        }
+    }
+
+    type CreateFuture<'a> = impl Future<Output = Result<ObjectHandle, Error>> + 'a where Self: 'a;
+
+    fn create<'a>(&'a mut self, info: ObjectInfo<'a>) -> Self::CreateFuture<'a> {
+        async move {
+            if info.format == object_format::ASSOCIATION || info.parent.is_some() {
+                return Err(Error::AccessDenied);
+            }
+            if info.size as usize > MAX_FILE_SIZE || info.name.len() > MAX_FILE_NAME {
+                return Err(Error::StoreFull);
+            }
+            let (handle, file) = (1..)
+                .zip(self.files.iter_mut())
+                .find(|(_, f)| !f.used)
+                .ok_or(Error::StoreFull)?;
+            file.used = true;
+            file.name[..info.name.len()].copy_from_slice(info.name.as_bytes());
+            file.name_len = info.name.len();
+            file.size = 0;
+            info!("Created {} as {}", info.name, handle);
+            Ok(handle)
+        }
+    }
+
+    type WriteFuture<'a> = impl Future<Output = Result<(), Error>> + 'a where Self: 'a;
+
+    fn write<'a>(&'a mut self, handle: ObjectHandle, offset: u32, data: &'a [u8]) -> Self::WriteFuture<'a> {
+        async move {
+            let file = self.file(handle)?;
+            let end = offset as usize + data.len();
+            if end > MAX_FILE_SIZE {
+                return Err(Error::StoreFull);
+            }
+            file.data[offset as usize..end].copy_from_slice(data);
+            file.size = file.size.max(end);
+            Ok(())
+        }
+    }
+
+    type DeleteFuture<'a> = impl Future<Output = Result<(), Error>> + 'a where Self: 'a;
+
+    fn delete(&mut self, handle: ObjectHandle) -> Self::DeleteFuture<'_> {
+        async move {
+            self.file(handle)?.used = false;
+            info!("Deleted {}", handle);
+            Ok(())
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        (MAX_FILES * MAX_FILE_SIZE) as u64
+    }
+
+    fn free_space(&self) -> u64 {
+        let free = self.files.iter().filter(|f| !f.used).count();
+        (free * MAX_FILE_SIZE) as u64
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB-MTP example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // Create the store, with a first file, and expose it with the class.
+    static FILES: StaticCell<[File; MAX_FILES]> = StaticCell::new();
+    const EMPTY: File = File {
+        used: false,
+        name: [0; MAX_FILE_NAME],
+        name_len: 0,
+        data: [0; MAX_FILE_SIZE],
+        size: 0,
+    };
+    let files = FILES.init([EMPTY; MAX_FILES]);
+    let readme = b"Hello from Embassy! Copy small files here, they are kept in RAM.\n";
+    files[0].used = true;
+    files[0].name[..10].copy_from_slice(b"README.txt");
+    files[0].name_len = 10;
+    files[0].data[..readme.len()].copy_from_slice(readme);
+    files[0].size = readme.len();
+
+    let mtp_config = mtp::Config {
+        manufacturer: "Embassy",
+        model: "RAM store",
+        device_version: "0.1",
+        serial_number: "12345678",
+        storage_description: "RAM",
+        read_only: false,
+        max_packet_size: 64,
+    };
+    let mut class = MtpClass::new(&mut builder, &mut state, RamStore { files }, mtp_config);
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device, and serve the files to the host.
+    join(usb.run(), class.run()).await;
+}
@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::mem;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::join::join;
+use embassy_nrf::usb::{Driver, Instance, PowerUsb, UsbSupply};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::printer::{Config as PrinterConfig, PrinterClass, Protocol, State};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::{Builder, Config};
+use {defmt_rtt as _, panic_probe as _};
+
+const DEVICE_ID: &str = "MFG:Embassy;MDL:Receipt Printer;CMD:ESC/POS;CLS:PRINTER;";
+
+// ESC/POS real-time status request, DLE EOT n.
+const DLE: u8 = 0x10;
+const EOT: u8 = 0x04;
+
+// Printer status for DLE EOT 1: online, drawer kick-out connector pin 3 low.
+const STATUS_ONLINE: u8 = 0x12;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB-printer example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    // The control buffer has to hold the device ID.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 128];
+
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // Create classes on the builder.
+    let printer_config = PrinterConfig {
+        protocol: Protocol::Bidirectional,
+        device_id: DEVICE_ID,
+        max_packet_size: 64,
+    };
+    let mut class = PrinterClass::new(&mut builder, &mut state, &printer_config);
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device.
+    let usb_fut = usb.run();
+
+    // Do stuff with the class!
+    let print_fut = async {
+        loop {
+            class.wait_connection().await;
+            info!("Connected");
+            let _ = print(&mut class).await;
+            info!("Disconnected");
+        }
+    };
+
+    // Run everything concurrently.
+    // If we had made everything `'static` above instead, we could do this using separate tasks instead.
+    join(usb_fut, print_fut).await;
+}
+
+struct Disconnected {}
+
+impl From<EndpointError> for Disconnected {
+    fn from(val: EndpointError) -> Self {
+        match val {
+            EndpointError::BufferOverflow => panic!("Buffer overflow"),
+            EndpointError::Disabled => Disconnected {},
+        }
+    }
+}
+
+async fn print<'d, T: Instance + 'd, P: UsbSupply + 'd>(
+    class: &mut PrinterClass<'d, Driver<'d, T, P>>,
+) -> Result<(), Disconnected> {
+    let mut buf = [0; 64];
+    loop {
+        let n = class.read_packet(&mut buf).await?;
+        if class.take_soft_reset() {
+            info!("Soft reset, job cancelled");
+        }
+        let data = &buf[..n];
+        info!("print data: {:x}", data);
+
+        // Answer the status requests, a real printer would parse the whole command stream.
+        if data.windows(3).any(|w| w == [DLE, EOT, 1]) {
+            class.write_packet(&[STATUS_ONLINE]).await?;
+        }
+    }
+}
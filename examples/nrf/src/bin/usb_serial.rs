@@ -11,7 +11,7 @@ use embassy_nrf::usb::{Driver, Instance, PowerUsb, UsbSupply};
 use embassy_nrf::{interrupt, pac};
 use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
 use embassy_usb::driver::EndpointError;
-use embassy_usb::{Builder, Config};
+use embassy_usb::{Builder, Config, StringHandler};
 use {defmt_rtt as _, panic_probe as _};
 
 #[embassy_executor::main]
@@ -32,7 +32,6 @@ async fn main(_spawner: Spawner) {
     let mut config = Config::new(0xc0de, 0xcafe);
     config.manufacturer = Some("Embassy");
     config.product = Some("USB-serial example");
-    config.serial_number = Some("12345678");
     config.max_power = 100;
     config.max_packet_size_0 = 64;
 
@@ -52,6 +51,9 @@ async fn main(_spawner: Spawner) {
 
     let mut state = State::new();
 
+    // The serial number is the unique device ID of the chip.
+    let mut serial_number = SerialNumber::new();
+
     let mut builder = Builder::new(
         driver,
         config,
@@ -61,6 +63,7 @@ async fn main(_spawner: Spawner) {
         &mut control_buf,
         None,
     );
+    builder.string_handler(&mut serial_number);
 
     // Create classes on the builder.
     let mut class = CdcAcmClass::new(&mut builder, &mut state, 64);
@@ -86,6 +89,31 @@ async fn main(_spawner: Spawner) {
     join(usb_fut, echo_fut).await;
 }
 
+/// Supplies the serial number, formatted from the FICR device ID.
+struct SerialNumber {
+    buf: [u8; 16],
+}
+
+impl SerialNumber {
+    fn new() -> Self {
+        let ficr = unsafe { &*pac::FICR::ptr() };
+        let id = (ficr.deviceid[1].read().bits() as u64) << 32 | ficr.deviceid[0].read().bits() as u64;
+
+        let mut buf = [0; 16];
+        for (i, b) in buf.iter_mut().enumerate() {
+            let digit = (id >> (60 - 4 * i)) as u8 & 0xf;
+            *b = b"0123456789ABCDEF"[digit as usize];
+        }
+        Self { buf }
+    }
+}
+
+impl StringHandler for SerialNumber {
+    fn serial_number(&mut self) -> Option<&str> {
+        core::str::from_utf8(&self.buf).ok()
+    }
+}
+
 struct Disconnected {}
 
 impl From<EndpointError> for Disconnected {
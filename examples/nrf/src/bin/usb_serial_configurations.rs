@@ -0,0 +1,143 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::mem;
+
+use defmt::{info, panic};
+use embassy_executor::Spawner;
+use embassy_futures::join::join3;
+use embassy_nrf::usb::{Driver, Instance, PowerUsb, UsbSupply};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Config, DeviceStateHandler};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config. The power settings are those of the first configuration.
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB-serial configurations example");
+    config.serial_number = Some("12345678");
+    config.max_power = 500;
+    config.max_packet_size_0 = 64;
+
+    // Required for windows compatiblity.
+    // https://developer.nordicsemi.com/nRF_Connect_SDK/doc/1.9.1/kconfig/CONFIG_CDC_ACM_IAD.html#help
+    config.device_class = 0xEF;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let device_state_handler = MyDeviceStateHandler {};
+    let mut bus_powered_state = State::new();
+    let mut self_powered_state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        Some(&device_state_handler),
+    );
+
+    // Create classes on the builder, one for each configuration. Each class allocates its own
+    // endpoints, the configurations don't share them.
+    let mut bus_powered_class = CdcAcmClass::new(&mut builder, &mut bus_powered_state, 64);
+    builder.configuration(true, 10);
+    let mut self_powered_class = CdcAcmClass::new(&mut builder, &mut self_powered_state, 64);
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device.
+    let usb_fut = usb.run();
+
+    // Do stuff with the classes! Only the class of the selected configuration gets connected.
+    let bus_powered_fut = async {
+        loop {
+            bus_powered_class.wait_connection().await;
+            info!("Connected, bus-powered");
+            let _ = echo(&mut bus_powered_class).await;
+            info!("Disconnected");
+        }
+    };
+    let self_powered_fut = async {
+        loop {
+            self_powered_class.wait_connection().await;
+            info!("Connected, self-powered");
+            let _ = echo(&mut self_powered_class).await;
+            info!("Disconnected");
+        }
+    };
+
+    // Run everything concurrently.
+    // If we had made everything `'static` above instead, we could do this using separate tasks instead.
+    join3(usb_fut, bus_powered_fut, self_powered_fut).await;
+}
+
+struct MyDeviceStateHandler {}
+
+impl DeviceStateHandler for MyDeviceStateHandler {
+    fn configuration_selected(&self, configuration: u8) {
+        match configuration {
+            1 => info!("Bus-powered configuration selected, the Vbus current limit is 500mA"),
+            _ => info!("Self-powered configuration selected, the Vbus current limit is 10mA"),
+        }
+    }
+
+    fn alternate_setting_selected(&self, iface: InterfaceNumber, alternate_setting: u8) {
+        info!(
+            "Alternate setting {} of interface {} selected",
+            alternate_setting,
+            u8::from(iface)
+        );
+    }
+}
+
+struct Disconnected {}
+
+impl From<EndpointError> for Disconnected {
+    fn from(val: EndpointError) -> Self {
+        match val {
+            EndpointError::BufferOverflow => panic!("Buffer overflow"),
+            EndpointError::Disabled => Disconnected {},
+        }
+    }
+}
+
+async fn echo<'d, T: Instance + 'd, P: UsbSupply + 'd>(
+    class: &mut CdcAcmClass<'d, Driver<'d, T, P>>,
+) -> Result<(), Disconnected> {
+    let mut buf = [0; 64];
+    loop {
+        let n = class.read_packet(&mut buf).await?;
+        let data = &buf[..n];
+        info!("data: {:x}", data);
+        class.write_packet(data).await?;
+    }
+}
@@ -0,0 +1,166 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::cell::RefCell;
+use core::mem;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::join::join;
+use embassy_nrf::gpio::{Level, Output, OutputDrive};
+use embassy_nrf::peripherals::P0_13;
+use embassy_nrf::usb::{Driver, Instance, PowerUsb, UsbSupply};
+use embassy_nrf::{interrupt, pac};
+use embassy_usb::class::vendor::{Config as VendorConfig, RequestHandler, State, VendorClass};
+use embassy_usb::control::{OutResponse, Request};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::msos::windows_version;
+use embassy_usb::{Builder, Config};
+use {defmt_rtt as _, panic_probe as _};
+
+// Vendor code of the Microsoft OS 2.0 requests.
+const VENDOR_CODE: u8 = 0x01;
+
+// Lets native applications find the device through WinUSB.
+const DEVICE_INTERFACE_GUIDS: &[&str] = &["{3E1D8F4C-9B2A-4F6E-8C71-5A0D2B7E9F13}"];
+
+// Vendor requests of the example protocol, addressed to the interface.
+const REQ_SET_LED: u8 = 0x01;
+const REQ_GET_VERSION: u8 = 0x02;
+
+const VERSION: [u8; 2] = [1, 0];
+
+/// Turns the LED on or off with `REQ_SET_LED`, and reports the protocol version with
+/// `REQ_GET_VERSION`.
+struct MyRequestHandler<'d> {
+    led: RefCell<Output<'d, P0_13>>,
+}
+
+impl<'d> RequestHandler for MyRequestHandler<'d> {
+    fn control_out(&self, req: Request, _data: &[u8]) -> OutResponse {
+        match req.request {
+            REQ_SET_LED => {
+                // The LED is active low.
+                match req.value {
+                    0 => self.led.borrow_mut().set_high(),
+                    _ => self.led.borrow_mut().set_low(),
+                }
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn control_in(&self, req: Request, buf: &mut [u8]) -> Option<usize> {
+        match req.request {
+            REQ_GET_VERSION => {
+                buf[..VERSION.len()].copy_from_slice(&VERSION);
+                Some(VERSION.len())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    let clock: pac::CLOCK = unsafe { mem::transmute(()) };
+
+    info!("Enabling ext hfosc...");
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    while clock.events_hfclkstarted.read().bits() != 1 {}
+
+    // Create the driver, from the HAL.
+    let irq = interrupt::take!(USBD);
+    let power_irq = interrupt::take!(POWER_CLOCK);
+    let driver = Driver::new(p.USBD, irq, PowerUsb::new(power_irq));
+
+    // Create embassy-usb Config
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Embassy");
+    config.product = Some("USB-vendor example");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Create embassy-usb DeviceBuilder using the driver and config.
+    // It needs some buffers for building the descriptors.
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut msos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let request_handler = MyRequestHandler {
+        led: RefCell::new(Output::new(p.P0_13, Level::High, OutputDrive::Standard)),
+    };
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut control_buf,
+        None,
+    );
+
+    // Bind the interface to WinUSB, so applications can use it on Windows without a driver.
+    builder.msos_descriptor(&mut msos_descriptor, windows_version::WIN8_1, VENDOR_CODE);
+
+    // Create classes on the builder.
+    let vendor_config = VendorConfig {
+        subclass: 0x00,
+        protocol: 0x00,
+        request_handler: Some(&request_handler),
+        max_packet_size: 64,
+        device_interface_guids: DEVICE_INTERFACE_GUIDS,
+    };
+    let mut class = VendorClass::new(&mut builder, &mut state, &vendor_config);
+
+    // Build the builder.
+    let mut usb = builder.build();
+
+    // Run the USB device.
+    let usb_fut = usb.run();
+
+    // Do stuff with the class!
+    let echo_fut = async {
+        loop {
+            class.wait_connection().await;
+            info!("Connected");
+            let _ = echo(&mut class).await;
+            info!("Disconnected");
+        }
+    };
+
+    // Run everything concurrently.
+    // If we had made everything `'static` above instead, we could do this using separate tasks instead.
+    join(usb_fut, echo_fut).await;
+}
+
+struct Disconnected {}
+
+impl From<EndpointError> for Disconnected {
+    fn from(val: EndpointError) -> Self {
+        match val {
+            EndpointError::BufferOverflow => panic!("Buffer overflow"),
+            EndpointError::Disabled => Disconnected {},
+        }
+    }
+}
+
+async fn echo<'d, T: Instance + 'd, P: UsbSupply + 'd>(
+    class: &mut VendorClass<'d, Driver<'d, T, P>>,
+) -> Result<(), Disconnected> {
+    let mut buf = [0; 64];
+    loop {
+        let n = class.read_packet(&mut buf).await?;
+        let data = &buf[..n];
+        info!("data: {:x}", data);
+        class.write_packet(data).await?;
+    }
+}
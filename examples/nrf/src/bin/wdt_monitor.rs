@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_nrf::gpio::{Input, Pull};
+use embassy_nrf::wdt::{Config, StarvedAction, Watchdog, WatchdogHandle};
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::task]
+async fn monitor(mut wdt: Watchdog) {
+    wdt.monitor(StarvedAction::Panic).await
+}
+
+#[embassy_executor::task]
+async fn blinker(mut handle: WatchdogHandle) {
+    loop {
+        handle.pet();
+        Timer::after(Duration::from_millis(500)).await;
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    info!("Hello World!");
+
+    let mut config = Config::default();
+    config.timeout_ticks = 32768 * 3; // 3 seconds
+    config.run_during_debug_halt = false;
+
+    let (wdt, [blinker_handle, mut button_handle]) = match Watchdog::try_new(p.WDT, config) {
+        Ok(x) => x,
+        Err(_) => {
+            info!("Watchdog already active with wrong config, waiting for it to timeout...");
+            loop {}
+        }
+    };
+
+    unwrap!(spawner.spawn(monitor(wdt)));
+    unwrap!(spawner.spawn(blinker(blinker_handle)));
+
+    let mut button = Input::new(p.P0_11, Pull::Up);
+
+    info!("Press button 1 at least every 2 seconds, or the monitor will panic naming handle 1!");
+
+    loop {
+        button.wait_for_high().await;
+        button.wait_for_low().await;
+        info!("Button pressed, petting watchdog!");
+        button_handle.pet();
+    }
+}
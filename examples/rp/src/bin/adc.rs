@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::adc::{convert_to_celsius, Adc, Channel, Config};
+use embassy_rp::dma::BlockList;
+use embassy_rp::interrupt;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+    let irq = interrupt::take!(ADC_IRQ_FIFO);
+    let mut adc = Adc::new(p.ADC, irq, Config::default());
+
+    let mut a0 = Channel::new_pin(p.PIN_26);
+    let a1 = Channel::new_pin(p.PIN_27);
+
+    info!("Single conversion: {}", unwrap!(adc.read(&mut a0).await));
+
+    let mut ts = Channel::new_temp_sensor(p.ADC_TEMP_SENSOR);
+    let temp = convert_to_celsius(unwrap!(adc.read(&mut ts).await));
+    info!("Die temperature: {} degrees", temp);
+
+    // Sample both pins 1000 times per second, in blocks of 100 samples per pin.
+    let channels = [a0, a1];
+    let mut buf = [0u16; 4 * 200];
+    let mut list = BlockList::new();
+    let mut sampling = adc.read_continuous(&channels, 1000, p.DMA_CH0, p.DMA_CH1, &mut buf, 4, &mut list);
+
+    loop {
+        match sampling.wait_block().await {
+            Ok(n) => {
+                let block = sampling.block(n);
+                let (mut sum0, mut sum1) = (0u32, 0u32);
+                for pair in block.chunks_exact(2) {
+                    sum0 += pair[0] as u32;
+                    sum1 += pair[1] as u32;
+                }
+                let count = block.len() as u32 / 2;
+                info!("Averages: A0 = {}, A1 = {}", sum0 / count, sum1 / count);
+            }
+            Err(e) => warn!("Sampling error: {:?}", e),
+        }
+    }
+}
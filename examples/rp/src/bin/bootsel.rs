@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::bootsel::Bootsel;
+use embassy_rp::gpio::{Level, Output};
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+    let mut bootsel = Bootsel::new(p.PIN_QSPI_SS);
+    let mut led = Output::new(p.PIN_25, Level::Low);
+
+    // Light the LED while the BOOTSEL button is pressed.
+    let mut was_pressed = false;
+    loop {
+        let pressed = bootsel.is_pressed();
+        if pressed != was_pressed {
+            info!("BOOTSEL {}", if pressed { "pressed" } else { "released" });
+            was_pressed = pressed;
+        }
+        led.set_level(if pressed { Level::High } else { Level::Low });
+        Timer::after(Duration::from_millis(10)).await;
+    }
+}
@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::gpio::{InterruptTrigger, Level, Output, Pull};
+use embassy_rp::power::dormant_until_pin;
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut p = embassy_rp::init(Default::default());
+    let mut led = Output::new(p.PIN_25, Level::Low);
+
+    loop {
+        info!("Blinking");
+        for _ in 0..5 {
+            led.set_high();
+            Timer::after(Duration::from_millis(100)).await;
+            led.set_low();
+            Timer::after(Duration::from_millis(100)).await;
+        }
+
+        // Wait for a button between pin 15 and ground, using almost no power.
+        info!("Going dormant until the button is pressed");
+        dormant_until_pin(&mut p.PIN_15, InterruptTrigger::EdgeLow, Pull::Up);
+        info!("Woken up");
+    }
+}
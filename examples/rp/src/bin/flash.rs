@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::flash::{Flash, ERASE_SIZE};
+use {defmt_rtt as _, panic_probe as _};
+
+/// Size of the flash of the Raspberry Pi Pico.
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// Offset of the sector used for data, after the program.
+const ADDR_OFFSET: u32 = 0x100000;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+    let mut flash = Flash::<FLASH_SIZE>::new(p.FLASH);
+
+    info!("Unique ID: {=[u8]:x}", flash.blocking_unique_id());
+    let jedec_id = flash.blocking_jedec_id();
+    info!("JEDEC ID: {:x}, size: {} bytes", jedec_id, 1u32 << (jedec_id & 0xff));
+
+    let mut buf = [0u8; 32];
+    unwrap!(flash.blocking_read(ADDR_OFFSET, &mut buf));
+    info!("Before: {=[u8]:x}", buf);
+
+    info!("Erasing...");
+    unwrap!(flash.blocking_erase(ADDR_OFFSET, ADDR_OFFSET + ERASE_SIZE as u32));
+    unwrap!(flash.blocking_read(ADDR_OFFSET, &mut buf));
+    info!("After erase: {=[u8]:x}", buf);
+
+    info!("Writing...");
+    let data: [u8; 32] = core::array::from_fn(|i| i as u8);
+    unwrap!(flash.blocking_write(ADDR_OFFSET, &data));
+    unwrap!(flash.blocking_read(ADDR_OFFSET, &mut buf));
+    info!("After write: {=[u8]:x}", buf);
+
+    if buf == data {
+        info!("Flash works!");
+    } else {
+        error!("Read back data doesn't match");
+    }
+}
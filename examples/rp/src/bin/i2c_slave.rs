@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::i2c_slave::{Command, Config, I2cSlave};
+use embassy_rp::interrupt;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    let mut config = Config::default();
+    config.addr = 0x42;
+    let irq = interrupt::take!(I2C0_IRQ);
+    let mut dev = I2cSlave::new(p.I2C0, p.PIN_5, p.PIN_4, irq, config);
+
+    // A small register file: the master writes a register number, optionally followed by
+    // data to store, and reads from the last register number written.
+    let mut registers = [0u8; 16];
+    let mut reg = 0usize;
+    let mut buf = [0u8; 17];
+
+    loop {
+        match dev.listen(&mut buf).await {
+            Ok(Command::GeneralCall(len)) => info!("General call: {:x}", buf[..len]),
+            Ok(Command::Write(0)) => {}
+            Ok(Command::Write(len)) => {
+                reg = buf[0] as usize % 16;
+                for (i, &byte) in buf[1..len].iter().enumerate() {
+                    registers[(reg + i) % 16] = byte;
+                }
+            }
+            Ok(Command::WriteRead(len)) => {
+                reg = buf[len - 1] as usize % 16;
+                unwrap!(dev.respond_and_fill(&registers[reg..], 0xFF).await);
+            }
+            Ok(Command::Read) => unwrap!(dev.respond_and_fill(&registers[reg..], 0xFF).await),
+            Err(e) => warn!("I2C error: {:?}", e),
+        }
+    }
+}
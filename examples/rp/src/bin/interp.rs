@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use defmt_rtt as _;
+use embassy_executor::Spawner;
+use embassy_rp::interp::{Interp, Lane, LaneConfig};
+use panic_probe as _;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    // Walk a 16x16 texture along a line, with 16.16 fixed-point coordinates. Lane 0 produces
+    // the texel index from the U coordinate and lane 1 from the V coordinate, and the full
+    // result adds both to the texture address in base 2. With `add_raw`, popping adds the
+    // bases to the unshifted coordinates in the accumulators.
+    let texture: [u8; 256] = core::array::from_fn(|i| i as u8);
+    let mut interp = Interp::new(p.INTERP0);
+    interp.configure_lane(
+        Lane::Lane0,
+        &LaneConfig {
+            shift: 16,
+            mask_lsb: 0,
+            mask_msb: 3,
+            add_raw: true,
+            ..Default::default()
+        },
+    );
+    interp.configure_lane(
+        Lane::Lane1,
+        &LaneConfig {
+            shift: 12,
+            mask_lsb: 4,
+            mask_msb: 7,
+            add_raw: true,
+            ..Default::default()
+        },
+    );
+
+    // Step U by 1.5 texels and V by 0.25 texels.
+    interp.set_base(0, 0x1_8000);
+    interp.set_base(1, 0x0_4000);
+    interp.set_base(2, texture.as_ptr() as u32);
+
+    for _ in 0..16 {
+        // Popping steps along the line.
+        let texel = unsafe { *(interp.peek_full() as *const u8) };
+        info!(
+            "U = {}, V = {}: texel {}",
+            interp.accumulator(Lane::Lane0) >> 16,
+            interp.accumulator(Lane::Lane1) >> 16,
+            texel
+        );
+        interp.pop_full();
+    }
+}
@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Executor;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::interrupt;
+use embassy_rp::multicore::{spawn_core1, Fifo, Stack};
+use embassy_rp::peripherals::PIN_25;
+use embassy_time::{Duration, Timer};
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+static mut CORE1_STACK: Stack<4096> = Stack::new();
+static EXECUTOR0: StaticCell<Executor> = StaticCell::new();
+static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
+
+const LED_ON: u32 = 1;
+const LED_OFF: u32 = 0;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let p = embassy_rp::init(Default::default());
+    let led = Output::new(p.PIN_25, Level::Low);
+
+    spawn_core1(p.CORE1, unsafe { &mut CORE1_STACK }, move || {
+        // The FIFO driver must be created on the core using it.
+        let fifo = Fifo::new(interrupt::take!(SIO_IRQ_PROC1));
+        let executor1 = EXECUTOR1.init(Executor::new());
+        executor1.run(|spawner| unwrap!(spawner.spawn(core1_task(fifo, led))));
+    });
+
+    let fifo = Fifo::new(interrupt::take!(SIO_IRQ_PROC0));
+    let executor0 = EXECUTOR0.init(Executor::new());
+    executor0.run(|spawner| unwrap!(spawner.spawn(core0_task(fifo))));
+}
+
+#[embassy_executor::task]
+async fn core0_task(mut fifo: Fifo<'static, interrupt::SIO_IRQ_PROC0>) {
+    loop {
+        info!("Core 0 turning the LED on");
+        fifo.send(LED_ON).await;
+        info!("Core 1 replied {}", fifo.receive().await);
+        Timer::after(Duration::from_millis(500)).await;
+
+        info!("Core 0 turning the LED off");
+        fifo.send(LED_OFF).await;
+        info!("Core 1 replied {}", fifo.receive().await);
+        Timer::after(Duration::from_millis(500)).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn core1_task(mut fifo: Fifo<'static, interrupt::SIO_IRQ_PROC1>, mut led: Output<'static, PIN_25>) {
+    loop {
+        let command = fifo.receive().await;
+        match command {
+            LED_ON => led.set_high(),
+            _ => led.set_low(),
+        }
+        // Echo the command back as an acknowledgement.
+        fifo.send(command).await;
+    }
+}
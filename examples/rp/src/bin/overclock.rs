@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::clocks::{self, ClockConfig, Gpout, GpoutSrc, PllConfig};
+use embassy_rp::config::Config;
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    // Run the cores at 200 MHz. The core voltage is raised to 1.15 V automatically.
+    let mut clock_config = ClockConfig::default();
+    clock_config.sys_pll = unwrap!(PllConfig::from_freq(200_000_000));
+    let mut config = Config::default();
+    config.clocks = clock_config;
+    let p = embassy_rp::init(config);
+
+    info!("clk_sys: {} Hz", clocks::clk_sys_freq());
+    info!("clk_peri: {} Hz", clocks::clk_peri_freq());
+
+    // Output clk_sys / 1000 on GPIO 21, for a frequency counter or a scope.
+    let mut gpout = Gpout::new(p.PIN_21);
+    gpout.set_src(GpoutSrc::Sys);
+    gpout.set_div(1000, 0);
+    gpout.enable();
+    info!("gpout0: {} Hz", gpout.get_freq());
+
+    loop {
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::dma::BlockList;
+use embassy_rp::pio::i2s::{self, frame, I2sOutput};
+use embassy_rp::pio::Pio;
+use {defmt_rtt as _, panic_probe as _};
+
+/// Samples per period of the generated tone, 48 kHz / 100 = 480 Hz.
+const PERIOD: usize = 100;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    let Pio { mut common, sm0, .. } = Pio::new(p.PIO0);
+
+    // Four blocks of 256 frames, starting with silence.
+    let mut buf = [0u32; 4 * 256];
+    let mut list = BlockList::new();
+
+    // For a PCM5102 DAC, or a MAX98357 amplifier: DIN on pin 18, BCLK on pin 19, LRCK on pin 20.
+    let mut i2s = unwrap!(I2sOutput::new(
+        &mut common,
+        sm0,
+        p.DMA_CH0,
+        p.DMA_CH1,
+        p.PIN_18,
+        p.PIN_19,
+        p.PIN_20,
+        &mut buf,
+        4,
+        &mut list,
+        i2s::Config::default(),
+    ));
+    info!("Sample rate: {} Hz", i2s.sample_rate());
+
+    // Play a triangle wave, refilling each block once it has been played.
+    let mut phase = 0;
+    loop {
+        let n = match i2s.wait_block().await {
+            Ok(n) => n,
+            Err(_) => {
+                warn!("Audio underrun");
+                continue;
+            }
+        };
+        for word in i2s.block(n) {
+            let half = PERIOD / 2;
+            let level = if phase < half { phase } else { PERIOD - phase };
+            let sample = ((level as i32 * 2 - half as i32) * 4000 / half as i32) as i16;
+            *word = frame(sample, sample);
+            phase = (phase + 1) % PERIOD;
+        }
+    }
+}
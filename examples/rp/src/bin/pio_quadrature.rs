@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::pio::quadrature::{Config, QuadratureEncoder};
+use embassy_rp::pio::Pio;
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    let Pio { mut common, sm0, .. } = Pio::new(p.PIO0);
+
+    // Connect the encoder's A and B outputs to pins 10 and 11, and its common pin to ground.
+    let mut encoder = unwrap!(QuadratureEncoder::new(
+        &mut common,
+        sm0,
+        p.PIN_10,
+        p.PIN_11,
+        Config::default()
+    ));
+
+    loop {
+        let position = encoder.wait_moved(4).await;
+        info!("position: {}", position);
+
+        Timer::after(Duration::from_millis(100)).await;
+        info!("velocity: {} steps/s", encoder.velocity());
+    }
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::pio::uart::{Config, PioUartRx, PioUartTx};
+use embassy_rp::pio::Pio;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    let Pio {
+        mut common, sm0, sm1, ..
+    } = Pio::new(p.PIO0);
+
+    // Connect pin 4 to pin 5 to loop the data back.
+    let config = Config::default();
+    let mut tx = unwrap!(PioUartTx::new(&mut common, sm0, p.PIN_4, config));
+    let mut rx = unwrap!(PioUartRx::new(&mut common, sm1, p.PIN_5, config));
+
+    loop {
+        // The RX FIFO holds 8 bytes, so the receiver doesn't need to run while sending this.
+        unwrap!(tx.write(b"Hello!").await);
+
+        let mut buf = [0; 6];
+        unwrap!(rx.read(&mut buf).await);
+        info!("Received {:a}", buf);
+    }
+}
@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::gpio::Pull;
+use embassy_rp::pwm::PwmInput;
+use embassy_time::Duration;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    // A fan tachometer output is open drain, so it needs a pull-up.
+    let mut input = PwmInput::new(p.PWM_CH3, p.PIN_7, Pull::Up);
+
+    loop {
+        match input.measure(Duration::from_millis(100)).await {
+            Ok(m) => info!("Frequency: {} Hz, duty cycle: {}%", m.frequency, m.duty_cycle * 100.0),
+            Err(e) => warn!("Measurement failed: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::spi::{Config, Spi};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    // The master drives CLK on PIN_2 and CS on PIN_1, sends on PIN_0 and receives on PIN_3.
+    let mut spi = Spi::new_slave(
+        p.SPI0,
+        p.PIN_2,
+        p.PIN_0,
+        p.PIN_3,
+        p.PIN_1,
+        p.DMA_CH0,
+        p.DMA_CH1,
+        Config::default(),
+    );
+
+    // Echo each 4-byte frame back to the master during the next one.
+    let mut reply = [0u8; 4];
+    loop {
+        let mut frame = [0u8; 4];
+        match spi.transfer(&mut frame, &reply).await {
+            Ok(()) => {
+                info!("Received {:x}", frame);
+                reply = frame;
+            }
+            Err(e) => warn!("SPI error: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::watchdog::{reset_reason, ResetReason, Watchdog};
+use embassy_time::{Duration, Timer};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+    let mut watchdog = Watchdog::new(p.WATCHDOG);
+
+    // Count watchdog resets in a scratch register, which survives them.
+    let reason = reset_reason();
+    let resets = match reason {
+        ResetReason::WatchdogTimeout | ResetReason::WatchdogForced => watchdog.get_scratch(0) + 1,
+        _ => 0,
+    };
+    watchdog.set_scratch(0, resets);
+    info!("Reset reason: {:?}, {} watchdog resets so far", reason, resets);
+
+    watchdog.pause_on_debug(true);
+    watchdog.start(Duration::from_millis(1500));
+
+    // Feed the watchdog a few times, then let it reset the chip.
+    for _ in 0..5 {
+        info!("Feeding the watchdog");
+        watchdog.feed();
+        Timer::after(Duration::from_secs(1)).await;
+    }
+
+    info!("Not feeding the watchdog anymore, it will reset the chip");
+    loop {
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,138 @@
+#![feature(type_alias_impl_trait)]
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::coap::{self, content_format, CoapServer, Code, Response};
+use embassy_net::{ConfigStrategy, Ipv4Address, Ipv4Cidr, PacketMetadata, Stack, StackResources};
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+macro_rules! singleton {
+    ($val:expr) => {{
+        type T = impl Sized;
+        static STATIC_CELL: StaticCell<T> = StaticCell::new();
+        STATIC_CELL.init_with(move || $val)
+    }};
+}
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<TunTapDevice>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        ConfigStrategy::Static(embassy_net::Config {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        })
+    } else {
+        ConfigStrategy::Dhcp
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    let stack = &*singleton!(Stack::new(
+        device,
+        config,
+        singleton!(StackResources::<1, 2, 8>::new()),
+        seed
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Then we can use it!
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; coap::BUFFER_SIZE * 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; coap::BUFFER_SIZE * 4];
+    let mut buf = [0; 4096];
+
+    let server = CoapServer::new(
+        stack,
+        coap::PORT,
+        coap::Config::default(),
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    )
+    .unwrap();
+
+    let mut counter = 0u32;
+    server
+        .run(&mut buf, |request, payload| {
+            info!(
+                "{:?} {:?}",
+                request.code,
+                request.uri_path().collect::<std::vec::Vec<_>>()
+            );
+            if request.path_eq("hello") && request.code == Code::GET {
+                let text = b"Hello from embassy!";
+                payload[..text.len()].copy_from_slice(text);
+                Response {
+                    content_format: Some(content_format::TEXT_PLAIN),
+                    len: text.len(),
+                    ..Response::new(Code::CONTENT)
+                }
+            } else if request.path_eq("counter") && request.code == Code::POST {
+                counter += 1;
+                let text = counter.to_string();
+                payload[..text.len()].copy_from_slice(text.as_bytes());
+                Response {
+                    content_format: Some(content_format::TEXT_PLAIN),
+                    len: text.len(),
+                    ..Response::new(Code::CHANGED)
+                }
+            } else {
+                Response::new(Code::NOT_FOUND)
+            }
+        })
+        .await;
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
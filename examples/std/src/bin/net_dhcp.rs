@@ -0,0 +1,110 @@
+#![feature(type_alias_impl_trait)]
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::{ConfigStrategy, DhcpConfig, DhcpEvent, Ipv4Address, Stack, StackResources};
+use embassy_time::Duration;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+macro_rules! singleton {
+    ($val:expr) => {{
+        type T = impl Sized;
+        static STATIC_CELL: StaticCell<T> = StaticCell::new();
+        STATIC_CELL.init_with(move || $val)
+    }};
+}
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// Host name registered by the DHCP server
+    #[clap(long, default_value = "embassy")]
+    hostname: String,
+    /// Address to ask the DHCP server for
+    #[clap(long)]
+    requested_address: Option<std::net::Ipv4Addr>,
+}
+
+fn lease_event(event: DhcpEvent) {
+    match event {
+        DhcpEvent::LeaseObtained {
+            address,
+            server,
+            lease_duration,
+        } => info!(
+            "lease of {} obtained from {} for {}s",
+            address,
+            server,
+            lease_duration.as_secs()
+        ),
+        DhcpEvent::LeaseRenewed { lease_duration } => info!("lease renewed for {}s", lease_duration.as_secs()),
+        DhcpEvent::LeaseLost => warn!("lease lost"),
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<TunTapDevice>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Identify the device to the DHCP server, and renew the lease at least every 10 minutes
+    let mut dhcp = DhcpConfig::default();
+    dhcp.hostname = Some(opts.hostname.as_str().into());
+    dhcp.requested_address = opts.requested_address.map(|a| Ipv4Address(a.octets()));
+    dhcp.max_lease_duration = Some(Duration::from_secs(600));
+    dhcp.on_event = Some(lease_event);
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    let stack = &*singleton!(Stack::new(
+        device,
+        ConfigStrategy::DhcpWithConfig(dhcp),
+        singleton!(StackResources::<1, 2, 8>::new()),
+        seed
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Then follow the configuration
+    loop {
+        stack.wait_config_changed().await;
+        info!("config: {:?}", stack.config());
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
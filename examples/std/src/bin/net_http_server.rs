@@ -0,0 +1,183 @@
+#![feature(type_alias_impl_trait)]
+
+use core::cell::RefCell;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::http::Method;
+use embassy_net::http_server::{content_type, form_value, url_decode, Error, HttpServer, Request, Router};
+use embassy_net::{ConfigStrategy, Ipv4Address, Ipv4Cidr, Stack, StackResources};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::{String, Vec};
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+macro_rules! singleton {
+    ($val:expr) => {{
+        type T = impl Sized;
+        static STATIC_CELL: StaticCell<T> = StaticCell::new();
+        STATIC_CELL.init_with(move || $val)
+    }};
+}
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<TunTapDevice>) -> ! {
+    stack.run().await
+}
+
+#[derive(Clone, Copy)]
+enum Page {
+    Index,
+    SetName,
+    Status,
+}
+
+static ROUTER: Router<'static, Page> = Router::new(&[
+    (Method::Get, "/", Page::Index),
+    (Method::Post, "/name", Page::SetName),
+    (Method::Get, "/status.json", Page::Status),
+]);
+
+static NAME: Mutex<CriticalSectionRawMutex, RefCell<String<32>>> = Mutex::new(RefCell::new(String::new()));
+
+async fn index(request: Request<'_, '_>) -> Result<(), Error> {
+    let mut response = request.respond(200, content_type::HTML, &[]).await?;
+    let name = NAME.lock(|n| n.borrow().clone());
+    response
+        .write(b"<!DOCTYPE html><html><body><h1>Hello from embassy!</h1><form method=\"post\" action=\"/name\">")
+        .await?;
+    response
+        .write(b"<label>Device name <input name=\"name\" value=\"")
+        .await?;
+    response.write(name.as_bytes()).await?;
+    response
+        .write(b"\"></label><button>Save</button></form></body></html>")
+        .await?;
+    response.finish().await
+}
+
+async fn set_name(mut request: Request<'_, '_>) -> Result<(), Error> {
+    let mut body = [0; 128];
+    let len = match request.read_to_end(&mut body).await {
+        Ok(len) => len,
+        Err(Error::BodyTooLarge) => return request.send(413, content_type::TEXT, b"form too large\n").await,
+        Err(e) => return Err(e),
+    };
+    let form = core::str::from_utf8(&body[..len]).unwrap_or("");
+    let mut decoded = [0; 32];
+    let name = form_value(form, "name")
+        .and_then(|v| url_decode(v, &mut decoded))
+        // The name is inserted in the page as is.
+        .filter(|name| !name.contains(['"', '<', '&']));
+    match name {
+        Some(name) => {
+            NAME.lock(|n| *n.borrow_mut() = name.into());
+            request.redirect("/").await
+        }
+        None => request.send(400, content_type::TEXT, b"invalid name\n").await,
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn http_task(stack: &'static Stack<TunTapDevice>) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 4096];
+    let mut buf = [0; 1024];
+    let mut server = HttpServer::new(stack, 80, &mut rx_buffer, &mut tx_buffer, &mut buf);
+
+    loop {
+        let request = server.next_request().await;
+        info!("{:?} {}", request.method(), request.path());
+
+        let result = match request.route(&ROUTER) {
+            Ok(Page::Index) => index(request).await,
+            Ok(Page::SetName) => set_name(request).await,
+            Ok(Page::Status) => {
+                let body = if stack.is_config_up() {
+                    &b"{\"online\":true}"[..]
+                } else {
+                    &b"{\"online\":false}"[..]
+                };
+                request.send(200, content_type::JSON, body).await
+            }
+            Err(e) => request.send(e.status(), content_type::TEXT, b"").await,
+        };
+        if let Err(e) = result {
+            warn!("response error: {:?}", e);
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        ConfigStrategy::Static(embassy_net::Config {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        })
+    } else {
+        ConfigStrategy::Dhcp
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    let stack = &*singleton!(Stack::new(
+        device,
+        config,
+        singleton!(StackResources::<1, 3, 8>::new()),
+        seed
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Then we can use it!
+    for _ in 0..2 {
+        spawner.spawn(http_task(stack)).unwrap();
+    }
+    info!("listening on port 80");
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
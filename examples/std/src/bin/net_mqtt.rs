@@ -0,0 +1,165 @@
+#![feature(type_alias_impl_trait)]
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::mqtt::{self, MqttClient, QoS};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{ConfigStrategy, Ipv4Address, Ipv4Cidr, Stack, StackResources};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::{Duration, Timer};
+use futures::future::select;
+use futures::pin_mut;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+macro_rules! singleton {
+    ($val:expr) => {{
+        type T = impl Sized;
+        static STATIC_CELL: StaticCell<T> = StaticCell::new();
+        STATIC_CELL.init_with(move || $val)
+    }};
+}
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<TunTapDevice>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        ConfigStrategy::Static(embassy_net::Config {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        })
+    } else {
+        ConfigStrategy::Dhcp
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    let stack = &*singleton!(Stack::new(
+        device,
+        config,
+        singleton!(StackResources::<1, 2, 8>::new()),
+        seed
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Then we can use it!
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+
+    socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(90)));
+
+    let remote_endpoint = (Ipv4Address::new(192, 168, 69, 100), 1883);
+    info!("connecting to {:?}...", remote_endpoint);
+    let r = socket.connect(remote_endpoint).await;
+    if let Err(e) = r {
+        warn!("connect error: {:?}", e);
+        return;
+    }
+    info!("connected!");
+
+    let mut mqtt_rx_buffer = [0; 1024];
+    let mut mqtt_tx_buffer = [0; 1024];
+    let mut config = mqtt::Config::new("embassy-std");
+    config.will = Some(mqtt::Will {
+        topic: "embassy/status",
+        payload: b"offline",
+        qos: QoS::AtLeastOnce,
+        retain: true,
+    });
+    let client =
+        match MqttClient::<NoopRawMutex>::connect(&mut socket, &config, &mut mqtt_rx_buffer, &mut mqtt_tx_buffer).await
+        {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("MQTT connect error: {:?}", e);
+                return;
+            }
+        };
+    info!("MQTT session open!");
+
+    let receive = async {
+        loop {
+            match client.receive().await {
+                Ok(message) => info!("{}: {:?}", message.topic(), message.payload()),
+                Err(e) => {
+                    warn!("receive error: {:?}", e);
+                    return;
+                }
+            }
+        }
+    };
+    // The acknowledgements are processed by `receive`, so it must run concurrently.
+    let publish = async {
+        if let Err(e) = client.subscribe("embassy/led", QoS::AtLeastOnce).await {
+            warn!("subscribe error: {:?}", e);
+            return;
+        }
+
+        let mut count = 0u32;
+        loop {
+            let payload = count.to_string();
+            if let Err(e) = client
+                .publish("embassy/count", payload.as_bytes(), QoS::AtLeastOnce, false)
+                .await
+            {
+                warn!("publish error: {:?}", e);
+                return;
+            }
+            count += 1;
+            Timer::after(Duration::from_secs(5)).await;
+        }
+    };
+    pin_mut!(receive, publish);
+    select(receive, publish).await;
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,127 @@
+#![feature(type_alias_impl_trait)]
+
+use std::fs::File;
+use std::io::Write as _;
+use std::sync::Mutex;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::pcap::{self, Direction};
+use embassy_net::{ConfigStrategy, Ipv4Address, Ipv4Cidr, Medium, Stack, StackResources};
+use embassy_time::Instant;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+macro_rules! singleton {
+    ($val:expr) => {{
+        type T = impl Sized;
+        static STATIC_CELL: StaticCell<T> = StaticCell::new();
+        STATIC_CELL.init_with(move || $val)
+    }};
+}
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+    /// File the capture is written to, to be opened with Wireshark
+    #[clap(long, default_value = "capture.pcap")]
+    output: String,
+}
+
+static CAPTURE: Mutex<Option<File>> = Mutex::new(None);
+
+fn capture(direction: Direction, timestamp: Instant, frame: &[u8]) {
+    let mut record = [0; pcap::RECORD_HEADER_LEN + 1514];
+    let len = pcap::encode_record(timestamp, frame, &mut record);
+    if let Some(file) = CAPTURE.lock().unwrap().as_mut() {
+        file.write_all(&record[..len]).unwrap();
+    }
+    debug!("captured {:?} frame of {} bytes", direction, frame.len());
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<TunTapDevice>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Start the capture, before any frame goes through the stack
+    let mut file = File::create(&opts.output).unwrap();
+    file.write_all(&pcap::global_header(Medium::Ethernet, 1514)).unwrap();
+    *CAPTURE.lock().unwrap() = Some(file);
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        ConfigStrategy::Static(embassy_net::Config {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::from_slice(&[Ipv4Address::new(8, 8, 4, 4)]).unwrap(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        })
+    } else {
+        ConfigStrategy::Dhcp
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    let stack = &*singleton!(Stack::new(
+        device,
+        config,
+        singleton!(StackResources::<1, 2, 8>::new()),
+        seed
+    ));
+    stack.set_tap(Some(capture));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Then we can use it!
+    while !stack.is_config_up() {
+        stack.wait_config_changed().await;
+    }
+    match stack.dns_query("example.com", DnsQueryType::A).await {
+        Ok(r) => info!("query response: {:?}", r),
+        Err(e) => warn!("query error: {:?}", e),
+    }
+
+    stack.set_tap(None);
+    info!("capture written to {}", opts.output);
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,115 @@
+#![feature(type_alias_impl_trait)]
+
+#[path = "../serial_port.rs"]
+mod serial_port;
+
+use async_io::Async;
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::ppp::{self, PppDevice, Runner, State};
+use embassy_net::{ConfigStrategy, Stack, StackResources};
+use embassy_time::{Duration, Timer};
+use embedded_io::adapters::FromFutures;
+use log::*;
+use nix::sys::termios;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+use crate::serial_port::SerialPort;
+
+macro_rules! singleton {
+    ($val:expr) => {{
+        type T = impl Sized;
+        static STATIC_CELL: StaticCell<T> = StaticCell::new();
+        STATIC_CELL.init_with(move || $val)
+    }};
+}
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// Serial port of the modem, already in data mode
+    #[clap(long, default_value = "/dev/ttyUSB0")]
+    device: String,
+    /// PPP username
+    #[clap(long, default_value = "")]
+    username: String,
+    /// PPP password
+    #[clap(long, default_value = "")]
+    password: String,
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<PppDevice>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn ppp_task(mut runner: Runner, stack: &'static Stack<PppDevice>, opts: Opts) -> ! {
+    let config = ppp::Config {
+        username: &opts.username,
+        password: &opts.password,
+    };
+
+    loop {
+        let port = SerialPort::new(opts.device.as_str(), termios::BaudRate::B115200).unwrap();
+        let port = FromFutures::new(Async::new(port).unwrap());
+
+        let error = runner.run(port, stack, &config).await;
+        warn!("PPP link ended: {:?}", error);
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init the PPP device, configured by the PPP runner
+    let (device, runner) = ppp::new(singleton!(State::new()));
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    let stack = &*singleton!(Stack::new(
+        device,
+        ConfigStrategy::None,
+        singleton!(StackResources::<1, 2, 8>::new()),
+        seed
+    ));
+
+    // Launch network and PPP tasks
+    spawner.spawn(net_task(stack)).unwrap();
+    spawner.spawn(ppp_task(runner, stack, opts)).unwrap();
+
+    // Then we can use it!
+    info!("waiting for the PPP link...");
+    while !stack.is_config_up() {
+        stack.wait_config_changed().await;
+    }
+    info!("configured: {:?}", stack.config());
+
+    match stack.dns_query("example.com", DnsQueryType::A).await {
+        Ok(r) => info!("query response: {:?}", r),
+        Err(e) => warn!("query error: {:?}", e),
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
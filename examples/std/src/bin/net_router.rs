@@ -0,0 +1,134 @@
+#![feature(type_alias_impl_trait)]
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::router::{splice, Router};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{ConfigStrategy, IpAddress, Ipv4Address, Ipv4Cidr, Stack, StackResources};
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+macro_rules! singleton {
+    ($val:expr) => {{
+        type T = impl Sized;
+        static STATIC_CELL: StaticCell<T> = StaticCell::new();
+        STATIC_CELL.init_with(move || $val)
+    }};
+}
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device of the uplink, with the default route
+    #[clap(long, default_value = "tap0")]
+    uplink: String,
+    /// TAP device of the local network, where connections are accepted
+    #[clap(long, default_value = "tap1")]
+    local: String,
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn net_task(stack: &'static Stack<TunTapDevice>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init the stack of each interface, with its own addressing
+    let uplink = &*singleton!(Stack::new(
+        TunTapDevice::new(&opts.uplink).unwrap(),
+        ConfigStrategy::Static(embassy_net::Config {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        }),
+        singleton!(StackResources::<1, 2, 8>::new()),
+        seed
+    ));
+    let local = &*singleton!(Stack::new(
+        TunTapDevice::new(&opts.local).unwrap(),
+        ConfigStrategy::Static(embassy_net::Config {
+            address: Ipv4Cidr::new(Ipv4Address::new(10, 42, 0, 1), 24),
+            dns_servers: Vec::new(),
+            gateway: None,
+        }),
+        singleton!(StackResources::<1, 2, 8>::new()),
+        seed.wrapping_add(1)
+    ));
+    let stacks = [uplink, local];
+
+    // Launch network tasks
+    spawner.spawn(net_task(uplink)).unwrap();
+    spawner.spawn(net_task(local)).unwrap();
+
+    let router: Router<2> = Router::new([uplink, local]);
+
+    // Forward the connections accepted on the local network to a server, through the
+    // interface chosen by the router.
+    let server = (Ipv4Address::new(192, 168, 69, 100), 8000);
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut server_rx_buffer = [0; 4096];
+    let mut server_tx_buffer = [0; 4096];
+    let mut buf_a = [0; 1024];
+    let mut buf_b = [0; 1024];
+    loop {
+        let mut client = TcpSocket::new(local, &mut rx_buffer, &mut tx_buffer);
+        info!("listening on port 8000...");
+        if let Err(e) = client.accept(8000).await {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+        info!("accepted {:?}", client.remote_endpoint());
+
+        let (i, source) = match router.route_with_source(IpAddress::Ipv4(server.0)) {
+            Some(route) => route,
+            None => {
+                warn!("no route to {:?}", server);
+                continue;
+            }
+        };
+        info!("connecting to {:?} from {}...", server, source);
+        let mut upstream = TcpSocket::new(stacks[i], &mut server_rx_buffer, &mut server_tx_buffer);
+        if let Err(e) = upstream.connect(server).await {
+            warn!("connect error: {:?}", e);
+            continue;
+        }
+
+        if let Err(e) = splice(&mut client, &mut upstream, &mut buf_a, &mut buf_b).await {
+            warn!("forwarding error: {:?}", e);
+        }
+        client.close();
+        upstream.close();
+        info!("connection closed");
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
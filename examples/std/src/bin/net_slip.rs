@@ -0,0 +1,124 @@
+#![feature(type_alias_impl_trait)]
+
+#[path = "../serial_port.rs"]
+mod serial_port;
+
+use async_io::Async;
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::slip::{self, Runner, SlipDevice, State};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{ConfigStrategy, Ipv4Address, Ipv4Cidr, Stack, StackResources};
+use embassy_time::{Duration, Timer};
+use embedded_io::adapters::FromFutures;
+use embedded_io::asynch::Write;
+use heapless::Vec;
+use log::*;
+use nix::sys::termios;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+use crate::serial_port::SerialPort;
+
+macro_rules! singleton {
+    ($val:expr) => {{
+        type T = impl Sized;
+        static STATIC_CELL: StaticCell<T> = StaticCell::new();
+        STATIC_CELL.init_with(move || $val)
+    }};
+}
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// Serial port attached to the host with slattach
+    #[clap(long, default_value = "/dev/ttyUSB0")]
+    device: String,
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<SlipDevice>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn slip_task(mut runner: Runner, device: String) -> ! {
+    loop {
+        let port = SerialPort::new(device.as_str(), termios::BaudRate::B115200).unwrap();
+        let port = FromFutures::new(Async::new(port).unwrap());
+
+        let error = runner.run(port).await;
+        warn!("SLIP link ended: {:?}", error);
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init the SLIP device
+    let (device, runner) = slip::new(singleton!(State::new()));
+
+    // SLIP has no address negotiation, the host is 192.168.7.1
+    let config = ConfigStrategy::Static(embassy_net::Config {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 7, 2), 24),
+        dns_servers: Vec::new(),
+        gateway: Some(Ipv4Address::new(192, 168, 7, 1)),
+    });
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    let stack = &*singleton!(Stack::new(
+        device,
+        config,
+        singleton!(StackResources::<1, 2, 8>::new()),
+        seed
+    ));
+
+    // Launch network and SLIP tasks
+    spawner.spawn(net_task(stack)).unwrap();
+    spawner.spawn(slip_task(runner, opts.device)).unwrap();
+
+    // Then we can use it!
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+
+    socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
+
+    let remote_endpoint = (Ipv4Address::new(192, 168, 7, 1), 8000);
+    info!("connecting to {:?}...", remote_endpoint);
+    let r = socket.connect(remote_endpoint).await;
+    if let Err(e) = r {
+        warn!("connect error: {:?}", e);
+        return;
+    }
+    info!("connected!");
+    loop {
+        let r = socket.write_all(b"Hello!\n").await;
+        if let Err(e) = r {
+            warn!("write error: {:?}", e);
+            return;
+        }
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}